@@ -17,15 +17,18 @@
 */
 
 use anyhow::Result;
-use capsule::batch::{Batch, Either};
+use capsule::batch::{Batch, Chain, Either, PacketTx, Pipeline, Poll, StageSwitches};
 use capsule::packets::ip::v4::Ipv4;
 use capsule::packets::{Ethernet, Packet};
+use capsule::testils::byte_arrays::IPV4_UDP_PACKET;
 use capsule::testils::criterion::BencherExt;
 use capsule::testils::proptest::*;
+use capsule::trace::PacketTrace;
 use capsule::{compose, Mbuf};
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use proptest::prelude::*;
 use proptest::strategy;
+use std::sync::mpsc;
 
 const BATCH_SIZE: usize = 500;
 
@@ -210,6 +213,53 @@ fn replace_batch(c: &mut Criterion) {
     group.finish()
 }
 
+fn keep(packet: Mbuf) -> Result<Either<Mbuf>> {
+    Ok(Either::Keep(packet))
+}
+
+#[capsule::bench(mempool_capacity = 511)]
+fn chain_tracing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("combinators::chain_tracing");
+
+    for &(name, every) in [
+        ("combinators::trace_disabled", 0),
+        ("combinators::trace_every_1000", 1000),
+    ]
+    .iter()
+    {
+        group.bench_function(name, |b| {
+            let switches = StageSwitches::default();
+            let chain = Chain::new(&switches)
+                .stage("bench_first", keep)
+                .stage("bench_second", keep);
+            let (mut tx, rx) = mpsc::channel();
+            let (out, out_rx) = mpsc::channel();
+            let mut pipeline = Poll::new(rx).chain(chain).send_named(name, out);
+
+            if every > 0 {
+                PacketTrace::enable(name, every);
+            }
+
+            b.iter_batched(
+                || {
+                    out_rx.try_iter().for_each(drop);
+                    tx.transmit(
+                        (0..32)
+                            .map(|_| Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap())
+                            .collect(),
+                    );
+                },
+                |_| pipeline.run_once(),
+                BatchSize::SmallInput,
+            );
+
+            PacketTrace::disable(name);
+        });
+    }
+
+    group.finish()
+}
+
 fn bench_config() -> Criterion {
     Criterion::default().with_plots()
 }
@@ -224,6 +274,7 @@ criterion_group! {
             replace_batch,
             map_batches,
             map_errors,
+            chain_tracing,
 }
 
 criterion_main!(benches);
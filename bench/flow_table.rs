@@ -0,0 +1,108 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+use capsule::packets::ip::ProtocolNumbers;
+use capsule::{FlowKey, FlowTable};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+const FLOWS: usize = 65536;
+
+fn keys(n: usize) -> Vec<FlowKey> {
+    (0..n)
+        .map(|i| {
+            FlowKey::new(
+                Ipv4Addr::from(0x0a00_0000 + i as u32).into(),
+                (i % 60000) as u16 + 1024,
+                Ipv4Addr::new(192, 168, 0, 1).into(),
+                443,
+                ProtocolNumbers::Tcp,
+            )
+        })
+        .collect()
+}
+
+fn filled(keys: &[FlowKey]) -> FlowTable<u64> {
+    let mut table = FlowTable::new(FLOWS, Duration::from_secs(30));
+    for &key in keys {
+        let _ = table.insert(key, 0);
+    }
+    table
+}
+
+fn flow_table(c: &mut Criterion) {
+    let mut group = c.benchmark_group("flow_table");
+    let keys = keys(FLOWS * 2);
+
+    group.bench_function("flow_table::insert", |b| {
+        b.iter_batched(
+            || FlowTable::<u64>::new(FLOWS, Duration::from_secs(30)),
+            |mut table| {
+                for &key in &keys[..FLOWS] {
+                    let _ = table.insert(key, 0);
+                }
+                table
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("flow_table::insert_with_eviction", |b| {
+        b.iter_batched(
+            || filled(&keys[..FLOWS]),
+            |mut table| {
+                for &key in &keys[FLOWS..] {
+                    let _ = table.insert(key, 0);
+                }
+                table
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let mut table = filled(&keys[..FLOWS]);
+    group.bench_function("flow_table::get_mut", |b| {
+        b.iter(|| {
+            for key in &keys[..FLOWS] {
+                *table.get_mut(key).unwrap() += 1;
+            }
+        })
+    });
+
+    group.bench_function("flow_table::canonical_lookup", |b| {
+        b.iter(|| {
+            for key in &keys[..FLOWS] {
+                let _ = table.get(&key.reversed().canonical());
+            }
+        })
+    });
+
+    group.finish()
+}
+
+fn bench_config() -> Criterion {
+    Criterion::default().with_plots()
+}
+
+criterion_group! {
+    name = benches;
+    config=bench_config();
+    targets=flow_table,
+}
+
+criterion_main!(benches);
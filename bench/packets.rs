@@ -17,14 +17,17 @@
 */
 
 use anyhow::Result;
+use capsule::net::MacAddr;
 use capsule::packets::ip::v4::Ipv4;
 use capsule::packets::ip::v6::{Ipv6, SegmentRouting};
-use capsule::packets::{Ethernet, Packet, Udp4};
+use capsule::packets::{
+    EtherType, EtherTypeSet, EtherTypes, Ethernet, EthernetHeader, Packet, Udp4, VlanTag,
+};
 use capsule::testils::criterion::BencherExt;
 use capsule::testils::proptest::*;
 use capsule::testils::{PacketExt, Rvg};
 use capsule::{fieldmap, Mbuf};
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
 use proptest::prelude::*;
 use std::net::Ipv6Addr;
 
@@ -92,6 +95,35 @@ fn multi_peek_vs_parse(c: &mut Criterion) {
     group.finish()
 }
 
+fn parse_ethernet(mbuf: Mbuf) -> Mbuf {
+    let ethernet = mbuf.parse::<Ethernet>().unwrap();
+    black_box(ethernet.ether_type());
+    ethernet.deparse()
+}
+
+fn view_ethernet(mbuf: Mbuf) -> Mbuf {
+    let view = Ethernet::view(&mbuf).unwrap();
+    black_box(view.ether_type());
+    mbuf
+}
+
+#[capsule::bench(mempool_capacity = 511)]
+fn ethernet_view_vs_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packets::ethernet_view_vs_parse");
+
+    group.bench_function("packets::parse_ethernet", |b| {
+        let s = v4_udp();
+        b.iter_proptest_batched(s, parse_ethernet, BATCH_SIZE)
+    });
+
+    group.bench_function("packets::view_ethernet", |b| {
+        let s = v4_udp();
+        b.iter_proptest_batched(s, view_ethernet, BATCH_SIZE)
+    });
+
+    group.finish()
+}
+
 fn single_parse_srh(ipv6: Ipv6) -> SegmentRouting<Ipv6> {
     ipv6.parse::<SegmentRouting<Ipv6>>().unwrap()
 }
@@ -265,6 +297,160 @@ fn set_srh_segments_sizes(c: &mut Criterion) {
     group.finish()
 }
 
+fn ether_type_set_vs_chain(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packets::ether_type_set_vs_chain");
+
+    // a mix of allowed and disallowed types, low and high values.
+    let values: [u16; 8] = [
+        0x0800, 0x86DD, 0x0806, 0x8100, 0x8847, 0x88E7, 0x894F, 0x0842,
+    ];
+    let ether_types = values
+        .iter()
+        .cycle()
+        .take(BATCH_SIZE)
+        .map(|&v| EtherType::new(v))
+        .collect::<Vec<_>>();
+
+    group.bench_function("packets::ether_type_chain", |b| {
+        b.iter(|| {
+            ether_types
+                .iter()
+                .filter(|&&t| {
+                    t == EtherTypes::Ipv4
+                        || t == EtherTypes::Ipv6
+                        || t == EtherTypes::Arp
+                        || t == EtherTypes::Mpls
+                })
+                .count()
+        })
+    });
+
+    let set = EtherTypeSet::from([
+        EtherTypes::Ipv4,
+        EtherTypes::Ipv6,
+        EtherTypes::Arp,
+        EtherTypes::Mpls,
+    ]);
+    group.bench_function("packets::ether_type_set", |b| {
+        b.iter(|| {
+            ether_types
+                .iter()
+                .filter(|&&t| black_box(&set).contains(t))
+                .count()
+        })
+    });
+
+    group.finish()
+}
+
+const TX_BURST: usize = 32;
+
+fn new_frames() -> Vec<Ethernet> {
+    (0..TX_BURST)
+        .map(|_| Mbuf::new().unwrap().push::<Ethernet>().unwrap())
+        .collect()
+}
+
+#[capsule::bench(mempool_capacity = 511)]
+fn stamp_template_vs_setters(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packets::stamp_template_vs_setters");
+
+    let src = MacAddr::new(0x02, 0, 0, 0, 0, 0x01);
+    let dst = MacAddr::new(0x02, 0, 0, 0, 0, 0x02);
+
+    group.bench_function("packets::stamp_with_setters", |b| {
+        b.iter_batched(
+            new_frames,
+            |mut frames| {
+                for ethernet in frames.iter_mut() {
+                    ethernet.set_src(src);
+                    ethernet.set_dst(dst);
+                    ethernet.set_ether_type(EtherTypes::Ipv4);
+                }
+                frames
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let template = EthernetHeader::new(src, dst, EtherTypes::Ipv4);
+    group.bench_function("packets::stamp_with_template", |b| {
+        b.iter_batched(
+            new_frames,
+            |mut frames| {
+                for ethernet in frames.iter_mut() {
+                    ethernet.apply_template(&template, None).unwrap();
+                }
+                frames
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    let tags = [VlanTag::new(100)];
+    group.bench_function("packets::stamp_with_tagged_template", |b| {
+        b.iter_batched(
+            new_frames,
+            |mut frames| {
+                for ethernet in frames.iter_mut() {
+                    ethernet.apply_template(&template, Some(&tags)).unwrap();
+                }
+                frames
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish()
+}
+
+fn new_burst() -> Vec<Mbuf> {
+    Mbuf::alloc_bulk(TX_BURST).unwrap()
+}
+
+#[capsule::bench(mempool_capacity = 511)]
+fn stamp_burst_vs_per_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packets::stamp_burst_vs_per_frame");
+
+    let dst = MacAddr::new(0x02, 0, 0, 0, 0, 0xff);
+    let template = EthernetHeader::new(MacAddr::UNSPECIFIED, dst, EtherTypes::Ipv4);
+    let sources = (1..=100)
+        .map(|i| MacAddr::new(0x02, 0, 0, 0, 0, i))
+        .collect::<Vec<_>>();
+
+    group.bench_function("packets::stamp_per_frame", |b| {
+        b.iter_batched(
+            new_burst,
+            |burst| {
+                burst
+                    .into_iter()
+                    .zip(sources.iter().cycle())
+                    .map(|(mbuf, &src)| {
+                        let mut ethernet = mbuf.push::<Ethernet>().unwrap();
+                        ethernet.apply_template(&template, None).unwrap();
+                        ethernet.set_src(src);
+                        ethernet
+                    })
+                    .collect::<Vec<_>>()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("packets::stamp_burst", |b| {
+        b.iter_batched(
+            new_burst,
+            |mut burst| {
+                Mbuf::stamp_burst(&mut burst, &template, &sources).unwrap();
+                burst
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish()
+}
+
 fn bench_config() -> Criterion {
     Criterion::default().with_plots()
 }
@@ -274,6 +460,7 @@ criterion_group! {
     config=bench_config();
     targets=single_peek_vs_parse,
             multi_peek_vs_parse,
+            ethernet_view_vs_parse,
             single_parse_srh_segments_sizes,
             multi_parse_upto_variable_srh,
             set_srh_segments_sizes,
@@ -283,6 +470,9 @@ criterion_group! {
             single_remove,
             multi_remove,
             reset,
+            ether_type_set_vs_chain,
+            stamp_template_vs_setters,
+            stamp_burst_vs_per_frame,
 }
 
 criterion_main!(benches);
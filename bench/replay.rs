@@ -0,0 +1,67 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+use capsule::batch::{Pacing, ReplaySource};
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::time::Duration;
+
+const PACKETS: usize = 100;
+
+fn spaced(gap: Duration) -> Vec<(Duration, Vec<u8>)> {
+    (0..PACKETS)
+        .map(|i| (gap * i as u32, vec![0; 64]))
+        .collect()
+}
+
+/// Measures how late the packets go out with the precise pacing, as the
+/// longest delay of a replay rather than the time it takes.
+#[capsule::bench(mempool_capacity = 511)]
+fn precise_pacing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("replay::precise_pacing_max_late");
+    group.sample_size(10);
+
+    for &gap in &[100u64, 1_000] {
+        group.bench_function(format!("replay::gap_{}us", gap), |b| {
+            b.iter_custom(|iters| {
+                let mut late = Duration::default();
+                for _ in 0..iters {
+                    let mut source = ReplaySource::with_timing(spaced(Duration::from_micros(gap)))
+                        .pacing(Pacing::Precise);
+                    while !source.is_done() {
+                        drop(source.receive());
+                    }
+                    late += source.report().max_late;
+                }
+                late
+            })
+        });
+    }
+
+    group.finish()
+}
+
+fn bench_config() -> Criterion {
+    Criterion::default().with_plots()
+}
+
+criterion_group! {
+    name = benches;
+    config=bench_config();
+    targets=precise_pacing,
+}
+
+criterion_main!(benches);
@@ -0,0 +1,395 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{Batch, Disposition, Either};
+use crate::capture::{self, DropSnapshot};
+use crate::packets::Packet;
+use crate::stats::{self, DropReason, StageCounters};
+use crate::trace::{self, Sampler};
+use crate::Mbuf;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+/// Stage chain errors.
+#[derive(Debug, Error)]
+pub(crate) enum ChainError {
+    /// The stage is not part of any chain.
+    #[error("Stage '{0}' not found.")]
+    NotFound(String),
+}
+
+/// A reusable packet processing step of a [`Chain`].
+///
+/// A stage is instantiated once per core by the pipeline installer, so it
+/// can keep mutable state, like a NAT table, without any locks. Closures
+/// with the same signature as [`process`] are stages too.
+///
+/// [`process`]: Stage::process
+pub trait Stage {
+    /// Processes one packet of the batch.
+    ///
+    /// Return `Either::Keep` to hand the packet to the next stage, or
+    /// `Either::Drop` to drop it. On error, the packet is aborted. Both drop
+    /// and abort skip the remaining stages.
+    fn process(&mut self, packet: Mbuf) -> Result<Either<Mbuf>>;
+}
+
+impl<F> Stage for F
+where
+    F: FnMut(Mbuf) -> Result<Either<Mbuf>>,
+{
+    #[inline]
+    fn process(&mut self, packet: Mbuf) -> Result<Either<Mbuf>> {
+        self(packet)
+    }
+}
+
+/// Runtime switches to enable and disable named stages.
+///
+/// A disabled stage is bypassed, passing packets to the next stage
+/// untouched. The switches are shared by all the chains built with them,
+/// so toggling a stage affects its instances on every core. Clone it into
+/// control tasks to toggle stages while the pipelines are running.
+///
+/// # Example
+///
+/// ```
+/// let switches = StageSwitches::default();
+///
+/// runtime.add_pipeline_to_port("eth1", {
+///     let switches = switches.clone();
+///     move |q| {
+///         let chain = Chain::new(&switches)
+///             .stage("decap", Decap::default())
+///             .stage("firewall", Firewall::new())
+///             .stage("encap", Encap::default());
+///         Poll::new(q.clone()).chain(chain).send(q)
+///     }
+/// })?;
+///
+/// runtime.spawn_control(async move {
+///     ...
+///     switches.disable("firewall").unwrap();
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct StageSwitches {
+    switches: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+}
+
+impl StageSwitches {
+    /// Returns the switch of a stage, adding an enabled one if the stage
+    /// is new.
+    fn get_or_add(&self, name: &str) -> Arc<AtomicBool> {
+        self.switches
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_insert_with(|| Arc::new(AtomicBool::new(true)))
+            .clone()
+    }
+
+    fn set(&self, name: &str, enabled: bool) -> Result<()> {
+        let switches = self.switches.lock().unwrap();
+        let switch = switches
+            .get(name)
+            .ok_or_else(|| ChainError::NotFound(name.to_owned()))?;
+        switch.store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Enables a stage on all cores.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ChainError::NotFound` if no chain has a stage with this name.
+    pub fn enable(&self, name: &str) -> Result<()> {
+        self.set(name, true)
+    }
+
+    /// Disables a stage on all cores. The stage is bypassed until enabled
+    /// again.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ChainError::NotFound` if no chain has a stage with this name.
+    pub fn disable(&self, name: &str) -> Result<()> {
+        self.set(name, false)
+    }
+
+    /// Returns whether a stage is enabled, or `None` if no chain has a
+    /// stage with this name.
+    pub fn is_enabled(&self, name: &str) -> Option<bool> {
+        self.switches
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|switch| switch.load(Ordering::Relaxed))
+    }
+}
+
+impl fmt::Debug for StageSwitches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let switches = self.switches.lock().unwrap();
+        f.debug_map()
+            .entries(
+                switches
+                    .iter()
+                    .map(|(name, switch)| (name, switch.load(Ordering::Relaxed))),
+            )
+            .finish()
+    }
+}
+
+/// A named stage of a chain.
+struct Link {
+    name: String,
+    stage: Box<dyn Stage>,
+    enabled: Arc<AtomicBool>,
+    counters: Arc<StageCounters>,
+}
+
+/// An ordered list of named stages.
+///
+/// Build the chain inside the pipeline installer so each core gets its own
+/// stage instances. Each stage keeps a set of counters under its name for
+/// the core it runs on.
+#[allow(missing_debug_implementations)]
+pub struct Chain {
+    switches: StageSwitches,
+    links: Vec<Link>,
+    sampler: Option<Sampler>,
+}
+
+impl Chain {
+    /// Creates an empty chain with stages toggled by `switches`.
+    pub fn new(switches: &StageSwitches) -> Self {
+        Chain {
+            switches: switches.clone(),
+            links: vec![],
+            sampler: None,
+        }
+    }
+
+    /// Appends a stage to the end of the chain.
+    ///
+    /// The stage starts out enabled, unless it was disabled through the
+    /// switches before the chain was built.
+    pub fn stage<S: Stage + 'static>(mut self, name: &str, stage: S) -> Self {
+        self.links.push(Link {
+            name: name.to_owned(),
+            stage: Box::new(stage),
+            enabled: self.switches.get_or_add(name),
+            counters: stats::register_stage(name),
+        });
+        self
+    }
+
+    /// Runs the packet through all the enabled stages.
+    ///
+    /// If the pipeline is traced and the packet is sampled, a snapshot is
+    /// taken after each stage that keeps the packet.
+    #[inline]
+    fn process<T: Packet>(&mut self, packet: T) -> Disposition<Mbuf> {
+        let mbuf = packet.reset();
+
+        match &self.sampler {
+            Some(sampler) if sampler.sample() => run_links(&mut self.links, mbuf, |name, mbuf| {
+                sampler.record(name, mbuf)
+            }),
+            _ => run_links(&mut self.links, mbuf, |_, _| ()),
+        }
+    }
+}
+
+/// Runs the packet through the enabled links, calling `trace` after each
+/// stage that keeps the packet.
+#[inline]
+fn run_links<F>(links: &mut [Link], mut mbuf: Mbuf, trace: F) -> Disposition<Mbuf>
+where
+    F: Fn(&str, &Mbuf),
+{
+    for link in links.iter_mut() {
+        if !link.enabled.load(Ordering::Relaxed) {
+            link.counters.record_bypassed();
+            continue;
+        }
+
+        link.counters.record_processed();
+        let snapshot = DropSnapshot::take(&mbuf);
+        match link.stage.process(mbuf) {
+            Ok(Either::Keep(next)) => {
+                trace(&link.name, &next);
+                mbuf = next;
+            }
+            Ok(Either::Drop(dropped)) => {
+                link.counters.record_dropped();
+                stats::record_dropped(DropReason::Filtered);
+                capture::tap_drop(&dropped, DropReason::Filtered, &link.name);
+                return Disposition::Drop(dropped);
+            }
+            Err(e) => {
+                link.counters.record_errored();
+                snapshot.tap_error(&link.name, &e);
+                return Disposition::Abort(e);
+            }
+        }
+    }
+
+    Disposition::Act(mbuf)
+}
+
+/// A batch that runs the packets of the underlying batch through a chain
+/// of stages.
+#[allow(missing_debug_implementations)]
+pub struct Chained<B: Batch> {
+    batch: B,
+    chain: Chain,
+}
+
+impl<B: Batch> Chained<B> {
+    /// Creates a new `Chained` batch.
+    #[inline]
+    pub fn new(batch: B, chain: Chain) -> Self {
+        Chained { batch, chain }
+    }
+}
+
+impl<B: Batch> Batch for Chained<B> {
+    type Item = Mbuf;
+
+    #[inline]
+    fn replenish(&mut self) {
+        self.chain.sampler = trace::current();
+        self.batch.replenish();
+    }
+
+    #[inline]
+    fn next(&mut self) -> Option<Disposition<Self::Item>> {
+        let chain = &mut self.chain;
+        self.batch
+            .next()
+            .map(|disp| disp.map(|packet| chain.process(packet)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{PacketTx, Pipeline, Poll};
+    use crate::net::MacAddr;
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::ip::ProtocolNumbers;
+    use crate::packets::Ethernet;
+    use crate::stats::RuntimeStats;
+    use crate::testils::byte_arrays::{IPV4_TCP_PACKET, IPV4_UDP_PACKET};
+    use std::sync::mpsc;
+
+    fn stamp_mac() -> MacAddr {
+        MacAddr::new(0x02, 0, 0, 0, 0, 0xff)
+    }
+
+    fn decap(packet: Mbuf) -> Result<Either<Mbuf>> {
+        packet.peek::<Ethernet>()?;
+        Ok(Either::Keep(packet))
+    }
+
+    /// Drops UDP packets.
+    fn firewall(packet: Mbuf) -> Result<Either<Mbuf>> {
+        let udp = packet.peek::<Ethernet>()?.peek::<Ipv4>()?.protocol() == ProtocolNumbers::Udp;
+        if udp {
+            Ok(Either::Drop(packet))
+        } else {
+            Ok(Either::Keep(packet))
+        }
+    }
+
+    fn stamp(packet: Mbuf) -> Result<Either<Mbuf>> {
+        let mut ethernet = packet.parse::<Ethernet>()?;
+        ethernet.set_src(stamp_mac());
+        Ok(Either::Keep(ethernet.reset()))
+    }
+
+    #[capsule::test]
+    fn toggle_middle_stage() {
+        let switches = StageSwitches::default();
+        let (mut tx, rx) = mpsc::channel();
+        let (out, out_rx) = mpsc::channel();
+
+        let chain = Chain::new(&switches)
+            .stage("chain_decap", decap)
+            .stage("chain_firewall", firewall)
+            .stage("chain_stamp", stamp);
+        let mut pipeline = Poll::new(rx).chain(chain).send(out);
+
+        let packets = || {
+            vec![
+                Mbuf::from_bytes(&IPV4_TCP_PACKET).unwrap(),
+                Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap(),
+            ]
+        };
+
+        // the firewall drops the udp packet.
+        tx.transmit(packets());
+        pipeline.run_once();
+        let sent = out_rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(1, sent.len());
+
+        // the firewall is bypassed, both packets go through.
+        switches.disable("chain_firewall").unwrap();
+        assert_eq!(Some(false), switches.is_enabled("chain_firewall"));
+        tx.transmit(packets());
+        pipeline.run_once();
+        let sent = out_rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(2, sent.len());
+
+        // the stages around the bypassed one still run.
+        for mbuf in sent {
+            assert_eq!(stamp_mac(), mbuf.peek::<Ethernet>().unwrap().src());
+        }
+
+        let snapshot = RuntimeStats::snapshot();
+
+        let decap = snapshot.stage("chain_decap").unwrap();
+        assert_eq!(4, decap.processed);
+        assert_eq!(0, decap.bypassed);
+
+        let firewall = snapshot.stage("chain_firewall").unwrap();
+        assert_eq!(2, firewall.processed);
+        assert_eq!(1, firewall.dropped);
+        assert_eq!(2, firewall.bypassed);
+
+        let stamp = snapshot.stage("chain_stamp").unwrap();
+        assert_eq!(3, stamp.processed);
+        assert_eq!(0, stamp.dropped);
+    }
+
+    #[capsule::test]
+    fn unknown_stage() {
+        let switches = StageSwitches::default();
+        let _chain = Chain::new(&switches).stage("chain_known", decap);
+
+        assert!(switches.enable("chain_known").is_ok());
+        assert!(switches.disable("chain_unknown").is_err());
+        assert_eq!(None, switches.is_enabled("chain_unknown"));
+    }
+}
@@ -0,0 +1,157 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{Batch, Disposition};
+use crate::capture::{self, DropSnapshot};
+use crate::packets::{EtherType, Ethernet, Packet};
+use crate::stats::{self, DropReason};
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// Handler for the frames of a registered EtherType.
+pub type DemuxHandler = dyn FnMut(Ethernet) -> Result<Ethernet>;
+
+/// Hook for the frames of an EtherType without a handler.
+pub type UnknownHook = dyn FnMut(Ethernet);
+
+/// What [`Demux`] does with the frames of an EtherType without a handler,
+/// when no [`on_unknown`] hook is set.
+///
+/// [`on_unknown`]: Demux::on_unknown
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnknownEtherType {
+    /// Passes the frame through unchanged.
+    Forward,
+
+    /// Drops the frame.
+    Drop,
+}
+
+impl Default for UnknownEtherType {
+    fn default() -> Self {
+        UnknownEtherType::Forward
+    }
+}
+
+/// A batch that dispatches the Ethernet frames of the underlying batch to
+/// handlers registered by EtherType.
+///
+/// The frames of an EtherType without a handler are given to the
+/// [`on_unknown`] hook if one is set. The hook takes the frame, so it is
+/// marked as emitted. Without a hook, the frames are forwarded or dropped
+/// according to the [`UnknownEtherType`] policy. On handler error, the
+/// frame is marked as aborted.
+///
+/// [`on_unknown`]: Demux::on_unknown
+#[allow(missing_debug_implementations)]
+pub struct Demux<B: Batch<Item = Ethernet>> {
+    batch: B,
+    handlers: HashMap<EtherType, Box<DemuxHandler>>,
+    on_unknown: Option<Box<UnknownHook>>,
+    unknown: UnknownEtherType,
+}
+
+impl<B: Batch<Item = Ethernet>> Demux<B> {
+    /// Creates a new `Demux` batch with no handlers.
+    #[inline]
+    pub fn new(batch: B) -> Self {
+        Demux {
+            batch,
+            handlers: HashMap::new(),
+            on_unknown: None,
+            unknown: UnknownEtherType::default(),
+        }
+    }
+
+    /// Registers the handler for the frames of the EtherType, replacing
+    /// the previous handler if there's one.
+    pub fn on<F>(mut self, ether_type: EtherType, handler: F) -> Self
+    where
+        F: FnMut(Ethernet) -> Result<Ethernet> + 'static,
+    {
+        self.handlers.insert(ether_type, Box::new(handler));
+        self
+    }
+
+    /// Sets the hook for the frames of the EtherTypes without a handler.
+    ///
+    /// The hook takes the whole frame, so it can inspect it, count it,
+    /// or send it somewhere else. The frame is gone from the batch
+    /// afterwards. Letting it go out of scope drops it.
+    pub fn on_unknown<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(Ethernet) + 'static,
+    {
+        self.on_unknown = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets what happens to the frames of the EtherTypes without a handler
+    /// when there's no [`on_unknown`] hook. The default is to forward them.
+    ///
+    /// [`on_unknown`]: Demux::on_unknown
+    pub fn unknown(mut self, policy: UnknownEtherType) -> Self {
+        self.unknown = policy;
+        self
+    }
+}
+
+impl<B: Batch<Item = Ethernet>> Batch for Demux<B> {
+    type Item = Ethernet;
+
+    #[inline]
+    fn replenish(&mut self) {
+        self.batch.replenish();
+    }
+
+    #[inline]
+    fn next(&mut self) -> Option<Disposition<Self::Item>> {
+        let handlers = &mut self.handlers;
+        let on_unknown = &mut self.on_unknown;
+        let unknown = self.unknown;
+
+        self.batch.next().map(|disp| {
+            disp.map(|frame| match handlers.get_mut(&frame.ether_type()) {
+                Some(handler) => {
+                    let snapshot = DropSnapshot::take(frame.mbuf());
+                    match handler(frame) {
+                        Ok(frame) => Disposition::Act(frame),
+                        Err(e) => {
+                            snapshot.tap_error("demux", &e);
+                            Disposition::Abort(e)
+                        }
+                    }
+                }
+                None => match on_unknown {
+                    Some(hook) => {
+                        hook(frame);
+                        Disposition::Emit
+                    }
+                    None => match unknown {
+                        UnknownEtherType::Forward => Disposition::Act(frame),
+                        UnknownEtherType::Drop => {
+                            stats::record_dropped(DropReason::Filtered);
+                            capture::tap_drop(frame.mbuf(), DropReason::Filtered, "demux");
+                            Disposition::Drop(frame.reset())
+                        }
+                    },
+                },
+            })
+        })
+    }
+}
@@ -0,0 +1,249 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::Pipeline;
+use crate::dpdk;
+use crate::stats::{self, TaskCounters};
+use futures::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// A closure invoked at a fixed period, driven by an external clock.
+///
+/// Time is measured in TSC cycles. The task does not catch up on missed
+/// periods. When the poll loop falls behind by more than one period, the
+/// skipped invocations are counted as missed and the schedule restarts
+/// from the current time.
+pub(crate) struct PeriodicTask<F: FnMut()> {
+    period: u64,
+    hz: u64,
+    next: Option<u64>,
+    f: F,
+    counters: Arc<TaskCounters>,
+}
+
+impl<F: FnMut()> PeriodicTask<F> {
+    /// Creates a new task that runs every `period` cycles of a clock
+    /// ticking `hz` times per second.
+    pub(crate) fn new(name: &str, period: u64, hz: u64, f: F) -> Self {
+        let counters = stats::register_task(name, to_duration(period, hz));
+        PeriodicTask {
+            period: period.max(1),
+            hz,
+            next: None,
+            f,
+            counters,
+        }
+    }
+
+    /// Invokes the closure if it is due at cycle `now`, using `clock` to
+    /// measure how long the invocation takes.
+    ///
+    /// The first invocation is one period after the first check.
+    pub(crate) fn run_at(&mut self, now: u64, clock: impl Fn() -> u64) {
+        let next = *self.next.get_or_insert(now + self.period);
+        if now < next {
+            return;
+        }
+
+        let late = now - next;
+        let missed = late / self.period;
+        self.next = Some(if missed > 0 {
+            now + self.period
+        } else {
+            next + self.period
+        });
+
+        (self.f)();
+
+        let busy = clock().saturating_sub(now);
+        self.counters.record_call(
+            missed,
+            busy > self.period,
+            to_duration(busy, self.hz),
+            to_duration(late, self.hz),
+        );
+    }
+}
+
+/// Converts clock cycles to a duration.
+#[inline]
+fn to_duration(cycles: u64, hz: u64) -> Duration {
+    Duration::from_nanos((u128::from(cycles) * 1_000_000_000 / u128::from(hz.max(1))) as u64)
+}
+
+/// A pipeline that also invokes a closure at a fixed period from the same
+/// poll loop.
+///
+/// The closure runs on the core the pipeline runs on, in between batches,
+/// so it can safely touch the same per-core state the pipeline does
+/// without any synchronization.
+#[allow(missing_debug_implementations)]
+pub struct Every<P: Pipeline, F: FnMut()> {
+    pipeline: P,
+    task: PeriodicTask<F>,
+}
+
+impl<P: Pipeline, F: FnMut()> Every<P, F> {
+    /// Creates a new `Every` pipeline.
+    #[inline]
+    pub fn new(pipeline: P, period: Duration, f: F) -> Self {
+        let hz = dpdk::tsc_hz();
+        let cycles = period.as_nanos() * u128::from(hz) / 1_000_000_000;
+        let task = PeriodicTask::new(pipeline.name(), cycles as u64, hz, f);
+        Every { pipeline, task }
+    }
+
+    fn run_task(&mut self) {
+        self.task.run_at(dpdk::tsc_cycles(), dpdk::tsc_cycles);
+    }
+}
+
+impl<P: Pipeline + Unpin, F: FnMut() + Unpin> Future for Every<P, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.pipeline).poll(cx);
+        this.run_task();
+        poll
+    }
+}
+
+impl<P: Pipeline + Unpin, F: FnMut() + Unpin> Pipeline for Every<P, F> {
+    #[inline]
+    fn name(&self) -> &str {
+        self.pipeline.name()
+    }
+
+    #[inline]
+    fn run_once(&mut self) {
+        self.pipeline.run_once();
+        self.run_task();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::RuntimeStats;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // one cycle per nanosecond keeps the arithmetic readable.
+    const HZ: u64 = 1_000_000_000;
+
+    fn task_stats(name: &str) -> stats::TaskStats {
+        RuntimeStats::snapshot().task(name).unwrap()
+    }
+
+    #[test]
+    fn invoke_at_period() {
+        let calls = Rc::new(Cell::new(0));
+        let counted = calls.clone();
+        let mut task = PeriodicTask::new("every_invoke_at_period", 100, HZ, move || {
+            counted.set(counted.get() + 1)
+        });
+
+        // polls every 10 cycles for 1000 cycles.
+        for now in (0..=1000).step_by(10) {
+            task.run_at(now, || now);
+        }
+
+        // the first call is one period after the first check.
+        assert_eq!(10, calls.get());
+
+        let stats = task_stats("every_invoke_at_period");
+        assert_eq!(10, stats.calls);
+        assert_eq!(0, stats.missed);
+        assert_eq!(0, stats.overran);
+        assert_eq!(Duration::from_nanos(100), stats.period);
+    }
+
+    #[test]
+    fn independent_periods() {
+        let fast_calls = Rc::new(Cell::new(0));
+        let slow_calls = Rc::new(Cell::new(0));
+        let (f, s) = (fast_calls.clone(), slow_calls.clone());
+        let mut fast =
+            PeriodicTask::new("every_independent_fast", 50, HZ, move || f.set(f.get() + 1));
+        let mut slow = PeriodicTask::new("every_independent_slow", 300, HZ, move || {
+            s.set(s.get() + 1)
+        });
+
+        for now in (0..=900).step_by(5) {
+            fast.run_at(now, || now);
+            slow.run_at(now, || now);
+        }
+
+        assert_eq!(18, fast_calls.get());
+        assert_eq!(3, slow_calls.get());
+    }
+
+    #[test]
+    fn account_for_slow_callbacks() {
+        let clock = Rc::new(Cell::new(0u64));
+        let shared = clock.clone();
+
+        // every call takes 150 cycles, longer than its 100 cycle period.
+        let mut task = PeriodicTask::new("every_slow_callbacks", 100, HZ, move || {
+            shared.set(shared.get() + 150)
+        });
+
+        for _ in 0..50 {
+            let now = clock.get();
+            task.run_at(now, || clock.get());
+            clock.set(clock.get() + 10);
+        }
+
+        let stats = task_stats("every_slow_callbacks");
+        assert!(stats.calls > 0);
+        assert_eq!(stats.calls, stats.overran);
+        assert_eq!(Duration::from_nanos(150 * stats.calls), stats.busy);
+        assert_eq!(Duration::from_nanos(150), stats.max_busy);
+    }
+
+    #[test]
+    fn count_missed_periods() {
+        let calls = Rc::new(Cell::new(0));
+        let counted = calls.clone();
+        let mut task = PeriodicTask::new("every_missed_periods", 100, HZ, move || {
+            counted.set(counted.get() + 1)
+        });
+
+        task.run_at(0, || 0);
+        task.run_at(100, || 100);
+
+        // the poll loop stalls for 350 cycles, the skipped calls are not
+        // made up for.
+        task.run_at(450, || 450);
+        assert_eq!(2, calls.get());
+
+        // the schedule restarts from the late call.
+        task.run_at(549, || 549);
+        task.run_at(550, || 550);
+        assert_eq!(3, calls.get());
+
+        let stats = task_stats("every_missed_periods");
+        assert_eq!(3, stats.calls);
+        assert_eq!(2, stats.missed);
+        assert_eq!(Duration::from_nanos(250), stats.max_late);
+    }
+}
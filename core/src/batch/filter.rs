@@ -17,7 +17,9 @@
 */
 
 use super::{Batch, Disposition};
+use crate::capture;
 use crate::packets::Packet;
+use crate::stats::{self, DropReason};
 
 /// A batch that filters the packets of the underlying batch.
 ///
@@ -61,6 +63,8 @@ where
                 if (self.predicate)(&pkt) {
                     Disposition::Act(pkt)
                 } else {
+                    stats::record_dropped(DropReason::Filtered);
+                    capture::tap_drop(pkt.mbuf(), DropReason::Filtered, "filter");
                     Disposition::Drop(pkt.reset())
                 }
             })
@@ -17,7 +17,9 @@
 */
 
 use super::{Batch, Disposition};
+use crate::capture::{self, DropSnapshot};
 use crate::packets::Packet;
+use crate::stats::{self, DropReason};
 use crate::Mbuf;
 use anyhow::Result;
 
@@ -72,10 +74,20 @@ where
     #[inline]
     fn next(&mut self) -> Option<Disposition<Self::Item>> {
         self.batch.next().map(|disp| {
-            disp.map(|orig| match (self.f)(orig) {
-                Ok(Either::Keep(new)) => Disposition::Act(new),
-                Ok(Either::Drop(mbuf)) => Disposition::Drop(mbuf),
-                Err(e) => Disposition::Abort(e),
+            disp.map(|orig| {
+                let snapshot = DropSnapshot::take(orig.mbuf());
+                match (self.f)(orig) {
+                    Ok(Either::Keep(new)) => Disposition::Act(new),
+                    Ok(Either::Drop(mbuf)) => {
+                        stats::record_dropped(DropReason::Filtered);
+                        capture::tap_drop(&mbuf, DropReason::Filtered, "filter_map");
+                        Disposition::Drop(mbuf)
+                    }
+                    Err(e) => {
+                        snapshot.tap_error("filter_map", &e);
+                        Disposition::Abort(e)
+                    }
+                }
             })
         })
     }
@@ -0,0 +1,357 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::dpdk::{PortId, PortQueue};
+use crate::{debug, Mbuf};
+use std::fmt;
+use std::iter::FromIterator;
+
+/// A set of ports, identified by their indices in a slice of ports.
+///
+/// The set is a fixed size bitmap, so it's cheap to copy and to keep one
+/// per VLAN or per broadcast domain. It holds up to [`CAPACITY`] ports.
+///
+/// [`CAPACITY`]: PortSet::CAPACITY
+#[derive(Clone, Copy, Default, Eq, Hash, PartialEq)]
+pub struct PortSet(u64);
+
+impl PortSet {
+    /// The maximum number of ports in a set.
+    pub const CAPACITY: usize = 64;
+
+    /// Creates an empty set.
+    #[inline]
+    pub fn new() -> Self {
+        PortSet(0)
+    }
+
+    /// Creates a set of the ports `0` to `len - 1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is greater than `CAPACITY`.
+    #[inline]
+    pub fn all(len: usize) -> Self {
+        assert!(len <= Self::CAPACITY, "port set holds up to 64 ports.");
+        if len == Self::CAPACITY {
+            PortSet(!0)
+        } else {
+            PortSet((1 << len) - 1)
+        }
+    }
+
+    /// Adds a port to the set. Returns whether the port is newly added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `CAPACITY`.
+    #[inline]
+    pub fn insert(&mut self, index: usize) -> bool {
+        assert!(index < Self::CAPACITY, "port set holds up to 64 ports.");
+        let added = !self.contains(index);
+        self.0 |= 1 << index;
+        added
+    }
+
+    /// Removes a port from the set. Returns whether the port was present.
+    #[inline]
+    pub fn remove(&mut self, index: usize) -> bool {
+        let present = self.contains(index);
+        if present {
+            self.0 &= !(1 << index);
+        }
+        present
+    }
+
+    /// Returns whether the set contains the port.
+    #[inline]
+    pub fn contains(&self, index: usize) -> bool {
+        index < Self::CAPACITY && self.0 & (1 << index) != 0
+    }
+
+    /// Returns the number of ports in the set.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Returns whether the set is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the ports in this set but not in `other`.
+    #[inline]
+    pub fn difference(&self, other: PortSet) -> PortSet {
+        PortSet(self.0 & !other.0)
+    }
+
+    /// Returns an iterator over the ports in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> {
+        let bits = self.0;
+        (0..Self::CAPACITY).filter(move |&index| bits & (1 << index) != 0)
+    }
+}
+
+impl FromIterator<usize> for PortSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut set = PortSet::new();
+        for index in iter {
+            set.insert(index);
+        }
+        set
+    }
+}
+
+impl fmt::Debug for PortSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// The transmit side of a port that packets can be flooded to.
+pub trait FloodTx {
+    /// Returns the ID of the port.
+    fn port_id(&self) -> PortId;
+
+    /// Transmits a single packet. Returns whether the packet is accepted,
+    /// either by the transmit queue or by its backlog.
+    fn transmit_one(&self, packet: Mbuf) -> bool;
+}
+
+impl FloodTx for PortQueue {
+    #[inline]
+    fn port_id(&self) -> PortId {
+        PortQueue::port_id(self)
+    }
+
+    #[inline]
+    fn transmit_one(&self, packet: Mbuf) -> bool {
+        PortQueue::transmit(self, vec![packet]) == 0
+    }
+}
+
+/// The outcome of a flood.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Flooded {
+    /// The ports the packet is flooded to.
+    pub targets: PortSet,
+
+    /// The ports that accepted the packet.
+    pub sent: PortSet,
+}
+
+impl Flooded {
+    /// Returns the ports that failed to send the packet.
+    #[inline]
+    pub fn failed(&self) -> PortSet {
+        self.targets.difference(self.sent)
+    }
+}
+
+/// Sends the packet out of every port except the ingress port.
+///
+/// See [`flood_to`] for details.
+///
+/// # Panics
+///
+/// Panics if there are more than `PortSet::CAPACITY` ports.
+///
+/// [`flood_to`]: flood_to
+pub fn flood<P: FloodTx>(packet: Mbuf, ports: &[P], except: PortId) -> Flooded {
+    flood_to(packet, ports, PortSet::all(ports.len()), except)
+}
+
+/// Sends the packet out of every port in `members` except the ingress port.
+///
+/// Every port but the last gets an indirect clone of the packet, which
+/// shares the data buffer of the original instead of copying it. The
+/// packet must not be modified after the flood. Ports are independent of
+/// each other, failing to clone or send to one port doesn't prevent
+/// sending to the rest. Member indices out of the bounds of `ports` are
+/// ignored. If there is no port to flood to, the packet is dropped.
+///
+/// # Example
+///
+/// ```
+/// let flooded = flood_to(packet, &queues, vlan_members, in_port.port_id());
+/// stats.flood_failures += flooded.failed().len();
+/// ```
+pub fn flood_to<P: FloodTx>(
+    packet: Mbuf,
+    ports: &[P],
+    members: PortSet,
+    except: PortId,
+) -> Flooded {
+    let targets = members
+        .iter()
+        .filter(|&index| index < ports.len() && ports[index].port_id() != except)
+        .collect::<PortSet>();
+    let mut sent = PortSet::new();
+
+    if let Some(last) = targets.iter().last() {
+        for index in targets.iter().filter(|&index| index != last) {
+            match packet.clone_indirect() {
+                Ok(clone) => {
+                    if ports[index].transmit_one(clone) {
+                        sent.insert(index);
+                    }
+                }
+                Err(err) => debug!(message = "failed to clone flooded packet.", ?err),
+            }
+        }
+
+        if ports[last].transmit_one(packet) {
+            sent.insert(last);
+        }
+    }
+
+    Flooded { targets, sent }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::IPV4_UDP_PACKET;
+    use std::cell::RefCell;
+
+    struct MockPort {
+        id: PortId,
+        up: bool,
+        sent: RefCell<Vec<Mbuf>>,
+    }
+
+    impl FloodTx for MockPort {
+        fn port_id(&self) -> PortId {
+            self.id
+        }
+
+        fn transmit_one(&self, packet: Mbuf) -> bool {
+            if self.up {
+                self.sent.borrow_mut().push(packet);
+            }
+            self.up
+        }
+    }
+
+    fn mock_ports(up: &[bool]) -> Vec<MockPort> {
+        up.iter()
+            .enumerate()
+            .map(|(i, &up)| MockPort {
+                id: PortId::new(i as u16 + 10),
+                up,
+                sent: RefCell::new(vec![]),
+            })
+            .collect()
+    }
+
+    fn sent_len(ports: &[MockPort]) -> Vec<usize> {
+        ports.iter().map(|port| port.sent.borrow().len()).collect()
+    }
+
+    #[test]
+    fn port_set() {
+        let mut set = PortSet::new();
+        assert!(set.is_empty());
+        assert!(set.insert(3));
+        assert!(!set.insert(3));
+        assert!(set.insert(63));
+        assert!(set.contains(3));
+        assert!(!set.contains(4));
+        assert!(!set.contains(64));
+        assert_eq!(vec![3, 63], set.iter().collect::<Vec<_>>());
+
+        assert!(set.remove(3));
+        assert!(!set.remove(3));
+        assert_eq!(1, set.len());
+
+        assert_eq!(4, PortSet::all(4).len());
+        assert_eq!(64, PortSet::all(64).len());
+        assert_eq!(
+            vec![0, 2],
+            PortSet::all(3)
+                .difference([1].iter().copied().collect())
+                .iter()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[capsule::test]
+    fn flood_all_but_ingress() {
+        let ports = mock_ports(&[true, true, true, true]);
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+
+        let flooded = flood(packet, &ports, ports[1].port_id());
+
+        assert_eq!(vec![0, 2, 3], flooded.targets.iter().collect::<Vec<_>>());
+        assert_eq!(flooded.targets, flooded.sent);
+        assert!(flooded.failed().is_empty());
+        assert_eq!(vec![1, 0, 1, 1], sent_len(&ports));
+
+        for port in ports.iter() {
+            for packet in port.sent.borrow().iter() {
+                let data = packet.read_data_slice::<u8>(0, packet.data_len()).unwrap();
+                assert_eq!(&IPV4_UDP_PACKET[..], unsafe { data.as_ref() });
+            }
+        }
+    }
+
+    #[capsule::test]
+    fn account_for_partial_failures() {
+        let ports = mock_ports(&[true, true, false, true]);
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+
+        let flooded = flood(packet, &ports, ports[0].port_id());
+
+        assert_eq!(vec![1, 2, 3], flooded.targets.iter().collect::<Vec<_>>());
+        assert_eq!(vec![1, 3], flooded.sent.iter().collect::<Vec<_>>());
+        assert_eq!(vec![2], flooded.failed().iter().collect::<Vec<_>>());
+        assert_eq!(vec![0, 1, 0, 1], sent_len(&ports));
+
+        // the last port failing doesn't affect the others.
+        let ports = mock_ports(&[true, true, false]);
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+
+        let flooded = flood(packet, &ports, ports[0].port_id());
+
+        assert_eq!(vec![1], flooded.sent.iter().collect::<Vec<_>>());
+        assert_eq!(vec![2], flooded.failed().iter().collect::<Vec<_>>());
+    }
+
+    #[capsule::test]
+    fn flood_to_members() {
+        let ports = mock_ports(&[true, true, true, true]);
+        let members = [0, 1, 3, 7].iter().copied().collect::<PortSet>();
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+
+        let flooded = flood_to(packet, &ports, members, ports[3].port_id());
+
+        assert_eq!(vec![0, 1], flooded.sent.iter().collect::<Vec<_>>());
+        assert_eq!(vec![1, 1, 0, 0], sent_len(&ports));
+
+        // no port to flood to.
+        let members = [2].iter().copied().collect::<PortSet>();
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+
+        let flooded = flood_to(packet, &ports, members, ports[2].port_id());
+
+        assert!(flooded.targets.is_empty());
+        assert_eq!(vec![1, 1, 0, 0], sent_len(&ports));
+    }
+}
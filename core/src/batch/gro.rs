@@ -0,0 +1,613 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{Batch, Disposition};
+use crate::packets::ip::v4::Ipv4;
+use crate::packets::ip::v6::Ipv6;
+use crate::packets::ip::IpPacket;
+use crate::packets::{EtherTypes, Ethernet, EthernetInfo, Packet, Tcp, Tcp4, Tcp6};
+use crate::{ensure, Mbuf};
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::mem;
+use std::net::IpAddr;
+use thiserror::Error;
+
+/// The largest IP packet a coalesced packet can grow to.
+const MAX_COALESCED_LEN: usize = u16::MAX as usize;
+
+/// Frame coalescing errors.
+#[derive(Debug, Error)]
+pub(crate) enum CoalesceError {
+    /// The frames are not of the same L2 flow.
+    #[error("Frames are not of the same flow.")]
+    NotSameFlow,
+}
+
+/// The addresses and ports of a TCP flow.
+#[derive(Clone, Copy, Eq, PartialEq)]
+struct FlowTuple {
+    src: IpAddr,
+    dst: IpAddr,
+    src_port: u16,
+    dst_port: u16,
+}
+
+/// The fields that must be the same for two segments to be coalesced.
+#[derive(Clone, Copy, Eq, PartialEq)]
+struct SegmentKey {
+    ethernet: EthernetInfo,
+    hop_limit: u8,
+    ack_no: u32,
+    tcp_len: usize,
+    options: [u8; 40],
+}
+
+/// A TCP segment of the burst.
+struct Segment {
+    tuple: FlowTuple,
+    // `None` if the segment can't be coalesced, because it has flags
+    // other than ACK and PSH, or no payload, or IP options.
+    key: Option<SegmentKey>,
+    seq_no: u32,
+    headers_len: usize,
+    ip_len: usize,
+    payload_len: usize,
+    psh: bool,
+}
+
+impl Segment {
+    /// Reads the segment from the packet, or returns `None` if the packet
+    /// is not TCP over IPv4 or IPv6.
+    fn read(mbuf: &Mbuf) -> Option<Segment> {
+        let ethernet = mbuf.peek::<Ethernet>().ok()?;
+        let info = ethernet.info();
+
+        match ethernet.ether_type() {
+            EtherTypes::Ipv4 => {
+                let ipv4 = ethernet.peek::<Ipv4>().ok()?;
+                let tcp = ipv4.peek::<Tcp4>().ok()?;
+                let plain = ipv4.ihl() == 5
+                    && !ipv4.more_fragments()
+                    && ipv4.fragment_offset() == 0
+                    && usize::from(ipv4.total_length()) == ipv4.len();
+                Segment::new(&tcp, info, ipv4.ttl(), ipv4.len(), plain)
+            }
+            EtherTypes::Ipv6 => {
+                let ipv6 = ethernet.peek::<Ipv6>().ok()?;
+                let tcp = ipv6.peek::<Tcp6>().ok()?;
+                let plain = usize::from(ipv6.payload_length()) == ipv6.payload_len();
+                Segment::new(&tcp, info, ipv6.hop_limit(), ipv6.len(), plain)
+            }
+            _ => None,
+        }
+    }
+
+    fn new<E: IpPacket>(
+        tcp: &Tcp<E>,
+        ethernet: EthernetInfo,
+        hop_limit: u8,
+        ip_len: usize,
+        plain: bool,
+    ) -> Option<Segment> {
+        let tcp_len = tcp.data_offset() as usize * 4;
+        let options_len = tcp_len.checked_sub(tcp.header_len())?;
+        let payload_len = tcp.len().checked_sub(tcp_len)?;
+
+        let flags_ok = tcp.ack()
+            && !(tcp.syn() || tcp.fin() || tcp.rst() || tcp.urg() || tcp.ece() || tcp.cwr());
+
+        let key = if plain && flags_ok && payload_len > 0 {
+            let mut options = [0; 40];
+            let bytes = tcp
+                .mbuf()
+                .read_data_slice::<u8>(tcp.payload_offset(), options_len)
+                .ok()?;
+            options[..options_len].copy_from_slice(unsafe { bytes.as_ref() });
+
+            Some(SegmentKey {
+                ethernet,
+                hop_limit,
+                ack_no: tcp.ack_no(),
+                tcp_len,
+                options,
+            })
+        } else {
+            None
+        };
+
+        Some(Segment {
+            tuple: FlowTuple {
+                src: tcp.envelope().src(),
+                dst: tcp.envelope().dst(),
+                src_port: tcp.src_port(),
+                dst_port: tcp.dst_port(),
+            },
+            key,
+            seq_no: tcp.seq_no(),
+            headers_len: tcp.offset() + tcp_len,
+            ip_len,
+            payload_len,
+            psh: tcp.psh(),
+        })
+    }
+}
+
+/// A coalesced packet being built.
+struct Flow {
+    tuple: FlowTuple,
+    key: SegmentKey,
+    // the position of the head packet in the ready queue.
+    index: usize,
+    next_seq: u32,
+    segment_size: usize,
+    ip_len: usize,
+    merged: bool,
+    psh: bool,
+    closed: bool,
+}
+
+/// A batch that coalesces the TCP segments of a flow received in the same
+/// burst into one packet, in software.
+///
+/// Consecutive in-order segments with the same headers, apart from the
+/// sequence numbers, are chained into the first segment. The last segment
+/// of a coalesced packet can be shorter than the others, or have the PSH
+/// flag. The IP length, IP checksum and TCP checksum of the coalesced
+/// packet are reconciled, and the packet is marked as coalesced the same
+/// way as with the `tcp_lro` receive offload. See
+/// [`Mbuf::coalesced_segment_size`].
+///
+/// Any other packet passes through unchanged, in order with the rest.
+///
+/// [`Mbuf::coalesced_segment_size`]: crate::Mbuf::coalesced_segment_size
+#[allow(missing_debug_implementations)]
+pub struct Gro<B: Batch<Item = Mbuf>> {
+    batch: B,
+    max_len: usize,
+    ready: VecDeque<Disposition<Mbuf>>,
+}
+
+impl<B: Batch<Item = Mbuf>> Gro<B> {
+    /// Creates a new `Gro` batch that coalesces up to `max_len` bytes of
+    /// IP packet.
+    #[inline]
+    pub fn new(batch: B, max_len: usize) -> Self {
+        Gro {
+            batch,
+            max_len: max_len.min(MAX_COALESCED_LEN),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Coalesces all the packets of the underlying batch into the ready
+    /// queue.
+    fn fill(&mut self) {
+        let mut flows: Vec<Flow> = vec![];
+
+        while let Some(disp) = self.batch.next() {
+            match disp {
+                Disposition::Act(mbuf) => self.coalesce(mbuf, &mut flows),
+                disp => self.ready.push_back(disp),
+            }
+        }
+
+        for flow in flows.into_iter().filter(|flow| flow.merged) {
+            let disp = mem::replace(&mut self.ready[flow.index], Disposition::Emit);
+            self.ready[flow.index] = disp.map(|mbuf| match finish(mbuf, &flow) {
+                Ok(mbuf) => Disposition::Act(mbuf),
+                Err(e) => Disposition::Abort(e),
+            });
+        }
+    }
+
+    /// Chains the packet to the flow it continues, or queues it.
+    fn coalesce(&mut self, mbuf: Mbuf, flows: &mut Vec<Flow>) {
+        let segment = match Segment::read(&mbuf) {
+            Some(segment) => segment,
+            None => {
+                self.ready.push_back(Disposition::Act(mbuf));
+                return;
+            }
+        };
+
+        let flow = flows
+            .iter_mut()
+            .find(|flow| flow.tuple == segment.tuple && !flow.closed);
+
+        if let (Some(flow), Some(key)) = (flow, segment.key) {
+            if flow.key == key
+                && flow.next_seq == segment.seq_no
+                && segment.payload_len <= flow.segment_size
+                && flow.ip_len + segment.payload_len <= self.max_len
+            {
+                if let Disposition::Act(head) = &mut self.ready[flow.index] {
+                    let mut tail = mbuf;
+                    let chained = tail
+                        .shrink(0, segment.headers_len)
+                        .and_then(|_| head.chain(tail));
+
+                    match chained {
+                        Ok(_) => {
+                            flow.next_seq = flow.next_seq.wrapping_add(segment.payload_len as u32);
+                            flow.ip_len += segment.payload_len;
+                            flow.merged = true;
+                            flow.psh |= segment.psh;
+                            flow.closed = segment.psh || segment.payload_len < flow.segment_size;
+                        }
+                        Err(e) => self.ready.push_back(Disposition::Abort(e)),
+                    }
+                    return;
+                }
+            }
+        }
+
+        // the segment doesn't continue the flow. no later segment can be
+        // coalesced into an earlier packet without reordering the flow.
+        for flow in flows.iter_mut().filter(|flow| flow.tuple == segment.tuple) {
+            flow.closed = true;
+        }
+
+        if let Some(key) = segment.key {
+            flows.push(Flow {
+                tuple: segment.tuple,
+                key,
+                index: self.ready.len(),
+                next_seq: segment.seq_no.wrapping_add(segment.payload_len as u32),
+                segment_size: segment.payload_len,
+                ip_len: segment.ip_len,
+                merged: false,
+                psh: segment.psh,
+                closed: segment.psh,
+            });
+        }
+
+        self.ready.push_back(Disposition::Act(mbuf));
+    }
+}
+
+/// Reconciles the headers of a coalesced packet and marks it as such.
+fn finish(mut mbuf: Mbuf, flow: &Flow) -> Result<Mbuf> {
+    mbuf.set_coalesced(flow.segment_size as u16);
+    let ethernet = mbuf.parse::<Ethernet>()?;
+
+    if ethernet.ether_type() == EtherTypes::Ipv4 {
+        let tcp = ethernet.parse::<Ipv4>()?.parse::<Tcp4>()?;
+        Ok(reconcile(tcp, flow.psh).reset())
+    } else {
+        let tcp = ethernet.parse::<Ipv6>()?.parse::<Tcp6>()?;
+        Ok(reconcile(tcp, flow.psh).reset())
+    }
+}
+
+fn reconcile<E: IpPacket>(mut tcp: Tcp<E>, psh: bool) -> Tcp<E> {
+    if psh {
+        tcp.set_psh();
+    }
+    tcp.reconcile_all();
+    tcp
+}
+
+impl<B: Batch<Item = Mbuf>> Batch for Gro<B> {
+    type Item = Mbuf;
+
+    #[inline]
+    fn replenish(&mut self) {
+        self.batch.replenish();
+    }
+
+    #[inline]
+    fn next(&mut self) -> Option<Disposition<Self::Item>> {
+        if self.ready.is_empty() {
+            self.fill();
+        }
+
+        self.ready.pop_front()
+    }
+}
+
+/// Returns whether two frames are of the same L2 flow, with the same source
+/// and destination MAC addresses, EtherType and VLAN identifiers.
+///
+/// This is the L2 check of a software receive offload that coalesces
+/// consecutive frames before handing them to the stack. Higher layers
+/// extend it with their own checks, for example that two TCP segments
+/// are of the same connection and in sequence.
+pub fn can_coalesce(a: &Ethernet, b: &Ethernet) -> bool {
+    a.src() == b.src()
+        && a.dst() == b.dst()
+        && a.ether_type() == b.ether_type()
+        && a.vlan_tags().len() == b.vlan_tags().len()
+        && a.vlan_ids() == b.vlan_ids()
+}
+
+/// Coalesces `tail` into `head` by chaining the payload of `tail` to the
+/// end of `head`. The Ethernet header of `tail` is removed, so the headers
+/// of `head` describe the whole frame.
+///
+/// The chained payload is not visible to the packet types parsed from
+/// `head`, they only see the first segment. The caller should fix the
+/// length fields of the higher layer headers.
+///
+/// # Errors
+///
+/// Returns `CoalesceError::NotSameFlow` if the frames can't be coalesced,
+/// or `BufferError::NotChained` if the payload can't be chained. `tail`
+/// is freed on error.
+pub fn coalesce(head: &mut Ethernet, tail: Ethernet) -> Result<()> {
+    ensure!(can_coalesce(head, &tail), CoalesceError::NotSameFlow);
+
+    let payload = tail.remove()?;
+    head.mbuf_mut().chain(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{PacketTx, Poll};
+    use crate::net::MacAddr;
+    use crate::packets::Tci;
+    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, VLAN_DOT1Q_PACKET};
+    use std::sync::mpsc;
+
+    /// Fills in a segment of the flow to port 80 from `src_port`.
+    fn segment<E: IpPacket>(mut tcp: Tcp<E>, src_port: u16, seq_no: u32, payload: &[u8]) -> Mbuf {
+        tcp.set_src_port(src_port);
+        tcp.set_dst_port(80);
+        tcp.set_seq_no(seq_no);
+        tcp.set_ack_no(1);
+        tcp.set_ack();
+
+        let offset = tcp.payload_offset();
+        tcp.mbuf_mut().extend(offset, payload.len()).unwrap();
+        tcp.mbuf_mut().write_data_slice(offset, payload).unwrap();
+        tcp.reconcile_all();
+        tcp.reset()
+    }
+
+    fn segment4(src_port: u16, seq_no: u32, payload: &[u8]) -> Tcp4 {
+        let tcp = Mbuf::new()
+            .unwrap()
+            .push::<Ethernet>()
+            .unwrap()
+            .push::<Ipv4>()
+            .unwrap()
+            .push::<Tcp4>()
+            .unwrap();
+        segment(tcp, src_port, seq_no, payload)
+            .parse::<Ethernet>()
+            .unwrap()
+            .parse::<Ipv4>()
+            .unwrap()
+            .parse::<Tcp4>()
+            .unwrap()
+    }
+
+    fn segment6(seq_no: u32, payload: &[u8]) -> Mbuf {
+        let tcp = Mbuf::new()
+            .unwrap()
+            .push::<Ethernet>()
+            .unwrap()
+            .push::<Ipv6>()
+            .unwrap()
+            .push::<Tcp6>()
+            .unwrap();
+        segment(tcp, 1000, seq_no, payload)
+    }
+
+    fn gro_batch(packets: Vec<Mbuf>) -> Gro<Poll<mpsc::Receiver<Mbuf>>> {
+        let (mut tx, rx) = mpsc::channel();
+        tx.transmit(packets);
+        let mut batch = Poll::new(rx).gro(MAX_COALESCED_LEN);
+        batch.replenish();
+        batch
+    }
+
+    fn next_act<B: Batch<Item = Mbuf>>(batch: &mut B) -> Mbuf {
+        match batch.next() {
+            Some(Disposition::Act(mbuf)) => mbuf,
+            _ => panic!("not an act."),
+        }
+    }
+
+    #[capsule::test]
+    fn coalesce_segment_train() {
+        let mut batch = gro_batch(vec![
+            segment4(1000, 1, &[1; 100]).reset(),
+            segment4(1000, 101, &[2; 100]).reset(),
+            segment4(1000, 201, &[3; 50]).reset(),
+        ]);
+
+        let mbuf = next_act(&mut batch);
+        assert!(batch.next().is_none());
+        assert_eq!(3, mbuf.segments());
+        assert_eq!(Some(100), mbuf.coalesced_segment_size());
+
+        let tcp = mbuf
+            .parse::<Ethernet>()
+            .unwrap()
+            .parse::<Ipv4>()
+            .unwrap()
+            .parse::<Tcp4>()
+            .unwrap();
+        assert_eq!(290, tcp.envelope().total_length());
+        assert_eq!(270, tcp.full_len());
+
+        // same as the segments sent as one.
+        let payload = [&[1; 100][..], &[2; 100], &[3; 50]].concat();
+        let whole = segment4(1000, 1, &payload);
+        assert_eq!(whole.checksum(), tcp.checksum());
+        assert_eq!(whole.envelope().checksum(), tcp.envelope().checksum());
+    }
+
+    #[capsule::test]
+    fn coalesce_ipv6_segment_train() {
+        let mut batch = gro_batch(vec![segment6(1, &[1; 100]), segment6(101, &[2; 100])]);
+
+        let ipv6 = next_act(&mut batch)
+            .parse::<Ethernet>()
+            .unwrap()
+            .parse::<Ipv6>()
+            .unwrap();
+        assert!(batch.next().is_none());
+        assert_eq!(220, ipv6.payload_length());
+    }
+
+    #[capsule::test]
+    fn keep_segments_out_of_order() {
+        let mut batch = gro_batch(vec![
+            segment4(1000, 1, &[1; 100]).reset(),
+            segment4(1000, 201, &[2; 100]).reset(),
+            segment4(1000, 101, &[3; 100]).reset(),
+        ]);
+
+        for _ in 0..3 {
+            assert_eq!(None, next_act(&mut batch).coalesced_segment_size());
+        }
+        assert!(batch.next().is_none());
+    }
+
+    #[capsule::test]
+    fn end_coalescing_on_flags() {
+        let mut psh = segment4(1000, 101, &[2; 100]);
+        psh.set_psh();
+        let mut fin = segment4(1000, 301, &[4; 100]);
+        fin.set_fin();
+
+        let mut batch = gro_batch(vec![
+            segment4(1000, 1, &[1; 100]).reset(),
+            psh.reset(),
+            segment4(1000, 201, &[3; 100]).reset(),
+            fin.reset(),
+            segment4(1000, 401, &[5; 100]).reset(),
+        ]);
+
+        // the PSH segment ends the first packet.
+        let tcp = next_act(&mut batch)
+            .parse::<Ethernet>()
+            .unwrap()
+            .parse::<Ipv4>()
+            .unwrap()
+            .parse::<Tcp4>()
+            .unwrap();
+        assert_eq!(240, tcp.full_len());
+        assert!(tcp.psh());
+
+        // the FIN segment can't be coalesced, and nothing after it can be
+        // coalesced into the packet before it.
+        assert_eq!(1, next_act(&mut batch).segments());
+        assert_eq!(1, next_act(&mut batch).segments());
+        assert_eq!(1, next_act(&mut batch).segments());
+        assert!(batch.next().is_none());
+    }
+
+    #[capsule::test]
+    fn coalesce_interleaved_flows() {
+        let mut batch = gro_batch(vec![
+            segment4(1000, 1, &[1; 100]).reset(),
+            segment4(2000, 1, &[1; 100]).reset(),
+            segment4(1000, 101, &[2; 100]).reset(),
+            segment4(2000, 101, &[2; 100]).reset(),
+        ]);
+
+        for src_port in &[1000, 2000] {
+            let tcp = next_act(&mut batch)
+                .parse::<Ethernet>()
+                .unwrap()
+                .parse::<Ipv4>()
+                .unwrap()
+                .parse::<Tcp4>()
+                .unwrap();
+            assert_eq!(*src_port, tcp.src_port());
+            assert_eq!(2, tcp.mbuf().segments());
+        }
+        assert!(batch.next().is_none());
+    }
+
+    #[capsule::test]
+    fn coalesce_up_to_max_len() {
+        let (mut tx, rx) = mpsc::channel();
+        tx.transmit(vec![
+            segment4(1000, 1, &[1; 100]).reset(),
+            segment4(1000, 101, &[2; 100]).reset(),
+            segment4(1000, 201, &[3; 100]).reset(),
+        ]);
+        let mut batch = Poll::new(rx).gro(250);
+        batch.replenish();
+
+        assert_eq!(2, next_act(&mut batch).segments());
+        assert_eq!(1, next_act(&mut batch).segments());
+        assert!(batch.next().is_none());
+    }
+
+    #[capsule::test]
+    fn coalesce_frames_of_same_flow() {
+        let mut head = Mbuf::from_bytes(&IPV4_UDP_PACKET)
+            .unwrap()
+            .parse::<Ethernet>()
+            .unwrap();
+        let tail = Mbuf::from_bytes(&IPV4_UDP_PACKET)
+            .unwrap()
+            .parse::<Ethernet>()
+            .unwrap();
+
+        assert!(can_coalesce(&head, &tail));
+        assert!(coalesce(&mut head, tail).is_ok());
+
+        // the header of the tail is removed.
+        assert_eq!(2, head.mbuf().segments());
+        assert_eq!(IPV4_UDP_PACKET.len(), head.mbuf().data_len());
+        assert_eq!(IPV4_UDP_PACKET.len() * 2 - 14, head.mbuf().pkt_len());
+
+        // the packet types only see the first segment.
+        assert!(head.parse::<Ipv4>().is_ok());
+    }
+
+    #[capsule::test]
+    fn not_coalesce_frames_of_different_flows() {
+        let parse = |bytes: &[u8]| {
+            Mbuf::from_bytes(bytes)
+                .unwrap()
+                .parse::<Ethernet>()
+                .unwrap()
+        };
+
+        let untagged = parse(&IPV4_UDP_PACKET);
+        let tagged = parse(&VLAN_DOT1Q_PACKET);
+        let mut other_vlan = parse(&VLAN_DOT1Q_PACKET);
+        other_vlan
+            .set_outer_tci(Tci {
+                vid: 456,
+                ..Default::default()
+            })
+            .unwrap();
+        let mut other_dst = parse(&IPV4_UDP_PACKET);
+        other_dst.set_dst(MacAddr::new(0x02, 0, 0, 0xff, 0xff, 0x02));
+        let mut other_type = parse(&IPV4_UDP_PACKET);
+        other_type.set_ether_type(EtherTypes::Ipv6);
+
+        assert!(can_coalesce(&tagged, &tagged));
+        assert!(!can_coalesce(&tagged, &other_vlan));
+        assert!(!can_coalesce(&untagged, &other_dst));
+        assert!(!can_coalesce(&untagged, &other_type));
+
+        let mut head = parse(&IPV4_UDP_PACKET);
+        assert!(coalesce(&mut head, tagged).is_err());
+        assert_eq!(1, head.mbuf().segments());
+    }
+}
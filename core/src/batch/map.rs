@@ -17,6 +17,7 @@
 */
 
 use super::{Batch, Disposition};
+use crate::capture::DropSnapshot;
 use crate::packets::Packet;
 use anyhow::Result;
 
@@ -58,9 +59,15 @@ where
     #[inline]
     fn next(&mut self) -> Option<Disposition<Self::Item>> {
         self.batch.next().map(|disp| {
-            disp.map(|orig| match (self.f)(orig) {
-                Ok(new) => Disposition::Act(new),
-                Err(e) => Disposition::Abort(e),
+            disp.map(|orig| {
+                let snapshot = DropSnapshot::take(orig.mbuf());
+                match (self.f)(orig) {
+                    Ok(new) => Disposition::Act(new),
+                    Err(e) => {
+                        snapshot.tap_error("map", &e);
+                        Disposition::Abort(e)
+                    }
+                }
             })
         })
     }
@@ -18,35 +18,48 @@
 
 //! Combinators that can be applied to batches of packets within a pipeline.
 
+mod chain;
+mod demux;
 mod emit;
+mod every;
 mod filter;
 mod filter_map;
+mod flood;
 mod for_each;
+mod gro;
 mod group_by;
 mod inspect;
 mod map;
 mod poll;
 mod replace;
+mod replay;
 mod rxtx;
 mod send;
 
+pub use self::chain::*;
+pub use self::demux::*;
 pub use self::emit::*;
+pub use self::every::*;
 pub use self::filter::*;
 pub use self::filter_map::*;
+pub use self::flood::*;
 pub use self::for_each::*;
+pub use self::gro::*;
 pub use self::group_by::*;
 pub use self::inspect::*;
 pub use self::map::*;
 pub use self::poll::*;
 pub use self::replace::*;
+pub use self::replay::*;
 pub use self::rxtx::*;
 pub use self::send::*;
 
-use crate::packets::Packet;
-use crate::Mbuf;
+use crate::packets::{Ethernet, Packet};
+use crate::{Mbuf, RingTx};
 use anyhow::{Error, Result};
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::time::Duration;
 
 /// Way to categorize the packets of a batch inside a processing pipeline.
 /// The disposition instructs the combinators how to process a packet.
@@ -116,6 +129,10 @@ pub trait PacketRx {
 pub trait PacketTx {
     /// Transmits a batch of packets.
     fn transmit(&mut self, packets: Vec<Mbuf>);
+
+    /// Retries the packets held back in a software queue, for example the
+    /// transmit backlog of a `PortQueue`. Does nothing by default.
+    fn flush(&mut self) {}
 }
 
 /// Common behaviors to apply on batches of packets.
@@ -134,6 +151,59 @@ pub trait Batch {
     /// [`replenish`]: Batch::replenish
     fn next(&mut self) -> Option<Disposition<Self::Item>>;
 
+    /// Creates a batch that runs each packet through a [`Chain`] of named
+    /// stages.
+    ///
+    /// The packets are reset to `Mbuf` before entering the chain, and come
+    /// out of it as `Mbuf`. Stages can be bypassed at runtime through the
+    /// [`StageSwitches`] the chain is built with.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let chain = Chain::new(&switches)
+    ///     .stage("decap", Decap::default())
+    ///     .stage("nat", Nat::new(pool));
+    /// let mut batch = batch.chain(chain);
+    /// ```
+    #[inline]
+    fn chain(self, chain: Chain) -> Chained<Self>
+    where
+        Self: Sized,
+    {
+        Chained::new(self, chain)
+    }
+
+    /// Creates a batch that dispatches the Ethernet frames to handlers
+    /// registered by EtherType.
+    ///
+    /// The frames of the EtherTypes without a handler are forwarded by
+    /// default. Set an [`on_unknown`] hook to take them instead, or an
+    /// [`UnknownEtherType`] policy to drop them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut batch = batch
+    ///     .map(|packet| packet.parse::<Ethernet>())
+    ///     .demux()
+    ///     .on(EtherTypes::Ipv4, do_ipv4)
+    ///     .on(EtherTypes::Arp, do_arp)
+    ///     .on_unknown(move |frame| {
+    ///         debug!(ether_type = ?frame.ether_type(), "unknown EtherType.");
+    ///         kni.transmit(vec![frame.reset()]);
+    ///     });
+    /// ```
+    ///
+    /// [`on_unknown`]: Demux::on_unknown
+    #[inline]
+    fn demux(self) -> Demux<Self>
+    where
+        Self: Batch<Item = Ethernet> + Sized,
+    {
+        Demux::new(self)
+    }
+
     /// Creates a batch that transmits all packets through the specified
     /// [`PacketTx`].
     ///
@@ -272,6 +342,28 @@ pub trait Batch {
         Inspect::new(self, f)
     }
 
+    /// Creates a batch that coalesces the TCP segments of a flow received
+    /// in the same burst, for ports whose device doesn't have the `tcp_lro`
+    /// receive offload.
+    ///
+    /// `max_len` caps the length of a coalesced IP packet, up to `65535`.
+    /// Use it first thing after receiving, before the packets are parsed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut batch = Poll::new(q.clone())
+    ///     .gro(65535)
+    ///     .map(|packet| packet.parse::<Ethernet>()?.parse::<Ipv4>());
+    /// ```
+    #[inline]
+    fn gro(self, max_len: usize) -> Gro<Self>
+    where
+        Self: Batch<Item = Mbuf> + Sized,
+    {
+        Gro::new(self, max_len)
+    }
+
     /// Splits the packets into multiple sub batches. Each sub batch runs
     /// through a separate pipeline, and are then merged back together.
     ///
@@ -384,6 +476,31 @@ pub trait Batch {
     {
         Send::new(name.to_owned(), self, tx)
     }
+
+    /// Turns the batch pipeline into an executable task that hands the
+    /// packets to a pipeline on another core through a [`PacketRing`].
+    ///
+    /// The pipeline is named after the ring. Packets the ring can't accept
+    /// are handled according to the ring's [`RingPolicy`].
+    ///
+    /// # Example
+    /// ```
+    /// let (tx, rx) = PacketRing::channel(1024, RingPolicy::Backpressure)?;
+    ///
+    /// Poll::new(q.clone()).filter(classify).send_to(tx);
+    /// Poll::new(rx).map(process).send(q);
+    /// ```
+    ///
+    /// [`PacketRing`]: crate::PacketRing
+    /// [`RingPolicy`]: crate::RingPolicy
+    #[inline]
+    fn send_to(self, ring: RingTx) -> Send<Self, RingTx>
+    where
+        Self: Sized,
+    {
+        let name = ring.ring().name().to_owned();
+        Send::new(name, self, ring)
+    }
 }
 
 /// Trait bound for batch pipelines. Can be used as a convenience for writing
@@ -402,6 +519,35 @@ pub trait Pipeline: futures::Future<Output = ()> {
 
     /// Runs the pipeline once to process one batch of packets.
     fn run_once(&mut self);
+
+    /// Invokes a closure at approximately every `period` from the same poll
+    /// loop the pipeline runs in.
+    ///
+    /// Use it for housekeeping that touches the same per-core state as the
+    /// pipeline, like expiring flow table entries, without a separate thread
+    /// or locks. The closure is checked after every batch, so a long running
+    /// closure delays the packets behind it. How long the calls take is
+    /// recorded in the pipeline's task stats. Chain `every` more than once to
+    /// add several tasks with independent periods.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let flows = Rc::new(RefCell::new(FlowTable::new()));
+    /// let sweeper = flows.clone();
+    ///
+    /// Poll::new(q.clone())
+    ///     .for_each(move |packet| flows.borrow_mut().track(packet))
+    ///     .send(q)
+    ///     .every(Duration::from_secs(1), move || sweeper.borrow_mut().expire());
+    /// ```
+    fn every<F>(self, period: Duration, f: F) -> Every<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(),
+    {
+        Every::new(self, period, f)
+    }
 }
 
 /// Splices a [`PacketRx`] directly to a [`PacketTx`] without any intermediary
@@ -431,8 +577,12 @@ mod tests {
     use crate::compose;
     use crate::packets::ip::v4::Ipv4;
     use crate::packets::ip::ProtocolNumbers;
-    use crate::packets::Ethernet;
-    use crate::testils::byte_arrays::{ICMPV4_PACKET, IPV4_TCP_PACKET, IPV4_UDP_PACKET};
+    use crate::packets::EtherTypes;
+    use crate::testils::byte_arrays::{
+        ARP4_PACKET, ICMPV4_PACKET, IPV4_TCP_PACKET, IPV4_UDP_PACKET, IPV6_TCP_PACKET,
+    };
+    use std::cell::Cell;
+    use std::rc::Rc;
     use std::sync::mpsc::{self, TryRecvError};
 
     fn new_batch(data: &[&[u8]]) -> impl Batch<Item = Mbuf> {
@@ -529,6 +679,58 @@ mod tests {
         assert!(side_effect);
     }
 
+    #[capsule::test]
+    fn demux_batch() {
+        let mut batch = new_batch(&[&IPV4_UDP_PACKET, &ARP4_PACKET, &IPV6_TCP_PACKET])
+            .map(|p| p.parse::<Ethernet>())
+            .demux()
+            .on(EtherTypes::Ipv4, |mut frame| {
+                frame.swap_addresses();
+                Ok(frame)
+            })
+            .on(EtherTypes::Arp, |_| Err(anyhow::anyhow!("no arp")));
+
+        assert!(batch.next().unwrap().is_act());
+        assert!(batch.next().unwrap().is_abort());
+
+        // unknown and no hook, forwarded by default.
+        let disp = batch.next().unwrap();
+        assert!(disp.is_act());
+        if let Disposition::Act(frame) = disp {
+            assert_eq!(EtherTypes::Ipv6, frame.ether_type());
+        }
+    }
+
+    #[capsule::test]
+    fn demux_drop_unknown() {
+        let mut batch = new_batch(&[&IPV4_UDP_PACKET, &IPV6_TCP_PACKET])
+            .map(|p| p.parse::<Ethernet>())
+            .demux()
+            .on(EtherTypes::Ipv4, Ok)
+            .unknown(UnknownEtherType::Drop);
+
+        assert!(batch.next().unwrap().is_act());
+        assert!(batch.next().unwrap().is_drop());
+    }
+
+    #[capsule::test]
+    fn demux_on_unknown() {
+        let seen = Rc::new(Cell::new(None));
+        let hook_seen = seen.clone();
+
+        let mut batch = new_batch(&[&IPV6_TCP_PACKET, &IPV4_UDP_PACKET])
+            .map(|p| p.parse::<Ethernet>())
+            .demux()
+            .on(EtherTypes::Ipv4, Ok)
+            .unknown(UnknownEtherType::Drop)
+            .on_unknown(move |frame| hook_seen.set(Some(frame.ether_type())));
+
+        // the hook takes precedence over the policy.
+        assert!(batch.next().unwrap().is_emit());
+        assert_eq!(Some(EtherTypes::Ipv6), seen.get());
+        assert!(batch.next().unwrap().is_act());
+    }
+
     #[capsule::test]
     fn group_by_batch() {
         let mut batch = new_batch(&[&IPV4_TCP_PACKET, &IPV4_UDP_PACKET, &ICMPV4_PACKET])
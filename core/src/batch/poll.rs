@@ -17,6 +17,7 @@
 */
 
 use super::{Batch, Disposition, PacketRx, PollRx};
+use crate::stats;
 use crate::Mbuf;
 use std::collections::VecDeque;
 
@@ -49,7 +50,9 @@ impl<Rx: PacketRx> Batch for Poll<Rx> {
         // conversion from `Vec` to `VecDeque` to be allocation-free. but
         // unfortunately that's not always the case. We need an efficient and
         // allocation-free data structure with pop semantic.
-        self.packets = Some(self.rx.receive().into());
+        let packets = self.rx.receive();
+        stats::record_received(packets.len());
+        self.packets = Some(packets.into());
     }
 
     #[inline]
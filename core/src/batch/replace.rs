@@ -17,7 +17,9 @@
 */
 
 use super::{Batch, Disposition};
+use crate::capture;
 use crate::packets::Packet;
+use crate::stats::{self, DropReason};
 use anyhow::Result;
 
 /// A batch that replaces each packet of the batch with another packet.
@@ -68,6 +70,8 @@ where
         // 2x in length because each item becomes 2 items.
         if let Some(pkt) = self.slot.take() {
             // has a packet in the temp slot. marks it as dropped.
+            stats::record_dropped(DropReason::Replaced);
+            capture::tap_drop(pkt.mbuf(), DropReason::Replaced, "replace");
             Some(Disposition::Drop(pkt.reset()))
         } else {
             // nothing in the slot, fetches a new packet from source.
@@ -80,7 +84,10 @@ where
                             self.slot.replace(orig);
                             Disposition::Act(new)
                         }
-                        Err(e) => Disposition::Abort(e),
+                        Err(e) => {
+                            capture::tap_error(orig.mbuf(), "replace", &e);
+                            Disposition::Abort(e)
+                        }
                     }
                 })
             })
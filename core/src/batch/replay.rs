@@ -0,0 +1,845 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::net::MacAddr;
+use crate::pcap;
+use crate::{debug, info, Mbuf};
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::hint;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The maximum number of packets replayed per receive.
+const REPLAY_BURST: usize = 32;
+
+/// How far ahead of a frame the timed replayer stops sleeping and starts
+/// spinning. Sleeps overshoot by up to a scheduler tick.
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// How fast a [`ReplaySource`] replays its packets.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pacing {
+    /// Replays a full burst on every receive.
+    MaxSpeed,
+
+    /// Replays at a fixed rate of packets per second.
+    Rate(u64),
+
+    /// Replays with the same spacing between packets as when they were
+    /// captured, at the resolution of the pipeline's polling.
+    Original,
+
+    /// Replays with the same spacing as `Original`, but waits in each
+    /// receive until the next packet is due, sleeping and then spinning.
+    /// The gaps are accurate to about 10µs, at the cost of blocking the
+    /// core between packets.
+    Precise,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Pacing::MaxSpeed
+    }
+}
+
+/// The position of the replay.
+#[derive(Clone, Copy, Default)]
+struct Cursor {
+    next: usize,
+    rounds: usize,
+    sent: u64,
+    round_start: Duration,
+}
+
+/// How closely a replay follows its intended timing.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ReplayReport {
+    /// The number of packets replayed.
+    pub packets: u64,
+    /// When the last packet replayed is due, since the start of the replay.
+    pub intended: Duration,
+    /// When the last packet replayed is handed out, since the start of the
+    /// replay.
+    pub achieved: Duration,
+    /// The longest delay between when a packet is due and when it's handed
+    /// out.
+    pub max_late: Duration,
+}
+
+/// The time source of a replay that waits for its packets.
+pub(crate) trait ReplayClock {
+    /// Returns the time since the replay started.
+    fn elapsed(&self) -> Duration;
+
+    /// Waits for up to `left`, the time until the next packet is due.
+    fn wait(&mut self, left: Duration);
+}
+
+/// The wall clock, started with the replay.
+struct SystemClock(Instant);
+
+impl ReplayClock for SystemClock {
+    fn elapsed(&self) -> Duration {
+        self.0.elapsed()
+    }
+
+    /// Sleeps until shortly before the packet is due, then spins. Sleeps
+    /// overshoot by up to a scheduler tick.
+    fn wait(&mut self, left: Duration) {
+        if left > SPIN_THRESHOLD {
+            thread::sleep(left - SPIN_THRESHOLD);
+        } else {
+            hint::spin_loop();
+        }
+    }
+}
+
+/// Waits on the clock until `due`, and returns the time then.
+fn wait_until<C: ReplayClock>(clock: &mut C, due: Duration) -> Duration {
+    loop {
+        let now = clock.elapsed();
+        if now >= due {
+            return now;
+        }
+        clock.wait(due - now);
+    }
+}
+
+/// A receive source that replays previously captured packets.
+///
+/// The packets are replayed in order, once by default, in bursts of up to
+/// 32 packets. The mbufs are allocated in bulk from the mempool of the
+/// current core.
+///
+/// When the replay is done, a [`ReplayReport`] of the achieved against the
+/// intended timing is logged, and can also be read with [`report`].
+///
+/// # Example
+///
+/// ```
+/// let source = ReplaySource::from_pcap("capture.pcap")?
+///     .pacing(Pacing::Precise)
+///     .speed(2.0)
+///     .rewrite_macs(q.mac_addr(), gateway_mac)
+///     .repeat(5);
+///
+/// let pipeline = Poll::new(source).map(...).send(q);
+/// ```
+///
+/// [`report`]: ReplaySource::report
+#[allow(missing_debug_implementations)]
+pub struct ReplaySource {
+    packets: Vec<(Duration, Vec<u8>)>,
+    pacing: Pacing,
+    speed: f64,
+    macs: Option<(MacAddr, MacAddr)>,
+    repeat: Option<usize>,
+    cursor: Cursor,
+    start: Option<Instant>,
+    report: ReplayReport,
+}
+
+impl ReplaySource {
+    /// Creates a new source that replays the packets. The packets have no
+    /// capture timing, so `Pacing::Original` replays them at max speed.
+    pub fn new(packets: Vec<Vec<u8>>) -> Self {
+        ReplaySource::with_timing(
+            packets
+                .into_iter()
+                .map(|data| (Duration::default(), data))
+                .collect(),
+        )
+    }
+
+    /// Creates a new source that replays the packets, each with its
+    /// capture time relative to the first packet.
+    pub fn with_timing(packets: Vec<(Duration, Vec<u8>)>) -> Self {
+        ReplaySource {
+            packets,
+            pacing: Pacing::default(),
+            speed: 1.0,
+            macs: None,
+            repeat: Some(1),
+            cursor: Cursor::default(),
+            start: None,
+            report: ReplayReport::default(),
+        }
+    }
+
+    /// Creates a new source that replays the packets of a pcap or pcapng
+    /// file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read, or is not a valid
+    /// capture file.
+    pub fn from_pcap<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let packets = pcap::read_file(path).collect::<Result<Vec<_>>>()?;
+        let first = packets.first().map(|&(ts, _)| ts).unwrap_or_default();
+
+        Ok(ReplaySource::with_timing(
+            packets
+                .into_iter()
+                .map(|(ts, data)| (ts.checked_sub(first).unwrap_or_default(), data))
+                .collect(),
+        ))
+    }
+
+    /// Sets how fast the packets are replayed.
+    pub fn pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Sets the speed multiplier of the `Original` and `Precise` pacings.
+    /// `2.0` replays twice as fast as captured, and `0.5` half as fast.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `multiplier` is not between `0.1` and `100.0`.
+    pub fn speed(mut self, multiplier: f64) -> Self {
+        assert!(
+            (0.1..=100.0).contains(&multiplier),
+            "replay speed must be between 0.1 and 100."
+        );
+        self.speed = multiplier;
+        self
+    }
+
+    /// Rewrites the source and destination MAC addresses of the replayed
+    /// packets, for example to the address of the egress port and of its
+    /// next hop.
+    pub fn rewrite_macs(mut self, src: MacAddr, dst: MacAddr) -> Self {
+        self.macs = Some((src, dst));
+        self
+    }
+
+    /// Sets the number of times the packets are replayed.
+    pub fn repeat(mut self, count: usize) -> Self {
+        self.repeat = Some(count);
+        self
+    }
+
+    /// Replays the packets in a loop until the source is dropped.
+    pub fn forever(mut self) -> Self {
+        self.repeat = None;
+        self
+    }
+
+    /// Returns whether all the packets are replayed.
+    pub fn is_done(&self) -> bool {
+        self.is_finished(&self.cursor)
+    }
+
+    /// Returns whether the cursor is past the last round.
+    fn is_finished(&self, cursor: &Cursor) -> bool {
+        self.packets.is_empty() || self.repeat.map_or(false, |count| cursor.rounds >= count)
+    }
+
+    /// Returns the timing of the replay so far.
+    pub fn report(&self) -> ReplayReport {
+        self.report
+    }
+
+    /// Returns when the next packet is due, since the start of the replay.
+    fn due_at(&self, cursor: &Cursor) -> Duration {
+        match self.pacing {
+            Pacing::MaxSpeed => Duration::default(),
+            Pacing::Rate(pps) => Duration::from_nanos(
+                (u128::from(cursor.sent + 1) * 1_000_000_000 / u128::from(pps.max(1))) as u64,
+            ),
+            Pacing::Original | Pacing::Precise => {
+                (cursor.round_start + self.packets[cursor.next].0).div_f64(self.speed)
+            }
+        }
+    }
+
+    /// Returns whether the next packet is due at `now`.
+    fn is_due(&self, cursor: &Cursor, now: Duration) -> bool {
+        match self.pacing {
+            Pacing::MaxSpeed => true,
+            Pacing::Rate(pps) => {
+                u128::from(cursor.sent) < now.as_nanos() * u128::from(pps) / 1_000_000_000
+            }
+            Pacing::Original | Pacing::Precise => self.due_at(cursor) <= now,
+        }
+    }
+
+    /// Moves the cursor to the next packet, wrapping around at the end of
+    /// the list. A new round starts where the last one ends.
+    fn advance(&self, cursor: &mut Cursor) {
+        cursor.sent += 1;
+        cursor.next += 1;
+        if cursor.next == self.packets.len() {
+            cursor.next = 0;
+            cursor.rounds += 1;
+            cursor.round_start += self.packets[self.packets.len() - 1].0;
+        }
+    }
+
+    /// Replays the packets due at `now`, the time since the replay
+    /// started.
+    pub(crate) fn receive_at(&mut self, now: Duration) -> Vec<Mbuf> {
+        // walks a copy of the cursor first, so nothing is skipped if the
+        // allocation fails.
+        let mut cursor = self.cursor;
+        let mut report = self.report;
+        let mut due = Vec::with_capacity(REPLAY_BURST);
+        while due.len() < REPLAY_BURST && !self.is_finished(&cursor) && self.is_due(&cursor, now) {
+            let due_at = self.due_at(&cursor);
+            report.packets += 1;
+            report.intended = due_at;
+            report.achieved = now;
+            report.max_late = report
+                .max_late
+                .max(now.checked_sub(due_at).unwrap_or_default());
+
+            due.push(cursor.next);
+            self.advance(&mut cursor);
+        }
+
+        if due.is_empty() {
+            return vec![];
+        }
+
+        let mbufs = match Mbuf::alloc_bulk(due.len()) {
+            Ok(mbufs) => mbufs,
+            Err(err) => {
+                debug!(message = "failed to allocate replayed packets.", ?err);
+                return vec![];
+            }
+        };
+        self.cursor = cursor;
+        self.report = report;
+
+        if self.is_done() {
+            info!(
+                message = "replay done.",
+                packets = report.packets,
+                intended = ?report.intended,
+                achieved = ?report.achieved,
+                max_late = ?report.max_late
+            );
+        }
+
+        let macs = self.macs;
+        mbufs
+            .into_iter()
+            .zip(due)
+            .filter_map(|(mut mbuf, index)| {
+                let data = &self.packets[index].1;
+                let written = mbuf
+                    .extend(0, data.len())
+                    .and_then(|_| mbuf.write_data_slice(0, &data[..]))
+                    .and_then(|_| match macs {
+                        // too short to have the addresses, left as is.
+                        Some((src, dst)) if data.len() >= 12 => {
+                            mbuf.write_data_slice(0, &dst.octets())?;
+                            mbuf.write_data_slice(6, &src.octets())?;
+                            Ok(())
+                        }
+                        _ => Ok(()),
+                    });
+                if let Err(err) = written {
+                    debug!(message = "failed to replay packet.", ?err);
+                    return None;
+                }
+                Some(mbuf)
+            })
+            .collect()
+    }
+
+    /// Waits on the clock until the next packet is due, then replays the
+    /// packets due by then.
+    pub(crate) fn receive_with<C: ReplayClock>(&mut self, clock: &mut C) -> Vec<Mbuf> {
+        if self.is_done() {
+            return vec![];
+        }
+
+        let now = wait_until(clock, self.due_at(&self.cursor));
+        self.receive_at(now)
+    }
+
+    /// Replays the packets due since the first receive. With the `Precise`
+    /// pacing, waits until the next packet is due first.
+    pub fn receive(&mut self) -> Vec<Mbuf> {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        match self.pacing {
+            Pacing::Precise => self.receive_with(&mut SystemClock(start)),
+            _ => self.receive_at(start.elapsed()),
+        }
+    }
+}
+
+/// Hands out captured frames with the same spacing as when they were
+/// received, for transmission.
+///
+/// Unlike [`ReplaySource`], the replayer takes the `Mbuf`s themselves, so
+/// frames captured by a pipeline can be re-transmitted as is. The spacing
+/// is scaled by the speed multiplier, `2.0` replays twice as fast and `0.5`
+/// half as fast. The frames are always handed out in order, a timestamp
+/// earlier than the one before is replayed right after the previous frame.
+///
+/// As an iterator, the replayer sleeps and then spins until each frame is
+/// due, for the most precise spacing. As a [`PacketRx`], it hands out the
+/// frames due without blocking, at the resolution of the pipeline's polling.
+///
+/// # Example
+///
+/// ```
+/// let replayer = TimedReplayer::from_timestamps(captured).speed(2.0);
+/// for mbuf in replayer {
+///     q.transmit(vec![mbuf]);
+/// }
+/// ```
+///
+/// [`PacketRx`]: crate::batch::PacketRx
+#[allow(missing_debug_implementations)]
+pub struct TimedReplayer {
+    frames: VecDeque<(Duration, Mbuf)>,
+    speed: f64,
+    start: Option<Instant>,
+}
+
+impl TimedReplayer {
+    /// Creates a new replayer of the frames, each with its receive time.
+    /// The times are relative to each other, the first frame is due as
+    /// soon as the replay starts.
+    pub fn new(frames: Vec<(Mbuf, Duration)>) -> Self {
+        let first = frames.first().map(|&(_, ts)| ts).unwrap_or_default();
+        let mut last = Duration::default();
+        let frames = frames
+            .into_iter()
+            .map(|(mbuf, ts)| {
+                last = last.max(ts.checked_sub(first).unwrap_or_default());
+                (last, mbuf)
+            })
+            .collect();
+
+        TimedReplayer {
+            frames,
+            speed: 1.0,
+            start: None,
+        }
+    }
+
+    /// Creates a new replayer of the frames, with the receive timestamps
+    /// of the `Mbuf`s in nanoseconds. A frame without a timestamp is
+    /// replayed right after the previous one.
+    pub fn from_timestamps(mbufs: Vec<Mbuf>) -> Self {
+        let mut last = 0;
+        let frames = mbufs
+            .into_iter()
+            .map(|mbuf| {
+                last = mbuf.timestamp().unwrap_or(last);
+                (mbuf, Duration::from_nanos(last))
+            })
+            .collect();
+        TimedReplayer::new(frames)
+    }
+
+    /// Sets the speed multiplier of the replay.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `multiplier` is not positive.
+    pub fn speed(mut self, multiplier: f64) -> Self {
+        assert!(multiplier > 0.0, "replay speed must be positive.");
+        self.speed = multiplier;
+        self
+    }
+
+    /// Returns the number of frames left to replay.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns whether all the frames are replayed.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Returns when the next frame is due, relative to the start of the
+    /// replay and scaled by the speed multiplier.
+    fn next_due(&self) -> Option<Duration> {
+        self.frames
+            .front()
+            .map(|&(offset, _)| offset.div_f64(self.speed))
+    }
+
+    /// Hands out up to a burst of the frames due at `now`, the time since
+    /// the replay started.
+    pub(crate) fn receive_at(&mut self, now: Duration) -> Vec<Mbuf> {
+        let mut due = Vec::with_capacity(REPLAY_BURST);
+        while due.len() < REPLAY_BURST && self.next_due().map_or(false, |next| next <= now) {
+            let (_, mbuf) = self.frames.pop_front().unwrap();
+            due.push(mbuf);
+        }
+        due
+    }
+
+    /// Hands out the frames due since the first receive, without blocking.
+    pub fn receive(&mut self) -> Vec<Mbuf> {
+        let now = self.start.get_or_insert_with(Instant::now).elapsed();
+        self.receive_at(now)
+    }
+}
+
+impl Iterator for TimedReplayer {
+    type Item = Mbuf;
+
+    /// Waits until the next frame is due and hands it out.
+    fn next(&mut self) -> Option<Mbuf> {
+        let due = self.next_due()?;
+        let start = *self.start.get_or_insert_with(Instant::now);
+        wait_until(&mut SystemClock(start), due);
+        self.frames.pop_front().map(|(_, mbuf)| mbuf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::PacketRx;
+    use std::fs;
+    use std::io::Write;
+
+    fn numbered(count: u8) -> Vec<Vec<u8>> {
+        (0..count).map(|i| vec![i; 64]).collect()
+    }
+
+    fn first_bytes(mbufs: &[Mbuf]) -> Vec<u8> {
+        mbufs
+            .iter()
+            .map(|mbuf| unsafe { *mbuf.read_data::<u8>(0).unwrap().as_ref() })
+            .collect()
+    }
+
+    #[capsule::test]
+    fn replay_in_order() {
+        let mut source = ReplaySource::new(numbered(3)).repeat(2);
+
+        let mbufs = source.receive_at(Duration::default());
+        assert_eq!(vec![0, 1, 2, 0, 1, 2], first_bytes(&mbufs));
+        assert_eq!(64, mbufs[0].data_len());
+        assert!(source.is_done());
+        assert!(source.receive_at(Duration::from_secs(1)).is_empty());
+    }
+
+    #[capsule::test]
+    fn replay_forever_in_bursts() {
+        let mut source = ReplaySource::new(numbered(20)).forever();
+
+        for round in 0..5 {
+            let mbufs = source.receive_at(Duration::default());
+            assert_eq!(REPLAY_BURST, mbufs.len());
+            assert_eq!((round * 32 % 20) as u8, first_bytes(&mbufs)[0]);
+        }
+        assert!(!source.is_done());
+
+        // also works through the receive trait.
+        assert_eq!(REPLAY_BURST, PacketRx::receive(&mut source).len());
+    }
+
+    #[capsule::test]
+    fn replay_at_fixed_rate() {
+        let mut source = ReplaySource::new(numbered(100))
+            .pacing(Pacing::Rate(1_000))
+            .repeat(1);
+
+        assert!(source.receive_at(Duration::default()).is_empty());
+        assert_eq!(10, source.receive_at(Duration::from_millis(10)).len());
+        assert!(source.receive_at(Duration::from_millis(10)).is_empty());
+        assert_eq!(1, source.receive_at(Duration::from_micros(11_500)).len());
+
+        // falls behind, catches up a burst at a time.
+        assert_eq!(32, source.receive_at(Duration::from_millis(60)).len());
+        assert_eq!(17, source.receive_at(Duration::from_millis(60)).len());
+
+        // never past the end.
+        assert_eq!(32, source.receive_at(Duration::from_secs(1)).len());
+        assert_eq!(8, source.receive_at(Duration::from_secs(1)).len());
+        assert!(source.is_done());
+    }
+
+    #[capsule::test]
+    fn replay_original_timing() {
+        let packets = numbered(3)
+            .into_iter()
+            .zip(&[0, 10, 30])
+            .map(|(data, &ms)| (Duration::from_millis(ms), data))
+            .collect();
+        let mut source = ReplaySource::with_timing(packets)
+            .pacing(Pacing::Original)
+            .repeat(2);
+
+        let at = |source: &mut ReplaySource, ms| {
+            first_bytes(&source.receive_at(Duration::from_millis(ms)))
+        };
+
+        assert_eq!(vec![0], at(&mut source, 0));
+        assert_eq!(Vec::<u8>::new(), at(&mut source, 9));
+        assert_eq!(vec![1], at(&mut source, 15));
+        // the second round starts where the first one ends.
+        assert_eq!(vec![2, 0], at(&mut source, 30));
+        assert_eq!(vec![1], at(&mut source, 40));
+        assert_eq!(vec![2], at(&mut source, 100));
+        assert!(source.is_done());
+    }
+
+    #[capsule::test]
+    fn replay_pcap_file() {
+        let mut bytes = vec![];
+        for word in &[0xa1b2_c3d4u32, 0x0004_0002, 0, 0, 65535, 1] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        for (secs, micros, byte) in &[(100u32, 500_000u32, 7u8), (101, 0, 8)] {
+            for word in &[*secs, *micros, 60, 60] {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[*byte; 60]);
+        }
+
+        let path = std::env::temp_dir().join("capsule_replay_pcap_file.pcap");
+        fs::File::create(&path).unwrap().write_all(&bytes).unwrap();
+
+        let mut source = ReplaySource::from_pcap(&path)
+            .unwrap()
+            .pacing(Pacing::Original);
+        assert_eq!(
+            vec![7],
+            first_bytes(&source.receive_at(Duration::default()))
+        );
+        assert!(source.receive_at(Duration::from_millis(499)).is_empty());
+        let mbufs = source.receive_at(Duration::from_millis(500));
+        assert_eq!(vec![8], first_bytes(&mbufs));
+        assert_eq!(60, mbufs[0].data_len());
+
+        // a truncated file is rejected.
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(&bytes[..bytes.len() - 1])
+            .unwrap();
+        assert!(ReplaySource::from_pcap(&path).is_err());
+        fs::remove_file(&path).unwrap();
+
+        // not a capture file.
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(&[0; 24])
+            .unwrap();
+        assert!(ReplaySource::from_pcap(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// A clock that moves forward only when waited on, by the time left
+    /// plus an overshoot.
+    struct MockClock {
+        now: Duration,
+        overshoot: Duration,
+        waits: Vec<Duration>,
+    }
+
+    impl MockClock {
+        fn new(overshoot: Duration) -> Self {
+            MockClock {
+                now: Duration::default(),
+                overshoot,
+                waits: vec![],
+            }
+        }
+    }
+
+    impl ReplayClock for MockClock {
+        fn elapsed(&self) -> Duration {
+            self.now
+        }
+
+        fn wait(&mut self, left: Duration) {
+            self.waits.push(left);
+            self.now += left + self.overshoot;
+        }
+    }
+
+    fn gaps(micros: &[u64]) -> Vec<(Duration, Vec<u8>)> {
+        numbered(micros.len() as u8)
+            .into_iter()
+            .zip(micros)
+            .map(|(data, &us)| (Duration::from_micros(us), data))
+            .collect()
+    }
+
+    #[capsule::test]
+    fn replay_precise_gaps() {
+        let mut source = ReplaySource::with_timing(gaps(&[0, 200, 250, 1250]))
+            .pacing(Pacing::Precise)
+            .speed(2.0);
+        let mut clock = MockClock::new(Duration::default());
+
+        for i in 0..4 {
+            assert_eq!(vec![i], first_bytes(&source.receive_with(&mut clock)));
+        }
+        assert!(source.is_done());
+        assert!(source.receive_with(&mut clock).is_empty());
+
+        // the gaps are halved, the first packet is due right away.
+        let us = Duration::from_micros;
+        assert_eq!(vec![us(100), us(25), us(500)], clock.waits);
+
+        let report = source.report();
+        assert_eq!(4, report.packets);
+        assert_eq!(us(625), report.intended);
+        assert_eq!(us(625), report.achieved);
+        assert_eq!(Duration::default(), report.max_late);
+    }
+
+    #[capsule::test]
+    fn report_late_replay() {
+        let mut source = ReplaySource::with_timing(gaps(&[0, 100, 125, 625]))
+            .pacing(Pacing::Precise)
+            .repeat(2);
+        let mut clock = MockClock::new(Duration::from_micros(10));
+
+        let mut replayed = 0;
+        while !source.is_done() {
+            replayed += source.receive_with(&mut clock).len();
+        }
+        assert_eq!(8, replayed);
+
+        // each wait overshoots by 10µs, and the packets due by then go
+        // out together. the second round starts where the first one ends.
+        let us = Duration::from_micros;
+        assert_eq!(
+            vec![us(100), us(15), us(490), us(90), us(15), us(490)],
+            clock.waits
+        );
+
+        let report = source.report();
+        assert_eq!(8, report.packets);
+        assert_eq!(us(1250), report.intended);
+        assert_eq!(us(1260), report.achieved);
+        assert_eq!(us(10), report.max_late);
+    }
+
+    #[capsule::test]
+    fn replay_original_at_speed() {
+        let mut source = ReplaySource::with_timing(gaps(&[0, 1000, 3000]))
+            .pacing(Pacing::Original)
+            .speed(0.5);
+
+        let at = |source: &mut ReplaySource, us| {
+            first_bytes(&source.receive_at(Duration::from_micros(us)))
+        };
+
+        assert_eq!(vec![0], at(&mut source, 0));
+        assert_eq!(Vec::<u8>::new(), at(&mut source, 1999));
+        assert_eq!(vec![1], at(&mut source, 2000));
+        assert_eq!(vec![2], at(&mut source, 6500));
+
+        let report = source.report();
+        assert_eq!(Duration::from_millis(6), report.intended);
+        assert_eq!(Duration::from_micros(6500), report.achieved);
+        assert_eq!(Duration::from_micros(500), report.max_late);
+    }
+
+    #[capsule::test]
+    fn replay_with_rewritten_macs() {
+        let src = MacAddr::new(0x02, 0, 0, 0, 0, 0x01);
+        let dst = MacAddr::new(0x02, 0, 0, 0, 0, 0x02);
+        let mut source =
+            ReplaySource::new(vec![vec![0xaa; 64], vec![0xbb; 8]]).rewrite_macs(src, dst);
+
+        let mbufs = source.receive_at(Duration::default());
+        assert_eq!(2, mbufs.len());
+
+        let frame = mbufs[0].read_segments(0);
+        assert_eq!(&dst.octets(), &frame[..6]);
+        assert_eq!(&src.octets(), &frame[6..12]);
+        assert_eq!(&[0xaa; 52][..], &frame[12..]);
+
+        // too short to have the addresses.
+        assert_eq!(vec![0xbb; 8], mbufs[1].read_segments(0));
+    }
+
+    fn timed(ms: &[u64]) -> TimedReplayer {
+        let frames = ms
+            .iter()
+            .enumerate()
+            .map(|(i, &ms)| {
+                let mut mbuf = Mbuf::new().unwrap();
+                mbuf.extend(0, 1).unwrap();
+                mbuf.write_data(0, &(i as u8)).unwrap();
+                mbuf.set_timestamp(ms * 1_000_000);
+                mbuf
+            })
+            .collect();
+        TimedReplayer::from_timestamps(frames)
+    }
+
+    #[capsule::test]
+    fn timed_replay_in_order() {
+        // the third frame is earlier than the second, and still goes out
+        // after it.
+        let mut replayer = timed(&[100, 110, 105, 130]);
+        assert_eq!(4, replayer.len());
+
+        let at = |replayer: &mut TimedReplayer, ms| {
+            first_bytes(&replayer.receive_at(Duration::from_millis(ms)))
+        };
+
+        assert_eq!(vec![0], at(&mut replayer, 0));
+        assert_eq!(Vec::<u8>::new(), at(&mut replayer, 9));
+        assert_eq!(vec![1, 2], at(&mut replayer, 10));
+        assert_eq!(Vec::<u8>::new(), at(&mut replayer, 29));
+        assert_eq!(vec![3], at(&mut replayer, 30));
+        assert!(replayer.is_empty());
+    }
+
+    #[capsule::test]
+    fn timed_replay_at_speed() {
+        let mut fast = timed(&[0, 10, 30]).speed(2.0);
+        assert_eq!(2, fast.receive_at(Duration::from_millis(5)).len());
+        assert_eq!(1, fast.receive_at(Duration::from_millis(15)).len());
+
+        let mut slow = timed(&[0, 10, 30]).speed(0.5);
+        assert_eq!(1, slow.receive_at(Duration::from_millis(10)).len());
+        assert_eq!(1, slow.receive_at(Duration::from_millis(20)).len());
+        assert!(slow.receive_at(Duration::from_millis(59)).is_empty());
+        assert_eq!(1, slow.receive_at(Duration::from_millis(60)).len());
+    }
+
+    #[capsule::test]
+    fn timed_replay_spacing() {
+        let replayer = timed(&[0, 20, 60]).speed(2.0);
+
+        let start = Instant::now();
+        let replayed = replayer
+            .map(|mbuf| (first_bytes(&[mbuf])[0], start.elapsed()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![0, 1, 2],
+            replayed.iter().map(|r| r.0).collect::<Vec<_>>()
+        );
+        assert!(replayed[1].1 >= Duration::from_millis(10));
+        assert!(replayed[2].1 >= Duration::from_millis(30));
+        assert!(replayed[2].1 < Duration::from_millis(500));
+    }
+}
@@ -24,12 +24,23 @@
 //!
 //! `PacketTx` implemented for `KniTxQueue`.
 //!
+//! `PacketRx` implemented for `RingRx`.
+//!
+//! `PacketTx` implemented for `RingTx`.
+//!
+//! `PacketTx` implemented for `NeighborTx`.
+//!
+//! `PacketRx` implemented for `ReplaySource`.
+//!
+//! `PacketRx` implemented for `TimedReplayer`.
+//!
 //! Implemented for the MPSC channel so it can be used as a batch source
 //! mostly in tests.
 
-use super::{PacketRx, PacketTx};
-use crate::{KniRx, KniTxQueue, Mbuf, PortQueue};
+use super::{PacketRx, PacketTx, ReplaySource, TimedReplayer};
+use crate::{KniRx, KniTxQueue, Mbuf, NeighborTx, PortQueue, RingRx, RingTx};
 use std::iter;
+use std::net::IpAddr;
 use std::sync::mpsc::{Receiver, Sender};
 
 impl PacketRx for PortQueue {
@@ -40,7 +51,11 @@ impl PacketRx for PortQueue {
 
 impl PacketTx for PortQueue {
     fn transmit(&mut self, packets: Vec<Mbuf>) {
-        PortQueue::transmit(self, packets)
+        PortQueue::transmit(self, packets);
+    }
+
+    fn flush(&mut self) {
+        PortQueue::flush(self)
     }
 }
 
@@ -56,6 +71,40 @@ impl PacketTx for KniTxQueue {
     }
 }
 
+impl PacketRx for RingRx {
+    fn receive(&mut self) -> Vec<Mbuf> {
+        RingRx::receive(self)
+    }
+}
+
+impl PacketRx for ReplaySource {
+    fn receive(&mut self) -> Vec<Mbuf> {
+        ReplaySource::receive(self)
+    }
+}
+
+impl PacketRx for TimedReplayer {
+    fn receive(&mut self) -> Vec<Mbuf> {
+        TimedReplayer::receive(self)
+    }
+}
+
+impl PacketTx for RingTx {
+    fn transmit(&mut self, packets: Vec<Mbuf>) {
+        RingTx::transmit(self, packets)
+    }
+}
+
+impl<Tx, F> PacketTx for NeighborTx<Tx, F>
+where
+    Tx: PacketTx,
+    F: FnMut(&Mbuf) -> Option<IpAddr>,
+{
+    fn transmit(&mut self, packets: Vec<Mbuf>) {
+        NeighborTx::transmit(self, packets)
+    }
+}
+
 impl PacketRx for Receiver<Mbuf> {
     fn receive(&mut self) -> Vec<Mbuf> {
         iter::from_fn(|| self.try_recv().ok()).collect::<Vec<_>>()
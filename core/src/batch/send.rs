@@ -17,26 +17,70 @@
 */
 
 use super::{Batch, Disposition, PacketTx, Pipeline};
-use crate::dpdk::CoreId;
-#[cfg(feature = "metrics")]
-use crate::metrics::{labels, Counter, SINK};
+use crate::capture::{CaptureTap, CaptureTarget};
+use crate::dpdk;
 use crate::packets::Packet;
+use crate::stats::{self, PipelineCounters};
+use crate::trace::PipelineTrace;
 use crate::Mbuf;
 use futures::{future, Future};
+use std::mem;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tokio_executor::current_thread;
 
-/// Creates a new pipeline counter.
-#[cfg(feature = "metrics")]
-fn new_counter(name: &'static str, pipeline: &str) -> Counter {
-    SINK.scoped("pipeline").counter_with_labels(
-        name,
-        labels!(
-            "pipeline" => pipeline.to_owned(),
-            "core" => CoreId::current().raw().to_string(),
-        ),
-    )
+/// The number of held packets that are transmitted right away.
+const TX_BURST_MAX: usize = 32;
+
+/// Holds outbound packets back until either a full burst accumulates or
+/// the oldest packet has been held for longer than the deadline.
+///
+/// Time is measured in TSC cycles, which are cheap to read on every poll.
+pub(crate) struct TxHold {
+    max_hold: u64,
+    burst: usize,
+    packets: Vec<Mbuf>,
+    oldest: u64,
+}
+
+impl TxHold {
+    /// Creates a new hold that releases `burst` packets right away, and
+    /// fewer packets once the oldest is `max_hold` cycles old.
+    pub(crate) fn new(max_hold: u64, burst: usize) -> Self {
+        TxHold {
+            max_hold,
+            burst,
+            packets: vec![],
+            oldest: 0,
+        }
+    }
+
+    /// Returns whether no packets are held.
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Adds the packets to the hold at TSC cycle `now`, and returns the
+    /// held packets if they are due for transmission.
+    ///
+    /// Call with an empty vec to only check the deadline.
+    pub(crate) fn push_at(&mut self, packets: Vec<Mbuf>, now: u64) -> Option<Vec<Mbuf>> {
+        if self.packets.is_empty() {
+            self.oldest = now;
+        }
+        self.packets.extend(packets);
+
+        let due =
+            self.packets.len() >= self.burst || now.wrapping_sub(self.oldest) >= self.max_hold;
+        if due && !self.packets.is_empty() {
+            Some(mem::take(&mut self.packets))
+        } else {
+            None
+        }
+    }
 }
 
 /// A batch that can be executed as a runtime task.
@@ -45,44 +89,58 @@ pub struct Send<B: Batch, Tx: PacketTx> {
     name: String,
     batch: B,
     tx: Tx,
-    #[cfg(feature = "metrics")]
-    runs: Counter,
-    #[cfg(feature = "metrics")]
-    processed: Counter,
-    #[cfg(feature = "metrics")]
-    dropped: Counter,
-    #[cfg(feature = "metrics")]
-    errors: Counter,
+    hold: Option<TxHold>,
+    counters: Arc<PipelineCounters>,
+    trace: PipelineTrace,
+    capture: CaptureTap,
 }
 
 impl<B: Batch, Tx: PacketTx> Send<B, Tx> {
     /// Creates a new `Send` batch.
-    #[cfg(not(feature = "metrics"))]
     #[inline]
     pub fn new(name: String, batch: B, tx: Tx) -> Self {
-        Send { name, batch, tx }
-    }
-
-    /// Creates a new `Send` batch.
-    #[cfg(feature = "metrics")]
-    #[inline]
-    pub fn new(name: String, batch: B, tx: Tx) -> Self {
-        let runs = new_counter("runs", &name);
-        let processed = new_counter("processed", &name);
-        let dropped = new_counter("dropped", &name);
-        let errors = new_counter("errors", &name);
+        let counters = stats::register_pipeline(&name);
+        let trace = PipelineTrace::new(&name);
+        let capture = CaptureTap::new(CaptureTarget::Pipeline(name.clone()));
         Send {
             name,
             batch,
             tx,
-            runs,
-            processed,
-            dropped,
-            errors,
+            hold: None,
+            counters,
+            trace,
+            capture,
         }
     }
 
+    /// Sets the maximum time a packet is held back to be transmitted
+    /// together with the packets of later batches.
+    ///
+    /// By default, the packets of each batch are transmitted as soon as the
+    /// batch completes. With a hold time, small batches are coalesced until
+    /// either a full burst of 32 packets accumulates, which is transmitted
+    /// right away, or the oldest packet has been held for `dur`. Packets
+    /// left in the transmit backlog are also retried once the hold time
+    /// passes, even if no new packets arrive.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let pipeline = batch.send(q).max_hold_time(Duration::from_micros(50));
+    /// ```
+    pub fn max_hold_time(mut self, dur: Duration) -> Self {
+        let cycles = dur.as_nanos() * u128::from(dpdk::tsc_hz()) / 1_000_000_000;
+        self.hold = Some(TxHold::new(cycles as u64, TX_BURST_MAX));
+        self
+    }
+
     fn run(&mut self) {
+        let start = Instant::now();
+
+        // the combinators record their counters to this pipeline.
+        let _scope = self.counters.enter();
+        let _trace = self.trace.enter();
+
         // let's get a new batch
         self.batch.replenish();
 
@@ -101,21 +159,30 @@ impl<B: Batch, Tx: PacketTx> Send<B, Tx> {
             }
         }
 
-        #[cfg(feature = "metrics")]
-        {
-            self.runs.record(1);
-            self.processed.record(transmit_q.len() as u64 + emitted);
-            self.dropped.record(drop_q.len() as u64);
-            self.errors.record(aborted);
-        }
+        let transmitted = transmit_q.len() as u64;
+        let dropped = drop_q.len() as u64;
+
+        self.capture.tap(&transmit_q);
 
-        if !transmit_q.is_empty() {
-            self.tx.transmit(transmit_q);
+        match &mut self.hold {
+            None => {
+                if !transmit_q.is_empty() {
+                    self.tx.transmit(transmit_q);
+                }
+            }
+            Some(hold) => match hold.push_at(transmit_q, dpdk::tsc_cycles()) {
+                Some(packets) => self.tx.transmit(packets),
+                None if hold.is_empty() => self.tx.flush(),
+                None => (),
+            },
         }
 
         if !drop_q.is_empty() {
             Mbuf::free_bulk(drop_q);
         }
+
+        self.counters
+            .record_run(transmitted, emitted, dropped, aborted, start.elapsed());
     }
 }
 
@@ -149,3 +216,59 @@ impl<B: Batch + Unpin, Tx: PacketTx + Unpin> Pipeline for Send<B, Tx> {
         self.run()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::IPV4_UDP_PACKET;
+
+    const HOLD: u64 = 100;
+
+    fn new_packets(n: usize) -> Vec<Mbuf> {
+        (0..n)
+            .map(|_| Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap())
+            .collect()
+    }
+
+    #[capsule::test]
+    fn release_lone_packet_after_deadline() {
+        let mut hold = TxHold::new(HOLD, 32);
+
+        assert!(hold.push_at(new_packets(1), 1000).is_none());
+        assert!(hold.push_at(vec![], 1000 + HOLD - 1).is_none());
+        assert!(!hold.is_empty());
+
+        let released = hold.push_at(vec![], 1000 + HOLD).unwrap();
+        assert_eq!(1, released.len());
+        assert!(hold.is_empty());
+
+        // nothing held, nothing to release.
+        assert!(hold.push_at(vec![], 5000).is_none());
+    }
+
+    #[capsule::test]
+    fn release_full_burst_immediately() {
+        let mut hold = TxHold::new(HOLD, 32);
+
+        assert_eq!(32, hold.push_at(new_packets(32), 1000).unwrap().len());
+        assert!(hold.is_empty());
+
+        // a burst filled up over several batches also goes right away.
+        assert!(hold.push_at(new_packets(20), 2000).is_none());
+        assert_eq!(40, hold.push_at(new_packets(20), 2001).unwrap().len());
+    }
+
+    #[capsule::test]
+    fn deadline_follows_oldest_packet() {
+        let mut hold = TxHold::new(HOLD, 32);
+
+        assert!(hold.push_at(new_packets(1), 1000).is_none());
+        assert!(hold.push_at(new_packets(1), 1000 + HOLD / 2).is_none());
+        assert_eq!(2, hold.push_at(vec![], 1000 + HOLD).unwrap().len());
+
+        // the clock restarts with the next packet held.
+        assert!(hold.push_at(new_packets(1), 1000 + HOLD + 10).is_none());
+        assert!(hold.push_at(vec![], 1000 + HOLD * 2).is_none());
+        assert_eq!(1, hold.push_at(vec![], 1000 + HOLD * 2 + 10).unwrap().len());
+    }
+}
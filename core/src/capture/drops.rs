@@ -0,0 +1,243 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{CaptureTap, CaptureTarget};
+use crate::stats::DropReason;
+use crate::Mbuf;
+use anyhow::Error;
+use once_cell::sync::Lazy;
+
+/// The tap shared by the drop paths of all the combinators.
+static DROPS: Lazy<CaptureTap> = Lazy::new(|| CaptureTap::new(CaptureTarget::Drops));
+
+/// Writes a dropped packet to the drop capture if it's on, annotated with
+/// the reason and the stage that drops it.
+#[inline]
+pub(crate) fn tap_drop(mbuf: &Mbuf, reason: DropReason, stage: &str) {
+    if DROPS.is_on() {
+        let comment = format!("reason={} stage={}", reason, stage);
+        DROPS.switch.capture_one(mbuf, &comment);
+    }
+}
+
+/// Writes a packet aborted by an error to the drop capture if it's on,
+/// annotated with the stage that fails and the error.
+#[inline]
+pub(crate) fn tap_error(mbuf: &Mbuf, stage: &str, err: &Error) {
+    if DROPS.is_on() {
+        let comment = format!(
+            "reason={} stage={} error={}",
+            DropReason::Errored,
+            stage,
+            err
+        );
+        DROPS.switch.capture_one(mbuf, &comment);
+    }
+}
+
+/// A copy of a packet taken before it's handed to a closure that may fail.
+///
+/// The closures of `map` and the like take the packet by value, so it's
+/// gone by the time the error is returned. The copy is only taken while
+/// the drop capture is on.
+pub(crate) struct DropSnapshot(Option<Vec<u8>>);
+
+impl DropSnapshot {
+    /// Copies the packet if the drop capture is on.
+    #[inline]
+    pub(crate) fn take(mbuf: &Mbuf) -> Self {
+        if DROPS.is_on() {
+            DropSnapshot(Some(mbuf.read_segments(0)))
+        } else {
+            DropSnapshot(None)
+        }
+    }
+
+    /// Writes the copy to the drop capture, annotated with the stage that
+    /// fails and the error.
+    pub(crate) fn tap_error(self, stage: &str, err: &Error) {
+        // the copy is written as a packet of its own, so the capture filter
+        // applies to it like to any other packet.
+        if let Some(mbuf) = self.0.and_then(|data| Mbuf::from_bytes(&data).ok()) {
+            tap_error(&mbuf, stage, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{Batch, PacketTx, Pipeline, Poll};
+    use crate::capture::{CaptureController, CaptureFilter, CaptureOpts};
+    use crate::dpdk::CoreId;
+    use crate::net::{Cidr, Ipv4Cidr, MacAddr};
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::ip::ProtocolNumbers;
+    use crate::packets::{Ethernet, Packet};
+    use crate::testils::byte_arrays::{IPV4_TCP_PACKET, IPV4_UDP_PACKET};
+    use std::convert::TryInto;
+    use std::fs;
+    use std::net::Ipv4Addr;
+    use std::path::Path;
+    use std::sync::mpsc;
+
+    /// Marks the packets of this test apart from the packets other tests
+    /// running at the same time drop.
+    const MARKER: [u8; 6] = [0x02, 0x00, 0x00, 0xd0, 0x0b, 0x01];
+
+    fn marked(bytes: &[u8]) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        bytes[..6].copy_from_slice(&MARKER);
+        bytes
+    }
+
+    /// Reads the packets of a capture file written by `PcapngWriter`, with
+    /// their comments.
+    fn read_annotated<P: AsRef<Path>>(path: P) -> Vec<(Vec<u8>, String)> {
+        let bytes = fs::read(path).unwrap();
+        let u16_at = |b: &[u8], i: usize| u16::from_le_bytes(b[i..i + 2].try_into().unwrap());
+        let u32_at = |b: &[u8], i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+
+        let mut annotated = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let block_len = u32_at(&bytes, offset + 4) as usize;
+
+            // only the enhanced packet blocks.
+            if u32_at(&bytes, offset) == 6 {
+                let body = &bytes[offset + 8..offset + block_len - 4];
+                let captured = u32_at(body, 12) as usize;
+                let data = body[20..20 + captured].to_vec();
+
+                let mut comment = String::new();
+                let mut opt = 20 + (captured + 3) / 4 * 4;
+                while opt + 4 <= body.len() && u16_at(body, opt) != 0 {
+                    let len = u16_at(body, opt + 2) as usize;
+                    if u16_at(body, opt) == 1 {
+                        comment = String::from_utf8(body[opt + 4..opt + 4 + len].to_vec()).unwrap();
+                    }
+                    opt += 4 + (len + 3) / 4 * 4;
+                }
+
+                annotated.push((data, comment));
+            }
+
+            offset += block_len;
+        }
+
+        annotated
+    }
+
+    // both parts capture the drops of all the pipelines, so they run in
+    // the same test one after the other.
+    #[capsule::test]
+    fn capture_dropped_packets() {
+        capture_filtered_and_errored();
+        sample_dropped_packets();
+    }
+
+    fn capture_filtered_and_errored() {
+        let controller = CaptureController;
+        let (mut tx, rx) = mpsc::channel();
+        let (out, out_rx) = mpsc::channel();
+
+        // drops the UDP packets, and fails on the packets too short to be
+        // Ethernet frames.
+        let mut pipeline = Poll::new(rx)
+            .map(|packet| packet.parse::<Ethernet>())
+            .map(|ethernet| ethernet.parse::<Ipv4>())
+            .filter(|ipv4| ipv4.protocol() == ProtocolNumbers::Tcp)
+            .send(out);
+
+        let tcp = marked(&IPV4_TCP_PACKET);
+        let udp = marked(&IPV4_UDP_PACKET);
+        let short = marked(&IPV4_UDP_PACKET[..12]);
+        let packets = || {
+            vec![
+                Mbuf::from_bytes(&tcp).unwrap(),
+                Mbuf::from_bytes(&udp).unwrap(),
+                Mbuf::from_bytes(&short).unwrap(),
+            ]
+        };
+
+        // nothing is captured while the drop capture is off.
+        tx.transmit(packets());
+        pipeline.run_once();
+        assert_eq!(1, out_rx.try_iter().count());
+
+        let opts = CaptureOpts::new("capture_drops");
+        controller
+            .start(CaptureTarget::Drops, opts.clone())
+            .unwrap();
+        tx.transmit(packets());
+        pipeline.run_once();
+        controller.stop(&CaptureTarget::Drops).unwrap();
+        assert_eq!(1, out_rx.try_iter().count());
+
+        let dropped = read_annotated(opts.path(CoreId::current(), 0))
+            .into_iter()
+            .filter(|(data, _)| data.starts_with(&MARKER))
+            .collect::<Vec<_>>();
+        assert_eq!(2, dropped.len());
+
+        assert_eq!(udp, dropped[0].0);
+        assert_eq!("reason=filtered stage=filter", dropped[0].1);
+
+        assert_eq!(short, dropped[1].0);
+        assert!(dropped[1].1.starts_with("reason=errored stage=map error="));
+
+        let _ = fs::remove_file(opts.path(CoreId::current(), 0));
+    }
+
+    fn sample_dropped_packets() {
+        let controller = CaptureController;
+        let (mut tx, rx) = mpsc::channel();
+        let (out, _out_rx) = mpsc::channel();
+
+        let mut pipeline = Poll::new(rx)
+            .map(|packet| packet.parse::<Ethernet>())
+            .filter(|ethernet| ethernet.dst() != MacAddr::from(MARKER))
+            .send(out);
+
+        // the capture filter keeps out the drops of the other tests.
+        let src = Ipv4Addr::new(10, 208, 11, 1);
+        let mut udp = marked(&IPV4_UDP_PACKET);
+        udp[26..30].copy_from_slice(&src.octets());
+
+        let opts = CaptureOpts::new("capture_drops_sampled")
+            .filter(CaptureFilter::new().ipv4_src(Ipv4Cidr::new(src, 32).unwrap()))
+            .sample(3);
+        controller
+            .start(CaptureTarget::Drops, opts.clone())
+            .unwrap();
+
+        // the 1st, 4th and 7th drops are written.
+        tx.transmit((0..7).map(|_| Mbuf::from_bytes(&udp).unwrap()).collect());
+        pipeline.run_once();
+        controller.stop(&CaptureTarget::Drops).unwrap();
+
+        let dropped = read_annotated(opts.path(CoreId::current(), 0));
+        assert_eq!(3, dropped.len());
+        for (data, comment) in dropped.iter() {
+            assert_eq!(&udp, data);
+            assert_eq!("reason=filtered stage=filter", comment);
+        }
+
+        let _ = fs::remove_file(opts.path(CoreId::current(), 0));
+    }
+}
@@ -0,0 +1,448 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::net::{Cidr, Ipv4Cidr, Ipv6Cidr};
+use crate::packets::ip::v4::Ipv4;
+use crate::packets::ip::v6::Ipv6;
+use crate::packets::ip::{IpPacket, ProtocolNumber, ProtocolNumbers};
+use crate::packets::{EtherType, EtherTypes, Ethernet, Packet, Tcp, Udp};
+use crate::Mbuf;
+use std::net::IpAddr;
+
+/// The header fields a capture filter matches on, parsed once per packet.
+///
+/// The fields of the layers that fail to parse are left as `None`.
+#[derive(Debug, Default)]
+struct Fields {
+    vlan_id: Option<u16>,
+    ether_type: Option<EtherType>,
+    src: Option<IpAddr>,
+    dst: Option<IpAddr>,
+    protocol: Option<ProtocolNumber>,
+    src_port: Option<u16>,
+    dst_port: Option<u16>,
+}
+
+impl Fields {
+    /// Parses the fields through the Ethernet, IP and transport layers,
+    /// without taking ownership of the mbuf.
+    fn parse(mbuf: &Mbuf) -> Self {
+        let mut fields = Fields::default();
+
+        let ethernet = match mbuf.peek::<Ethernet>() {
+            Ok(ethernet) => ethernet,
+            Err(_) => return fields,
+        };
+        fields.vlan_id = ethernet.outer_tci().map(|tci| tci.vid);
+        fields.ether_type = Some(ethernet.ether_type());
+
+        match ethernet.ether_type() {
+            EtherTypes::Ipv4 => {
+                if let Ok(ipv4) = ethernet.peek::<Ipv4>() {
+                    fields.parse_ip(&*ipv4);
+                }
+            }
+            EtherTypes::Ipv6 => {
+                if let Ok(ipv6) = ethernet.peek::<Ipv6>() {
+                    fields.parse_ip(&*ipv6);
+                }
+            }
+            _ => (),
+        }
+
+        fields
+    }
+
+    fn parse_ip<T: IpPacket>(&mut self, ip: &T) {
+        self.src = Some(ip.src());
+        self.dst = Some(ip.dst());
+        self.protocol = Some(ip.next_protocol());
+
+        match ip.next_protocol() {
+            ProtocolNumbers::Tcp => {
+                if let Ok(tcp) = ip.peek::<Tcp<T>>() {
+                    self.src_port = Some(tcp.src_port());
+                    self.dst_port = Some(tcp.dst_port());
+                }
+            }
+            ProtocolNumbers::Udp => {
+                if let Ok(udp) = ip.peek::<Udp<T>>() {
+                    self.src_port = Some(udp.src_port());
+                    self.dst_port = Some(udp.dst_port());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn is_protocol(&self, protocol: ProtocolNumber) -> bool {
+        self.protocol == Some(protocol)
+    }
+}
+
+/// A condition of a capture filter.
+#[derive(Clone, Debug)]
+enum Predicate {
+    VlanId(u16),
+    EtherType(EtherType),
+    Ipv4Src(Ipv4Cidr),
+    Ipv4Dst(Ipv4Cidr),
+    Ipv6Src(Ipv6Cidr),
+    Ipv6Dst(Ipv6Cidr),
+    Protocol(ProtocolNumber),
+    TcpSrcPort(u16),
+    TcpDstPort(u16),
+    UdpSrcPort(u16),
+    UdpDstPort(u16),
+    Any(Vec<CaptureFilter>),
+}
+
+impl Predicate {
+    fn matches(&self, fields: &Fields) -> bool {
+        match *self {
+            Predicate::VlanId(vid) => fields.vlan_id == Some(vid),
+            Predicate::EtherType(ether_type) => fields.ether_type == Some(ether_type),
+            Predicate::Ipv4Src(cidr) => {
+                matches!(fields.src, Some(IpAddr::V4(addr)) if cidr.contains(addr))
+            }
+            Predicate::Ipv4Dst(cidr) => {
+                matches!(fields.dst, Some(IpAddr::V4(addr)) if cidr.contains(addr))
+            }
+            Predicate::Ipv6Src(cidr) => {
+                matches!(fields.src, Some(IpAddr::V6(addr)) if cidr.contains(addr))
+            }
+            Predicate::Ipv6Dst(cidr) => {
+                matches!(fields.dst, Some(IpAddr::V6(addr)) if cidr.contains(addr))
+            }
+            Predicate::Protocol(protocol) => fields.is_protocol(protocol),
+            Predicate::TcpSrcPort(port) => {
+                fields.is_protocol(ProtocolNumbers::Tcp) && fields.src_port == Some(port)
+            }
+            Predicate::TcpDstPort(port) => {
+                fields.is_protocol(ProtocolNumbers::Tcp) && fields.dst_port == Some(port)
+            }
+            Predicate::UdpSrcPort(port) => {
+                fields.is_protocol(ProtocolNumbers::Udp) && fields.src_port == Some(port)
+            }
+            Predicate::UdpDstPort(port) => {
+                fields.is_protocol(ProtocolNumbers::Udp) && fields.dst_port == Some(port)
+            }
+            Predicate::Any(ref filters) => {
+                filters.iter().any(|filter| filter.matches_fields(fields))
+            }
+        }
+    }
+}
+
+/// Selects the packets a capture writes.
+///
+/// A filter is built from conditions on the packet headers, and matches a
+/// packet only if all the conditions hold. An empty filter matches every
+/// packet. Use [`or`] to match either of two filters.
+///
+/// The headers are parsed through the VLAN tags, so the same filter matches
+/// both the untagged and the tagged variants of a packet. A tag the device
+/// stripped counts as the outer tag.
+///
+/// # Example
+///
+/// ```
+/// // only TCP port 443 from 10.1.2.0/24.
+/// let filter = CaptureFilter::new()
+///     .ipv4_src("10.1.2.0/24".parse()?)
+///     .tcp_dst_port(443);
+/// ```
+///
+/// [`or`]: CaptureFilter::or
+#[derive(Clone, Debug, Default)]
+pub struct CaptureFilter {
+    predicates: Vec<Predicate>,
+}
+
+impl CaptureFilter {
+    /// Creates an empty filter that matches every packet.
+    pub fn new() -> Self {
+        CaptureFilter::default()
+    }
+
+    fn with(mut self, predicate: Predicate) -> Self {
+        self.predicates.push(predicate);
+        self
+    }
+
+    /// Matches the frames with the outer VLAN identifier.
+    pub fn vlan_id(self, vid: u16) -> Self {
+        self.with(Predicate::VlanId(vid))
+    }
+
+    /// Matches the frames with the EtherType. The EtherType of a tagged
+    /// frame is the one after the VLAN tags.
+    pub fn ether_type(self, ether_type: EtherType) -> Self {
+        self.with(Predicate::EtherType(ether_type))
+    }
+
+    /// Matches the IPv4 packets with a source address in the range.
+    pub fn ipv4_src(self, cidr: Ipv4Cidr) -> Self {
+        self.with(Predicate::Ipv4Src(cidr))
+    }
+
+    /// Matches the IPv4 packets with a destination address in the range.
+    pub fn ipv4_dst(self, cidr: Ipv4Cidr) -> Self {
+        self.with(Predicate::Ipv4Dst(cidr))
+    }
+
+    /// Matches the IPv6 packets with a source address in the range.
+    pub fn ipv6_src(self, cidr: Ipv6Cidr) -> Self {
+        self.with(Predicate::Ipv6Src(cidr))
+    }
+
+    /// Matches the IPv6 packets with a destination address in the range.
+    pub fn ipv6_dst(self, cidr: Ipv6Cidr) -> Self {
+        self.with(Predicate::Ipv6Dst(cidr))
+    }
+
+    /// Matches the IP packets with the next protocol.
+    pub fn protocol(self, protocol: ProtocolNumber) -> Self {
+        self.with(Predicate::Protocol(protocol))
+    }
+
+    /// Matches the TCP segments with the source port.
+    pub fn tcp_src_port(self, port: u16) -> Self {
+        self.with(Predicate::TcpSrcPort(port))
+    }
+
+    /// Matches the TCP segments with the destination port.
+    pub fn tcp_dst_port(self, port: u16) -> Self {
+        self.with(Predicate::TcpDstPort(port))
+    }
+
+    /// Matches the UDP datagrams with the source port.
+    pub fn udp_src_port(self, port: u16) -> Self {
+        self.with(Predicate::UdpSrcPort(port))
+    }
+
+    /// Matches the UDP datagrams with the destination port.
+    pub fn udp_dst_port(self, port: u16) -> Self {
+        self.with(Predicate::UdpDstPort(port))
+    }
+
+    /// Combines two filters into one that matches the packets either of
+    /// them matches.
+    pub fn or(self, other: CaptureFilter) -> Self {
+        CaptureFilter::new().with(Predicate::Any(vec![self, other]))
+    }
+
+    /// Returns whether the filter matches every packet.
+    pub fn is_empty(&self) -> bool {
+        self.predicates.is_empty()
+    }
+
+    /// Returns whether the packet matches the filter.
+    pub fn matches(&self, mbuf: &Mbuf) -> bool {
+        // the empty filter doesn't need to parse anything.
+        self.is_empty() || self.matches_fields(&Fields::parse(mbuf))
+    }
+
+    fn matches_fields(&self, fields: &Fields) -> bool {
+        self.predicates
+            .iter()
+            .all(|predicate| predicate.matches(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::{
+        ARP4_PACKET, IPV4_TCP_PACKET, IPV4_UDP_PACKET, IPV6_TCP_PACKET,
+    };
+
+    /// Inserts VLAN tags after the MAC addresses, outermost first.
+    fn tagged(bytes: &[u8], tags: &[(u16, u16)]) -> Vec<u8> {
+        let mut frame = bytes[..12].to_vec();
+        for &(tpid, vid) in tags {
+            frame.extend_from_slice(&tpid.to_be_bytes());
+            frame.extend_from_slice(&vid.to_be_bytes());
+        }
+        frame.extend_from_slice(&bytes[12..]);
+        frame
+    }
+
+    /// Returns whether the filter matches the frame, untagged, Dot1q and
+    /// QinQ tagged.
+    fn matches_all_variants(filter: &CaptureFilter, bytes: &[u8]) -> [bool; 3] {
+        let frames = [
+            bytes.to_vec(),
+            tagged(bytes, &[(0x8100, 100)]),
+            tagged(bytes, &[(0x88a8, 100), (0x8100, 200)]),
+        ];
+
+        let mut results = [false; 3];
+        for (result, frame) in results.iter_mut().zip(frames.iter()) {
+            *result = filter.matches(&Mbuf::from_bytes(frame).unwrap());
+        }
+        results
+    }
+
+    #[capsule::test]
+    fn empty_filter_matches_everything() {
+        let filter = CaptureFilter::new();
+        assert!(filter.is_empty());
+        assert_eq!([true; 3], matches_all_variants(&filter, &ARP4_PACKET));
+        assert!(filter.matches(&Mbuf::from_bytes(&[0; 4]).unwrap()));
+    }
+
+    #[capsule::test]
+    fn match_vlan_id() {
+        let filter = CaptureFilter::new().vlan_id(100);
+        assert_eq!(
+            [false, true, true],
+            matches_all_variants(&filter, &IPV4_TCP_PACKET)
+        );
+
+        // only the outer tag of a QinQ frame.
+        let filter = CaptureFilter::new().vlan_id(200);
+        assert_eq!(
+            [false, false, false],
+            matches_all_variants(&filter, &IPV4_TCP_PACKET)
+        );
+    }
+
+    #[capsule::test]
+    fn match_ether_type() {
+        let filter = CaptureFilter::new().ether_type(EtherTypes::Arp);
+        assert_eq!([true; 3], matches_all_variants(&filter, &ARP4_PACKET));
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+    }
+
+    #[capsule::test]
+    fn match_ipv4_addresses() {
+        let filter = CaptureFilter::new().ipv4_src("139.133.217.0/24".parse().unwrap());
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV6_TCP_PACKET));
+
+        let filter = CaptureFilter::new().ipv4_src("139.133.233.0/24".parse().unwrap());
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+
+        let filter = CaptureFilter::new().ipv4_dst("139.133.233.2/32".parse().unwrap());
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV4_UDP_PACKET));
+        assert_eq!([false; 3], matches_all_variants(&filter, &ARP4_PACKET));
+    }
+
+    #[capsule::test]
+    fn match_ipv6_addresses() {
+        let filter = CaptureFilter::new().ipv6_src("2001:db8:85a3::/64".parse().unwrap());
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV6_TCP_PACKET));
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+
+        let filter =
+            CaptureFilter::new().ipv6_dst("2001:db8:85a3::8a2e:370:7334/128".parse().unwrap());
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV6_TCP_PACKET));
+
+        let filter = CaptureFilter::new().ipv6_dst("2001:db8:85a3::1/128".parse().unwrap());
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV6_TCP_PACKET));
+    }
+
+    #[capsule::test]
+    fn match_protocol() {
+        let filter = CaptureFilter::new().protocol(ProtocolNumbers::Udp);
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV4_UDP_PACKET));
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+        assert_eq!([false; 3], matches_all_variants(&filter, &ARP4_PACKET));
+    }
+
+    #[capsule::test]
+    fn match_tcp_ports() {
+        let filter = CaptureFilter::new().tcp_src_port(36869);
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV6_TCP_PACKET));
+
+        let filter = CaptureFilter::new().tcp_dst_port(23);
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+
+        let filter = CaptureFilter::new().tcp_dst_port(36869);
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+
+        // a UDP datagram with the same port is not a TCP segment.
+        let filter = CaptureFilter::new().tcp_dst_port(1087);
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_UDP_PACKET));
+    }
+
+    #[capsule::test]
+    fn match_udp_ports() {
+        let filter = CaptureFilter::new().udp_src_port(39376);
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV4_UDP_PACKET));
+
+        let filter = CaptureFilter::new().udp_dst_port(1087);
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV4_UDP_PACKET));
+
+        let filter = CaptureFilter::new().udp_dst_port(23);
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+    }
+
+    #[capsule::test]
+    fn match_all_conditions() {
+        let filter = CaptureFilter::new()
+            .vlan_id(100)
+            .ipv4_src("139.133.217.0/24".parse().unwrap())
+            .tcp_dst_port(23);
+        assert_eq!(
+            [false, true, true],
+            matches_all_variants(&filter, &IPV4_TCP_PACKET)
+        );
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_UDP_PACKET));
+
+        let filter = CaptureFilter::new()
+            .ipv4_src("139.133.217.0/24".parse().unwrap())
+            .tcp_dst_port(443);
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+    }
+
+    #[capsule::test]
+    fn match_either_filter() {
+        let filter = CaptureFilter::new()
+            .tcp_dst_port(23)
+            .or(CaptureFilter::new().ether_type(EtherTypes::Arp));
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV4_TCP_PACKET));
+        assert_eq!([true; 3], matches_all_variants(&filter, &IPV6_TCP_PACKET));
+        assert_eq!([true; 3], matches_all_variants(&filter, &ARP4_PACKET));
+        assert_eq!([false; 3], matches_all_variants(&filter, &IPV4_UDP_PACKET));
+
+        // the alternatives combined with a condition on both.
+        let filter = CaptureFilter::new()
+            .ipv4_src("139.133.217.0/24".parse().unwrap())
+            .or(CaptureFilter::new().ipv6_src("2001:db8::/32".parse().unwrap()))
+            .vlan_id(100);
+        assert_eq!(
+            [false, true, true],
+            matches_all_variants(&filter, &IPV4_UDP_PACKET)
+        );
+        assert_eq!(
+            [false, true, true],
+            matches_all_variants(&filter, &IPV6_TCP_PACKET)
+        );
+        assert_eq!([false; 3], matches_all_variants(&filter, &ARP4_PACKET));
+    }
+
+    #[capsule::test]
+    fn no_match_on_truncated_packet() {
+        let filter = CaptureFilter::new().tcp_dst_port(23);
+        let packet = Mbuf::from_bytes(&IPV4_TCP_PACKET[..40]).unwrap();
+        assert!(!filter.matches(&packet));
+    }
+}
@@ -0,0 +1,1101 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Packet captures of ports and pipelines switched on and off at runtime.
+//!
+//! A capture is started and stopped from the control plane with the
+//! [`CaptureController`]. Capturing a port records the packets received
+//! from the port, after the port's RX filter and before the pipelines see
+//! them. Capturing a pipeline records the packets the pipeline hands to
+//! its TX.
+//!
+//! The packets are written as pcapng to a series of files, rotated when a
+//! file reaches its maximum size. Only the most recent files are kept, the
+//! older ones are deleted. A capture can also stop by itself after a
+//! duration or a number of packets.
+//!
+//! A [`CaptureFilter`] narrows down the packets written, for example to
+//! only the TCP segments to port 443 from a subnet. The filter of a running
+//! capture can be swapped without restarting it.
+//!
+//! The packets dropped by the pipelines can be captured too, with the
+//! [`Drops`] target. Each packet is annotated with a pcapng comment giving
+//! the reason it's dropped and the combinator or the [`Chain`] stage that
+//! drops it, for example `reason=filtered stage=firewall`. Use
+//! [`CaptureOpts::sample`] to bound the cost when a lot is dropped.
+//!
+//! Each core of the captured port or pipeline writes to its own files,
+//! named `{prefix}-lcore{N}-{n}.pcapng`, through its own buffered writer,
+//! so the cores never wait on each other. A core creates its first file
+//! when it captures its first packet. Set [`CaptureOpts::merged`] to merge
+//! the files of all the cores by timestamp into `{prefix}.pcapng` when the
+//! capture stops. When the capture is off, the only cost is a single branch
+//! per burst.
+//!
+//! For post-mortem debugging without files, a [`CaptureRing`] keeps byte
+//! snapshots of the most recent frames a core processes in memory.
+//!
+//! # Example
+//!
+//! ```
+//! let captures = runtime.capture_controller();
+//! let eth1 = CaptureTarget::Port("eth1".to_owned());
+//!
+//! captures.start(
+//!     eth1.clone(),
+//!     CaptureOpts::new("/tmp/eth1")
+//!         .snaplen(128)
+//!         .max_file_size(16 * 1024 * 1024)
+//!         .max_files(4)
+//!         .duration(Duration::from_secs(60))
+//!         .merged(),
+//! )?;
+//! ...
+//! captures.set_filter(&eth1, CaptureFilter::new().tcp_dst_port(443))?;
+//! ...
+//! captures.stop(&eth1)?;
+//!
+//! captures.start(
+//!     CaptureTarget::Drops,
+//!     CaptureOpts::new("/tmp/drops").sample(100),
+//! )?;
+//! ```
+//!
+//! [`Drops`]: CaptureTarget::Drops
+//! [`Chain`]: crate::batch::Chain
+
+mod drops;
+mod filter;
+mod ring;
+
+pub(crate) use self::drops::{tap_drop, tap_error, DropSnapshot};
+#[allow(unreachable_pub)]
+pub use self::filter::*;
+#[allow(unreachable_pub)]
+pub use self::ring::*;
+
+use crate::dpdk::CoreId;
+use crate::ffi;
+use crate::pcap;
+use crate::pcapng::{self, Interface, InterfaceId, PcapngWriter};
+use crate::stats::{self, CaptureCounters};
+use crate::{ensure, info, warn, Mbuf};
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// The default maximum size of a capture file, 64 MiB.
+const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The default number of capture files kept.
+const DEFAULT_MAX_FILES: usize = 8;
+
+/// The number of lcores a capture keeps the sessions of.
+const MAX_LCORES: usize = ffi::RTE_MAX_LCORE as usize;
+
+/// Errors controlling a packet capture.
+#[derive(Debug, Error)]
+pub(crate) enum CaptureError {
+    /// The target is already captured.
+    #[error("Capture of {0} is already started.")]
+    AlreadyStarted(CaptureTarget),
+
+    /// The target is not captured.
+    #[error("Capture of {0} is not started.")]
+    NotStarted(CaptureTarget),
+
+    /// The capture is set to keep no files.
+    #[error("Capture must keep at least one file.")]
+    NoFiles,
+}
+
+/// What to capture the packets of.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum CaptureTarget {
+    /// The packets received from the port with the name.
+    Port(String),
+    /// The packets transmitted by the pipeline with the name.
+    Pipeline(String),
+    /// The packets dropped by all the pipelines, annotated with the reason.
+    Drops,
+}
+
+impl fmt::Display for CaptureTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureTarget::Port(name) => write!(f, "port:{}", name),
+            CaptureTarget::Pipeline(name) => write!(f, "pipeline:{}", name),
+            CaptureTarget::Drops => write!(f, "drops"),
+        }
+    }
+}
+
+/// The settings of a packet capture.
+#[derive(Clone, Debug)]
+pub struct CaptureOpts {
+    prefix: String,
+    snaplen: usize,
+    max_file_size: u64,
+    max_files: usize,
+    duration: Option<Duration>,
+    max_packets: Option<u64>,
+    sample: u64,
+    filter: CaptureFilter,
+    merge: bool,
+}
+
+impl CaptureOpts {
+    /// Creates the settings of a capture written to files starting with
+    /// `prefix`. The files of each core are named
+    /// `{prefix}-lcore{N}-{n}.pcapng`, with `N` the id of the core and `n`
+    /// counting up from `0` as the files rotate. The threads not pinned to
+    /// a core share the `lcoreany` files.
+    ///
+    /// By default, the packets are not truncated, a file holds up to 64 MiB
+    /// and the 8 most recent files of each core are kept. The capture runs
+    /// until it's stopped, and the files are not merged.
+    pub fn new(prefix: &str) -> Self {
+        CaptureOpts {
+            prefix: prefix.to_owned(),
+            snaplen: 0,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+            duration: None,
+            max_packets: None,
+            sample: 1,
+            filter: CaptureFilter::new(),
+            merge: false,
+        }
+    }
+
+    /// Sets the maximum number of bytes captured per packet. Longer
+    /// packets are truncated before they are copied out of the mbuf, so
+    /// the cost of capturing a packet scales with the snaplen rather than
+    /// the packet size. The original length of the packet is still
+    /// recorded. `0` means no limit.
+    ///
+    /// The snaplen can be shorter than the Ethernet header, the filter
+    /// still matches on the whole packet.
+    pub fn snaplen(mut self, snaplen: usize) -> Self {
+        self.snaplen = snaplen;
+        self
+    }
+
+    /// Sets the maximum size of a capture file in bytes. A file always
+    /// holds at least one packet, even if the packet alone is larger.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// Sets the number of most recent capture files kept per core.
+    pub fn max_files(mut self, count: usize) -> Self {
+        self.max_files = count;
+        self
+    }
+
+    /// Stops the capture after it's been on for `dur`.
+    pub fn duration(mut self, dur: Duration) -> Self {
+        self.duration = Some(dur);
+        self
+    }
+
+    /// Stops the capture after `count` packets are written by all the
+    /// cores together.
+    pub fn max_packets(mut self, count: u64) -> Self {
+        self.max_packets = Some(count);
+        self
+    }
+
+    /// Writes only one in every `n` packets that pass the filter on each
+    /// core, starting with the first. `0` and `1` write all of them.
+    pub fn sample(mut self, n: u64) -> Self {
+        self.sample = n;
+        self
+    }
+
+    /// Sets the filter that selects the packets written. By default, all
+    /// the packets are.
+    pub fn filter(mut self, filter: CaptureFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Merges the files kept of all the cores into `{prefix}.pcapng` when
+    /// the capture stops, with the packets interleaved by timestamp. The
+    /// files of the cores are kept too.
+    ///
+    /// See [`pcap::merge`] for how the packets are ordered.
+    pub fn merged(mut self) -> Self {
+        self.merge = true;
+        self
+    }
+
+    /// Returns the path of the `n`th file of the core.
+    fn path(&self, core: CoreId, n: u64) -> PathBuf {
+        format!("{}-{}-{}.pcapng", self.prefix, lcore_name(core), n).into()
+    }
+
+    /// Returns the path of the file the cores' files are merged into.
+    fn merged_path(&self) -> PathBuf {
+        format!("{}.pcapng", self.prefix).into()
+    }
+}
+
+/// A file writer that counts the bytes written.
+struct CountingWriter {
+    writer: BufWriter<File>,
+    bytes: u64,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.writer.write(buf)?;
+        self.bytes += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A capture that's on, and the file a core is currently writing to.
+struct CaptureSession {
+    name: String,
+    core: CoreId,
+    opts: CaptureOpts,
+    started: Instant,
+    seen: u64,
+    writer: PcapngWriter<CountingWriter>,
+    interface: InterfaceId,
+    file_packets: u64,
+    files: VecDeque<PathBuf>,
+    next_file: u64,
+}
+
+impl CaptureSession {
+    /// Starts the capture of a core with its first file.
+    fn start(
+        target: &CaptureTarget,
+        core: CoreId,
+        opts: CaptureOpts,
+        started: Instant,
+        counters: &CaptureCounters,
+    ) -> Result<Self> {
+        let name = format!("{}@{}", target, lcore_name(core));
+        let path = opts.path(core, 0);
+        let (writer, interface) = open_file(&path, &name, opts.snaplen, counters)?;
+
+        Ok(CaptureSession {
+            name,
+            core,
+            opts,
+            started,
+            seen: 0,
+            writer,
+            interface,
+            file_packets: 0,
+            files: vec![path].into(),
+            next_file: 1,
+        })
+    }
+
+    /// Returns whether the capture has run for its duration.
+    fn is_expired(&self) -> bool {
+        self.opts
+            .duration
+            .map_or(false, |dur| self.started.elapsed() >= dur)
+    }
+
+    /// Closes the current file and opens the next one, deleting the oldest
+    /// files over the limit.
+    fn rotate(&mut self, counters: &CaptureCounters) -> Result<()> {
+        let path = self.opts.path(self.core, self.next_file);
+        let (writer, interface) = open_file(&path, &self.name, self.opts.snaplen, counters)?;
+        let prev = mem::replace(&mut self.writer, writer);
+        prev.finish()?;
+
+        self.interface = interface;
+        self.file_packets = 0;
+        self.files.push_back(path);
+        self.next_file += 1;
+
+        while self.files.len() > self.opts.max_files {
+            if let Some(oldest) = self.files.pop_front() {
+                fs::remove_file(oldest)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the packets, and returns whether the capture is expired.
+    fn write(
+        &mut self,
+        mbufs: &[Mbuf],
+        packets: &AtomicU64,
+        counters: &CaptureCounters,
+    ) -> Result<bool> {
+        for mbuf in mbufs {
+            if self.write_one(mbuf, None, packets, counters)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Writes the packet with an optional comment if it passes the filter
+    /// and is sampled, and returns whether the capture is expired.
+    ///
+    /// `packets` counts the packets written by all the cores, to stop the
+    /// capture at its number of packets.
+    fn write_one(
+        &mut self,
+        mbuf: &Mbuf,
+        comment: Option<&str>,
+        packets: &AtomicU64,
+        counters: &CaptureCounters,
+    ) -> Result<bool> {
+        if self.is_expired() {
+            return Ok(true);
+        }
+
+        if !self.opts.filter.matches(mbuf) {
+            counters.record_filtered();
+            return Ok(false);
+        }
+
+        self.seen += 1;
+        if self.opts.sample > 1 && (self.seen - 1) % self.opts.sample != 0 {
+            return Ok(false);
+        }
+
+        // reserves the packet before writing it, so the cores together
+        // never write more than the limit.
+        let written = packets.fetch_add(1, Ordering::Relaxed) + 1;
+        let last = match self.opts.max_packets {
+            Some(max) if written > max => return Ok(true),
+            Some(max) => written == max,
+            None => false,
+        };
+
+        let captured = match self.opts.snaplen {
+            0 => mbuf.pkt_len(),
+            snaplen => cmp::min(mbuf.pkt_len(), snaplen),
+        };
+        let comment_len = comment.map_or(0, pcapng::comment_len);
+        let file_size =
+            self.writer.get_ref().bytes + (pcapng::packet_block_len(captured) + comment_len) as u64;
+        if self.file_packets > 0 && file_size > self.opts.max_file_size {
+            self.rotate(counters)?;
+        }
+
+        let before = self.writer.get_ref().bytes;
+        self.writer.write_mbuf(self.interface, mbuf, comment)?;
+        counters.record_bytes(self.writer.get_ref().bytes - before);
+        counters.record_packet();
+
+        self.file_packets += 1;
+        Ok(last)
+    }
+
+    /// Flushes the current file, and returns the files kept.
+    fn finish(self) -> Result<VecDeque<PathBuf>> {
+        self.writer.finish()?;
+        Ok(self.files)
+    }
+}
+
+/// Returns the name of a core in the capture files, `lcore{N}`, or
+/// `lcoreany` for a thread that's not pinned to a core.
+fn lcore_name(core: CoreId) -> String {
+    if core == CoreId::ANY {
+        "lcoreany".to_owned()
+    } else {
+        format!("lcore{}", core.raw())
+    }
+}
+
+/// Creates a capture file with the target described as its interface.
+fn open_file(
+    path: &Path,
+    name: &str,
+    snaplen: usize,
+    counters: &CaptureCounters,
+) -> Result<(PcapngWriter<CountingWriter>, InterfaceId)> {
+    let file = CountingWriter {
+        writer: BufWriter::new(File::create(path)?),
+        bytes: 0,
+    };
+    let mut writer = PcapngWriter::new(file)?;
+    // the snapshot length field of pcapng is 32 bits.
+    let snaplen = cmp::min(snaplen, u32::MAX as usize) as u32;
+    let interface = writer.add_interface(&Interface::new(name).snaplen(snaplen))?;
+
+    counters.record_file();
+    counters.record_bytes(writer.get_ref().bytes);
+    Ok((writer, interface))
+}
+
+/// The settings of a capture that's started, shared by its cores.
+struct CaptureControl {
+    opts: CaptureOpts,
+    started: Instant,
+}
+
+/// The capture state of a target.
+///
+/// Each core writes to its own session, so the cores never contend with
+/// each other. The lock of a core's session is only shared with the
+/// control plane, when the capture is stopped or its filter is swapped.
+struct CaptureSwitch {
+    target: CaptureTarget,
+    on: AtomicBool,
+    // bumped when a capture is closed, so a core doesn't keep a session it
+    // opens while the capture is closing.
+    epoch: AtomicU64,
+    packets: AtomicU64,
+    control: Mutex<Option<CaptureControl>>,
+    // the session of each lcore, and the last one is shared by the threads
+    // that are not pinned to a core.
+    cores: Vec<Mutex<Option<CaptureSession>>>,
+    counters: Arc<CaptureCounters>,
+}
+
+impl CaptureSwitch {
+    fn new(target: CaptureTarget, counters: Arc<CaptureCounters>) -> Self {
+        CaptureSwitch {
+            target,
+            on: AtomicBool::new(false),
+            epoch: AtomicU64::new(0),
+            packets: AtomicU64::new(0),
+            control: Mutex::new(None),
+            cores: (0..=MAX_LCORES).map(|_| Mutex::new(None)).collect(),
+            counters,
+        }
+    }
+
+    /// Returns the session of the core.
+    fn slot(&self, core: CoreId) -> &Mutex<Option<CaptureSession>> {
+        &self.cores[cmp::min(core.raw(), MAX_LCORES)]
+    }
+
+    /// Writes the packets to the capture, and turns the capture off once
+    /// it's expired or fails.
+    fn capture(&self, mbufs: &[Mbuf]) {
+        self.capture_with(|current, packets, counters| current.write(mbufs, packets, counters))
+    }
+
+    /// Writes the packet with a comment to the capture, and turns the
+    /// capture off once it's expired or fails.
+    fn capture_one(&self, mbuf: &Mbuf, comment: &str) {
+        self.capture_with(|current, packets, counters| {
+            current.write_one(mbuf, Some(comment), packets, counters)
+        })
+    }
+
+    fn capture_with<F>(&self, write: F)
+    where
+        F: FnOnce(&mut CaptureSession, &AtomicU64, &CaptureCounters) -> Result<bool>,
+    {
+        let core = CoreId::current();
+        let slot = self.slot(core);
+        let mut session = slot.lock().unwrap();
+        if session.is_none() {
+            drop(session);
+            self.open(core);
+            session = slot.lock().unwrap();
+        }
+
+        let res = match session.as_mut() {
+            Some(current) => write(current, &self.packets, &self.counters),
+            None => return,
+        };
+
+        // the files of all the cores are flushed by the controller, the
+        // next time it stops, checks or starts the capture.
+        match res {
+            Ok(false) => (),
+            Ok(true) => self.turn_off(),
+            Err(err) => {
+                warn!(message = "capture failed.", target = %self.target, ?core, ?err);
+                self.turn_off();
+            }
+        }
+    }
+
+    /// Opens the first file of the core the first time it captures.
+    fn open(&self, core: CoreId) {
+        let (opts, started, epoch) = match self.control.lock().unwrap().as_ref() {
+            Some(current) => (
+                current.opts.clone(),
+                current.started,
+                self.epoch.load(Ordering::SeqCst),
+            ),
+            None => return,
+        };
+
+        let res = CaptureSession::start(&self.target, core, opts, started, &self.counters);
+        let opened = match res {
+            Ok(opened) => opened,
+            Err(err) => {
+                warn!(message = "failed to open capture file.", target = %self.target, ?core, ?err);
+                self.turn_off();
+                return;
+            }
+        };
+
+        // the capture may be closed while the file is created, after the
+        // controller already went through the session of the core.
+        let mut session = self.slot(core).lock().unwrap();
+        if self.epoch.load(Ordering::SeqCst) == epoch {
+            *session = Some(opened);
+        } else {
+            let _ = opened.finish();
+        }
+    }
+
+    /// Turns the capture off from the data path.
+    fn turn_off(&self) {
+        self.on.store(false, Ordering::Relaxed);
+        self.counters.record_active(false);
+    }
+
+    /// Returns whether the capture is on and not past its duration.
+    fn is_running(&self, control: &CaptureControl) -> bool {
+        self.on.load(Ordering::Relaxed)
+            && control
+                .opts
+                .duration
+                .map_or(true, |dur| control.started.elapsed() < dur)
+    }
+
+    /// Turns the capture off, flushes the current file of every core, and
+    /// merges the files if the capture asks for it.
+    fn close(&self, control: &mut Option<CaptureControl>) -> Result<()> {
+        let current = match control.take() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        self.turn_off();
+
+        // the cores are in id order, so the merge breaks the timestamp ties
+        // by core id.
+        let mut files = vec![];
+        let mut res = Ok(());
+        for slot in self.cores.iter() {
+            if let Some(session) = slot.lock().unwrap().take() {
+                match session.finish() {
+                    Ok(kept) => files.extend(kept),
+                    Err(err) => res = Err(err),
+                }
+            }
+        }
+        res?;
+
+        if current.opts.merge {
+            let path = current.opts.merged_path();
+            let count = pcap::merge(&files, &path)?;
+            info!(message = "merged capture files.", target = %self.target, ?path, count);
+        }
+
+        Ok(())
+    }
+}
+
+static SWITCHES: Lazy<Mutex<HashMap<CaptureTarget, Arc<CaptureSwitch>>>> =
+    Lazy::new(Default::default);
+
+/// Returns the switch of a target, adding one that's off if the target
+/// is new.
+fn switch(target: &CaptureTarget) -> Arc<CaptureSwitch> {
+    SWITCHES
+        .lock()
+        .unwrap()
+        .entry(target.clone())
+        .or_insert_with(|| {
+            let counters = stats::register_capture(&target.to_string());
+            Arc::new(CaptureSwitch::new(target.clone(), counters))
+        })
+        .clone()
+}
+
+/// The data path end of a target's capture.
+#[derive(Clone)]
+pub(crate) struct CaptureTap {
+    switch: Arc<CaptureSwitch>,
+}
+
+impl CaptureTap {
+    /// Creates the tap of a target.
+    pub(crate) fn new(target: CaptureTarget) -> Self {
+        CaptureTap {
+            switch: switch(&target),
+        }
+    }
+
+    /// Returns whether the capture is on.
+    #[inline]
+    pub(crate) fn is_on(&self) -> bool {
+        self.switch.on.load(Ordering::Relaxed)
+    }
+
+    /// Writes the packets to the capture if it's on.
+    #[inline]
+    pub(crate) fn tap(&self, mbufs: &[Mbuf]) {
+        if self.is_on() {
+            self.switch.capture(mbufs);
+        }
+    }
+}
+
+/// A tap that's not attached to any target, and is never on.
+impl Default for CaptureTap {
+    fn default() -> Self {
+        let target = CaptureTarget::Port(String::new());
+        let counters = Arc::new(CaptureCounters::default());
+        CaptureTap {
+            switch: Arc::new(CaptureSwitch::new(target, counters)),
+        }
+    }
+}
+
+/// Starts and stops packet captures at runtime.
+///
+/// The controller is returned by [`Runtime::capture_controller`], and can
+/// be cloned and moved to control tasks. A target can be captured before
+/// its port or pipeline is added, the capture picks up the packets once
+/// there are any.
+///
+/// [`Runtime::capture_controller`]: crate::Runtime::capture_controller
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CaptureController;
+
+impl CaptureController {
+    /// Starts capturing the packets of the target. Takes effect from the
+    /// next burst, and each core creates its first file when it captures
+    /// its first packet.
+    ///
+    /// A previous capture of the target that stopped by itself is flushed,
+    /// and merged if it asks for it, first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the target is already captured, the settings
+    /// keep no files, or the previous capture fails to close.
+    pub fn start(&self, target: CaptureTarget, opts: CaptureOpts) -> Result<()> {
+        ensure!(opts.max_files > 0, CaptureError::NoFiles);
+
+        let switch = switch(&target);
+        let mut control = switch.control.lock().unwrap();
+        if let Some(current) = control.as_ref() {
+            ensure!(
+                !switch.is_running(current),
+                CaptureError::AlreadyStarted(target.clone())
+            );
+            switch.close(&mut control)?;
+        }
+
+        *control = Some(CaptureControl {
+            opts,
+            started: Instant::now(),
+        });
+        switch.packets.store(0, Ordering::Relaxed);
+        switch.counters.record_active(true);
+        switch.on.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Swaps the filter of a running capture. Takes effect from the next
+    /// burst, the packets already written are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the target is not captured.
+    pub fn set_filter(&self, target: &CaptureTarget, filter: CaptureFilter) -> Result<()> {
+        let switch = switch(target);
+        let mut control = switch.control.lock().unwrap();
+        let current = control
+            .as_mut()
+            .ok_or_else(|| CaptureError::NotStarted(target.clone()))?;
+        current.opts.filter = filter.clone();
+
+        for slot in switch.cores.iter() {
+            if let Some(session) = slot.lock().unwrap().as_mut() {
+                session.opts.filter = filter.clone();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops capturing the packets of the target, flushes the current file
+    /// of every core, and merges the files if the capture asks for it. Does
+    /// nothing if the capture is already closed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing or merging the files fails.
+    pub fn stop(&self, target: &CaptureTarget) -> Result<()> {
+        let switch = switch(target);
+        let mut control = switch.control.lock().unwrap();
+        switch.close(&mut control)
+    }
+
+    /// Returns whether the target is captured.
+    ///
+    /// A capture that stopped by itself, or runs past its duration while no
+    /// packets arrive, is closed here like it's stopped.
+    pub fn is_active(&self, target: &CaptureTarget) -> bool {
+        let switch = switch(target);
+        let mut control = switch.control.lock().unwrap();
+        match control.as_ref().map(|current| switch.is_running(current)) {
+            Some(true) => true,
+            Some(false) => {
+                if let Err(err) = switch.close(&mut control) {
+                    warn!(message = "failed to close capture.", target = %target, ?err);
+                }
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap;
+    use crate::stats::RuntimeStats;
+    use crate::testils::byte_arrays::{IPV4_TCP_PACKET, IPV4_UDP_PACKET};
+    use std::thread;
+
+    fn packets(count: usize) -> Vec<Mbuf> {
+        (0..count)
+            .map(|_| Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap())
+            .collect()
+    }
+
+    /// Returns the path of the `n`th file of the test's thread.
+    fn path(opts: &CaptureOpts, n: u64) -> PathBuf {
+        opts.path(CoreId::current(), n)
+    }
+
+    fn remove_files(opts: &CaptureOpts, count: u64) {
+        for n in 0..count {
+            let _ = fs::remove_file(path(opts, n));
+        }
+    }
+
+    #[capsule::test]
+    fn capture_with_snaplen() {
+        let target = CaptureTarget::Pipeline("capture_snaplen".to_owned());
+        let opts = CaptureOpts::new("capture_snaplen").snaplen(20);
+        let tap = CaptureTap::new(target.clone());
+        let controller = CaptureController;
+
+        controller.start(target.clone(), opts.clone()).unwrap();
+        assert!(controller.is_active(&target));
+        assert!(controller.start(target.clone(), opts.clone()).is_err());
+
+        tap.tap(&packets(3));
+        controller.stop(&target).unwrap();
+        assert!(!controller.is_active(&target));
+
+        let captured = pcap::read_records(path(&opts, 0))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(3, captured.len());
+        for record in captured.iter() {
+            assert_eq!(&IPV4_UDP_PACKET[..20], &record.data[..]);
+            assert_eq!(IPV4_UDP_PACKET.len(), record.len);
+        }
+
+        let stats = RuntimeStats::snapshot()
+            .capture("pipeline:capture_snaplen")
+            .unwrap();
+        assert!(!stats.active);
+        assert_eq!(3, stats.packets);
+        assert_eq!(1, stats.files);
+        assert_eq!(fs::metadata(path(&opts, 0)).unwrap().len(), stats.bytes);
+
+        remove_files(&opts, 1);
+    }
+
+    #[capsule::test]
+    fn snaplen_shorter_than_header() {
+        let target = CaptureTarget::Pipeline("capture_short_snaplen".to_owned());
+        let opts = CaptureOpts::new("capture_short_snaplen")
+            .snaplen(6)
+            .filter(CaptureFilter::new().udp_dst_port(1087));
+        let tap = CaptureTap::new(target.clone());
+        let controller = CaptureController;
+
+        // the filter still sees the UDP header past the snapshot.
+        controller.start(target.clone(), opts.clone()).unwrap();
+        tap.tap(&[
+            Mbuf::from_bytes(&IPV4_TCP_PACKET).unwrap(),
+            Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap(),
+        ]);
+        controller.stop(&target).unwrap();
+
+        let captured = pcap::read_records(path(&opts, 0))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(1, captured.len());
+        assert_eq!(&IPV4_UDP_PACKET[..6], &captured[0].data[..]);
+        assert_eq!(IPV4_UDP_PACKET.len(), captured[0].len);
+
+        remove_files(&opts, 1);
+    }
+
+    #[capsule::test]
+    fn rotate_at_max_file_size() {
+        let target = CaptureTarget::Pipeline("capture_rotation".to_owned());
+        let block = pcapng::packet_block_len(IPV4_UDP_PACKET.len()) as u64;
+
+        // sizes the files to hold the headers and 3 packets.
+        let mut probe = Vec::new();
+        {
+            let mut writer = PcapngWriter::new(&mut probe).unwrap();
+            let name = format!("{}@{}", target, lcore_name(CoreId::current()));
+            let _ = writer.add_interface(&Interface::new(&name)).unwrap();
+        }
+        let header = probe.len() as u64;
+        let opts = CaptureOpts::new("capture_rotation")
+            .max_file_size(header + block * 3)
+            .max_files(2);
+
+        let tap = CaptureTap::new(target.clone());
+        let controller = CaptureController;
+        controller.start(target.clone(), opts.clone()).unwrap();
+
+        // 10 packets are written to 4 files, 3 + 3 + 3 + 1. only the last 2
+        // files are kept.
+        tap.tap(&packets(4));
+        tap.tap(&packets(6));
+        controller.stop(&target).unwrap();
+
+        assert!(!path(&opts, 0).exists());
+        assert!(!path(&opts, 1).exists());
+        assert_eq!(
+            header + block * 3,
+            fs::metadata(path(&opts, 2)).unwrap().len()
+        );
+        assert_eq!(header + block, fs::metadata(path(&opts, 3)).unwrap().len());
+        assert!(!path(&opts, 4).exists());
+
+        let stats = RuntimeStats::snapshot()
+            .capture("pipeline:capture_rotation")
+            .unwrap();
+        assert_eq!(10, stats.packets);
+        assert_eq!(4, stats.files);
+        assert_eq!(header * 4 + block * 10, stats.bytes);
+
+        remove_files(&opts, 4);
+    }
+
+    #[capsule::test]
+    fn keep_oversized_packet_in_own_file() {
+        let target = CaptureTarget::Pipeline("capture_oversized".to_owned());
+        let opts = CaptureOpts::new("capture_oversized").max_file_size(64);
+        let tap = CaptureTap::new(target.clone());
+        let controller = CaptureController;
+
+        controller.start(target.clone(), opts.clone()).unwrap();
+        tap.tap(&packets(2));
+        controller.stop(&target).unwrap();
+
+        for n in 0..2 {
+            let captured = pcap::read_file(path(&opts, n))
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(1, captured.len());
+        }
+
+        remove_files(&opts, 2);
+    }
+
+    #[capsule::test]
+    fn stop_after_max_packets() {
+        let target = CaptureTarget::Pipeline("capture_max_packets".to_owned());
+        let opts = CaptureOpts::new("capture_max_packets").max_packets(5);
+        let tap = CaptureTap::new(target.clone());
+        let controller = CaptureController;
+
+        controller.start(target.clone(), opts.clone()).unwrap();
+        tap.tap(&packets(3));
+        assert!(controller.is_active(&target));
+        tap.tap(&packets(3));
+        assert!(!controller.is_active(&target));
+        tap.tap(&packets(3));
+
+        let captured = pcap::read_file(path(&opts, 0)).count();
+        assert_eq!(5, captured);
+
+        // already stopped by itself.
+        assert!(controller.stop(&target).is_ok());
+
+        remove_files(&opts, 1);
+    }
+
+    #[capsule::test]
+    fn stop_after_duration() {
+        let target = CaptureTarget::Pipeline("capture_duration".to_owned());
+        let opts = CaptureOpts::new("capture_duration").duration(Duration::from_millis(10));
+        let controller = CaptureController;
+
+        controller.start(target.clone(), opts.clone()).unwrap();
+        assert!(controller.is_active(&target));
+        thread::sleep(Duration::from_millis(20));
+        assert!(!controller.is_active(&target));
+
+        let stats = RuntimeStats::snapshot()
+            .capture("pipeline:capture_duration")
+            .unwrap();
+        assert!(!stats.active);
+
+        remove_files(&opts, 1);
+    }
+
+    #[capsule::test]
+    fn swap_filter_at_runtime() {
+        let target = CaptureTarget::Port("capture_swap_filter".to_owned());
+        let opts =
+            CaptureOpts::new("capture_swap_filter").filter(CaptureFilter::new().tcp_dst_port(23));
+        let tap = CaptureTap::new(target.clone());
+        let controller = CaptureController;
+
+        let mixed = || {
+            vec![
+                Mbuf::from_bytes(&IPV4_TCP_PACKET).unwrap(),
+                Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap(),
+                Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap(),
+            ]
+        };
+
+        assert!(controller
+            .set_filter(&target, CaptureFilter::new())
+            .is_err());
+
+        controller.start(target.clone(), opts.clone()).unwrap();
+        tap.tap(&mixed());
+        controller
+            .set_filter(&target, CaptureFilter::new().udp_dst_port(1087))
+            .unwrap();
+        tap.tap(&mixed());
+        controller.stop(&target).unwrap();
+
+        // the TCP segment of the first burst, then the UDP datagrams of
+        // the second.
+        let captured = pcap::read_file(path(&opts, 0))
+            .map(|res| res.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(3, captured.len());
+        assert_eq!(&IPV4_TCP_PACKET[..], &captured[0][..]);
+        assert_eq!(&IPV4_UDP_PACKET[..], &captured[1][..]);
+        assert_eq!(&IPV4_UDP_PACKET[..], &captured[2][..]);
+
+        let stats = RuntimeStats::snapshot()
+            .capture("port:capture_swap_filter")
+            .unwrap();
+        assert_eq!(3, stats.packets);
+        assert_eq!(3, stats.filtered);
+
+        remove_files(&opts, 1);
+    }
+
+    #[capsule::test]
+    fn merge_files_at_stop() {
+        let target = CaptureTarget::Pipeline("capture_merged".to_owned());
+        let opts = CaptureOpts::new("capture_merged")
+            .max_file_size(256)
+            .merged();
+        let tap = CaptureTap::new(target.clone());
+        let controller = CaptureController;
+
+        controller.start(target.clone(), opts.clone()).unwrap();
+        tap.tap(&packets(4));
+        tap.tap(&packets(4));
+        controller.stop(&target).unwrap();
+
+        // the rotated files of the core are merged back in order.
+        let captured = pcap::read_file(opts.merged_path())
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(8, captured.len());
+        assert!(captured.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert!(path(&opts, 0).exists());
+
+        remove_files(&opts, 8);
+        let _ = fs::remove_file(opts.merged_path());
+    }
+
+    #[test]
+    fn reject_no_files() {
+        let target = CaptureTarget::Pipeline("capture_no_files".to_owned());
+        let opts = CaptureOpts::new("capture_no_files").max_files(0);
+        assert!(CaptureController.start(target, opts).is_err());
+    }
+
+    #[capsule::test]
+    fn disabled_tap_is_single_branch() {
+        let target = CaptureTarget::Port("capture_disabled".to_owned());
+        let opts = CaptureOpts::new("capture_disabled");
+        let tap = CaptureTap::new(target.clone());
+        let controller = CaptureController;
+
+        controller.start(target.clone(), opts.clone()).unwrap();
+        tap.tap(&packets(2));
+        controller.stop(&target).unwrap();
+
+        // poisons the session lock of the core. a tap that's off must not
+        // touch it, or it panics.
+        let core = CoreId::current();
+        let switch = tap.switch.clone();
+        let _ = thread::spawn(move || {
+            let _session = switch.slot(core).lock().unwrap();
+            panic!("poison the session lock.");
+        })
+        .join();
+        assert!(tap.switch.slot(core).is_poisoned());
+
+        tap.tap(&packets(4));
+        let stats = RuntimeStats::snapshot()
+            .capture("port:capture_disabled")
+            .unwrap();
+        assert!(!stats.active);
+        assert_eq!(2, stats.packets);
+
+        remove_files(&opts, 1);
+    }
+}
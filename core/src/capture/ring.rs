@@ -0,0 +1,130 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::packets::Ethernet;
+use anyhow::Result;
+use std::collections::VecDeque;
+
+/// A ring buffer of byte snapshots of the most recent frames.
+///
+/// Useful for post-mortem debugging. Record the frames a core processes,
+/// and drain the ring to inspect the recent history when an anomaly is
+/// detected. Once the ring is full, the oldest snapshot is discarded for
+/// each new one. The ring is not shared, keep one per core to avoid
+/// contention.
+///
+/// # Example
+///
+/// ```
+/// let mut ring = CaptureRing::new(64);
+///
+/// let mut batch = batch.for_each(move |ethernet| {
+///     ring.record(ethernet)?;
+///     if is_anomaly(ethernet) {
+///         dump(ring.drain());
+///     }
+///     Ok(())
+/// });
+/// ```
+#[derive(Debug)]
+pub struct CaptureRing {
+    capacity: usize,
+    frames: VecDeque<Vec<u8>>,
+}
+
+impl CaptureRing {
+    /// Creates a new ring that keeps the last `capacity` frames.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capture ring capacity must be positive.");
+
+        CaptureRing {
+            capacity,
+            frames: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the maximum number of frames kept.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of frames recorded.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns whether no frames are recorded.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Records a snapshot of the frame, discarding the oldest snapshot if
+    /// the ring is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame's bytes can't be read from the buffer.
+    pub fn record(&mut self, frame: &Ethernet) -> Result<()> {
+        let snapshot = frame.to_vec()?;
+        if self.frames.len() == self.capacity {
+            let _ = self.frames.pop_front();
+        }
+        self.frames.push_back(snapshot);
+        Ok(())
+    }
+
+    /// Removes all the snapshots from the ring, from the oldest to the
+    /// newest.
+    pub fn drain(&mut self) -> Vec<Vec<u8>> {
+        self.frames.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::Packet;
+    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, VLAN_DOT1Q_PACKET, VLAN_QINQ_PACKET};
+    use crate::Mbuf;
+
+    #[capsule::test]
+    fn capture_ring_keeps_last_frames() {
+        let mut ring = CaptureRing::new(2);
+        assert!(ring.is_empty());
+
+        for bytes in [&IPV4_UDP_PACKET[..], &VLAN_DOT1Q_PACKET, &VLAN_QINQ_PACKET].iter() {
+            let packet = Mbuf::from_bytes(bytes).unwrap();
+            let ethernet = packet.parse::<Ethernet>().unwrap();
+            ring.record(&ethernet).unwrap();
+        }
+
+        // the oldest frame is discarded.
+        assert_eq!(2, ring.len());
+        let frames = ring.drain();
+        assert_eq!(&VLAN_DOT1Q_PACKET[..], &frames[0][..]);
+        assert_eq!(&VLAN_QINQ_PACKET[..], &frames[1][..]);
+        assert!(ring.is_empty());
+    }
+}
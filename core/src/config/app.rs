@@ -0,0 +1,212 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{ConfigFormat, RuntimeConfig};
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::sync::Arc;
+use toml::value::{Table, Value};
+
+/// The file the settings are loaded from.
+#[derive(Clone, Debug)]
+pub(crate) struct ConfigSource {
+    file: String,
+    content: Arc<str>,
+    format: ConfigFormat,
+}
+
+impl ConfigSource {
+    pub(crate) fn new(file: &str, content: &str, format: ConfigFormat) -> Self {
+        ConfigSource {
+            file: file.to_owned(),
+            content: content.into(),
+            format,
+        }
+    }
+}
+
+/// The settings are the same regardless of the file they are loaded from.
+impl PartialEq for ConfigSource {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// The `[app]` table of the file. The settings of the application are
+/// deserialized from it, both from the parsed value and from the file to
+/// locate the problems, so the errors are the same.
+#[derive(Deserialize)]
+struct AppSection<T> {
+    app: T,
+}
+
+impl RuntimeConfig {
+    /// Returns the settings of the application, in the `[app]` table of
+    /// the file.
+    ///
+    /// Capsule keeps the table as is, and the overrides reach into it with
+    /// the `app.` prefix. The table is deserialized on every call, so call
+    /// it once at startup. Without an `[app]` table, `T` is deserialized
+    /// from an empty table, which works if all its fields have defaults.
+    ///
+    /// # Errors
+    ///
+    /// Returns the `Diagnostics` with the location in the file if the
+    /// table can't be deserialized as `T`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[derive(Deserialize)]
+    /// struct Settings {
+    ///     vips: Vec<Ipv4Addr>,
+    ///     policy: String,
+    /// }
+    ///
+    /// let config = load_config()?;
+    /// let settings = config.app_config::<Settings>()?;
+    /// ```
+    pub fn app_config<T: DeserializeOwned>(&self) -> Result<T> {
+        let app = self
+            .app
+            .clone()
+            .unwrap_or_else(|| Value::Table(Table::new()));
+        let mut table = Table::new();
+        let _ = table.insert("app".to_owned(), app);
+
+        match Value::Table(table).try_into::<AppSection<T>>() {
+            Ok(section) => Ok(section.app),
+            Err(err) => Err(match &self.source {
+                Some(source) => source
+                    .format
+                    .locate_error::<AppSection<T>>(&source.file, &source.content, err)
+                    .into(),
+                None => err.into(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{parse_config, Diagnostics, Override};
+    use std::net::Ipv4Addr;
+
+    const CONFIG: &str = r#"
+app_name = "myapp"
+master_core = 0
+
+[[ports]]
+    name = "eth0"
+    device = "0000:00:01.0"
+    cores = [1]
+
+[app]
+    policy = "allow"
+    rate_limit = 1000
+
+    [[app.vips]]
+        address = "10.0.0.1"
+        port = 80
+"#;
+
+    #[derive(Debug, Deserialize)]
+    struct Vip {
+        address: Ipv4Addr,
+        port: u16,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Settings {
+        policy: String,
+        rate_limit: u32,
+        vips: Vec<Vip>,
+        #[serde(default)]
+        verbose: bool,
+    }
+
+    #[test]
+    fn load_app_config() {
+        let config = parse_config("config.toml", CONFIG, ConfigFormat::Toml, &[]).unwrap();
+        let settings = config.app_config::<Settings>().unwrap();
+
+        assert_eq!("allow", settings.policy);
+        assert_eq!(1000, settings.rate_limit);
+        assert_eq!(Ipv4Addr::new(10, 0, 0, 1), settings.vips[0].address);
+        assert_eq!(80, settings.vips[0].port);
+        assert!(!settings.verbose);
+    }
+
+    #[test]
+    fn override_app_config() {
+        let overrides = vec![
+            Override::from_env("CAPSULE_APP_RATE_LIMIT", "500")
+                .unwrap()
+                .unwrap(),
+            Override::from_env("CAPSULE_APP_VIPS_0_PORT", "8080")
+                .unwrap()
+                .unwrap(),
+            Override::from_env("CAPSULE_APP_VERBOSE", "true")
+                .unwrap()
+                .unwrap(),
+            Override::from_key("app.vips.1.address", "10.0.0.2").unwrap(),
+            Override::from_key("app.vips.1.port", "443").unwrap(),
+        ];
+
+        let config = parse_config("config.toml", CONFIG, ConfigFormat::Toml, &overrides).unwrap();
+        let settings = config.app_config::<Settings>().unwrap();
+
+        assert_eq!(500, settings.rate_limit);
+        assert_eq!(8080, settings.vips[0].port);
+        assert!(settings.verbose);
+        assert_eq!(Ipv4Addr::new(10, 0, 0, 2), settings.vips[1].address);
+        assert_eq!(443, settings.vips[1].port);
+
+        // the app name is a setting of capsule, not of the app.
+        let o = Override::from_env("CAPSULE_APP_NAME", "other")
+            .unwrap()
+            .unwrap();
+        let config = parse_config("config.toml", CONFIG, ConfigFormat::Toml, &[o]).unwrap();
+        assert_eq!("other", config.app_name);
+    }
+
+    #[test]
+    fn locate_app_config_errors() {
+        let content = CONFIG.replace("port = 80", "port = \"http\"");
+        let config = parse_config("config.toml", &content, ConfigFormat::Toml, &[]).unwrap();
+
+        let err = config.app_config::<Settings>().unwrap_err();
+        let diagnostics = err.downcast_ref::<Diagnostics>().unwrap();
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert!(diagnostic
+            .message()
+            .contains("invalid type: string \"http\""));
+        let span = diagnostic.span().unwrap();
+        assert_eq!("config.toml", span.file);
+        assert_eq!(16, span.line);
+
+        // the value of the file is converted to the type in the file.
+        let o = Override::from_key("app.rate_limit", "lots").unwrap();
+        let err = parse_config("config.toml", CONFIG, ConfigFormat::Toml, &[o]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("app.rate_limit is not an integer: 'lots'."));
+    }
+}
@@ -0,0 +1,522 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::ConfigError;
+use crate::warn;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// How serious a problem with the settings is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    /// The settings can't be used.
+    Error,
+
+    /// The settings work, but are likely a mistake.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// The location of a setting in the config file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Span {
+    /// The name of the file.
+    pub file: String,
+
+    /// The line, starting at `1`.
+    pub line: usize,
+
+    /// The column, starting at `1`.
+    pub column: usize,
+
+    /// The text of the line.
+    pub text: String,
+}
+
+/// A problem with the settings.
+#[derive(Debug)]
+pub struct Diagnostic {
+    severity: Severity,
+    message: String,
+    key: Option<String>,
+    span: Option<Span>,
+}
+
+impl Diagnostic {
+    /// Returns the severity.
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// Returns the message.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the setting with the problem as an override key, for
+    /// example `ports.0.rxd`. Not known for errors parsing the file.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /// Returns the location in the config file, if the settings are loaded
+    /// from one and the setting is in it.
+    pub fn span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+}
+
+/// Renders the diagnostic with the offending line of the file, for example
+///
+/// ```
+/// error: Port eth0 rxd '0' is not between 1 and 65535.
+///   --> config.toml:12:5
+///    |
+/// 12 |     rxd = 0
+///    |     ^^^^^^^
+/// ```
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)?;
+
+        match (&self.span, &self.key) {
+            (Some(span), _) => {
+                let width = span.line.to_string().len();
+                let pad = " ".repeat(width);
+                let indent = span.column - 1;
+                let carets = span.text.trim_end().len().saturating_sub(indent).max(1);
+
+                write!(
+                    f,
+                    "\n{}--> {}:{}:{}",
+                    pad, span.file, span.line, span.column
+                )?;
+                write!(f, "\n{} |", pad)?;
+                write!(f, "\n{} | {}", span.line, span.text.trim_end())?;
+                write!(
+                    f,
+                    "\n{} | {}{}",
+                    pad,
+                    " ".repeat(indent),
+                    "^".repeat(carets)
+                )
+            }
+            (None, Some(key)) => write!(f, "\n --> {}", key),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// All the problems found with the settings.
+///
+/// The semantic checks collect every problem instead of stopping at the
+/// first. A file that can't be parsed only has the parse error.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    /// Adds a semantic problem with a setting.
+    pub(crate) fn push(&mut self, key: String, error: ConfigError) {
+        self.diagnostics.push(Diagnostic {
+            severity: error.severity(),
+            message: error.to_string(),
+            key: Some(key),
+            span: None,
+        });
+    }
+
+    /// Creates the diagnostic of an error parsing the file.
+    pub(crate) fn parse_error(file: &str, content: &str, err: &toml::de::Error) -> Self {
+        let location = err.line_col().map(|(line, column)| (line + 1, column + 1));
+        Diagnostics::located_error(file, content, err.to_string(), location)
+    }
+
+    /// Creates the diagnostic of an error parsing the file at the line and
+    /// column, both starting at `1`.
+    pub(crate) fn located_error(
+        file: &str,
+        content: &str,
+        mut message: String,
+        location: Option<(usize, usize)>,
+    ) -> Self {
+        let span = location.map(|(line, column)| {
+            // the location is rendered separately.
+            let suffix = format!(" at line {} column {}", line, column);
+            if message.ends_with(&suffix) {
+                message.truncate(message.len() - suffix.len());
+            }
+
+            Span {
+                file: file.to_owned(),
+                line,
+                column,
+                text: content
+                    .lines()
+                    .nth(line.saturating_sub(1))
+                    .unwrap_or_default()
+                    .to_owned(),
+            }
+        });
+
+        Diagnostics {
+            diagnostics: vec![Diagnostic {
+                severity: Severity::Error,
+                message,
+                key: None,
+                span,
+            }],
+        }
+    }
+
+    /// Finds the settings with problems in the file. A setting not in the
+    /// file, for example one set by an override, is located at the closest
+    /// table that is.
+    pub(crate) fn locate(&mut self, file: &str, content: &str) {
+        for diagnostic in self.diagnostics.iter_mut() {
+            let mut key = match &diagnostic.key {
+                Some(key) => key.as_str(),
+                None => continue,
+            };
+
+            diagnostic.span = loop {
+                if let Some((line, column)) = locate(content, key) {
+                    break Some(Span {
+                        file: file.to_owned(),
+                        line,
+                        column,
+                        text: content.lines().nth(line - 1).unwrap_or_default().to_owned(),
+                    });
+                }
+
+                match key.rfind('.') {
+                    Some(dot) => key = &key[..dot],
+                    None => break None,
+                }
+            };
+        }
+    }
+
+    /// Returns an iterator over the diagnostics, in the order they are
+    /// found.
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    /// Returns whether any of the diagnostics is an error.
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    /// Fails with all the diagnostics if there are errors, and logs the
+    /// warnings otherwise.
+    pub(crate) fn into_result(self) -> Result<()> {
+        if self.has_errors() {
+            return Err(self.into());
+        }
+
+        for diagnostic in self.diagnostics.iter() {
+            warn!("{}", diagnostic);
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for Diagnostics {}
+
+/// Returns the line and column of a setting in the file, both starting at
+/// `1`. The key is the dotted path of the setting, for example
+/// `ports.1.name`, or of a table, for example `ports.1`.
+///
+/// Only reads as much of TOML as the config files use, the table headers
+/// and the `key = value` lines.
+fn locate(content: &str, key: &str) -> Option<(usize, usize)> {
+    // the path of the current table, with the array indices.
+    let mut table = String::new();
+    // the number of elements of each array of tables so far.
+    let mut arrays = HashMap::<String, usize>::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        let column = line.len() - line.trim_start().len() + 1;
+
+        if trimmed.starts_with('[') {
+            let is_array = trimmed.starts_with("[[");
+            let header = trimmed.trim_start_matches('[').split(']').next()?;
+            let parts = header.split('.').map(str::trim).collect::<Vec<_>>();
+
+            table.clear();
+            for (j, part) in parts.iter().enumerate() {
+                if !table.is_empty() {
+                    table.push('.');
+                }
+                table.push_str(part.trim_matches('"'));
+
+                let last = j == parts.len() - 1;
+                if last && is_array {
+                    let count = arrays.entry(table.clone()).or_insert(0);
+                    *count += 1;
+                    table = format!("{}.{}", table, *count - 1);
+                } else if !last {
+                    if let Some(count) = arrays.get(&table) {
+                        table = format!("{}.{}", table, count - 1);
+                    }
+                }
+            }
+
+            if table == key {
+                return Some((i + 1, column));
+            }
+        } else if !trimmed.starts_with('#') {
+            if let Some(eq) = trimmed.find('=') {
+                let name = trimmed[..eq].trim().trim_matches('"');
+                let path = if table.is_empty() {
+                    name.to_owned()
+                } else {
+                    format!("{}.{}", table, name)
+                };
+
+                if path == key {
+                    return Some((i + 1, column));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{parse_config, ConfigFormat, Override, PortConfig, RuntimeConfig};
+
+    /// Returns the diagnostics of the settings of the file.
+    fn diagnose(content: &str) -> String {
+        let err = parse_config("config.toml", content, ConfigFormat::Toml, &[]).unwrap_err();
+        err.downcast_ref::<Diagnostics>().unwrap().to_string()
+    }
+
+    #[test]
+    fn locate_settings() {
+        const CONFIG: &str = r#"
+app_name = "myapp"
+master_core = 0
+
+[[ports]]
+    name = "eth0"
+    cores = [1]
+
+    [[ports.queues]]
+        core = 1
+
+    [[ports.queues]]
+        core = 2
+
+[[ports]]
+    name = "eth1"
+
+    [ports.vdev]
+        # type = "null"
+        type = "tap"
+"#;
+
+        assert_eq!(Some((3, 1)), locate(CONFIG, "master_core"));
+        assert_eq!(Some((5, 1)), locate(CONFIG, "ports.0"));
+        assert_eq!(Some((7, 5)), locate(CONFIG, "ports.0.cores"));
+        assert_eq!(Some((13, 9)), locate(CONFIG, "ports.0.queues.1.core"));
+        assert_eq!(Some((16, 5)), locate(CONFIG, "ports.1.name"));
+        assert_eq!(Some((20, 9)), locate(CONFIG, "ports.1.vdev.type"));
+        assert_eq!(None, locate(CONFIG, "ports.1.cores"));
+        assert_eq!(None, locate(CONFIG, "ports.2"));
+    }
+
+    #[test]
+    fn collect_all_problems() {
+        const CONFIG: &str = r#"
+app_name = "myapp"
+master_core = 0
+
+[mempool]
+    capacity = 1000
+
+[[ports]]
+    name = "eth0"
+    device = "0000:00:01.0"
+    cores = [1, 1]
+    rxd = 0
+
+[[ports]]
+    name = "eth0"
+    device = "0000:00:02.0"
+    cores = [2]
+    rx_burst = 1024
+"#;
+
+        const EXPECTED: &str = r#"warning: Mempool capacity '1000' is not a power of two minus one, which wastes memory.
+ --> config.toml:6:5
+  |
+6 |     capacity = 1000
+  |     ^^^^^^^^^^^^^^^
+
+error: core1 is assigned to port eth0 more than once.
+  --> config.toml:11:5
+   |
+11 |     cores = [1, 1]
+   |     ^^^^^^^^^^^^^^
+
+error: Port eth0 rxd '0' is not between 1 and 65535.
+  --> config.toml:12:5
+   |
+12 |     rxd = 0
+   |     ^^^^^^^
+
+error: Port eth0 rx_burst '1024' is not between 1 and 512.
+  --> config.toml:18:5
+   |
+18 |     rx_burst = 1024
+   |     ^^^^^^^^^^^^^^^
+
+error: Port eth0 is configured more than once.
+  --> config.toml:15:5
+   |
+15 |     name = "eth0"
+   |     ^^^^^^^^^^^^^"#;
+
+        assert_eq!(EXPECTED, diagnose(CONFIG));
+    }
+
+    #[test]
+    fn locate_overridden_settings() {
+        const CONFIG: &str = r#"
+app_name = "myapp"
+master_core = 0
+
+[[ports]]
+    name = "eth0"
+    device = "0000:00:01.0"
+    cores = [1]
+"#;
+
+        const EXPECTED: &str = r#"error: core2 of the queue settings is not assigned to port eth0.
+ --> config.toml:5:1
+  |
+5 | [[ports]]
+  | ^^^^^^^^^"#;
+
+        // the queue is only in the overrides, so the port is located instead.
+        let overrides = vec![Override::from_key("ports.0.queues.0.core", "2").unwrap()];
+        let err = parse_config("config.toml", CONFIG, ConfigFormat::Toml, &overrides).unwrap_err();
+        let rendered = err.downcast_ref::<Diagnostics>().unwrap().to_string();
+
+        assert_eq!(EXPECTED, rendered);
+    }
+
+    #[test]
+    fn render_without_file() {
+        let err = RuntimeConfig::builder()
+            .app_name("myapp")
+            .port(
+                PortConfig::builder()
+                    .name("eth0")
+                    .device("net_ring0")
+                    .core(1),
+            )
+            .port(
+                PortConfig::builder()
+                    .name("eth1")
+                    .device("net_ring1")
+                    .core(2)
+                    .rx_filter(&[], &[100, 5000]),
+            )
+            .build()
+            .unwrap_err();
+
+        assert_eq!(
+            "error: Port eth1 VLAN id '5000' is not between 0 and 4095.\n --> ports.1.vlan_ids",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn locate_parse_errors() {
+        const SYNTAX: &str = r#"
+app_name = "myapp
+master_core = 0
+"#;
+
+        let rendered = diagnose(SYNTAX);
+        assert!(rendered.starts_with("error: "));
+        assert!(rendered.contains(" --> config.toml:2:"));
+        assert!(rendered.contains("2 | app_name = \"myapp"));
+
+        const UNKNOWN: &str = r#"
+app_name = "myapp"
+master_core = 0
+
+[[ports]]
+    name = "eth0"
+    device = "0000:00:01.0"
+    cores = [1]
+    rx_bust = 64
+"#;
+
+        let rendered = diagnose(UNKNOWN);
+        assert!(rendered.starts_with("error: unknown field `rx_bust`"));
+    }
+
+    #[test]
+    fn warnings_are_not_errors() {
+        let config = RuntimeConfig::builder()
+            .app_name("myapp")
+            .mempool(1000, 0)
+            .build()
+            .unwrap();
+
+        assert_eq!(1000, config.mempool.capacity);
+        assert!(!config.check().has_errors());
+        assert_eq!(1, config.check().iter().count());
+    }
+}
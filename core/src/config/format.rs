@@ -0,0 +1,338 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{ConfigError, Diagnostics};
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+use std::str::FromStr;
+use toml::Value;
+
+/// The format of a config file.
+///
+/// TOML is always supported. YAML needs the `config-yaml` feature and JSON
+/// the `config-json` feature.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfigFormat {
+    /// TOML, with the `.toml` extension.
+    Toml,
+
+    /// YAML, with the `.yaml` or `.yml` extension.
+    Yaml,
+
+    /// JSON, with the `.json` extension.
+    Json,
+}
+
+impl ConfigFormat {
+    /// Returns the format of the file by its extension.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::UnknownFormat` if the extension is not one of
+    /// the formats.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        extension.parse()
+    }
+
+    /// Parses the content of the file to the value the overrides apply to.
+    ///
+    /// The problems are reported with their location in the file.
+    pub(crate) fn parse(self, file: &str, content: &str) -> Result<Value> {
+        match self {
+            ConfigFormat::Toml => content
+                .parse::<Value>()
+                .map_err(|err| Diagnostics::parse_error(file, content, &err).into()),
+            ConfigFormat::Yaml => parse_yaml(file, content),
+            ConfigFormat::Json => parse_json(file, content),
+        }
+    }
+
+    /// Locates the error converting the parsed value to the settings of
+    /// type `T`.
+    ///
+    /// The parsed value doesn't keep the locations, so the error is located
+    /// by parsing the file again. Unless it's caused by an override, which
+    /// the file doesn't have.
+    pub(crate) fn locate_error<T: DeserializeOwned>(
+        self,
+        file: &str,
+        content: &str,
+        err: toml::de::Error,
+    ) -> Diagnostics {
+        let located = match self {
+            ConfigFormat::Toml => toml::from_str::<T>(content)
+                .err()
+                .map(|err| Diagnostics::parse_error(file, content, &err)),
+            #[cfg(feature = "config-yaml")]
+            ConfigFormat::Yaml => serde_yaml::from_str::<T>(content)
+                .err()
+                .map(|err| yaml_error(file, content, &err)),
+            #[cfg(feature = "config-json")]
+            ConfigFormat::Json => serde_json::from_str::<T>(content)
+                .err()
+                .map(|err| json_error(file, content, &err)),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        };
+
+        located.unwrap_or_else(|| Diagnostics::parse_error(file, content, &err))
+    }
+
+    /// Returns whether the semantic problems can be located in the file.
+    pub(crate) fn can_locate(self) -> bool {
+        self == ConfigFormat::Toml
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "toml" => Ok(ConfigFormat::Toml),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(ConfigError::UnknownFormat(s.to_owned()).into()),
+        }
+    }
+}
+
+#[cfg(feature = "config-yaml")]
+fn parse_yaml(file: &str, content: &str) -> Result<Value> {
+    serde_yaml::from_str(content).map_err(|err| yaml_error(file, content, &err).into())
+}
+
+#[cfg(not(feature = "config-yaml"))]
+fn parse_yaml(_file: &str, _content: &str) -> Result<Value> {
+    Err(ConfigError::FormatNotEnabled("YAML", "config-yaml").into())
+}
+
+#[cfg(feature = "config-yaml")]
+fn yaml_error(file: &str, content: &str, err: &serde_yaml::Error) -> Diagnostics {
+    let location = err.location().map(|loc| (loc.line(), loc.column()));
+    Diagnostics::located_error(file, content, err.to_string(), location)
+}
+
+#[cfg(feature = "config-json")]
+fn parse_json(file: &str, content: &str) -> Result<Value> {
+    serde_json::from_str(content).map_err(|err| json_error(file, content, &err).into())
+}
+
+#[cfg(not(feature = "config-json"))]
+fn parse_json(_file: &str, _content: &str) -> Result<Value> {
+    Err(ConfigError::FormatNotEnabled("JSON", "config-json").into())
+}
+
+#[cfg(feature = "config-json")]
+fn json_error(file: &str, content: &str, err: &serde_json::Error) -> Diagnostics {
+    // the line is 0 if the error is not in the content.
+    let location = Some((err.line(), err.column())).filter(|&(line, _)| line > 0);
+    Diagnostics::located_error(file, content, err.to_string(), location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{parse_config, Override};
+
+    const TOML: &str = r#"
+app_name = "myapp"
+master_core = 0
+cores = [3]
+duration = 5
+max_frame_len = 9018
+
+[mempool]
+    capacity = 8191
+    cache_size = 256
+
+[[ports]]
+    name = "eth0"
+    device = "0000:00:01.0"
+    cores = [1, 2]
+    promiscuous = true
+    ether_types = [0x0800]
+    vlan_ids = [100]
+
+    [[ports.queues]]
+        core = 2
+        rxd = 512
+
+[[ports]]
+    name = "eth1"
+    device = "net_tap0"
+    args = "iface=tap0"
+    cores = [1]
+"#;
+
+    const YAML: &str = r#"
+app_name: myapp
+master_core: 0
+cores: [3]
+duration: 5
+max_frame_len: 9018
+mempool:
+  capacity: 8191
+  cache_size: 256
+ports:
+  - name: eth0
+    device: "0000:00:01.0"
+    cores: [1, 2]
+    promiscuous: true
+    ether_types: [2048]
+    vlan_ids: [100]
+    queues:
+      - core: 2
+        rxd: 512
+  - name: eth1
+    device: net_tap0
+    args: iface=tap0
+    cores: [1]
+"#;
+
+    const JSON: &str = r#"{
+    "app_name": "myapp",
+    "master_core": 0,
+    "cores": [3],
+    "duration": 5,
+    "max_frame_len": 9018,
+    "mempool": { "capacity": 8191, "cache_size": 256 },
+    "ports": [
+        {
+            "name": "eth0",
+            "device": "0000:00:01.0",
+            "cores": [1, 2],
+            "promiscuous": true,
+            "ether_types": [2048],
+            "vlan_ids": [100],
+            "queues": [{ "core": 2, "rxd": 512 }]
+        },
+        {
+            "name": "eth1",
+            "device": "net_tap0",
+            "args": "iface=tap0",
+            "cores": [1]
+        }
+    ]
+}"#;
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            ConfigFormat::Toml,
+            ConfigFormat::from_path("config.toml").unwrap()
+        );
+        assert_eq!(
+            ConfigFormat::Yaml,
+            ConfigFormat::from_path("/etc/myapp/config.yml").unwrap()
+        );
+        assert_eq!(
+            ConfigFormat::Yaml,
+            ConfigFormat::from_path("config.YAML").unwrap()
+        );
+        assert_eq!(
+            ConfigFormat::Json,
+            ConfigFormat::from_path("config.json").unwrap()
+        );
+        assert!(ConfigFormat::from_path("config").is_err());
+        assert!(ConfigFormat::from_path("config.ini").is_err());
+    }
+
+    #[cfg(all(feature = "config-yaml", feature = "config-json"))]
+    #[test]
+    fn same_config_in_all_formats() {
+        let toml = parse_config("config.toml", TOML, ConfigFormat::Toml, &[]).unwrap();
+        let yaml = parse_config("config.yaml", YAML, ConfigFormat::Yaml, &[]).unwrap();
+        let json = parse_config("config.json", JSON, ConfigFormat::Json, &[]).unwrap();
+
+        assert_eq!(toml, yaml);
+        assert_eq!(toml, json);
+        assert_eq!(2, toml.ports.len());
+        assert_eq!(Some(9018), toml.max_frame_len);
+    }
+
+    #[cfg(all(feature = "config-yaml", feature = "config-json"))]
+    #[test]
+    fn override_all_formats() {
+        let overrides = vec![
+            Override::from_key("ports.0.device", "0000:01:00.0").unwrap(),
+            Override::from_key("ports.1.cores", "1,2").unwrap(),
+        ];
+
+        let toml = parse_config("config.toml", TOML, ConfigFormat::Toml, &overrides).unwrap();
+        let yaml = parse_config("config.yaml", YAML, ConfigFormat::Yaml, &overrides).unwrap();
+        let json = parse_config("config.json", JSON, ConfigFormat::Json, &overrides).unwrap();
+
+        assert_eq!(toml, yaml);
+        assert_eq!(toml, json);
+        assert_eq!("0000:01:00.0", toml.ports[0].device);
+    }
+
+    #[cfg(feature = "config-yaml")]
+    #[test]
+    fn locate_yaml_errors() {
+        const CONFIG: &str = r#"
+app_name: myapp
+master_core: 0
+ports:
+  - name: eth0
+    device: "0000:00:01.0"
+    cores: [1]
+    rdx: 512
+"#;
+
+        let err = parse_config("config.yaml", CONFIG, ConfigFormat::Yaml, &[]).unwrap_err();
+        let diagnostics = err.downcast_ref::<Diagnostics>().unwrap();
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert!(diagnostic.message().contains("unknown field `rdx`"));
+        assert_eq!(8, diagnostic.span().unwrap().line);
+    }
+
+    #[cfg(feature = "config-json")]
+    #[test]
+    fn locate_json_errors() {
+        const CONFIG: &str = r#"{
+    "app_name": "myapp",
+    "master_core": 0,
+    "ports": [
+        { "name": "eth0", "cores": [1], }
+    ]
+}"#;
+
+        let err = parse_config("config.json", CONFIG, ConfigFormat::Json, &[]).unwrap_err();
+        let diagnostics = err.downcast_ref::<Diagnostics>().unwrap();
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(5, diagnostic.span().unwrap().line);
+    }
+
+    #[cfg(not(feature = "config-yaml"))]
+    #[test]
+    fn yaml_needs_feature() {
+        let err = parse_config("config.yaml", YAML, ConfigFormat::Yaml, &[]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ConfigError>(),
+            Some(ConfigError::FormatNotEnabled(_, "config-yaml"))
+        ));
+    }
+}
@@ -0,0 +1,2259 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Toml-based configuration for use with Capsule applications.
+//!
+//! # Example
+//!
+//! A configuration from our [`pktdump`] example:
+//! ```
+//! app_name = "pktdump"
+//! master_core = 0
+//! duration = 5
+//!
+//! [mempool]
+//!     capacity = 65535
+//!     cache_size = 256
+//!
+//! [[ports]]
+//!     name = "eth1"
+//!     device = "net_pcap0"
+//!     args = "rx_pcap=tcp4.pcap,tx_iface=lo"
+//!     cores = [0]
+//!
+//! [[ports]]
+//!     name = "eth2"
+//!     device = "net_pcap1"
+//!     args = "rx_pcap=tcp6.pcap,tx_iface=lo"
+//!     cores = [0]
+//! ```
+//!
+//! The same configuration built in code:
+//! ```
+//! let config = RuntimeConfig::builder()
+//!     .app_name("pktdump")
+//!     .master_core(0)
+//!     .duration(Duration::from_secs(5))
+//!     .mempool(65535, 256)
+//!     .port(
+//!         PortConfig::builder()
+//!             .name("eth1")
+//!             .device("net_pcap0")
+//!             .args("rx_pcap=tcp4.pcap,tx_iface=lo")
+//!             .core(0),
+//!     )
+//!     .port(
+//!         PortConfig::builder()
+//!             .name("eth2")
+//!             .device("net_pcap1")
+//!             .args("rx_pcap=tcp6.pcap,tx_iface=lo")
+//!             .core(0),
+//!     )
+//!     .build()?;
+//! ```
+//!
+//! # Formats
+//!
+//! The file can also be YAML or JSON, with the same settings, chosen by
+//! the extension of the file or explicitly with [`ConfigFormat`]. YAML
+//! needs the `config-yaml` feature and JSON the `config-json` feature.
+//! ```
+//! app_name: myapp
+//! master_core: 0
+//! ports:
+//!   - name: eth0
+//!     device: "0000:00:08.0"
+//!     cores: [1, 2]
+//! ```
+//!
+//! A setting that is not set is left out, because TOML doesn't have a
+//! null. Problems with the settings of YAML and JSON files are reported
+//! with their key instead of their line.
+//!
+//! # Overrides
+//!
+//! Settings of the file can be overridden without editing it, for example
+//! to bake one file into a container image and set the device and the
+//! cores of each instance. Overrides apply after the file is parsed and
+//! before the settings are validated, in the order of
+//!
+//!   1. the settings of the file,
+//!   2. the environment variables with the `CAPSULE_` prefix,
+//!   3. the key/value pairs, set with `-s` on the command line for
+//!      [`load_config`].
+//!
+//! A key is the dotted path of the setting, with the array index of a
+//! port, for example `ports.0.device`. The variable is the same path in
+//! upper case, separated by underscores, for example
+//! `CAPSULE_PORTS_0_DEVICE`. Lists are separated by commas.
+//! ```
+//! home$ CAPSULE_CORES=2,3 ./myapp -f config.toml -s ports.0.device=0000:01:00.0
+//! ```
+//!
+//! An index can append an element to an array, so a port can be added
+//! with overrides alone. A value that can't be converted to the type of
+//! the setting is reported with the variable name or key.
+//!
+//! # Application settings
+//!
+//! The settings of the application can be in the same file, in the `[app]`
+//! table. Capsule keeps the table as is, and the application deserializes
+//! it to its own type with [`RuntimeConfig::app_config`].
+//! ```
+//! [app]
+//!     policy = "allow"
+//!     vips = ["10.0.0.1", "10.0.0.2"]
+//! ```
+//!
+//! The overrides reach into the table with the `app.` prefix, for example
+//! `app.policy` or `CAPSULE_APP_POLICY`. The words of a variable are
+//! joined into the keys the table has, and a value is converted to the
+//! type of the value it overrides. The settings of capsule take precedence,
+//! so `CAPSULE_APP_NAME` is always the app name.
+//!
+//! # Diagnostics
+//!
+//! The settings are checked for all the problems at once, instead of
+//! stopping at the first. Each problem is reported with the line of the
+//! file that has the setting.
+//! ```
+//! error: Port eth0 rx_burst '1024' is not between 1 and 512.
+//!   --> config.toml:18:5
+//!    |
+//! 18 |     rx_burst = 1024
+//!    |     ^^^^^^^^^^^^^^^
+//! ```
+//!
+//! Settings that work but are likely a mistake, such as a mempool capacity
+//! that is not a power of two minus one, are logged as warnings.
+//!
+//! [`pktdump`]: https://github.com/capsule-rs/capsule/tree/master/examples/pktdump
+
+mod app;
+mod diagnostics;
+mod format;
+mod overrides;
+mod reload;
+
+pub(crate) use self::app::*;
+pub use self::diagnostics::*;
+pub use self::format::*;
+pub use self::overrides::*;
+pub use self::reload::*;
+
+use crate::dpdk::{
+    BondMode, CoreId, LacpTimers, RxBurstMode, RxOffload, TxOffload, TxPolicy, XmitHashPolicy,
+    RX_BURST_MAX, RX_BURST_MIN, VLAN_ID_MAX,
+};
+use crate::net::{Ipv4Cidr, Ipv6Cidr, MacAddr};
+use crate::packets::ParseOptions;
+use anyhow::Result;
+use clap::{clap_app, crate_version};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Config errors.
+#[derive(Debug, Error)]
+pub(crate) enum ConfigError {
+    /// The app name is empty.
+    #[error("App name is empty.")]
+    EmptyAppName,
+
+    /// A port doesn't have a name.
+    #[error("Port name is empty.")]
+    EmptyPortName,
+
+    /// More than one port has the same name.
+    #[error("Port {0} is configured more than once.")]
+    DuplicatePort(String),
+
+    /// The port has neither a device name nor a typed virtual device.
+    #[error("Port {0} does not have a device.")]
+    MissingDevice(String),
+
+    /// The port doesn't have any cores.
+    #[error("Port {0} is not assigned any cores.")]
+    NoCores(String),
+
+    /// The queue settings are for a core the port doesn't have.
+    #[error("{1:?} of the queue settings is not assigned to port {0}.")]
+    CoreNotAssigned(String, CoreId),
+
+    /// The variable or key of an override is not one of the settings.
+    #[error("{0} is not a setting.")]
+    UnknownSetting(String),
+
+    /// The command line override isn't a key/value pair.
+    #[error("Override '{0}' is not a key=value pair.")]
+    NotKeyValue(String),
+
+    /// The value of an override can't be converted to the setting's type.
+    #[error("{0} is not {1}: '{2}'.")]
+    InvalidOverride(String, &'static str, String),
+
+    /// The array index of an override skips past the end of the array.
+    #[error("{0} index '{1}' is past the end of the array of length '{2}'.")]
+    IndexOutOfRange(String, usize, usize),
+
+    /// The override sets a setting under a value of the file that is not a
+    /// table or an array.
+    #[error("{0} conflicts with the settings of the file.")]
+    Conflict(String),
+
+    /// The mempool capacity is not a power of two minus one.
+    #[error("Mempool capacity '{0}' is not a power of two minus one, which wastes memory.")]
+    CapacityNotOptimal(usize),
+
+    /// The same core is assigned to a port more than once.
+    #[error("{1:?} is assigned to port {0} more than once.")]
+    DuplicateCore(String, CoreId),
+
+    /// More than one queue setting is for the same core.
+    #[error("{1:?} has more than one queue settings for port {0}.")]
+    DuplicateQueue(String, CoreId),
+
+    /// The queue capacity is not one a device can have.
+    #[error("Port {0} {1} '{2}' is not between 1 and {}.", u16::MAX)]
+    InvalidDescriptors(String, &'static str, usize),
+
+    /// The receive burst size is out of range.
+    #[error(
+        "Port {0} rx_burst '{1}' is not between {} and {}.",
+        RX_BURST_MIN,
+        RX_BURST_MAX
+    )]
+    InvalidRxBurst(String, usize),
+
+    /// The VLAN id is out of range.
+    #[error("Port {0} VLAN id '{1}' is not between 0 and {}.", VLAN_ID_MAX)]
+    InvalidVlanId(String, u16),
+
+    /// The new settings change the ones that need a restart.
+    #[error("Settings {0} can't be changed without a restart.")]
+    NotReloadable(String),
+
+    /// The format of the config file is not known.
+    #[error("Config format '{0}' is not toml, yaml or json.")]
+    UnknownFormat(String),
+
+    /// The config file format is supported behind a cargo feature that is
+    /// not enabled.
+    #[cfg(not(all(feature = "config-json", feature = "config-yaml")))]
+    #[error("{0} config files need the '{1}' feature.")]
+    FormatNotEnabled(&'static str, &'static str),
+}
+
+impl ConfigError {
+    /// Returns whether the settings can still be used with the problem.
+    fn severity(&self) -> Severity {
+        match self {
+            ConfigError::CapacityNotOptimal(_) => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+// make `CoreId` serde deserializable.
+impl<'de> Deserialize<'de> for CoreId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let i = usize::deserialize(deserializer)?;
+        Ok(CoreId::new(i))
+    }
+}
+
+// make `MacAddr` serde deserializable.
+impl<'de> Deserialize<'de> for MacAddr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        MacAddr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+// make `Ipv4Cidr` serde deserializable.
+impl<'de> Deserialize<'de> for Ipv4Cidr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ipv4Cidr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+// make `Ipv6Cidr` serde deserializable.
+impl<'de> Deserialize<'de> for Ipv6Cidr {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Ipv6Cidr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+/// Deserializes a duration from seconds expressed as `u64`.
+pub fn duration_from_secs<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let secs = u64::deserialize(deserializer)?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Deserializes an option of duration from seconds expressed as `u64`.
+pub fn duration_option_from_secs<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // for now this is the cleanest way to deserialize an option, till a better
+    // way is implemented, https://github.com/serde-rs/serde/issues/723
+    #[derive(Deserialize)]
+    struct Wrapper(#[serde(deserialize_with = "duration_from_secs")] Duration);
+
+    let option = Option::deserialize(deserializer)?.and_then(|Wrapper(dur)| {
+        if dur.as_secs() > 0 {
+            Some(dur)
+        } else {
+            None
+        }
+    });
+    Ok(option)
+}
+
+/// Runtime configuration settings.
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// Application name. This must be unique if you want to run multiple
+    /// DPDK applications on the same system.
+    pub app_name: String,
+
+    /// Indicating whether the process is a secondary process. Secondary
+    /// process cannot initialize shared memory, but can attach to pre-
+    /// initialized shared memory by the primary process and create objects
+    /// in it. Defaults to `false`.
+    #[serde(default)]
+    pub secondary: bool,
+
+    /// Application group name. Use this to group primary and secondary
+    /// processes together in a multi-process setup; and allow them to share
+    /// the same memory regions. The default value is the `app_name`. Each
+    /// process works independently.
+    #[serde(default)]
+    pub app_group: Option<String>,
+
+    /// The identifier of the master core. This is the core the main thread
+    /// will run on.
+    pub master_core: CoreId,
+
+    /// Additional cores that are available to the application, and can be
+    /// used for running general tasks. Packet pipelines cannot be run on
+    /// these cores unless the core is also assigned to a port separately.
+    /// Defaults to empty list.
+    #[serde(default)]
+    pub cores: Vec<CoreId>,
+
+    /// Per mempool settings. On a system with multiple sockets, aka NUMA
+    /// nodes, one mempool will be allocated for each socket the apllication
+    /// uses.
+    #[serde(default)]
+    pub mempool: MempoolConfig,
+
+    /// The ports to use for the application. Must have at least one.
+    pub ports: Vec<PortConfig>,
+
+    /// Additional DPDK [`parameters`] to pass on for EAL initialization. When
+    /// set, the values are passed through as is without validation.
+    ///
+    /// [`parameters`]: https://doc.dpdk.org/guides/linux_gsg/linux_eal_parameters.html
+    #[serde(default)]
+    pub dpdk_args: Option<String>,
+
+    /// If set, the application will stop after the duration expires. Useful
+    /// for setting a timeout for integration tests.
+    #[serde(default, deserialize_with = "duration_option_from_secs")]
+    pub duration: Option<Duration>,
+
+    /// If set, frames longer than the maximum fail to parse as Ethernet
+    /// on every core, before any further processing. A robustness knob
+    /// for internet-facing applications. Defaults to unlimited.
+    #[serde(default)]
+    pub max_frame_len: Option<usize>,
+
+    /// The settings of the application, in the `[app]` table. Capsule
+    /// doesn't read them, see [`app_config`].
+    ///
+    /// [`app_config`]: RuntimeConfig::app_config
+    #[serde(default)]
+    pub(crate) app: Option<toml::Value>,
+
+    /// The file the settings are loaded from, to locate the problems with
+    /// the settings of the application.
+    #[serde(skip)]
+    pub(crate) source: Option<ConfigSource>,
+}
+
+impl RuntimeConfig {
+    /// Returns a builder for the runtime settings, an alternative to the
+    /// TOML file for applications that have their own configuration.
+    pub fn builder() -> RuntimeConfigBuilder {
+        RuntimeConfigBuilder::default()
+    }
+
+    /// Loads the settings from a file, and overrides them with the
+    /// environment variables, and then with the key/value pairs. See the
+    /// [module documentation] for the keys and the variable names.
+    ///
+    /// The format of the file is chosen by its extension, see
+    /// [`ConfigFormat::from_path`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or parsed, an override
+    /// is not a setting or its value has the wrong type, or the settings
+    /// are invalid.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let config = RuntimeConfig::from_sources(
+    ///     "config.toml",
+    ///     std::env::vars(),
+    ///     &[("ports.0.device", "0000:01:00.0")],
+    /// )?;
+    /// ```
+    ///
+    /// [module documentation]: crate::config
+    pub fn from_sources<P, E>(file: P, env: E, overrides: &[(&str, &str)]) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        E: IntoIterator<Item = (String, String)>,
+    {
+        let format = ConfigFormat::from_path(&file)?;
+        RuntimeConfig::from_sources_with_format(file, format, env, overrides)
+    }
+
+    /// Loads the settings from a file of the format, regardless of its
+    /// extension. Otherwise the same as [`from_sources`].
+    ///
+    /// [`from_sources`]: RuntimeConfig::from_sources
+    pub fn from_sources_with_format<P, E>(
+        file: P,
+        format: ConfigFormat,
+        env: E,
+        overrides: &[(&str, &str)],
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        E: IntoIterator<Item = (String, String)>,
+    {
+        let content = fs::read_to_string(&file)?;
+
+        let mut all = env_overrides(env)?;
+        for (key, value) in overrides.iter() {
+            all.push(Override::from_key(key, value)?);
+        }
+
+        parse_config(&file.as_ref().display().to_string(), &content, format, &all)
+    }
+
+    /// Validates the settings. The TOML file and the builder both go
+    /// through the same checks.
+    ///
+    /// Fails with all the problems found, and logs the warnings if there
+    /// are only warnings.
+    pub(crate) fn validate(&self) -> Result<()> {
+        self.check().into_result()
+    }
+
+    /// Checks the settings, and returns all the problems found.
+    pub(crate) fn check(&self) -> Diagnostics {
+        let mut diagnostics = Diagnostics::default();
+
+        if self.app_name.is_empty() {
+            diagnostics.push("app_name".to_owned(), ConfigError::EmptyAppName);
+        }
+
+        if !(self.mempool.capacity + 1).is_power_of_two() {
+            diagnostics.push(
+                "mempool.capacity".to_owned(),
+                ConfigError::CapacityNotOptimal(self.mempool.capacity),
+            );
+        }
+
+        let mut names = HashSet::new();
+        for (i, port) in self.ports.iter().enumerate() {
+            let key = format!("ports.{}", i);
+            port.check(&key, &mut diagnostics);
+            if !port.name.is_empty() && !names.insert(&port.name) {
+                diagnostics.push(
+                    format!("{}.name", key),
+                    ConfigError::DuplicatePort(port.name.clone()),
+                );
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Returns all the cores assigned to the runtime.
+    pub(crate) fn all_cores(&self) -> Vec<CoreId> {
+        let mut cores = vec![];
+        cores.push(self.master_core);
+        cores.extend(self.cores.iter());
+
+        self.ports.iter().for_each(|port| {
+            cores.extend(port.cores.iter());
+        });
+
+        cores.sort();
+        cores.dedup();
+        cores
+    }
+
+    /// Extracts the EAL arguments from runtime settings.
+    pub(crate) fn to_eal_args(&self) -> Vec<String> {
+        let mut eal_args = vec![];
+
+        // adds the app name
+        eal_args.push(self.app_name.clone());
+
+        let proc_type = if self.secondary {
+            "secondary".to_owned()
+        } else {
+            "primary".to_owned()
+        };
+
+        // adds the proc type
+        eal_args.push("--proc-type".to_owned());
+        eal_args.push(proc_type);
+
+        // adds the mem file prefix
+        let prefix = self.app_group.as_ref().unwrap_or(&self.app_name);
+        eal_args.push("--file-prefix".to_owned());
+        eal_args.push(prefix.clone());
+
+        // adds all the ports. bonded devices are created by the runtime
+        // after the EAL probed their slaves.
+        self.ports.iter().for_each(|port| {
+            if let Some(bond) = &port.bond {
+                bond.slaves.iter().for_each(|slave| {
+                    if is_pci_device(slave) {
+                        eal_args.push("--pci-whitelist".to_owned());
+                    } else {
+                        eal_args.push("--vdev".to_owned());
+                    }
+                    eal_args.push(slave.clone());
+                });
+            } else if port.is_pci() {
+                eal_args.push("--pci-whitelist".to_owned());
+                eal_args.push(port.device.clone());
+            } else {
+                eal_args.push("--vdev".to_owned());
+                eal_args.push(port.devargs());
+            }
+        });
+
+        // adds the master core
+        eal_args.push("--master-lcore".to_owned());
+        eal_args.push(self.master_core.raw().to_string());
+
+        // limits the EAL to only the master core. actual threads are
+        // managed by the runtime not the EAL.
+        eal_args.push("-l".to_owned());
+        eal_args.push(self.master_core.raw().to_string());
+
+        // adds additional DPDK args
+        if let Some(args) = &self.dpdk_args {
+            eal_args.extend(args.split_ascii_whitespace().map(str::to_owned));
+        }
+
+        eal_args
+    }
+
+    /// Returns the parse options of the cores.
+    pub(crate) fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            max_frame_len: self.max_frame_len,
+        }
+    }
+
+    /// Returns the number of KNI enabled ports
+    pub(crate) fn num_knis(&self) -> usize {
+        self.ports.iter().filter(|p| p.kni).count()
+    }
+}
+
+impl fmt::Debug for RuntimeConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("runtime");
+        d.field("app_name", &self.app_name)
+            .field("secondary", &self.secondary)
+            .field(
+                "app_group",
+                self.app_group.as_ref().unwrap_or(&self.app_name),
+            )
+            .field("master_core", &self.master_core)
+            .field("cores", &self.cores)
+            .field("mempool", &self.mempool)
+            .field("ports", &self.ports);
+        if let Some(dpdk_args) = &self.dpdk_args {
+            d.field("dpdk_args", dpdk_args);
+        }
+        if let Some(duration) = &self.duration {
+            d.field("duration", duration);
+        }
+        if let Some(max_frame_len) = &self.max_frame_len {
+            d.field("max_frame_len", max_frame_len);
+        }
+        if let Some(app) = &self.app {
+            d.field("app", app);
+        }
+        d.finish()
+    }
+}
+
+/// Mempool configuration settings.
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MempoolConfig {
+    /// The maximum number of Mbufs the mempool can allocate. The optimum
+    /// size (in terms of memory usage) is when n is a power of two minus
+    /// one. Defaults to `65535` or `2 ^ 16 - 1`.
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+
+    /// The size of the per core object cache. If cache_size is non-zero,
+    /// the library will try to limit the accesses to the common lockless
+    /// pool. The cache can be disabled if the argument is set to 0. Defaults
+    /// to `0`.
+    #[serde(default = "default_cache_size")]
+    pub cache_size: usize,
+}
+
+fn default_capacity() -> usize {
+    65535
+}
+
+fn default_cache_size() -> usize {
+    0
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        MempoolConfig {
+            capacity: default_capacity(),
+            cache_size: default_cache_size(),
+        }
+    }
+}
+
+impl fmt::Debug for MempoolConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("mempool")
+            .field("capacity", &self.capacity)
+            .field("cache_size", &self.cache_size)
+            .finish()
+    }
+}
+
+/// Port configuration settings.
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PortConfig {
+    /// The application assigned logical name of the port.
+    ///
+    /// For applications with more than one port, this name can be used to
+    /// identifer the port.
+    pub name: String,
+
+    /// The device name of the port. It can be the following formats,
+    ///
+    ///   * PCIe address, for example `0000:02:00.0`
+    ///   * DPDK virtual device, for example `net_[pcap0|null0|tap0]`
+    ///
+    /// Required unless the port is a typed `vdev`, in which case it
+    /// defaults to the driver name followed by the port name, for example
+    /// `net_tap_eth1`.
+    #[serde(default)]
+    pub device: String,
+
+    /// Additional arguments to configure a virtual device.
+    #[serde(default)]
+    pub args: Option<String>,
+
+    /// If set, the port is a virtual device declared with typed settings
+    /// instead of a device name and raw arguments. `args` can still add
+    /// the arguments not covered by the settings. Defaults to not set.
+    #[serde(default)]
+    pub vdev: Option<VdevConfig>,
+
+    /// The cores assigned to the port for running the pipelines. The values
+    /// can overlap with the runtime cores. Each core services one receive
+    /// and transmit queue pair, and the position of the core in the list
+    /// is the index of its queues.
+    pub cores: Vec<CoreId>,
+
+    /// Whether the cores can be on a different NUMA socket than the port.
+    /// Crossing sockets slows down every packet, so the port fails to
+    /// start unless allowed. Defaults to `false`.
+    #[serde(default)]
+    pub cross_socket: bool,
+
+    /// Settings that override the port's for the queues of some cores.
+    #[serde(default)]
+    pub queues: Vec<QueueConfig>,
+
+    /// The receive queue capacity. Defaults to `128`.
+    #[serde(default = "default_port_rxd")]
+    pub rxd: usize,
+
+    /// The transmit queue capacity. Defaults to `128`.
+    #[serde(default = "default_port_txd")]
+    pub txd: usize,
+
+    /// Whether promiscuous mode is enabled for this port. Defaults to `false`.
+    #[serde(default)]
+    pub promiscuous: bool,
+
+    /// Whether multicast packet reception is enabled for this port. Defaults
+    /// to `true`.
+    #[serde(default = "default_multicast_mode")]
+    pub multicast: bool,
+
+    /// Whether kernel NIC interface is enabled for this port. with KNI, this
+    /// port can exchange packets with the kernel networking stack. Defaults
+    /// to `false`.
+    #[serde(default)]
+    pub kni: bool,
+
+    /// The policy for packets that don't fit into a full transmit queue.
+    /// Defaults to `drop_newest`.
+    #[serde(default)]
+    pub tx_policy: TxPolicy,
+
+    /// The maximum number of packets each transmit queue holds on to for
+    /// retry. Not used with the `drop_newest` policy. Defaults to `256`.
+    #[serde(default = "default_port_tx_backlog")]
+    pub tx_backlog: usize,
+
+    /// The number of packets each receive queue is polled for at a time,
+    /// between `1` and `512`. Smaller bursts favor latency, and larger
+    /// bursts favor throughput. Defaults to `32`.
+    #[serde(default = "default_port_rx_burst")]
+    pub rx_burst: usize,
+
+    /// How the receive burst size adapts to the traffic. With `adaptive`,
+    /// `rx_burst` is the largest burst. Defaults to `fixed`.
+    #[serde(default)]
+    pub rx_burst_mode: RxBurstMode,
+
+    /// The MTU of the port, up to `2022` so the frames fit in a single
+    /// mbuf. Defaults to the device default, usually `1500`.
+    #[serde(default)]
+    pub mtu: Option<u16>,
+
+    /// The receive offloads the device performs, for example
+    /// `["ipv4_cksum", "tcp_cksum"]`. The port fails to build if the
+    /// device doesn't have one of them. Defaults to none.
+    ///
+    /// With `tcp_lro`, the device coalesces TCP segments into packets of
+    /// multiple mbuf segments. Use the `gro` combinator instead on ports
+    /// whose device can't.
+    #[serde(default)]
+    pub rx_offloads: Vec<RxOffload>,
+
+    /// The transmit offloads the device performs, for example
+    /// `["multi_segs"]`. The port fails to build if the device doesn't
+    /// have one of them. Defaults to none.
+    #[serde(default)]
+    pub tx_offloads: Vec<TxOffload>,
+
+    /// The EtherTypes of the packets the port receives, for example
+    /// `[0x0800, 0x86dd]`. The EtherType of a VLAN tagged packet is the
+    /// one after the tags. The other packets are dropped in software
+    /// before they reach the pipelines. Defaults to all.
+    #[serde(default)]
+    pub ether_types: Vec<u16>,
+
+    /// The VLAN ids of the tagged packets the port receives. Untagged
+    /// packets are always received. The device filters the ids if it has
+    /// the `vlan_filter` receive offload, and the port filters them in
+    /// software otherwise. Defaults to all.
+    #[serde(default)]
+    pub vlan_ids: Vec<u16>,
+
+    /// If set, the port waits up to the number of seconds for its link to
+    /// come up when it's started, and fails to start otherwise. Keeps the
+    /// pipelines from starting on a dead link. Defaults to not waiting.
+    #[serde(default, deserialize_with = "duration_option_from_secs")]
+    pub link_timeout: Option<Duration>,
+
+    /// If set, the port is a bonded device aggregating the slave devices.
+    /// The device name must then start with `net_bonding`. Defaults to
+    /// not bonded.
+    #[serde(default)]
+    pub bond: Option<BondConfig>,
+}
+
+/// Bonded port configuration settings.
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BondConfig {
+    /// How the bonded port uses its slaves.
+    pub mode: BondMode,
+
+    /// The slave devices, either PCIe addresses or DPDK virtual devices.
+    /// Virtual devices can have additional arguments after the device
+    /// name, for example `net_pcap0,iface=eth1`. The slaves can't be
+    /// ports of their own.
+    pub slaves: Vec<String>,
+
+    /// The device name of the primary slave. Defaults to the first slave.
+    #[serde(default)]
+    pub primary: Option<String>,
+
+    /// The transmit hash policy of the `balance` and `lacp` modes.
+    /// Defaults to `l2`.
+    #[serde(default)]
+    pub xmit_policy: Option<XmitHashPolicy>,
+
+    /// The LACP timers of the `lacp` mode. Defaults to the driver's.
+    #[serde(default)]
+    pub lacp: LacpTimers,
+}
+
+impl BondConfig {
+    /// Returns the device names of the slaves, without the additional
+    /// arguments.
+    pub(crate) fn slave_devices(&self) -> Vec<String> {
+        self.slaves
+            .iter()
+            .map(|slave| slave.split(',').next().unwrap_or_default().to_owned())
+            .collect()
+    }
+}
+
+impl fmt::Debug for BondConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("bond");
+        d.field("mode", &self.mode).field("slaves", &self.slaves);
+        if let Some(primary) = &self.primary {
+            d.field("primary", primary);
+        }
+        if let Some(xmit_policy) = &self.xmit_policy {
+            d.field("xmit_policy", xmit_policy);
+        }
+        if self.lacp != LacpTimers::default() {
+            d.field("lacp", &self.lacp);
+        }
+        d.finish()
+    }
+}
+
+/// Virtual device configuration settings.
+///
+/// The `type` field selects the driver.
+///
+/// # Example
+///
+/// ```
+/// [[ports]]
+///     name = "tap0"
+///     cores = [1]
+///
+///     [ports.vdev]
+///         type = "tap"
+///         iface = "capsule0"
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VdevConfig {
+    /// A Linux `AF_PACKET` socket bound to an existing kernel interface.
+    AfPacket {
+        /// The kernel interface to bind to.
+        iface: String,
+
+        /// The number of receive and transmit queue pairs. Defaults to `1`.
+        #[serde(default)]
+        qpairs: Option<usize>,
+    },
+
+    /// A TAP interface created for the port, which exchanges packets with
+    /// the kernel networking stack.
+    Tap {
+        /// The name of the kernel interface. Defaults to `dtap<N>`.
+        #[serde(default)]
+        iface: Option<String>,
+
+        /// The MAC address of the port. Defaults to a random address.
+        #[serde(default)]
+        mac: Option<MacAddr>,
+    },
+
+    /// A device that drops every packet transmitted, and receives as many
+    /// packets as polled for.
+    Null {
+        /// The length of the received packets. Defaults to `64`.
+        #[serde(default)]
+        size: Option<usize>,
+
+        /// Whether the packets are copied on transmit and receive.
+        /// Defaults to `false`.
+        #[serde(default)]
+        copy: bool,
+    },
+
+    /// A loopback device backed by a DPDK ring. The packets transmitted
+    /// are received back.
+    Ring,
+
+    /// A device that receives the packets of a pcap file, and writes the
+    /// packets transmitted to another. The transmit file is flushed and
+    /// closed when the port stops.
+    Pcap {
+        /// The pcap file to receive the packets from. Defaults to
+        /// receiving nothing.
+        #[serde(default)]
+        rx_file: Option<String>,
+
+        /// The pcap file to write the transmitted packets to. Defaults to
+        /// dropping them.
+        #[serde(default)]
+        tx_file: Option<String>,
+
+        /// What the port does at the end of the receive file. Defaults to
+        /// `stop`.
+        #[serde(default)]
+        eof: PcapEof,
+    },
+}
+
+/// What a pcap port does at the end of its receive file.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PcapEof {
+    /// Stops receiving. Each packet of the file is received once.
+    Stop,
+
+    /// Loops back to the start of the file. The packets are read into
+    /// memory when the port starts, so the file should fit in the mempool.
+    Loop,
+}
+
+impl Default for PcapEof {
+    fn default() -> Self {
+        PcapEof::Stop
+    }
+}
+
+impl VdevConfig {
+    /// Returns the name of the driver.
+    pub(crate) fn driver(&self) -> &'static str {
+        match self {
+            VdevConfig::AfPacket { .. } => "net_af_packet",
+            VdevConfig::Tap { .. } => "net_tap",
+            VdevConfig::Null { .. } => "net_null",
+            VdevConfig::Ring => "net_ring",
+            VdevConfig::Pcap { .. } => "net_pcap",
+        }
+    }
+
+    /// Returns the device arguments for the settings.
+    pub(crate) fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+
+        match self {
+            VdevConfig::AfPacket { iface, qpairs } => {
+                args.push(format!("iface={}", iface));
+                if let Some(qpairs) = qpairs {
+                    args.push(format!("qpairs={}", qpairs));
+                }
+            }
+            VdevConfig::Tap { iface, mac } => {
+                if let Some(iface) = iface {
+                    args.push(format!("iface={}", iface));
+                }
+                if let Some(mac) = mac {
+                    args.push(format!("mac={}", mac));
+                }
+            }
+            VdevConfig::Null { size, copy } => {
+                if let Some(size) = size {
+                    args.push(format!("size={}", size));
+                }
+                if *copy {
+                    args.push("copy=1".to_owned());
+                }
+            }
+            VdevConfig::Ring => (),
+            VdevConfig::Pcap {
+                rx_file,
+                tx_file,
+                eof,
+            } => {
+                if let Some(rx_file) = rx_file {
+                    args.push(format!("rx_pcap={}", rx_file));
+                    if *eof == PcapEof::Loop {
+                        args.push("infinite_rx=1".to_owned());
+                    }
+                }
+                if let Some(tx_file) = tx_file {
+                    args.push(format!("tx_pcap={}", tx_file));
+                }
+            }
+        }
+
+        args
+    }
+}
+
+/// Queue configuration settings.
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QueueConfig {
+    /// The core servicing the queues. It must be one of the port's cores.
+    pub core: CoreId,
+
+    /// The receive queue capacity. Defaults to the port's.
+    #[serde(default)]
+    pub rxd: Option<usize>,
+
+    /// The transmit queue capacity. Defaults to the port's.
+    #[serde(default)]
+    pub txd: Option<usize>,
+}
+
+impl fmt::Debug for QueueConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("queue");
+        d.field("core", &self.core);
+        if let Some(rxd) = &self.rxd {
+            d.field("rxd", rxd);
+        }
+        if let Some(txd) = &self.txd {
+            d.field("txd", txd);
+        }
+        d.finish()
+    }
+}
+
+fn default_port_rxd() -> usize {
+    128
+}
+
+fn default_port_txd() -> usize {
+    128
+}
+
+fn default_port_tx_backlog() -> usize {
+    256
+}
+
+fn default_port_rx_burst() -> usize {
+    32
+}
+
+fn default_multicast_mode() -> bool {
+    true
+}
+
+/// Returns whether the device is a PCIe address instead of a DPDK virtual
+/// device.
+fn is_pci_device(device: &str) -> bool {
+    static PCIE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d{4}:\d{2}:\d{2}\.\d$").unwrap());
+    PCIE.is_match(device)
+}
+
+impl PortConfig {
+    /// Returns a builder for the port settings.
+    pub fn builder() -> PortConfigBuilder {
+        PortConfigBuilder::default()
+    }
+
+    /// Validates the settings that don't depend on the other ports.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let mut diagnostics = Diagnostics::default();
+        self.check("port", &mut diagnostics);
+        diagnostics.into_result()
+    }
+
+    /// Checks the settings that don't depend on the other ports, and adds
+    /// the problems found. `key` is the key of the port's table.
+    pub(crate) fn check(&self, key: &str, diagnostics: &mut Diagnostics) {
+        let name = &self.name;
+
+        if name.is_empty() {
+            diagnostics.push(format!("{}.name", key), ConfigError::EmptyPortName);
+        }
+        if self.device.is_empty() && self.vdev.is_none() {
+            diagnostics.push(
+                format!("{}.device", key),
+                ConfigError::MissingDevice(name.clone()),
+            );
+        }
+
+        if self.cores.is_empty() {
+            diagnostics.push(format!("{}.cores", key), ConfigError::NoCores(name.clone()));
+        }
+        let mut cores = HashSet::new();
+        for &core in self.cores.iter() {
+            if !cores.insert(core) {
+                diagnostics.push(
+                    format!("{}.cores", key),
+                    ConfigError::DuplicateCore(name.clone(), core),
+                );
+            }
+        }
+
+        let mut descriptors = vec![
+            (format!("{}.rxd", key), "rxd", self.rxd),
+            (format!("{}.txd", key), "txd", self.txd),
+        ];
+
+        let mut queues = HashSet::new();
+        for (i, queue) in self.queues.iter().enumerate() {
+            let queue_key = format!("{}.queues.{}", key, i);
+            if !self.cores.contains(&queue.core) {
+                diagnostics.push(
+                    format!("{}.core", queue_key),
+                    ConfigError::CoreNotAssigned(name.clone(), queue.core),
+                );
+            } else if !queues.insert(queue.core) {
+                diagnostics.push(
+                    format!("{}.core", queue_key),
+                    ConfigError::DuplicateQueue(name.clone(), queue.core),
+                );
+            }
+            if let Some(rxd) = queue.rxd {
+                descriptors.push((format!("{}.rxd", queue_key), "rxd", rxd));
+            }
+            if let Some(txd) = queue.txd {
+                descriptors.push((format!("{}.txd", queue_key), "txd", txd));
+            }
+        }
+
+        for (desc_key, field, count) in descriptors {
+            if count == 0 || count > u16::MAX as usize {
+                diagnostics.push(
+                    desc_key,
+                    ConfigError::InvalidDescriptors(name.clone(), field, count),
+                );
+            }
+        }
+
+        if !(RX_BURST_MIN..=RX_BURST_MAX).contains(&self.rx_burst) {
+            diagnostics.push(
+                format!("{}.rx_burst", key),
+                ConfigError::InvalidRxBurst(name.clone(), self.rx_burst),
+            );
+        }
+
+        if let Some(&vid) = self.vlan_ids.iter().find(|&&vid| vid > VLAN_ID_MAX) {
+            diagnostics.push(
+                format!("{}.vlan_ids", key),
+                ConfigError::InvalidVlanId(name.clone(), vid),
+            );
+        }
+    }
+
+    /// Returns the device name of the port, derived from the port name for
+    /// a typed virtual device without one.
+    pub(crate) fn device_name(&self) -> String {
+        match &self.vdev {
+            Some(vdev) if self.device.is_empty() => format!("{}_{}", vdev.driver(), self.name),
+            _ => self.device.clone(),
+        }
+    }
+
+    /// Returns the additional arguments of the device, the typed virtual
+    /// device settings followed by `args`.
+    pub(crate) fn device_args(&self) -> Option<String> {
+        let mut args = self.vdev.as_ref().map(VdevConfig::args).unwrap_or_default();
+        if let Some(extra) = &self.args {
+            args.push(extra.clone());
+        }
+
+        if args.is_empty() {
+            None
+        } else {
+            Some(args.join(","))
+        }
+    }
+
+    /// Returns whether the device is a PCIe address instead of a DPDK
+    /// virtual device.
+    pub(crate) fn is_pci(&self) -> bool {
+        self.vdev.is_none() && is_pci_device(&self.device)
+    }
+
+    /// Returns the name of the bus the device is attached to.
+    pub(crate) fn bus_name(&self) -> &'static str {
+        if self.is_pci() {
+            "pci"
+        } else {
+            "vdev"
+        }
+    }
+
+    /// Returns the device arguments, the device name followed by the
+    /// additional arguments if there are any.
+    pub(crate) fn devargs(&self) -> String {
+        if let Some(args) = self.device_args() {
+            format!("{},{}", self.device_name(), args)
+        } else {
+            self.device_name()
+        }
+    }
+}
+
+impl fmt::Debug for PortConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("port");
+        d.field("name", &self.name);
+        d.field("device", &self.device);
+        if let Some(args) = &self.args {
+            d.field("args", args);
+        }
+        if let Some(vdev) = &self.vdev {
+            d.field("vdev", vdev);
+        }
+        d.field("cores", &self.cores)
+            .field("cross_socket", &self.cross_socket)
+            .field("rxd", &self.rxd)
+            .field("txd", &self.txd)
+            .field("promiscuous", &self.promiscuous)
+            .field("multicast", &self.multicast)
+            .field("kni", &self.kni)
+            .field("tx_policy", &self.tx_policy)
+            .field("tx_backlog", &self.tx_backlog)
+            .field("rx_burst", &self.rx_burst)
+            .field("rx_burst_mode", &self.rx_burst_mode);
+        if let Some(mtu) = &self.mtu {
+            d.field("mtu", mtu);
+        }
+        if !self.rx_offloads.is_empty() {
+            d.field("rx_offloads", &self.rx_offloads);
+        }
+        if !self.tx_offloads.is_empty() {
+            d.field("tx_offloads", &self.tx_offloads);
+        }
+        if !self.ether_types.is_empty() {
+            d.field("ether_types", &self.ether_types);
+        }
+        if !self.vlan_ids.is_empty() {
+            d.field("vlan_ids", &self.vlan_ids);
+        }
+        if let Some(link_timeout) = &self.link_timeout {
+            d.field("link_timeout", link_timeout);
+        }
+        if !self.queues.is_empty() {
+            d.field("queues", &self.queues);
+        }
+        if let Some(bond) = &self.bond {
+            d.field("bond", bond);
+        }
+        d.finish()
+    }
+}
+
+/// Builds the runtime settings in code.
+///
+/// The builder is an alternative to the TOML file, for applications that
+/// have their own configuration. The settings default to the same values
+/// as the ones left out of the file.
+///
+/// # Example
+///
+/// ```
+/// let config = RuntimeConfig::builder()
+///     .app_name("myapp")
+///     .master_core(0)
+///     .port(
+///         PortConfig::builder()
+///             .name("eth1")
+///             .device("0000:01:00.0")
+///             .cores(&[1, 2]),
+///     )
+///     .build()?;
+/// ```
+#[derive(Debug)]
+pub struct RuntimeConfigBuilder {
+    config: RuntimeConfig,
+}
+
+impl Default for RuntimeConfigBuilder {
+    fn default() -> Self {
+        RuntimeConfigBuilder {
+            config: RuntimeConfig {
+                app_name: String::new(),
+                secondary: false,
+                app_group: None,
+                master_core: CoreId::new(0),
+                cores: vec![],
+                mempool: MempoolConfig::default(),
+                ports: vec![],
+                dpdk_args: None,
+                duration: None,
+                max_frame_len: None,
+                app: None,
+                source: None,
+            },
+        }
+    }
+}
+
+impl RuntimeConfigBuilder {
+    /// Sets the application name. Required.
+    pub fn app_name(mut self, app_name: &str) -> Self {
+        self.config.app_name = app_name.to_owned();
+        self
+    }
+
+    /// Sets whether the process is a secondary process.
+    pub fn secondary(mut self, secondary: bool) -> Self {
+        self.config.secondary = secondary;
+        self
+    }
+
+    /// Sets the application group name.
+    pub fn app_group(mut self, app_group: &str) -> Self {
+        self.config.app_group = Some(app_group.to_owned());
+        self
+    }
+
+    /// Sets the identifier of the master core. Defaults to `0`.
+    pub fn master_core(mut self, core: usize) -> Self {
+        self.config.master_core = CoreId::new(core);
+        self
+    }
+
+    /// Adds a core available for running general tasks.
+    pub fn core(mut self, core: usize) -> Self {
+        self.config.cores.push(CoreId::new(core));
+        self
+    }
+
+    /// Sets the per mempool settings.
+    pub fn mempool(mut self, capacity: usize, cache_size: usize) -> Self {
+        self.config.mempool = MempoolConfig {
+            capacity,
+            cache_size,
+        };
+        self
+    }
+
+    /// Adds a port.
+    pub fn port(mut self, port: PortConfigBuilder) -> Self {
+        self.config.ports.push(port.config);
+        self
+    }
+
+    /// Sets the additional DPDK parameters.
+    pub fn dpdk_args(mut self, dpdk_args: &str) -> Self {
+        self.config.dpdk_args = Some(dpdk_args.to_owned());
+        self
+    }
+
+    /// Sets the duration after which the application stops.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.config.duration = Some(duration);
+        self
+    }
+
+    /// Sets the maximum length of the frames parsed on every core.
+    pub fn max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.config.max_frame_len = Some(max_frame_len);
+        self
+    }
+
+    /// Validates and returns the settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error with all the problems found if the app name is
+    /// empty, or a port is configured more than once, or one of the ports
+    /// is invalid.
+    pub fn build(self) -> Result<RuntimeConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// Builds the port settings in code.
+///
+/// The port is validated when the runtime settings are built, or on its
+/// own with [`build`] before adding it to a running [`Runtime`].
+///
+/// [`build`]: PortConfigBuilder::build
+/// [`Runtime`]: crate::Runtime
+#[derive(Debug)]
+pub struct PortConfigBuilder {
+    config: PortConfig,
+}
+
+impl Default for PortConfigBuilder {
+    fn default() -> Self {
+        PortConfigBuilder {
+            config: PortConfig {
+                name: String::new(),
+                device: String::new(),
+                args: None,
+                vdev: None,
+                cores: vec![],
+                cross_socket: false,
+                queues: vec![],
+                rxd: default_port_rxd(),
+                txd: default_port_txd(),
+                promiscuous: false,
+                multicast: default_multicast_mode(),
+                kni: false,
+                tx_policy: TxPolicy::default(),
+                tx_backlog: default_port_tx_backlog(),
+                rx_burst: default_port_rx_burst(),
+                rx_burst_mode: RxBurstMode::default(),
+                mtu: None,
+                rx_offloads: vec![],
+                tx_offloads: vec![],
+                ether_types: vec![],
+                vlan_ids: vec![],
+                link_timeout: None,
+                bond: None,
+            },
+        }
+    }
+}
+
+impl PortConfigBuilder {
+    /// Sets the logical name of the port. Required.
+    pub fn name(mut self, name: &str) -> Self {
+        self.config.name = name.to_owned();
+        self
+    }
+
+    /// Sets the device name of the port. Required unless the port is a
+    /// typed virtual device.
+    pub fn device(mut self, device: &str) -> Self {
+        self.config.device = device.to_owned();
+        self
+    }
+
+    /// Sets the additional arguments of a virtual device.
+    pub fn args(mut self, args: &str) -> Self {
+        self.config.args = Some(args.to_owned());
+        self
+    }
+
+    /// Sets the typed virtual device settings.
+    pub fn vdev(mut self, vdev: VdevConfig) -> Self {
+        self.config.vdev = Some(vdev);
+        self
+    }
+
+    /// Assigns a core to the port. Each core services one receive and
+    /// transmit queue pair, in the order the cores are assigned. At least
+    /// one is required.
+    pub fn core(mut self, core: usize) -> Self {
+        self.config.cores.push(CoreId::new(core));
+        self
+    }
+
+    /// Assigns the cores to the port.
+    pub fn cores(mut self, cores: &[usize]) -> Self {
+        self.config
+            .cores
+            .extend(cores.iter().map(|&core| CoreId::new(core)));
+        self
+    }
+
+    /// Sets whether the cores can be on a different socket than the port.
+    pub fn cross_socket(mut self, cross_socket: bool) -> Self {
+        self.config.cross_socket = cross_socket;
+        self
+    }
+
+    /// Overrides the queue capacities for the queues of one of the port's
+    /// cores.
+    pub fn queue(mut self, core: usize, rxd: Option<usize>, txd: Option<usize>) -> Self {
+        self.config.queues.push(QueueConfig {
+            core: CoreId::new(core),
+            rxd,
+            txd,
+        });
+        self
+    }
+
+    /// Sets the receive queue capacity.
+    pub fn rxd(mut self, rxd: usize) -> Self {
+        self.config.rxd = rxd;
+        self
+    }
+
+    /// Sets the transmit queue capacity.
+    pub fn txd(mut self, txd: usize) -> Self {
+        self.config.txd = txd;
+        self
+    }
+
+    /// Sets whether promiscuous mode is enabled.
+    pub fn promiscuous(mut self, promiscuous: bool) -> Self {
+        self.config.promiscuous = promiscuous;
+        self
+    }
+
+    /// Sets whether multicast packet reception is enabled.
+    pub fn multicast(mut self, multicast: bool) -> Self {
+        self.config.multicast = multicast;
+        self
+    }
+
+    /// Sets whether kernel NIC interface is enabled.
+    pub fn kni(mut self, kni: bool) -> Self {
+        self.config.kni = kni;
+        self
+    }
+
+    /// Sets the policy for packets that don't fit into a full transmit
+    /// queue, and the number of packets each queue holds on to for retry.
+    pub fn tx_policy(mut self, tx_policy: TxPolicy, tx_backlog: usize) -> Self {
+        self.config.tx_policy = tx_policy;
+        self.config.tx_backlog = tx_backlog;
+        self
+    }
+
+    /// Sets the receive burst size and how it adapts to the traffic.
+    pub fn rx_burst(mut self, rx_burst: usize, rx_burst_mode: RxBurstMode) -> Self {
+        self.config.rx_burst = rx_burst;
+        self.config.rx_burst_mode = rx_burst_mode;
+        self
+    }
+
+    /// Sets the MTU of the port.
+    pub fn mtu(mut self, mtu: u16) -> Self {
+        self.config.mtu = Some(mtu);
+        self
+    }
+
+    /// Sets the receive and transmit offloads the device performs.
+    pub fn offloads(mut self, rx_offloads: &[RxOffload], tx_offloads: &[TxOffload]) -> Self {
+        self.config.rx_offloads = rx_offloads.to_vec();
+        self.config.tx_offloads = tx_offloads.to_vec();
+        self
+    }
+
+    /// Sets the EtherTypes and the VLAN ids of the packets the port
+    /// receives.
+    pub fn rx_filter(mut self, ether_types: &[u16], vlan_ids: &[u16]) -> Self {
+        self.config.ether_types = ether_types.to_vec();
+        self.config.vlan_ids = vlan_ids.to_vec();
+        self
+    }
+
+    /// Sets how long the port waits for its link to come up when started.
+    pub fn link_timeout(mut self, link_timeout: Duration) -> Self {
+        self.config.link_timeout = Some(link_timeout);
+        self
+    }
+
+    /// Sets the bonded port settings.
+    pub fn bond(mut self, bond: BondConfig) -> Self {
+        self.config.bond = Some(bond);
+        self
+    }
+
+    /// Validates and returns the port settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the port doesn't have a name, a device or a
+    /// core, or its queue settings are for a core it doesn't have.
+    pub fn build(self) -> Result<PortConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// Loads the app config from a file, with the overrides of the
+/// environment and the command line. The format of the file is chosen by
+/// its extension, unless set with `--format`.
+///
+/// # Example
+///
+/// ```
+/// home$ ./myapp -f config.toml -s ports.0.device=0000:01:00.0 -s ports.0.cores=1,2
+/// home$ ./myapp -f config.j2.out --format yaml
+/// ```
+pub fn load_config() -> Result<RuntimeConfig> {
+    let matches = clap_app!(capsule =>
+        (version: crate_version!())
+        (@arg file: -f --file +required +takes_value "configuration file")
+        (@arg set: -s --set +takes_value +multiple "overrides a setting, as key=value")
+        (@arg format: --format +takes_value "format of the file: toml, yaml or json")
+    )
+    .get_matches();
+
+    let path = matches.value_of("file").unwrap();
+    let mut overrides = vec![];
+    for arg in matches.values_of("set").into_iter().flatten() {
+        let mut pair = arg.splitn(2, '=');
+        match (pair.next(), pair.next()) {
+            (Some(key), Some(value)) => overrides.push((key, value)),
+            _ => return Err(ConfigError::NotKeyValue(arg.to_owned()).into()),
+        }
+    }
+
+    let format = match matches.value_of("format") {
+        Some(format) => format.parse()?,
+        None => ConfigFormat::from_path(path)?,
+    };
+
+    RuntimeConfig::from_sources_with_format(path, format, env::vars(), &overrides)
+}
+
+/// Parses the settings of a file, applies the overrides, and validates
+/// them the same way the builder does.
+///
+/// The problems are reported with their location in the file. The
+/// semantic problems of YAML and JSON files only with their key.
+pub(crate) fn parse_config(
+    file: &str,
+    content: &str,
+    format: ConfigFormat,
+    overrides: &[Override],
+) -> Result<RuntimeConfig> {
+    // all the formats are parsed to the same value, so the overrides apply
+    // to them the same way.
+    let mut value = format.parse(file, content)?;
+    for o in overrides.iter() {
+        o.apply(&mut value)?;
+    }
+
+    let mut config: RuntimeConfig = value
+        .try_into()
+        .map_err(|err| format.locate_error::<RuntimeConfig>(file, content, err))?;
+    config.source = Some(ConfigSource::new(file, content, format));
+
+    let mut diagnostics = config.check();
+    if format.can_locate() {
+        diagnostics.locate(file, content);
+    }
+    diagnostics.into_result()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the config error, or the first one of the diagnostics.
+    fn first_error(err: &anyhow::Error) -> Option<&ConfigError> {
+        err.downcast_ref::<ConfigError>().or_else(|| {
+            err.downcast_ref::<Diagnostics>()
+                .and_then(|diagnostics| diagnostics.iter().find_map(Diagnostic::error))
+        })
+    }
+
+    #[test]
+    fn config_defaults() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [2, 3]
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(false, config.secondary);
+        assert_eq!(None, config.app_group);
+        assert!(config.cores.is_empty());
+        assert_eq!(None, config.dpdk_args);
+        assert_eq!(None, config.max_frame_len);
+        assert_eq!(default_capacity(), config.mempool.capacity);
+        assert_eq!(default_cache_size(), config.mempool.cache_size);
+        assert_eq!(None, config.ports[0].args);
+        assert_eq!(default_port_rxd(), config.ports[0].rxd);
+        assert_eq!(default_port_txd(), config.ports[0].txd);
+        assert_eq!(false, config.ports[0].promiscuous);
+        assert_eq!(default_multicast_mode(), config.ports[0].multicast);
+        assert_eq!(false, config.ports[0].kni);
+        assert_eq!(TxPolicy::DropNewest, config.ports[0].tx_policy);
+        assert_eq!(default_port_tx_backlog(), config.ports[0].tx_backlog);
+        assert_eq!(default_port_rx_burst(), config.ports[0].rx_burst);
+        assert_eq!(RxBurstMode::Fixed, config.ports[0].rx_burst_mode);
+        assert_eq!(None, config.ports[0].mtu);
+        assert_eq!(None, config.ports[0].link_timeout);
+        assert_eq!(false, config.ports[0].cross_socket);
+        assert!(config.ports[0].queues.is_empty());
+    }
+
+    #[test]
+    fn config_queues() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [3, 2]
+                cross_socket = true
+
+                [[ports.queues]]
+                    core = 2
+                    rxd = 1024
+
+                [[ports.queues]]
+                    core = 3
+                    txd = 512
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+        let port = &config.ports[0];
+
+        assert_eq!(vec![CoreId::new(3), CoreId::new(2)], port.cores);
+        assert!(port.cross_socket);
+        assert_eq!(2, port.queues.len());
+        assert_eq!(CoreId::new(2), port.queues[0].core);
+        assert_eq!(Some(1024), port.queues[0].rxd);
+        assert_eq!(None, port.queues[0].txd);
+        assert_eq!(None, port.queues[1].rxd);
+        assert_eq!(Some(512), port.queues[1].txd);
+    }
+
+    #[test]
+    fn config_offloads() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth1"
+                device = "0000:00:01.0"
+                cores = [1]
+                rx_offloads = ["ipv4_cksum", "scatter"]
+                tx_offloads = ["multi_segs"]
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            vec![RxOffload::Ipv4Cksum, RxOffload::Scatter],
+            config.ports[0].rx_offloads
+        );
+        assert_eq!(vec![TxOffload::MultiSegs], config.ports[0].tx_offloads);
+
+        const UNKNOWN: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth1"
+                device = "0000:00:01.0"
+                cores = [1]
+                rx_offloads = ["lro"]
+        "#;
+
+        assert!(toml::from_str::<RuntimeConfig>(UNKNOWN).is_err());
+    }
+
+    #[test]
+    fn config_rx_filter() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth1"
+                device = "0000:00:01.0"
+                cores = [1]
+                ether_types = [0x0800, 0x86dd]
+                vlan_ids = [100, 200]
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(vec![0x0800, 0x86dd], config.ports[0].ether_types);
+        assert_eq!(vec![100, 200], config.ports[0].vlan_ids);
+    }
+
+    #[test]
+    fn config_bond() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "bond0"
+                device = "net_bonding0"
+                cores = [1]
+
+                [ports.bond]
+                    mode = "lacp"
+                    slaves = ["0000:00:01.0", "net_pcap0,iface=eth1"]
+                    primary = "net_pcap0"
+                    xmit_policy = "l34"
+
+                    [ports.bond.lacp]
+                        fast_periodic_ms = 100
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+        let bond = config.ports[0].bond.as_ref().unwrap();
+
+        assert_eq!(BondMode::Lacp, bond.mode);
+        assert_eq!(
+            vec!["0000:00:01.0".to_owned(), "net_pcap0".to_owned()],
+            bond.slave_devices()
+        );
+        assert_eq!(Some("net_pcap0".to_owned()), bond.primary);
+        assert_eq!(Some(XmitHashPolicy::L34), bond.xmit_policy);
+        assert_eq!(Some(100), bond.lacp.fast_periodic_ms);
+        assert_eq!(None, bond.lacp.long_timeout_ms);
+
+        assert_eq!(
+            &[
+                "myapp",
+                "--proc-type",
+                "primary",
+                "--file-prefix",
+                "myapp",
+                "--pci-whitelist",
+                "0000:00:01.0",
+                "--vdev",
+                "net_pcap0,iface=eth1",
+                "--master-lcore",
+                "0",
+                "-l",
+                "0",
+            ],
+            config.to_eal_args().as_slice(),
+        )
+    }
+
+    #[test]
+    fn config_vdevs() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth1"
+                cores = [1]
+
+                [ports.vdev]
+                    type = "af_packet"
+                    iface = "eth1"
+                    qpairs = 2
+
+            [[ports]]
+                name = "tap0"
+                cores = [1]
+                args = "remote=eth2"
+
+                [ports.vdev]
+                    type = "tap"
+                    iface = "capsule0"
+                    mac = "02:00:00:ff:ff:01"
+
+            [[ports]]
+                name = "null0"
+                device = "net_null7"
+                cores = [1]
+
+                [ports.vdev]
+                    type = "null"
+                    size = 128
+
+            [[ports]]
+                name = "ring0"
+                cores = [1]
+
+                [ports.vdev]
+                    type = "ring"
+
+            [[ports]]
+                name = "pcap0"
+                cores = [1]
+
+                [ports.vdev]
+                    type = "pcap"
+                    rx_file = "in.pcap"
+                    tx_file = "out.pcap"
+                    eof = "loop"
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+        let devargs = config
+            .ports
+            .iter()
+            .map(PortConfig::devargs)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "net_af_packet_eth1,iface=eth1,qpairs=2",
+                "net_tap_tap0,iface=capsule0,mac=02:00:00:ff:ff:01,remote=eth2",
+                "net_null7,size=128",
+                "net_ring_ring0",
+                "net_pcap_pcap0,rx_pcap=in.pcap,infinite_rx=1,tx_pcap=out.pcap",
+            ],
+            devargs
+        );
+        assert!(config.ports.iter().all(|port| port.bus_name() == "vdev"));
+        assert_eq!("net_ring_ring0", config.ports[3].device_name());
+        assert_eq!(None, config.ports[3].device_args());
+    }
+
+    #[test]
+    fn config_rx_burst() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [2, 3]
+                rx_burst = 8
+                rx_burst_mode = "adaptive"
+                link_timeout = 5
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(8, config.ports[0].rx_burst);
+        assert_eq!(RxBurstMode::Adaptive, config.ports[0].rx_burst_mode);
+        assert_eq!(Some(Duration::from_secs(5)), config.ports[0].link_timeout);
+    }
+
+    #[test]
+    fn config_to_eal_args() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            secondary = false
+            app_group = "mygroup"
+            master_core = 0
+            cores = [1]
+            dpdk_args = "-v --log-level eal:8"
+
+            [mempool]
+                capacity = 255
+                cache_size = 16
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [2, 3]
+                rxd = 32
+                txd = 32
+
+            [[ports]]
+                name = "eth1"
+                device = "net_pcap0"
+                args = "rx=lo,tx=lo"
+                cores = [0, 4]
+                rxd = 32
+                txd = 32
+        "#;
+
+        let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(
+            &[
+                "myapp",
+                "--proc-type",
+                "primary",
+                "--file-prefix",
+                "mygroup",
+                "--pci-whitelist",
+                "0000:00:01.0",
+                "--vdev",
+                "net_pcap0,rx=lo,tx=lo",
+                "--master-lcore",
+                "0",
+                "-l",
+                "0",
+                "-v",
+                "--log-level",
+                "eal:8"
+            ],
+            config.to_eal_args().as_slice(),
+        )
+    }
+
+    #[test]
+    fn builder_matches_toml() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+            cores = [4]
+            duration = 5
+
+            [mempool]
+                capacity = 255
+                cache_size = 16
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [2, 3]
+                rxd = 512
+
+                [[ports.queues]]
+                    core = 3
+                    txd = 1024
+
+            [[ports]]
+                name = "tap0"
+                cores = [1]
+
+                [ports.vdev]
+                    type = "tap"
+                    iface = "capsule0"
+        "#;
+
+        let built = RuntimeConfig::builder()
+            .app_name("myapp")
+            .master_core(0)
+            .core(4)
+            .duration(Duration::from_secs(5))
+            .mempool(255, 16)
+            .port(
+                PortConfig::builder()
+                    .name("eth0")
+                    .device("0000:00:01.0")
+                    .cores(&[2, 3])
+                    .rxd(512)
+                    .queue(3, None, Some(1024)),
+            )
+            .port(
+                PortConfig::builder()
+                    .name("tap0")
+                    .core(1)
+                    .vdev(VdevConfig::Tap {
+                        iface: Some("capsule0".to_owned()),
+                        mac: None,
+                    }),
+            )
+            .build()
+            .unwrap();
+        let parsed = parse_config("config.toml", CONFIG, ConfigFormat::Toml, &[]).unwrap();
+
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", built));
+        assert_eq!(parsed.to_eal_args(), built.to_eal_args());
+    }
+
+    #[test]
+    fn reject_core_not_assigned() {
+        let err = RuntimeConfig::builder()
+            .app_name("myapp")
+            .port(
+                PortConfig::builder()
+                    .name("eth0")
+                    .device("0000:00:01.0")
+                    .core(1)
+                    .queue(2, Some(1024), None),
+            )
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            first_error(&err),
+            Some(ConfigError::CoreNotAssigned(name, core))
+                if name == "eth0" && *core == CoreId::new(2)
+        ));
+
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = []
+        "#;
+
+        let err = parse_config("config.toml", CONFIG, ConfigFormat::Toml, &[]).unwrap_err();
+        assert!(matches!(
+            first_error(&err),
+            Some(ConfigError::NoCores(name)) if name == "eth0"
+        ));
+    }
+
+    #[test]
+    fn reject_duplicate_ports() {
+        let err = RuntimeConfig::builder()
+            .app_name("myapp")
+            .port(
+                PortConfig::builder()
+                    .name("eth0")
+                    .device("net_ring0")
+                    .core(1),
+            )
+            .port(
+                PortConfig::builder()
+                    .name("eth0")
+                    .device("net_ring1")
+                    .core(2),
+            )
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            first_error(&err),
+            Some(ConfigError::DuplicatePort(name)) if name == "eth0"
+        ));
+
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth0"
+                device = "net_ring0"
+                cores = [1]
+
+            [[ports]]
+                name = "eth0"
+                device = "net_ring1"
+                cores = [2]
+        "#;
+
+        let err = parse_config("config.toml", CONFIG, ConfigFormat::Toml, &[]).unwrap_err();
+        assert!(matches!(
+            first_error(&err),
+            Some(ConfigError::DuplicatePort(name)) if name == "eth0"
+        ));
+    }
+
+    #[test]
+    fn reject_port_without_device() {
+        let err = PortConfig::builder()
+            .name("eth0")
+            .core(1)
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            first_error(&err),
+            Some(ConfigError::MissingDevice(name)) if name == "eth0"
+        ));
+
+        let err = RuntimeConfig::builder().build().unwrap_err();
+        assert!(matches!(first_error(&err), Some(ConfigError::EmptyAppName)));
+    }
+
+    #[test]
+    fn override_nested_arrays() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [1]
+
+            [[ports]]
+                name = "eth1"
+                device = "0000:00:02.0"
+                cores = [2, 3]
+
+                [[ports.queues]]
+                    core = 2
+                    rxd = 256
+        "#;
+
+        let overrides = vec![
+            Override::from_env("CAPSULE_CORES", "4,5").unwrap().unwrap(),
+            Override::from_env("CAPSULE_PORTS_1_DEVICE", "0000:01:00.0")
+                .unwrap()
+                .unwrap(),
+            Override::from_env("CAPSULE_PORTS_1_QUEUES_0_RXD", "1024")
+                .unwrap()
+                .unwrap(),
+            Override::from_key("ports.1.queues.1.core", "3").unwrap(),
+            Override::from_key("ports.1.queues.1.txd", "512").unwrap(),
+            Override::from_key("ports.2.name", "eth2").unwrap(),
+            Override::from_key("ports.2.device", "net_ring0").unwrap(),
+            Override::from_key("ports.2.cores", "6").unwrap(),
+        ];
+        let config = parse_config("config.toml", CONFIG, ConfigFormat::Toml, &overrides).unwrap();
+
+        assert_eq!(vec![CoreId::new(4), CoreId::new(5)], config.cores);
+        assert_eq!("0000:00:01.0", config.ports[0].device);
+        assert_eq!("0000:01:00.0", config.ports[1].device);
+        assert_eq!(2, config.ports[1].queues.len());
+        assert_eq!(Some(1024), config.ports[1].queues[0].rxd);
+        assert_eq!(CoreId::new(3), config.ports[1].queues[1].core);
+        assert_eq!(Some(512), config.ports[1].queues[1].txd);
+        assert_eq!(3, config.ports.len());
+        assert_eq!("eth2", config.ports[2].name);
+        assert_eq!(vec![CoreId::new(6)], config.ports[2].cores);
+    }
+
+    #[test]
+    fn override_type_mismatch() {
+        const CONFIG: &str = r#"
+            app_name = "myapp"
+            master_core = 0
+
+            [[ports]]
+                name = "eth0"
+                device = "0000:00:01.0"
+                cores = [1]
+        "#;
+
+        let path = "config_override_type_mismatch.toml";
+        fs::write(path, CONFIG).unwrap();
+
+        let env = vec![("CAPSULE_PORTS_0_RXD".to_owned(), "lots".to_owned())];
+        let err = RuntimeConfig::from_sources(path, env, &[]).unwrap_err();
+        assert!(matches!(
+            first_error(&err),
+            Some(ConfigError::InvalidOverride(name, "an integer", value))
+                if name == "CAPSULE_PORTS_0_RXD" && value == "lots"
+        ));
+
+        // the key/value pairs apply after the environment.
+        let env = vec![
+            ("CAPSULE_PORTS_0_RXD".to_owned(), "512".to_owned()),
+            ("HOME".to_owned(), "/root".to_owned()),
+        ];
+        let config = RuntimeConfig::from_sources(path, env, &[("ports.0.rxd", "1024")]).unwrap();
+        assert_eq!(1024, config.ports[0].rxd);
+
+        let _ = fs::remove_file(path);
+    }
+}
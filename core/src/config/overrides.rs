@@ -0,0 +1,526 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::ConfigError;
+use anyhow::Result;
+use toml::value::{Table, Value};
+
+/// The prefix of the environment variables that override settings.
+pub const ENV_PREFIX: &str = "CAPSULE_";
+
+/// The type of a setting, which the override's value is converted to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    String,
+    Integer,
+    Boolean,
+    Strings,
+    Integers,
+    /// A setting of the `[app]` table, of the type of the value it
+    /// overrides.
+    App,
+}
+
+impl Kind {
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::String => "a string",
+            Kind::Integer => "an integer",
+            Kind::Boolean => "a boolean",
+            Kind::Strings => "a list of strings",
+            Kind::Integers => "a list of integers",
+            Kind::App => "a value",
+        }
+    }
+
+    /// Returns the type of an application setting. It's the type of the
+    /// value in the file, or the type the override's value looks like if
+    /// the file doesn't have one.
+    fn infer(existing: Option<&Value>, value: &str) -> Kind {
+        match existing {
+            Some(Value::Integer(_)) => Kind::Integer,
+            Some(Value::Boolean(_)) => Kind::Boolean,
+            Some(Value::Array(array)) => match array.first() {
+                Some(Value::Integer(_)) => Kind::Integers,
+                _ => Kind::Strings,
+            },
+            Some(_) => Kind::String,
+            None if value.trim().parse::<bool>().is_ok() => Kind::Boolean,
+            None if parse_integer(value.trim()).is_some() => Kind::Integer,
+            None => Kind::String,
+        }
+    }
+}
+
+/// The settings that can be overridden, with `N` for an array index.
+const SETTINGS: &[(&str, Kind)] = &[
+    ("app_name", Kind::String),
+    ("secondary", Kind::Boolean),
+    ("app_group", Kind::String),
+    ("master_core", Kind::Integer),
+    ("cores", Kind::Integers),
+    ("mempool.capacity", Kind::Integer),
+    ("mempool.cache_size", Kind::Integer),
+    ("dpdk_args", Kind::String),
+    ("duration", Kind::Integer),
+    ("max_frame_len", Kind::Integer),
+    ("ports.N.name", Kind::String),
+    ("ports.N.device", Kind::String),
+    ("ports.N.args", Kind::String),
+    ("ports.N.cores", Kind::Integers),
+    ("ports.N.cross_socket", Kind::Boolean),
+    ("ports.N.rxd", Kind::Integer),
+    ("ports.N.txd", Kind::Integer),
+    ("ports.N.promiscuous", Kind::Boolean),
+    ("ports.N.multicast", Kind::Boolean),
+    ("ports.N.kni", Kind::Boolean),
+    ("ports.N.tx_policy", Kind::String),
+    ("ports.N.tx_backlog", Kind::Integer),
+    ("ports.N.rx_burst", Kind::Integer),
+    ("ports.N.rx_burst_mode", Kind::String),
+    ("ports.N.mtu", Kind::Integer),
+    ("ports.N.rx_offloads", Kind::Strings),
+    ("ports.N.tx_offloads", Kind::Strings),
+    ("ports.N.ether_types", Kind::Integers),
+    ("ports.N.vlan_ids", Kind::Integers),
+    ("ports.N.link_timeout", Kind::Integer),
+    ("ports.N.queues.N.core", Kind::Integer),
+    ("ports.N.queues.N.rxd", Kind::Integer),
+    ("ports.N.queues.N.txd", Kind::Integer),
+    ("ports.N.vdev.type", Kind::String),
+    ("ports.N.vdev.iface", Kind::String),
+    ("ports.N.vdev.qpairs", Kind::Integer),
+    ("ports.N.vdev.mac", Kind::String),
+    ("ports.N.vdev.size", Kind::Integer),
+    ("ports.N.vdev.copy", Kind::Boolean),
+    ("ports.N.vdev.rx_file", Kind::String),
+    ("ports.N.vdev.tx_file", Kind::String),
+    ("ports.N.vdev.eof", Kind::String),
+    ("ports.N.bond.mode", Kind::String),
+    ("ports.N.bond.slaves", Kind::Strings),
+    ("ports.N.bond.primary", Kind::String),
+    ("ports.N.bond.xmit_policy", Kind::String),
+    ("ports.N.bond.lacp.fast_periodic_ms", Kind::Integer),
+    ("ports.N.bond.lacp.slow_periodic_ms", Kind::Integer),
+    ("ports.N.bond.lacp.short_timeout_ms", Kind::Integer),
+    ("ports.N.bond.lacp.long_timeout_ms", Kind::Integer),
+    ("ports.N.bond.lacp.aggregate_wait_ms", Kind::Integer),
+];
+
+/// A step of the path to a setting.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+    /// The rest of the path to an application setting, as the words of an
+    /// environment variable. The words are joined into keys by the tables
+    /// of the file.
+    Words(Vec<String>),
+}
+
+/// Returns whether the path is to a setting of the `[app]` table.
+fn is_app(path: &[Segment]) -> bool {
+    path.len() > 1 && path[0] == Segment::Key("app".to_owned())
+}
+
+/// Matches the path segments of a setting against the pattern of one of
+/// the settings that can be overridden.
+fn matches_pattern(pattern: &str, path: &[Segment]) -> bool {
+    let parts = pattern.split('.').collect::<Vec<_>>();
+    parts.len() == path.len()
+        && parts.iter().zip(path).all(|(part, segment)| match segment {
+            Segment::Key(key) => part == key,
+            Segment::Index(_) => *part == "N",
+            Segment::Words(_) => false,
+        })
+}
+
+/// Splits the lowercased name of an environment variable, without the
+/// prefix, into the path of the setting the pattern describes. Returns
+/// `None` if the name is not the pattern's.
+fn split_env(pattern: &str, tokens: &[&str]) -> Option<Vec<Segment>> {
+    let mut path = vec![];
+    let mut tokens = tokens.iter();
+
+    for part in pattern.split('.') {
+        if part == "N" {
+            let index = tokens.next()?.parse::<usize>().ok()?;
+            path.push(Segment::Index(index));
+        } else {
+            // the key itself can have underscores, for example `app_name`.
+            for word in part.split('_') {
+                if tokens.next()? != &word {
+                    return None;
+                }
+            }
+            path.push(Segment::Key(part.to_owned()));
+        }
+    }
+
+    if tokens.next().is_none() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// A setting of the config file overridden by an environment variable or
+/// a key/value pair.
+#[derive(Debug)]
+pub(crate) struct Override {
+    /// The variable name or the key, to report errors with.
+    source: String,
+    path: Vec<Segment>,
+    kind: Kind,
+    value: String,
+}
+
+impl Override {
+    /// Creates an override from a dotted key, for example `ports.0.device`.
+    pub(crate) fn from_key(key: &str, value: &str) -> Result<Self> {
+        let path = key
+            .split('.')
+            .map(|part| match part.parse::<usize>() {
+                Ok(index) => Segment::Index(index),
+                Err(_) => Segment::Key(part.to_owned()),
+            })
+            .collect::<Vec<_>>();
+
+        let kind = if is_app(&path) {
+            Kind::App
+        } else {
+            SETTINGS
+                .iter()
+                .find(|(pattern, _)| matches_pattern(pattern, &path))
+                .ok_or_else(|| ConfigError::UnknownSetting(key.to_owned()))?
+                .1
+        };
+
+        Ok(Override {
+            source: key.to_owned(),
+            path,
+            kind,
+            value: value.to_owned(),
+        })
+    }
+
+    /// Creates an override from an environment variable, for example
+    /// `CAPSULE_PORTS_0_DEVICE`. Returns `None` if the variable doesn't
+    /// have the prefix.
+    pub(crate) fn from_env(name: &str, value: &str) -> Result<Option<Self>> {
+        if !name.starts_with(ENV_PREFIX) {
+            return Ok(None);
+        }
+
+        let lowercase = name[ENV_PREFIX.len()..].to_lowercase();
+        let tokens = lowercase.split('_').collect::<Vec<_>>();
+
+        let (path, kind) = SETTINGS
+            .iter()
+            .find_map(|&(pattern, kind)| split_env(pattern, &tokens).map(|path| (path, kind)))
+            .or_else(|| match tokens.split_first() {
+                // the settings of capsule come first, `CAPSULE_APP_NAME` is
+                // always the app name.
+                Some((&"app", words)) if !words.is_empty() => {
+                    let words = words.iter().map(|&word| word.to_owned()).collect();
+                    let path = vec![Segment::Key("app".to_owned()), Segment::Words(words)];
+                    Some((path, Kind::App))
+                }
+                _ => None,
+            })
+            .ok_or_else(|| ConfigError::UnknownSetting(name.to_owned()))?;
+
+        Ok(Some(Override {
+            source: name.to_owned(),
+            path,
+            kind,
+            value: value.to_owned(),
+        }))
+    }
+
+    /// Converts the value to the type of the setting. Lists are separated
+    /// by commas.
+    fn coerce(&self) -> Result<Value> {
+        self.coerce_as(self.kind)
+    }
+
+    /// Converts the value to the type.
+    fn coerce_as(&self, kind: Kind) -> Result<Value> {
+        let invalid = || {
+            ConfigError::InvalidOverride(self.source.clone(), kind.describe(), self.value.clone())
+        };
+
+        let items = || {
+            self.value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+        };
+
+        let value = match kind {
+            Kind::String | Kind::App => Value::String(self.value.clone()),
+            Kind::Integer => parse_integer(self.value.trim()).ok_or_else(invalid)?,
+            Kind::Boolean => self
+                .value
+                .trim()
+                .parse::<bool>()
+                .map(Value::Boolean)
+                .map_err(|_| invalid())?,
+            Kind::Strings => Value::Array(items().map(|item| item.to_owned().into()).collect()),
+            Kind::Integers => Value::Array(
+                items()
+                    .map(parse_integer)
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(invalid)?,
+            ),
+        };
+
+        Ok(value)
+    }
+
+    /// Sets the value in the parsed config file. The tables and the array
+    /// elements on the path are added if missing. An index can append to
+    /// an array, but not skip past its end.
+    ///
+    /// An application setting is converted to the type of the value it
+    /// overrides.
+    pub(crate) fn apply(&self, root: &mut Value) -> Result<()> {
+        let path = resolve(root, &self.path);
+        let value = match self.kind {
+            Kind::App => self.coerce_as(Kind::infer(lookup(root, &path), &self.value))?,
+            _ => self.coerce()?,
+        };
+
+        let mut node = root;
+        for (i, segment) in path.iter().enumerate() {
+            let missing = match path.get(i + 1) {
+                Some(Segment::Index(_)) => Value::Array(vec![]),
+                Some(Segment::Key(_)) | Some(Segment::Words(_)) => Value::Table(Table::new()),
+                None => value.clone(),
+            };
+            node = self.child(node, segment, missing)?;
+        }
+        *node = value;
+
+        Ok(())
+    }
+
+    /// Returns the child of the node on the path, added if missing.
+    fn child<'a>(
+        &self,
+        node: &'a mut Value,
+        segment: &Segment,
+        missing: Value,
+    ) -> Result<&'a mut Value> {
+        match (node, segment) {
+            (Value::Table(table), Segment::Key(key)) => {
+                Ok(table.entry(key.clone()).or_insert(missing))
+            }
+            (Value::Array(array), &Segment::Index(index)) => {
+                if index == array.len() {
+                    array.push(missing);
+                }
+                let len = array.len();
+                array.get_mut(index).ok_or_else(|| {
+                    ConfigError::IndexOutOfRange(self.source.clone(), index, len).into()
+                })
+            }
+            _ => Err(ConfigError::Conflict(self.source.clone()).into()),
+        }
+    }
+}
+
+/// Returns the value at the path, if the file has it.
+fn lookup<'a>(root: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(root, |node, segment| match segment {
+        Segment::Key(key) => node.get(key.as_str()),
+        Segment::Index(index) => node.get(*index),
+        Segment::Words(_) => None,
+    })
+}
+
+/// Resolves the words of the path to the keys and the indices of the
+/// tables and the arrays of the file.
+fn resolve(root: &Value, path: &[Segment]) -> Vec<Segment> {
+    let mut resolved = vec![];
+    let mut node = Some(root);
+
+    for segment in path.iter() {
+        match segment {
+            Segment::Words(words) => resolved.extend(resolve_words(node, words)),
+            segment => {
+                node = node.and_then(|node| lookup(node, std::slice::from_ref(segment)));
+                resolved.push(segment.clone());
+            }
+        }
+    }
+
+    resolved
+}
+
+/// Resolves the words of an environment variable under the node. A number
+/// is an array index. Otherwise the words are joined with underscores into
+/// the longest key the table has, and the rest into one key if it has
+/// none.
+fn resolve_words(node: Option<&Value>, words: &[String]) -> Vec<Segment> {
+    let (first, rest) = match words.split_first() {
+        Some(split) => split,
+        None => return vec![],
+    };
+
+    if let Ok(index) = first.parse::<usize>() {
+        let mut path = vec![Segment::Index(index)];
+        path.extend(resolve_words(node.and_then(|node| node.get(index)), rest));
+        return path;
+    }
+
+    if let Some(Value::Table(table)) = node {
+        for len in (1..=words.len()).rev() {
+            let key = words[..len].join("_");
+            if let Some(child) = table.get(&key) {
+                let mut path = vec![Segment::Key(key)];
+                path.extend(resolve_words(Some(child), &words[len..]));
+                return path;
+            }
+        }
+    }
+
+    vec![Segment::Key(words.join("_"))]
+}
+
+/// Parses a decimal or a `0x` prefixed hexadecimal integer.
+fn parse_integer(value: &str) -> Option<Value> {
+    let integer = if value.starts_with("0x") {
+        i64::from_str_radix(&value[2..], 16).ok()?
+    } else {
+        value.parse::<i64>().ok()?
+    };
+
+    Some(Value::Integer(integer))
+}
+
+/// Returns the overrides of the environment variables with the prefix,
+/// sorted by name so they apply in the same order on every run.
+pub(crate) fn env_overrides<I>(vars: I) -> Result<Vec<Override>>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut vars = vars.into_iter().collect::<Vec<_>>();
+    vars.sort();
+
+    let mut overrides = vec![];
+    for (name, value) in vars.iter() {
+        if let Some(o) = Override::from_env(name, value)? {
+            overrides.push(o);
+        }
+    }
+
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: &str) -> Segment {
+        Segment::Key(key.to_owned())
+    }
+
+    #[test]
+    fn split_env_names() {
+        let o = Override::from_env("CAPSULE_MASTER_CORE", "1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(vec![key("master_core")], o.path);
+        assert_eq!(Kind::Integer, o.kind);
+
+        let o = Override::from_env("CAPSULE_PORTS_1_RX_BURST_MODE", "adaptive")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![key("ports"), Segment::Index(1), key("rx_burst_mode")],
+            o.path
+        );
+
+        let o = Override::from_env("CAPSULE_PORTS_0_QUEUES_2_RXD", "512")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![
+                key("ports"),
+                Segment::Index(0),
+                key("queues"),
+                Segment::Index(2),
+                key("rxd")
+            ],
+            o.path
+        );
+
+        assert!(Override::from_env("HOME", "/root").unwrap().is_none());
+
+        let err = Override::from_env("CAPSULE_PORTS_DEVICE", "0000:01:00.0").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ConfigError>(),
+            Some(ConfigError::UnknownSetting(name)) if name == "CAPSULE_PORTS_DEVICE"
+        ));
+    }
+
+    #[test]
+    fn coerce_values() {
+        let o = Override::from_key("cores", "1, 2,3").unwrap();
+        assert_eq!(
+            Value::Array(vec![1.into(), 2.into(), 3.into()]),
+            o.coerce().unwrap()
+        );
+
+        let o = Override::from_key("ports.0.ether_types", "0x0800,0x86dd").unwrap();
+        assert_eq!(
+            Value::Array(vec![0x0800.into(), 0x86dd.into()]),
+            o.coerce().unwrap()
+        );
+
+        let o = Override::from_key("ports.0.cores", "").unwrap();
+        assert_eq!(Value::Array(vec![]), o.coerce().unwrap());
+
+        let o = Override::from_key("ports.0.kni", "maybe").unwrap();
+        let err = o.coerce().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ConfigError>(),
+            Some(ConfigError::InvalidOverride(name, "a boolean", value))
+                if name == "ports.0.kni" && value == "maybe"
+        ));
+    }
+
+    #[test]
+    fn skip_past_array_end() {
+        let mut root = Value::Table(Table::new());
+        Override::from_key("ports.0.name", "eth0")
+            .unwrap()
+            .apply(&mut root)
+            .unwrap();
+
+        let err = Override::from_key("ports.2.name", "eth2")
+            .unwrap()
+            .apply(&mut root)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ConfigError>(),
+            Some(ConfigError::IndexOutOfRange(name, 2, 1)) if name == "ports.2.name"
+        ));
+    }
+}
@@ -0,0 +1,353 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{ConfigError, RuntimeConfig};
+use crate::dpdk::PortError;
+use crate::packets::ParseOptions;
+use anyhow::Result;
+
+/// The runtime settings that can change without restarting the data plane.
+///
+/// Get the current settings with [`RuntimeConfig::reloadable`], change
+/// them and apply them with [`Runtime::apply`]. Each setting documents when
+/// the cores see the change.
+///
+/// # Example
+///
+/// ```
+/// let mut reloadable = runtime.config().reloadable();
+/// reloadable.max_frame_len = Some(9018);
+/// reloadable.ports[0].promiscuous = true;
+/// runtime.apply(reloadable)?;
+/// ```
+///
+/// [`Runtime::apply`]: crate::Runtime::apply
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReloadableConfig {
+    /// The maximum length of the frames parsed on every core. See
+    /// [`RuntimeConfig::max_frame_len`].
+    ///
+    /// Per-core eventual. Each core switches to the new maximum between
+    /// two runs of its tasks, so for a short while some cores use the old
+    /// one. A batch is always parsed with one maximum.
+    pub max_frame_len: Option<usize>,
+
+    /// The settings of the ports. A port left out is not changed.
+    pub ports: Vec<ReloadablePortConfig>,
+}
+
+/// The port settings that can change without restarting the data plane.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReloadablePortConfig {
+    /// The name of the port.
+    pub name: String,
+
+    /// Whether promiscuous mode is enabled.
+    ///
+    /// Synchronized. The device changes its mode for all the queues at
+    /// once, before `apply` returns. The packets already received are
+    /// not filtered again.
+    pub promiscuous: bool,
+
+    /// Whether multicast packet reception is enabled.
+    ///
+    /// Synchronized, like `promiscuous`.
+    pub multicast: bool,
+}
+
+/// The changes to make to the running data plane, the difference between
+/// the current and the new reloadable settings.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReloadPlan {
+    /// The parse options of every core, if they change.
+    pub(crate) parse_options: Option<ParseOptions>,
+
+    /// The ports with changed modes, and the new modes.
+    pub(crate) ports: Vec<ReloadablePortConfig>,
+}
+
+impl ReloadPlan {
+    /// Returns whether nothing changes.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.parse_options.is_none() && self.ports.is_empty()
+    }
+
+    /// Applies the per-core changes to the current core. Runs on each
+    /// core, between the runs of its tasks.
+    pub(crate) fn apply_to_core(&self) {
+        if let Some(parse_options) = self.parse_options {
+            parse_options.set();
+        }
+    }
+}
+
+impl ReloadableConfig {
+    /// Returns the changes from the current to the new settings.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::NotFound` if the new settings have a port that
+    /// the current ones don't.
+    pub(crate) fn diff(&self, new: &ReloadableConfig) -> Result<ReloadPlan> {
+        let mut plan = ReloadPlan::default();
+
+        if new.max_frame_len != self.max_frame_len {
+            plan.parse_options = Some(ParseOptions {
+                max_frame_len: new.max_frame_len,
+            });
+        }
+
+        for port in new.ports.iter() {
+            let current = self
+                .ports
+                .iter()
+                .find(|p| p.name == port.name)
+                .ok_or_else(|| PortError::NotFound(port.name.clone()))?;
+            if current != port {
+                plan.ports.push(port.clone());
+            }
+        }
+
+        Ok(plan)
+    }
+}
+
+impl RuntimeConfig {
+    /// Returns the settings that can change without restarting the data
+    /// plane.
+    pub fn reloadable(&self) -> ReloadableConfig {
+        ReloadableConfig {
+            max_frame_len: self.max_frame_len,
+            ports: self
+                .ports
+                .iter()
+                .map(|port| ReloadablePortConfig {
+                    name: port.name.clone(),
+                    promiscuous: port.promiscuous,
+                    multicast: port.multicast,
+                })
+                .collect(),
+        }
+    }
+
+    /// Updates the reloadable settings.
+    pub(crate) fn set_reloadable(&mut self, reloadable: &ReloadableConfig) {
+        self.max_frame_len = reloadable.max_frame_len;
+
+        for new in reloadable.ports.iter() {
+            if let Some(port) = self.ports.iter_mut().find(|p| p.name == new.name) {
+                port.promiscuous = new.promiscuous;
+                port.multicast = new.multicast;
+            }
+        }
+    }
+
+    /// Checks that the new settings only change the reloadable ones.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::NotReloadable` with the keys of the other
+    /// settings that change. A port is matched by name, so a port added,
+    /// removed or renamed is a change of its own.
+    pub(crate) fn check_reload(&self, new: &RuntimeConfig) -> Result<()> {
+        // the reloadable settings don't count as changes.
+        let mut masked = new.clone();
+        masked.set_reloadable(&self.reloadable());
+
+        let mut changed = vec![];
+        if masked.app_name != self.app_name {
+            changed.push("app_name".to_owned());
+        }
+        if masked.secondary != self.secondary {
+            changed.push("secondary".to_owned());
+        }
+        if masked.app_group != self.app_group {
+            changed.push("app_group".to_owned());
+        }
+        if masked.master_core != self.master_core {
+            changed.push("master_core".to_owned());
+        }
+        if masked.cores != self.cores {
+            changed.push("cores".to_owned());
+        }
+        if masked.mempool != self.mempool {
+            changed.push("mempool".to_owned());
+        }
+        if masked.dpdk_args != self.dpdk_args {
+            changed.push("dpdk_args".to_owned());
+        }
+        if masked.duration != self.duration {
+            changed.push("duration".to_owned());
+        }
+        if masked.app != self.app {
+            changed.push("app".to_owned());
+        }
+
+        for (i, port) in masked.ports.iter().enumerate() {
+            match self.ports.iter().find(|p| p.name == port.name) {
+                Some(current) if current == port => (),
+                _ => changed.push(format!("ports.{}", i)),
+            }
+        }
+        if masked.ports.len() != self.ports.len() && changed.is_empty() {
+            changed.push("ports".to_owned());
+        }
+
+        if changed.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::NotReloadable(changed.join(", ")).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{Batch, Pipeline, Poll};
+    use crate::config::PortConfig;
+    use crate::packets::{Ethernet, Packet};
+    use crate::testils::byte_arrays::IPV4_UDP_PACKET;
+    use crate::Mbuf;
+    use std::sync::mpsc;
+
+    fn config() -> RuntimeConfig {
+        RuntimeConfig::builder()
+            .app_name("myapp")
+            .max_frame_len(1518)
+            .port(
+                PortConfig::builder()
+                    .name("eth0")
+                    .device("0000:00:01.0")
+                    .core(1),
+            )
+            .port(
+                PortConfig::builder()
+                    .name("eth1")
+                    .device("0000:00:02.0")
+                    .core(2)
+                    .promiscuous(true),
+            )
+            .build()
+            .unwrap()
+    }
+
+    /// Returns a frame of the length with the UDP packet's headers, in
+    /// segments of up to 1800 bytes.
+    fn frame(len: usize) -> Mbuf {
+        let mut head = IPV4_UDP_PACKET.to_vec();
+        head.resize(len.min(1800), 0);
+        let mut packet = Mbuf::from_bytes(&head).unwrap();
+        while packet.pkt_len() < len {
+            let segment = vec![0; (len - packet.pkt_len()).min(1800)];
+            packet.chain(Mbuf::from_bytes(&segment).unwrap()).unwrap();
+        }
+        packet
+    }
+
+    #[test]
+    fn diff_reloadable_settings() {
+        let current = config().reloadable();
+        assert!(current.diff(&current).unwrap().is_empty());
+
+        let mut new = current.clone();
+        new.max_frame_len = None;
+        new.ports[1].promiscuous = false;
+        // a port left out is not changed.
+        let _ = new.ports.remove(0);
+
+        let plan = current.diff(&new).unwrap();
+        assert_eq!(None, plan.parse_options.unwrap().max_frame_len);
+        assert_eq!(1, plan.ports.len());
+        assert_eq!("eth1", plan.ports[0].name);
+        assert!(!plan.ports[0].promiscuous);
+
+        new.ports.push(ReloadablePortConfig {
+            name: "eth2".to_owned(),
+            ..Default::default()
+        });
+        let err = current.diff(&new).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PortError>(),
+            Some(PortError::NotFound(name)) if name == "eth2"
+        ));
+    }
+
+    #[test]
+    fn reject_non_reloadable_changes() {
+        let current = config();
+
+        let mut new = current.clone();
+        new.max_frame_len = Some(9018);
+        new.ports[0].multicast = false;
+        assert!(current.check_reload(&new).is_ok());
+
+        new.mempool.capacity = 1023;
+        new.ports[1].rxd = 1024;
+        new.ports[0].name = "eth2".to_owned();
+        let err = current.check_reload(&new).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ConfigError>(),
+            Some(ConfigError::NotReloadable(keys)) if keys == "mempool, ports.0, ports.1"
+        ));
+
+        let mut new = current.clone();
+        let _ = new.ports.pop();
+        let err = current.check_reload(&new).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ConfigError>(),
+            Some(ConfigError::NotReloadable(keys)) if keys == "ports"
+        ));
+    }
+
+    #[capsule::test]
+    fn reload_between_batches() {
+        let mut current = config();
+        current.parse_options().set();
+
+        let (tx, rx) = mpsc::channel();
+        let (out, out_rx) = mpsc::channel();
+        let mut pipeline = Poll::new(rx)
+            .map(|packet| packet.parse::<Ethernet>())
+            .send(out);
+
+        // the jumbo frame is longer than the maximum.
+        tx.send(frame(60)).unwrap();
+        tx.send(frame(4000)).unwrap();
+        pipeline.run_once();
+        assert_eq!(1, out_rx.try_iter().count());
+
+        // packets received before the reload wait in the queue.
+        tx.send(frame(4000)).unwrap();
+        tx.send(frame(60)).unwrap();
+
+        let mut new = current.reloadable();
+        new.max_frame_len = Some(9018);
+        let plan = current.reloadable().diff(&new).unwrap();
+        plan.apply_to_core();
+        current.set_reloadable(&new);
+
+        // none are lost, and the whole batch uses the new maximum.
+        tx.send(frame(9000)).unwrap();
+        pipeline.run_once();
+        assert_eq!(3, out_rx.try_iter().count());
+        assert_eq!(Some(9018), current.max_frame_len);
+
+        ParseOptions::default().set();
+    }
+}
@@ -0,0 +1,250 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::Mbuf;
+use crate::stats::QueueCounters;
+use serde::Deserialize;
+use std::cmp;
+use std::mem;
+
+/// The policy for packets that don't fit into a full transmit queue.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxPolicy {
+    /// Drops the packets that the transmit queue can't accept. These are
+    /// the newest packets of the batch.
+    DropNewest,
+
+    /// Parks the packets that the transmit queue can't accept in a bounded
+    /// backlog. The backlog is retried first on the next transmit or poll
+    /// iteration. When the backlog overflows, the oldest packets are
+    /// dropped.
+    RetryThenDropOldest,
+
+    /// Same as `RetryThenDropOldest`, but additionally shrinks the next
+    /// receive burst by the length of the backlog. The pipeline receives
+    /// fewer new packets until the transmit queue catches up, and the
+    /// excess inbound traffic is left in the device's receive queue.
+    Backpressure,
+}
+
+impl Default for TxPolicy {
+    fn default() -> Self {
+        TxPolicy::DropNewest
+    }
+}
+
+/// The software queue for packets the transmit queue can't accept.
+pub(crate) struct TxBacklog {
+    policy: TxPolicy,
+    capacity: usize,
+    packets: Vec<Mbuf>,
+}
+
+impl TxBacklog {
+    /// Creates a new backlog that holds up to `capacity` packets.
+    pub(crate) fn new(policy: TxPolicy, capacity: usize) -> Self {
+        TxBacklog {
+            policy,
+            capacity,
+            packets: Vec::new(),
+        }
+    }
+
+    /// Returns the number of packets waiting to be transmitted.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Returns whether the backlog is empty.
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Returns the size of the next receive burst.
+    #[inline]
+    pub(crate) fn rx_burst_size(&self, max: usize) -> usize {
+        match self.policy {
+            TxPolicy::Backpressure => max.saturating_sub(self.len()),
+            _ => max,
+        }
+    }
+
+    /// Transmits the backlog followed by `packets`.
+    ///
+    /// `burst` is a closure that sends as many packets from the front of
+    /// the vec as the transmit queue accepts, removes them from the vec and
+    /// returns the number of packets sent. It's invoked repeatedly as long
+    /// as it makes progress. Whatever is left is then handled according to
+    /// the policy.
+    ///
+    /// Returns the number of packets from `packets` that are dropped. The
+    /// packets parked in the backlog are not counted as dropped.
+    pub(crate) fn transmit<F>(
+        &mut self,
+        packets: Vec<Mbuf>,
+        counters: &QueueCounters,
+        mut burst: F,
+    ) -> usize
+    where
+        F: FnMut(&mut Vec<Mbuf>) -> usize,
+    {
+        let new = packets.len();
+        // the backlog is usually empty, and the packets are sent as is.
+        let mut queue = if self.packets.is_empty() {
+            packets
+        } else {
+            let mut queue = mem::take(&mut self.packets);
+            queue.extend(packets);
+            queue
+        };
+
+        while !queue.is_empty() {
+            let sent = burst(&mut queue);
+            if sent == 0 {
+                break;
+            }
+            counters.record_transmitted(sent as u64);
+        }
+
+        if queue.is_empty() {
+            return 0;
+        }
+
+        // the new packets are at the back of the queue.
+        let left_new = cmp::min(queue.len(), new);
+        let left_old = queue.len() - left_new;
+
+        match self.policy {
+            TxPolicy::DropNewest => {
+                // tx queue is full and we can't make progress, start dropping
+                // packets to avoid potentially stuck in an endless loop.
+                counters.record_dropped(queue.len() as u64);
+                Mbuf::free_bulk(queue);
+                left_new
+            }
+            TxPolicy::RetryThenDropOldest | TxPolicy::Backpressure => {
+                let excess = queue.len().saturating_sub(self.capacity);
+                if excess > 0 {
+                    counters.record_overflowed(excess as u64);
+                    Mbuf::free_bulk(queue.drain(..excess).collect());
+                }
+                self.packets = queue;
+                excess.saturating_sub(left_old)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::IPV4_UDP_PACKET;
+
+    /// A mock transmit queue that accepts up to `k` packets.
+    fn mock_tx(k: usize, sent: &mut Vec<Mbuf>) -> impl FnMut(&mut Vec<Mbuf>) -> usize + '_ {
+        let mut budget = k;
+        move |packets| {
+            let n = cmp::min(budget, packets.len());
+            budget -= n;
+            sent.extend(packets.drain(..n));
+            n
+        }
+    }
+
+    fn new_packets(n: usize) -> Vec<Mbuf> {
+        (0..n)
+            .map(|i| {
+                let mut mbuf = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+                mbuf.write_data(0, &(i as u8)).unwrap();
+                mbuf
+            })
+            .collect()
+    }
+
+    fn tag(mbuf: &Mbuf) -> u8 {
+        unsafe { *mbuf.read_data::<u8>(0).unwrap().as_ref() }
+    }
+
+    #[capsule::test]
+    fn drop_newest() {
+        let counters = QueueCounters::default();
+        let mut backlog = TxBacklog::new(TxPolicy::DropNewest, 4);
+        let mut sent = vec![];
+
+        let dropped = backlog.transmit(new_packets(5), &counters, mock_tx(3, &mut sent));
+        assert_eq!(2, dropped);
+
+        assert_eq!(vec![0, 1, 2], sent.iter().map(tag).collect::<Vec<_>>());
+        assert!(backlog.is_empty());
+        assert_eq!(3, counters.snapshot().transmitted);
+        assert_eq!(2, counters.snapshot().dropped);
+        assert_eq!(0, counters.snapshot().overflowed);
+        assert_eq!(32, backlog.rx_burst_size(32));
+    }
+
+    #[capsule::test]
+    fn retry_then_drop_oldest() {
+        let counters = QueueCounters::default();
+        let mut backlog = TxBacklog::new(TxPolicy::RetryThenDropOldest, 4);
+        let mut sent = vec![];
+
+        // 2 sent, 3 parked.
+        let dropped = backlog.transmit(new_packets(5), &counters, mock_tx(2, &mut sent));
+        assert_eq!(0, dropped);
+        assert_eq!(3, backlog.len());
+        assert_eq!(0, counters.snapshot().dropped);
+
+        // tx is stuck. 3 + 5 parked, but only the newest 4 are kept. one of
+        // the dropped packets is from this call.
+        let dropped = backlog.transmit(new_packets(5), &counters, mock_tx(0, &mut sent));
+        assert_eq!(1, dropped);
+        assert_eq!(4, backlog.len());
+        assert_eq!(4, counters.snapshot().overflowed);
+
+        // tx recovers. the backlog goes out first.
+        sent.clear();
+        backlog.transmit(vec![], &counters, mock_tx(8, &mut sent));
+        assert!(backlog.is_empty());
+        assert_eq!(vec![1, 2, 3, 4], sent.iter().map(tag).collect::<Vec<_>>());
+        assert_eq!(6, counters.snapshot().transmitted);
+        assert_eq!(0, counters.snapshot().dropped);
+        assert_eq!(32, backlog.rx_burst_size(32));
+    }
+
+    #[capsule::test]
+    fn backpressure() {
+        let counters = QueueCounters::default();
+        let mut backlog = TxBacklog::new(TxPolicy::Backpressure, 8);
+        let mut sent = vec![];
+
+        backlog.transmit(new_packets(6), &counters, mock_tx(1, &mut sent));
+        assert_eq!(5, backlog.len());
+        assert_eq!(27, backlog.rx_burst_size(32));
+        assert_eq!(0, backlog.rx_burst_size(4));
+
+        backlog.transmit(vec![], &counters, mock_tx(5, &mut sent));
+        assert!(backlog.is_empty());
+        assert_eq!(32, backlog.rx_burst_size(32));
+        assert_eq!(6, counters.snapshot().transmitted);
+        assert_eq!(0, counters.snapshot().dropped);
+        assert_eq!(0, counters.snapshot().overflowed);
+    }
+}
@@ -0,0 +1,477 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Link aggregation with the DPDK bonding driver.
+
+use super::{DpdkError, PortId, SocketId};
+use crate::ffi::{self, AsStr, ToCString, ToResult};
+use crate::{debug, ensure, info};
+use anyhow::Result;
+use serde::Deserialize;
+use std::os::raw;
+use thiserror::Error;
+
+/// The most slaves a bonded port can have.
+const MAX_SLAVES: usize = ffi::RTE_MAX_ETHPORTS as usize;
+
+/// How a bonded port uses its slaves.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BondMode {
+    /// Transmits through the slaves in turn.
+    RoundRobin,
+
+    /// Transmits and receives through the primary slave only. Another
+    /// slave takes over when the primary's link goes down.
+    ActiveBackup,
+
+    /// Transmits each flow through one slave, picked by the transmit hash
+    /// policy.
+    Balance,
+
+    /// Transmits every packet through all the slaves.
+    Broadcast,
+
+    /// Aggregates the slaves with the link aggregation control protocol
+    /// of 802.3ad. The partner switch must run LACP too.
+    Lacp,
+
+    /// Balances the transmit load over the slaves.
+    Tlb,
+
+    /// Balances both the transmit and the receive load over the slaves.
+    Alb,
+}
+
+impl BondMode {
+    fn raw(self) -> u8 {
+        let mode = match self {
+            BondMode::RoundRobin => ffi::BONDING_MODE_ROUND_ROBIN,
+            BondMode::ActiveBackup => ffi::BONDING_MODE_ACTIVE_BACKUP,
+            BondMode::Balance => ffi::BONDING_MODE_BALANCE,
+            BondMode::Broadcast => ffi::BONDING_MODE_BROADCAST,
+            BondMode::Lacp => ffi::BONDING_MODE_8023AD,
+            BondMode::Tlb => ffi::BONDING_MODE_TLB,
+            BondMode::Alb => ffi::BONDING_MODE_ALB,
+        };
+        mode as u8
+    }
+
+    /// Returns whether the mode picks the slave with the transmit hash
+    /// policy.
+    fn hashes(self) -> bool {
+        matches!(self, BondMode::Balance | BondMode::Lacp)
+    }
+}
+
+/// The header fields hashed to pick the transmitting slave for a flow.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum XmitHashPolicy {
+    /// The MAC addresses.
+    L2,
+
+    /// The MAC and IP addresses.
+    L23,
+
+    /// The IP addresses and the TCP or UDP ports.
+    L34,
+}
+
+impl XmitHashPolicy {
+    fn raw(self) -> u8 {
+        let policy = match self {
+            XmitHashPolicy::L2 => ffi::BALANCE_XMIT_POLICY_LAYER2,
+            XmitHashPolicy::L23 => ffi::BALANCE_XMIT_POLICY_LAYER23,
+            XmitHashPolicy::L34 => ffi::BALANCE_XMIT_POLICY_LAYER34,
+        };
+        policy as u8
+    }
+}
+
+/// The LACP timers, in milliseconds. The driver defaults are kept for the
+/// timers not set.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct LacpTimers {
+    /// The LACPDU interval with a short timeout partner. Defaults to `900`.
+    #[serde(default)]
+    pub fast_periodic_ms: Option<u32>,
+
+    /// The LACPDU interval with a long timeout partner. Defaults to `29000`.
+    #[serde(default)]
+    pub slow_periodic_ms: Option<u32>,
+
+    /// How long the partner is kept without an LACPDU, with a short
+    /// timeout. Defaults to `3000`.
+    #[serde(default)]
+    pub short_timeout_ms: Option<u32>,
+
+    /// How long the partner is kept without an LACPDU, with a long
+    /// timeout. Defaults to `90000`.
+    #[serde(default)]
+    pub long_timeout_ms: Option<u32>,
+
+    /// How long the slaves wait before joining the aggregator. Defaults to
+    /// `2000`.
+    #[serde(default)]
+    pub aggregate_wait_ms: Option<u32>,
+}
+
+impl LacpTimers {
+    /// Overrides the driver's timers with the ones set.
+    fn apply(&self, conf: &mut ffi::rte_eth_bond_8023ad_conf) {
+        let mut timers = [
+            (self.fast_periodic_ms, &mut conf.fast_periodic_ms),
+            (self.slow_periodic_ms, &mut conf.slow_periodic_ms),
+            (self.short_timeout_ms, &mut conf.short_timeout_ms),
+            (self.long_timeout_ms, &mut conf.long_timeout_ms),
+            (self.aggregate_wait_ms, &mut conf.aggregate_wait_timeout_ms),
+        ];
+
+        for (timer, raw) in timers.iter_mut() {
+            if let Some(ms) = *timer {
+                **raw = ms;
+            }
+        }
+    }
+}
+
+/// Bonded port errors.
+#[derive(Debug, Error)]
+pub(crate) enum BondError {
+    /// The bonded port has no slaves.
+    #[error("Bonded port {0} has no slaves.")]
+    NoSlaves(String),
+
+    /// The primary is not one of the slaves.
+    #[error("Primary {0} is not a slave of the bonded port.")]
+    PrimaryNotSlave(String),
+
+    /// The port is not a slave of the bonded port.
+    #[error("{0:?} is not a slave of {1:?}.")]
+    NotSlave(PortId, PortId),
+
+    /// The port is not a bonded port.
+    #[error("{0:?} is not a bonded port.")]
+    NotBonded(PortId),
+}
+
+/// Creates a bonded port from slave devices the EAL already probed.
+pub(crate) struct BondBuilder {
+    name: String,
+    mode: BondMode,
+    slaves: Vec<String>,
+    primary: Option<String>,
+    xmit_policy: Option<XmitHashPolicy>,
+    lacp: LacpTimers,
+    socket_id: SocketId,
+}
+
+impl BondBuilder {
+    /// Creates a new builder for the bonded device `name`, which should
+    /// start with `net_bonding`.
+    pub(crate) fn new(name: &str, mode: BondMode) -> Self {
+        BondBuilder {
+            name: name.to_owned(),
+            mode,
+            slaves: vec![],
+            primary: None,
+            xmit_policy: None,
+            lacp: LacpTimers::default(),
+            socket_id: SocketId::ANY,
+        }
+    }
+
+    /// Sets the device names of the slaves.
+    pub(crate) fn slaves(&mut self, slaves: &[String]) -> &mut Self {
+        self.slaves = slaves.to_vec();
+        self
+    }
+
+    /// Sets the device name of the primary slave. Defaults to the first
+    /// slave.
+    pub(crate) fn primary(&mut self, primary: Option<&str>) -> &mut Self {
+        self.primary = primary.map(str::to_owned);
+        self
+    }
+
+    /// Sets the transmit hash policy. Only used by the balance and LACP
+    /// modes.
+    pub(crate) fn xmit_policy(&mut self, policy: Option<XmitHashPolicy>) -> &mut Self {
+        self.xmit_policy = policy;
+        self
+    }
+
+    /// Sets the LACP timers. Only used by the LACP mode.
+    pub(crate) fn lacp(&mut self, timers: LacpTimers) -> &mut Self {
+        self.lacp = timers;
+        self
+    }
+
+    /// Sets the socket the bonded device is allocated on.
+    pub(crate) fn socket_id(&mut self, socket_id: SocketId) -> &mut Self {
+        self.socket_id = socket_id;
+        self
+    }
+
+    /// Creates the bonded device and enslaves the slaves. The bonded
+    /// device is then configured as a regular port with `PortBuilder`.
+    ///
+    /// # Errors
+    ///
+    /// If a slave is not probed or can't be enslaved, the bonded device
+    /// is freed and `DpdkError` is returned.
+    pub(crate) fn finish(&self) -> Result<PortId> {
+        ensure!(
+            !self.slaves.is_empty(),
+            BondError::NoSlaves(self.name.clone())
+        );
+        if let Some(primary) = &self.primary {
+            ensure!(
+                self.slaves.contains(primary),
+                BondError::PrimaryNotSlave(primary.clone())
+            );
+        }
+
+        let socket_id = if self.socket_id == SocketId::ANY {
+            0
+        } else {
+            self.socket_id.raw() as u8
+        };
+        let port_id = unsafe {
+            ffi::rte_eth_bond_create(
+                self.name.clone().into_cstring().as_ptr(),
+                self.mode.raw(),
+                socket_id,
+            )
+            .into_result(DpdkError::from_errno)?
+        };
+        let port_id = PortId::new(port_id as u16);
+
+        if let Err(err) = self.setup(port_id) {
+            unsafe {
+                ffi::rte_eth_bond_free(self.name.clone().into_cstring().as_ptr());
+            }
+            return Err(err);
+        }
+
+        info!(
+            "created bonded port {} with {} slaves.",
+            self.name,
+            self.slaves.len()
+        );
+        Ok(port_id)
+    }
+
+    fn setup(&self, port_id: PortId) -> Result<()> {
+        for slave in self.slaves.iter() {
+            let slave_id = PortId::by_device(slave)?;
+            unsafe {
+                ffi::rte_eth_bond_slave_add(port_id.raw(), slave_id.raw())
+                    .into_result(DpdkError::from_errno)?;
+            }
+            debug!("enslaved {} as {:?}.", slave, slave_id);
+        }
+
+        if let Some(primary) = &self.primary {
+            port_id.set_bond_primary(PortId::by_device(primary)?)?;
+        }
+
+        if let Some(policy) = self.xmit_policy {
+            if self.mode.hashes() {
+                unsafe {
+                    ffi::rte_eth_bond_xmit_policy_set(port_id.raw(), policy.raw())
+                        .into_result(DpdkError::from_errno)?;
+                }
+            }
+        }
+
+        if self.mode == BondMode::Lacp {
+            let mut conf = ffi::rte_eth_bond_8023ad_conf::default();
+            unsafe {
+                ffi::rte_eth_bond_8023ad_conf_get(port_id.raw(), &mut conf)
+                    .into_result(DpdkError::from_errno)?;
+                self.lacp.apply(&mut conf);
+                ffi::rte_eth_bond_8023ad_setup(port_id.raw(), &mut conf)
+                    .into_result(DpdkError::from_errno)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PortId {
+    /// Returns the ID of the port of a probed device.
+    pub(crate) fn by_device(device: &str) -> Result<PortId> {
+        let mut port_id = 0u16;
+        unsafe {
+            ffi::rte_eth_dev_get_port_by_name(device.into_cstring().as_ptr(), &mut port_id)
+                .into_result(DpdkError::from_errno)?;
+        }
+        Ok(PortId::new(port_id))
+    }
+
+    /// Returns the device name of the port.
+    pub(crate) fn device_name(self) -> Result<String> {
+        let mut name = [0 as raw::c_char; ffi::RTE_ETH_NAME_MAX_LEN as usize];
+        unsafe {
+            ffi::rte_eth_dev_get_name_by_port(self.raw(), name.as_mut_ptr())
+                .into_result(DpdkError::from_errno)?;
+        }
+        Ok((name.as_ptr() as *const raw::c_char).as_str().to_owned())
+    }
+
+    /// Returns the slaves of a bonded port.
+    ///
+    /// The slaves are not registered ports, but their links can be read
+    /// with [`link`]. The runtime's link change callbacks also report the
+    /// slaves of bonded ports.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BondError::NotBonded` if the port is not a bonded port.
+    ///
+    /// [`link`]: PortId::link
+    pub fn bond_slaves(self) -> Result<Vec<PortId>> {
+        let mut slaves = [0u16; MAX_SLAVES];
+        let len = unsafe {
+            ffi::rte_eth_bond_slaves_get(self.raw(), slaves.as_mut_ptr(), MAX_SLAVES as u16)
+                .into_result(|_| BondError::NotBonded(self))?
+        };
+        Ok(slaves[..len as usize]
+            .iter()
+            .map(|&id| PortId::new(id))
+            .collect())
+    }
+
+    /// Returns the slaves of a bonded port the bonded port currently uses.
+    ///
+    /// A slave is active when its link is up. In active-backup mode, only
+    /// the primary slave passes traffic but all the slaves with a link are
+    /// active.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BondError::NotBonded` if the port is not a bonded port.
+    pub fn bond_active_slaves(self) -> Result<Vec<PortId>> {
+        let mut slaves = [0u16; MAX_SLAVES];
+        let len = unsafe {
+            ffi::rte_eth_bond_active_slaves_get(self.raw(), slaves.as_mut_ptr(), MAX_SLAVES as u16)
+                .into_result(|_| BondError::NotBonded(self))?
+        };
+        Ok(slaves[..len as usize]
+            .iter()
+            .map(|&id| PortId::new(id))
+            .collect())
+    }
+
+    /// Returns the primary slave of a bonded port.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BondError::NotBonded` if the port is not a bonded port.
+    pub fn bond_primary(self) -> Result<PortId> {
+        let primary = unsafe {
+            ffi::rte_eth_bond_primary_get(self.raw()).into_result(|_| BondError::NotBonded(self))?
+        };
+        Ok(PortId::new(primary as u16))
+    }
+
+    /// Sets the primary slave of a bonded port.
+    ///
+    /// In active-backup mode, this forces a failover to the slave if its
+    /// link is up.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BondError::NotSlave` if the port is not a slave of the
+    /// bonded port.
+    pub fn set_bond_primary(self, slave: PortId) -> Result<()> {
+        ensure!(
+            self.bond_slaves()?.contains(&slave),
+            BondError::NotSlave(slave, self)
+        );
+        unsafe {
+            ffi::rte_eth_bond_primary_set(self.raw(), slave.raw())
+                .into_result(DpdkError::from_errno)?;
+        }
+        info!("set {:?} as the primary of {:?}.", slave, self);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_lacp_timers() {
+        let mut conf = ffi::rte_eth_bond_8023ad_conf {
+            fast_periodic_ms: 900,
+            slow_periodic_ms: 29000,
+            short_timeout_ms: 3000,
+            long_timeout_ms: 90000,
+            aggregate_wait_timeout_ms: 2000,
+            ..Default::default()
+        };
+
+        let timers = LacpTimers {
+            fast_periodic_ms: Some(100),
+            long_timeout_ms: Some(60000),
+            ..Default::default()
+        };
+        timers.apply(&mut conf);
+
+        assert_eq!(100, conf.fast_periodic_ms);
+        assert_eq!(29000, conf.slow_periodic_ms);
+        assert_eq!(3000, conf.short_timeout_ms);
+        assert_eq!(60000, conf.long_timeout_ms);
+        assert_eq!(2000, conf.aggregate_wait_timeout_ms);
+    }
+
+    #[test]
+    fn bond_modes() {
+        assert_eq!(1, BondMode::ActiveBackup.raw());
+        assert_eq!(4, BondMode::Lacp.raw());
+        assert!(BondMode::Lacp.hashes());
+        assert!(!BondMode::ActiveBackup.hashes());
+        assert_eq!(2, XmitHashPolicy::L34.raw());
+    }
+
+    #[test]
+    fn primary_must_be_slave() {
+        let err = BondBuilder::new("net_bonding9", BondMode::ActiveBackup)
+            .slaves(&["net_ring8".to_owned()])
+            .primary(Some("net_ring9"))
+            .finish()
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BondError>(),
+            Some(BondError::PrimaryNotSlave(_))
+        ));
+
+        let err = BondBuilder::new("net_bonding9", BondMode::ActiveBackup)
+            .finish()
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BondError>(),
+            Some(BondError::NoSlaves(_))
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use serde::Deserialize;
+use std::cmp;
+
+/// The smallest receive burst size.
+pub(crate) const RX_BURST_MIN: usize = 1;
+
+/// The largest receive burst size.
+pub(crate) const RX_BURST_MAX: usize = 512;
+
+/// The number of consecutive empty polls before an adaptive burst shrinks.
+const SHRINK_AFTER: u32 = 16;
+
+/// The number of consecutive full polls before an adaptive burst grows.
+const GROW_AFTER: u32 = 4;
+
+/// How the receive burst size is chosen for each poll.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RxBurstMode {
+    /// Always polls for the configured number of packets.
+    Fixed,
+
+    /// Starts at the configured size. Halves the burst after consecutive
+    /// polls come back empty, and doubles it back up to the configured size
+    /// after consecutive polls come back full. Smaller bursts keep the
+    /// latency low when the traffic is light.
+    Adaptive,
+}
+
+impl Default for RxBurstMode {
+    fn default() -> Self {
+        RxBurstMode::Fixed
+    }
+}
+
+/// The receive burst size of a port queue.
+pub(crate) struct RxBurst {
+    mode: RxBurstMode,
+    max: usize,
+    size: usize,
+    empty: u32,
+    full: u32,
+}
+
+impl RxBurst {
+    /// Creates a new receive burst of `size` packets. In adaptive mode,
+    /// `size` is the largest burst.
+    pub(crate) fn new(mode: RxBurstMode, size: usize) -> Self {
+        RxBurst {
+            mode,
+            max: size,
+            size,
+            empty: 0,
+            full: 0,
+        }
+    }
+
+    /// Returns the effective burst size.
+    #[inline]
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Records the number of packets received by the last poll, and adjusts
+    /// the burst size in adaptive mode.
+    #[inline]
+    pub(crate) fn record(&mut self, received: usize) {
+        if self.mode == RxBurstMode::Fixed {
+            return;
+        }
+
+        if received == 0 {
+            self.full = 0;
+            self.empty += 1;
+            if self.empty == SHRINK_AFTER {
+                self.empty = 0;
+                self.size = cmp::max(self.size / 2, RX_BURST_MIN);
+            }
+        } else if received >= self.size {
+            self.empty = 0;
+            self.full += 1;
+            if self.full == GROW_AFTER {
+                self.full = 0;
+                self.size = cmp::min(self.size * 2, self.max);
+            }
+        } else {
+            self.empty = 0;
+            self.full = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll(burst: &mut RxBurst, received: usize, times: u32) {
+        for _ in 0..times {
+            burst.record(received);
+        }
+    }
+
+    #[test]
+    fn fixed_burst() {
+        let mut burst = RxBurst::new(RxBurstMode::Fixed, 64);
+
+        poll(&mut burst, 0, 100);
+        assert_eq!(64, burst.size());
+
+        poll(&mut burst, 64, 100);
+        assert_eq!(64, burst.size());
+    }
+
+    #[test]
+    fn shrink_when_idle() {
+        let mut burst = RxBurst::new(RxBurstMode::Adaptive, 64);
+
+        poll(&mut burst, 0, SHRINK_AFTER - 1);
+        assert_eq!(64, burst.size());
+        poll(&mut burst, 0, 1);
+        assert_eq!(32, burst.size());
+
+        // a non-empty poll restarts the count.
+        poll(&mut burst, 0, SHRINK_AFTER - 1);
+        poll(&mut burst, 3, 1);
+        poll(&mut burst, 0, SHRINK_AFTER - 1);
+        assert_eq!(32, burst.size());
+
+        // never shrinks below one.
+        poll(&mut burst, 0, SHRINK_AFTER * 10);
+        assert_eq!(RX_BURST_MIN, burst.size());
+    }
+
+    #[test]
+    fn grow_under_load() {
+        let mut burst = RxBurst::new(RxBurstMode::Adaptive, 64);
+        poll(&mut burst, 0, SHRINK_AFTER * 3);
+        assert_eq!(8, burst.size());
+
+        poll(&mut burst, 8, GROW_AFTER - 1);
+        assert_eq!(8, burst.size());
+        poll(&mut burst, 8, 1);
+        assert_eq!(16, burst.size());
+
+        // a partial poll restarts the count.
+        poll(&mut burst, 16, GROW_AFTER - 1);
+        poll(&mut burst, 5, 1);
+        poll(&mut burst, 16, GROW_AFTER - 1);
+        assert_eq!(16, burst.size());
+
+        // never grows past the configured size.
+        poll(&mut burst, 64, GROW_AFTER * 10);
+        assert_eq!(64, burst.size());
+    }
+}
@@ -0,0 +1,192 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{PortId, RssHashFields, RxOffload, TxOffload};
+use crate::ffi::{self, AsStr};
+use anyhow::Result;
+
+/// What the device of a port can do.
+///
+/// The capabilities are read from the device info when the port is built,
+/// and cached in each [`PortQueue`], so pipelines can branch on them in
+/// the fast path. For example, to compute the checksums in software only
+/// when the device can't.
+///
+/// The device info doesn't tell which `rte_flow` items and actions the
+/// device supports. Use [`PortId::validate_flow`] to check a rule instead.
+///
+/// # Example
+///
+/// ```
+/// if !q.capabilities().has_tx_offload(TxOffload::TcpCksum) {
+///     tcp.reconcile_all();
+/// }
+/// ```
+///
+/// [`PortQueue`]: crate::PortQueue
+/// [`PortId::validate_flow`]: crate::PortId::validate_flow
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PortCapabilities {
+    rx_offloads: u64,
+    tx_offloads: u64,
+
+    /// The name of the device driver.
+    pub driver: String,
+
+    /// The maximum number of receive queues.
+    pub max_rx_queues: u16,
+
+    /// The maximum number of transmit queues.
+    pub max_tx_queues: u16,
+
+    /// The header fields the device can compute the RSS hash over.
+    pub rss_hash_fields: RssHashFields,
+
+    /// The number of entries in the RSS redirection table.
+    pub reta_size: u16,
+
+    /// The length of the RSS key in bytes.
+    pub rss_key_size: u8,
+}
+
+impl PortCapabilities {
+    /// Reads the capabilities from the device info.
+    pub(crate) fn new(dev_info: &ffi::rte_eth_dev_info) -> Self {
+        let driver = if dev_info.driver_name.is_null() {
+            String::new()
+        } else {
+            dev_info.driver_name.as_str().to_owned()
+        };
+
+        PortCapabilities {
+            rx_offloads: dev_info.rx_offload_capa,
+            tx_offloads: dev_info.tx_offload_capa,
+            driver,
+            max_rx_queues: dev_info.max_rx_queues,
+            max_tx_queues: dev_info.max_tx_queues,
+            rss_hash_fields: RssHashFields::from_bits(dev_info.flow_type_rss_offloads),
+            reta_size: dev_info.reta_size,
+            rss_key_size: dev_info.hash_key_size,
+        }
+    }
+
+    /// Returns whether the device has the receive offload.
+    pub fn has_rx_offload(&self, offload: RxOffload) -> bool {
+        self.rx_offloads & offload.bits() != 0
+    }
+
+    /// Returns whether the device has the transmit offload.
+    pub fn has_tx_offload(&self, offload: TxOffload) -> bool {
+        self.tx_offloads & offload.bits() != 0
+    }
+
+    /// Returns the receive offloads the device has.
+    pub fn rx_offloads(&self) -> Vec<RxOffload> {
+        RxOffload::ALL
+            .iter()
+            .copied()
+            .filter(|&offload| self.has_rx_offload(offload))
+            .collect()
+    }
+
+    /// Returns the transmit offloads the device has.
+    pub fn tx_offloads(&self) -> Vec<TxOffload> {
+        TxOffload::ALL
+            .iter()
+            .copied()
+            .filter(|&offload| self.has_tx_offload(offload))
+            .collect()
+    }
+
+    /// Returns whether the device can spread the received packets across
+    /// queues with RSS.
+    pub fn has_rss(&self) -> bool {
+        !self.rss_hash_fields.is_empty() && self.reta_size > 0
+    }
+}
+
+impl PortId {
+    /// Returns the capabilities of the device.
+    ///
+    /// Unlike [`PortQueue::capabilities`], this reads the device info
+    /// every time.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DpdkError` if the device fails to report its info.
+    ///
+    /// [`PortQueue::capabilities`]: crate::PortQueue::capabilities
+    pub fn capabilities(self) -> Result<PortCapabilities> {
+        Ok(PortCapabilities::new(&self.dev_info()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_from_dev_info() {
+        let mut dev_info = ffi::rte_eth_dev_info::default();
+        dev_info.rx_offload_capa = (ffi::DEV_RX_OFFLOAD_VLAN_STRIP
+            | ffi::DEV_RX_OFFLOAD_TCP_CKSUM
+            | ffi::DEV_RX_OFFLOAD_TCP_LRO) as u64;
+        dev_info.tx_offload_capa =
+            (ffi::DEV_TX_OFFLOAD_TCP_CKSUM | ffi::DEV_TX_OFFLOAD_TCP_TSO) as u64;
+        dev_info.max_rx_queues = 16;
+        dev_info.max_tx_queues = 8;
+        dev_info.flow_type_rss_offloads = RssHashFields::L3_L4.bits();
+        dev_info.reta_size = 128;
+        dev_info.hash_key_size = 40;
+
+        let caps = PortCapabilities::new(&dev_info);
+
+        assert!(caps.has_rx_offload(RxOffload::TcpLro));
+        assert!(!caps.has_rx_offload(RxOffload::Timestamp));
+        assert_eq!(
+            vec![RxOffload::VlanStrip, RxOffload::TcpCksum, RxOffload::TcpLro],
+            caps.rx_offloads()
+        );
+        assert!(caps.has_tx_offload(TxOffload::TcpTso));
+        assert!(!caps.has_tx_offload(TxOffload::VlanInsert));
+        assert_eq!(
+            vec![TxOffload::TcpCksum, TxOffload::TcpTso],
+            caps.tx_offloads()
+        );
+
+        assert_eq!("", caps.driver);
+        assert_eq!(16, caps.max_rx_queues);
+        assert_eq!(8, caps.max_tx_queues);
+        assert!(caps.rss_hash_fields.contains(RssHashFields::TCP));
+        assert_eq!(40, caps.rss_key_size);
+        assert!(caps.has_rss());
+    }
+
+    #[test]
+    fn capabilities_without_rss() {
+        let mut dev_info = ffi::rte_eth_dev_info::default();
+        dev_info.max_rx_queues = 1;
+        dev_info.max_tx_queues = 1;
+
+        let caps = PortCapabilities::new(&dev_info);
+
+        assert!(caps.rx_offloads().is_empty());
+        assert!(caps.tx_offloads().is_empty());
+        assert!(!caps.has_rss());
+    }
+}
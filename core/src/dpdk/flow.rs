@@ -0,0 +1,894 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Hardware flow steering with `rte_flow`.
+
+use super::{DpdkError, PortId};
+use crate::ffi::{self, AsStr};
+use crate::net::{Cidr, Ipv4Cidr, Ipv6Cidr, MacAddr};
+use crate::packets::ip::ProtocolNumber;
+use crate::packets::EtherType;
+use crate::{ensure, info};
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::os::raw;
+use std::ptr::{self, NonNull};
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// Matches on the Ethernet header. Fields left as `None` match any value.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EthMatch {
+    /// The source MAC address.
+    pub src: Option<MacAddr>,
+
+    /// The destination MAC address.
+    pub dst: Option<MacAddr>,
+
+    /// The EtherType of the payload.
+    pub ether_type: Option<EtherType>,
+}
+
+/// Matches on an 802.1Q VLAN tag. Fields left as `None` match any value.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct VlanMatch {
+    /// The VLAN identifier. Only the lower 12 bits are used.
+    pub vid: Option<u16>,
+}
+
+/// Matches on the IPv4 header. Fields left as `None` match any value.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Ipv4Match {
+    /// The source address range.
+    pub src: Option<Ipv4Cidr>,
+
+    /// The destination address range.
+    pub dst: Option<Ipv4Cidr>,
+
+    /// The protocol of the payload.
+    pub protocol: Option<ProtocolNumber>,
+}
+
+/// Matches on the IPv6 header. Fields left as `None` match any value.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Ipv6Match {
+    /// The source address range.
+    pub src: Option<Ipv6Cidr>,
+
+    /// The destination address range.
+    pub dst: Option<Ipv6Cidr>,
+
+    /// The protocol of the next header.
+    pub next_header: Option<ProtocolNumber>,
+}
+
+/// Matches on the TCP or UDP ports. Fields left as `None` match any value.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PortMatch {
+    /// The source port.
+    pub src_port: Option<u16>,
+
+    /// The destination port.
+    pub dst_port: Option<u16>,
+}
+
+/// A protocol layer of a flow rule pattern.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FlowItem {
+    /// The Ethernet header.
+    Eth(EthMatch),
+    /// An 802.1Q VLAN tag.
+    Vlan(VlanMatch),
+    /// The IPv4 header.
+    Ipv4(Ipv4Match),
+    /// The IPv6 header.
+    Ipv6(Ipv6Match),
+    /// The TCP header.
+    Tcp(PortMatch),
+    /// The UDP header.
+    Udp(PortMatch),
+}
+
+/// What the NIC does with the packets matching a flow rule.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FlowAction {
+    /// Steers the packets to a receive queue.
+    Queue(u16),
+    /// Spreads the packets over the receive queues with RSS.
+    Rss(Vec<u16>),
+    /// Drops the packets.
+    Drop,
+    /// Marks the packets with an ID, readable with [`Mbuf::flow_mark`].
+    ///
+    /// [`Mbuf::flow_mark`]: crate::Mbuf::flow_mark
+    Mark(u32),
+}
+
+impl FlowAction {
+    /// Returns whether the action decides the fate of the packets. A rule
+    /// has at most one fate action.
+    fn is_fate(&self) -> bool {
+        !matches!(self, FlowAction::Mark(_))
+    }
+}
+
+/// A hardware flow rule for the ingress traffic of a port.
+///
+/// The pattern lists the protocol layers from the outermost, and a packet
+/// matches when all the layers match. A layer with no field set matches
+/// any header of that protocol, but still has to be there. The rule is
+/// checked by the device when it's created with [`PortId::create_flow`].
+///
+/// # Example
+///
+/// ```
+/// // VLAN 100 + TCP destination port 443 to queue 3.
+/// let rule = FlowRule::new()
+///     .eth(EthMatch::default())
+///     .vlan(VlanMatch { vid: Some(100) })
+///     .ipv4(Ipv4Match::default())
+///     .tcp(PortMatch { dst_port: Some(443), ..Default::default() })
+///     .queue(3);
+///
+/// // drops a /24.
+/// let rule = FlowRule::new()
+///     .eth(EthMatch::default())
+///     .ipv4(Ipv4Match { src: Some("10.1.2.0/24".parse()?), ..Default::default() })
+///     .discard();
+///
+/// let flow = port_id.create_flow(&rule)?;
+/// ```
+///
+/// [`PortId::create_flow`]: crate::PortId::create_flow
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FlowRule {
+    group: u32,
+    priority: u32,
+    pattern: Vec<FlowItem>,
+    actions: Vec<FlowAction>,
+}
+
+impl FlowRule {
+    /// Creates an empty rule in group `0` with priority `0`.
+    pub fn new() -> Self {
+        FlowRule::default()
+    }
+
+    /// Sets the group of the rule. Groups are device specific.
+    pub fn group(mut self, group: u32) -> Self {
+        self.group = group;
+        self
+    }
+
+    /// Sets the priority of the rule, `0` is the highest.
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Appends a protocol layer to the pattern.
+    pub fn item(mut self, item: FlowItem) -> Self {
+        self.pattern.push(item);
+        self
+    }
+
+    /// Appends an Ethernet header to the pattern.
+    pub fn eth(self, eth: EthMatch) -> Self {
+        self.item(FlowItem::Eth(eth))
+    }
+
+    /// Appends a VLAN tag to the pattern.
+    pub fn vlan(self, vlan: VlanMatch) -> Self {
+        self.item(FlowItem::Vlan(vlan))
+    }
+
+    /// Appends an IPv4 header to the pattern.
+    pub fn ipv4(self, ipv4: Ipv4Match) -> Self {
+        self.item(FlowItem::Ipv4(ipv4))
+    }
+
+    /// Appends an IPv6 header to the pattern.
+    pub fn ipv6(self, ipv6: Ipv6Match) -> Self {
+        self.item(FlowItem::Ipv6(ipv6))
+    }
+
+    /// Appends a TCP header to the pattern.
+    pub fn tcp(self, tcp: PortMatch) -> Self {
+        self.item(FlowItem::Tcp(tcp))
+    }
+
+    /// Appends a UDP header to the pattern.
+    pub fn udp(self, udp: PortMatch) -> Self {
+        self.item(FlowItem::Udp(udp))
+    }
+
+    /// Appends an action.
+    pub fn action(mut self, action: FlowAction) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    /// Steers the matching packets to a receive queue.
+    pub fn queue(self, index: u16) -> Self {
+        self.action(FlowAction::Queue(index))
+    }
+
+    /// Spreads the matching packets over the receive queues.
+    pub fn rss(self, queues: &[u16]) -> Self {
+        self.action(FlowAction::Rss(queues.to_vec()))
+    }
+
+    /// Drops the matching packets.
+    pub fn discard(self) -> Self {
+        self.action(FlowAction::Drop)
+    }
+
+    /// Marks the matching packets with an ID.
+    pub fn mark(self, id: u32) -> Self {
+        self.action(FlowAction::Mark(id))
+    }
+
+    /// Returns the pattern.
+    pub fn pattern(&self) -> &[FlowItem] {
+        &self.pattern
+    }
+
+    /// Returns the actions.
+    pub fn actions(&self) -> &[FlowAction] {
+        &self.actions
+    }
+
+    /// Checks the rule for the mistakes the device doesn't need to see.
+    fn check(&self) -> Result<()> {
+        ensure!(!self.actions.is_empty(), FlowError::NoAction);
+        ensure!(
+            self.actions.iter().filter(|a| a.is_fate()).count() <= 1,
+            FlowError::ConflictingFates
+        );
+        ensure!(
+            !self.actions.contains(&FlowAction::Rss(vec![])),
+            FlowError::NoRssQueues
+        );
+        Ok(())
+    }
+}
+
+/// Flow rule errors.
+#[derive(Debug, Error)]
+pub(crate) enum FlowError {
+    /// The rule has no action.
+    #[error("Flow rule has no action.")]
+    NoAction,
+
+    /// The rule has more than one of queue, RSS and drop.
+    #[error("Flow rule has more than one queue, RSS or drop action.")]
+    ConflictingFates,
+
+    /// The RSS action has no queues.
+    #[error("Flow rule spreads packets over no queues.")]
+    NoRssQueues,
+
+    /// The device rejected the rule.
+    #[error("{0:?} rejected the flow rule, {1}.")]
+    Rejected(PortId, String),
+}
+
+/// A flow rule in the layout `rte_flow` takes.
+///
+/// The pattern items and actions point into the specs, masks and action
+/// configurations kept alongside, so they are valid as long as the raw
+/// rule is.
+struct RawFlowRule {
+    attr: ffi::rte_flow_attr,
+    pattern: Vec<ffi::rte_flow_item>,
+    actions: Vec<ffi::rte_flow_action>,
+    confs: Vec<Box<dyn Any>>,
+}
+
+impl RawFlowRule {
+    fn new(rule: &FlowRule) -> Self {
+        let mut attr = ffi::rte_flow_attr {
+            group: rule.group,
+            priority: rule.priority,
+            ..Default::default()
+        };
+        attr.set_ingress(1);
+
+        let mut raw = RawFlowRule {
+            attr,
+            pattern: vec![],
+            actions: vec![],
+            confs: vec![],
+        };
+
+        for item in rule.pattern.iter() {
+            raw.push_item(item);
+        }
+        raw.pattern.push(ffi::rte_flow_item {
+            type_: ffi::rte_flow_item_type::RTE_FLOW_ITEM_TYPE_END,
+            ..Default::default()
+        });
+
+        for action in rule.actions.iter() {
+            raw.push_action(action);
+        }
+        raw.actions.push(ffi::rte_flow_action {
+            type_: ffi::rte_flow_action_type::RTE_FLOW_ACTION_TYPE_END,
+            ..Default::default()
+        });
+
+        raw
+    }
+
+    /// Keeps a value alive for as long as the rule, and returns a pointer
+    /// to it.
+    fn keep<T: 'static>(&mut self, value: T) -> *const raw::c_void {
+        let boxed = Box::new(value);
+        let ptr = &*boxed as *const T as *const raw::c_void;
+        self.confs.push(boxed);
+        ptr
+    }
+
+    /// Appends a pattern item. An item without any field to match on is
+    /// passed without a spec, so it matches any header of the protocol.
+    fn push_spec<T>(&mut self, type_: ffi::rte_flow_item_type::Type, spec: T, mask: T)
+    where
+        T: Default + PartialEq + 'static,
+    {
+        let (spec, mask) = if mask == T::default() {
+            (ptr::null(), ptr::null())
+        } else {
+            (self.keep(spec), self.keep(mask))
+        };
+
+        self.pattern.push(ffi::rte_flow_item {
+            type_,
+            spec,
+            last: ptr::null(),
+            mask,
+        });
+    }
+
+    fn push_item(&mut self, item: &FlowItem) {
+        use ffi::rte_flow_item_type::*;
+
+        match *item {
+            FlowItem::Eth(eth) => {
+                let mut spec = ffi::rte_flow_item_eth::default();
+                let mut mask = ffi::rte_flow_item_eth::default();
+                if let Some(src) = eth.src {
+                    spec.src.addr_bytes = src.octets();
+                    mask.src.addr_bytes = [0xff; 6];
+                }
+                if let Some(dst) = eth.dst {
+                    spec.dst.addr_bytes = dst.octets();
+                    mask.dst.addr_bytes = [0xff; 6];
+                }
+                if let Some(ether_type) = eth.ether_type {
+                    spec.type_ = ether_type.0.to_be();
+                    mask.type_ = 0xffff;
+                }
+                self.push_spec(RTE_FLOW_ITEM_TYPE_ETH, spec, mask);
+            }
+            FlowItem::Vlan(vlan) => {
+                let mut spec = ffi::rte_flow_item_vlan::default();
+                let mut mask = ffi::rte_flow_item_vlan::default();
+                if let Some(vid) = vlan.vid {
+                    spec.tci = (vid & 0x0fff).to_be();
+                    mask.tci = 0x0fffu16.to_be();
+                }
+                self.push_spec(RTE_FLOW_ITEM_TYPE_VLAN, spec, mask);
+            }
+            FlowItem::Ipv4(ipv4) => {
+                let mut spec = ffi::rte_flow_item_ipv4::default();
+                let mut mask = ffi::rte_flow_item_ipv4::default();
+                if let Some(src) = ipv4.src {
+                    spec.hdr.src_addr = u32::from(src.network()).to_be();
+                    mask.hdr.src_addr = u32::from(src.netmask()).to_be();
+                }
+                if let Some(dst) = ipv4.dst {
+                    spec.hdr.dst_addr = u32::from(dst.network()).to_be();
+                    mask.hdr.dst_addr = u32::from(dst.netmask()).to_be();
+                }
+                if let Some(protocol) = ipv4.protocol {
+                    spec.hdr.next_proto_id = protocol.0;
+                    mask.hdr.next_proto_id = 0xff;
+                }
+                self.push_spec(RTE_FLOW_ITEM_TYPE_IPV4, spec, mask);
+            }
+            FlowItem::Ipv6(ipv6) => {
+                let mut spec = ffi::rte_flow_item_ipv6::default();
+                let mut mask = ffi::rte_flow_item_ipv6::default();
+                if let Some(src) = ipv6.src {
+                    spec.hdr.src_addr = src.network().octets();
+                    mask.hdr.src_addr = src.netmask().octets();
+                }
+                if let Some(dst) = ipv6.dst {
+                    spec.hdr.dst_addr = dst.network().octets();
+                    mask.hdr.dst_addr = dst.netmask().octets();
+                }
+                if let Some(next_header) = ipv6.next_header {
+                    spec.hdr.proto = next_header.0;
+                    mask.hdr.proto = 0xff;
+                }
+                self.push_spec(RTE_FLOW_ITEM_TYPE_IPV6, spec, mask);
+            }
+            FlowItem::Tcp(ports) => {
+                let mut spec = ffi::rte_flow_item_tcp::default();
+                let mut mask = ffi::rte_flow_item_tcp::default();
+                if let Some(port) = ports.src_port {
+                    spec.hdr.src_port = port.to_be();
+                    mask.hdr.src_port = 0xffff;
+                }
+                if let Some(port) = ports.dst_port {
+                    spec.hdr.dst_port = port.to_be();
+                    mask.hdr.dst_port = 0xffff;
+                }
+                self.push_spec(RTE_FLOW_ITEM_TYPE_TCP, spec, mask);
+            }
+            FlowItem::Udp(ports) => {
+                let mut spec = ffi::rte_flow_item_udp::default();
+                let mut mask = ffi::rte_flow_item_udp::default();
+                if let Some(port) = ports.src_port {
+                    spec.hdr.src_port = port.to_be();
+                    mask.hdr.src_port = 0xffff;
+                }
+                if let Some(port) = ports.dst_port {
+                    spec.hdr.dst_port = port.to_be();
+                    mask.hdr.dst_port = 0xffff;
+                }
+                self.push_spec(RTE_FLOW_ITEM_TYPE_UDP, spec, mask);
+            }
+        }
+    }
+
+    fn push_action(&mut self, action: &FlowAction) {
+        use ffi::rte_flow_action_type::*;
+
+        let (type_, conf) = match action {
+            FlowAction::Queue(index) => (
+                RTE_FLOW_ACTION_TYPE_QUEUE,
+                self.keep(ffi::rte_flow_action_queue { index: *index }),
+            ),
+            FlowAction::Rss(queues) => {
+                let queues = queues.clone();
+                let rss = ffi::rte_flow_action_rss {
+                    func: ffi::rte_eth_hash_function::RTE_ETH_HASH_FUNCTION_DEFAULT,
+                    queue_num: queues.len() as u32,
+                    queue: queues.as_ptr(),
+                    ..Default::default()
+                };
+                // the heap buffer of the queues doesn't move with the vec.
+                self.keep(queues);
+                (RTE_FLOW_ACTION_TYPE_RSS, self.keep(rss))
+            }
+            FlowAction::Drop => (RTE_FLOW_ACTION_TYPE_DROP, ptr::null()),
+            FlowAction::Mark(id) => (
+                RTE_FLOW_ACTION_TYPE_MARK,
+                self.keep(ffi::rte_flow_action_mark { id: *id }),
+            ),
+        };
+
+        self.actions.push(ffi::rte_flow_action { type_, conf });
+    }
+
+    /// Describes why the device rejected the rule, pointing at the item or
+    /// action at fault when the device tells which one.
+    fn describe(&self, error: &ffi::rte_flow_error, cause: DpdkError) -> String {
+        use ffi::rte_flow_error_type::*;
+
+        let index_of = |base: usize, size: usize, len: usize| {
+            let offset = (error.cause as usize).wrapping_sub(base);
+            if !error.cause.is_null() && offset / size < len {
+                Some(offset / size)
+            } else {
+                None
+            }
+        };
+
+        let subject = match error.type_ {
+            RTE_FLOW_ERROR_TYPE_ATTR_GROUP => "group".to_owned(),
+            RTE_FLOW_ERROR_TYPE_ATTR_PRIORITY => "priority".to_owned(),
+            RTE_FLOW_ERROR_TYPE_ATTR_INGRESS
+            | RTE_FLOW_ERROR_TYPE_ATTR_EGRESS
+            | RTE_FLOW_ERROR_TYPE_ATTR_TRANSFER
+            | RTE_FLOW_ERROR_TYPE_ATTR => "attributes".to_owned(),
+            RTE_FLOW_ERROR_TYPE_ITEM_NUM => "pattern".to_owned(),
+            RTE_FLOW_ERROR_TYPE_ITEM_SPEC
+            | RTE_FLOW_ERROR_TYPE_ITEM_LAST
+            | RTE_FLOW_ERROR_TYPE_ITEM_MASK
+            | RTE_FLOW_ERROR_TYPE_ITEM => {
+                let base = self.pattern.as_ptr() as usize;
+                let size = mem::size_of::<ffi::rte_flow_item>();
+                match index_of(base, size, self.pattern.len()) {
+                    Some(index) => format!("pattern item {}", index),
+                    None => "pattern".to_owned(),
+                }
+            }
+            RTE_FLOW_ERROR_TYPE_ACTION_NUM => "actions".to_owned(),
+            RTE_FLOW_ERROR_TYPE_ACTION_CONF | RTE_FLOW_ERROR_TYPE_ACTION => {
+                let base = self.actions.as_ptr() as usize;
+                let size = mem::size_of::<ffi::rte_flow_action>();
+                match index_of(base, size, self.actions.len()) {
+                    Some(index) => format!("action {}", index),
+                    None => "actions".to_owned(),
+                }
+            }
+            _ => "rule".to_owned(),
+        };
+
+        let message = if error.message.is_null() {
+            "no details"
+        } else {
+            error.message.as_str()
+        };
+
+        format!("{}: {} ({})", subject, message, cause)
+    }
+}
+
+impl fmt::Debug for RawFlowRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawFlowRule")
+            .field("pattern", &self.pattern.len())
+            .field("actions", &self.actions.len())
+            .finish()
+    }
+}
+
+/// The number of times the flows of each port were flushed. A flush
+/// destroys the rules behind all the outstanding handles.
+static FLUSHES: Lazy<Mutex<HashMap<PortId, u64>>> = Lazy::new(Default::default);
+
+fn flushes(port_id: PortId) -> u64 {
+    FLUSHES
+        .lock()
+        .unwrap()
+        .get(&port_id)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Invalidates the outstanding handles of a port, after its flows are
+/// flushed or the port is closed.
+pub(crate) fn invalidate_flows(port_id: PortId) {
+    *FLUSHES.lock().unwrap().entry(port_id).or_default() += 1;
+}
+
+/// A hardware flow rule installed on a port.
+///
+/// The rule stays installed when the handle is dropped. Use [`destroy`]
+/// to remove it.
+///
+/// [`destroy`]: FlowHandle::destroy
+pub struct FlowHandle {
+    port_id: PortId,
+    flow: NonNull<ffi::rte_flow>,
+    flushes: u64,
+}
+
+// the handle is only an opaque identifier of the rule in the device.
+unsafe impl Send for FlowHandle {}
+
+impl FlowHandle {
+    /// Returns the port the rule is installed on.
+    pub fn port_id(&self) -> PortId {
+        self.port_id
+    }
+
+    /// Removes the rule from the device. Does nothing if the rule is
+    /// already removed by a flush.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FlowError` if the device fails to remove the rule.
+    pub fn destroy(self) -> Result<()> {
+        if flushes(self.port_id) != self.flushes {
+            return Ok(());
+        }
+
+        let mut error = ffi::rte_flow_error::default();
+        let ret =
+            unsafe { ffi::rte_flow_destroy(self.port_id.raw(), self.flow.as_ptr(), &mut error) };
+        ensure!(
+            ret == 0,
+            FlowError::Rejected(self.port_id, error_message(&error, ret))
+        );
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FlowHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlowHandle")
+            .field("port", &self.port_id)
+            .field("flow", &self.flow)
+            .finish()
+    }
+}
+
+/// Describes an error not tied to a rule.
+fn error_message(error: &ffi::rte_flow_error, ret: raw::c_int) -> String {
+    let message = if error.message.is_null() {
+        "no details"
+    } else {
+        error.message.as_str()
+    };
+    format!("{} ({})", message, DpdkError::from_errno(ret))
+}
+
+impl PortId {
+    /// Checks whether the device would accept the flow rule, without
+    /// installing it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FlowError` describing the part of the rule the device
+    /// rejects.
+    pub fn validate_flow(self, rule: &FlowRule) -> Result<()> {
+        rule.check()?;
+
+        let raw = RawFlowRule::new(rule);
+        let mut error = ffi::rte_flow_error::default();
+        let ret = unsafe {
+            ffi::rte_flow_validate(
+                self.raw(),
+                &raw.attr,
+                raw.pattern.as_ptr(),
+                raw.actions.as_ptr(),
+                &mut error,
+            )
+        };
+        ensure!(
+            ret == 0,
+            FlowError::Rejected(self, raw.describe(&error, DpdkError::from_errno(ret)))
+        );
+        Ok(())
+    }
+
+    /// Installs a flow rule on the port.
+    ///
+    /// Depending on the device, rules can be installed before or after the
+    /// port is started.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FlowError` describing the part of the rule the device
+    /// rejects.
+    pub fn create_flow(self, rule: &FlowRule) -> Result<FlowHandle> {
+        rule.check()?;
+
+        let raw = RawFlowRule::new(rule);
+        let mut error = ffi::rte_flow_error::default();
+        let flow = unsafe {
+            ffi::rte_flow_create(
+                self.raw(),
+                &raw.attr,
+                raw.pattern.as_ptr(),
+                raw.actions.as_ptr(),
+                &mut error,
+            )
+        };
+
+        match NonNull::new(flow) {
+            Some(flow) => {
+                info!("installed flow rule on {:?}.", self);
+                Ok(FlowHandle {
+                    port_id: self,
+                    flow,
+                    flushes: flushes(self),
+                })
+            }
+            None => Err(FlowError::Rejected(self, raw.describe(&error, DpdkError::new())).into()),
+        }
+    }
+
+    /// Removes all the flow rules installed on the port. The outstanding
+    /// handles are invalidated.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FlowError` if the device fails to remove the rules.
+    pub fn flush_flows(self) -> Result<()> {
+        let mut error = ffi::rte_flow_error::default();
+        let ret = unsafe { ffi::rte_flow_flush(self.raw(), &mut error) };
+        invalidate_flows(self);
+        ensure!(
+            ret == 0,
+            FlowError::Rejected(self, error_message(&error, ret))
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::ip::ProtocolNumbers;
+    use crate::packets::EtherTypes;
+    use std::ffi::CString;
+
+    unsafe fn spec<T: Copy>(item: &ffi::rte_flow_item) -> (T, T) {
+        (*(item.spec as *const T), *(item.mask as *const T))
+    }
+
+    #[test]
+    fn check_rule() {
+        let rule = FlowRule::new().eth(EthMatch::default());
+        assert!(rule.check().is_err());
+
+        let rule = FlowRule::new().queue(1).discard();
+        assert!(rule.check().is_err());
+
+        let rule = FlowRule::new().rss(&[]);
+        assert!(rule.check().is_err());
+
+        let rule = FlowRule::new().mark(7).queue(1);
+        assert!(rule.check().is_ok());
+        assert_eq!(&[FlowAction::Mark(7), FlowAction::Queue(1)], rule.actions());
+    }
+
+    #[test]
+    fn vlan_tcp_to_queue() {
+        use ffi::rte_flow_item_type::*;
+
+        let rule = FlowRule::new()
+            .priority(2)
+            .eth(EthMatch::default())
+            .vlan(VlanMatch { vid: Some(100) })
+            .ipv4(Ipv4Match::default())
+            .tcp(PortMatch {
+                dst_port: Some(443),
+                ..Default::default()
+            })
+            .queue(3);
+        let raw = RawFlowRule::new(&rule);
+
+        assert_eq!(1, raw.attr.ingress());
+        assert_eq!(2, raw.attr.priority);
+
+        let types = raw
+            .pattern
+            .iter()
+            .map(|item| item.type_)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                RTE_FLOW_ITEM_TYPE_ETH,
+                RTE_FLOW_ITEM_TYPE_VLAN,
+                RTE_FLOW_ITEM_TYPE_IPV4,
+                RTE_FLOW_ITEM_TYPE_TCP,
+                RTE_FLOW_ITEM_TYPE_END
+            ],
+            types
+        );
+
+        // the layers without fields match anything.
+        assert!(raw.pattern[0].spec.is_null() && raw.pattern[0].mask.is_null());
+        assert!(raw.pattern[2].spec.is_null() && raw.pattern[2].mask.is_null());
+
+        let (vlan, mask) = unsafe { spec::<ffi::rte_flow_item_vlan>(&raw.pattern[1]) };
+        assert_eq!(100, u16::from_be(vlan.tci));
+        assert_eq!(0x0fff, u16::from_be(mask.tci));
+
+        let (tcp, mask) = unsafe { spec::<ffi::rte_flow_item_tcp>(&raw.pattern[3]) };
+        assert_eq!(443, u16::from_be(tcp.hdr.dst_port));
+        assert_eq!(0xffff, { mask.hdr.dst_port });
+        assert_eq!(0, { mask.hdr.src_port });
+
+        assert_eq!(2, raw.actions.len());
+        let queue = unsafe { *(raw.actions[0].conf as *const ffi::rte_flow_action_queue) };
+        assert_eq!(3, queue.index);
+        assert_eq!(
+            ffi::rte_flow_action_type::RTE_FLOW_ACTION_TYPE_END,
+            raw.actions[1].type_
+        );
+    }
+
+    #[test]
+    fn drop_ipv4_prefix() {
+        let rule = FlowRule::new()
+            .eth(EthMatch {
+                ether_type: Some(EtherTypes::Ipv4),
+                ..Default::default()
+            })
+            .ipv4(Ipv4Match {
+                src: Some("10.1.2.0/24".parse().unwrap()),
+                protocol: Some(ProtocolNumbers::Udp),
+                ..Default::default()
+            })
+            .discard();
+        let raw = RawFlowRule::new(&rule);
+
+        let (eth, mask) = unsafe { spec::<ffi::rte_flow_item_eth>(&raw.pattern[0]) };
+        assert_eq!(0x0800, u16::from_be(eth.type_));
+        assert_eq!([0; 6], mask.src.addr_bytes);
+
+        let (ipv4, mask) = unsafe { spec::<ffi::rte_flow_item_ipv4>(&raw.pattern[1]) };
+        assert_eq!(0x0a01_0200, u32::from_be(ipv4.hdr.src_addr));
+        assert_eq!(0xffff_ff00, u32::from_be(mask.hdr.src_addr));
+        assert_eq!(0, { mask.hdr.dst_addr });
+        assert_eq!(17, ipv4.hdr.next_proto_id);
+
+        assert_eq!(
+            ffi::rte_flow_action_type::RTE_FLOW_ACTION_TYPE_DROP,
+            raw.actions[0].type_
+        );
+        assert!(raw.actions[0].conf.is_null());
+    }
+
+    #[test]
+    fn mark_and_rss() {
+        let rule = FlowRule::new()
+            .eth(EthMatch::default())
+            .ipv6(Ipv6Match {
+                dst: Some("2001:db8::/32".parse().unwrap()),
+                ..Default::default()
+            })
+            .udp(PortMatch {
+                src_port: Some(53),
+                ..Default::default()
+            })
+            .mark(0xbeef)
+            .rss(&[0, 1, 2, 3]);
+        let raw = RawFlowRule::new(&rule);
+
+        let (ipv6, mask) = unsafe { spec::<ffi::rte_flow_item_ipv6>(&raw.pattern[1]) };
+        assert_eq!([0x20, 0x01, 0x0d, 0xb8], ipv6.hdr.dst_addr[..4]);
+        assert_eq!([0xff; 4], mask.hdr.dst_addr[..4]);
+        assert_eq!([0; 12], mask.hdr.dst_addr[4..]);
+
+        let mark = unsafe { *(raw.actions[0].conf as *const ffi::rte_flow_action_mark) };
+        assert_eq!(0xbeef, mark.id);
+
+        let rss = unsafe { *(raw.actions[1].conf as *const ffi::rte_flow_action_rss) };
+        assert_eq!(4, rss.queue_num);
+        let queues = unsafe { std::slice::from_raw_parts(rss.queue, 4) };
+        assert_eq!(&[0, 1, 2, 3], queues);
+    }
+
+    #[test]
+    fn describe_rejection() {
+        let rule = FlowRule::new()
+            .eth(EthMatch::default())
+            .tcp(PortMatch::default())
+            .queue(9);
+        let raw = RawFlowRule::new(&rule);
+        let message = CString::new("unsupported item").unwrap();
+
+        let error = ffi::rte_flow_error {
+            type_: ffi::rte_flow_error_type::RTE_FLOW_ERROR_TYPE_ITEM,
+            cause: &raw.pattern[1] as *const _ as *const raw::c_void,
+            message: message.as_ptr(),
+        };
+        let described = raw.describe(&error, DpdkError::from_errno(-libc::ENOTSUP));
+        assert!(described.starts_with("pattern item 1: unsupported item ("));
+
+        let error = ffi::rte_flow_error {
+            type_: ffi::rte_flow_error_type::RTE_FLOW_ERROR_TYPE_ACTION_CONF,
+            cause: ptr::null(),
+            message: ptr::null(),
+        };
+        let described = raw.describe(&error, DpdkError::from_errno(-libc::EINVAL));
+        assert!(described.starts_with("actions: no details ("));
+    }
+}
@@ -16,10 +16,12 @@
 * SPDX-License-Identifier: Apache-2.0
 */
 
-use super::{Mbuf, PortId};
+use super::{Mbuf, PortError, PortId};
 use crate::dpdk::DpdkError;
-use crate::ffi::{self, AsStr, ToResult};
+use crate::ffi::{self, ToResult};
 
+#[cfg(feature = "metrics")]
+use crate::ffi::AsStr;
 #[cfg(feature = "metrics")]
 use crate::metrics::{labels, Counter, SINK};
 use crate::net::MacAddr;
@@ -30,6 +32,8 @@ use std::cmp;
 use std::mem;
 use std::os::raw;
 use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use thiserror::Error;
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
@@ -94,7 +98,7 @@ impl KniRx {
         let mbufs = unsafe {
             ptrs.set_len(len as usize);
             ptrs.into_iter()
-                .map(|ptr| Mbuf::from_ptr(ptr))
+                .map(|ptr| Mbuf::from_new_ptr(ptr))
                 .collect::<Vec<_>>()
         };
 
@@ -126,6 +130,7 @@ impl KniRx {
 #[derive(Clone)]
 pub struct KniTxQueue {
     tx_enque: UnboundedSender<Vec<Mbuf>>,
+    dropped: Arc<AtomicU64>,
 }
 
 impl KniTxQueue {
@@ -133,9 +138,18 @@ impl KniTxQueue {
     pub fn transmit(&mut self, packets: Vec<Mbuf>) {
         if let Err(err) = self.tx_enque.try_send(packets) {
             warn!(message = "failed to send to kni tx queue.");
-            Mbuf::free_bulk(err.into_inner());
+            let packets = err.into_inner();
+            self.dropped
+                .fetch_add(packets.len() as u64, Ordering::Relaxed);
+            Mbuf::free_bulk(packets);
         }
     }
+
+    /// Returns the number of kernel bound packets dropped, either because
+    /// the kernel receive ring was full or the interface is closed.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
 }
 
 /// The KNI transmit handle. Because the underlying interface is single
@@ -144,6 +158,7 @@ impl KniTxQueue {
 pub(crate) struct KniTx {
     raw: NonNull<ffi::rte_kni>,
     tx_deque: Option<UnboundedReceiver<Vec<Mbuf>>>,
+    ring_full: Arc<AtomicU64>,
     #[cfg(feature = "metrics")]
     packets: Counter,
     #[cfg(feature = "metrics")]
@@ -155,16 +170,25 @@ pub(crate) struct KniTx {
 impl KniTx {
     /// Creates a new `KniTx`.
     #[cfg(not(feature = "metrics"))]
-    pub(crate) fn new(raw: NonNull<ffi::rte_kni>, tx_deque: UnboundedReceiver<Vec<Mbuf>>) -> Self {
+    pub(crate) fn new(
+        raw: NonNull<ffi::rte_kni>,
+        tx_deque: UnboundedReceiver<Vec<Mbuf>>,
+        ring_full: Arc<AtomicU64>,
+    ) -> Self {
         KniTx {
             raw,
             tx_deque: Some(tx_deque),
+            ring_full,
         }
     }
 
     /// Creates a new `KniTx` with stats.
     #[cfg(feature = "metrics")]
-    pub(crate) fn new(raw: NonNull<ffi::rte_kni>, tx_deque: UnboundedReceiver<Vec<Mbuf>>) -> Self {
+    pub(crate) fn new(
+        raw: NonNull<ffi::rte_kni>,
+        tx_deque: UnboundedReceiver<Vec<Mbuf>>,
+        ring_full: Arc<AtomicU64>,
+    ) -> Self {
         let name = unsafe { ffi::rte_kni_get_name(raw.as_ref()).as_str().to_owned() };
         let packets = new_counter("packets", &name, "tx");
         let octets = new_counter("octets", &name, "tx");
@@ -172,6 +196,7 @@ impl KniTx {
         KniTx {
             raw,
             tx_deque: Some(tx_deque),
+            ring_full,
             packets,
             octets,
             dropped,
@@ -210,6 +235,8 @@ impl KniTx {
             } else {
                 // tx queue is full and we can't make progress, start dropping packets
                 // to avoid potentially stuck in an endless loop.
+                self.ring_full.fetch_add(to_send as u64, Ordering::Relaxed);
+
                 #[cfg(feature = "metrics")]
                 self.dropped.record(to_send as u64);
 
@@ -232,6 +259,25 @@ impl KniTx {
 unsafe impl Send for KniRx {}
 unsafe impl Send for KniTx {}
 
+/// A handle to update the carrier of the kernel interface, so `ip link`
+/// shows the state of the DPDK link.
+pub(crate) struct KniLink {
+    raw: NonNull<ffi::rte_kni>,
+}
+
+impl KniLink {
+    /// Sets the carrier of the kernel interface on or off.
+    pub(crate) fn update(&self, up: bool) {
+        let ret = unsafe { ffi::rte_kni_update_link(self.raw.as_ptr(), up as raw::c_uint) };
+        if ret < 0 {
+            warn!(message = "failed to update kni link.", up);
+        }
+    }
+}
+
+// updating the carrier only writes to the sysfs entry of the interface.
+unsafe impl Send for KniLink {}
+
 /// KNI errors.
 #[derive(Debug, Error)]
 pub(crate) enum KniError {
@@ -261,13 +307,17 @@ impl Kni {
     /// Creates a new KNI.
     pub(crate) fn new(raw: NonNull<ffi::rte_kni>) -> Kni {
         let (send, recv) = mpsc::unbounded_channel();
+        let dropped = Arc::new(AtomicU64::new(0));
 
         // making 3 clones of the same raw pointer. but we know it is safe
         // to do because rx and tx happen on two independent queues. so while
         // each one is single-threaded, they can function in parallel.
         let rx = KniRx::new(raw);
-        let tx = KniTx::new(raw, recv);
-        let txq = KniTxQueue { tx_enque: send };
+        let tx = KniTx::new(raw, recv, dropped.clone());
+        let txq = KniTxQueue {
+            tx_enque: send,
+            dropped,
+        };
 
         Kni {
             raw,
@@ -292,6 +342,11 @@ impl Kni {
         self.txq.clone()
     }
 
+    /// Returns a handle to update the carrier of the kernel interface.
+    pub(crate) fn link(&self) -> KniLink {
+        KniLink { raw: self.raw }
+    }
+
     /// Returns the raw struct needed for FFI calls.
     #[inline]
     pub(crate) fn raw_mut(&mut self) -> &mut ffi::rte_kni {
@@ -311,29 +366,112 @@ impl Drop for Kni {
     }
 }
 
-/// Does not support changing the link MTU.
+/// A configuration change requested by the kernel through the virtual
+/// interface, for example with `ip link set`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum KniRequest {
+    ChangeMtu(u32),
+    ConfigNetworkIf(bool),
+    ConfigMacAddr(MacAddr),
+    ConfigPromiscuity(bool),
+    ConfigAllmulticast(bool),
+}
+
+/// The port operations the kernel requests are applied to.
+pub(crate) trait KniPortOps {
+    fn set_mtu(&self, mtu: u16) -> Result<()>;
+    fn set_link_up(&self, up: bool) -> Result<()>;
+    fn set_mac_addr(&self, addr: MacAddr) -> Result<()>;
+    fn set_promiscuous(&self, enabled: bool) -> Result<()>;
+    fn set_allmulticast(&self, enabled: bool) -> Result<()>;
+}
+
+impl KniPortOps for PortId {
+    fn set_mtu(&self, mtu: u16) -> Result<()> {
+        PortId::set_mtu(*self, mtu)
+    }
+
+    fn set_link_up(&self, up: bool) -> Result<()> {
+        PortId::set_link_up(*self, up)
+    }
+
+    fn set_mac_addr(&self, addr: MacAddr) -> Result<()> {
+        PortId::set_mac_addr(*self, addr)
+    }
+
+    fn set_promiscuous(&self, enabled: bool) -> Result<()> {
+        PortId::set_promiscuous(*self, enabled)
+    }
+
+    fn set_allmulticast(&self, enabled: bool) -> Result<()> {
+        PortId::set_allmulticast(*self, enabled)
+    }
+}
+
+impl KniRequest {
+    /// Applies the request to the port, and returns `0` on success or a
+    /// negative `errno` the kernel reports back to the caller.
+    pub(crate) fn dispatch<P: KniPortOps>(self, port: &P) -> raw::c_int {
+        let result = match self {
+            KniRequest::ChangeMtu(mtu) => {
+                if mtu > u16::MAX as u32 {
+                    return -libc::EINVAL;
+                }
+                port.set_mtu(mtu as u16)
+            }
+            KniRequest::ConfigNetworkIf(up) => port.set_link_up(up),
+            KniRequest::ConfigMacAddr(addr) => port.set_mac_addr(addr),
+            KniRequest::ConfigPromiscuity(on) => port.set_promiscuous(on),
+            KniRequest::ConfigAllmulticast(on) => port.set_allmulticast(on),
+        };
+
+        match result {
+            Ok(_) => {
+                debug!(message = "applied kni request.", request = ?self);
+                0
+            }
+            Err(err) => {
+                warn!(message = "failed to apply kni request.", request = ?self, ?err);
+                match err.downcast_ref::<PortError>() {
+                    Some(PortError::NotSupported(..)) => -libc::ENOTSUP,
+                    Some(PortError::InvalidMtu(..)) | Some(PortError::NotUnicastMac(..)) => {
+                        -libc::EINVAL
+                    }
+                    _ => -libc::EIO,
+                }
+            }
+        }
+    }
+}
+
+/// Changes the MTU of the port.
 extern "C" fn change_mtu(port_id: u16, new_mtu: raw::c_uint) -> raw::c_int {
-    warn!("ignored change port {} mtu to {}.", port_id, new_mtu);
-    -1
+    KniRequest::ChangeMtu(new_mtu).dispatch(&PortId::new(port_id))
 }
 
-/// Does not change the link up/down status, but will return 0 so the
-/// command succeeds.
+/// Brings the link of the port up or down.
 extern "C" fn config_network_if(port_id: u16, if_up: u8) -> raw::c_int {
-    warn!("ignored change port {} status to {}.", port_id, if_up);
-    0
+    KniRequest::ConfigNetworkIf(if_up != 0).dispatch(&PortId::new(port_id))
 }
 
-/// Does not support changing the link MAC address.
-extern "C" fn config_mac_address(port_id: u16, _mac_addr: *mut u8) -> raw::c_int {
-    warn!("ignored change port {} mac address.", port_id);
-    -1
+/// Changes the MAC address of the port.
+unsafe extern "C" fn config_mac_address(port_id: u16, mac_addr: *mut u8) -> raw::c_int {
+    if mac_addr.is_null() {
+        return -libc::EINVAL;
+    }
+
+    let octets = ptr::read(mac_addr as *const [u8; 6]);
+    KniRequest::ConfigMacAddr(octets.into()).dispatch(&PortId::new(port_id))
 }
 
-/// Does not support changing the link promiscusity.
+/// Enables or disables the promiscuous mode of the port.
 extern "C" fn config_promiscusity(port_id: u16, to_on: u8) -> raw::c_int {
-    warn!("ignored change port {} promiscusity to {}.", port_id, to_on);
-    -1
+    KniRequest::ConfigPromiscuity(to_on != 0).dispatch(&PortId::new(port_id))
+}
+
+/// Enables or disables the all-multicast mode of the port.
+extern "C" fn config_allmulticast(port_id: u16, to_on: u8) -> raw::c_int {
+    KniRequest::ConfigAllmulticast(to_on != 0).dispatch(&PortId::new(port_id))
 }
 
 /// Builds a KNI device from the configuration values.
@@ -385,6 +523,7 @@ impl<'a> KniBuilder<'a> {
         self.ops.config_network_if = Some(config_network_if);
         self.ops.config_mac_address = Some(config_mac_address);
         self.ops.config_promiscusity = Some(config_promiscusity);
+        self.ops.config_allmulticast = Some(config_allmulticast);
 
         unsafe {
             ffi::rte_kni_alloc(self.mempool, &self.conf, &mut self.ops)
@@ -409,3 +548,106 @@ pub(crate) fn kni_close() {
         ffi::rte_kni_close();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ensure;
+    use std::cell::RefCell;
+
+    /// Records the operations applied by the requests.
+    #[derive(Default)]
+    struct RecordingPort {
+        ops: RefCell<Vec<String>>,
+        unsupported: bool,
+    }
+
+    impl RecordingPort {
+        fn record(&self, op: String) -> Result<()> {
+            if self.unsupported {
+                return Err(PortError::NotSupported(PortId::new(0), "recording").into());
+            }
+            self.ops.borrow_mut().push(op);
+            Ok(())
+        }
+    }
+
+    impl KniPortOps for RecordingPort {
+        fn set_mtu(&self, mtu: u16) -> Result<()> {
+            ensure!(mtu >= 68, PortError::InvalidMtu(mtu, 68, 2022));
+            self.record(format!("mtu {}", mtu))
+        }
+
+        fn set_link_up(&self, up: bool) -> Result<()> {
+            self.record(format!("link {}", up))
+        }
+
+        fn set_mac_addr(&self, addr: MacAddr) -> Result<()> {
+            self.record(format!("mac {}", addr))
+        }
+
+        fn set_promiscuous(&self, enabled: bool) -> Result<()> {
+            self.record(format!("promiscuous {}", enabled))
+        }
+
+        fn set_allmulticast(&self, enabled: bool) -> Result<()> {
+            self.record(format!("allmulticast {}", enabled))
+        }
+    }
+
+    #[test]
+    fn dispatch_kni_requests() {
+        let port = RecordingPort::default();
+        let mac = MacAddr::new(0x02, 0, 0, 0xff, 0xff, 0x02);
+
+        assert_eq!(0, KniRequest::ChangeMtu(1400).dispatch(&port));
+        assert_eq!(0, KniRequest::ConfigNetworkIf(false).dispatch(&port));
+        assert_eq!(0, KniRequest::ConfigMacAddr(mac).dispatch(&port));
+        assert_eq!(0, KniRequest::ConfigPromiscuity(true).dispatch(&port));
+        assert_eq!(0, KniRequest::ConfigAllmulticast(true).dispatch(&port));
+
+        assert_eq!(
+            vec![
+                "mtu 1400",
+                "link false",
+                "mac 02:00:00:ff:ff:02",
+                "promiscuous true",
+                "allmulticast true",
+            ],
+            *port.ops.borrow()
+        );
+    }
+
+    #[test]
+    fn reject_invalid_kni_requests() {
+        let port = RecordingPort::default();
+
+        assert_eq!(-libc::EINVAL, KniRequest::ChangeMtu(70_000).dispatch(&port));
+        assert_eq!(-libc::EINVAL, KniRequest::ChangeMtu(40).dispatch(&port));
+        assert!(port.ops.borrow().is_empty());
+
+        let port = RecordingPort {
+            unsupported: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            -libc::ENOTSUP,
+            KniRequest::ConfigPromiscuity(true).dispatch(&port)
+        );
+    }
+
+    #[capsule::test]
+    fn count_dropped_kernel_bound_packets() {
+        let (send, recv) = mpsc::unbounded_channel();
+        let mut txq = KniTxQueue {
+            tx_enque: send,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+
+        // the tx pipeline is gone, so nothing can be delivered.
+        drop(recv);
+        txq.transmit(vec![Mbuf::new().unwrap(), Mbuf::new().unwrap()]);
+
+        assert_eq!(2, txq.dropped());
+    }
+}
@@ -0,0 +1,451 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{check_supported, DpdkError, PortId};
+use crate::ffi::{self, ToResult};
+use crate::{debug, info, warn};
+use anyhow::Result;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use once_cell::sync::Lazy;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::os::raw;
+use std::ptr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// How often the link is checked while waiting for it to come up.
+const LINK_UP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The status of the link of a port.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LinkStatus {
+    /// Whether the link is up.
+    pub up: bool,
+
+    /// The link speed in Mbps. `0` if the speed is unknown.
+    pub speed_mbps: u32,
+
+    /// Whether the link is full duplex.
+    pub full_duplex: bool,
+
+    /// Whether the link speed and duplex are auto-negotiated.
+    pub autoneg: bool,
+}
+
+impl From<ffi::rte_eth_link> for LinkStatus {
+    fn from(link: ffi::rte_eth_link) -> Self {
+        LinkStatus {
+            up: link.link_status() == ffi::ETH_LINK_UP as u16,
+            speed_mbps: link.link_speed,
+            full_duplex: link.link_duplex() == ffi::ETH_LINK_FULL_DUPLEX as u16,
+            autoneg: link.link_autoneg() == ffi::ETH_LINK_AUTONEG as u16,
+        }
+    }
+}
+
+impl fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.up {
+            write!(
+                f,
+                "up {} Mbps {}-duplex",
+                self.speed_mbps,
+                if self.full_duplex { "full" } else { "half" }
+            )
+        } else {
+            write!(f, "down")
+        }
+    }
+}
+
+/// Link related errors.
+#[derive(Debug, Error)]
+pub(crate) enum LinkError {
+    /// The link didn't come up before the timeout.
+    #[error("Link of {0:?} is still down after {1:?}.")]
+    StillDown(PortId, Duration),
+}
+
+impl PortId {
+    /// Returns the current status of the link without waiting for the
+    /// device to complete auto-negotiation.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DpdkError` if the device fails to report the link.
+    pub fn link(self) -> Result<LinkStatus> {
+        let mut link = ffi::rte_eth_link::default();
+        unsafe {
+            ffi::rte_eth_link_get_nowait(self.raw(), &mut link)
+                .into_result(DpdkError::from_errno)?;
+        }
+        Ok(link.into())
+    }
+
+    /// Brings the link of a started port administratively up or down.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::NotSupported` if the device can't change the
+    /// link state.
+    pub fn set_link_up(self, up: bool) -> Result<()> {
+        let ret = unsafe {
+            if up {
+                ffi::rte_eth_dev_set_link_up(self.raw())
+            } else {
+                ffi::rte_eth_dev_set_link_down(self.raw())
+            }
+        };
+        check_supported(ret, self, "changing the link state")
+    }
+
+    /// Blocks the current thread until the link is up, and returns the
+    /// status of the link.
+    ///
+    /// The link is checked every 100 milliseconds. The port must already
+    /// be started.
+    ///
+    /// # Errors
+    ///
+    /// Returns `LinkError` if the link is still down when the timeout
+    /// expires.
+    pub fn wait_for_link_up(self, timeout: Duration) -> Result<LinkStatus> {
+        wait_until_up(|| self.link(), timeout, LINK_UP_CHECK_INTERVAL)?
+            .ok_or_else(|| LinkError::StillDown(self, timeout).into())
+    }
+}
+
+/// Checks the link until it is up or the timeout expires. Returns `None`
+/// on timeout.
+fn wait_until_up<F>(
+    mut link: F,
+    timeout: Duration,
+    interval: Duration,
+) -> Result<Option<LinkStatus>>
+where
+    F: FnMut() -> Result<LinkStatus>,
+{
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let status = link()?;
+        if status.up {
+            return Ok(Some(status));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+
+        thread::sleep(cmp::min(interval, deadline - now));
+    }
+}
+
+/// The ports with link status change interrupts enabled, and the
+/// subscriber the interrupts are forwarded to.
+#[derive(Default)]
+struct LscEvents {
+    ports: HashSet<PortId>,
+    subscriber: Option<UnboundedSender<PortId>>,
+}
+
+static LSC_EVENTS: Lazy<Mutex<LscEvents>> = Lazy::new(Default::default);
+
+/// Invoked by DPDK on its interrupt thread when the link of a port changes.
+unsafe extern "C" fn on_lsc_event(
+    port_id: u16,
+    _event: ffi::rte_eth_event_type::Type,
+    _cb_arg: *mut raw::c_void,
+    _ret_param: *mut raw::c_void,
+) -> raw::c_int {
+    // must not panic across the FFI boundary.
+    if let Ok(events) = LSC_EVENTS.lock() {
+        if let Some(subscriber) = &events.subscriber {
+            let _ = subscriber.unbounded_send(PortId::new(port_id));
+        }
+    }
+    0
+}
+
+/// Returns whether the device can notify link status changes through an
+/// interrupt.
+pub(crate) fn lsc_supported(dev_info: &ffi::rte_eth_dev_info) -> bool {
+    !dev_info.dev_flags.is_null() && unsafe { *dev_info.dev_flags } & ffi::RTE_ETH_DEV_INTR_LSC != 0
+}
+
+/// Registers the link status change interrupt handler of a port. The port
+/// must be configured with the LSC interrupt turned on.
+pub(crate) fn lsc_register(port_id: PortId) -> Result<()> {
+    unsafe {
+        ffi::rte_eth_dev_callback_register(
+            port_id.raw(),
+            ffi::rte_eth_event_type::RTE_ETH_EVENT_INTR_LSC,
+            Some(on_lsc_event),
+            ptr::null_mut(),
+        )
+        .into_result(DpdkError::from_errno)?;
+    }
+
+    LSC_EVENTS.lock().unwrap().ports.insert(port_id);
+    debug!("registered link status change interrupt for {:?}.", port_id);
+    Ok(())
+}
+
+/// Unregisters the link status change interrupt handler of a port.
+pub(crate) fn lsc_unregister(port_id: PortId) {
+    LSC_EVENTS.lock().unwrap().ports.remove(&port_id);
+
+    // the handler takes the lock, so it's not held while unregistering.
+    unsafe {
+        ffi::rte_eth_dev_callback_unregister(
+            port_id.raw(),
+            ffi::rte_eth_event_type::RTE_ETH_EVENT_INTR_LSC,
+            Some(on_lsc_event),
+            ptr::null_mut(),
+        );
+    }
+}
+
+/// Returns whether the port notifies link status changes through an
+/// interrupt.
+pub(crate) fn lsc_enabled(port_id: PortId) -> bool {
+    LSC_EVENTS.lock().unwrap().ports.contains(&port_id)
+}
+
+/// Subscribes to the link status change interrupts of all the ports. Only
+/// the latest subscriber receives the interrupts.
+pub(crate) fn lsc_subscribe() -> UnboundedReceiver<PortId> {
+    let (sender, receiver) = mpsc::unbounded();
+    LSC_EVENTS.lock().unwrap().subscriber = Some(sender);
+    receiver
+}
+
+/// A callback invoked with the name of the port and its new link status.
+pub(crate) type LinkCallback = Box<dyn Fn(&str, LinkStatus)>;
+
+/// Tracks the links of the ports, and invokes the callbacks when a link
+/// changes.
+pub(crate) struct LinkWatcher {
+    links: HashMap<String, (PortId, LinkStatus)>,
+    callbacks: Vec<LinkCallback>,
+}
+
+impl LinkWatcher {
+    /// Creates a new watcher that invokes the callbacks on change.
+    pub(crate) fn new(callbacks: Vec<LinkCallback>) -> Self {
+        LinkWatcher {
+            links: HashMap::new(),
+            callbacks,
+        }
+    }
+
+    /// Checks the links of the ports that are `due`.
+    ///
+    /// The first status read from a newly seen port is the baseline and
+    /// doesn't invoke the callbacks. Ports not in `ports` anymore are
+    /// forgotten.
+    pub(crate) fn poll<D, F>(&mut self, ports: &[(String, PortId)], due: D, link: F)
+    where
+        D: Fn(PortId) -> bool,
+        F: Fn(PortId) -> Result<LinkStatus>,
+    {
+        self.links
+            .retain(|name, _| ports.iter().any(|(active, _)| active == name));
+
+        for (name, id) in ports.iter() {
+            let known = self
+                .links
+                .get(name)
+                .filter(|(known_id, _)| known_id == id)
+                .map(|&(_, last)| last);
+
+            if known.is_some() && !due(*id) {
+                continue;
+            }
+
+            let status = match link(*id) {
+                Ok(status) => status,
+                Err(err) => {
+                    warn!(
+                        message = "failed to read the link.",
+                        port = name.as_str(),
+                        ?err
+                    );
+                    continue;
+                }
+            };
+
+            self.links.insert(name.clone(), (*id, status));
+
+            match known {
+                Some(last) if last != status => {
+                    info!("port {} link is {}.", name, status);
+                    for callback in self.callbacks.iter() {
+                        callback(name, status);
+                    }
+                }
+                Some(_) => (),
+                None => debug!("port {} link is {}.", name, status),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dpdk::{CoreId, Mempool};
+    use crate::testils::TestVdev;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    fn link_up(speed_mbps: u32) -> LinkStatus {
+        LinkStatus {
+            up: true,
+            speed_mbps,
+            full_duplex: true,
+            autoneg: true,
+        }
+    }
+
+    #[test]
+    fn link_status_from_raw() {
+        let mut link = ffi::rte_eth_link::default();
+        assert_eq!(LinkStatus::default(), link.into());
+        assert_eq!("down", LinkStatus::from(link).to_string());
+
+        link.link_speed = 10_000;
+        link.set_link_status(ffi::ETH_LINK_UP as u16);
+        link.set_link_duplex(ffi::ETH_LINK_FULL_DUPLEX as u16);
+        link.set_link_autoneg(ffi::ETH_LINK_AUTONEG as u16);
+
+        let status = LinkStatus::from(link);
+        assert_eq!(link_up(10_000), status);
+        assert_eq!("up 10000 Mbps full-duplex", status.to_string());
+    }
+
+    #[test]
+    fn wait_until_link_up() {
+        let checks = Cell::new(0);
+        let link = || {
+            checks.set(checks.get() + 1);
+            Ok(if checks.get() < 3 {
+                LinkStatus::default()
+            } else {
+                link_up(1000)
+            })
+        };
+
+        let status = wait_until_up(link, Duration::from_secs(1), Duration::from_millis(1));
+        assert_eq!(Some(link_up(1000)), status.unwrap());
+        assert_eq!(3, checks.get());
+
+        // times out if the link stays down.
+        let status = wait_until_up(
+            || Ok(LinkStatus::default()),
+            Duration::from_millis(5),
+            Duration::from_millis(1),
+        );
+        assert_eq!(None, status.unwrap());
+    }
+
+    #[test]
+    fn invoke_callbacks_on_link_change() {
+        let changes = Rc::new(RefCell::new(vec![]));
+        let recorded = changes.clone();
+        let callback: LinkCallback =
+            Box::new(move |name, status| recorded.borrow_mut().push((name.to_owned(), status)));
+        let mut watcher = LinkWatcher::new(vec![callback]);
+
+        let eth1 = PortId::new(1);
+        let eth2 = PortId::new(2);
+        let ports = vec![("eth1".to_owned(), eth1), ("eth2".to_owned(), eth2)];
+        let links = RefCell::new(HashMap::new());
+        links.borrow_mut().insert(eth1, LinkStatus::default());
+        links.borrow_mut().insert(eth2, link_up(1000));
+        let link = |id: PortId| -> Result<LinkStatus> { Ok(links.borrow()[&id]) };
+
+        // the first poll sets the baseline.
+        watcher.poll(&ports, |_| true, link);
+        assert!(changes.borrow().is_empty());
+
+        links.borrow_mut().insert(eth1, link_up(10_000));
+        watcher.poll(&ports, |_| true, link);
+        assert_eq!(
+            vec![("eth1".to_owned(), link_up(10_000))],
+            *changes.borrow()
+        );
+
+        // no change, no callback.
+        watcher.poll(&ports, |_| true, link);
+        assert_eq!(1, changes.borrow().len());
+
+        // ports that are not due are not checked.
+        links.borrow_mut().insert(eth2, LinkStatus::default());
+        watcher.poll(&ports, |id| id != eth2, link);
+        assert_eq!(1, changes.borrow().len());
+        watcher.poll(&ports, |id| id == eth2, link);
+        assert_eq!(
+            ("eth2".to_owned(), LinkStatus::default()),
+            changes.borrow()[1]
+        );
+
+        // a removed port is forgotten, and sets a new baseline when it
+        // comes back.
+        watcher.poll(&ports[..1], |_| true, link);
+        links.borrow_mut().insert(eth2, link_up(1000));
+        watcher.poll(&ports, |_| false, link);
+        assert_eq!(2, changes.borrow().len());
+    }
+
+    #[capsule::test]
+    fn null_vdev_link_follows_port_state() {
+        let core_id = CoreId::new(0);
+        let vdev = TestVdev::new("net_null");
+        let mut mempools = vec![Mempool::new(63, 0, core_id.socket_id()).unwrap()];
+
+        let mut port = vdev.port("vm1", &[core_id], &mut mempools);
+        let id = port.id();
+
+        assert!(!id.link().unwrap().up);
+        assert!(id.wait_for_link_up(Duration::from_millis(10)).is_err());
+
+        port.start().unwrap();
+        assert!(id.wait_for_link_up(Duration::from_secs(1)).unwrap().up);
+
+        // the watcher picks up the link going down.
+        let changes = Rc::new(Cell::new(0));
+        let counted = changes.clone();
+        let callback: LinkCallback = Box::new(move |_, status| {
+            assert!(!status.up);
+            counted.set(counted.get() + 1);
+        });
+        let mut watcher = LinkWatcher::new(vec![callback]);
+        let ports = vec![("vm1".to_owned(), id)];
+
+        watcher.poll(&ports, |_| true, PortId::link);
+        port.stop();
+        watcher.poll(&ports, |_| true, PortId::link);
+        assert_eq!(1, changes.get());
+    }
+}
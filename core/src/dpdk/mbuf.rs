@@ -19,16 +19,23 @@
 use super::MEMPOOL;
 use crate::dpdk::{DpdkError, MempoolError};
 use crate::ffi::{self, ToResult};
-use crate::packets::{Internal, Packet};
+use crate::net::MacAddr;
+use crate::packets::{EthernetHeader, Internal, LayerInfo, Packet};
+use crate::pcap;
 use crate::{ensure, trace};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use std::cmp;
 use std::fmt;
 use std::mem;
 use std::os::raw;
+use std::path::Path;
 use std::ptr::{self, NonNull};
 use std::slice;
 use thiserror::Error;
 
+/// The Ethernet preamble followed by the start frame delimiter.
+const PREAMBLE: [u8; 8] = [0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0xd5];
+
 /// A trait for returning the size of a type in bytes.
 ///
 /// Size of the structs are used for bound checks when reading and writing
@@ -95,15 +102,24 @@ pub(crate) enum BufferError {
     /// The struct size exceeds the remaining buffer length.
     #[error("Struct size {0} exceeds the remaining buffer length {1}.")]
     OutOfBuffer(usize, usize),
+
+    /// The buffer can't be chained to another.
+    #[error("Buffer is not chained.")]
+    NotChained,
 }
 
 /// A DPDK message buffer that carries the network packet.
 ///
 /// # Remarks
 ///
-/// Multi-segment Mbuf is not supported. It's the application's responsibilty
-/// to ensure that the ethernet device's MTU is less than the default size
-/// of a single Mbuf segment (`RTE_MBUF_DEFAULT_DATAROOM` = 2048).
+/// Multi-segment Mbuf is not supported. The frames must fit in the default
+/// size of a single Mbuf segment (`RTE_MBUF_DEFAULT_DATAROOM` = 2048), so
+/// [`PortId::set_mtu`] rejects MTUs larger than 2022. The only exception
+/// is the segments appended with [`chain`], which the packet types don't
+/// see. They only parse and modify the data of the first segment.
+///
+/// [`PortId::set_mtu`]: crate::PortId::set_mtu
+/// [`chain`]: Mbuf::chain
 pub struct Mbuf {
     inner: MbufInner,
 }
@@ -151,9 +167,11 @@ impl Mbuf {
         let raw =
             unsafe { ffi::_rte_pktmbuf_alloc(mempool).into_result(|_| MempoolError::Exhausted)? };
 
-        Ok(Mbuf {
+        let mut mbuf = Mbuf {
             inner: MbufInner::Original(raw),
-        })
+        };
+        mbuf.set_metadata(0);
+        Ok(mbuf)
     }
 
     /// Creates a new message buffer from a byte array.
@@ -171,6 +189,57 @@ impl Mbuf {
         Ok(mbuf)
     }
 
+    /// Reads the packets of a pcap or pcapng file into new message buffers,
+    /// allocated in bulk from the `Mempool` assigned to the current core.
+    ///
+    /// The capture times are not kept. Use [`pcap::read_file`] for them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or is not a valid
+    /// capture. Returns `DpdkError` if the allocation of mbufs fails, or
+    /// `BufferError::NotResized` if a packet is larger than the maximum
+    /// mbuf size.
+    ///
+    /// [`pcap::read_file`]: crate::pcap::read_file
+    pub fn from_pcap<P: AsRef<Path>>(path: P) -> Result<Vec<Self>> {
+        let packets = pcap::read_file(path).collect::<Result<Vec<_>>>()?;
+        let mut mbufs = Mbuf::alloc_bulk(packets.len())?;
+
+        for (mbuf, (_, data)) in mbufs.iter_mut().zip(packets.iter()) {
+            mbuf.extend(0, data.len())?;
+            mbuf.write_data_slice(0, &data[..])?;
+        }
+
+        Ok(mbufs)
+    }
+
+    /// Creates an indirect clone of the message buffer.
+    ///
+    /// The clone is allocated from the `Mempool` assigned to the current
+    /// executing thread, and shares the data buffer of the original instead
+    /// of copying it. The metadata is copied. The data buffer is freed only after both the original
+    /// and all its clones are freed. Because the data is shared, neither
+    /// should be modified while the clone is alive.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MempoolError::Exhausted` if the allocation of mbuf fails.
+    #[inline]
+    pub(crate) fn clone_indirect(&self) -> Result<Self> {
+        let mempool = MEMPOOL.with(|tls| tls.get());
+        let raw = unsafe {
+            ffi::_rte_pktmbuf_clone(self.as_ptr(), mempool)
+                .into_result(|_| MempoolError::Exhausted)?
+        };
+
+        let mut clone = Mbuf {
+            inner: MbufInner::Original(raw),
+        };
+        clone.set_metadata(self.metadata());
+        Ok(clone)
+    }
+
     /// Creates a new `Mbuf` from a raw pointer.
     #[inline]
     pub(crate) unsafe fn from_ptr(ptr: *mut ffi::rte_mbuf) -> Self {
@@ -179,6 +248,18 @@ impl Mbuf {
         }
     }
 
+    /// Creates a new `Mbuf` from a raw pointer to a buffer freshly
+    /// allocated from a mempool, either by us or by a driver on receive.
+    ///
+    /// DPDK leaves the metadata of the buffer's previous use in place, so
+    /// it's cleared here.
+    #[inline]
+    pub(crate) unsafe fn from_new_ptr(ptr: *mut ffi::rte_mbuf) -> Self {
+        let mut mbuf = Mbuf::from_ptr(ptr);
+        mbuf.set_metadata(0);
+        mbuf
+    }
+
     /// Returns the raw struct needed for FFI calls.
     #[inline]
     fn raw(&self) -> &ffi::rte_mbuf {
@@ -191,12 +272,238 @@ impl Mbuf {
         unsafe { self.inner.ptr_mut().as_mut() }
     }
 
+    /// Returns the application metadata carried with the packet.
+    ///
+    /// The metadata is kept in the mbuf header, it's not part of the packet
+    /// data and never goes on the wire. It's cleared when the mbuf is
+    /// allocated or received from a port, but is kept when the mbuf is
+    /// passed to another core through a ring.
+    #[inline]
+    pub(crate) fn metadata(&self) -> u64 {
+        unsafe { self.raw().__bindgen_anon_5.udata64 }
+    }
+
+    /// Sets the application metadata carried with the packet.
+    #[inline]
+    pub(crate) fn set_metadata(&mut self, metadata: u64) {
+        self.raw_mut().__bindgen_anon_5.udata64 = metadata;
+    }
+
+    /// Returns the ID the NIC marked the packet with, if the packet matched
+    /// a flow rule with a mark action.
+    ///
+    /// See [`FlowRule::mark`].
+    ///
+    /// [`FlowRule::mark`]: crate::FlowRule::mark
+    #[inline]
+    pub fn flow_mark(&self) -> Option<u32> {
+        let raw = self.raw();
+        if raw.ol_flags & ffi::PKT_RX_FDIR_ID as u64 != 0 {
+            Some(unsafe { raw.__bindgen_anon_4.hash.fdir.hi })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the receive timestamp of the packet, or `None` if the
+    /// packet is not timestamped.
+    ///
+    /// The unit of a timestamp set by the device is device specific.
+    /// Timestamps set with [`set_timestamp`] are in nanoseconds by
+    /// convention.
+    ///
+    /// [`set_timestamp`]: Mbuf::set_timestamp
+    #[inline]
+    pub fn timestamp(&self) -> Option<u64> {
+        let raw = self.raw();
+        if raw.ol_flags & ffi::PKT_RX_TIMESTAMP as u64 != 0 {
+            Some(raw.timestamp)
+        } else {
+            None
+        }
+    }
+
+    /// Sets the receive timestamp of the packet.
+    #[inline]
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        let raw = self.raw_mut();
+        raw.timestamp = timestamp;
+        raw.ol_flags |= ffi::PKT_RX_TIMESTAMP as u64;
+    }
+
+    /// Returns the tag control information of the VLAN tag the device
+    /// stripped from the packet, or `None` if no tag was stripped.
+    ///
+    /// The device strips the outer VLAN tag only if the port is built with
+    /// the `vlan_strip` receive offload. The tag is no longer in the packet
+    /// data, but [`Ethernet::has_vlan`] and the tag accessors still report
+    /// it. To put the tag back on the wire, use [`set_vlan_for_tx`].
+    ///
+    /// [`Ethernet::has_vlan`]: crate::packets::Ethernet::has_vlan
+    /// [`set_vlan_for_tx`]: Mbuf::set_vlan_for_tx
+    #[inline]
+    pub fn vlan_tci(&self) -> Option<u16> {
+        let raw = self.raw();
+        if raw.ol_flags & ffi::PKT_RX_VLAN_STRIPPED as u64 != 0 {
+            Some(raw.vlan_tci)
+        } else {
+            None
+        }
+    }
+
+    /// Sets the tag control information of the stripped VLAN tag, as the
+    /// device does with the `vlan_strip` receive offload.
+    #[inline]
+    pub fn set_vlan_tci(&mut self, tci: u16) {
+        let raw = self.raw_mut();
+        raw.vlan_tci = tci;
+        raw.ol_flags |= (ffi::PKT_RX_VLAN | ffi::PKT_RX_VLAN_STRIPPED) as u64;
+    }
+
+    /// Returns the size of the TCP segments coalesced into the packet, or
+    /// `None` if the packet is not coalesced.
+    ///
+    /// Packets are coalesced by the device with the `tcp_lro` receive
+    /// offload, or in software with the [`gro`] combinator. A coalesced
+    /// packet is usually made of multiple segments, and its IP length
+    /// field may be off. Use [`Packet::full_len`] for the real length.
+    ///
+    /// The size is kept in the same mbuf field as the TSO segment size on
+    /// transmit, so a coalesced packet can be segmented again as it was.
+    ///
+    /// [`gro`]: crate::batch::Batch::gro
+    /// [`Packet::full_len`]: crate::packets::Packet::full_len
+    #[inline]
+    pub fn coalesced_segment_size(&self) -> Option<u16> {
+        let raw = self.raw();
+        if raw.ol_flags & ffi::PKT_RX_LRO as u64 != 0 {
+            Some(unsafe { raw.__bindgen_anon_6.__bindgen_anon_1.tso_segsz() } as u16)
+        } else {
+            None
+        }
+    }
+
+    /// Marks the packet as coalesced from TCP segments of `segment_size`
+    /// bytes, as the device does with the `tcp_lro` receive offload.
+    #[inline]
+    pub fn set_coalesced(&mut self, segment_size: u16) {
+        let raw = self.raw_mut();
+        unsafe {
+            raw.__bindgen_anon_6
+                .__bindgen_anon_1
+                .set_tso_segsz(segment_size.into());
+        }
+        raw.ol_flags |= ffi::PKT_RX_LRO as u64;
+    }
+
+    /// Asks the device to insert a VLAN tag with the tag control information
+    /// when the packet is transmitted, without touching the packet data.
+    ///
+    /// The port must be built with the `vlan_insert` transmit offload,
+    /// otherwise the packet goes out untagged.
+    #[inline]
+    pub fn set_vlan_for_tx(&mut self, tci: u16) {
+        let raw = self.raw_mut();
+        raw.vlan_tci = tci;
+        raw.ol_flags |= ffi::PKT_TX_VLAN_PKT;
+    }
+
     /// Returns amount of data stored in the buffer.
     #[inline]
     pub fn data_len(&self) -> usize {
         self.raw().data_len as usize
     }
 
+    /// Returns the amount of data stored in all the segments of the packet.
+    #[inline]
+    pub fn pkt_len(&self) -> usize {
+        self.raw().pkt_len as usize
+    }
+
+    /// Returns the number of segments of the packet.
+    #[inline]
+    pub fn segments(&self) -> usize {
+        self.raw().nb_segs as usize
+    }
+
+    /// Appends the segments of `tail` to the end of the packet.
+    ///
+    /// The data of `tail` becomes part of the packet without being copied.
+    /// It's sent with the packet on a port with multi-segment transmit
+    /// support, and freed with the packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BufferError::NotChained` if either buffer is a clone, or
+    /// if the packet would exceed the maximum number of segments. `tail`
+    /// is freed on error.
+    pub fn chain(&mut self, tail: Mbuf) -> Result<()> {
+        ensure!(
+            matches!(self.inner, MbufInner::Original(_))
+                && matches!(tail.inner, MbufInner::Original(_)),
+            BufferError::NotChained
+        );
+
+        let tail = tail.into_ptr();
+        let ret = unsafe { ffi::_rte_pktmbuf_chain(self.raw_mut(), tail) };
+        if ret < 0 {
+            unsafe {
+                ffi::_rte_pktmbuf_free(tail);
+            }
+            return Err(BufferError::NotChained.into());
+        }
+
+        Ok(())
+    }
+
+    /// Frees the segments after the first, so the packet is the data of
+    /// the first segment alone.
+    pub(crate) fn free_tail_segments(&mut self) {
+        let raw = self.raw_mut();
+        let tail = raw.next;
+        if !tail.is_null() {
+            raw.next = ptr::null_mut();
+            raw.nb_segs = 1;
+            raw.pkt_len = raw.data_len as u32;
+            unsafe {
+                ffi::_rte_pktmbuf_free(tail);
+            }
+        }
+    }
+
+    /// Copies the data from the offset to the end of the last segment.
+    pub(crate) fn read_segments(&self, offset: usize) -> Vec<u8> {
+        self.read_segments_upto(offset, usize::MAX)
+    }
+
+    /// Copies at most `max` bytes of data from the offset, across the
+    /// segments. The segments past the bytes copied are not read.
+    pub(crate) fn read_segments_upto(&self, offset: usize, max: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(cmp::min(self.pkt_len().saturating_sub(offset), max));
+        let mut skip = offset;
+
+        for segment in super::mbuf_segments(self.as_ptr()) {
+            let left = max - data.len();
+            if left == 0 {
+                break;
+            }
+
+            let segment = unsafe { &*segment };
+            let len = segment.data_len as usize;
+            if skip < len {
+                let count = cmp::min(len - skip, left);
+                unsafe {
+                    let start =
+                        (segment.buf_addr as *const u8).add(segment.data_off as usize + skip);
+                    data.extend_from_slice(slice::from_raw_parts(start, count));
+                }
+            }
+            skip = skip.saturating_sub(len);
+        }
+
+        data
+    }
+
     /// Returns the raw pointer from the offset
     #[inline]
     pub(crate) unsafe fn data_address(&self, offset: usize) -> *mut u8 {
@@ -206,7 +513,7 @@ impl Mbuf {
 
     /// Returns the amount of bytes left in the buffer.
     #[inline]
-    fn tailroom(&self) -> usize {
+    pub(crate) fn tailroom(&self) -> usize {
         let raw = self.raw();
         (raw.buf_len - raw.data_off - raw.data_len) as usize
     }
@@ -302,6 +609,45 @@ impl Mbuf {
         Ok(())
     }
 
+    /// Strips the Ethernet preamble and start frame delimiter from the
+    /// start of the data, if they are there.
+    ///
+    /// NICs strip them on receive, but some raw capture sources keep the
+    /// 7 bytes of `0x55` and the `0xd5` delimiter in front of the frame.
+    /// Call this before `parse::<Ethernet>()` to normalize those buffers.
+    /// The data offset is moved past the 8 bytes, nothing is copied.
+    ///
+    /// Returns whether anything was stripped. A frame that already starts
+    /// with the destination MAC address is left as is, unless the address
+    /// is the multicast `55:55:55:55:55:55` and the source address starts
+    /// with `0xd5`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BufferError::NotResized` if there's no frame after the
+    /// preamble.
+    #[inline]
+    pub fn strip_preamble(&mut self) -> Result<bool> {
+        if self.data_len() < PREAMBLE.len() {
+            return Ok(false);
+        }
+
+        let head = unsafe { slice::from_raw_parts(self.data_address(0), PREAMBLE.len()) };
+        if head != &PREAMBLE[..] {
+            return Ok(false);
+        }
+
+        ensure!(self.data_len() > PREAMBLE.len(), BufferError::NotResized);
+
+        let len = PREAMBLE.len() as u16;
+        let raw = self.raw_mut();
+        raw.data_off += len;
+        raw.data_len -= len;
+        raw.pkt_len -= len as u32;
+
+        Ok(true)
+    }
+
     /// Reads the data at offset as `T` and returns it as a raw pointer.
     ///
     /// # Errors
@@ -412,6 +758,13 @@ impl Mbuf {
         self.read_data_slice(offset, count)
     }
 
+    /// Returns the underlying raw struct pointer without giving up the
+    /// ownership.
+    #[inline]
+    pub(crate) fn as_ptr(&self) -> *mut ffi::rte_mbuf {
+        self.inner.ptr().as_ptr()
+    }
+
     /// Acquires the underlying raw struct pointer.
     ///
     /// The `Mbuf` is consumed. It is the caller's the responsibility to
@@ -438,7 +791,7 @@ impl Mbuf {
 
             ptrs.set_len(len);
             ptrs.into_iter()
-                .map(|ptr| Mbuf::from_ptr(ptr))
+                .map(|ptr| Mbuf::from_new_ptr(ptr))
                 .collect::<Vec<_>>()
         };
 
@@ -450,6 +803,53 @@ impl Mbuf {
         let ptrs = mbufs.into_iter().map(Mbuf::into_ptr).collect::<Vec<_>>();
         super::mbuf_free_bulk(ptrs);
     }
+
+    /// Pushes an Ethernet header built from the template onto each mbuf of
+    /// the burst, overriding the source MAC address of each frame.
+    ///
+    /// The source addresses are assigned in order, starting over from the
+    /// first one if there are fewer addresses than mbufs. The template is
+    /// serialized once for the whole burst, which makes it cheaper than
+    /// pushing and templating each frame separately.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `src_macs` is empty, or if an mbuf fails to
+    /// resize. The frames before the failed one are already stamped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let template = EthernetHeader::new(MacAddr::UNSPECIFIED, gateway, EtherTypes::Ipv4);
+    /// let sources = (1..=100).map(|i| MacAddr::new(0x02, 0, 0, 0, 0, i)).collect::<Vec<_>>();
+    ///
+    /// let mut burst = Mbuf::alloc_bulk(32)?;
+    /// Mbuf::stamp_burst(&mut burst, &template, &sources)?;
+    /// ```
+    pub fn stamp_burst(
+        mbufs: &mut [Mbuf],
+        template: &EthernetHeader,
+        src_macs: &[MacAddr],
+    ) -> Result<()> {
+        ensure!(
+            !src_macs.is_empty(),
+            anyhow!("no source MAC addresses to stamp the burst with.")
+        );
+
+        // the packed header is laid out the same as on the wire.
+        let len = template.len();
+        let mut bytes = unsafe {
+            slice::from_raw_parts(template as *const EthernetHeader as *const u8, len).to_vec()
+        };
+
+        for (mbuf, src) in mbufs.iter_mut().zip(src_macs.iter().cycle()) {
+            bytes[6..12].copy_from_slice(&src.octets());
+            mbuf.extend(0, len)?;
+            mbuf.write_data_slice(0, &bytes)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl fmt::Debug for Mbuf {
@@ -518,6 +918,16 @@ impl Packet for Mbuf {
         0
     }
 
+    /// The mbuf is the end of every envelope chain.
+    #[inline]
+    fn layer_summary(&self) -> Vec<LayerInfo> {
+        vec![LayerInfo {
+            name: self.layer_name(),
+            offset: 0,
+            header_len: 0,
+        }]
+    }
+
     #[inline]
     unsafe fn clone(&self, _internal: Internal) -> Self {
         let raw = self.inner.ptr();
@@ -558,6 +968,7 @@ impl Packet for Mbuf {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::packets::{EtherTypes, Ethernet};
 
     const BUFFER: [u8; 16] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
 
@@ -725,6 +1136,55 @@ mod tests {
         assert!(mbuf.read_data_slice::<u8>(10, 16).is_err());
     }
 
+    #[capsule::test]
+    fn metadata_reset_on_reuse() {
+        let mut mbuf = Mbuf::from_bytes(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(0, mbuf.metadata());
+        mbuf.set_metadata(0xdead_beef);
+        assert_eq!(0xdead_beef, mbuf.metadata());
+
+        // not part of the packet data.
+        let data = mbuf.read_data_slice::<u8>(0, 4).unwrap();
+        assert_eq!(&[1, 2, 3, 4], unsafe { data.as_ref() });
+
+        drop(mbuf);
+        assert_eq!(0, Mbuf::new().unwrap().metadata());
+
+        let mut mbufs = Mbuf::alloc_bulk(4).unwrap();
+        for mbuf in mbufs.iter_mut() {
+            mbuf.set_metadata(1);
+        }
+        drop(mbufs);
+        assert!(Mbuf::alloc_bulk(4)
+            .unwrap()
+            .iter()
+            .all(|mbuf| mbuf.metadata() == 0));
+    }
+
+    #[capsule::test]
+    fn clone_indirect() {
+        let mut mbuf = Mbuf::from_bytes(&[1, 2, 3, 4]).unwrap();
+        mbuf.set_metadata(7);
+        let clone = mbuf.clone_indirect().unwrap();
+        assert_eq!(7, clone.metadata());
+
+        assert_ne!(mbuf.as_ptr(), clone.as_ptr());
+        assert_eq!(4, clone.data_len());
+        assert_eq!(unsafe { mbuf.data_address(0) }, unsafe {
+            clone.data_address(0)
+        });
+
+        // the data buffer outlives the original.
+        drop(mbuf);
+        let data = clone.read_data_slice::<u8>(0, 4).unwrap();
+        assert_eq!(&[1, 2, 3, 4], unsafe { data.as_ref() });
+
+        // bulk free takes care of the clones too.
+        let other = Mbuf::from_bytes(&[5, 6]).unwrap();
+        let other_clone = other.clone_indirect().unwrap();
+        Mbuf::free_bulk(vec![clone, other, other_clone]);
+    }
+
     #[capsule::test]
     fn alloc_bulk() {
         let mbufs = Mbuf::alloc_bulk(8).unwrap();
@@ -734,4 +1194,178 @@ mod tests {
             assert_eq!(0, mbuf.data_len());
         }
     }
+
+    #[capsule::test]
+    fn flow_mark() {
+        let mut mbuf = Mbuf::new().unwrap();
+        assert_eq!(None, mbuf.flow_mark());
+
+        mbuf.raw_mut().__bindgen_anon_4.hash.fdir.hi = 42;
+        assert_eq!(None, mbuf.flow_mark());
+
+        mbuf.raw_mut().ol_flags |= ffi::PKT_RX_FDIR_ID as u64;
+        assert_eq!(Some(42), mbuf.flow_mark());
+    }
+
+    #[capsule::test]
+    fn timestamp() {
+        let mut mbuf = Mbuf::new().unwrap();
+        assert_eq!(None, mbuf.timestamp());
+
+        mbuf.set_timestamp(1_000);
+        assert_eq!(Some(1_000), mbuf.timestamp());
+    }
+
+    #[capsule::test]
+    fn coalesced_segment_size() {
+        let mut mbuf = Mbuf::new().unwrap();
+        assert_eq!(None, mbuf.coalesced_segment_size());
+
+        mbuf.set_coalesced(1448);
+        assert_eq!(Some(1448), mbuf.coalesced_segment_size());
+    }
+
+    #[capsule::test]
+    fn read_segments() {
+        let mut head = Mbuf::from_bytes(&BUFFER).unwrap();
+        let tail = Mbuf::from_bytes(&[42; 8]).unwrap();
+        head.chain(tail).unwrap();
+
+        let data = head.read_segments(4);
+        assert_eq!(20, data.len());
+        assert_eq!(&BUFFER[4..], &data[..12]);
+        assert_eq!(&[42; 8], &data[12..]);
+
+        assert_eq!(vec![42; 2], head.read_segments(22));
+    }
+
+    #[capsule::test]
+    fn read_segments_upto() {
+        let mut head = Mbuf::from_bytes(&BUFFER).unwrap();
+        let tail = Mbuf::from_bytes(&[42; 8]).unwrap();
+        head.chain(tail).unwrap();
+
+        // stops in the middle of the second segment.
+        let data = head.read_segments_upto(12, 7);
+        assert_eq!(&BUFFER[12..], &data[..4]);
+        assert_eq!(&[42; 3], &data[4..]);
+
+        // stops at the end of the first segment.
+        assert_eq!(&BUFFER[..], &head.read_segments_upto(0, BUFFER.len())[..]);
+        assert_eq!(24, head.read_segments_upto(0, 100).len());
+        assert!(head.read_segments_upto(2, 0).is_empty());
+    }
+
+    #[capsule::test]
+    fn vlan_tci() {
+        let mut mbuf = Mbuf::new().unwrap();
+        assert_eq!(None, mbuf.vlan_tci());
+
+        // the tag to insert on transmit is not a stripped tag.
+        mbuf.set_vlan_for_tx(100);
+        assert_eq!(None, mbuf.vlan_tci());
+        assert!(mbuf.raw().ol_flags & ffi::PKT_TX_VLAN_PKT != 0);
+
+        mbuf.set_vlan_tci(200);
+        assert_eq!(Some(200), mbuf.vlan_tci());
+    }
+
+    #[capsule::test]
+    fn mbufs_from_pcap() {
+        use crate::testils::byte_arrays::{IPV4_UDP_PACKET, VLAN_DOT1Q_PACKET};
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/be_nano.pcapng");
+        let mbufs = Mbuf::from_pcap(path).unwrap();
+        assert_eq!(2, mbufs.len());
+
+        let read = |mbuf: &Mbuf| unsafe {
+            mbuf.read_data_slice::<u8>(0, mbuf.data_len())
+                .unwrap()
+                .as_ref()
+                .to_vec()
+        };
+        assert_eq!(&IPV4_UDP_PACKET[..], &read(&mbufs[0])[..]);
+        assert_eq!(&VLAN_DOT1Q_PACKET[..], &read(&mbufs[1])[..]);
+
+        assert!(Mbuf::from_pcap("no_such_capture.pcap").is_err());
+    }
+
+    #[capsule::test]
+    fn chain_segments() {
+        let mut head = Mbuf::from_bytes(&BUFFER).unwrap();
+        let tail = Mbuf::from_bytes(&BUFFER[..8]).unwrap();
+        assert_eq!(1, head.segments());
+
+        assert!(head.chain(tail).is_ok());
+        assert_eq!(2, head.segments());
+        assert_eq!(16, head.data_len());
+        assert_eq!(24, head.pkt_len());
+
+        // frees the segments with the head.
+        Mbuf::free_bulk(vec![head]);
+    }
+
+    #[capsule::test]
+    fn strip_preamble() {
+        let frame = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x01, 0x02, 0x03];
+
+        // a normalized frame is left as is.
+        let mut mbuf = Mbuf::from_bytes(&frame).unwrap();
+        assert!(!mbuf.strip_preamble().unwrap());
+        assert_eq!(frame.len(), mbuf.data_len());
+
+        let mut raw = PREAMBLE.to_vec();
+        raw.extend_from_slice(&frame);
+        let mut mbuf = Mbuf::from_bytes(&raw).unwrap();
+        assert!(mbuf.strip_preamble().unwrap());
+        assert_eq!(frame.len(), mbuf.data_len());
+        assert_eq!(frame.len(), mbuf.raw().pkt_len as usize);
+        let data = mbuf.read_data_slice::<u8>(0, frame.len()).unwrap();
+        assert_eq!(&frame, unsafe { data.as_ref() });
+
+        // only stripped once.
+        assert!(!mbuf.strip_preamble().unwrap());
+
+        // a preamble with no frame after it.
+        let mut mbuf = Mbuf::from_bytes(&PREAMBLE).unwrap();
+        assert!(mbuf.strip_preamble().is_err());
+
+        // too short to have a preamble.
+        let mut mbuf = Mbuf::from_bytes(&PREAMBLE[..4]).unwrap();
+        assert!(!mbuf.strip_preamble().unwrap());
+    }
+
+    #[capsule::test]
+    fn stamp_burst_with_source_macs() {
+        let dst = MacAddr::new(0x02, 0, 0, 0, 0, 0xff);
+        let template = EthernetHeader::new(MacAddr::UNSPECIFIED, dst, EtherTypes::Ipv6);
+        let sources = [
+            MacAddr::new(0x02, 0, 0, 0, 0, 1),
+            MacAddr::new(0x02, 0, 0, 0, 0, 2),
+        ];
+
+        let mut burst = (0..3)
+            .map(|_| Mbuf::from_bytes(&[0xab; 20]).unwrap())
+            .collect::<Vec<_>>();
+        Mbuf::stamp_burst(&mut burst, &template, &sources).unwrap();
+
+        // the sources cycle when there are more frames.
+        for (mbuf, &src) in burst.into_iter().zip(&[sources[0], sources[1], sources[0]]) {
+            assert_eq!(34, mbuf.data_len());
+            let ethernet = mbuf.parse::<Ethernet>().unwrap();
+            assert_eq!(src, ethernet.src());
+            assert_eq!(dst, ethernet.dst());
+            assert_eq!(EtherTypes::Ipv6, ethernet.ether_type());
+
+            let payload = ethernet
+                .mbuf()
+                .read_data_slice::<u8>(ethernet.payload_offset(), 20)
+                .unwrap();
+            assert_eq!(&[0xab; 20], unsafe { payload.as_ref() });
+        }
+
+        let mut burst = vec![Mbuf::new().unwrap()];
+        assert!(Mbuf::stamp_burst(&mut burst, &template, &[]).is_err());
+        assert_eq!(0, burst[0].data_len());
+    }
 }
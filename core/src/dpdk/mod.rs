@@ -16,20 +16,48 @@
 * SPDX-License-Identifier: Apache-2.0
 */
 
+mod backlog;
+mod bond;
+mod burst;
+mod capability;
+mod flow;
 mod kni;
+mod link;
 mod mbuf;
 mod mempool;
+mod offload;
 mod port;
+mod ring;
+mod rss;
+mod rx_filter;
 #[cfg(feature = "metrics")]
 mod stats;
 
 #[allow(unreachable_pub)] // https://github.com/rust-lang/rust/issues/57411
+pub use self::backlog::*;
+#[allow(unreachable_pub)]
+pub use self::bond::*;
+pub use self::burst::*;
+#[allow(unreachable_pub)]
+pub use self::capability::*;
+#[allow(unreachable_pub)]
+pub use self::flow::*;
+#[allow(unreachable_pub)]
 pub use self::kni::*;
 #[allow(unreachable_pub)]
+pub use self::link::*;
+#[allow(unreachable_pub)]
 pub use self::mbuf::*;
 pub(crate) use self::mempool::*;
 #[allow(unreachable_pub)]
+pub use self::offload::*;
+#[allow(unreachable_pub)]
 pub use self::port::*;
+#[allow(unreachable_pub)]
+pub use self::ring::*;
+#[allow(unreachable_pub)]
+pub use self::rss::*;
+pub(crate) use self::rx_filter::*;
 #[cfg(feature = "metrics")]
 pub(crate) use self::stats::*;
 
@@ -206,6 +234,48 @@ pub(crate) fn eal_cleanup() -> Result<()> {
     }
 }
 
+/// Attaches a new device to the bus and probes it.
+///
+/// `bus` is either `pci` or `vdev`. `args` are the additional arguments
+/// to configure a virtual device, and can be empty.
+pub(crate) fn dev_hotplug_add(bus: &str, device: &str, args: &str) -> Result<()> {
+    let bus = bus.into_cstring();
+    let device = device.into_cstring();
+    let args = args.into_cstring();
+
+    unsafe {
+        ffi::rte_eal_hotplug_add(bus.as_ptr(), device.as_ptr(), args.as_ptr())
+            .into_result(DpdkError::from_errno)
+            .map(|_| ())
+    }
+}
+
+/// Removes a device from the bus.
+///
+/// The device's port must be stopped and closed first.
+pub(crate) fn dev_hotplug_remove(bus: &str, device: &str) -> Result<()> {
+    let bus = bus.into_cstring();
+    let device = device.into_cstring();
+
+    unsafe {
+        ffi::rte_eal_hotplug_remove(bus.as_ptr(), device.as_ptr())
+            .into_result(DpdkError::from_errno)
+            .map(|_| ())
+    }
+}
+
+/// Returns the current value of the time-stamp counter (TSC).
+#[inline]
+pub(crate) fn tsc_cycles() -> u64 {
+    unsafe { ffi::_rte_rdtsc() }
+}
+
+/// Returns the number of TSC cycles in one second.
+#[inline]
+pub(crate) fn tsc_hz() -> u64 {
+    unsafe { ffi::rte_get_tsc_hz() }
+}
+
 /// Returns the `MacAddr` of a port.
 fn eth_macaddr_get(port_id: u16) -> MacAddr {
     let mut addr = ffi::rte_ether_addr::default();
@@ -215,10 +285,36 @@ fn eth_macaddr_get(port_id: u16) -> MacAddr {
     addr.addr_bytes.into()
 }
 
+/// Returns the segments of a chained mbuf. The links are read before any
+/// segment is freed.
+fn mbuf_segments(mbuf: *mut ffi::rte_mbuf) -> Vec<*mut ffi::rte_mbuf> {
+    let mut segments = vec![];
+    let mut next = mbuf;
+    while !next.is_null() {
+        segments.push(next);
+        next = unsafe { (*next).next };
+    }
+    segments
+}
+
 /// Frees the `rte_mbuf` in bulk.
 pub(crate) fn mbuf_free_bulk(mbufs: Vec<*mut ffi::rte_mbuf>) {
     assert!(!mbufs.is_empty());
 
+    // detaches the indirect clones, and holds back the mbufs that are still
+    // referenced by a clone. only the rest can go back to the mempool. the
+    // segments of a chained mbuf are freed individually.
+    let mbufs = mbufs
+        .into_iter()
+        .flat_map(mbuf_segments)
+        .map(|mbuf| unsafe { ffi::_rte_pktmbuf_prefree_seg(mbuf) })
+        .filter(|mbuf| !mbuf.is_null())
+        .collect::<Vec<_>>();
+
+    if mbufs.is_empty() {
+        return;
+    }
+
     let mut to_free = Vec::with_capacity(mbufs.len());
     let pool = unsafe { (*mbufs[0]).pool };
 
@@ -0,0 +1,255 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::PortId;
+use crate::ffi;
+use anyhow::Result;
+use serde::Deserialize;
+use std::fmt;
+use thiserror::Error;
+
+/// A receive offload the device performs in hardware.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RxOffload {
+    /// Strips the VLAN tag of the received packets. The tag is kept in
+    /// the mbuf, see [`Mbuf::vlan_tci`].
+    ///
+    /// [`Mbuf::vlan_tci`]: crate::Mbuf::vlan_tci
+    VlanStrip,
+    /// Drops the received packets tagged with a VLAN id not added with
+    /// [`PortId::vlan_filter`].
+    ///
+    /// [`PortId::vlan_filter`]: crate::PortId::vlan_filter
+    VlanFilter,
+    /// Validates the IPv4 header checksum.
+    Ipv4Cksum,
+    /// Validates the UDP checksum.
+    UdpCksum,
+    /// Validates the TCP checksum.
+    TcpCksum,
+    /// Coalesces TCP segments into larger packets.
+    TcpLro,
+    /// Receives packets larger than a single mbuf into chained mbufs.
+    Scatter,
+    /// Timestamps the received packets.
+    Timestamp,
+}
+
+impl RxOffload {
+    /// All the receive offloads.
+    pub(crate) const ALL: [RxOffload; 8] = [
+        RxOffload::VlanStrip,
+        RxOffload::VlanFilter,
+        RxOffload::Ipv4Cksum,
+        RxOffload::UdpCksum,
+        RxOffload::TcpCksum,
+        RxOffload::TcpLro,
+        RxOffload::Scatter,
+        RxOffload::Timestamp,
+    ];
+
+    /// Returns the `DEV_RX_OFFLOAD_*` bit.
+    pub(crate) fn bits(self) -> u64 {
+        let bits = match self {
+            RxOffload::VlanStrip => ffi::DEV_RX_OFFLOAD_VLAN_STRIP,
+            RxOffload::VlanFilter => ffi::DEV_RX_OFFLOAD_VLAN_FILTER,
+            RxOffload::Ipv4Cksum => ffi::DEV_RX_OFFLOAD_IPV4_CKSUM,
+            RxOffload::UdpCksum => ffi::DEV_RX_OFFLOAD_UDP_CKSUM,
+            RxOffload::TcpCksum => ffi::DEV_RX_OFFLOAD_TCP_CKSUM,
+            RxOffload::TcpLro => ffi::DEV_RX_OFFLOAD_TCP_LRO,
+            RxOffload::Scatter => ffi::DEV_RX_OFFLOAD_SCATTER,
+            RxOffload::Timestamp => ffi::DEV_RX_OFFLOAD_TIMESTAMP,
+        };
+        bits as u64
+    }
+}
+
+impl fmt::Display for RxOffload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RxOffload::VlanStrip => "vlan_strip",
+            RxOffload::VlanFilter => "vlan_filter",
+            RxOffload::Ipv4Cksum => "ipv4_cksum",
+            RxOffload::UdpCksum => "udp_cksum",
+            RxOffload::TcpCksum => "tcp_cksum",
+            RxOffload::TcpLro => "tcp_lro",
+            RxOffload::Scatter => "scatter",
+            RxOffload::Timestamp => "timestamp",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A transmit offload the device performs in hardware.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxOffload {
+    /// Inserts the VLAN tag set in the mbuf with [`Mbuf::set_vlan_for_tx`].
+    ///
+    /// [`Mbuf::set_vlan_for_tx`]: crate::Mbuf::set_vlan_for_tx
+    VlanInsert,
+    /// Computes the IPv4 header checksum.
+    Ipv4Cksum,
+    /// Computes the UDP checksum.
+    UdpCksum,
+    /// Computes the TCP checksum.
+    TcpCksum,
+    /// Segments large TCP packets.
+    TcpTso,
+    /// Transmits packets of chained mbufs.
+    MultiSegs,
+}
+
+impl TxOffload {
+    /// All the transmit offloads.
+    pub(crate) const ALL: [TxOffload; 6] = [
+        TxOffload::VlanInsert,
+        TxOffload::Ipv4Cksum,
+        TxOffload::UdpCksum,
+        TxOffload::TcpCksum,
+        TxOffload::TcpTso,
+        TxOffload::MultiSegs,
+    ];
+
+    /// Returns the `DEV_TX_OFFLOAD_*` bit.
+    pub(crate) fn bits(self) -> u64 {
+        let bits = match self {
+            TxOffload::VlanInsert => ffi::DEV_TX_OFFLOAD_VLAN_INSERT,
+            TxOffload::Ipv4Cksum => ffi::DEV_TX_OFFLOAD_IPV4_CKSUM,
+            TxOffload::UdpCksum => ffi::DEV_TX_OFFLOAD_UDP_CKSUM,
+            TxOffload::TcpCksum => ffi::DEV_TX_OFFLOAD_TCP_CKSUM,
+            TxOffload::TcpTso => ffi::DEV_TX_OFFLOAD_TCP_TSO,
+            TxOffload::MultiSegs => ffi::DEV_TX_OFFLOAD_MULTI_SEGS,
+        };
+        bits as u64
+    }
+}
+
+impl fmt::Display for TxOffload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TxOffload::VlanInsert => "vlan_insert",
+            TxOffload::Ipv4Cksum => "ipv4_cksum",
+            TxOffload::UdpCksum => "udp_cksum",
+            TxOffload::TcpCksum => "tcp_cksum",
+            TxOffload::TcpTso => "tcp_tso",
+            TxOffload::MultiSegs => "multi_segs",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Offload errors.
+#[derive(Debug, Error)]
+pub(crate) enum OffloadError {
+    /// The device doesn't have the receive offload.
+    #[error("{0:?} does not support the {1} receive offload.")]
+    UnsupportedRx(PortId, RxOffload),
+
+    /// The device doesn't have the transmit offload.
+    #[error("{0:?} does not support the {1} transmit offload.")]
+    UnsupportedTx(PortId, TxOffload),
+}
+
+/// Checks the receive offloads against the capabilities of the device,
+/// and returns the offload bits to configure the port with.
+pub(crate) fn rx_offload_bits(
+    port_id: PortId,
+    dev_info: &ffi::rte_eth_dev_info,
+    offloads: &[RxOffload],
+) -> Result<u64> {
+    offloads.iter().try_fold(0, |bits, &offload| {
+        if dev_info.rx_offload_capa & offload.bits() == 0 {
+            Err(OffloadError::UnsupportedRx(port_id, offload).into())
+        } else {
+            Ok(bits | offload.bits())
+        }
+    })
+}
+
+/// Checks the transmit offloads against the capabilities of the device,
+/// and returns the offload bits to configure the port with.
+pub(crate) fn tx_offload_bits(
+    port_id: PortId,
+    dev_info: &ffi::rte_eth_dev_info,
+    offloads: &[TxOffload],
+) -> Result<u64> {
+    offloads.iter().try_fold(0, |bits, &offload| {
+        if dev_info.tx_offload_capa & offload.bits() == 0 {
+            Err(OffloadError::UnsupportedTx(port_id, offload).into())
+        } else {
+            Ok(bits | offload.bits())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_supported_offloads() {
+        let mut dev_info = ffi::rte_eth_dev_info::default();
+        dev_info.rx_offload_capa = (ffi::DEV_RX_OFFLOAD_IPV4_CKSUM
+            | ffi::DEV_RX_OFFLOAD_UDP_CKSUM
+            | ffi::DEV_RX_OFFLOAD_SCATTER) as u64;
+        dev_info.tx_offload_capa = ffi::DEV_TX_OFFLOAD_MULTI_SEGS as u64;
+
+        let port_id = PortId::new(0);
+        let rx = rx_offload_bits(
+            port_id,
+            &dev_info,
+            &[RxOffload::Ipv4Cksum, RxOffload::UdpCksum],
+        )
+        .unwrap();
+        assert_eq!(
+            (ffi::DEV_RX_OFFLOAD_IPV4_CKSUM | ffi::DEV_RX_OFFLOAD_UDP_CKSUM) as u64,
+            rx
+        );
+
+        let tx = tx_offload_bits(port_id, &dev_info, &[TxOffload::MultiSegs]).unwrap();
+        assert_eq!(ffi::DEV_TX_OFFLOAD_MULTI_SEGS as u64, tx);
+
+        assert_eq!(0, rx_offload_bits(port_id, &dev_info, &[]).unwrap());
+    }
+
+    #[test]
+    fn report_unsupported_offload() {
+        let mut dev_info = ffi::rte_eth_dev_info::default();
+        dev_info.rx_offload_capa = ffi::DEV_RX_OFFLOAD_IPV4_CKSUM as u64;
+
+        let port_id = PortId::new(0);
+        let err = rx_offload_bits(
+            port_id,
+            &dev_info,
+            &[RxOffload::Ipv4Cksum, RxOffload::TcpCksum],
+        )
+        .unwrap_err();
+        assert_eq!(
+            "port0 does not support the tcp_cksum receive offload.",
+            err.to_string()
+        );
+
+        let err = tx_offload_bits(port_id, &dev_info, &[TxOffload::TcpTso]).unwrap_err();
+        assert_eq!(
+            "port0 does not support the tcp_tso transmit offload.",
+            err.to_string()
+        );
+    }
+}
@@ -16,30 +16,53 @@
 * SPDX-License-Identifier: Apache-2.0
 */
 
-use super::{CoreId, Kni, KniBuilder, KniTxQueue, Mbuf, Mempool, MempoolMap, SocketId};
+use super::{
+    CoreId, Kni, KniBuilder, KniTxQueue, Mbuf, Mempool, MempoolMap, PortCapabilities,
+    RssHashFields, RxBurst, RxBurstMode, RxFilter, RxOffload, SocketId, TxBacklog, TxOffload,
+    TxPolicy, RX_BURST_MAX, RX_BURST_MIN,
+};
+use crate::capture::{CaptureTap, CaptureTarget};
 use crate::dpdk::DpdkError;
 use crate::ffi::{self, AsStr, ToCString, ToResult};
-#[cfg(feature = "metrics")]
-use crate::metrics::{labels, Counter, SINK};
 use crate::net::MacAddr;
+use crate::packets::EtherType;
 #[cfg(feature = "pcap-dump")]
 use crate::pcap;
+use crate::stats::{self, QueueCounters};
 use crate::{debug, ensure, info, warn};
 use anyhow::Result;
-use std::collections::HashMap;
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::mem;
 use std::os::raw;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use thiserror::Error;
 
-const DEFAULT_RSS_HF: u64 =
-    (ffi::ETH_RSS_IP | ffi::ETH_RSS_TCP | ffi::ETH_RSS_UDP | ffi::ETH_RSS_SCTP) as u64;
+/// The bytes a frame takes on top of the MTU, the Ethernet header with up
+/// to two VLAN tags and the CRC.
+const FRAME_OVERHEAD: usize = (ffi::RTE_ETHER_HDR_LEN + 8 + ffi::RTE_ETHER_CRC_LEN) as usize;
+
+/// The largest MTU with frames that fit in a single mbuf segment.
+const MBUF_MAX_MTU: usize = ffi::RTE_MBUF_DEFAULT_DATAROOM as usize - FRAME_OVERHEAD;
+
+/// The largest VLAN id.
+pub(crate) const VLAN_ID_MAX: u16 = 4095;
 
 /// An opaque identifier for an Ethernet device port.
-#[derive(Copy, Clone)]
-pub(crate) struct PortId(u16);
+#[derive(Copy, Clone, Eq, Hash, PartialEq)]
+pub struct PortId(u16);
 
 impl PortId {
+    /// Creates a new port ID from the raw value.
+    #[inline]
+    pub(crate) fn new(raw: u16) -> Self {
+        PortId(raw)
+    }
+
     /// Returns the ID of the socket the port is connected to.
     ///
     /// Virtual devices do not have real socket IDs. The value returned
@@ -61,6 +84,314 @@ impl PortId {
     pub(crate) fn raw(&self) -> u16 {
         self.0
     }
+
+    /// Returns the counters kept by the Ethernet device.
+    ///
+    /// The counters are read without stopping the port, so they can be
+    /// read from the control thread while the pipelines are running.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DpdkError` if the device fails to report the counters.
+    pub fn stats(self) -> Result<EthStats> {
+        let mut stats = ffi::rte_eth_stats::default();
+        let mut dev_info = ffi::rte_eth_dev_info::default();
+        unsafe {
+            ffi::rte_eth_stats_get(self.0, &mut stats).into_result(DpdkError::from_errno)?;
+            ffi::rte_eth_dev_info_get(self.0, &mut dev_info).into_result(DpdkError::from_errno)?;
+        }
+
+        let len = cmp::min(
+            cmp::max(dev_info.nb_rx_queues, dev_info.nb_tx_queues) as usize,
+            ffi::RTE_ETHDEV_QUEUE_STAT_CNTRS as usize,
+        );
+        let queues = (0..len)
+            .map(|i| EthQueueStats {
+                ipackets: stats.q_ipackets[i],
+                opackets: stats.q_opackets[i],
+                ibytes: stats.q_ibytes[i],
+                obytes: stats.q_obytes[i],
+                errors: stats.q_errors[i],
+            })
+            .collect();
+
+        Ok(EthStats {
+            ipackets: stats.ipackets,
+            opackets: stats.opackets,
+            ibytes: stats.ibytes,
+            obytes: stats.obytes,
+            imissed: stats.imissed,
+            ierrors: stats.ierrors,
+            oerrors: stats.oerrors,
+            rx_nombuf: stats.rx_nombuf,
+            queues,
+        })
+    }
+
+    /// Returns the extended counters of the Ethernet device by name. The
+    /// names and the number of counters are driver specific.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DpdkError` if the device fails to report the counters.
+    pub fn xstats(self) -> Result<Vec<(String, u64)>> {
+        let len = unsafe {
+            ffi::rte_eth_xstats_get_names(self.0, ptr::null_mut(), 0)
+                .into_result(DpdkError::from_errno)? as usize
+        };
+
+        let mut names = vec![unsafe { mem::zeroed::<ffi::rte_eth_xstat_name>() }; len];
+        let mut values = vec![ffi::rte_eth_xstat::default(); len];
+        let (names_len, values_len) = unsafe {
+            let names_len =
+                ffi::rte_eth_xstats_get_names(self.0, names.as_mut_ptr(), len as raw::c_uint)
+                    .into_result(DpdkError::from_errno)? as usize;
+            let values_len =
+                ffi::rte_eth_xstats_get(self.0, values.as_mut_ptr(), len as raw::c_uint)
+                    .into_result(DpdkError::from_errno)? as usize;
+            (names_len, values_len)
+        };
+
+        // the counters may have been added to in between the calls, only
+        // the ones read by both are reported.
+        names.truncate(names_len);
+        values.truncate(values_len);
+
+        Ok(values
+            .iter()
+            .filter_map(|xstat| {
+                names
+                    .get(xstat.id as usize)
+                    .map(|name| (name.name[..].as_str().to_owned(), xstat.value))
+            })
+            .collect())
+    }
+
+    /// Resets both the counters and the extended counters of the Ethernet
+    /// device to zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DpdkError` if the device fails to reset the counters.
+    pub fn reset_stats(self) -> Result<()> {
+        unsafe {
+            ffi::rte_eth_stats_reset(self.0).into_result(DpdkError::from_errno)?;
+            ffi::rte_eth_xstats_reset(self.0).into_result(DpdkError::from_errno)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the MTU of the port.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DpdkError` if the device fails to report the MTU.
+    pub fn mtu(self) -> Result<u16> {
+        let mut mtu = 0u16;
+        unsafe {
+            ffi::rte_eth_dev_get_mtu(self.0, &mut mtu).into_result(DpdkError::from_errno)?;
+        }
+        Ok(mtu)
+    }
+
+    /// Sets the MTU of the port.
+    ///
+    /// Besides the range the device supports, the frames must fit in a
+    /// single mbuf segment, which limits the MTU to 2022 bytes. Some
+    /// devices can only change the MTU while the port is stopped.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::InvalidMtu` if the MTU is out of range, or
+    /// `PortError::NotSupported` if the device can't change the MTU.
+    pub fn set_mtu(self, mtu: u16) -> Result<()> {
+        let (min, max) = mtu_range(&self.dev_info()?);
+        ensure!(
+            mtu >= min && mtu <= max,
+            PortError::InvalidMtu(mtu, min, max)
+        );
+
+        let ret = unsafe { ffi::rte_eth_dev_set_mtu(self.0, mtu) };
+        check_supported(ret, self, "changing the MTU")?;
+
+        info!("set {:?} MTU to {}.", self, mtu);
+        Ok(())
+    }
+
+    /// Returns whether promiscuous mode is enabled.
+    pub fn is_promiscuous(self) -> bool {
+        unsafe { ffi::rte_eth_promiscuous_get(self.0) == 1 }
+    }
+
+    /// Enables or disables promiscuous mode.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::NotSupported` if the device doesn't have
+    /// promiscuous mode.
+    pub fn set_promiscuous(self, enabled: bool) -> Result<()> {
+        let ret = unsafe {
+            if enabled {
+                ffi::rte_eth_promiscuous_enable(self.0)
+            } else {
+                ffi::rte_eth_promiscuous_disable(self.0)
+            }
+        };
+        check_supported(ret, self, "promiscuous mode")
+    }
+
+    /// Returns whether all multicast packets are received.
+    pub fn is_allmulticast(self) -> bool {
+        unsafe { ffi::rte_eth_allmulticast_get(self.0) == 1 }
+    }
+
+    /// Enables or disables the reception of all multicast packets.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::NotSupported` if the device doesn't have
+    /// all-multicast mode.
+    pub fn set_allmulticast(self, enabled: bool) -> Result<()> {
+        let ret = unsafe {
+            if enabled {
+                ffi::rte_eth_allmulticast_enable(self.0)
+            } else {
+                ffi::rte_eth_allmulticast_disable(self.0)
+            }
+        };
+        check_supported(ret, self, "all-multicast mode")
+    }
+
+    /// Returns the MAC address of the port.
+    pub fn mac_addr(self) -> MacAddr {
+        super::eth_macaddr_get(self.0)
+    }
+
+    /// Replaces the MAC address of the port.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::NotUnicastMac` if the address is multicast, or
+    /// `PortError::NotSupported` if the device can't change its address.
+    pub fn set_mac_addr(self, addr: MacAddr) -> Result<()> {
+        ensure!(!addr.is_multicast(), PortError::NotUnicastMac(addr));
+
+        let mut addr = ffi::rte_ether_addr {
+            addr_bytes: addr.octets(),
+        };
+        let ret = unsafe { ffi::rte_eth_dev_default_mac_addr_set(self.0, &mut addr) };
+        check_supported(ret, self, "changing the MAC address")
+    }
+
+    /// Adds a secondary unicast MAC address the port receives packets for.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::NotUnicastMac` if the address is multicast, or
+    /// `PortError::NotSupported` if the device doesn't filter on more than
+    /// one address. Returns `DpdkError` if the device has no room for
+    /// another address.
+    pub fn add_mac_addr(self, addr: MacAddr) -> Result<()> {
+        ensure!(!addr.is_multicast(), PortError::NotUnicastMac(addr));
+
+        let mut addr = ffi::rte_ether_addr {
+            addr_bytes: addr.octets(),
+        };
+        let ret = unsafe { ffi::rte_eth_dev_mac_addr_add(self.0, &mut addr, 0) };
+        check_supported(ret, self, "secondary MAC addresses")
+    }
+
+    /// Removes a secondary unicast MAC address.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DpdkError` if the address is the default address of the
+    /// port, or `PortError::NotSupported` if the device doesn't filter on
+    /// more than one address.
+    pub fn remove_mac_addr(self, addr: MacAddr) -> Result<()> {
+        let mut addr = ffi::rte_ether_addr {
+            addr_bytes: addr.octets(),
+        };
+        let ret = unsafe { ffi::rte_eth_dev_mac_addr_remove(self.0, &mut addr) };
+        check_supported(ret, self, "secondary MAC addresses")
+    }
+
+    /// Adds or removes a VLAN id from the VLAN filter of the device.
+    ///
+    /// Once any id is added, the device drops the received packets tagged
+    /// with an id not in the filter. Untagged packets are still received.
+    /// The port must be built with the `vlan_filter` receive offload.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::InvalidVlanId` if the id is out of range,
+    /// `PortError::VlanFilterDisabled` if the port is built without the
+    /// offload, or `PortError::NotSupported` if the device doesn't filter
+    /// VLANs.
+    pub fn vlan_filter(self, vid: u16, on: bool) -> Result<()> {
+        ensure!(vid <= VLAN_ID_MAX, PortError::InvalidVlanId(vid));
+
+        let ret = unsafe { ffi::rte_eth_dev_vlan_filter(self.0, vid, on as raw::c_int) };
+        ensure!(ret != -libc::ENOSYS, PortError::VlanFilterDisabled(self));
+        check_supported(ret, self, "VLAN filtering")
+    }
+
+    /// Returns the contextual information of the device.
+    pub(crate) fn dev_info(self) -> Result<ffi::rte_eth_dev_info> {
+        let mut dev_info = ffi::rte_eth_dev_info::default();
+        unsafe {
+            ffi::rte_eth_dev_info_get(self.0, &mut dev_info).into_result(DpdkError::from_errno)?;
+        }
+        Ok(dev_info)
+    }
+}
+
+/// Maps the queue pair to the device counters of the same index.
+fn map_queue_stats(port_id: PortId, idx: u16) {
+    let stat_idx = idx as u8;
+    let rx = unsafe { ffi::rte_eth_dev_set_rx_queue_stats_mapping(port_id.0, idx, stat_idx) };
+    let tx = unsafe { ffi::rte_eth_dev_set_tx_queue_stats_mapping(port_id.0, idx, stat_idx) };
+    debug!(
+        cond: rx != 0 || tx != 0,
+        message = "device has no queue stats mapping.",
+        port = ?port_id,
+        queue = idx
+    );
+}
+
+/// Returns the range of MTUs both the device and a single mbuf segment
+/// support.
+fn mtu_range(dev_info: &ffi::rte_eth_dev_info) -> (u16, u16) {
+    let max = cmp::min(dev_info.max_mtu as usize, MBUF_MAX_MTU) as u16;
+    (dev_info.min_mtu, max)
+}
+
+/// Fits a queue's descriptor count to the limits of the device. The count
+/// is rounded up to the alignment, then clamped between the smallest and
+/// the largest aligned counts. `0` is kept, which selects the device
+/// default. Returns `None` if no aligned count is within the limits.
+fn fit_descriptors(count: usize, lim: &ffi::rte_eth_desc_lim) -> Option<u16> {
+    if count == 0 {
+        return Some(0);
+    }
+
+    let align = cmp::max(lim.nb_align, 1) as usize;
+    let min = (lim.nb_min as usize + align - 1) / align * align;
+    let max = lim.nb_max as usize / align * align;
+    if min > max || max == 0 {
+        return None;
+    }
+
+    let rounded = (count + align - 1) / align * align;
+    Some(rounded.max(min).min(max) as u16)
+}
+
+/// Converts the return value of a device control call to a result, with a
+/// distinct error when the device doesn't support the operation.
+pub(crate) fn check_supported(ret: raw::c_int, port_id: PortId, op: &'static str) -> Result<()> {
+    ensure!(ret != -libc::ENOTSUP, PortError::NotSupported(port_id, op));
+    ret.into_result(DpdkError::from_errno)?;
+    Ok(())
 }
 
 impl fmt::Debug for PortId {
@@ -69,6 +400,53 @@ impl fmt::Debug for PortId {
     }
 }
 
+/// The counters of a port queue kept by the Ethernet device.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EthQueueStats {
+    /// Total number of successfully received packets.
+    pub ipackets: u64,
+    /// Total number of successfully transmitted packets.
+    pub opackets: u64,
+    /// Total number of successfully received bytes.
+    pub ibytes: u64,
+    /// Total number of successfully transmitted bytes.
+    pub obytes: u64,
+    /// Total number of packets dropped by the receive queue.
+    pub errors: u64,
+}
+
+/// The counters of a port kept by the Ethernet device.
+///
+/// Unlike the port queue counters in [`RuntimeStats`], which count the
+/// packets handed between the queues and the pipelines, these count the
+/// packets seen by the device. Comparing the two tells whether packets
+/// are dropped in the NIC or in the application.
+///
+/// [`RuntimeStats`]: crate::stats::RuntimeStats
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EthStats {
+    /// Total number of successfully received packets.
+    pub ipackets: u64,
+    /// Total number of successfully transmitted packets.
+    pub opackets: u64,
+    /// Total number of successfully received bytes.
+    pub ibytes: u64,
+    /// Total number of successfully transmitted bytes.
+    pub obytes: u64,
+    /// Total number of packets dropped by the device because the receive
+    /// queues are full.
+    pub imissed: u64,
+    /// Total number of erroneous received packets.
+    pub ierrors: u64,
+    /// Total number of failed transmitted packets.
+    pub oerrors: u64,
+    /// Total number of receive mbuf allocation failures.
+    pub rx_nombuf: u64,
+    /// The counters of the configured queues, by queue index. Devices keep
+    /// the counters for up to 16 queues.
+    pub queues: Vec<EthQueueStats>,
+}
+
 /// The index of a receive queue.
 #[derive(Copy, Clone)]
 pub(crate) struct RxQueueIndex(u16);
@@ -102,6 +480,62 @@ pub(crate) enum RxTxQueue {
     Tx(TxQueueIndex),
 }
 
+/// The state of a queue pair that changes on every poll.
+struct QueueState {
+    backlog: TxBacklog,
+    burst: RxBurst,
+}
+
+/// The state of a queue pair, shared by the clones of the queue pair.
+///
+/// A queue pair is polled by the core that owns it, so the state is never
+/// contended and isn't behind a lock. The flag catches the misuse of the
+/// queue pair from two cores at once, the same way a `RefCell` catches a
+/// second borrow. The state, and the packets parked in the backlog, are
+/// dropped with the last clone of the queue pair.
+struct QueueCell {
+    busy: AtomicBool,
+    state: UnsafeCell<QueueState>,
+}
+
+// the flag guarantees exclusive access to the state.
+unsafe impl Sync for QueueCell {}
+
+impl QueueCell {
+    fn new(state: QueueState) -> Self {
+        QueueCell {
+            busy: AtomicBool::new(false),
+            state: UnsafeCell::new(state),
+        }
+    }
+
+    /// Invokes `f` with the state of the queue pair.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the state is in use by another core.
+    fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut QueueState) -> R,
+    {
+        struct Release<'a>(&'a AtomicBool);
+
+        impl Drop for Release<'_> {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::Release);
+            }
+        }
+
+        assert!(
+            !self.busy.swap(true, Ordering::Acquire),
+            "queue pair is polled from two cores at once."
+        );
+        let _release = Release(&self.busy);
+
+        f(unsafe { &mut *self.state.get() })
+    }
+}
+
 /// The receive and transmit queue abstraction. Instead of modeling them
 /// as two standalone queues, in the run-to-completion mode, they are modeled
 /// as a queue pair associated with the core that runs the pipeline from
@@ -113,95 +547,155 @@ pub struct PortQueue {
     rxq: RxQueueIndex,
     txq: TxQueueIndex,
     kni: Option<KniTxQueue>,
-    #[cfg(feature = "metrics")]
-    received: Option<Counter>,
-    #[cfg(feature = "metrics")]
-    transmitted: Option<Counter>,
-    #[cfg(feature = "metrics")]
-    dropped: Option<Counter>,
+    capabilities: Arc<PortCapabilities>,
+    filter: Arc<RxFilter>,
+    capture: CaptureTap,
+    counters: Arc<QueueCounters>,
+    state: Arc<QueueCell>,
 }
 
 impl PortQueue {
-    #[cfg(not(feature = "metrics"))]
-    fn new(port: PortId, rxq: RxQueueIndex, txq: TxQueueIndex) -> Self {
+    fn new(
+        port: PortId,
+        rxq: RxQueueIndex,
+        txq: TxQueueIndex,
+        counters: Arc<QueueCounters>,
+        tx_policy: TxPolicy,
+        tx_backlog: usize,
+        rx_burst: RxBurst,
+    ) -> Self {
+        counters.record_rx_burst(rx_burst.size());
+
         PortQueue {
             port_id: port,
             rxq,
             txq,
             kni: None,
+            capabilities: Default::default(),
+            filter: Default::default(),
+            capture: Default::default(),
+            counters,
+            state: Arc::new(QueueCell::new(QueueState {
+                backlog: TxBacklog::new(tx_policy, tx_backlog),
+                burst: rx_burst,
+            })),
         }
     }
 
-    #[cfg(feature = "metrics")]
-    fn new(port: PortId, rxq: RxQueueIndex, txq: TxQueueIndex) -> Self {
-        PortQueue {
-            port_id: port,
-            rxq,
-            txq,
-            kni: None,
-            received: None,
-            transmitted: None,
-            dropped: None,
+    /// Receives a burst of packets from the receive queue, up to the
+    /// port's configured burst size.
+    ///
+    /// If there are packets left in the transmit backlog, they are retried
+    /// first. With the `Backpressure` policy, the burst is shrunk by the
+    /// length of the backlog. The packets the port's software filter
+    /// rejects are dropped here, before the pipeline sees them.
+    pub(crate) fn receive(&self) -> Vec<Mbuf> {
+        self.receive_with(|burst_size| self.rx_burst(burst_size))
+    }
+
+    /// Receives a burst of packets with `rx`, which is given the burst size
+    /// to poll for.
+    fn receive_with<F>(&self, rx: F) -> Vec<Mbuf>
+    where
+        F: FnOnce(usize) -> Vec<Mbuf>,
+    {
+        let burst_size = self.state.with(|state| {
+            if !state.backlog.is_empty() {
+                state
+                    .backlog
+                    .transmit(vec![], &self.counters, |packets| self.tx_burst(packets));
+            }
+            state.backlog.rx_burst_size(state.burst.size())
+        });
+
+        if burst_size == 0 {
+            return vec![];
+        }
+
+        let mut packets = rx(burst_size);
+
+        let size = self.state.with(|state| {
+            state.burst.record(packets.len());
+            state.burst.size()
+        });
+        self.counters.record_rx_burst(size);
+
+        if !self.filter.is_empty() {
+            let received = packets.len();
+            packets.retain(|mbuf| self.filter.accepts(mbuf));
+            self.counters
+                .record_filtered((received - packets.len()) as u64);
         }
+
+        self.capture.tap(&packets);
+        packets
     }
-    /// Receives a burst of packets from the receive queue, up to a maximum
-    /// of 32 packets.
-    pub(crate) fn receive(&self) -> Vec<Mbuf> {
-        const RX_BURST_MAX: usize = 32;
-        let mut ptrs = Vec::with_capacity(RX_BURST_MAX);
+
+    /// Polls the receive queue for up to `burst_size` packets.
+    fn rx_burst(&self, burst_size: usize) -> Vec<Mbuf> {
+        let mut ptrs = Vec::with_capacity(burst_size);
 
         let len = unsafe {
             ffi::_rte_eth_rx_burst(
                 self.port_id.0,
                 self.rxq.0,
                 ptrs.as_mut_ptr(),
-                RX_BURST_MAX as u16,
+                burst_size as u16,
             )
         };
 
-        #[cfg(feature = "metrics")]
-        self.received.as_ref().unwrap().record(len as u64);
+        self.counters.record_received(len as u64);
 
         unsafe {
             ptrs.set_len(len as usize);
             ptrs.into_iter()
-                .map(|ptr| Mbuf::from_ptr(ptr))
+                .map(|ptr| Mbuf::from_new_ptr(ptr))
                 .collect::<Vec<_>>()
         }
     }
 
-    /// Sends the packets to the transmit queue.
-    pub(crate) fn transmit(&self, packets: Vec<Mbuf>) {
-        let mut ptrs = packets.into_iter().map(Mbuf::into_ptr).collect::<Vec<_>>();
-
-        loop {
-            let to_send = ptrs.len() as u16;
-            let sent = unsafe {
-                ffi::_rte_eth_tx_burst(self.port_id.0, self.txq.0, ptrs.as_mut_ptr(), to_send)
-            };
+    /// Sends as many packets as the transmit queue accepts, and removes
+    /// them from the front of the vec. Returns the number of packets sent.
+    fn tx_burst(&self, packets: &mut Vec<Mbuf>) -> usize {
+        let mut ptrs = packets.iter().map(Mbuf::as_ptr).collect::<Vec<_>>();
+        let sent = unsafe {
+            ffi::_rte_eth_tx_burst(
+                self.port_id.0,
+                self.txq.0,
+                ptrs.as_mut_ptr(),
+                ptrs.len() as u16,
+            )
+        };
 
-            if sent > 0 {
-                #[cfg(feature = "metrics")]
-                self.transmitted.as_ref().unwrap().record(sent as u64);
+        // the ownership of the sent packets is given back to DPDK.
+        packets.drain(..sent as usize).for_each(mem::forget);
+        sent as usize
+    }
 
-                if to_send - sent > 0 {
-                    // still have packets not sent. tx queue is full but still making
-                    // progress. we will keep trying until all packets are sent. drains
-                    // the ones already sent first and try again on the rest.
-                    let _drained = ptrs.drain(..sent as usize).collect::<Vec<_>>();
-                } else {
-                    break;
-                }
-            } else {
-                // tx queue is full and we can't make progress, start dropping packets
-                // to avoid potentially stuck in an endless loop.
-                #[cfg(feature = "metrics")]
-                self.dropped.as_ref().unwrap().record(ptrs.len() as u64);
+    /// Sends the packets to the transmit queue, and returns the number of
+    /// packets dropped.
+    ///
+    /// Packets the transmit queue can't accept are handled according to
+    /// the port's [`TxPolicy`].
+    ///
+    /// [`TxPolicy`]: crate::TxPolicy
+    pub(crate) fn transmit(&self, packets: Vec<Mbuf>) -> usize {
+        self.state.with(|state| {
+            state
+                .backlog
+                .transmit(packets, &self.counters, |packets| self.tx_burst(packets))
+        })
+    }
 
-                super::mbuf_free_bulk(ptrs);
-                break;
+    /// Retries the packets left in the transmit backlog, if there are any.
+    pub(crate) fn flush(&self) {
+        self.state.with(|state| {
+            if !state.backlog.is_empty() {
+                state
+                    .backlog
+                    .transmit(vec![], &self.counters, |packets| self.tx_burst(packets));
             }
-        }
+        });
     }
 
     /// Returns a handle to send packets to the associated KNI interface.
@@ -214,47 +708,38 @@ impl PortQueue {
         self.kni = Some(kni);
     }
 
-    /// Sets the per queue counters. Some device drivers don't track TX
-    /// and RX packets per queue. Instead we will track them here for all
-    /// devices. Additionally we also track the TX packet drops when the
-    /// TX queue is full.
-    #[cfg(feature = "metrics")]
-    fn set_counters(&mut self, port: &str, core_id: CoreId) {
-        let counter = SINK.scoped("port").counter_with_labels(
-            "packets",
-            labels!(
-                "port" => port.to_owned(),
-                "dir" => "rx",
-                "core" => core_id.0.to_string(),
-            ),
-        );
-        self.received = Some(counter);
-
-        let counter = SINK.scoped("port").counter_with_labels(
-            "packets",
-            labels!(
-                "port" => port.to_owned(),
-                "dir" => "tx",
-                "core" => core_id.0.to_string(),
-            ),
-        );
-        self.transmitted = Some(counter);
-
-        let counter = SINK.scoped("port").counter_with_labels(
-            "dropped",
-            labels!(
-                "port" => port.to_owned(),
-                "dir" => "tx",
-                "core" => core_id.0.to_string(),
-            ),
-        );
-        self.dropped = Some(counter);
+    /// Returns the capabilities of the port's device.
+    ///
+    /// The capabilities are read once when the port is built, so checking
+    /// them doesn't cost a call into the driver.
+    pub fn capabilities(&self) -> &PortCapabilities {
+        &self.capabilities
+    }
+
+    /// Sets the capabilities of the port's device.
+    fn set_capabilities(&mut self, capabilities: Arc<PortCapabilities>) {
+        self.capabilities = capabilities;
+    }
+
+    /// Sets the software filter for the received packets.
+    fn set_filter(&mut self, filter: Arc<RxFilter>) {
+        self.filter = filter;
+    }
+
+    /// Sets the capture tap of the packets received by the port.
+    fn set_capture(&mut self, capture: CaptureTap) {
+        self.capture = capture;
     }
 
     /// Returns the MAC address of the port.
     pub fn mac_addr(&self) -> MacAddr {
         super::eth_macaddr_get(self.port_id.0)
     }
+
+    /// Returns the ID of the port.
+    pub fn port_id(&self) -> PortId {
+        self.port_id
+    }
 }
 
 /// Error indicating failed to initialize the port.
@@ -264,6 +749,18 @@ pub(crate) enum PortError {
     #[error("Port {0} is not found.")]
     NotFound(String),
 
+    /// Port with the same name already exists.
+    #[error("Port {0} already exists.")]
+    AlreadyExists(String),
+
+    /// Port has KNI enabled and can't be added or removed at runtime.
+    #[error("Port {0} has KNI enabled and is not hot pluggable.")]
+    KniNotHotpluggable(String),
+
+    /// Port is a bonded port and can't be added or removed at runtime.
+    #[error("Port {0} is a bonded port and is not hot pluggable.")]
+    BondNotHotpluggable(String),
+
     #[error("Port is not bound to any cores.")]
     CoreNotBound,
 
@@ -276,6 +773,42 @@ pub(crate) enum PortError {
     /// assigned to the port.
     #[error("Insufficient number of TX queues '{0}'.")]
     InsufficientTxQueues(usize),
+
+    /// The receive burst size is out of range.
+    #[error("RX burst size '{0}' is not between 1 and 512.")]
+    InvalidRxBurst(usize),
+
+    /// The MTU is out of the range the device and the mbufs support.
+    #[error("MTU '{0}' is not between {1} and {2}.")]
+    InvalidMtu(u16, u16, u16),
+
+    /// The MAC address is a multicast address.
+    #[error("MAC address {0} is not unicast.")]
+    NotUnicastMac(MacAddr),
+
+    /// The device doesn't support the operation.
+    #[error("{0:?} does not support {1}.")]
+    NotSupported(PortId, &'static str),
+
+    /// The queue settings are for a core not assigned to the port.
+    #[error("{0:?} is not assigned to the port.")]
+    QueueCoreNotBound(CoreId),
+
+    /// The core is on a different socket than the port.
+    #[error("{0:?} is on {1:?}, but the port is on {2:?}.")]
+    CrossSocket(CoreId, SocketId, SocketId),
+
+    /// The VLAN id is out of range.
+    #[error("VLAN id '{0}' is not between 0 and 4095.")]
+    InvalidVlanId(u16),
+
+    /// The port is built without the `vlan_filter` receive offload.
+    #[error("{0:?} does not have VLAN filtering enabled.")]
+    VlanFilterDisabled(PortId),
+
+    /// No descriptor count fits the limits of the device.
+    #[error("{0} descriptor count '{1}' can't fit between {2} and {3} in multiples of {4}.")]
+    InvalidDescriptors(&'static str, usize, u16, u16, u16),
 }
 
 /// An Ethernet device port.
@@ -286,6 +819,7 @@ pub(crate) struct Port {
     queues: HashMap<CoreId, PortQueue>,
     kni: Option<Kni>,
     dev_info: ffi::rte_eth_dev_info,
+    lsc: bool,
 }
 
 impl Port {
@@ -302,11 +836,21 @@ impl Port {
         self.name.as_str()
     }
 
+    /// Returns the device name of the port.
+    pub(crate) fn device(&self) -> &str {
+        self.device.as_str()
+    }
+
     /// Returns the MAC address of the port.
     pub(crate) fn mac_addr(&self) -> MacAddr {
         super::eth_macaddr_get(self.id.0)
     }
 
+    /// Returns whether the port has KNI enabled.
+    pub(crate) fn has_kni(&self) -> bool {
+        self.kni.is_some()
+    }
+
     /// Returns the available port queues.
     pub(crate) fn queues(&self) -> &HashMap<CoreId, PortQueue> {
         &self.queues
@@ -369,9 +913,16 @@ impl Drop for Port {
     fn drop(&mut self) {
         debug!("freeing {}.", self.name);
 
+        if self.lsc {
+            super::lsc_unregister(self.id);
+        }
+
         unsafe {
             ffi::rte_eth_dev_close(self.id.0);
         }
+
+        // closing the device removes its flow rules.
+        super::invalidate_flows(self.id);
     }
 }
 
@@ -385,6 +936,17 @@ pub(crate) struct PortBuilder<'a> {
     mempools: MempoolMap<'a>,
     rxd: u16,
     txd: u16,
+    tx_policy: TxPolicy,
+    tx_backlog: usize,
+    rx_burst: usize,
+    rx_burst_mode: RxBurstMode,
+    mtu: Option<u16>,
+    queue_capacities: HashMap<CoreId, (u16, u16)>,
+    cross_socket: bool,
+    rx_offloads: u64,
+    tx_offloads: u64,
+    filter: RxFilter,
+    vlan_ids: Vec<u16>,
 }
 
 impl<'a> PortBuilder<'a> {
@@ -404,7 +966,7 @@ impl<'a> PortBuilder<'a> {
                 .into_result(DpdkError::from_errno)?;
         }
 
-        let port_id = PortId(port_id);
+        let port_id = PortId::new(port_id);
         debug!("{} is {:?}.", name, port_id);
 
         let mut dev_info = ffi::rte_eth_dev_info::default();
@@ -421,13 +983,25 @@ impl<'a> PortBuilder<'a> {
             mempools: Default::default(),
             rxd: 0,
             txd: 0,
+            tx_policy: TxPolicy::default(),
+            tx_backlog: 0,
+            rx_burst: 32,
+            rx_burst_mode: RxBurstMode::default(),
+            mtu: None,
+            queue_capacities: HashMap::new(),
+            cross_socket: false,
+            rx_offloads: 0,
+            tx_offloads: 0,
+            filter: RxFilter::default(),
+            vlan_ids: vec![],
         })
     }
 
     /// Sets the processing cores assigned to the port.
     ///
     /// Each core assigned will receive from and transmit through the port
-    /// independently using the run-to-completion model.
+    /// independently using the run-to-completion model. The position of
+    /// the core in the list is the index of the queue pair it services.
     ///
     /// # Errors
     ///
@@ -436,9 +1010,12 @@ impl<'a> PortBuilder<'a> {
     pub(crate) fn cores(&mut self, cores: &[CoreId]) -> Result<&mut Self> {
         ensure!(!cores.is_empty(), PortError::CoreNotBound);
 
-        let mut cores = cores.to_vec();
-        cores.sort();
-        cores.dedup();
+        let mut unique = HashSet::new();
+        let cores = cores
+            .iter()
+            .copied()
+            .filter(|&core_id| unique.insert(core_id))
+            .collect::<Vec<_>>();
         let len = cores.len() as u16;
 
         ensure!(
@@ -454,42 +1031,184 @@ impl<'a> PortBuilder<'a> {
         Ok(self)
     }
 
+    /// Fits a descriptor count to the limits of the device, with a warning
+    /// if the count is adjusted.
+    fn fit_descriptors(
+        &self,
+        dir: &'static str,
+        count: usize,
+        lim: &ffi::rte_eth_desc_lim,
+    ) -> Result<u16> {
+        let fitted = fit_descriptors(count, lim).ok_or(PortError::InvalidDescriptors(
+            dir,
+            count,
+            lim.nb_min,
+            lim.nb_max,
+            lim.nb_align,
+        ))?;
+
+        warn!(
+            cond: fitted as usize != count,
+            message = "adjusted descriptor count to the device limits.",
+            port = self.name.as_str(),
+            dir,
+            before = count,
+            after = fitted
+        );
+        Ok(fitted)
+    }
+
     /// Sets the receive and transmit queues' capacity.
     ///
     /// `rxd` is the receive queue capacity and `txd` is the trasmit queue
     /// capacity. The values are checked against the descriptor limits of
-    /// the Ethernet device, and are adjusted if they exceed the boundaries.
+    /// the Ethernet device, and are rounded to the alignment and clamped
+    /// to the boundaries with a warning.
     ///
     /// # Errors
     ///
-    /// If the adjustment failed, `DpdkError` is returned.
+    /// If no count fits the limits, `PortError` is returned.
     pub(crate) fn rx_tx_queue_capacity(&mut self, rxd: usize, txd: usize) -> Result<&mut Self> {
-        let mut rxd2 = rxd as u16;
-        let mut txd2 = txd as u16;
+        self.rxd = self.fit_descriptors("rx", rxd, &self.dev_info.rx_desc_lim)?;
+        self.txd = self.fit_descriptors("tx", txd, &self.dev_info.tx_desc_lim)?;
+        Ok(self)
+    }
 
-        unsafe {
-            ffi::rte_eth_dev_adjust_nb_rx_tx_desc(self.port_id.0, &mut rxd2, &mut txd2)
-                .into_result(DpdkError::from_errno)?;
-        }
+    /// Overrides the receive and transmit queues' capacity of the queue
+    /// pair a core services. The port's capacity is used for the values
+    /// not set. The values are adjusted to the descriptor limits of the
+    /// Ethernet device like the port's.
+    ///
+    /// # Errors
+    ///
+    /// If the core is not assigned to the port, or no count fits the
+    /// limits, `PortError` is returned.
+    pub(crate) fn queue_capacity(
+        &mut self,
+        core_id: CoreId,
+        rxd: Option<usize>,
+        txd: Option<usize>,
+    ) -> Result<&mut Self> {
+        ensure!(
+            self.cores.contains(&core_id),
+            PortError::QueueCoreNotBound(core_id)
+        );
 
-        info!(
-            cond: rxd2 != rxd as u16,
-            message = "adjusted rxd.",
-            before = rxd,
-            after = rxd2
+        let rxd = match rxd {
+            Some(rxd) => self.fit_descriptors("rx", rxd, &self.dev_info.rx_desc_lim)?,
+            None => self.rxd,
+        };
+        let txd = match txd {
+            Some(txd) => self.fit_descriptors("tx", txd, &self.dev_info.tx_desc_lim)?,
+            None => self.txd,
+        };
+
+        debug!(
+            message = "overrode queue capacity.",
+            core = ?core_id,
+            rxd = rxd,
+            txd = txd
         );
-        info!(
-            cond: txd2 != txd as u16,
-            message = "adjusted txd.",
-            before = txd,
-            after = txd2
+        self.queue_capacities.insert(core_id, (rxd, txd));
+        Ok(self)
+    }
+
+    /// Sets whether the cores can be on a different socket than the port.
+    ///
+    /// Crossing sockets slows down every packet, so it's rejected unless
+    /// allowed explicitly. Virtual devices don't have a socket, and are
+    /// never rejected.
+    pub(crate) fn cross_socket(&mut self, allowed: bool) -> &mut Self {
+        self.cross_socket = allowed;
+        self
+    }
+
+    /// Sets the policy for packets the transmit queues can't accept.
+    ///
+    /// `backlog` is the maximum number of packets each queue holds on to
+    /// for retry. It's not used with the `DropNewest` policy.
+    pub(crate) fn tx_policy(&mut self, policy: TxPolicy, backlog: usize) -> &mut Self {
+        self.tx_policy = policy;
+        self.tx_backlog = backlog;
+        self
+    }
+
+    /// Sets the number of packets each receive queue is polled for at a
+    /// time, and how the size adapts to the traffic.
+    ///
+    /// With `RxBurstMode::Adaptive`, `size` is the largest burst.
+    ///
+    /// # Errors
+    ///
+    /// If the size is not between 1 and 512, `PortError` is returned.
+    pub(crate) fn rx_burst(&mut self, size: usize, mode: RxBurstMode) -> Result<&mut Self> {
+        ensure!(
+            (RX_BURST_MIN..=RX_BURST_MAX).contains(&size),
+            PortError::InvalidRxBurst(size)
         );
 
-        self.rxd = rxd2;
-        self.txd = txd2;
+        self.rx_burst = size;
+        self.rx_burst_mode = mode;
+        Ok(self)
+    }
+
+    /// Sets the hardware offloads of the port.
+    ///
+    /// # Errors
+    ///
+    /// If the device doesn't have one of the offloads, `OffloadError` is
+    /// returned naming the offload.
+    pub(crate) fn offloads(&mut self, rx: &[RxOffload], tx: &[TxOffload]) -> Result<&mut Self> {
+        self.rx_offloads = super::rx_offload_bits(self.port_id, &self.dev_info, rx)?;
+        self.tx_offloads = super::tx_offload_bits(self.port_id, &self.dev_info, tx)?;
+        Ok(self)
+    }
+
+    /// Sets the EtherTypes and the VLAN ids of the packets the port
+    /// receives. An empty list accepts all.
+    ///
+    /// The VLAN ids are filtered by the device if it has the `vlan_filter`
+    /// receive offload, and in software by the port queues otherwise. The
+    /// EtherTypes are always filtered in software. Either way, the packets
+    /// are dropped before they reach the pipelines.
+    ///
+    /// # Errors
+    ///
+    /// If a VLAN id is out of range, `PortError` is returned.
+    pub(crate) fn rx_filter(
+        &mut self,
+        ether_types: &[EtherType],
+        vlan_ids: &[u16],
+    ) -> Result<&mut Self> {
+        if let Some(&vid) = vlan_ids.iter().find(|&&vid| vid > VLAN_ID_MAX) {
+            return Err(PortError::InvalidVlanId(vid).into());
+        }
+
+        if !ether_types.is_empty() {
+            self.filter.ether_types(ether_types);
+        }
+
+        if !vlan_ids.is_empty() {
+            if self.dev_info.rx_offload_capa & RxOffload::VlanFilter.bits() > 0 {
+                self.vlan_ids = vlan_ids.to_vec();
+            } else {
+                debug!(
+                    message = "device can't filter VLANs, filtering in software.",
+                    port = self.name.as_str()
+                );
+                self.filter.vlan_ids(vlan_ids);
+            }
+        }
+
         Ok(self)
     }
 
+    /// Sets the MTU of the port. The device default is kept if not set.
+    pub(crate) fn mtu(&mut self, mtu: Option<u16>) -> &mut Self {
+        self.mtu = mtu;
+        self
+    }
+
     /// Sets the available mempools.
     pub(crate) fn mempools(&'a mut self, mempools: &'a mut [Mempool]) -> &'a mut Self {
         self.mempools = MempoolMap::new(mempools);
@@ -506,12 +1225,24 @@ impl<'a> PortBuilder<'a> {
     ) -> anyhow::Result<Port> {
         let len = self.cores.len() as u16;
         let mut conf = ffi::rte_eth_conf::default();
+        conf.rxmode.offloads = self.rx_offloads;
+        conf.txmode.offloads = self.tx_offloads;
+
+        if !self.vlan_ids.is_empty() {
+            conf.rxmode.offloads |= RxOffload::VlanFilter.bits();
+        }
+
+        // caps the coalesced packets so their length still fits in the
+        // 16-bit IP length fields.
+        if self.rx_offloads & ffi::DEV_RX_OFFLOAD_TCP_LRO as u64 > 0 {
+            conf.rxmode.max_lro_pkt_size = self.dev_info.max_lro_pkt_size.min(u16::MAX.into());
+        }
 
         // turns on receive side scaling if port has multiple cores.
         if len > 1 {
             conf.rxmode.mq_mode = ffi::rte_eth_rx_mq_mode::ETH_MQ_RX_RSS;
             conf.rx_adv_conf.rss_conf.rss_hf =
-                DEFAULT_RSS_HF & self.dev_info.flow_type_rss_offloads;
+                RssHashFields::L3_L4.bits() & self.dev_info.flow_type_rss_offloads;
         }
 
         // turns on optimization for fast release of mbufs.
@@ -520,20 +1251,64 @@ impl<'a> PortBuilder<'a> {
             debug!("turned on optimization for fast release of mbufs.");
         }
 
+        // turns on the link status change interrupt if the device has it,
+        // otherwise the link is polled.
+        let lsc = super::lsc_supported(&self.dev_info);
+        if lsc {
+            conf.intr_conf.set_lsc(1);
+        }
+
         // must configure the device first before everything else.
         unsafe {
             ffi::rte_eth_dev_configure(self.port_id.0, len, len, &conf)
                 .into_result(DpdkError::from_errno)?;
         }
 
+        if lsc {
+            super::lsc_register(self.port_id)?;
+        }
+
+        // programs the VLAN filter of the device. if the device rejects
+        // the ids, the port queues filter them in software instead.
+        let port_id = self.port_id;
+        if let Err(err) = self
+            .vlan_ids
+            .iter()
+            .try_for_each(|&vid| port_id.vlan_filter(vid, true))
+        {
+            warn!(
+                message = "failed to set VLAN filter, filtering in software.",
+                port = self.name.as_str(),
+                ?err
+            );
+            self.filter.vlan_ids(&self.vlan_ids);
+        }
+
         // if the port is virtual, we will allocate it to the socket of
         // the first assigned core.
-        let socket_id = self
-            .port_id
-            .socket_id()
-            .unwrap_or_else(|| self.cores[0].socket_id());
+        let port_socket = self.port_id.socket_id();
+        let socket_id = port_socket.unwrap_or_else(|| self.cores[0].socket_id());
         debug!("{} connected to {:?}.", self.name, socket_id);
 
+        // for best performance, the port and cores should connect to the
+        // same socket.
+        for &core_id in self.cores.iter() {
+            if core_id.socket_id() == socket_id {
+                continue;
+            }
+
+            ensure!(
+                self.cross_socket || port_socket.is_none(),
+                PortError::CrossSocket(core_id, core_id.socket_id(), socket_id)
+            );
+            warn!(
+                message = "core socket does not match port socket.",
+                core = ?core_id,
+                core_socket = core_id.socket_id().0,
+                port_socket = socket_id.0
+            );
+        }
+
         // the socket determines which pool to allocate mbufs from.
         let mempool = self.mempools.get_raw(socket_id)?;
 
@@ -550,19 +1325,17 @@ impl<'a> PortBuilder<'a> {
         };
 
         let mut queues = HashMap::new();
+        let capabilities = Arc::new(PortCapabilities::new(&self.dev_info));
+        let filter = Arc::new(self.filter.clone());
 
         // for each core, we setup a rx/tx queue pair. for simplicity, we
         // will use the same index for both queues.
         for (idx, &core_id) in self.cores.iter().enumerate() {
-            // for best performance, the port and cores should connect to
-            // the same socket.
-            warn!(
-                cond: core_id.socket_id() != socket_id,
-                message = "core socket does not match port socket.",
-                core = ?core_id,
-                core_socket = core_id.socket_id().0,
-                port_socket = socket_id.0
-            );
+            let (rxd, txd) = self
+                .queue_capacities
+                .get(&core_id)
+                .copied()
+                .unwrap_or((self.rxd, self.txd));
 
             // configures the RX queue with defaults
             let rxq = RxQueueIndex(idx as u16);
@@ -570,7 +1343,7 @@ impl<'a> PortBuilder<'a> {
                 ffi::rte_eth_rx_queue_setup(
                     self.port_id.0,
                     rxq.0,
-                    self.rxd,
+                    rxd,
                     socket_id.0 as raw::c_uint,
                     ptr::null(),
                     mempool,
@@ -584,7 +1357,7 @@ impl<'a> PortBuilder<'a> {
                 ffi::rte_eth_tx_queue_setup(
                     self.port_id.0,
                     txq.0,
-                    self.txd,
+                    txd,
                     socket_id.0 as raw::c_uint,
                     ptr::null(),
                 )
@@ -608,33 +1381,57 @@ impl<'a> PortBuilder<'a> {
                 )?;
             }
 
-            let mut q = PortQueue::new(self.port_id, rxq, txq);
+            // maps the queues to the device counters of the same index, so
+            // `PortId::stats` reports them by queue index. most devices
+            // keep the counters by queue already, and don't have mappings.
+            if idx < ffi::RTE_ETHDEV_QUEUE_STAT_CNTRS as usize {
+                map_queue_stats(self.port_id, idx as u16);
+            }
+
+            // some device drivers don't track TX and RX packets per queue.
+            // instead we will track them here for all devices.
+            let counters = stats::register_queue(&self.name, core_id);
+            let rx_burst = RxBurst::new(self.rx_burst_mode, self.rx_burst);
+            let mut q = PortQueue::new(
+                self.port_id,
+                rxq,
+                txq,
+                counters,
+                self.tx_policy,
+                self.tx_backlog,
+                rx_burst,
+            );
+            q.set_capabilities(capabilities.clone());
+            q.set_filter(filter.clone());
+            q.set_capture(CaptureTap::new(CaptureTarget::Port(self.name.clone())));
 
             if let Some(kni) = &kni {
                 q.set_kni(kni.txq());
             }
 
-            #[cfg(feature = "metrics")]
-            q.set_counters(&self.name, core_id);
-
             queues.insert(core_id, q);
             debug!("initialized port queue for {:?}.", core_id);
         }
 
-        unsafe {
-            // sets the port's promiscuous mode.
-            if promiscuous {
-                ffi::rte_eth_promiscuous_enable(self.port_id.0);
-            } else {
-                ffi::rte_eth_promiscuous_disable(self.port_id.0);
-            }
+        // sets the port's promiscuous and multicast modes. not every device
+        // has them, which isn't fatal to the port.
+        if let Err(err) = self.port_id.set_promiscuous(promiscuous) {
+            warn!(
+                message = "failed to set promiscuous mode.",
+                port = self.name.as_str(),
+                ?err
+            );
+        }
+        if let Err(err) = self.port_id.set_allmulticast(multicast) {
+            warn!(
+                message = "failed to set multicast mode.",
+                port = self.name.as_str(),
+                ?err
+            );
+        }
 
-            // sets the port's multicast mode.
-            if multicast {
-                ffi::rte_eth_allmulticast_enable(self.port_id.0);
-            } else {
-                ffi::rte_eth_allmulticast_disable(self.port_id.0);
-            }
+        if let Some(mtu) = self.mtu {
+            self.port_id.set_mtu(mtu)?;
         }
 
         info!("initialized port {}.", self.name);
@@ -646,6 +1443,295 @@ impl<'a> PortBuilder<'a> {
             queues,
             kni,
             dev_info: self.dev_info,
+            lsc,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::TestVdev;
+    use std::cell::RefCell;
+
+    fn new_queue(mode: RxBurstMode, size: usize) -> PortQueue {
+        PortQueue::new(
+            PortId::new(0),
+            RxQueueIndex(0),
+            TxQueueIndex(0),
+            Arc::new(QueueCounters::default()),
+            TxPolicy::DropNewest,
+            0,
+            RxBurst::new(mode, size),
+        )
+    }
+
+    #[test]
+    fn poll_with_configured_burst() {
+        let q = new_queue(RxBurstMode::Fixed, 8);
+        let requested = RefCell::new(vec![]);
+
+        // a mock receive queue that is always empty.
+        let mock_rx = |size: usize| {
+            requested.borrow_mut().push(size);
+            vec![]
+        };
+
+        for _ in 0..20 {
+            let _ = q.receive_with(mock_rx);
+        }
+
+        assert_eq!(vec![8; 20], *requested.borrow());
+        assert_eq!(8, q.counters.snapshot().rx_burst);
+    }
+
+    #[test]
+    fn poll_with_adaptive_burst() {
+        let q = new_queue(RxBurstMode::Adaptive, 64);
+        let requested = RefCell::new(vec![]);
+
+        let mock_rx = |size: usize| {
+            requested.borrow_mut().push(size);
+            vec![]
+        };
+
+        for _ in 0..20 {
+            let _ = q.receive_with(mock_rx);
+        }
+
+        // the queue stays empty, the burst shrinks and it shows in stats.
+        assert_eq!(64, requested.borrow()[0]);
+        assert_eq!(32, *requested.borrow().last().unwrap());
+        assert_eq!(32, q.counters.snapshot().rx_burst);
+    }
+
+    #[capsule::test]
+    fn drop_filtered_packets() {
+        use crate::packets::EtherTypes;
+        use crate::testils::byte_arrays::{ARP4_PACKET, IPV4_UDP_PACKET};
+
+        let mut q = new_queue(RxBurstMode::Fixed, 8);
+        let mut filter = RxFilter::default();
+        filter.ether_types(&[EtherTypes::Ipv4]);
+        q.set_filter(Arc::new(filter));
+
+        let mock_rx = |_| {
+            vec![
+                Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap(),
+                Mbuf::from_bytes(&ARP4_PACKET).unwrap(),
+                Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap(),
+            ]
+        };
+
+        let packets = q.receive_with(mock_rx);
+        assert_eq!(2, packets.len());
+
+        // the filtered packets are counted apart from the pipeline's.
+        let stats = q.counters.snapshot();
+        assert_eq!(1, stats.filtered);
+        assert_eq!(0, stats.dropped);
+    }
+
+    #[test]
+    fn mtu_range_fits_in_mbuf() {
+        let mut dev_info = ffi::rte_eth_dev_info::default();
+        dev_info.min_mtu = 68;
+        dev_info.max_mtu = 9000;
+        assert_eq!((68, 2022), mtu_range(&dev_info));
+
+        dev_info.max_mtu = 1500;
+        assert_eq!((68, 1500), mtu_range(&dev_info));
+
+        // the largest frame fills the mbuf exactly.
+        assert_eq!(
+            ffi::RTE_MBUF_DEFAULT_DATAROOM as usize,
+            MBUF_MAX_MTU + FRAME_OVERHEAD
+        );
+    }
+
+    #[test]
+    fn fit_descriptors_to_device_limits() {
+        let mut dev_info = ffi::rte_eth_dev_info::default();
+        dev_info.rx_desc_lim = ffi::rte_eth_desc_lim {
+            nb_max: 1024,
+            nb_min: 64,
+            nb_align: 32,
+            ..Default::default()
+        };
+        let lim = &dev_info.rx_desc_lim;
+
+        assert_eq!(Some(512), fit_descriptors(512, lim));
+        assert_eq!(Some(1024), fit_descriptors(4096, lim));
+        assert_eq!(Some(64), fit_descriptors(16, lim));
+        assert_eq!(Some(128), fit_descriptors(100, lim));
+        assert_eq!(Some(0), fit_descriptors(0, lim));
+
+        // rounds down when rounding up exceeds the largest count.
+        dev_info.rx_desc_lim.nb_max = 1000;
+        assert_eq!(Some(992), fit_descriptors(1000, &dev_info.rx_desc_lim));
+
+        // no count is aligned between the limits.
+        dev_info.rx_desc_lim = ffi::rte_eth_desc_lim {
+            nb_max: 100,
+            nb_min: 70,
+            nb_align: 64,
+            ..Default::default()
+        };
+        assert_eq!(None, fit_descriptors(80, &dev_info.rx_desc_lim));
+
+        let err = PortError::InvalidDescriptors("rx", 80, 70, 100, 64);
+        assert_eq!(
+            "rx descriptor count '80' can't fit between 70 and 100 in multiples of 64.",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn not_supported_is_distinct() {
+        let port_id = PortId::new(0);
+        assert!(check_supported(0, port_id, "test").is_ok());
+
+        let err = check_supported(-libc::ENOTSUP, port_id, "test").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PortError>(),
+            Some(PortError::NotSupported(_, "test"))
+        ));
+        assert_eq!("port0 does not support test.", err.to_string());
+
+        let err = check_supported(-libc::EINVAL, port_id, "test").unwrap_err();
+        assert!(err.downcast_ref::<PortError>().is_none());
+    }
+
+    #[capsule::test]
+    fn ring_device_control() {
+        let core_id = CoreId::new(0);
+        let vdev = TestVdev::new("net_ring");
+        let mut mempools = vec![Mempool::new(63, 0, core_id.socket_id()).unwrap()];
+
+        let mut port = vdev.port("ring0", &[core_id], &mut mempools);
+        port.start().unwrap();
+
+        let id = port.id();
+        assert!(!id.is_promiscuous());
+        id.set_promiscuous(true).unwrap();
+        assert!(id.is_promiscuous());
+        id.set_promiscuous(false).unwrap();
+        assert!(!id.is_promiscuous());
+
+        id.set_allmulticast(true).unwrap();
+        assert!(id.is_allmulticast());
+        id.set_allmulticast(false).unwrap();
+        assert!(!id.is_allmulticast());
+
+        // invalid values are rejected before reaching the device.
+        let mtu = id.mtu().unwrap();
+        assert!(id.set_mtu(9000).is_err());
+        assert!(id.set_mtu(0).is_err());
+        assert_eq!(mtu, id.mtu().unwrap());
+
+        let mac = id.mac_addr();
+        assert_eq!(mac, port.mac_addr());
+        assert!(id.set_mac_addr(MacAddr::BROADCAST).is_err());
+        assert!(id
+            .add_mac_addr(MacAddr::new(0x01, 0, 0x5e, 0, 0, 1))
+            .is_err());
+        assert_eq!(mac, id.mac_addr());
+
+        // the ring device has no hardware behind it to offload to.
+        let caps = id.capabilities().unwrap();
+        assert_eq!("net_ring", caps.driver);
+        assert!(caps.max_rx_queues > 0);
+        assert!(!caps.has_tx_offload(TxOffload::TcpTso));
+        assert!(!caps.has_rss());
+        assert_eq!(&caps, port.queues()[&core_id].capabilities());
+
+        port.stop();
+    }
+
+    #[capsule::test]
+    fn null_device_stats() {
+        let core_id = CoreId::new(0);
+        let vdev = TestVdev::new("net_null");
+        let mut mempools = vec![Mempool::new(127, 0, core_id.socket_id()).unwrap()];
+
+        let mut port = vdev.port("null0", &[core_id], &mut mempools);
+        port.start().unwrap();
+
+        let id = port.id();
+        id.reset_stats().unwrap();
+        assert_eq!(0, id.stats().unwrap().ipackets);
+
+        // the null device fills every receive burst, and accepts every
+        // transmitted packet.
+        let q = port.queues()[&core_id].clone();
+        let packets = q.receive();
+        let received = packets.len() as u64;
+        assert!(received > 0);
+        q.transmit(packets);
+
+        let stats = id.stats().unwrap();
+        assert_eq!(received, stats.ipackets);
+        assert_eq!(received, stats.opackets);
+        assert_eq!(1, stats.queues.len());
+        assert_eq!(received, stats.queues[0].ipackets);
+
+        let xstats = id.xstats().unwrap();
+        assert!(xstats
+            .iter()
+            .any(|(name, value)| name == "rx_good_packets" && *value == received));
+
+        id.reset_stats().unwrap();
+        assert_eq!(0, id.stats().unwrap().ipackets);
+        assert_eq!(0, id.stats().unwrap().opackets);
+
+        port.stop();
+    }
+
+    #[capsule::test]
+    fn ring_device_queue_stats() {
+        let cores = [CoreId::new(1), CoreId::new(0)];
+        let vdev = TestVdev::new("net_ring");
+        let mut mempools = vec![Mempool::new(63, 0, cores[0].socket_id()).unwrap()];
+
+        let mut builder = vdev.port_builder("ring1");
+        builder
+            .cores(&cores)
+            .unwrap()
+            .rx_tx_queue_capacity(32, 32)
+            .unwrap()
+            .queue_capacity(CoreId::new(0), Some(64), None)
+            .unwrap();
+        assert!(builder.queue_capacity(CoreId::new(2), None, None).is_err());
+
+        let mut port = builder
+            .mempools(&mut mempools)
+            .finish(false, false, false)
+            .unwrap();
+        port.start().unwrap();
+
+        // the ring device loops each transmit queue back to the receive
+        // queue of the same index.
+        let queues = port.queues();
+        let first = &queues[&cores[0]];
+        let second = &queues[&cores[1]];
+        assert_eq!(0, first.rxq.0);
+        assert_eq!(1, second.rxq.0);
+
+        let packets = (0..3).map(|_| Mbuf::new().unwrap()).collect();
+        assert_eq!(3, first.transmit(packets));
+        let packets = (0..5).map(|_| Mbuf::new().unwrap()).collect();
+        assert_eq!(5, second.transmit(packets));
+        assert_eq!(3, first.receive().len());
+        assert_eq!(5, second.receive().len());
+
+        let stats = port.id().stats().unwrap();
+        assert_eq!(2, stats.queues.len());
+        assert_eq!(3, stats.queues[0].opackets);
+        assert_eq!(3, stats.queues[0].ipackets);
+        assert_eq!(5, stats.queues[1].opackets);
+        assert_eq!(5, stats.queues[1].ipackets);
+        assert_eq!(8, stats.ipackets);
+
+        port.stop();
+    }
+}
@@ -0,0 +1,615 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{Mbuf, SocketId};
+use crate::dpdk::DpdkError;
+use crate::ffi::{self, AsStr, ToCString, ToResult};
+use crate::packets::{Ethernet, Packet};
+use crate::{debug, info};
+use anyhow::Result;
+use std::fmt;
+use std::hint;
+use std::os::raw;
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The policy for packets that don't fit into a full ring.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RingPolicy {
+    /// Drops the packets that the ring can't accept.
+    Drop,
+
+    /// Spins until the consumer frees up enough room for all the packets.
+    /// The producing pipeline stalls, and the excess inbound traffic is
+    /// left in its receive source. If the consumer is gone, the packets
+    /// are dropped instead.
+    Backpressure,
+}
+
+impl Default for RingPolicy {
+    fn default() -> Self {
+        RingPolicy::Drop
+    }
+}
+
+/// The sync mode of the producers and the consumers of a ring.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum RingSync {
+    /// A single producer and a single consumer.
+    Single,
+
+    /// Any number of producers and consumers.
+    Multi,
+}
+
+/// A DPDK ring of `Mbuf`s, the common part of `PacketRing` and `MbufRing`.
+struct RawRing {
+    raw: NonNull<ffi::rte_ring>,
+    sync: RingSync,
+    dropped: AtomicU64,
+}
+
+impl RawRing {
+    /// Creates a new ring on the socket of the current core.
+    fn new(prefix: &str, capacity: usize, sync: RingSync) -> Result<Self> {
+        static RING_COUNT: AtomicUsize = AtomicUsize::new(0);
+        let n = RING_COUNT.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{}{}", prefix, n);
+
+        let flags = match sync {
+            RingSync::Single => ffi::RING_F_SP_ENQ | ffi::RING_F_SC_DEQ | ffi::RING_F_EXACT_SZ,
+            RingSync::Multi => ffi::RING_F_EXACT_SZ,
+        };
+
+        let raw = unsafe {
+            ffi::rte_ring_create(
+                name.clone().into_cstring().as_ptr(),
+                capacity as raw::c_uint,
+                SocketId::current().raw(),
+                flags,
+            )
+            .into_result(|_| DpdkError::new())?
+        };
+
+        info!("created {}.", name);
+
+        Ok(RawRing {
+            raw,
+            sync,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns the raw struct needed for FFI calls.
+    #[inline]
+    fn raw(&self) -> &ffi::rte_ring {
+        unsafe { self.raw.as_ref() }
+    }
+
+    #[inline]
+    fn name(&self) -> &str {
+        self.raw().name[..].as_str()
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.raw().capacity as usize
+    }
+
+    #[inline]
+    fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Enqueues as many packets as the ring accepts. Returns the number of
+    /// packets enqueued.
+    fn enqueue(&self, ptrs: &[*mut ffi::rte_mbuf]) -> usize {
+        let enqueue_burst = match self.sync {
+            RingSync::Single => ffi::_rte_ring_sp_enqueue_burst,
+            RingSync::Multi => ffi::_rte_ring_mp_enqueue_burst,
+        };
+
+        unsafe {
+            enqueue_burst(
+                self.raw.as_ptr(),
+                ptrs.as_ptr() as *const *mut raw::c_void,
+                ptrs.len() as raw::c_uint,
+                ptr::null_mut(),
+            ) as usize
+        }
+    }
+
+    /// Dequeues up to `max` packets from the ring.
+    fn dequeue(&self, max: usize) -> Vec<*mut ffi::rte_mbuf> {
+        let dequeue_burst = match self.sync {
+            RingSync::Single => ffi::_rte_ring_sc_dequeue_burst,
+            RingSync::Multi => ffi::_rte_ring_mc_dequeue_burst,
+        };
+
+        let mut ptrs = Vec::with_capacity(max);
+
+        unsafe {
+            let len = dequeue_burst(
+                self.raw.as_ptr(),
+                ptrs.as_mut_ptr() as *mut *mut raw::c_void,
+                max as raw::c_uint,
+                ptr::null_mut(),
+            );
+            ptrs.set_len(len as usize);
+        }
+
+        ptrs
+    }
+
+    /// Frees the packets the ring can't accept and counts them as dropped.
+    fn drop_packets(&self, ptrs: Vec<*mut ffi::rte_mbuf>) {
+        self.dropped.fetch_add(ptrs.len() as u64, Ordering::Relaxed);
+        super::mbuf_free_bulk(ptrs);
+    }
+}
+
+impl fmt::Debug for RawRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct(self.name())
+            .field("capacity", &self.capacity())
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+impl Drop for RawRing {
+    fn drop(&mut self) {
+        debug!("freeing {}.", self.name());
+
+        loop {
+            let ptrs = self.dequeue(32);
+            if ptrs.is_empty() {
+                break;
+            }
+            super::mbuf_free_bulk(ptrs);
+        }
+
+        unsafe {
+            ffi::rte_ring_free(self.raw.as_ptr());
+        }
+    }
+}
+
+// the enqueue and dequeue calls match the sync mode the ring is created
+// with. the single producer and single consumer access of a `PacketRing`
+// is enforced by the `RingTx` and `RingRx` handles.
+unsafe impl Send for RawRing {}
+unsafe impl Sync for RawRing {}
+
+/// A bounded single-producer single-consumer ring for handing packets
+/// between pipelines running on different cores.
+///
+/// The ring is backed by a DPDK ring and transports the ownership of the
+/// `Mbuf`s without copying. Packets still parked in the ring when both
+/// ends are dropped are freed.
+///
+/// # Example
+///
+/// ```
+/// let (tx, rx) = PacketRing::channel(1024, RingPolicy::Backpressure)?;
+///
+/// // on core 0, classifies and hands the packets to core 1.
+/// Poll::new(q.clone()).filter(classify).send(tx);
+///
+/// // on core 1, does the heavy per-flow work.
+/// Poll::new(rx).map(process).send(q);
+/// ```
+pub struct PacketRing {
+    inner: RawRing,
+}
+
+impl PacketRing {
+    /// Creates a new ring that holds up to `capacity` packets, and returns
+    /// the producer and consumer ends.
+    ///
+    /// The memory is allocated on the socket of the current core.
+    ///
+    /// # Errors
+    ///
+    /// If allocation fails, then `DpdkError` is returned.
+    pub fn channel(capacity: usize, policy: RingPolicy) -> Result<(RingTx, RingRx)> {
+        let ring = Arc::new(PacketRing {
+            inner: RawRing::new("ring", capacity, RingSync::Single)?,
+        });
+
+        let tx = RingTx {
+            ring: ring.clone(),
+            policy,
+        };
+        let rx = RingRx { ring };
+
+        Ok((tx, rx))
+    }
+
+    /// Returns the name of the ring.
+    #[inline]
+    pub fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    /// Returns the maximum number of packets the ring can hold.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Returns the total number of packets dropped because the ring is full.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+}
+
+impl fmt::Debug for PacketRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+/// The producer end of a `PacketRing`.
+///
+/// There is only one producer per ring, so the handle can't be cloned.
+#[derive(Debug)]
+pub struct RingTx {
+    ring: Arc<PacketRing>,
+    policy: RingPolicy,
+}
+
+impl RingTx {
+    /// Returns the underlying ring.
+    #[inline]
+    pub fn ring(&self) -> &PacketRing {
+        &self.ring
+    }
+
+    /// Enqueues the packets onto the ring.
+    ///
+    /// Packets the ring can't accept are handled according to the
+    /// `RingPolicy`.
+    pub fn transmit(&mut self, packets: Vec<Mbuf>) {
+        let mut ptrs = packets.into_iter().map(Mbuf::into_ptr).collect::<Vec<_>>();
+
+        while !ptrs.is_empty() {
+            let sent = self.ring.inner.enqueue(&ptrs);
+            let _ = ptrs.drain(..sent);
+
+            if ptrs.is_empty() {
+                break;
+            }
+
+            // there is no one left to make room if the consumer is gone.
+            let consumer_gone = Arc::strong_count(&self.ring) == 1;
+
+            if self.policy == RingPolicy::Drop || consumer_gone {
+                self.ring.inner.drop_packets(ptrs);
+                break;
+            }
+
+            hint::spin_loop();
+        }
+    }
+}
+
+/// The consumer end of a `PacketRing`.
+///
+/// There is only one consumer per ring, so the handle can't be cloned.
+#[derive(Debug)]
+pub struct RingRx {
+    ring: Arc<PacketRing>,
+}
+
+impl RingRx {
+    /// Returns the underlying ring.
+    #[inline]
+    pub fn ring(&self) -> &PacketRing {
+        &self.ring
+    }
+
+    /// Dequeues a burst of packets from the ring, up to a maximum of
+    /// **32** packets.
+    pub fn receive(&mut self) -> Vec<Mbuf> {
+        const RX_BURST_MAX: usize = 32;
+
+        self.ring
+            .inner
+            .dequeue(RX_BURST_MAX)
+            .into_iter()
+            .map(|ptr| unsafe { Mbuf::from_ptr(ptr) })
+            .collect::<Vec<_>>()
+    }
+}
+
+/// A bounded multi-producer multi-consumer ring of `Mbuf`s.
+///
+/// Unlike `PacketRing`, the handle can be cloned and shared by any number
+/// of producing and consuming cores, at the cost of an atomic compare and
+/// swap per burst. It's meant for pipeline-parallel designs, where the
+/// cores parsing the packets hand the frames off to the cores transmitting
+/// them. Packets still parked in the ring when the last handle is dropped
+/// are freed.
+///
+/// # Example
+///
+/// ```
+/// let ring = MbufRing::new(1024)?;
+///
+/// // on the parsing cores.
+/// ring.enqueue_burst(frames);
+///
+/// // on the transmitting core.
+/// let packets = ring.dequeue_burst(32);
+/// ```
+#[derive(Clone)]
+pub struct MbufRing {
+    inner: Arc<RawRing>,
+}
+
+impl MbufRing {
+    /// Creates a new ring that holds up to `capacity` packets.
+    ///
+    /// The memory is allocated on the socket of the current core.
+    ///
+    /// # Errors
+    ///
+    /// If allocation fails, then `DpdkError` is returned.
+    pub fn new(capacity: usize) -> Result<Self> {
+        Ok(MbufRing {
+            inner: Arc::new(RawRing::new("mbuf_ring", capacity, RingSync::Multi)?),
+        })
+    }
+
+    /// Returns the name of the ring.
+    #[inline]
+    pub fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    /// Returns the maximum number of packets the ring can hold.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Returns the total number of packets dropped because the ring is full.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+
+    /// Deparses the frames back to their `Mbuf`s and enqueues them onto
+    /// the ring. Returns the number of packets enqueued.
+    ///
+    /// The packets the ring can't accept are dropped.
+    pub fn enqueue_burst(&self, frames: Vec<Ethernet>) -> usize {
+        let ptrs = frames
+            .into_iter()
+            .map(|frame| frame.deparse().into_ptr())
+            .collect::<Vec<_>>();
+        self.enqueue(ptrs)
+    }
+
+    /// Enqueues the packets onto the ring. Returns the number of packets
+    /// enqueued.
+    ///
+    /// The packets the ring can't accept are dropped.
+    pub fn enqueue_mbufs(&self, packets: Vec<Mbuf>) -> usize {
+        let ptrs = packets.into_iter().map(Mbuf::into_ptr).collect::<Vec<_>>();
+        self.enqueue(ptrs)
+    }
+
+    /// Enqueues as many packets as the ring accepts and frees the rest.
+    fn enqueue(&self, mut ptrs: Vec<*mut ffi::rte_mbuf>) -> usize {
+        let sent = self.inner.enqueue(&ptrs);
+        if sent < ptrs.len() {
+            self.inner.drop_packets(ptrs.split_off(sent));
+        }
+        sent
+    }
+
+    /// Dequeues up to `n` packets from the ring.
+    pub fn dequeue_burst(&self, n: usize) -> Vec<Mbuf> {
+        self.inner
+            .dequeue(n)
+            .into_iter()
+            .map(|ptr| unsafe { Mbuf::from_ptr(ptr) })
+            .collect::<Vec<_>>()
+    }
+}
+
+impl fmt::Debug for MbufRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{Batch, PacketTx, Pipeline, Poll};
+    use crate::dpdk::MEMPOOL;
+    use std::cmp;
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn avail_count() -> usize {
+        unsafe { ffi::rte_mempool_avail_count(MEMPOOL.with(|tls| tls.get())) as usize }
+    }
+
+    #[capsule::test]
+    fn drop_when_full() {
+        let (mut tx, mut rx) = PacketRing::channel(4, RingPolicy::Drop).unwrap();
+        assert_eq!(4, tx.ring().capacity());
+
+        tx.transmit(Mbuf::alloc_bulk(6).unwrap());
+        assert_eq!(2, tx.ring().dropped());
+
+        assert_eq!(4, rx.receive().len());
+        assert!(rx.receive().is_empty());
+        assert_eq!(15, avail_count());
+    }
+
+    #[capsule::test]
+    fn drop_consumer_under_backpressure() {
+        let (mut tx, rx) = PacketRing::channel(4, RingPolicy::Backpressure).unwrap();
+        drop(rx);
+
+        // doesn't spin forever without a consumer.
+        tx.transmit(Mbuf::alloc_bulk(6).unwrap());
+        assert_eq!(2, tx.ring().dropped());
+    }
+
+    #[capsule::test]
+    fn send_to_ring() {
+        let (mut tx, rx) = mpsc::channel();
+        let (ring_tx, mut ring_rx) = PacketRing::channel(8, RingPolicy::Drop).unwrap();
+        let mut pipeline = Poll::new(rx).send_to(ring_tx);
+
+        tx.transmit(Mbuf::alloc_bulk(4).unwrap());
+        pipeline.run_once();
+
+        assert_eq!(4, ring_rx.receive().len());
+        assert_eq!(0, ring_rx.ring().dropped());
+    }
+
+    #[capsule::test]
+    fn free_parked_packets_on_drop() {
+        let (mut tx, rx) = PacketRing::channel(8, RingPolicy::Drop).unwrap();
+        tx.transmit(Mbuf::alloc_bulk(8).unwrap());
+        assert_eq!(7, avail_count());
+
+        drop(tx);
+        drop(rx);
+        assert_eq!(15, avail_count());
+    }
+
+    #[capsule::test(mempool_capacity = 511)]
+    fn move_packets_across_cores() {
+        const TOTAL: usize = 1_000_000;
+
+        let (mut tx, mut rx) = PacketRing::channel(256, RingPolicy::Backpressure).unwrap();
+
+        let consumer = thread::spawn(move || {
+            let mut received = 0;
+            while received < TOTAL {
+                received += rx.receive().len();
+            }
+            received
+        });
+
+        let mut sent = 0;
+        while sent < TOTAL {
+            let batch = Mbuf::alloc_bulk(32).unwrap();
+            sent += batch.len();
+            tx.transmit(batch);
+        }
+
+        assert_eq!(TOTAL, consumer.join().unwrap());
+        assert_eq!(0, tx.ring().dropped());
+
+        drop(tx);
+        assert_eq!(511, avail_count());
+    }
+
+    #[capsule::test]
+    fn enqueue_frames() {
+        let ring = MbufRing::new(4).unwrap();
+        assert_eq!(4, ring.capacity());
+
+        let frames = Mbuf::alloc_bulk(6)
+            .unwrap()
+            .into_iter()
+            .map(|mut mbuf| {
+                mbuf.extend(0, 14).unwrap();
+                mbuf.parse::<Ethernet>().unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(4, ring.enqueue_burst(frames));
+        assert_eq!(2, ring.dropped());
+        assert_eq!(13, avail_count());
+
+        let packets = ring.dequeue_burst(3);
+        assert_eq!(3, packets.len());
+        assert!(packets.iter().all(|mbuf| mbuf.data_len() == 14));
+        assert_eq!(1, ring.dequeue_burst(32).len());
+        assert!(ring.dequeue_burst(32).is_empty());
+    }
+
+    #[capsule::test]
+    fn free_parked_mbufs_on_last_drop() {
+        let ring = MbufRing::new(8).unwrap();
+        let clone = ring.clone();
+        ring.enqueue_mbufs(Mbuf::alloc_bulk(8).unwrap());
+        assert_eq!(7, avail_count());
+
+        drop(ring);
+        assert_eq!(7, avail_count());
+
+        drop(clone);
+        assert_eq!(15, avail_count());
+    }
+
+    #[capsule::test(mempool_capacity = 511)]
+    fn move_frames_across_cores() {
+        const TOTAL: usize = 100_000;
+
+        let ring = MbufRing::new(256).unwrap();
+        let received = Arc::new(AtomicUsize::new(0));
+
+        // two transmitting cores share the consuming end.
+        let consumers = (0..2)
+            .map(|_| {
+                let ring = ring.clone();
+                let received = received.clone();
+                thread::spawn(move || {
+                    while received.load(Ordering::Relaxed) < TOTAL {
+                        let len = ring.dequeue_burst(32).len();
+                        received.fetch_add(len, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut sent = 0;
+        while sent < TOTAL {
+            let frames = Mbuf::alloc_bulk(cmp::min(32, TOTAL - sent))
+                .unwrap()
+                .into_iter()
+                .map(|mut mbuf| {
+                    mbuf.extend(0, 14).unwrap();
+                    mbuf.parse::<Ethernet>().unwrap()
+                })
+                .collect::<Vec<_>>();
+            sent += ring.enqueue_burst(frames);
+        }
+
+        consumers
+            .into_iter()
+            .for_each(|consumer| consumer.join().unwrap());
+        assert_eq!(TOTAL, received.load(Ordering::Relaxed));
+
+        drop(ring);
+        assert_eq!(511, avail_count());
+    }
+}
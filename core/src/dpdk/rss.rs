@@ -0,0 +1,464 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Receive side scaling.
+//!
+//! With RSS, the NIC hashes the selected header fields of each received
+//! packet with a Toeplitz hash and a secret key, then looks the hash up in
+//! the redirection table (RETA) to pick the receive queue. The queue is
+//! `reta[hash % reta.len()]`.
+//!
+//! All the packets of a flow hash the same, so they land on the same queue
+//! as long as the key, the hashed fields and the table don't change. With
+//! the default random key of most devices, the two directions of a flow
+//! hash differently. Use [`SYMMETRIC_RSS_KEY`] to keep both directions on
+//! the same queue.
+
+use super::{check_supported, PortId};
+use crate::ensure;
+use crate::ffi;
+use anyhow::Result;
+use std::fmt;
+use std::ops::BitOr;
+use thiserror::Error;
+
+/// A Toeplitz key that hashes both directions of a flow the same.
+///
+/// The key repeats every 16 bits, so swapping the source and destination
+/// addresses, or ports, doesn't change the hash. The flip side is that
+/// the hash spreads the flows a little less evenly than with a random key.
+pub const SYMMETRIC_RSS_KEY: [u8; 40] = [
+    0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a,
+    0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a,
+    0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a,
+];
+
+/// The header fields the RSS hash is computed over.
+#[derive(Clone, Copy, Default, Eq, Hash, PartialEq)]
+pub struct RssHashFields(u64);
+
+impl RssHashFields {
+    /// The IPv4 and IPv6 addresses.
+    pub const L3: Self = RssHashFields(ffi::ETH_RSS_IP as u64);
+
+    /// The TCP ports.
+    pub const TCP: Self = RssHashFields(ffi::ETH_RSS_TCP as u64);
+
+    /// The UDP ports.
+    pub const UDP: Self = RssHashFields(ffi::ETH_RSS_UDP as u64);
+
+    /// The SCTP ports.
+    pub const SCTP: Self = RssHashFields(ffi::ETH_RSS_SCTP as u64);
+
+    /// The IP addresses, and the ports of TCP, UDP and SCTP.
+    pub const L3_L4: Self = RssHashFields(
+        (ffi::ETH_RSS_IP | ffi::ETH_RSS_TCP | ffi::ETH_RSS_UDP | ffi::ETH_RSS_SCTP) as u64,
+    );
+
+    /// Creates from the raw `ETH_RSS_*` bits.
+    pub(crate) fn from_bits(bits: u64) -> Self {
+        RssHashFields(bits)
+    }
+
+    /// Returns the raw `ETH_RSS_*` bits.
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns whether all the fields of `other` are included.
+    pub fn contains(self, other: RssHashFields) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns whether no field is hashed, which turns RSS off.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the fields not in `other`.
+    fn difference(self, other: RssHashFields) -> RssHashFields {
+        RssHashFields(self.0 & !other.0)
+    }
+}
+
+impl BitOr for RssHashFields {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        RssHashFields(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for RssHashFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RssHashFields({:#x})", self.0)
+    }
+}
+
+/// The RSS hash configuration of a port.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RssConfig {
+    /// The Toeplitz key.
+    pub key: Vec<u8>,
+
+    /// The header fields hashed.
+    pub hash_fields: RssHashFields,
+}
+
+impl RssConfig {
+    /// Returns the configuration that hashes the fields with the
+    /// [`SYMMETRIC_RSS_KEY`].
+    pub fn symmetric(hash_fields: RssHashFields) -> Self {
+        RssConfig {
+            key: SYMMETRIC_RSS_KEY.to_vec(),
+            hash_fields,
+        }
+    }
+}
+
+/// RSS errors.
+#[derive(Debug, Error)]
+pub(crate) enum RssError {
+    /// The key length doesn't match the device.
+    #[error("{0:?} needs a {2} bytes RSS key, got {1} bytes.")]
+    InvalidKeyLength(PortId, usize, u8),
+
+    /// The device can't hash some of the fields.
+    #[error("{0:?} can't hash on {1:?}.")]
+    UnsupportedFields(PortId, RssHashFields),
+
+    /// The table size doesn't match the device.
+    #[error("{0:?} has a {2} entries RSS redirection table, got {1} entries.")]
+    InvalidRetaSize(PortId, usize, u16),
+
+    /// The table points to a queue the port doesn't have.
+    #[error("{0:?} has {2} receive queues, the RSS redirection table points to queue {1}.")]
+    InvalidQueue(PortId, u16, u16),
+}
+
+impl PortId {
+    /// Returns the RSS hash configuration of the port.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::NotSupported` if the device doesn't have RSS.
+    pub fn rss_config(self) -> Result<RssConfig> {
+        let dev_info = self.dev_info()?;
+        let mut key = vec![0; dev_info.hash_key_size as usize];
+        let mut conf = ffi::rte_eth_rss_conf {
+            rss_key: if key.is_empty() {
+                std::ptr::null_mut()
+            } else {
+                key.as_mut_ptr()
+            },
+            rss_key_len: dev_info.hash_key_size,
+            rss_hf: 0,
+        };
+
+        let ret = unsafe { ffi::rte_eth_dev_rss_hash_conf_get(self.raw(), &mut conf) };
+        check_supported(ret, self, "RSS")?;
+
+        Ok(RssConfig {
+            key,
+            hash_fields: RssHashFields(conf.rss_hf),
+        })
+    }
+
+    /// Sets the RSS hash configuration of the port.
+    ///
+    /// The port must be configured with more than one core for RSS to be
+    /// turned on. Changing the key or the fields moves the flows to
+    /// different queues, including the flows already in progress.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RssError::InvalidKeyLength` if the key isn't the length the
+    /// device uses, or `RssError::UnsupportedFields` if the device can't
+    /// hash on some of the fields.
+    pub fn set_rss_config(self, config: &RssConfig) -> Result<()> {
+        let dev_info = self.dev_info()?;
+        ensure!(
+            config.key.len() == dev_info.hash_key_size as usize,
+            RssError::InvalidKeyLength(self, config.key.len(), dev_info.hash_key_size)
+        );
+        let unsupported = config
+            .hash_fields
+            .difference(RssHashFields(dev_info.flow_type_rss_offloads));
+        ensure!(
+            unsupported.is_empty(),
+            RssError::UnsupportedFields(self, unsupported)
+        );
+
+        let mut key = config.key.clone();
+        let mut conf = ffi::rte_eth_rss_conf {
+            rss_key: key.as_mut_ptr(),
+            rss_key_len: key.len() as u8,
+            rss_hf: config.hash_fields.bits(),
+        };
+
+        let ret = unsafe { ffi::rte_eth_dev_rss_hash_update(self.raw(), &mut conf) };
+        check_supported(ret, self, "RSS")
+    }
+
+    /// Returns the RSS redirection table of the port. Each entry is the
+    /// receive queue of the hashes that fall on it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PortError::NotSupported` if the device doesn't have RSS.
+    pub fn rss_reta(self) -> Result<Vec<u16>> {
+        let dev_info = self.dev_info()?;
+        let mut entries = reta_entries(dev_info.reta_size as usize);
+
+        let ret = unsafe {
+            ffi::rte_eth_dev_rss_reta_query(self.raw(), entries.as_mut_ptr(), dev_info.reta_size)
+        };
+        check_supported(ret, self, "RSS")?;
+
+        Ok(entries
+            .iter()
+            .flat_map(|entry| entry.reta.iter().copied())
+            .take(dev_info.reta_size as usize)
+            .collect())
+    }
+
+    /// Sets the RSS redirection table of the port.
+    ///
+    /// The table is used to rebalance the queues, by pointing more or fewer
+    /// entries at a queue. Only the flows on the entries that change move
+    /// to a different queue.
+    ///
+    /// # Errors
+    ///
+    /// Returns `RssError::InvalidRetaSize` if the table isn't the size the
+    /// device uses, or `RssError::InvalidQueue` if an entry points to a
+    /// queue the port doesn't have.
+    pub fn set_rss_reta(self, reta: &[u16]) -> Result<()> {
+        let dev_info = self.dev_info()?;
+        ensure!(
+            !reta.is_empty() && reta.len() == dev_info.reta_size as usize,
+            RssError::InvalidRetaSize(self, reta.len(), dev_info.reta_size)
+        );
+        if let Some(&queue) = reta.iter().find(|&&q| q >= dev_info.nb_rx_queues) {
+            return Err(RssError::InvalidQueue(self, queue, dev_info.nb_rx_queues).into());
+        }
+
+        let mut entries = reta_entries(reta.len());
+        for (chunk, entry) in reta
+            .chunks(ffi::RTE_RETA_GROUP_SIZE as usize)
+            .zip(&mut entries)
+        {
+            entry.reta[..chunk.len()].copy_from_slice(chunk);
+        }
+
+        let ret = unsafe {
+            ffi::rte_eth_dev_rss_reta_update(self.raw(), entries.as_mut_ptr(), dev_info.reta_size)
+        };
+        check_supported(ret, self, "RSS")
+    }
+}
+
+/// Returns the groups of redirection table entries covering `size`
+/// entries, with all of them selected.
+fn reta_entries(size: usize) -> Vec<ffi::rte_eth_rss_reta_entry64> {
+    let group = ffi::RTE_RETA_GROUP_SIZE as usize;
+    (0..size)
+        .step_by(group)
+        .map(|start| {
+            let len = std::cmp::min(size - start, group);
+            ffi::rte_eth_rss_reta_entry64 {
+                mask: if len == group {
+                    u64::MAX
+                } else {
+                    (1 << len) - 1
+                },
+                reta: [0; 64],
+            }
+        })
+        .collect()
+}
+
+/// Computes the Toeplitz hash the NIC computes for RSS.
+///
+/// The input is the hashed fields in network byte order. For TCP over
+/// IPv4, it's the source address, the destination address, the source
+/// port and the destination port. The key must be at least 4 bytes longer
+/// than the input.
+pub fn toeplitz_hash(key: &[u8], input: &[u8]) -> u32 {
+    assert!(key.len() >= input.len() + 4, "the key is too short.");
+
+    let mut hash = 0;
+    let mut window = u32::from_be_bytes([key[0], key[1], key[2], key[3]]);
+    for (i, &byte) in input.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                hash ^= window;
+            }
+            window = (window << 1) | u32::from((key[i + 4] >> (7 - bit)) & 1);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dpdk::{CoreId, Mempool};
+    use crate::testils::TestVdev;
+    use std::net::Ipv4Addr;
+
+    /// The key of the Microsoft RSS verification suite.
+    const VERIFICATION_KEY: [u8; 40] = [
+        0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f,
+        0xb0, 0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30,
+        0xf2, 0x0c, 0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+    ];
+
+    fn l3(src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+        let mut input = src.octets().to_vec();
+        input.extend_from_slice(&dst.octets());
+        input
+    }
+
+    fn l3_l4(src: Ipv4Addr, dst: Ipv4Addr, src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut input = l3(src, dst);
+        input.extend_from_slice(&src_port.to_be_bytes());
+        input.extend_from_slice(&dst_port.to_be_bytes());
+        input
+    }
+
+    #[test]
+    fn verification_suite() {
+        let cases = [
+            (
+                "66.9.149.187",
+                2794,
+                "161.142.100.80",
+                1766,
+                0x323e_8fc2,
+                0x51cc_c178,
+            ),
+            (
+                "199.92.111.2",
+                14230,
+                "65.69.140.83",
+                4739,
+                0xd718_262a,
+                0xc626_b0ea,
+            ),
+        ];
+
+        for &(src, src_port, dst, dst_port, l3_hash, l3_l4_hash) in cases.iter() {
+            let src = src.parse().unwrap();
+            let dst = dst.parse().unwrap();
+            assert_eq!(l3_hash, toeplitz_hash(&VERIFICATION_KEY, &l3(src, dst)));
+            assert_eq!(
+                l3_l4_hash,
+                toeplitz_hash(&VERIFICATION_KEY, &l3_l4(src, dst, src_port, dst_port))
+            );
+        }
+    }
+
+    #[test]
+    fn symmetric_key_keeps_flow_affinity() {
+        let reta = (0..128).map(|i| i % 4).collect::<Vec<u16>>();
+        let queue = |hash: u32| reta[hash as usize % reta.len()];
+
+        for i in 0..256u32 {
+            let src = Ipv4Addr::from(0x0a00_0000 | i * 7919);
+            let dst = Ipv4Addr::from(0xc0a8_0000 | i * 104_729);
+            let src_port = (1024 + i * 31) as u16;
+            let dst_port = (i * 17 + 80) as u16;
+
+            let forward = toeplitz_hash(&SYMMETRIC_RSS_KEY, &l3_l4(src, dst, src_port, dst_port));
+            let reverse = toeplitz_hash(&SYMMETRIC_RSS_KEY, &l3_l4(dst, src, dst_port, src_port));
+            assert_eq!(forward, reverse);
+            assert_eq!(queue(forward), queue(reverse));
+
+            let forward = toeplitz_hash(&SYMMETRIC_RSS_KEY, &l3(src, dst));
+            let reverse = toeplitz_hash(&SYMMETRIC_RSS_KEY, &l3(dst, src));
+            assert_eq!(forward, reverse);
+        }
+
+        // the default key isn't symmetric.
+        let src = "66.9.149.187".parse().unwrap();
+        let dst = "161.142.100.80".parse().unwrap();
+        assert_ne!(
+            toeplitz_hash(&VERIFICATION_KEY, &l3(src, dst)),
+            toeplitz_hash(&VERIFICATION_KEY, &l3(dst, src))
+        );
+    }
+
+    #[test]
+    fn hash_fields() {
+        assert!(RssHashFields::L3_L4.contains(RssHashFields::L3));
+        assert!(RssHashFields::L3_L4.contains(RssHashFields::TCP | RssHashFields::UDP));
+        assert!(!RssHashFields::L3.contains(RssHashFields::TCP));
+        assert!(RssHashFields::default().is_empty());
+
+        let supported = RssHashFields::L3 | RssHashFields::TCP;
+        assert_eq!(
+            RssHashFields::UDP | RssHashFields::SCTP,
+            RssHashFields::L3_L4.difference(supported)
+        );
+    }
+
+    #[test]
+    fn reta_entries_cover_table() {
+        let entries = reta_entries(128);
+        assert_eq!(2, entries.len());
+        assert!(entries.iter().all(|e| e.mask == u64::MAX));
+
+        let entries = reta_entries(100);
+        assert_eq!(2, entries.len());
+        assert_eq!(u64::MAX, entries[0].mask);
+        assert_eq!((1 << 36) - 1, entries[1].mask);
+    }
+
+    #[capsule::test]
+    fn null_vdev_rss_control() {
+        let core_id = CoreId::new(0);
+        let vdev = TestVdev::new("net_null");
+        let mut mempools = vec![Mempool::new(63, 0, core_id.socket_id()).unwrap()];
+
+        let port = vdev.port("vm1", &[core_id], &mut mempools);
+        let id = port.id();
+
+        let config = RssConfig::symmetric(RssHashFields::L3_L4);
+        id.set_rss_config(&config).unwrap();
+        assert_eq!(config, id.rss_config().unwrap());
+
+        // the key length is the device's.
+        let short = RssConfig {
+            key: vec![0x6d; 16],
+            hash_fields: RssHashFields::L3,
+        };
+        assert!(id.set_rss_config(&short).is_err());
+
+        let mut reta = id.rss_reta().unwrap();
+        assert!(!reta.is_empty());
+        assert!(id.set_rss_reta(&reta[1..]).is_err());
+
+        // the port has only one queue.
+        reta[0] = 1;
+        assert!(id.set_rss_reta(&reta).is_err());
+        reta[0] = 0;
+        id.set_rss_reta(&reta).unwrap();
+        assert_eq!(reta, id.rss_reta().unwrap());
+    }
+}
@@ -0,0 +1,221 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::Mbuf;
+use crate::packets::{EtherType, EtherTypeSet, EtherTypes};
+use std::fmt;
+
+// The offset of the EtherType, or of the first tag's TPID, in the frame.
+const ETHER_TYPE_OFFSET: usize = 12;
+const VLAN_TAG_SIZE: usize = 4;
+const VLAN_ID_MASK: u16 = 0x0fff;
+
+/// The set of VLAN ids the software filter accepts, one bit per id.
+#[derive(Clone)]
+struct VlanIdSet([u64; 64]);
+
+impl VlanIdSet {
+    fn new(vlan_ids: &[u16]) -> Self {
+        let mut set = [0; 64];
+        for &vid in vlan_ids {
+            let vid = vid & VLAN_ID_MASK;
+            set[vid as usize / 64] |= 1 << (vid % 64);
+        }
+        VlanIdSet(set)
+    }
+
+    #[inline]
+    fn contains(&self, vid: u16) -> bool {
+        self.0[vid as usize / 64] & (1 << (vid % 64)) != 0
+    }
+}
+
+impl fmt::Debug for VlanIdSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries((0..=VLAN_ID_MASK).filter(|&vid| self.contains(vid)))
+            .finish()
+    }
+}
+
+/// The software filter a port queue applies to the received packets
+/// before they reach the pipeline.
+///
+/// The filter covers what the device can't filter in hardware. It reads
+/// the raw frame without parsing it, looking through up to two VLAN tags,
+/// so it's cheap enough to run on every received packet. Frames too short
+/// to read are dropped.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RxFilter {
+    ether_types: Option<EtherTypeSet>,
+    vlan_ids: Option<VlanIdSet>,
+}
+
+impl RxFilter {
+    /// Accepts only the frames with a payload of one of the EtherTypes.
+    /// The EtherType of a tagged frame is the one after the VLAN tags.
+    pub(crate) fn ether_types(&mut self, ether_types: &[EtherType]) -> &mut Self {
+        self.ether_types = Some(ether_types.into());
+        self
+    }
+
+    /// Accepts only the tagged frames with an outer VLAN id in the list.
+    /// Untagged frames are accepted, like with the device's VLAN filter.
+    pub(crate) fn vlan_ids(&mut self, vlan_ids: &[u16]) -> &mut Self {
+        self.vlan_ids = Some(VlanIdSet::new(vlan_ids));
+        self
+    }
+
+    /// Returns whether the filter accepts every frame.
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ether_types.is_none() && self.vlan_ids.is_none()
+    }
+
+    /// Returns whether the filter accepts the received packet.
+    pub(crate) fn accepts(&self, mbuf: &Mbuf) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        match peek(mbuf) {
+            Some((vid, ether_type)) => {
+                let ether_type_ok = self
+                    .ether_types
+                    .as_ref()
+                    .map_or(true, |set| set.contains(ether_type));
+                let vid_ok = match (&self.vlan_ids, vid) {
+                    (Some(set), Some(vid)) => set.contains(vid),
+                    _ => true,
+                };
+                ether_type_ok && vid_ok
+            }
+            None => false,
+        }
+    }
+}
+
+/// Reads the big-endian `u16` at the offset, if the frame is long enough.
+#[inline]
+fn read_u16(mbuf: &Mbuf, offset: usize) -> Option<u16> {
+    if offset + 2 > mbuf.data_len() {
+        return None;
+    }
+
+    let bytes = mbuf.read_data::<[u8; 2]>(offset).ok()?;
+    Some(u16::from_be_bytes(unsafe { *bytes.as_ptr() }))
+}
+
+/// Returns the outer VLAN id and the payload EtherType of the frame.
+///
+/// A tag the device stripped is the outer tag, and the frame data starts
+/// with the inner tag, if there is one.
+fn peek(mbuf: &Mbuf) -> Option<(Option<u16>, EtherType)> {
+    let mut vid = mbuf.vlan_tci().map(|tci| tci & VLAN_ID_MASK);
+    let mut offset = ETHER_TYPE_OFFSET;
+    let mut ether_type = read_u16(mbuf, offset)?;
+
+    for _ in 0..2 {
+        if ether_type != EtherTypes::Vlan.0 && ether_type != EtherTypes::QinQ.0 {
+            break;
+        }
+
+        let tci = read_u16(mbuf, offset + 2)?;
+        vid.get_or_insert(tci & VLAN_ID_MASK);
+        offset += VLAN_TAG_SIZE;
+        ether_type = read_u16(mbuf, offset)?;
+    }
+
+    Some((vid, EtherType(ether_type)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, VLAN_DOT1Q_PACKET, VLAN_QINQ_PACKET};
+
+    #[capsule::test]
+    fn empty_filter_accepts_all() {
+        let filter = RxFilter::default();
+        let packet = Mbuf::from_bytes(&[0; 4]).unwrap();
+        assert!(filter.is_empty());
+        assert!(filter.accepts(&packet));
+    }
+
+    #[capsule::test]
+    fn filter_ether_types() {
+        let mut filter = RxFilter::default();
+        filter.ether_types(&[EtherTypes::Ipv4]);
+
+        let ipv4 = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(filter.accepts(&ipv4));
+
+        // the ARP payload behind the tags is what's checked.
+        let dot1q = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        assert!(!filter.accepts(&dot1q));
+        let qinq = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        assert!(!filter.accepts(&qinq));
+
+        filter.ether_types(&[EtherTypes::Arp]);
+        assert!(!filter.accepts(&ipv4));
+        assert!(filter.accepts(&dot1q));
+        assert!(filter.accepts(&qinq));
+    }
+
+    #[capsule::test]
+    fn filter_vlan_ids() {
+        let mut filter = RxFilter::default();
+        filter.vlan_ids(&[123]);
+
+        let dot1q = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        assert!(filter.accepts(&dot1q));
+
+        // only the outer tag is checked.
+        let qinq = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        assert!(!filter.accepts(&qinq));
+        filter.vlan_ids(&[30]);
+        assert!(filter.accepts(&qinq));
+        assert!(!filter.accepts(&dot1q));
+
+        let untagged = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(filter.accepts(&untagged));
+    }
+
+    #[capsule::test]
+    fn filter_stripped_vlan_id() {
+        let mut filter = RxFilter::default();
+        filter.vlan_ids(&[42]).ether_types(&[EtherTypes::Ipv4]);
+
+        // the device stripped the tag, the frame data is untagged.
+        let mut packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        packet.set_vlan_tci(42);
+        assert!(filter.accepts(&packet));
+
+        packet.set_vlan_tci(43);
+        assert!(!filter.accepts(&packet));
+    }
+
+    #[capsule::test]
+    fn drop_short_frames() {
+        let mut filter = RxFilter::default();
+        filter.ether_types(&[EtherTypes::Arp]);
+
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET[..16]).unwrap();
+        assert!(!filter.accepts(&packet));
+    }
+}
@@ -17,8 +17,7 @@
 */
 
 use super::{Mempool, Port, PortId};
-use crate::dpdk::DpdkError;
-use crate::ffi::{self, AsStr, ToResult};
+use crate::ffi::{self, AsStr};
 use crate::metrics::{labels, Key, Measurement};
 use anyhow::Result;
 use std::ptr::NonNull;
@@ -59,11 +58,7 @@ impl PortStats {
 
     /// Collects the port stats tracked by DPDK.
     pub(crate) fn collect(&self) -> Result<Vec<(Key, Measurement)>> {
-        let mut stats = ffi::rte_eth_stats::default();
-        unsafe {
-            ffi::rte_eth_stats_get(self.id.raw(), &mut stats).into_result(DpdkError::from_errno)?;
-        }
-
+        let stats = self.id.stats()?;
         let mut values = Vec::new();
 
         values.push(self.new_counter("octets", stats.ibytes, "rx"));
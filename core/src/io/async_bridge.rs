@@ -0,0 +1,207 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Bounded channel moving frames from the pipelines to async tasks.
+//!
+//! The frames are sent as owned byte copies, never as mbufs. Once sent, a
+//! frame no longer refers to the mempool, so the async consumer can hold it
+//! across `.await` points for as long as it likes without keeping buffers
+//! from being recycled, and the pipeline is free to forward or drop the
+//! original right away.
+//!
+//! The pipeline end never blocks. When the channel is full, [`send`] fails
+//! and the frame is not queued.
+//!
+//! # Example
+//!
+//! ```
+//! let (mut frames_tx, mut frames_rx) = frame_channel(1024);
+//!
+//! runtime.spawn_control(async move {
+//!     while let Some(frame) = frames_rx.recv().await {
+//!         inspect(&frame).await;
+//!     }
+//! });
+//!
+//! runtime.add_pipeline_to_port("eth1", move |q| {
+//!     Poll::new(q.clone())
+//!         .map(|packet| packet.parse::<Ethernet>())
+//!         .for_each(move |ethernet| {
+//!             let _ = frames_tx.send(ethernet);
+//!             Ok(())
+//!         })
+//!         .send(q)
+//! })?;
+//! ```
+//!
+//! [`send`]: FrameSender::send
+
+use crate::packets::Ethernet;
+use anyhow::Result;
+use futures::channel::mpsc::{self, Receiver, Sender};
+use futures::StreamExt;
+use std::fmt;
+use thiserror::Error;
+
+/// Frame channel errors.
+#[derive(Debug, Error)]
+pub(crate) enum FrameChannelError {
+    /// The channel is full.
+    #[error("Frame channel is full.")]
+    Full,
+
+    /// The receiving end is dropped.
+    #[error("Frame channel is disconnected.")]
+    Disconnected,
+}
+
+/// Creates a bounded channel for moving copies of the frames from the
+/// pipelines to async tasks, holding up to `capacity` frames.
+pub fn frame_channel(capacity: usize) -> (FrameSender, FrameReceiver) {
+    // the futures channel has one extra slot per sender.
+    let (sender, receiver) = mpsc::channel(capacity.saturating_sub(1));
+    (FrameSender { sender }, FrameReceiver { receiver })
+}
+
+/// The pipeline end of a frame channel.
+///
+/// Can be cloned to send frames from more than one pipeline. Each clone
+/// adds one frame to the capacity of the channel.
+pub struct FrameSender {
+    sender: Sender<Vec<u8>>,
+}
+
+impl FrameSender {
+    /// Sends a copy of the frame, from the start of the Ethernet header to
+    /// the end of the buffer, without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FrameChannelError::Full` if the channel is full, or
+    /// `FrameChannelError::Disconnected` if the receiving end is dropped.
+    /// Returns an error if the frame's bytes can't be read from the buffer.
+    pub fn send(&mut self, frame: &Ethernet) -> Result<()> {
+        self.sender.try_send(frame.to_vec()?).map_err(|err| {
+            if err.is_full() {
+                FrameChannelError::Full.into()
+            } else {
+                FrameChannelError::Disconnected.into()
+            }
+        })
+    }
+}
+
+impl Clone for FrameSender {
+    fn clone(&self) -> Self {
+        FrameSender {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for FrameSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameSender").finish()
+    }
+}
+
+/// The async end of a frame channel.
+pub struct FrameReceiver {
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl FrameReceiver {
+    /// Receives the next frame, waiting for one if the channel is empty.
+    ///
+    /// Returns `None` once the channel is empty and all the pipeline ends
+    /// are dropped.
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        self.receiver.next().await
+    }
+
+    /// Receives the next frame if there is one, without waiting.
+    ///
+    /// Returns `None` if the channel is empty, or all the pipeline ends
+    /// are dropped.
+    pub fn try_recv(&mut self) -> Option<Vec<u8>> {
+        self.receiver.try_next().ok().flatten()
+    }
+}
+
+impl fmt::Debug for FrameReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameReceiver").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::Packet;
+    use crate::testils::byte_arrays::{IPV4_TCP_PACKET, IPV4_UDP_PACKET, VLAN_DOT1Q_PACKET};
+    use crate::Mbuf;
+    use futures::executor;
+
+    #[capsule::test]
+    fn push_frames_through_channel() {
+        let (mut tx, mut rx) = frame_channel(4);
+
+        for bytes in &[&IPV4_TCP_PACKET[..], &IPV4_UDP_PACKET, &VLAN_DOT1Q_PACKET] {
+            let packet = Mbuf::from_bytes(bytes).unwrap();
+            let mut ethernet = packet.parse::<Ethernet>().unwrap();
+            tx.send(&ethernet).unwrap();
+
+            // the copy doesn't follow changes to the frame once sent.
+            ethernet.swap_addresses();
+        }
+        drop(tx);
+
+        let received = executor::block_on(async {
+            let mut frames = vec![];
+            while let Some(frame) = rx.recv().await {
+                frames.push(frame);
+            }
+            frames
+        });
+
+        assert_eq!(3, received.len());
+        assert_eq!(&IPV4_TCP_PACKET[..], &received[0][..]);
+        assert_eq!(&IPV4_UDP_PACKET[..], &received[1][..]);
+        assert_eq!(&VLAN_DOT1Q_PACKET[..], &received[2][..]);
+    }
+
+    #[capsule::test]
+    fn send_to_full_channel() {
+        let (mut tx, mut rx) = frame_channel(2);
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+
+        tx.send(&ethernet).unwrap();
+        tx.send(&ethernet).unwrap();
+        assert!(tx.send(&ethernet).is_err());
+
+        assert!(rx.try_recv().is_some());
+        tx.send(&ethernet).unwrap();
+        assert!(rx.try_recv().is_some());
+        assert!(rx.try_recv().is_some());
+        assert_eq!(None, rx.try_recv());
+
+        drop(rx);
+        assert!(tx.send(&ethernet).is_err());
+    }
+}
@@ -83,7 +83,14 @@
 //! ## Feature flags
 //!
 //! - `default`: Enables metrics by default.
+//! - `config-json`: Enables JSON config files.
+//! - `config-yaml`: Enables YAML config files.
+//! - `journal`: Enables the Ethernet frame mutation journal for debugging.
 //! - `metrics`: Enables automatic [`metrics`] collection.
+//! - `parse-errors`: Enables counting the frames that fail to parse, per
+//!   core and by reason.
+//! - `parse-timing`: Enables timing packet parses by packet type, for
+//!   profiling.
 //! - `pcap-dump`: Enables capturing port traffic to `pcap` files.
 //! - `testils`: Enables utilities for unit testing and benchmarking.
 //! - `full`: Enables all features.
@@ -122,25 +129,39 @@
 extern crate self as capsule;
 
 pub mod batch;
+pub mod capture;
 pub mod config;
 mod dpdk;
 mod ffi;
+pub mod io;
 mod macros;
 #[cfg(feature = "metrics")]
 #[cfg_attr(docsrs, doc(cfg(all(feature = "default", feature = "metrics"))))]
 pub mod metrics;
 pub mod net;
 pub mod packets;
-#[cfg(feature = "pcap-dump")]
-#[cfg_attr(docsrs, doc(cfg(feature = "pcap-dump")))]
-mod pcap;
+pub mod pcap;
+pub mod pcapng;
 mod runtime;
+pub mod stats;
 #[cfg(any(test, feature = "testils"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "testils")))]
 pub mod testils;
+pub mod trace;
 
-pub use self::dpdk::{KniRx, KniTxQueue, Mbuf, PortQueue, SizeOf};
-pub use self::runtime::{Runtime, UnixSignal};
+pub use self::dpdk::{
+    toeplitz_hash, BondMode, EthMatch, EthQueueStats, EthStats, FlowAction, FlowHandle, FlowItem,
+    FlowRule, Ipv4Match, Ipv6Match, KniRx, KniTxQueue, LacpTimers, LinkStatus, Mbuf, MbufRing,
+    PacketRing, PortCapabilities, PortId, PortMatch, PortQueue, RingPolicy, RingRx, RingTx,
+    RssConfig, RssHashFields, RxBurstMode, RxOffload, SizeOf, TxOffload, TxPolicy, VlanMatch,
+    XmitHashPolicy, SYMMETRIC_RSS_KEY,
+};
+pub use self::runtime::{
+    command_channel, owner_core, Bridge, BridgeEntry, CommandRx, CommandTx, FlowKey, FlowTable,
+    IcmpConfig, IcmpResponder, IcmpVerdict, NeighborCache, NeighborConfig, NeighborEntry,
+    NeighborState, NeighborTx, PortRegistry, ReorderBuffer, ReorderEvent, Runtime, RuntimeHandle,
+    UnixSignal, Verdict, VlanRateLimiter,
+};
 pub use capsule_macros::SizeOf;
 #[cfg(any(test, feature = "testils"))]
 #[cfg_attr(docsrs, doc(cfg(feature = "testils")))]
@@ -31,9 +31,16 @@
 //! failed to transmit.
 //! * `port.no_mbuf`, total number of packets dropped due to mbuf allocation
 //! failures.
+//! * `port.overflowed`, total number of packets dropped from the front of
+//! the transmit backlog because it's full.
+//! * `port.filtered`, total number of received packets dropped by the
+//! port's software filter before reaching the pipeline.
+//! * `port.burst`, the number of packets the receive queue is currently
+//! polled for at a time.
 //!
 //! Each metric is labeled with the port name and a direction, which can be
-//! either RX or TX. `port.packets` and `port.dropped` are tracked per core
+//! either RX or TX. `port.packets`, `port.dropped`, `port.overflowed`,
+//! `port.filtered` and `port.burst` are tracked per core
 //! and labeled with the core id. The others are tracked by only the overall
 //! metrics.
 //!
@@ -70,13 +77,30 @@
 //! Each metric is tracked per core and labeled with the core id and the
 //! pipeline name. If the pipeline doesn't have a name, it will be labeled
 //! as "default".
+//!
+//! # Stage Metrics
+//!
+//! * `stage.processed`, total number of packets processed by the stage.
+//! * `stage.bypassed`, total number of packets passed through while the
+//! stage is disabled.
+//! * `stage.dropped`, total number of packets dropped by the stage.
+//! * `stage.errors`, total number of packets aborted due to stage errors.
+//!
+//! Each metric is tracked per core and labeled with the core id and the
+//! stage name.
+//!
+//! The pipeline and stage metrics, as well as the per core port metrics, are fed
+//! from the [`stats`] counters.
+//!
+//! [`stats`]: crate::stats
 
 // re-export some metrics types to make feature gated imports easier.
-pub(crate) use metrics_core::{labels, Key};
+pub(crate) use metrics_core::{labels, Key, Label};
 pub(crate) use metrics_runtime::data::Counter;
 pub(crate) use metrics_runtime::Measurement;
 
 use crate::dpdk::{Mempool, MempoolStats, Port};
+use crate::stats::RuntimeStats;
 use crate::warn;
 use anyhow::{anyhow, Result};
 use metrics_runtime::{Receiver, Sink};
@@ -98,11 +122,28 @@ pub(crate) fn init() -> Result<()> {
     Ok(())
 }
 
-/// Registers DPDK collected port stats with the metrics store.
+/// Returns a counter with labels.
+fn new_counter(name: &'static str, value: u64, labels: Vec<Label>) -> (Key, Measurement) {
+    (
+        Key::from_name_and_labels(name, labels),
+        Measurement::Counter(value),
+    )
+}
+
+/// Returns a gauge with labels.
+fn new_gauge(name: &'static str, value: i64, labels: Vec<Label>) -> (Key, Measurement) {
+    (
+        Key::from_name_and_labels(name, labels),
+        Measurement::Gauge(value),
+    )
+}
+
+/// Registers DPDK collected port stats, and the per core port queue stats
+/// tracked by the runtime, with the metrics store.
 pub(crate) fn register_port_stats(ports: &[Port]) {
     let stats = ports.iter().map(Port::stats).collect::<Vec<_>>();
     SINK.clone().proxy("port", move || {
-        stats
+        let mut values = stats
             .iter()
             .flat_map(|s| {
                 s.collect().unwrap_or_else(|err| {
@@ -110,6 +151,75 @@ pub(crate) fn register_port_stats(ports: &[Port]) {
                     Vec::new()
                 })
             })
+            .collect::<Vec<_>>();
+
+        let snapshot = RuntimeStats::snapshot();
+        for (port, core, q) in snapshot.port_queues() {
+            let with_dir = |dir: &'static str| {
+                labels!(
+                    "port" => port.to_owned(),
+                    "dir" => dir,
+                    "core" => core.to_string(),
+                )
+            };
+            values.push(new_counter("packets", q.received, with_dir("rx")));
+            values.push(new_counter("packets", q.transmitted, with_dir("tx")));
+            values.push(new_counter("dropped", q.dropped, with_dir("tx")));
+            values.push(new_counter("overflowed", q.overflowed, with_dir("tx")));
+            values.push(new_counter("filtered", q.filtered, with_dir("rx")));
+            values.push(new_gauge("burst", q.rx_burst as i64, with_dir("rx")));
+        }
+
+        values
+    });
+}
+
+/// Registers the pipeline stats tracked by the runtime with the metrics
+/// store.
+pub(crate) fn register_pipeline_stats() {
+    SINK.clone().proxy("pipeline", || {
+        let snapshot = RuntimeStats::snapshot();
+        snapshot
+            .pipelines()
+            .flat_map(|(pipeline, core, p)| {
+                let with_core = || {
+                    labels!(
+                        "pipeline" => pipeline.to_owned(),
+                        "core" => core.to_string(),
+                    )
+                };
+                vec![
+                    new_counter("runs", p.runs, with_core()),
+                    new_counter("processed", p.transmitted + p.emitted, with_core()),
+                    new_counter("dropped", p.dropped, with_core()),
+                    new_counter("errors", p.errored, with_core()),
+                ]
+            })
+            .collect()
+    });
+}
+
+/// Registers the chain stage stats tracked by the runtime with the metrics
+/// store.
+pub(crate) fn register_stage_stats() {
+    SINK.clone().proxy("stage", || {
+        let snapshot = RuntimeStats::snapshot();
+        snapshot
+            .stages()
+            .flat_map(|(stage, core, s)| {
+                let with_core = || {
+                    labels!(
+                        "stage" => stage.to_owned(),
+                        "core" => core.to_string(),
+                    )
+                };
+                vec![
+                    new_counter("processed", s.processed, with_core()),
+                    new_counter("bypassed", s.bypassed, with_core()),
+                    new_counter("dropped", s.dropped, with_core()),
+                    new_counter("errors", s.errored, with_core()),
+                ]
+            })
             .collect()
     });
 }
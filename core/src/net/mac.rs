@@ -22,7 +22,7 @@ use std::str::FromStr;
 use thiserror::Error;
 
 /// Ethernet MAC address.
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(C, packed)]
 pub struct MacAddr([u8; 6]);
 
@@ -30,17 +30,200 @@ impl MacAddr {
     /// A MAC address representing an unspecified address: 00:00:00:00:00:00.
     pub const UNSPECIFIED: Self = MacAddr([0, 0, 0, 0, 0, 0]);
 
+    /// The broadcast MAC address: ff:ff:ff:ff:ff:ff.
+    pub const BROADCAST: Self = MacAddr([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+    /// The bridge group address STP BPDUs are sent to: 01:80:c2:00:00:00.
+    pub const STP_BRIDGE: Self = MacAddr([0x01, 0x80, 0xc2, 0x00, 0x00, 0x00]);
+
+    /// The nearest bridge address LLDP frames are sent to:
+    /// 01:80:c2:00:00:0e. PTP peer delay messages are sent to it too.
+    pub const LLDP_MULTICAST: Self = MacAddr([0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e]);
+
+    /// The address PTP over Ethernet messages other than peer delay are
+    /// sent to: 01:1b:19:00:00:00.
+    pub const PTP_PRIMARY: Self = MacAddr([0x01, 0x1b, 0x19, 0x00, 0x00, 0x00]);
+
     /// Creates a MAC address from 6 octets.
     #[allow(clippy::many_single_char_names)]
     pub fn new(a: u8, b: u8, c: u8, d: u8, e: u8, f: u8) -> Self {
         MacAddr([a, b, c, d, e, f])
     }
 
+    /// Creates the address of the block reserved by 802.1D for the bridge
+    /// protocols, 01:80:c2:00:00:0x, with `suffix` as the last octet.
+    ///
+    /// Bridges never forward frames sent to these addresses. Returns
+    /// `None` if `suffix` is greater than `0x0f`, out of the reserved
+    /// block.
+    pub fn bridge_reserved(suffix: u8) -> Option<Self> {
+        if suffix <= 0x0f {
+            Some(MacAddr([0x01, 0x80, 0xc2, 0x00, 0x00, suffix]))
+        } else {
+            None
+        }
+    }
+
     /// Returns the six bytes the MAC address consists of.
     #[allow(clippy::trivially_copy_pass_by_ref)]
     pub fn octets(&self) -> [u8; 6] {
         self.0
     }
+
+    /// Returns whether the address is a group address, which includes the
+    /// broadcast address.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    /// Returns whether the address is the broadcast address.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn is_broadcast(&self) -> bool {
+        *self == MacAddr::BROADCAST
+    }
+
+    /// Returns the control protocol the address is reserved for, or `None`
+    /// if the address is not a reserved multicast address.
+    ///
+    /// The whole 01:80:c2:00:00:0x block is recognized, the addresses
+    /// without a well-known protocol as [`BridgeReserved`].
+    ///
+    /// [`BridgeReserved`]: ReservedProtocol::BridgeReserved
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn reserved_protocol(&self) -> Option<ReservedProtocol> {
+        if *self == MacAddr::PTP_PRIMARY {
+            return Some(ReservedProtocol::Ptp);
+        }
+
+        match self.octets() {
+            [0x01, 0x80, 0xc2, 0x00, 0x00, suffix] if suffix <= 0x0f => Some(match suffix {
+                0x00 => ReservedProtocol::Stp,
+                0x01 => ReservedProtocol::MacControl,
+                0x02 => ReservedProtocol::SlowProtocols,
+                0x03 => ReservedProtocol::PortAuth,
+                0x0e => ReservedProtocol::Lldp,
+                _ => ReservedProtocol::BridgeReserved(suffix),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns a pseudonym of the address derived with a keyed PRF.
+    ///
+    /// The same address always maps to the same pseudonym under the same
+    /// key. The individual/group and the universal/local bits are kept, and
+    /// group addresses, including broadcast, are returned unchanged because
+    /// they identify protocols rather than hosts.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn pseudonymize(&self, key: &[u8; 16]) -> MacAddr {
+        if self.is_multicast() {
+            return *self;
+        }
+
+        let hash = siphash24(key, &self.octets()).to_be_bytes();
+        let mut octets = [0; 6];
+        octets.copy_from_slice(&hash[..6]);
+        octets[0] = (octets[0] & 0xfc) | (self.0[0] & 0x03);
+        MacAddr(octets)
+    }
+
+    /// Parses a MAC address from 12 hex digits with no separators, for
+    /// example, `aabbccddeeff`. Both upper and lower case digits are
+    /// accepted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MacParseError` if the string has a character that is not a
+    /// hex digit, or is not exactly 12 digits long.
+    pub fn from_hex(s: &str) -> Result<Self, MacParseError> {
+        if s.len() != 12 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(MacParseError(s.to_owned()));
+        }
+
+        let mut octets = [0; 6];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            // all the digits are checked, so this can't fail.
+            *octet = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        Ok(octets.into())
+    }
+}
+
+/// A control protocol with a reserved multicast destination address.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ReservedProtocol {
+    /// Spanning tree BPDUs, to [`MacAddr::STP_BRIDGE`].
+    Stp,
+    /// MAC control frames, such as the pause frames.
+    MacControl,
+    /// The slow protocols, such as LACP.
+    SlowProtocols,
+    /// Port-based network access control, 802.1X.
+    PortAuth,
+    /// LLDP and PTP peer delay, to [`MacAddr::LLDP_MULTICAST`].
+    Lldp,
+    /// PTP over Ethernet, to [`MacAddr::PTP_PRIMARY`].
+    Ptp,
+    /// Another address of the 802.1D reserved block, with its last octet.
+    BridgeReserved(u8),
+}
+
+/// SipHash-2-4 of the data.
+fn siphash24(key: &[u8; 16], data: &[u8]) -> u64 {
+    #[inline]
+    fn read_u64(bytes: &[u8]) -> u64 {
+        let mut buf = [0; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(buf)
+    }
+
+    #[inline]
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+
+    let k0 = read_u64(&key[..8]);
+    let k1 = read_u64(&key[8..]);
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+
+    let chunks = data.chunks_exact(8);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let m = read_u64(chunk);
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    }
+
+    // the last block holds the remaining bytes and the length.
+    let m = read_u64(tail) | ((data.len() as u64) << 56);
+    v[3] ^= m;
+    round(&mut v);
+    round(&mut v);
+    v[0] ^= m;
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+
+    v[0] ^ v[1] ^ v[2] ^ v[3]
 }
 
 impl fmt::Display for MacAddr {
@@ -67,7 +250,13 @@ pub struct MacParseError(String);
 impl FromStr for MacAddr {
     type Err = MacParseError;
 
+    /// Parses a MAC address in any of the forms `aa:bb:cc:dd:ee:ff`,
+    /// `aa-bb-cc-dd-ee-ff` or `aabbccddeeff`.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !s.contains(|c| c == ':' || c == '-') {
+            return MacAddr::from_hex(s);
+        }
+
         let u8s = s
             .split(|c| c == ':' || c == '-')
             .map(|s| u8::from_str_radix(s, 16))
@@ -119,4 +308,129 @@ mod tests {
             "12:34:56:ab:cd:ef".parse().unwrap()
         );
     }
+
+    #[test]
+    fn hex_string_to_mac_addr() {
+        let mac = MacAddr::new(0xaa, 0xbb, 0xcc, 0x0d, 0xee, 0xff);
+        assert_eq!(mac, MacAddr::from_hex("aabbcc0deeff").unwrap());
+        assert_eq!(mac, MacAddr::from_hex("AABBCC0DEEFF").unwrap());
+
+        assert!(MacAddr::from_hex("aabbcc0deef").is_err());
+        assert!(MacAddr::from_hex("aabbcc0deeff00").is_err());
+        assert!(MacAddr::from_hex("").is_err());
+        assert!(MacAddr::from_hex("aabbcc0deefg").is_err());
+        assert!(MacAddr::from_hex("+abbcc0deeff").is_err());
+        assert!(MacAddr::from_hex("aa:bb:cc:0d:ee:ff").is_err());
+
+        let err = MacAddr::from_hex("aabbcc0deefg").unwrap_err();
+        assert_eq!(
+            "Failed to parse 'aabbcc0deefg' as MAC address.",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn all_forms_round_trip() {
+        let mac = MacAddr::new(0x12, 0x34, 0x56, 0xab, 0xcd, 0xef);
+
+        for form in &["12:34:56:ab:cd:ef", "12-34-56-ab-cd-ef", "123456abcdef"] {
+            let parsed = form.parse::<MacAddr>().unwrap();
+            assert_eq!(mac, parsed);
+            assert_eq!(mac, parsed.to_string().parse().unwrap());
+        }
+
+        assert!("12345".parse::<MacAddr>().is_err());
+        assert!("12:34:56".parse::<MacAddr>().is_err());
+    }
+
+    #[test]
+    fn multicast_and_broadcast_addrs() {
+        assert!(MacAddr::BROADCAST.is_multicast());
+        assert!(MacAddr::BROADCAST.is_broadcast());
+        assert!(MacAddr::new(0x01, 0x00, 0x5e, 0, 0, 1).is_multicast());
+        assert!(MacAddr::new(0x33, 0x33, 0, 0, 0, 1).is_multicast());
+        assert!(!MacAddr::new(0x33, 0x33, 0, 0, 0, 1).is_broadcast());
+        assert!(!MacAddr::new(0x02, 0, 0, 0, 0, 1).is_multicast());
+    }
+
+    #[test]
+    fn siphash_reference_vector() {
+        let mut key = [0; 16];
+        key.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let data = (0..15).collect::<Vec<u8>>();
+
+        assert_eq!(0x726f_db47_dd0e_0e31, siphash24(&key, &[]));
+        assert_eq!(0xa129_ca61_49be_45e5, siphash24(&key, &data));
+    }
+
+    #[test]
+    fn recognize_reserved_addresses() {
+        assert_eq!(
+            Some(ReservedProtocol::Stp),
+            MacAddr::STP_BRIDGE.reserved_protocol()
+        );
+        assert_eq!(
+            Some(ReservedProtocol::Lldp),
+            MacAddr::LLDP_MULTICAST.reserved_protocol()
+        );
+        assert_eq!(
+            Some(ReservedProtocol::Ptp),
+            MacAddr::PTP_PRIMARY.reserved_protocol()
+        );
+
+        assert_eq!(Some(MacAddr::STP_BRIDGE), MacAddr::bridge_reserved(0x00));
+        assert_eq!(
+            Some(MacAddr::LLDP_MULTICAST),
+            MacAddr::bridge_reserved(0x0e)
+        );
+        assert_eq!(
+            Some(ReservedProtocol::SlowProtocols),
+            MacAddr::bridge_reserved(0x02).unwrap().reserved_protocol()
+        );
+        assert_eq!(
+            Some(ReservedProtocol::BridgeReserved(0x08)),
+            MacAddr::bridge_reserved(0x08).unwrap().reserved_protocol()
+        );
+        for suffix in 0..=0x0f {
+            assert!(MacAddr::bridge_reserved(suffix)
+                .unwrap()
+                .reserved_protocol()
+                .is_some());
+        }
+        assert_eq!(None, MacAddr::bridge_reserved(0x10));
+
+        // just past the reserved block, and a plain multicast address.
+        assert_eq!(
+            None,
+            MacAddr::new(0x01, 0x80, 0xc2, 0x00, 0x00, 0x10).reserved_protocol()
+        );
+        assert_eq!(
+            None,
+            MacAddr::new(0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb).reserved_protocol()
+        );
+        assert_eq!(None, MacAddr::BROADCAST.reserved_protocol());
+    }
+
+    #[test]
+    fn pseudonymize_mac_addr() {
+        let key = [7; 16];
+        let mac = MacAddr::new(0x00, 0x1b, 0x21, 0x3c, 0x4d, 0x5e);
+        let local = MacAddr::new(0x02, 0x00, 0x00, 0x00, 0x00, 0x01);
+
+        // stable under the same key.
+        let pseudonym = mac.pseudonymize(&key);
+        assert_ne!(mac, pseudonym);
+        assert_eq!(pseudonym, mac.pseudonymize(&key));
+        assert_ne!(pseudonym, mac.pseudonymize(&[8; 16]));
+        assert_ne!(pseudonym, local.pseudonymize(&key));
+
+        // keeps the universal/local and the individual/group bits.
+        assert_eq!(0x00, pseudonym.octets()[0] & 0x03);
+        assert_eq!(0x02, local.pseudonymize(&key).octets()[0] & 0x03);
+
+        // group addresses are unchanged.
+        let multicast = MacAddr::new(0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb);
+        assert_eq!(multicast, multicast.pseudonymize(&key));
+        assert_eq!(MacAddr::BROADCAST, MacAddr::BROADCAST.pseudonymize(&key));
+    }
 }
@@ -22,4 +22,4 @@ mod cidr;
 mod mac;
 
 pub use self::cidr::{Cidr, CidrError, Ipv4Cidr, Ipv6Cidr};
-pub use self::mac::{MacAddr, MacParseError};
+pub use self::mac::{MacAddr, MacParseError, ReservedProtocol};
@@ -0,0 +1,550 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::ethernet::VlanError;
+use super::{Ethernet, EthernetHeader, Packet, Tci, VlanTag};
+use crate::ensure;
+use crate::net::MacAddr;
+use anyhow::Result;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+
+// The action set of a frame is kept in a per-thread table of slots, and
+// the mbuf metadata holds a handle to the slot above the hop count. The
+// handle is the ID of the thread's table, the generation of the slot and
+// the slot index.
+const ACTION_META_PRESENT: u64 = 1 << 63;
+const ACTION_META_MASK: u64 = !0xffff;
+const HANDLE_ID_SHIFT: u64 = 47;
+const HANDLE_ID_MASK: u64 = 0xffff;
+const HANDLE_GENERATION_SHIFT: u64 = 26;
+const HANDLE_GENERATION_MASK: u64 = 0x1f_ffff;
+const HANDLE_INDEX_SHIFT: u64 = 16;
+const HANDLE_INDEX_MASK: u64 = 0x3ff;
+const ACTION_SLOTS: usize = 1024;
+const MAX_ACTIONS: usize = 8;
+
+/// Action set errors.
+#[derive(Debug, Error)]
+pub(crate) enum ActionError {
+    /// The action set of the frame is full.
+    #[error("Action set is full, at most {0} actions.")]
+    Full(usize),
+
+    /// The slot of the frame's action set is reused by another frame.
+    #[error("Action set of the frame is reused by another frame.")]
+    Stale,
+
+    /// The action set of the frame is staged on another thread.
+    #[error("Action set of the frame is staged on another thread.")]
+    ForeignThread,
+
+    /// The frame already has two VLAN tags.
+    #[error("Frame already has two VLAN tags.")]
+    TooManyTags,
+}
+
+/// An action staged on a frame, see [`ActionSet`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Sets the source MAC address.
+    SetSrc(MacAddr),
+    /// Sets the destination MAC address.
+    SetDst(MacAddr),
+    /// Sets the VLAN identifier of the outer tag, keeping its priority.
+    SetVlanVid(u16),
+    /// Pushes a new outer VLAN tag with the VLAN identifier. An untagged
+    /// frame becomes Dot1q, and a Dot1q frame becomes QinQ.
+    PushVlan(u16),
+    /// Removes the outer VLAN tag.
+    PopVlan,
+    /// Forwards the frame out of the port.
+    Output(u16),
+    /// Hands the frame to the control plane.
+    Punt,
+    /// Drops the frame. The actions after it are not applied.
+    Drop,
+}
+
+/// What to do with a frame once its action set is applied.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FrameDisposition {
+    /// Forwards the frame out of the port.
+    Forward(u16),
+    /// Hands the frame to the control plane.
+    Punt,
+    /// Drops the frame.
+    Drop,
+}
+
+/// The actions accumulated on a frame as it goes through the tables of an
+/// OpenFlow-style pipeline, applied all at once at the end.
+///
+/// The stages append actions to the frame's set with [`append`], without
+/// changing the frame, so the later tables still match on the frame as it
+/// was received. The last stage runs them in order with [`apply`].
+///
+/// The set is carried with the mbuf through its metadata, as a handle to a
+/// slot in a table of 1024 slots per thread. The slot is released when the
+/// set is taken or applied. A frame must be applied on the thread its
+/// actions are appended on, the set of a frame from another thread is an
+/// `ActionError::ForeignThread`. If more than 1024 frames hold a set at
+/// once, the slot of the oldest is reused, and its set is an
+/// `ActionError::Stale` from then on. A set holds up to 8 actions.
+///
+/// # Example
+///
+/// ```
+/// let mut batch = batch
+///     .map(|packet| packet.parse::<Ethernet>())
+///     .map(|mut frame| {
+///         if frame.dst() == router_mac {
+///             ActionSet::append(&mut frame, Action::SetDst(next_hop))?;
+///             ActionSet::append(&mut frame, Action::PushVlan(100))?;
+///             ActionSet::append(&mut frame, Action::Output(1))?;
+///         }
+///         Ok(frame)
+///     })
+///     .filter_map(|mut frame| match ActionSet::apply(&mut frame)? {
+///         FrameDisposition::Forward(_) => Ok(Either::Keep(frame)),
+///         _ => Ok(Either::Drop(frame.reset())),
+///     });
+/// ```
+///
+/// [`append`]: ActionSet::append
+/// [`apply`]: ActionSet::apply
+#[derive(Clone, Copy)]
+pub struct ActionSet {
+    actions: [Action; MAX_ACTIONS],
+    len: usize,
+}
+
+impl ActionSet {
+    /// Creates an empty action set.
+    pub fn new() -> Self {
+        ActionSet {
+            actions: [Action::Drop; MAX_ACTIONS],
+            len: 0,
+        }
+    }
+
+    /// Returns the actions in the order they are appended.
+    pub fn actions(&self) -> &[Action] {
+        &self.actions[..self.len]
+    }
+
+    /// Returns the number of actions.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether there are no actions.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends an action.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ActionError::Full` if the set already has 8 actions.
+    pub fn push(&mut self, action: Action) -> Result<()> {
+        ensure!(self.len < MAX_ACTIONS, ActionError::Full(MAX_ACTIONS));
+        self.actions[self.len] = action;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Appends an action to the set of the frame, starting a set if the
+    /// frame doesn't have one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ActionError::Full` if the set already has 8 actions,
+    /// `ActionError::Stale` if the slot of the frame's set is reused, or
+    /// `ActionError::ForeignThread` if the set is staged on another thread.
+    pub fn append(frame: &mut Ethernet, action: Action) -> Result<()> {
+        let meta = frame.mbuf().metadata();
+        let handle = if meta & ACTION_META_PRESENT != 0 {
+            meta & ACTION_META_MASK
+        } else {
+            let handle = ACTION_SLAB.with(|slab| slab.borrow_mut().alloc());
+            frame
+                .mbuf_mut()
+                .set_metadata((meta & !ACTION_META_MASK) | handle);
+            handle
+        };
+
+        ACTION_SLAB.with(|slab| slab.borrow_mut().get_mut(handle)?.push(action))
+    }
+
+    /// Returns a copy of the set of the frame, or `None` if the frame has
+    /// none.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ActionError::Stale` if the slot of the frame's set is
+    /// reused, or `ActionError::ForeignThread` if the set is staged on
+    /// another thread.
+    pub fn of(frame: &Ethernet) -> Result<Option<ActionSet>> {
+        let meta = frame.mbuf().metadata();
+        if meta & ACTION_META_PRESENT == 0 {
+            return Ok(None);
+        }
+
+        ACTION_SLAB.with(|slab| {
+            let set = *slab.borrow_mut().get_mut(meta & ACTION_META_MASK)?;
+            Ok(Some(set))
+        })
+    }
+
+    /// Removes the set from the frame without applying it, releases its
+    /// slot, and returns it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ActionError::Stale` if the slot of the frame's set is
+    /// reused, or `ActionError::ForeignThread` if the set is staged on
+    /// another thread. The frame no longer refers to the set either way.
+    pub fn take(frame: &mut Ethernet) -> Result<Option<ActionSet>> {
+        let meta = frame.mbuf().metadata();
+        if meta & ACTION_META_PRESENT == 0 {
+            return Ok(None);
+        }
+
+        frame.mbuf_mut().set_metadata(meta & !ACTION_META_MASK);
+        ACTION_SLAB.with(|slab| {
+            let set = slab.borrow_mut().release(meta & ACTION_META_MASK)?;
+            Ok(Some(set))
+        })
+    }
+
+    /// Removes the set from the frame, and applies its actions in the
+    /// order they are appended.
+    ///
+    /// The frame is forwarded out of the port of the last `Output` action,
+    /// or punted if a `Punt` comes after it. Like in OpenFlow, a frame
+    /// without either, or without a set, is dropped. A `Drop` action drops
+    /// the frame right away, and the actions after it are not applied.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the set of the frame is stale or staged on
+    /// another thread, or an action can't be applied to the frame, for example popping a VLAN tag off
+    /// an untagged frame. The actions before the failing one are applied.
+    pub fn apply(frame: &mut Ethernet) -> Result<FrameDisposition> {
+        let set = match ActionSet::take(frame)? {
+            Some(set) => set,
+            None => return Ok(FrameDisposition::Drop),
+        };
+
+        let mut disposition = FrameDisposition::Drop;
+        for &action in set.actions() {
+            match action {
+                Action::SetSrc(mac) => frame.set_src(mac),
+                Action::SetDst(mac) => frame.set_dst(mac),
+                Action::SetVlanVid(vid) => {
+                    let tci = frame.outer_tci().ok_or(VlanError::NotTagged)?;
+                    frame.set_outer_tci(Tci { vid, ..tci })?;
+                }
+                Action::PushVlan(vid) => {
+                    let tags = frame.vlan_tags();
+                    ensure!(tags.len() < 2, ActionError::TooManyTags);
+                    let mut pushed = [VlanTag::new(vid), VlanTag::new(0)];
+                    pushed[1..=tags.len()].copy_from_slice(tags);
+                    let len = tags.len() + 1;
+                    retag(frame, &pushed[..len])?;
+                }
+                Action::PopVlan => {
+                    let tags = frame.vlan_tags();
+                    ensure!(!tags.is_empty(), VlanError::NotTagged);
+                    let mut popped = [VlanTag::new(0)];
+                    popped[..tags.len() - 1].copy_from_slice(&tags[1..]);
+                    let len = tags.len() - 1;
+                    retag(frame, &popped[..len])?;
+                }
+                Action::Output(port) => disposition = FrameDisposition::Forward(port),
+                Action::Punt => disposition = FrameDisposition::Punt,
+                Action::Drop => return Ok(FrameDisposition::Drop),
+            }
+        }
+
+        Ok(disposition)
+    }
+}
+
+impl Default for ActionSet {
+    fn default() -> Self {
+        ActionSet::new()
+    }
+}
+
+impl fmt::Debug for ActionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.actions()).finish()
+    }
+}
+
+/// Rewrites the header of the frame with the VLAN tags, keeping the
+/// addresses and the EtherType.
+fn retag(frame: &mut Ethernet, tags: &[VlanTag]) -> Result<()> {
+    let template = EthernetHeader::new(frame.src(), frame.dst(), frame.ether_type());
+    frame.apply_template(&template, Some(tags))
+}
+
+/// A slot of the action set table.
+#[derive(Clone, Copy)]
+struct ActionSlot {
+    generation: u64,
+    live: bool,
+    set: ActionSet,
+}
+
+/// The table of action set slots of a thread.
+struct ActionSlab {
+    id: u64,
+    slots: Vec<ActionSlot>,
+    next: usize,
+}
+
+impl ActionSlab {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let slot = ActionSlot {
+            generation: 0,
+            live: false,
+            set: ActionSet::new(),
+        };
+
+        ActionSlab {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed) & HANDLE_ID_MASK,
+            slots: vec![slot; ACTION_SLOTS],
+            next: 0,
+        }
+    }
+
+    /// Takes a free slot with an empty set, and returns its handle. If
+    /// none is free, the slot of the oldest set is reused.
+    fn alloc(&mut self) -> u64 {
+        let index = (0..ACTION_SLOTS)
+            .map(|i| (self.next + i) % ACTION_SLOTS)
+            .find(|&i| !self.slots[i].live)
+            .unwrap_or(self.next);
+        self.next = (index + 1) % ACTION_SLOTS;
+
+        let slot = &mut self.slots[index];
+        slot.generation = (slot.generation + 1) & HANDLE_GENERATION_MASK;
+        slot.live = true;
+        slot.set = ActionSet::new();
+
+        ACTION_META_PRESENT
+            | self.id << HANDLE_ID_SHIFT
+            | slot.generation << HANDLE_GENERATION_SHIFT
+            | (index as u64) << HANDLE_INDEX_SHIFT
+    }
+
+    /// Returns the slot of the handle.
+    fn slot_mut(&mut self, handle: u64) -> Result<&mut ActionSlot> {
+        let id = (handle >> HANDLE_ID_SHIFT) & HANDLE_ID_MASK;
+        let generation = (handle >> HANDLE_GENERATION_SHIFT) & HANDLE_GENERATION_MASK;
+        let index = ((handle >> HANDLE_INDEX_SHIFT) & HANDLE_INDEX_MASK) as usize;
+
+        ensure!(id == self.id, ActionError::ForeignThread);
+        match self.slots.get_mut(index) {
+            Some(slot) if slot.live && slot.generation == generation => Ok(slot),
+            _ => Err(ActionError::Stale.into()),
+        }
+    }
+
+    /// Returns the set of the handle.
+    fn get_mut(&mut self, handle: u64) -> Result<&mut ActionSet> {
+        self.slot_mut(handle).map(|slot| &mut slot.set)
+    }
+
+    /// Frees the slot of the handle, and returns its set.
+    fn release(&mut self, handle: u64) -> Result<ActionSet> {
+        let slot = self.slot_mut(handle)?;
+        slot.live = false;
+        Ok(slot.set)
+    }
+}
+
+thread_local! {
+    static ACTION_SLAB: RefCell<ActionSlab> = RefCell::new(ActionSlab::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::EtherTypes;
+    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, VLAN_QINQ_PACKET};
+    use crate::Mbuf;
+
+    #[capsule::test]
+    fn apply_actions_in_order() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        let mac = MacAddr::new(0x02, 0, 0, 0, 0, 0x0a);
+
+        ActionSet::append(&mut ethernet, Action::SetDst(mac)).unwrap();
+        ActionSet::append(&mut ethernet, Action::PushVlan(100)).unwrap();
+        ActionSet::append(&mut ethernet, Action::PushVlan(200)).unwrap();
+        ActionSet::append(&mut ethernet, Action::SetVlanVid(300)).unwrap();
+        ActionSet::append(&mut ethernet, Action::Output(3)).unwrap();
+
+        // nothing is applied until the end.
+        assert_ne!(mac, ethernet.dst());
+        assert_eq!(
+            vec![
+                Action::SetDst(mac),
+                Action::PushVlan(100),
+                Action::PushVlan(200),
+                Action::SetVlanVid(300),
+                Action::Output(3),
+            ],
+            ActionSet::of(&ethernet).unwrap().unwrap().actions()
+        );
+
+        assert_eq!(
+            FrameDisposition::Forward(3),
+            ActionSet::apply(&mut ethernet).unwrap()
+        );
+        assert_eq!(mac, ethernet.dst());
+        assert!(ethernet.is_qinq());
+        assert_eq!(300, ethernet.outer_tci().unwrap().vid);
+        assert_eq!(100, ethernet.vlan_tags()[1].identifier());
+        assert_eq!(EtherTypes::Ipv4, ethernet.ether_type());
+        assert_eq!(IPV4_UDP_PACKET.len() + 8, ethernet.len());
+
+        // the set is gone once applied.
+        assert!(ActionSet::of(&ethernet).unwrap().is_none());
+        assert_eq!(
+            FrameDisposition::Drop,
+            ActionSet::apply(&mut ethernet).unwrap()
+        );
+    }
+
+    #[capsule::test]
+    fn apply_pop_vlan_and_punt() {
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        ethernet.set_hop_meta(3);
+
+        ActionSet::append(&mut ethernet, Action::Output(1)).unwrap();
+        ActionSet::append(&mut ethernet, Action::PopVlan).unwrap();
+        ActionSet::append(&mut ethernet, Action::Punt).unwrap();
+
+        assert_eq!(
+            FrameDisposition::Punt,
+            ActionSet::apply(&mut ethernet).unwrap()
+        );
+        assert!(ethernet.is_dot1q());
+        assert_eq!(101, ethernet.outer_tci().unwrap().vid);
+        assert_eq!(VLAN_QINQ_PACKET.len() - 4, ethernet.len());
+
+        // the hop count shares the metadata, and is left alone.
+        assert_eq!(Some(3), ethernet.hop_meta());
+    }
+
+    #[capsule::test]
+    fn drop_action_stops_the_set() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        let src = ethernet.src();
+
+        ActionSet::append(&mut ethernet, Action::Output(1)).unwrap();
+        ActionSet::append(&mut ethernet, Action::Drop).unwrap();
+        ActionSet::append(&mut ethernet, Action::SetSrc(MacAddr::BROADCAST)).unwrap();
+
+        assert_eq!(
+            FrameDisposition::Drop,
+            ActionSet::apply(&mut ethernet).unwrap()
+        );
+        assert_eq!(src, ethernet.src());
+    }
+
+    #[capsule::test]
+    fn invalid_action_sets() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        // at most 8 actions.
+        for _ in 0..8 {
+            ActionSet::append(&mut ethernet, Action::Punt).unwrap();
+        }
+        assert!(ActionSet::append(&mut ethernet, Action::Punt).is_err());
+        assert!(ActionSet::take(&mut ethernet).unwrap().is_some());
+
+        // popping a tag off an untagged frame.
+        ActionSet::append(&mut ethernet, Action::PopVlan).unwrap();
+        assert!(ActionSet::apply(&mut ethernet).is_err());
+
+        // the slot is reused once all the others hold a set.
+        ActionSet::append(&mut ethernet, Action::Output(1)).unwrap();
+        let mut others = (0..ACTION_SLOTS)
+            .map(|_| {
+                let mut other = Mbuf::from_bytes(&IPV4_UDP_PACKET)
+                    .unwrap()
+                    .parse::<Ethernet>()
+                    .unwrap();
+                ActionSet::append(&mut other, Action::Drop).unwrap();
+                other
+            })
+            .collect::<Vec<_>>();
+        let err = ActionSet::apply(&mut ethernet).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ActionError>(),
+            Some(ActionError::Stale)
+        ));
+        assert!(ActionSet::of(&ethernet).unwrap().is_none());
+
+        // the slots are released once applied.
+        for other in others.iter_mut() {
+            assert_eq!(FrameDisposition::Drop, ActionSet::apply(other).unwrap());
+        }
+        ActionSet::append(&mut ethernet, Action::Output(1)).unwrap();
+        for _ in 0..ACTION_SLOTS - 1 {
+            ActionSet::append(&mut others[0], Action::Drop).unwrap();
+            let _ = ActionSet::take(&mut others[0]).unwrap();
+        }
+        assert!(ActionSet::of(&ethernet).unwrap().is_some());
+    }
+
+    #[test]
+    fn foreign_thread_action_set() {
+        let mut slab = ActionSlab::new();
+        let mut foreign = ActionSlab::new();
+
+        let handle = slab.alloc();
+        let err = foreign.get_mut(handle).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ActionError>(),
+            Some(ActionError::ForeignThread)
+        ));
+
+        slab.release(handle).unwrap();
+        let err = slab.get_mut(handle).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ActionError>(),
+            Some(ActionError::Stale)
+        ));
+    }
+}
@@ -484,6 +484,12 @@ impl OperationCode {
     pub fn new(value: u16) -> Self {
         OperationCode(value)
     }
+
+    /// Returns the value of the operation code.
+    #[cfg(any(test, feature = "testils"))]
+    pub(crate) fn value(self) -> u16 {
+        self.0
+    }
 }
 
 /// Supported operation codes.
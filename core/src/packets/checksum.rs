@@ -194,6 +194,39 @@ pub fn compute_inc(old_checksum: u16, old_value: &[u16], new_value: &[u16]) -> u
     !(checksum as u16)
 }
 
+/// The lookup table of the reflected CRC-32 polynomial `0xEDB88320`.
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Computes the CRC-32 as defined in [IEEE 802.3], the same CRC as the
+/// Ethernet frame check sequence.
+///
+/// [IEEE 802.3]: https://standards.ieee.org/standard/802_3-2018.html
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC32_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
 /// Incrementally computes the new checksum for an IP address change.
 ///
 /// # Errors
@@ -227,4 +260,10 @@ mod tests {
     fn compute_checksum_incrementally() {
         assert_eq!(0x0000, compute_inc(0xdd2f, &[0x5555], &[0x3285]));
     }
+
+    #[test]
+    fn compute_crc32() {
+        assert_eq!(0, crc32(&[]));
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
 }
@@ -0,0 +1,402 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Walks the encapsulation chain of a frame without parsing it into
+//! packet types.
+//!
+//! Each tunnel type contributes a step that locates the encapsulated
+//! payload and identifies it, either as another Ethernet frame or by its
+//! EtherType. The walk stops at the first payload no step recognizes.
+
+use super::ip::ProtocolNumbers;
+use super::pbb::ITAG_SIZE;
+use super::{EtherType, EtherTypes, EthernetHeader, VlanTag};
+use crate::{Mbuf, SizeOf};
+
+/// The maximum number of layers to walk. Guards against crafted frames
+/// that nest tunnels indefinitely.
+pub(crate) const MAX_DEPTH: usize = 8;
+
+// IP protocol numbers of the tunnels.
+const IPV4_IN_IP: u8 = 0x04;
+const IPV6_IN_IP: u8 = 0x29;
+const GRE: u8 = 0x2f;
+
+/// GRE protocol type of an encapsulated Ethernet frame.
+const TRANSPARENT_ETHERNET_BRIDGING: u16 = 0x6558;
+
+/// The IANA assigned VXLAN UDP port.
+const VXLAN_PORT: u16 = 4789;
+const VXLAN_HEADER_SIZE: usize = 8;
+const UDP_HEADER_SIZE: usize = 8;
+const IPV6_HEADER_SIZE: usize = 40;
+const MPLS_LABEL_SIZE: usize = 4;
+const CONTROL_WORD_SIZE: usize = 4;
+
+/// An encapsulated layer found by a step.
+enum Layer {
+    /// An Ethernet frame at the offset.
+    Ethernet(usize),
+
+    /// A payload of the EtherType at the offset.
+    Payload(EtherType, usize),
+}
+
+/// A layer visited by the walk.
+struct Step {
+    ether_type: EtherType,
+    // the offset of the payload of the layer.
+    payload: usize,
+    // the number of VLAN tags of the layer, if it's an Ethernet frame.
+    tags: usize,
+    // whether the walk found a layer encapsulated in this one.
+    tunnel: bool,
+}
+
+/// Walks the Ethernet frame at `offset` and every layer it encapsulates,
+/// outermost first.
+fn walk<F: FnMut(&Step)>(mbuf: &Mbuf, offset: usize, mut visit: F) {
+    let mut depth = 0;
+    let mut layer = Some(Layer::Ethernet(offset));
+
+    while let Some(current) = layer {
+        if depth == MAX_DEPTH {
+            break;
+        }
+
+        let (ether_type, payload, tags) = match current {
+            Layer::Ethernet(offset) => match ethernet(mbuf, offset) {
+                Some(found) => found,
+                None => break,
+            },
+            Layer::Payload(ether_type, offset) => (ether_type, offset, 0),
+        };
+
+        layer = next(mbuf, ether_type, payload);
+        visit(&Step {
+            ether_type,
+            payload,
+            tags,
+            tunnel: layer.is_some(),
+        });
+        depth += 1;
+    }
+}
+
+/// Returns the EtherTypes of the Ethernet frame at `offset` and of every
+/// layer it encapsulates, outermost first.
+pub(crate) fn chain(mbuf: &Mbuf, offset: usize) -> Vec<EtherType> {
+    let mut chain = vec![];
+    walk(mbuf, offset, |step| chain.push(step.ether_type));
+    chain
+}
+
+/// Returns the number of VLAN tags, MPLS labels and other tunnels of the
+/// Ethernet frame at `offset` and of every layer it encapsulates.
+///
+/// An MPLS layer counts its labels, even when its payload can't be
+/// identified. Any other layer that encapsulates one the walk recognizes
+/// counts as one tunnel.
+pub(crate) fn depth(mbuf: &Mbuf, offset: usize) -> usize {
+    let mut depth = 0;
+    walk(mbuf, offset, |step| {
+        depth += step.tags;
+        depth += match step.ether_type {
+            EtherTypes::Mpls | EtherTypes::MplsMulticast => labels(mbuf, step.payload).0,
+            _ if step.tunnel => 1,
+            _ => 0,
+        };
+    });
+    depth
+}
+
+/// Returns `len` bytes of the buffer at `offset`, or `None` if they are
+/// out of bounds.
+fn bytes(mbuf: &Mbuf, offset: usize, len: usize) -> Option<&[u8]> {
+    mbuf.read_data_slice::<u8>(offset, len)
+        .ok()
+        .map(|data| unsafe { &*data.as_ptr() })
+}
+
+/// Returns the EtherType, the payload offset and the number of VLAN tags
+/// of the Ethernet frame.
+fn ethernet(mbuf: &Mbuf, offset: usize) -> Option<(EtherType, usize, usize)> {
+    let header = mbuf.read_data::<EthernetHeader>(offset).ok()?;
+    let header = unsafe { &*header.as_ptr() };
+
+    // makes sure the tags are in the buffer before reading the union.
+    if mbuf.data_len() < offset + header.len() {
+        return None;
+    }
+
+    let tags = (header.len() - EthernetHeader::size_of()) / VlanTag::size_of();
+    Some((header.ether_type(), offset + header.len(), tags))
+}
+
+/// Locates the layer encapsulated in the payload of the EtherType.
+fn next(mbuf: &Mbuf, ether_type: EtherType, offset: usize) -> Option<Layer> {
+    match ether_type {
+        EtherTypes::Pbb => Some(Layer::Ethernet(offset + ITAG_SIZE)),
+        EtherTypes::Mpls | EtherTypes::MplsMulticast => mpls(mbuf, offset),
+        EtherTypes::Ipv4 => ipv4(mbuf, offset),
+        EtherTypes::Ipv6 => ipv6(mbuf, offset),
+        _ => None,
+    }
+}
+
+/// Skips the MPLS label stack.
+///
+/// MPLS doesn't identify its payload, so it's inferred from the first
+/// nibble after the bottom of the stack. IP packets are recognized by
+/// their version. An Ethernet pseudowire is only recognized with the
+/// control word, whose first nibble is 0.
+fn mpls(mbuf: &Mbuf, offset: usize) -> Option<Layer> {
+    let (count, bottom) = labels(mbuf, offset);
+    if !bottom {
+        return None;
+    }
+
+    let offset = offset + count * MPLS_LABEL_SIZE;
+    match bytes(mbuf, offset, 1)?[0] >> 4 {
+        4 => Some(Layer::Payload(EtherTypes::Ipv4, offset)),
+        6 => Some(Layer::Payload(EtherTypes::Ipv6, offset)),
+        0 => Some(Layer::Ethernet(offset + CONTROL_WORD_SIZE)),
+        _ => None,
+    }
+}
+
+/// Returns the number of labels of the MPLS label stack at the offset, and
+/// whether the stack ends with its bottom label within the buffer.
+fn labels(mbuf: &Mbuf, mut offset: usize) -> (usize, bool) {
+    let mut count = 0;
+    while let Some(label) = bytes(mbuf, offset, MPLS_LABEL_SIZE) {
+        count += 1;
+        offset += MPLS_LABEL_SIZE;
+        if label[2] & 0x01 != 0 {
+            return (count, true);
+        }
+    }
+
+    (count, false)
+}
+
+/// Locates the tunnel payload of an IPv4 packet. Fragments are not
+/// followed.
+fn ipv4(mbuf: &Mbuf, offset: usize) -> Option<Layer> {
+    let header = bytes(mbuf, offset, 20)?;
+    let ihl = usize::from(header[0] & 0x0f) * 4;
+    let more_fragments = header[6] & 0x20 != 0;
+    let fragment_offset = u16::from_be_bytes([header[6], header[7]]) & 0x1fff;
+
+    if more_fragments || fragment_offset != 0 {
+        return None;
+    }
+
+    ip_payload(mbuf, header[9], offset + ihl)
+}
+
+/// Locates the tunnel payload of an IPv6 packet. Extension headers are
+/// not followed.
+fn ipv6(mbuf: &Mbuf, offset: usize) -> Option<Layer> {
+    let header = bytes(mbuf, offset, IPV6_HEADER_SIZE)?;
+    ip_payload(mbuf, header[6], offset + IPV6_HEADER_SIZE)
+}
+
+/// Locates the tunnel payload of an IP packet by its protocol.
+fn ip_payload(mbuf: &Mbuf, protocol: u8, offset: usize) -> Option<Layer> {
+    match protocol {
+        IPV4_IN_IP => Some(Layer::Payload(EtherTypes::Ipv4, offset)),
+        IPV6_IN_IP => Some(Layer::Payload(EtherTypes::Ipv6, offset)),
+        GRE => gre(mbuf, offset),
+        p if p == ProtocolNumbers::Udp.0 => vxlan(mbuf, offset),
+        _ => None,
+    }
+}
+
+/// Skips the GRE header. The protocol type is an EtherType.
+fn gre(mbuf: &Mbuf, offset: usize) -> Option<Layer> {
+    let header = bytes(mbuf, offset, 4)?;
+
+    // only version 0 has the optional fields below.
+    if header[1] & 0x07 != 0 {
+        return None;
+    }
+
+    // the checksum, key and sequence number flags each add 4 bytes.
+    let optional = [0x80, 0x20, 0x10]
+        .iter()
+        .filter(|&&flag| header[0] & flag != 0)
+        .count();
+    let payload = offset + 4 + optional * 4;
+
+    match u16::from_be_bytes([header[2], header[3]]) {
+        TRANSPARENT_ETHERNET_BRIDGING => Some(Layer::Ethernet(payload)),
+        protocol => Some(Layer::Payload(EtherType::new(protocol), payload)),
+    }
+}
+
+/// Skips the UDP and VXLAN headers if the destination is the VXLAN port.
+fn vxlan(mbuf: &Mbuf, offset: usize) -> Option<Layer> {
+    let header = bytes(mbuf, offset, UDP_HEADER_SIZE)?;
+    if u16::from_be_bytes([header[2], header[3]]) == VXLAN_PORT {
+        Some(Layer::Ethernet(
+            offset + UDP_HEADER_SIZE + VXLAN_HEADER_SIZE,
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::{
+        ARP4_PACKET, IPV4_UDP_PACKET, PBB_PACKET, VLAN_DOT1Q_PACKET,
+    };
+
+    #[rustfmt::skip]
+    const OUTER_MACS: [u8; 12] = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+    ];
+
+    fn frame(layers: &[&[u8]]) -> Mbuf {
+        Mbuf::from_bytes(&layers.concat()).unwrap()
+    }
+
+    #[capsule::test]
+    fn plain_frame() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert_eq!(vec![EtherTypes::Ipv4], chain(&packet, 0));
+
+        let packet = Mbuf::from_bytes(&PBB_PACKET).unwrap();
+        assert_eq!(vec![EtherTypes::Pbb, EtherTypes::Arp], chain(&packet, 0));
+    }
+
+    #[capsule::test]
+    fn ethernet_over_mpls() {
+        #[rustfmt::skip]
+        let labels = [
+            0x88, 0x47,
+            // two labels, the second is the bottom of the stack
+            0x00, 0x01, 0x40, 0x40,
+            0x00, 0x02, 0x81, 0x40,
+            // control word
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        let packet = frame(&[&OUTER_MACS, &labels, &ARP4_PACKET]);
+
+        assert_eq!(vec![EtherTypes::Mpls, EtherTypes::Arp], chain(&packet, 0));
+    }
+
+    #[capsule::test]
+    fn ip_over_mpls() {
+        #[rustfmt::skip]
+        let labels = [
+            0x88, 0x47,
+            0x00, 0x02, 0x81, 0x40,
+        ];
+        let packet = frame(&[&OUTER_MACS, &labels, &IPV4_UDP_PACKET[14..]]);
+
+        assert_eq!(vec![EtherTypes::Mpls, EtherTypes::Ipv4], chain(&packet, 0));
+    }
+
+    #[capsule::test]
+    fn ethernet_over_vxlan() {
+        #[rustfmt::skip]
+        let headers = [
+            0x08, 0x00,
+            // IPv4 header, protocol UDP
+            0x45, 0x00, 0x00, 0x4e, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+            0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x02,
+            // UDP header, destination port 4789
+            0xc0, 0x00, 0x12, 0xb5, 0x00, 0x3a, 0x00, 0x00,
+            // VXLAN header
+            0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64, 0x00,
+        ];
+        let packet = frame(&[&OUTER_MACS, &headers, &IPV4_UDP_PACKET]);
+
+        assert_eq!(vec![EtherTypes::Ipv4, EtherTypes::Ipv4], chain(&packet, 0));
+    }
+
+    #[capsule::test]
+    fn ethernet_over_gre() {
+        #[rustfmt::skip]
+        let headers = [
+            0x08, 0x00,
+            // IPv4 header, protocol GRE
+            0x45, 0x00, 0x00, 0x4e, 0x00, 0x00, 0x40, 0x00, 0x40, 0x2f,
+            0x00, 0x00, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00, 0x00, 0x02,
+            // GRE header with a key, transparent ethernet bridging
+            0x20, 0x00, 0x65, 0x58, 0x00, 0x00, 0x00, 0x07,
+        ];
+        let packet = frame(&[&OUTER_MACS, &headers, &ARP4_PACKET]);
+
+        assert_eq!(vec![EtherTypes::Ipv4, EtherTypes::Arp], chain(&packet, 0));
+    }
+
+    #[capsule::test]
+    fn bound_the_depth() {
+        #[rustfmt::skip]
+        let pbb = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+            0x88, 0xe7,
+            0x60, 0x01, 0x86, 0xa0,
+        ];
+        let layers = vec![&pbb[..]; MAX_DEPTH * 2];
+        let packet = frame(&layers);
+
+        assert_eq!(vec![EtherTypes::Pbb; MAX_DEPTH], chain(&packet, 0));
+    }
+
+    #[capsule::test]
+    fn count_the_depth() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert_eq!(0, depth(&packet, 0));
+
+        // a Dot1q tagged frame over an MPLS pseudowire of two labels.
+        #[rustfmt::skip]
+        let labels = [
+            0x88, 0x47,
+            0x00, 0x01, 0x40, 0x40,
+            0x00, 0x02, 0x81, 0x40,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        let packet = frame(&[&OUTER_MACS, &labels, &VLAN_DOT1Q_PACKET]);
+        assert_eq!(3, depth(&packet, 0));
+
+        // the backbone frame encapsulates the customer frame.
+        let packet = Mbuf::from_bytes(&PBB_PACKET).unwrap();
+        assert_eq!(1, depth(&packet, 0));
+
+        // the labels of a stack that never ends still count.
+        let labels = [0x88, 0x47, 0x00, 0x01, 0x40, 0x40];
+        let packet = frame(&[&OUTER_MACS, &labels]);
+        assert_eq!(1, depth(&packet, 0));
+    }
+
+    #[capsule::test]
+    fn stop_at_truncated_layer() {
+        // the label stack never ends.
+        let labels = [0x88, 0x47, 0x00, 0x01, 0x40, 0x40];
+        let packet = frame(&[&OUTER_MACS, &labels]);
+
+        assert_eq!(vec![EtherTypes::Mpls], chain(&packet, 0));
+    }
+}
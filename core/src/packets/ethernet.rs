@@ -19,18 +19,66 @@
 use crate::dpdk::BufferError;
 use crate::net::MacAddr;
 use crate::packets::types::u16be;
-use crate::packets::{Internal, Packet};
+use crate::packets::{checksum, decap, Internal, Packet, ParseOptions};
+use crate::stats::{self, ParseFailure};
 use crate::{ensure, Mbuf, SizeOf};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use std::cmp;
+use std::collections::HashMap;
 use std::fmt;
-use std::ptr::NonNull;
+use std::iter::FromIterator;
+use std::mem;
+use std::net::IpAddr;
+use std::ptr::{self, NonNull};
+use std::slice;
+use thiserror::Error;
 
 const ETH_HEADER_SIZE: usize = 14;
+const MAC_ADDRS_SIZE: usize = 12;
+const CRC32_SIZE: usize = 4;
 
 // Tag protocol identifiers.
 const VLAN_802_1Q: u16 = 0x8100;
 const VLAN_802_1AD: u16 = 0x88a8;
 
+// The hop count is kept in the lowest byte of the mbuf metadata, with a
+// flag to tell a count of 0 apart from no count.
+const HOP_META_MASK: u64 = 0x1ff;
+const HOP_META_PRESENT: u64 = 0x100;
+
+/// Ethernet frame errors.
+#[derive(Debug, Error)]
+pub(crate) enum FrameError {
+    /// The frame is longer than the maximum of the parse options.
+    #[error("Frame length '{0}' exceeds the maximum frame length '{1}'.")]
+    TooLong(usize, usize),
+}
+
+/// VLAN tag errors.
+#[derive(Debug, Error)]
+pub(crate) enum VlanError {
+    /// The frame has no VLAN tag.
+    #[error("Frame is not VLAN tagged.")]
+    NotTagged,
+
+    /// The tag has the reserved VLAN identifier 4095.
+    #[error("VLAN identifier 4095 is reserved.")]
+    ReservedVid,
+
+    /// The service tag of a QinQ frame is priority-tagged. Only customer
+    /// tags can be.
+    #[error("Service tag is priority-tagged.")]
+    PriorityTaggedStag,
+}
+
+/// Next hop resolution errors.
+#[derive(Debug, Error)]
+pub(crate) enum NeighborError {
+    /// The MAC address of the next hop is not known.
+    #[error("Next hop {0} is not resolved.")]
+    Unresolved(IpAddr),
+}
+
 /// Ethernet II frame.
 ///
 /// This is an implementation of the Ethernet II frame specified in IEEE
@@ -116,15 +164,73 @@ const VLAN_802_1AD: u16 = 0x88a8;
 /// S-TAG, or service tag, comes first, followed by the inner C-TAG, or customer
 /// tag. In such cases, 802.1ad specifies a TPID of `0x88a8` for S-TAG.
 ///
+/// # 802.1ah aka MAC-in-MAC
+///
+/// The frame may also be the customer frame encapsulated in a provider
+/// backbone frame as per [IEEE 802.1ah]. Such frames are parsed with
+/// [`Pbb::parse_inner`], at the offset of the customer frame in the mbuf.
+///
 /// [IEEE 802.1Q]: https://en.wikipedia.org/wiki/IEEE_802.1Q
 /// [IEEE 802.1ad]: https://en.wikipedia.org/wiki/IEEE_802.1ad
+/// [IEEE 802.1ah]: https://en.wikipedia.org/wiki/IEEE_802.1ah-2008
+/// [`Pbb::parse_inner`]: crate::packets::pbb::Pbb::parse_inner
 pub struct Ethernet {
     envelope: Mbuf,
     header: NonNull<EthernetHeader>,
     offset: usize,
+    #[cfg(feature = "journal")]
+    journal: Option<HeaderSnapshot>,
 }
 
 impl Ethernet {
+    /// Parses the frame at `offset` of the mbuf.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `Ethernet` header is larger than the data
+    /// payload, or the frame is longer than the [`max_frame_len`] of the
+    /// parse options.
+    ///
+    /// [`max_frame_len`]: ParseOptions::max_frame_len
+    pub(crate) fn parse_at(envelope: Mbuf, offset: usize) -> Result<Self> {
+        let mbuf = envelope.mbuf();
+
+        // checks the frame across all the segments before reading anything.
+        if let Some(max) = ParseOptions::current().max_frame_len {
+            let len = mbuf.pkt_len().saturating_sub(offset);
+            if len > max {
+                stats::record_parse_failure(ParseFailure::Oversized);
+                return Err(FrameError::TooLong(len, max).into());
+            }
+        }
+
+        let header = mbuf.read_data(offset).map_err(|err| {
+            stats::record_parse_failure(ParseFailure::OutOfBuffer);
+            err
+        })?;
+
+        let packet = Ethernet {
+            envelope,
+            header,
+            offset,
+            #[cfg(feature = "journal")]
+            journal: None,
+        };
+
+        // we've only parsed 14 bytes as the Ethernet header, in case of
+        // vlan, we need to make sure there's enough data for the whole
+        // header including tags, otherwise accessing the union type in the
+        // header will cause a panic.
+        ensure_tags_in_buffer(packet.header(), packet.mbuf().data_len() - offset).map_err(
+            |err| {
+                stats::record_parse_failure(ParseFailure::BadVlan);
+                err
+            },
+        )?;
+
+        Ok(packet)
+    }
+
     #[inline]
     fn header(&self) -> &EthernetHeader {
         unsafe { self.header.as_ref() }
@@ -162,22 +268,25 @@ impl Ethernet {
     /// Returns the marker that indicates whether the frame is VLAN.
     #[inline]
     fn vlan_marker(&self) -> u16 {
-        unsafe { self.header().chunk.ether_type.into() }
+        self.header().vlan_marker()
+    }
+
+    /// Returns the tag control information of the VLAN tag the device
+    /// stripped from the frame. Only the outermost frame of the mbuf can
+    /// have its tag stripped.
+    #[inline]
+    fn stripped_tci(&self) -> Option<u16> {
+        if self.offset == 0 {
+            self.mbuf().vlan_tci()
+        } else {
+            None
+        }
     }
 
     /// Returns the protocol identifier of the payload.
     #[inline]
     pub fn ether_type(&self) -> EtherType {
-        let header = self.header();
-        let ether_type = unsafe {
-            match self.vlan_marker() {
-                VLAN_802_1Q => header.chunk.dot1q.ether_type,
-                VLAN_802_1AD => header.chunk.qinq.ether_type,
-                _ => header.chunk.ether_type,
-            }
-        };
-
-        EtherType::new(ether_type.into())
+        self.header().ether_type()
     }
 
     /// Sets the protocol identifier of the payload.
@@ -191,6 +300,38 @@ impl Ethernet {
         }
     }
 
+    /// Rewrites the protocol identifier of the payload with its entry in
+    /// the translation table. Returns whether the table has an entry for
+    /// it, and the frame is left untouched otherwise.
+    ///
+    /// For VLAN tagged frames, it's the identifier after the tags that's
+    /// translated. The tags are kept as is.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut table = HashMap::new();
+    /// table.insert(EtherType::new(0x88b5), EtherTypes::Ipv4);
+    ///
+    /// let mut ethernet = packet.parse::<Ethernet>()?;
+    /// if ethernet.translate_ether_type(&table) {
+    ///     let ipv4 = ethernet.parse::<Ipv4>()?;
+    /// }
+    /// ```
+    #[inline]
+    pub fn translate_ether_type<S: std::hash::BuildHasher>(
+        &mut self,
+        table: &HashMap<EtherType, EtherType, S>,
+    ) -> bool {
+        match table.get(&self.ether_type()) {
+            Some(&ether_type) => {
+                self.set_ether_type(ether_type);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Returns whether the frame is VLAN Dot1q (802.1Q) tagged.
     #[inline]
     pub fn is_dot1q(&self) -> bool {
@@ -203,338 +344,3210 @@ impl Ethernet {
         self.vlan_marker() == VLAN_802_1AD
     }
 
-    /// Swaps the source MAC address with the destination MAC address.
+    /// Returns whether the frame is VLAN tagged, either in the buffer or
+    /// with a tag the device stripped with the `vlan_strip` receive
+    /// offload.
+    ///
+    /// Unlike [`is_dot1q`] and [`is_qinq`], which only look at the header
+    /// in the buffer, the answer is the same whether the port strips the
+    /// tags or not.
+    ///
+    /// [`is_dot1q`]: Ethernet::is_dot1q
+    /// [`is_qinq`]: Ethernet::is_qinq
     #[inline]
-    pub fn swap_addresses(&mut self) {
-        let src = self.src();
-        let dst = self.dst();
-        self.set_src(dst);
-        self.set_dst(src);
+    pub fn has_vlan(&self) -> bool {
+        self.stripped_tci().is_some() || self.is_dot1q() || self.is_qinq()
     }
-}
 
-impl fmt::Debug for Ethernet {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("ethernet")
-            .field("src", &format!("{}", self.src()))
-            .field("dst", &format!("{}", self.dst()))
-            .field("ether_type", &format!("{}", self.ether_type()))
-            .field("vlan", &(self.is_dot1q() || self.is_qinq()))
-            .field("$offset", &self.offset())
-            .field("$len", &self.len())
-            .field("$header_len", &self.header_len())
-            .finish()
+    /// Returns the tag control information of the outer VLAN tag, or
+    /// `None` if the frame is untagged.
+    ///
+    /// If the device stripped the tag, it's read from the mbuf instead.
+    #[inline]
+    pub fn outer_tci(&self) -> Option<Tci> {
+        if let Some(bits) = self.stripped_tci() {
+            return Some(Tci::from_bits(bits));
+        }
+
+        match self.vlan_marker() {
+            // the outer tag of a QinQ frame is at the same position as the
+            // only tag of a Dot1q frame.
+            VLAN_802_1Q | VLAN_802_1AD => unsafe {
+                Some(Tci::from_bits(self.header().chunk.dot1q.tag.tci.into()))
+            },
+            _ => None,
+        }
     }
-}
 
-impl Packet for Ethernet {
-    /// The preceding type for Ethernet must be `Mbuf`.
-    type Envelope = Mbuf;
+    /// Sets the priority, drop eligibility and VLAN identifier of the outer
+    /// VLAN tag at once, with a single write of the 16-bit tag control
+    /// information.
+    ///
+    /// # Errors
+    ///
+    /// Returns `VlanError::NotTagged` if the frame is untagged.
+    #[inline]
+    pub fn set_outer_tci(&mut self, tci: Tci) -> Result<()> {
+        if self.stripped_tci().is_some() {
+            self.mbuf_mut().set_vlan_tci(tci.to_bits());
+            return Ok(());
+        }
+
+        match self.vlan_marker() {
+            VLAN_802_1Q | VLAN_802_1AD => {
+                self.header_mut().chunk.dot1q.tag.tci = tci.to_bits().into();
+                Ok(())
+            }
+            _ => Err(VlanError::NotTagged.into()),
+        }
+    }
 
+    /// Returns the VLAN tags of the frame, outermost first. The slice is
+    /// empty if the frame is untagged.
+    ///
+    /// Only the tags in the packet data are returned. A tag stripped by
+    /// the device is not, use [`outer_tci`] to read it.
+    ///
+    /// [`outer_tci`]: Ethernet::outer_tci
     #[inline]
-    fn envelope(&self) -> &Self::Envelope {
-        &self.envelope
+    pub fn vlan_tags(&self) -> &[VlanTag] {
+        let header = self.header();
+        unsafe {
+            match self.vlan_marker() {
+                VLAN_802_1Q => slice::from_ref(&header.chunk.dot1q.tag),
+                // the service and customer tags are next to each other.
+                VLAN_802_1AD => slice::from_raw_parts(&header.chunk.qinq.stag, 2),
+                _ => &[],
+            }
+        }
+    }
+
+    /// Checks that the VLAN tags of the frame conform to 802.1Q.
+    ///
+    /// Priority-tagged frames, with a VLAN identifier of `0`, are accepted
+    /// as long as it's the customer tag. Use [`VlanTag::is_priority_tagged`]
+    /// to treat them differently.
+    ///
+    /// # Errors
+    ///
+    /// Returns `VlanError::ReservedVid` if any tag has the reserved VLAN
+    /// identifier 4095, or `VlanError::PriorityTaggedStag` if the service
+    /// tag of a QinQ frame is priority-tagged.
+    pub fn validate_vlan_tags(&self) -> Result<()> {
+        let tags = self.vlan_tags();
+        ensure!(
+            !tags.iter().any(VlanTag::is_reserved)
+                && self.outer_tci().map_or(true, |tci| tci.vid != 0x0fff),
+            VlanError::ReservedVid
+        );
+        ensure!(
+            !(self.is_qinq() && tags[0].is_priority_tagged()),
+            VlanError::PriorityTaggedStag
+        );
+        Ok(())
     }
 
+    /// Returns whether the frame is priority-tagged, with a Dot1q tag that
+    /// has the VLAN identifier `0`. The frame carries a priority code point
+    /// but is not a member of any VLAN.
     #[inline]
-    fn envelope_mut(&mut self) -> &mut Self::Envelope {
-        &mut self.envelope
+    pub fn is_priority_tagged(&self) -> bool {
+        !self.is_qinq() && self.outer_tci().map_or(false, |tci| tci.vid == 0)
+    }
+
+    /// Returns the identifier of the VLAN the frame is switched on, or
+    /// `None` if the frame is untagged or priority-tagged.
+    ///
+    /// A priority-tagged frame is switched like an untagged frame, on the
+    /// native VLAN of the port it arrived on, while its priority is still
+    /// available through [`outer_tci`].
+    ///
+    /// [`outer_tci`]: Ethernet::outer_tci
+    #[inline]
+    pub fn switching_vid(&self) -> Option<u16> {
+        self.outer_tci().map(|tci| tci.vid).filter(|&vid| vid != 0)
     }
 
+    /// Returns an owned snapshot of the header fields.
     #[inline]
-    fn offset(&self) -> usize {
-        self.offset
+    pub fn info(&self) -> EthernetInfo {
+        self.header().info(self.stripped_tci())
     }
 
-    /// Returns the length of the packet header.
+    /// Returns the hop count of the frame, or `None` if it's not set.
     ///
-    /// The length of the Ethernet header depends on the VLAN tags.
+    /// The hop count is kept in the mbuf metadata instead of on the wire.
+    /// A software bridge without STP can increment it on every pass and
+    /// drop the frame once it exceeds a limit, to keep a forwarding loop
+    /// from circulating the frame forever.
     #[inline]
-    fn header_len(&self) -> usize {
-        if self.is_dot1q() {
-            EthernetHeader::size_of() + VlanTag::size_of()
-        } else if self.is_qinq() {
-            EthernetHeader::size_of() + VlanTag::size_of() * 2
+    pub fn hop_meta(&self) -> Option<u8> {
+        let meta = self.mbuf().metadata();
+        if meta & HOP_META_PRESENT != 0 {
+            Some(meta as u8)
         } else {
-            EthernetHeader::size_of()
+            None
         }
     }
 
+    /// Sets the hop count of the frame. The count is reset when the mbuf
+    /// is reused.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let hops = ethernet.hop_meta().unwrap_or(0);
+    /// if hops >= MAX_HOPS {
+    ///     return Ok(Either::Drop(ethernet));
+    /// }
+    /// ethernet.set_hop_meta(hops + 1);
+    /// ```
     #[inline]
-    unsafe fn clone(&self, internal: Internal) -> Self {
-        Ethernet {
-            envelope: self.envelope.clone(internal),
-            header: self.header,
-            offset: self.offset,
+    pub fn set_hop_meta(&mut self, hops: u8) {
+        let meta = self.mbuf().metadata() & !HOP_META_MASK;
+        self.mbuf_mut()
+            .set_metadata(meta | HOP_META_PRESENT | u64::from(hops));
+    }
+
+    /// Swaps the source MAC address with the destination MAC address.
+    #[inline]
+    pub fn swap_addresses(&mut self) {
+        let src = self.src();
+        let dst = self.dst();
+        self.set_src(dst);
+        self.set_dst(src);
+    }
+
+    /// Swaps the source MAC address with the destination MAC address, and
+    /// remaps the VLAN identifier of the outer tag with `map`.
+    ///
+    /// This is the common reflector operation that translates the ingress
+    /// VLAN to the return VLAN. The priority and drop eligibility of the
+    /// tag are kept. For an untagged frame, it behaves exactly like
+    /// [`swap_addresses`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `VlanError::ReservedVid` if `map` returns a VLAN identifier
+    /// of 4095 or larger. The frame is left unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// ethernet.swap_addresses_and_remap_vid(|vid| match vid {
+    ///     100 => 200,
+    ///     200 => 100,
+    ///     vid => vid,
+    /// })?;
+    /// ```
+    ///
+    /// [`swap_addresses`]: Ethernet::swap_addresses
+    pub fn swap_addresses_and_remap_vid(&mut self, map: impl Fn(u16) -> u16) -> Result<()> {
+        if let Some(tci) = self.outer_tci() {
+            let vid = map(tci.vid);
+            ensure!(vid < 0x0fff, VlanError::ReservedVid);
+            self.set_outer_tci(Tci { vid, ..tci })?;
         }
+
+        self.swap_addresses();
+        Ok(())
     }
 
-    /// Parses the mbuf's payload as `Ethernet`.
+    /// Sets the destination MAC address to the one of the next hop, looked
+    /// up in the neighbor cache.
+    ///
+    /// This is where routing hands the frame over to L2, once it has picked
+    /// the next hop. The lookup doesn't resolve unknown neighbors. To queue
+    /// the packet while the next hop is being resolved, use
+    /// [`NeighborCache::resolve`] on the mbuf instead.
     ///
     /// # Errors
     ///
-    /// Returns an error if the `Ethernet` header is larger than the data
-    /// payload.
+    /// Returns `NeighborError::Unresolved` if the MAC address of the next
+    /// hop is not known. The frame is left unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let next_hop = routes.lookup(ipv4.dst())?;
+    /// let mut ethernet = ipv4.deparse();
+    /// ethernet.resolve_and_set_dst(next_hop.into(), &*cache.borrow())?;
+    /// ```
+    ///
+    /// [`NeighborCache::resolve`]: crate::NeighborCache::resolve
+    pub fn resolve_and_set_dst<C: NeighborLookup + ?Sized>(
+        &mut self,
+        next_hop: IpAddr,
+        cache: &C,
+    ) -> Result<()> {
+        let mac = cache
+            .lookup_mac(next_hop)
+            .ok_or(NeighborError::Unresolved(next_hop))?;
+        self.set_dst(mac);
+        Ok(())
+    }
+
+    /// Replaces the source and destination MAC addresses with their
+    /// pseudonyms derived with `key`.
+    ///
+    /// The mapping is deterministic, so frames anonymized with the same key
+    /// keep their flow structure. Broadcast and multicast addresses are
+    /// preserved. See [`MacAddr::pseudonymize`] for details.
+    ///
+    /// [`MacAddr::pseudonymize`]: MacAddr::pseudonymize
     #[inline]
-    fn try_parse(envelope: Self::Envelope, _internal: Internal) -> Result<Self> {
-        let mbuf = envelope.mbuf();
-        let offset = envelope.payload_offset();
-        let header = mbuf.read_data(offset)?;
+    pub fn anonymize_macs(&mut self, key: &[u8; 16]) {
+        let src = self.src().pseudonymize(key);
+        let dst = self.dst().pseudonymize(key);
+        self.set_src(src);
+        self.set_dst(dst);
+    }
 
-        let packet = Ethernet {
-            envelope,
-            header,
-            offset,
-        };
+    /// Computes a CRC-32 over the payload and appends it to the end of the
+    /// frame, in network byte order.
+    ///
+    /// The trailer is an end-to-end integrity tag for custom protocols,
+    /// distinct from the frame check sequence, which the device checks and
+    /// strips on every hop. Use [`verify_payload_crc32`] on the receiving
+    /// end. Appending to the frame changes its payload, so a tag must not
+    /// be appended twice.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BufferError` if the mbuf has no room for the trailer.
+    ///
+    /// [`verify_payload_crc32`]: Ethernet::verify_payload_crc32
+    pub fn append_payload_crc32(&mut self) -> Result<()> {
+        let crc = checksum::crc32(self.payload());
+        let offset = self.mbuf().data_len();
+        self.mbuf_mut().extend(offset, CRC32_SIZE)?;
+        self.mbuf_mut()
+            .write_data_slice(offset, &crc.to_be_bytes())?;
+        Ok(())
+    }
 
-        // we've only parsed 14 bytes as the Ethernet header, in case of
-        // vlan, we need to make sure there's enough data for the whole
-        // header including tags, otherwise accessing the union type in the
-        // header will cause a panic.
-        ensure!(
-            packet.mbuf().data_len() >= packet.header_len(),
-            BufferError::OutOfBuffer(packet.header_len(), packet.mbuf().data_len())
-        );
+    /// Returns whether the payload ends with a CRC-32 trailer appended by
+    /// [`append_payload_crc32`] that matches the rest of the payload.
+    ///
+    /// [`append_payload_crc32`]: Ethernet::append_payload_crc32
+    pub fn verify_payload_crc32(&self) -> bool {
+        let payload = self.payload();
+        if payload.len() < CRC32_SIZE {
+            return false;
+        }
 
-        Ok(packet)
+        let (data, trailer) = payload.split_at(payload.len() - CRC32_SIZE);
+        checksum::crc32(data).to_be_bytes() == trailer
     }
 
-    /// Prepends a new packet to the beginning of the envelope's payload.
+    /// Returns whether the frame ends with a frame check sequence.
+    ///
+    /// Devices usually strip the FCS on receive, but some drivers and
+    /// capture sources keep it. If the source is known to keep it, pass
+    /// `true` as `expected`, and the last 4 bytes are taken as the FCS
+    /// without checking. Otherwise, they are only taken as the FCS if they
+    /// match the CRC-32 of the rest of the frame, see [`verify_fcs`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// if ethernet.has_trailing_fcs(keeps_crc) {
+    ///     ethernet.strip_fcs()?;
+    /// }
+    /// ```
+    ///
+    /// [`verify_fcs`]: Ethernet::verify_fcs
+    pub fn has_trailing_fcs(&self, expected: bool) -> bool {
+        if expected {
+            self.frame().len() >= self.header_len() + CRC32_SIZE
+        } else {
+            self.verify_fcs()
+        }
+    }
+
+    /// Removes the 4-byte frame check sequence from the end of the frame.
+    ///
+    /// The trailing bytes are removed whether they are a valid FCS or not.
+    /// Use [`has_trailing_fcs`] first to tell if the frame has one.
     ///
     /// # Errors
     ///
-    /// Returns an error if the buffer does not have enough free space.
-    #[inline]
-    fn try_push(mut envelope: Self::Envelope, _internal: Internal) -> Result<Self> {
-        let offset = envelope.payload_offset();
-        let mbuf = envelope.mbuf_mut();
+    /// Returns `BufferError::NotResized` if the frame is shorter than its
+    /// header and the FCS.
+    ///
+    /// [`has_trailing_fcs`]: Ethernet::has_trailing_fcs
+    pub fn strip_fcs(&mut self) -> Result<()> {
+        let len = self.mbuf().data_len();
+        ensure!(
+            self.frame().len() >= self.header_len() + CRC32_SIZE,
+            BufferError::NotResized
+        );
+        self.mbuf_mut().truncate(len - CRC32_SIZE)
+    }
 
-        // can't write `EthernetHeader` directly because the union size
-        // is actually 22 (14 + double vlan tags). writing the union into
-        // the buffer will cause data corruption because it will write
-        // past the 14 bytes we extended the buffer by.
-        mbuf.extend(offset, ETH_HEADER_SIZE)?;
-        let _ = mbuf.write_data_slice(offset, &[0; ETH_HEADER_SIZE])?;
-        let header = mbuf.read_data(offset)?;
+    /// Returns whether the frame ends with a frame check sequence that
+    /// matches the CRC-32 of the rest of the frame.
+    ///
+    /// The FCS covers the whole frame from the destination MAC address,
+    /// and is in the byte order it's sent on the wire, least significant
+    /// byte first.
+    pub fn verify_fcs(&self) -> bool {
+        let frame = self.frame();
+        if frame.len() < self.header_len() + CRC32_SIZE {
+            return false;
+        }
 
-        Ok(Ethernet {
-            envelope,
-            header,
-            offset,
-        })
+        let (data, fcs) = frame.split_at(frame.len() - CRC32_SIZE);
+        checksum::crc32(data).to_le_bytes() == fcs
     }
 
-    #[inline]
-    fn deparse(self) -> Self::Envelope {
-        self.envelope
+    /// Returns the whole frame in the first mbuf segment.
+    fn frame(&self) -> &[u8] {
+        let len = self.mbuf().data_len() - self.offset();
+        match self.mbuf().read_data_slice(self.offset(), len) {
+            Ok(data) => unsafe { &*data.as_ptr() },
+            Err(_) => &[],
+        }
     }
-}
-
-/// The protocol identifier of the Ethernet frame payload.
-#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
-#[repr(C, packed)]
-pub struct EtherType(pub u16);
 
-impl EtherType {
-    /// Creates an Ethernet payload protocol identifier.
-    pub fn new(value: u16) -> Self {
-        EtherType(value)
+    /// Returns the payload of the frame in the first mbuf segment.
+    fn payload(&self) -> &[u8] {
+        if let Ok(data) = self
+            .mbuf()
+            .read_data_slice(self.payload_offset(), self.payload_len())
+        {
+            unsafe { &*data.as_ptr() }
+        } else {
+            // the frame has no payload.
+            &[]
+        }
     }
-}
 
-/// Supported Ethernet payload protocol types.
-#[allow(non_snake_case)]
-#[allow(non_upper_case_globals)]
-pub mod EtherTypes {
-    use super::EtherType;
+    /// Removes the VLAN tag with the identifier `vid`, regardless of its
+    /// position in the tag stack. The other tag and the EtherType are
+    /// preserved. Returns whether a tag was removed.
+    ///
+    /// When the inner tag of a QinQ frame is removed, the remaining outer
+    /// tag is marked as Dot1q, the only single tag format the frame can
+    /// be parsed with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the buffer fails to shrink.
+    pub fn remove_vlan_with_vid(&mut self, vid: u16) -> Result<bool> {
+        // the tags start after the source MAC.
+        let tags_offset = self.offset() + ETH_HEADER_SIZE - 2;
 
-    /// Address resolution protocol.
-    pub const Arp: EtherType = EtherType(0x0806);
-    /// Internet Protocol version 4.
-    pub const Ipv4: EtherType = EtherType(0x0800);
-    /// Internet Protocol version 6.
-    pub const Ipv6: EtherType = EtherType(0x86DD);
+        let index = {
+            let header = self.header();
+            unsafe {
+                match self.vlan_marker() {
+                    VLAN_802_1Q if header.chunk.dot1q.tag.identifier() == vid => 0,
+                    VLAN_802_1AD if header.chunk.qinq.stag.identifier() == vid => 0,
+                    VLAN_802_1AD if header.chunk.qinq.ctag.identifier() == vid => 1,
+                    _ => return Ok(false),
+                }
+            }
+        };
+
+        let len = VlanTag::size_of();
+        self.mbuf_mut().shrink(tags_offset + index * len, len)?;
+
+        if index == 1 {
+            self.header_mut().chunk.dot1q.tag.tpid = VLAN_802_1Q.into();
+        }
+
+        Ok(true)
+    }
+
+    /// Returns whether a VLAN tag can be pushed onto the frame in place.
+    ///
+    /// An untagged frame can take a Dot1q tag, and a Dot1q frame can take
+    /// an outer tag to become QinQ. The buffer must also have room for the
+    /// 4 additional bytes without reallocation. Returns `false` otherwise,
+    /// in which case the frame needs to be copied to a larger buffer first.
+    #[inline]
+    pub fn can_push_vlan(&self) -> bool {
+        self.can_push_tags(1)
+    }
+
+    /// Returns whether a QinQ tag pair can be pushed onto the frame in
+    /// place.
+    ///
+    /// Only an untagged frame can take both tags, and the buffer must have
+    /// room for the 8 additional bytes without reallocation.
+    #[inline]
+    pub fn can_push_qinq(&self) -> bool {
+        self.can_push_tags(2)
+    }
+
+    /// Returns whether `count` VLAN tags fit in both the tag stack and the
+    /// buffer.
+    #[inline]
+    fn can_push_tags(&self, count: usize) -> bool {
+        let len = VlanTag::size_of() * count;
+        let max_header_len = ETH_HEADER_SIZE + VlanTag::size_of() * 2;
+
+        // the buffer is extended from the tags onward, which consumes the
+        // tailroom. extending requires strictly more room than the length.
+        self.header_len() + len <= max_header_len && self.mbuf().tailroom() > len
+    }
+
+    /// Returns the VLAN identifiers of the frame combined into one value,
+    /// or `0` if the frame is untagged.
+    #[inline]
+    pub(crate) fn vlan_ids(&self) -> u32 {
+        if let Some(bits) = self.stripped_tci() {
+            return Tci::from_bits(bits).vid as u32;
+        }
+
+        let header = self.header();
+        unsafe {
+            match self.vlan_marker() {
+                VLAN_802_1Q => header.chunk.dot1q.tag.identifier() as u32,
+                VLAN_802_1AD => {
+                    (header.chunk.qinq.stag.identifier() as u32) << 12
+                        | header.chunk.qinq.ctag.identifier() as u32
+                }
+                _ => 0,
+            }
+        }
+    }
+
+    /// Returns a symmetric hash of the source and destination MAC addresses,
+    /// and the VLAN identifiers if the frame is tagged.
+    ///
+    /// The hash is the same for both directions of a conversation, so it
+    /// can be used to distribute frames across cores where each core must
+    /// see both directions.
+    #[inline]
+    pub fn symmetric_l2_hash(&self) -> u32 {
+        self.symmetric_l2_hash_seeded(0)
+    }
+
+    /// Returns a symmetric hash of the source and destination MAC addresses,
+    /// and the VLAN identifiers if the frame is tagged, with a seed.
+    ///
+    /// Frames hashed with different seeds are distributed differently.
+    pub fn symmetric_l2_hash_seeded(&self, seed: u32) -> u32 {
+        // orders the addresses so the result doesn't depend on the direction.
+        let (lo, hi) = {
+            let src = self.src().octets();
+            let dst = self.dst().octets();
+            if src <= dst {
+                (src, dst)
+            } else {
+                (dst, src)
+            }
+        };
+
+        // FNV-1a over the ordered addresses and the VLAN identifiers.
+        let mut hash = 0x811c_9dc5 ^ seed;
+        for &byte in lo
+            .iter()
+            .chain(hi.iter())
+            .chain(self.vlan_ids().to_be_bytes().iter())
+        {
+            hash ^= byte as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+
+        // finalizes with the murmur3 mixer for a better bit distribution.
+        hash ^= hash >> 16;
+        hash = hash.wrapping_mul(0x85eb_ca6b);
+        hash ^= hash >> 13;
+        hash = hash.wrapping_mul(0xc2b2_ae35);
+        hash ^ (hash >> 16)
+    }
+
+    /// Returns the destination and source MAC addresses followed by the
+    /// EtherType, as a key of the conversation of the frame.
+    ///
+    /// With `ignore_vlan`, the EtherType is the one of the payload after
+    /// the tags, so the tagged and untagged frames of the same
+    /// conversation have the same key. Otherwise it's the EtherType of the
+    /// outer tag, the same as the first 14 bytes of the frame on the wire,
+    /// which tells the tagged frames apart from the untagged ones. A tag
+    /// stripped by the device counts as a Dot1q tag. The VLAN identifiers
+    /// are not part of the key either way.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut conversations = HashMap::new();
+    /// let key = ethernet.canonical_l2_key(true);
+    /// *conversations.entry(key).or_insert(0) += 1;
+    /// ```
+    pub fn canonical_l2_key(&self, ignore_vlan: bool) -> [u8; 14] {
+        let ether_type = if ignore_vlan {
+            self.ether_type()
+        } else if self.stripped_tci().is_some() {
+            EtherTypes::Vlan
+        } else {
+            EtherType::new(self.vlan_marker())
+        };
+
+        let mut key = [0; 14];
+        key[..6].copy_from_slice(&self.dst().octets());
+        key[6..12].copy_from_slice(&self.src().octets());
+        key[12..].copy_from_slice(&ether_type.to_be_bytes());
+        key
+    }
+
+    /// Returns a snapshot of the journaled header fields.
+    #[cfg(feature = "journal")]
+    fn snapshot(&self) -> HeaderSnapshot {
+        HeaderSnapshot {
+            src: self.src(),
+            dst: self.dst(),
+            ether_type: self.ether_type(),
+            vlan: self.vlan_ids(),
+        }
+    }
+
+    /// Starts a mutation journal by recording a snapshot of the header
+    /// fields. Calling it again restarts the journal.
+    ///
+    /// A debugging aid to trace which pipeline stage altered a frame.
+    /// Use [`diff_journal`] to see what has changed since.
+    ///
+    /// [`diff_journal`]: Ethernet::diff_journal
+    #[cfg(feature = "journal")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "journal")))]
+    pub fn begin_journal(&mut self) {
+        self.journal = Some(self.snapshot());
+    }
+
+    /// Returns the changes to the header fields since the journal started.
+    ///
+    /// Returns an empty vec if no field has changed, or the journal has
+    /// not been started.
+    #[cfg(feature = "journal")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "journal")))]
+    pub fn diff_journal(&self) -> Vec<FieldChange> {
+        let before = match self.journal {
+            Some(before) => before,
+            None => return vec![],
+        };
+        let after = self.snapshot();
+
+        let mut changes = vec![];
+        if before.src != after.src {
+            changes.push(FieldChange::Src(before.src, after.src));
+        }
+        if before.dst != after.dst {
+            changes.push(FieldChange::Dst(before.dst, after.dst));
+        }
+        if before.ether_type != after.ether_type {
+            changes.push(FieldChange::EtherType(before.ether_type, after.ether_type));
+        }
+        if before.vlan != after.vlan {
+            changes.push(FieldChange::Vlan(before.vlan, after.vlan));
+        }
+        changes
+    }
+
+    /// Overwrites the header with a precomputed template, and optionally
+    /// tags the frame with one or two VLAN tags.
+    ///
+    /// The whole header, including the tags, is written into the buffer
+    /// with one copy instead of setting each field. Build the template once
+    /// and apply it to every frame of a burst. The buffer is resized if the
+    /// number of tags differs from the frame's current header. One tag
+    /// makes a Dot1q frame, and two tags make a QinQ frame with the first
+    /// tag as the outer tag. The tag protocol identifiers are set to match.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are more than two tags, or if the buffer
+    /// fails to resize.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let template = EthernetHeader::new(src, dst, EtherTypes::Ipv4);
+    /// let tags = [VlanTag::new(100)];
+    ///
+    /// for mbuf in burst {
+    ///     let mut ethernet = mbuf.push::<Ethernet>()?;
+    ///     ethernet.apply_template(&template, Some(&tags))?;
+    /// }
+    /// ```
+    pub fn apply_template(
+        &mut self,
+        template: &EthernetHeader,
+        vlan: Option<&[VlanTag]>,
+    ) -> Result<()> {
+        let tags = vlan.unwrap_or(&[]);
+        ensure!(
+            tags.len() <= 2,
+            anyhow!("cannot tag a frame more than twice.")
+        );
+
+        let tpids: &[u16] = match tags.len() {
+            1 => &[VLAN_802_1Q],
+            2 => &[VLAN_802_1AD, VLAN_802_1Q],
+            _ => &[],
+        };
+
+        // assembles the header on the stack first.
+        let (dst, src) = (template.dst, template.src);
+        let mut bytes = [0u8; ETH_HEADER_SIZE + 8];
+        bytes[..6].copy_from_slice(&dst.octets());
+        bytes[6..12].copy_from_slice(&src.octets());
+        let mut pos = 12;
+        for (tag, tpid) in tags.iter().zip(tpids) {
+            let tci: u16 = tag.tci.into();
+            bytes[pos..pos + 2].copy_from_slice(&tpid.to_be_bytes());
+            bytes[pos + 2..pos + 4].copy_from_slice(&tci.to_be_bytes());
+            pos += VlanTag::size_of();
+        }
+        bytes[pos..pos + 2].copy_from_slice(&template.ether_type().to_be_bytes());
+        let len = pos + 2;
+
+        // the tags start after the source MAC.
+        let offset = self.offset();
+        let current = self.header_len();
+        if len > current {
+            self.mbuf_mut().extend(offset + 12, len - current)?;
+        } else if len < current {
+            self.mbuf_mut().shrink(offset + 12, current - len)?;
+        }
+
+        self.mbuf_mut().write_data_slice(offset, &bytes[..len])?;
+        Ok(())
+    }
+
+    /// Returns the EtherTypes of the frame and of the layers encapsulated
+    /// in it, outermost first. The last one is the innermost protocol.
+    ///
+    /// The chain follows PBB, MPLS, IP-in-IP, GRE and VXLAN tunnels, and
+    /// stops at the first payload that is not a tunnel, is truncated or
+    /// can't be identified. At most 8 layers are walked, so crafted frames
+    /// that nest tunnels indefinitely can't loop.
+    ///
+    /// An Ethernet frame encapsulated in IP or MPLS has no EtherType of
+    /// its own in the chain. For example, a VXLAN frame carrying IPv4
+    /// yields `[Ipv4, Ipv4]`. MPLS pseudowires are recognized only with the
+    /// control word.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let ethernet = packet.parse::<Ethernet>()?;
+    /// if ethernet.decapsulation_chain().last() == Some(&EtherTypes::Ipv6) {
+    ///     ...
+    /// }
+    /// ```
+    pub fn decapsulation_chain(&self) -> Vec<EtherType> {
+        decap::chain(self.mbuf(), self.offset())
+    }
+
+    /// Returns the number of encapsulation layers of the frame, to sort or
+    /// bucket frames by how deeply they are encapsulated.
+    ///
+    /// Each VLAN tag counts as one, including a tag stripped by the device,
+    /// and an MPLS label stack counts its labels. Every other tunnel walked
+    /// by [`decapsulation_chain`] counts as one. A plain IPv4 frame is `0`,
+    /// a Dot1q frame `1` and a QinQ frame `2`. The layers are located
+    /// without parsing them.
+    ///
+    /// [`decapsulation_chain`]: Ethernet::decapsulation_chain
+    pub fn encapsulation_depth(&self) -> usize {
+        let stripped = if self.stripped_tci().is_some() { 1 } else { 0 };
+        stripped + decap::depth(self.mbuf(), self.offset())
+    }
+
+    /// Returns the `Debug` output of the frame, with the EtherType replaced
+    /// by the whole [`decapsulation_chain`], for example `MPLS→IPv4`.
+    ///
+    /// The chain is bounded like [`decapsulation_chain`], so a crafted
+    /// frame can't make the output arbitrarily long. For diagnostics only,
+    /// the chain is walked on every call.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// debug!("{}", ethernet.debug_with_chain());
+    /// ```
+    ///
+    /// [`decapsulation_chain`]: Ethernet::decapsulation_chain
+    pub fn debug_with_chain(&self) -> String {
+        let chain = self.decapsulation_chain();
+        let ether_type = if chain.is_empty() {
+            self.ether_type().to_string()
+        } else {
+            chain
+                .iter()
+                .map(|ether_type| ether_type.to_string())
+                .collect::<Vec<_>>()
+                .join("→")
+        };
+
+        format!("{:?}", DebugWith(self, &ether_type))
+    }
+
+    /// Writes the `Debug` output of the frame, with the EtherType as given.
+    fn fmt_with_ether_type(&self, f: &mut fmt::Formatter<'_>, ether_type: &str) -> fmt::Result {
+        f.debug_struct("ethernet")
+            .field("src", &format!("{}", self.src()))
+            .field("dst", &format!("{}", self.dst()))
+            .field("ether_type", &ether_type)
+            .field("vlan", &self.has_vlan())
+            .field("$offset", &self.offset())
+            .field("$len", &self.len())
+            .field("$header_len", &self.header_len())
+            .finish()
+    }
+
+    /// Returns a copy of the frame's bytes, from the start of the header
+    /// to the end of the buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame's bytes can't be read from the buffer.
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        let data = self.mbuf().read_data_slice::<u8>(self.offset, self.len())?;
+        Ok(unsafe { data.as_ref().to_vec() })
+    }
+
+    /// Splits the frame at the end of the header into two buffers, the
+    /// headers and the payload.
+    ///
+    /// The headers stay in the frame's buffer, which is cut short after
+    /// the Ethernet header and keeps the metadata, such as the port and
+    /// the stripped VLAN tag. The payload is copied into new buffers from
+    /// the current core's mempool, chained if it doesn't fit in one, so
+    /// it can be stored apart from the headers. An empty payload is an
+    /// empty buffer. Each buffer is freed on its own.
+    ///
+    /// # Errors
+    ///
+    /// Returns `MempoolError::Exhausted` if the buffers for the payload
+    /// can't be allocated. The frame is freed on error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let (headers, payload) = ethernet.split_at_payload()?;
+    /// control.send(headers)?;
+    /// bulk.send(payload)?;
+    /// ```
+    pub fn split_at_payload(self) -> Result<(Mbuf, Mbuf)> {
+        let offset = self.payload_offset();
+        let payload = copy_to_segments(&self.mbuf().read_segments(offset))?;
+
+        let mut headers = self.reset();
+        headers.free_tail_segments();
+        if offset < headers.data_len() {
+            headers.truncate(offset)?;
+        }
+
+        Ok((headers, payload))
+    }
+
+    /// Returns the header bytes, including the VLAN tags.
+    #[inline]
+    pub fn as_header_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.header.as_ptr() as *const u8, self.header_len()) }
+    }
+
+    /// Returns the header bytes, including the VLAN tags, as a mutable
+    /// slice for patching several fields at once.
+    ///
+    /// The slice covers exactly `header_len()` bytes and never reaches
+    /// into the payload.
+    ///
+    /// # Remarks
+    ///
+    /// The slice borrows the frame mutably, so the typed accessors can't be
+    /// used until it's dropped. The bytes are in network order, and writes
+    /// go straight to the mbuf. Indirect clones of the packet share the
+    /// buffer and see the writes too.
+    ///
+    /// The header length is derived from the TPID at bytes 12 and 13, and
+    /// of the second tag for QinQ. Overwriting a TPID with a different
+    /// marker changes the length the frame reports without moving the
+    /// payload, so the frame is corrupted. Use [`apply_template`] or
+    /// [`remove_vlan_with_vid`] to change the tags instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// // rewrites both MAC addresses in one copy.
+    /// ethernet.as_header_bytes_mut()[..12].copy_from_slice(&macs);
+    /// ```
+    ///
+    /// [`apply_template`]: Ethernet::apply_template
+    /// [`remove_vlan_with_vid`]: Ethernet::remove_vlan_with_vid
+    #[inline]
+    pub fn as_header_bytes_mut(&mut self) -> &mut [u8] {
+        let len = self.header_len();
+        unsafe { slice::from_raw_parts_mut(self.header.as_ptr() as *mut u8, len) }
+    }
+}
+
+impl fmt::Debug for Ethernet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with_ether_type(f, &self.ether_type().to_string())
+    }
+}
+
+/// The frame, debug formatted with a different EtherType.
+struct DebugWith<'a>(&'a Ethernet, &'a str);
+
+impl fmt::Debug for DebugWith<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_with_ether_type(f, self.1)
+    }
+}
+
+impl Packet for Ethernet {
+    /// The preceding type for Ethernet must be `Mbuf`.
+    type Envelope = Mbuf;
+
+    #[inline]
+    fn envelope(&self) -> &Self::Envelope {
+        &self.envelope
+    }
+
+    #[inline]
+    fn envelope_mut(&mut self) -> &mut Self::Envelope {
+        &mut self.envelope
+    }
+
+    #[inline]
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the length of the packet header.
+    ///
+    /// The length of the Ethernet header depends on the VLAN tags.
+    #[inline]
+    fn header_len(&self) -> usize {
+        self.header().len()
+    }
+
+    #[inline]
+    unsafe fn clone(&self, internal: Internal) -> Self {
+        Ethernet {
+            envelope: self.envelope.clone(internal),
+            header: self.header,
+            offset: self.offset,
+            #[cfg(feature = "journal")]
+            journal: self.journal,
+        }
+    }
+
+    /// Parses the mbuf's payload as `Ethernet`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `Ethernet` header is larger than the data
+    /// payload, or the frame is longer than the [`max_frame_len`] of the
+    /// parse options.
+    ///
+    /// [`max_frame_len`]: ParseOptions::max_frame_len
+    #[inline]
+    fn try_parse(envelope: Self::Envelope, _internal: Internal) -> Result<Self> {
+        let offset = envelope.payload_offset();
+        Ethernet::parse_at(envelope, offset)
+    }
+
+    /// Prepends a new packet to the beginning of the envelope's payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the buffer does not have enough free space.
+    #[inline]
+    fn try_push(mut envelope: Self::Envelope, _internal: Internal) -> Result<Self> {
+        let offset = envelope.payload_offset();
+        let mbuf = envelope.mbuf_mut();
+
+        // can't write `EthernetHeader` directly because the union size
+        // is actually 22 (14 + double vlan tags). writing the union into
+        // the buffer will cause data corruption because it will write
+        // past the 14 bytes we extended the buffer by.
+        mbuf.extend(offset, ETH_HEADER_SIZE)?;
+        let _ = mbuf.write_data_slice(offset, &[0; ETH_HEADER_SIZE])?;
+        let header = mbuf.read_data(offset)?;
+
+        Ok(Ethernet {
+            envelope,
+            header,
+            offset,
+            #[cfg(feature = "journal")]
+            journal: None,
+        })
+    }
+
+    #[inline]
+    fn deparse(self) -> Self::Envelope {
+        self.envelope
+    }
 }
 
-impl fmt::Display for EtherType {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match *self {
-                EtherTypes::Arp => "ARP".to_string(),
-                EtherTypes::Ipv4 => "IPv4".to_string(),
-                EtherTypes::Ipv6 => "IPv6".to_string(),
-                _ => {
-                    let t = self.0;
-                    format!("0x{:04x}", t)
-                }
-            }
-        )
+impl Ethernet {
+    /// Returns a read-only view of the Ethernet frame at the start of the
+    /// mbuf's data buffer.
+    ///
+    /// Unlike `parse`, the view borrows the mbuf instead of taking the
+    /// ownership. It's meant for fast paths that only read the header
+    /// fields before deciding what to do with the packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the `Ethernet` header is larger than the data
+    /// buffer.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let view = Ethernet::view(&packet)?;
+    /// if view.ether_type() == EtherTypes::Arp {
+    ///     arp_q.transmit(vec![packet]);
+    /// }
+    /// ```
+    #[inline]
+    pub fn view(mbuf: &Mbuf) -> Result<EthernetView<'_>> {
+        let header = mbuf.read_data::<EthernetHeader>(0)?;
+        let header = unsafe { &*header.as_ptr() };
+
+        // same as `try_parse`, makes sure the tags are in the buffer before
+        // reading the union.
+        ensure_tags_in_buffer(header, mbuf.data_len())?;
+
+        Ok(EthernetView {
+            header,
+            stripped: mbuf.vlan_tci(),
+        })
+    }
+
+    /// Parses the Ethernet header at the start of the mbuf's data buffer,
+    /// tolerating a buffer that's too short for the VLAN tags.
+    ///
+    /// This is meant for capture pipelines that truncate the frames to the
+    /// first N bytes. Only the MAC addresses are required. The rest of the
+    /// header is read as far as the buffer goes, and the accessors of the
+    /// returned [`PartialEthernet`] return `None` for what's cut off.
+    ///
+    /// A truncated frame can't be parsed as `Ethernet` because its
+    /// accessors assume the whole header is in the buffer. Use
+    /// [`PartialEthernet::into_ethernet`] for the strict parsing once the
+    /// header is known to be complete.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BufferError::OutOfBuffer` if the buffer is shorter than
+    /// the MAC addresses.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let partial = Ethernet::parse_partial(packet)?;
+    /// match partial.outer_tci() {
+    ///     Some(tci) => sampler.record(partial.src(), tci.vid),
+    ///     None => sampler.record_untagged(partial.src()),
+    /// }
+    /// ```
+    #[inline]
+    pub fn parse_partial(mbuf: Mbuf) -> Result<PartialEthernet> {
+        let available = mbuf.data_len();
+        ensure!(
+            available >= MAC_ADDRS_SIZE,
+            BufferError::OutOfBuffer(MAC_ADDRS_SIZE, available)
+        );
+
+        // copies what's there into a zeroed header, so the accessors never
+        // read past the data buffer. the bytes that are cut off are
+        // reported as missing based on `available`.
+        let mut header = EthernetHeader::default();
+        let len = cmp::min(available, mem::size_of::<EthernetHeader>());
+        let bytes = mbuf.read_data_slice::<u8>(0, len)?;
+        unsafe {
+            ptr::copy_nonoverlapping(
+                bytes.as_ptr() as *const u8,
+                &mut header as *mut EthernetHeader as *mut u8,
+                len,
+            );
+        }
+
+        Ok(PartialEthernet {
+            header,
+            available,
+            stripped: mbuf.vlan_tci(),
+            mbuf,
+        })
+    }
+}
+
+/// A read-only view of an Ethernet frame borrowed from the `Mbuf`.
+///
+/// The view is created with [`Ethernet::view`] and exposes the same read
+/// accessors as `Ethernet`, but none of the mutators.
+///
+/// [`Ethernet::view`]: Ethernet::view
+pub struct EthernetView<'a> {
+    header: &'a EthernetHeader,
+    stripped: Option<u16>,
+}
+
+impl EthernetView<'_> {
+    /// Returns the source MAC address.
+    #[inline]
+    pub fn src(&self) -> MacAddr {
+        self.header.src
+    }
+
+    /// Returns the destination MAC address.
+    #[inline]
+    pub fn dst(&self) -> MacAddr {
+        self.header.dst
+    }
+
+    /// Returns the protocol identifier of the payload.
+    #[inline]
+    pub fn ether_type(&self) -> EtherType {
+        self.header.ether_type()
+    }
+
+    /// Returns whether the frame is VLAN Dot1q (802.1Q) tagged.
+    #[inline]
+    pub fn is_dot1q(&self) -> bool {
+        self.header.vlan_marker() == VLAN_802_1Q
+    }
+
+    /// Returns whether the frame is VLAN QinQ (802.1ad) tagged.
+    #[inline]
+    pub fn is_qinq(&self) -> bool {
+        self.header.vlan_marker() == VLAN_802_1AD
+    }
+
+    /// Returns whether the frame is VLAN tagged, either in the buffer or
+    /// with a tag the device stripped, see [`Ethernet::has_vlan`].
+    #[inline]
+    pub fn has_vlan(&self) -> bool {
+        self.stripped.is_some() || self.is_dot1q() || self.is_qinq()
+    }
+
+    /// Returns the length of the header including the VLAN tags.
+    #[inline]
+    pub fn header_len(&self) -> usize {
+        self.header.len()
+    }
+
+    /// Returns an owned snapshot of the header fields.
+    #[inline]
+    pub fn info(&self) -> EthernetInfo {
+        self.header.info(self.stripped)
+    }
+}
+
+impl fmt::Debug for EthernetView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ethernet")
+            .field("src", &format!("{}", self.src()))
+            .field("dst", &format!("{}", self.dst()))
+            .field("ether_type", &format!("{}", self.ether_type()))
+            .field("vlan", &self.has_vlan())
+            .field("$header_len", &self.header_len())
+            .finish()
+    }
+}
+
+/// An Ethernet frame that may be truncated before the end of its header.
+///
+/// The frame is created with [`Ethernet::parse_partial`]. The MAC addresses
+/// are always available, while the VLAN tags and the EtherType are `None`
+/// if they are cut off.
+///
+/// [`Ethernet::parse_partial`]: Ethernet::parse_partial
+pub struct PartialEthernet {
+    mbuf: Mbuf,
+    header: EthernetHeader,
+    available: usize,
+    stripped: Option<u16>,
+}
+
+impl PartialEthernet {
+    /// Returns whether the first `end` bytes of the header are in the
+    /// buffer.
+    #[inline]
+    fn has(&self, end: usize) -> bool {
+        self.available >= end
+    }
+
+    /// Returns the marker that indicates whether the frame is VLAN, or
+    /// `None` if it's cut off.
+    #[inline]
+    fn vlan_marker(&self) -> Option<u16> {
+        if self.has(ETH_HEADER_SIZE) {
+            Some(self.header.vlan_marker())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the source MAC address.
+    #[inline]
+    pub fn src(&self) -> MacAddr {
+        self.header.src
+    }
+
+    /// Returns the destination MAC address.
+    #[inline]
+    pub fn dst(&self) -> MacAddr {
+        self.header.dst
+    }
+
+    /// Returns the number of bytes of the frame in the buffer.
+    #[inline]
+    pub fn available(&self) -> usize {
+        self.available
+    }
+
+    /// Returns whether the frame is VLAN Dot1q (802.1Q) tagged. Returns
+    /// `false` if the tag marker is cut off.
+    #[inline]
+    pub fn is_dot1q(&self) -> bool {
+        self.vlan_marker() == Some(VLAN_802_1Q)
+    }
+
+    /// Returns whether the frame is VLAN QinQ (802.1ad) tagged. Returns
+    /// `false` if the tag marker is cut off.
+    #[inline]
+    pub fn is_qinq(&self) -> bool {
+        self.vlan_marker() == Some(VLAN_802_1AD)
+    }
+
+    /// Returns whether the frame is VLAN tagged, either in the buffer or
+    /// with a tag the device stripped, see [`Ethernet::has_vlan`]. Returns
+    /// `false` if the tag marker is cut off and no tag is stripped.
+    #[inline]
+    pub fn has_vlan(&self) -> bool {
+        self.stripped.is_some() || self.is_dot1q() || self.is_qinq()
+    }
+
+    /// Returns the length of the header including the VLAN tags, or `None`
+    /// if the tag marker is cut off.
+    ///
+    /// The length is what the header would be, it can be longer than the
+    /// bytes available.
+    #[inline]
+    pub fn header_len(&self) -> Option<usize> {
+        self.vlan_marker().map(|_| self.header.len())
+    }
+
+    /// Returns whether the whole header including the VLAN tags is in the
+    /// buffer.
+    #[inline]
+    pub fn is_complete(&self) -> bool {
+        self.header_len().map_or(false, |len| self.has(len))
+    }
+
+    /// Returns the protocol identifier of the payload, or `None` if it's
+    /// cut off.
+    #[inline]
+    pub fn ether_type(&self) -> Option<EtherType> {
+        if self.is_complete() {
+            Some(self.header.ether_type())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the tag control information of the outer VLAN tag, or `None`
+    /// if the frame is untagged or the tag is cut off.
+    ///
+    /// If the device stripped the tag, it's read from the mbuf instead.
+    #[inline]
+    pub fn outer_tci(&self) -> Option<Tci> {
+        if let Some(bits) = self.stripped {
+            return Some(Tci::from_bits(bits));
+        }
+
+        match self.vlan_marker() {
+            // the tags follow the MAC addresses.
+            Some(VLAN_802_1Q) | Some(VLAN_802_1AD)
+                if self.has(MAC_ADDRS_SIZE + VlanTag::size_of()) =>
+            unsafe { Some(Tci::from_bits(self.header.chunk.dot1q.tag.tci.into())) },
+            _ => None,
+        }
+    }
+
+    /// Returns the tag control information of the inner VLAN tag of a QinQ
+    /// frame, or `None` if the frame is not QinQ or the tag is cut off.
+    #[inline]
+    pub fn inner_tci(&self) -> Option<Tci> {
+        if self.is_qinq() && self.has(MAC_ADDRS_SIZE + VlanTag::size_of() * 2) {
+            unsafe { Some(Tci::from_bits(self.header.chunk.qinq.ctag.tci.into())) }
+        } else {
+            None
+        }
+    }
+
+    /// Returns the underlying mbuf.
+    #[inline]
+    pub fn mbuf(&self) -> &Mbuf {
+        &self.mbuf
+    }
+
+    /// Parses the frame as `Ethernet` with the strict bounds checks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header is truncated.
+    #[inline]
+    pub fn into_ethernet(self) -> Result<Ethernet> {
+        self.mbuf.parse::<Ethernet>()
+    }
+
+    /// Returns the underlying mbuf, discarding the parsed header.
+    #[inline]
+    pub fn into_mbuf(self) -> Mbuf {
+        self.mbuf
+    }
+}
+
+impl fmt::Debug for PartialEthernet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ethernet")
+            .field("src", &format!("{}", self.src()))
+            .field("dst", &format!("{}", self.dst()))
+            .field("ether_type", &self.ether_type().map(|t| format!("{}", t)))
+            .field("vlan", &self.has_vlan())
+            .field("$available", &self.available())
+            .field("$complete", &self.is_complete())
+            .finish()
+    }
+}
+
+/// A source of the MAC addresses of the next hops, for
+/// [`Ethernet::resolve_and_set_dst`].
+///
+/// It's implemented by the runtime's [`NeighborCache`], and by a `HashMap`
+/// for static tables. Applications with their own ARP or neighbor discovery
+/// implement it for their cache.
+///
+/// [`Ethernet::resolve_and_set_dst`]: Ethernet::resolve_and_set_dst
+/// [`NeighborCache`]: crate::NeighborCache
+pub trait NeighborLookup {
+    /// Returns the MAC address of the neighbor, or `None` if it's not
+    /// resolved.
+    fn lookup_mac(&self, ip: IpAddr) -> Option<MacAddr>;
+}
+
+impl<S: std::hash::BuildHasher> NeighborLookup for HashMap<IpAddr, MacAddr, S> {
+    fn lookup_mac(&self, ip: IpAddr) -> Option<MacAddr> {
+        self.get(&ip).copied()
+    }
+}
+
+/// An owned snapshot of the Ethernet header fields.
+///
+/// The snapshot doesn't borrow the buffer it's read from. Use it to keep
+/// the fields around after the packet is gone, or to read the fields of
+/// a frame that's not in an `Mbuf` with [`parse_slice`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EthernetInfo {
+    /// The source MAC address.
+    pub src: MacAddr,
+
+    /// The destination MAC address.
+    pub dst: MacAddr,
+
+    /// The protocol identifier of the payload.
+    pub ether_type: EtherType,
+
+    /// The tag control information of the outer VLAN tag, or the only
+    /// tag of a Dot1q frame.
+    pub outer_tci: Option<Tci>,
+
+    /// The tag control information of the inner VLAN tag of a QinQ frame.
+    pub inner_tci: Option<Tci>,
+
+    /// The length of the header including the VLAN tags.
+    pub header_len: usize,
+}
+
+/// Parses the Ethernet header at the start of a plain byte slice, and
+/// returns an owned snapshot of the header fields.
+///
+/// This reads the same fields as [`Ethernet::view`] with the same bounds
+/// checks, but doesn't need an `Mbuf` or the DPDK runtime. It's meant for
+/// tools that analyze frames from other sources, for example a pcap file.
+///
+/// # Errors
+///
+/// Returns `BufferError::OutOfBuffer` if the slice is shorter than the
+/// header including the VLAN tags.
+///
+/// # Example
+///
+/// ```
+/// let info = parse_slice(&bytes)?;
+/// if info.ether_type == EtherTypes::Ipv4 {
+///     let ipv4 = &bytes[info.header_len..];
+/// }
+/// ```
+pub fn parse_slice(bytes: &[u8]) -> Result<EthernetInfo> {
+    ensure!(
+        bytes.len() >= EthernetHeader::size_of(),
+        BufferError::OutOfBuffer(EthernetHeader::size_of(), bytes.len())
+    );
+
+    // copies what's there into a zeroed header. the union is larger than
+    // an untagged header, so the slice can't be read in place.
+    let mut header = EthernetHeader::default();
+    let len = cmp::min(bytes.len(), mem::size_of::<EthernetHeader>());
+    unsafe {
+        ptr::copy_nonoverlapping(
+            bytes.as_ptr(),
+            &mut header as *mut EthernetHeader as *mut u8,
+            len,
+        );
+    }
+
+    ensure_tags_in_buffer(&header, bytes.len())?;
+    Ok(header.info(None))
+}
+
+/// Makes sure the VLAN tags of the header are in the buffer, with
+/// `available` bytes from the start of the header.
+#[inline]
+fn ensure_tags_in_buffer(header: &EthernetHeader, available: usize) -> Result<()> {
+    ensure!(
+        available >= header.len(),
+        BufferError::OutOfBuffer(header.len(), available)
+    );
+    Ok(())
+}
+
+/// Copies the bytes into a new buffer, and chains more buffers for the
+/// bytes that don't fit.
+fn copy_to_segments(data: &[u8]) -> Result<Mbuf> {
+    let mut head = Mbuf::new()?;
+    // extending needs more room than the length.
+    let room = head.tailroom() - 1;
+
+    let (first, rest) = data.split_at(cmp::min(room, data.len()));
+    if !first.is_empty() {
+        head.extend(0, first.len())?;
+        head.write_data_slice(0, first)?;
+    }
+    for chunk in rest.chunks(room) {
+        head.chain(Mbuf::from_bytes(chunk)?)?;
+    }
+
+    Ok(head)
+}
+
+/// Returns the size of the largest Ethernet frame a pipeline builds, to
+/// check the mempool data room against. The mempools have a data room of
+/// 2048 bytes, a larger frame fails with a `BufferError`.
+///
+/// `mtu` is the largest IP packet carried by the frame, headers included.
+/// `layers` are the lengths of the headers the pipeline puts in front of
+/// the IP packet, starting with the Ethernet header and its VLAN tags. Only
+/// the layers given are counted.
+///
+/// # Example
+///
+/// ```
+/// // Ethernet + IPv4 + TCP at 1500 MTU. The IPv4 and TCP headers are
+/// // part of the 1500 bytes.
+/// assert_eq!(1514, max_frame_size(&[EthernetHeader::size_of()], 1500));
+///
+/// // the same frame with room for a QinQ tag pair.
+/// let qinq = [EthernetHeader::size_of(), VlanTag::size_of() * 2];
+/// assert_eq!(1522, max_frame_size(&qinq, 1500));
+///
+/// // the same packet tunneled through VXLAN, with the outer Ethernet,
+/// // IPv4, UDP and VXLAN headers, and the inner Ethernet header.
+/// assert_eq!(1564, max_frame_size(&[14, 20, 8, 8, 14], 1500));
+/// ```
+pub fn max_frame_size(layers: &[usize], mtu: usize) -> usize {
+    layers.iter().sum::<usize>() + mtu
+}
+
+/// The journaled header fields.
+#[cfg(feature = "journal")]
+#[derive(Clone, Copy)]
+struct HeaderSnapshot {
+    src: MacAddr,
+    dst: MacAddr,
+    ether_type: EtherType,
+    vlan: u32,
+}
+
+/// A change to an Ethernet header field, reported by the mutation journal
+/// with the values before and after.
+#[cfg(feature = "journal")]
+#[cfg_attr(docsrs, doc(cfg(feature = "journal")))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FieldChange {
+    /// The source MAC address changed.
+    Src(MacAddr, MacAddr),
+
+    /// The destination MAC address changed.
+    Dst(MacAddr, MacAddr),
+
+    /// The payload EtherType changed.
+    EtherType(EtherType, EtherType),
+
+    /// The VLAN tags changed. The values are the VLAN identifiers combined,
+    /// the outer identifier in the upper 12 bits for QinQ frames, or `0`
+    /// if the frame is untagged.
+    Vlan(u32, u32),
+}
+
+/// The protocol identifier of the Ethernet frame payload.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[repr(C, packed)]
+pub struct EtherType(pub u16);
+
+impl EtherType {
+    /// Creates an Ethernet payload protocol identifier.
+    pub fn new(value: u16) -> Self {
+        EtherType(value)
+    }
+
+    /// Creates an Ethernet payload protocol identifier from its
+    /// representation as a byte array in big endian, or network byte
+    /// order, as it appears on the wire.
+    pub fn from_be_bytes(bytes: [u8; 2]) -> Self {
+        EtherType(u16::from_be_bytes(bytes))
+    }
+
+    /// Creates an Ethernet payload protocol identifier from its
+    /// representation as a byte array in little endian.
+    pub fn from_le_bytes(bytes: [u8; 2]) -> Self {
+        EtherType(u16::from_le_bytes(bytes))
+    }
+
+    /// Returns the memory representation of the identifier as a byte array
+    /// in big endian, or network byte order.
+    pub fn to_be_bytes(self) -> [u8; 2] {
+        self.0.to_be_bytes()
+    }
+
+    /// Returns the memory representation of the identifier as a byte array
+    /// in little endian.
+    pub fn to_le_bytes(self) -> [u8; 2] {
+        self.0.to_le_bytes()
+    }
+
+    /// Returns whether the payload is a known tunneling or overlay
+    /// protocol.
+    ///
+    /// The tunneling protocols are `Mpls`, `MplsMulticast`, `Pbb` and
+    /// `Nsh`.
+    pub fn is_tunnel(&self) -> bool {
+        matches!(
+            *self,
+            EtherTypes::Mpls | EtherTypes::MplsMulticast | EtherTypes::Pbb | EtherTypes::Nsh
+        )
+    }
+
+    /// Returns the protocol as a `KnownEtherType`, or `None` if the crate
+    /// doesn't recognize it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// match ethernet.ether_type().known() {
+    ///     Some(KnownEtherType::Ipv4) => ...,
+    ///     Some(KnownEtherType::Ipv6) => ...,
+    ///     Some(_) | None => ...,
+    /// }
+    /// ```
+    pub fn known(&self) -> Option<KnownEtherType> {
+        match *self {
+            EtherTypes::Arp => Some(KnownEtherType::Arp),
+            EtherTypes::Ipv4 => Some(KnownEtherType::Ipv4),
+            EtherTypes::Ipv6 => Some(KnownEtherType::Ipv6),
+            EtherTypes::Vlan => Some(KnownEtherType::Vlan),
+            EtherTypes::QinQ => Some(KnownEtherType::QinQ),
+            EtherTypes::Pbb => Some(KnownEtherType::Pbb),
+            EtherTypes::Mpls => Some(KnownEtherType::Mpls),
+            EtherTypes::MplsMulticast => Some(KnownEtherType::MplsMulticast),
+            EtherTypes::Nsh => Some(KnownEtherType::Nsh),
+            EtherTypes::Lldp => Some(KnownEtherType::Lldp),
+            _ => None,
+        }
+    }
+}
+
+/// Supported Ethernet payload protocol types.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod EtherTypes {
+    use super::EtherType;
+
+    /// Address resolution protocol.
+    pub const Arp: EtherType = EtherType(0x0806);
+    /// Internet Protocol version 4.
+    pub const Ipv4: EtherType = EtherType(0x0800);
+    /// Internet Protocol version 6.
+    pub const Ipv6: EtherType = EtherType(0x86DD);
+    /// Provider backbone bridging, or MAC-in-MAC.
+    pub const Pbb: EtherType = EtherType(0x88E7);
+    /// Multiprotocol label switching unicast.
+    pub const Mpls: EtherType = EtherType(0x8847);
+    /// Multiprotocol label switching multicast.
+    pub const MplsMulticast: EtherType = EtherType(0x8848);
+    /// Network service header.
+    pub const Nsh: EtherType = EtherType(0x894F);
+    /// IEEE 802.1Q VLAN tag.
+    pub const Vlan: EtherType = EtherType(0x8100);
+    /// IEEE 802.1ad service VLAN tag, or QinQ.
+    pub const QinQ: EtherType = EtherType(0x88A8);
+    /// Link layer discovery protocol.
+    pub const Lldp: EtherType = EtherType(0x88CC);
+}
+
+/// The Ethernet payload protocols the crate recognizes.
+///
+/// The enum companion to the [`EtherTypes`] consts. Matching on it is
+/// exhaustive, so a newly recognized protocol shows up at compile time
+/// instead of falling through a wildcard.
+///
+/// [`EtherTypes`]: crate::packets::EtherTypes
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum KnownEtherType {
+    /// Address resolution protocol.
+    Arp,
+    /// Internet Protocol version 4.
+    Ipv4,
+    /// Internet Protocol version 6.
+    Ipv6,
+    /// IEEE 802.1Q VLAN tag.
+    Vlan,
+    /// IEEE 802.1ad service VLAN tag, or QinQ.
+    QinQ,
+    /// Provider backbone bridging, or MAC-in-MAC.
+    Pbb,
+    /// Multiprotocol label switching unicast.
+    Mpls,
+    /// Multiprotocol label switching multicast.
+    MplsMulticast,
+    /// Network service header.
+    Nsh,
+    /// Link layer discovery protocol.
+    Lldp,
+}
+
+impl From<KnownEtherType> for EtherType {
+    fn from(known: KnownEtherType) -> Self {
+        match known {
+            KnownEtherType::Arp => EtherTypes::Arp,
+            KnownEtherType::Ipv4 => EtherTypes::Ipv4,
+            KnownEtherType::Ipv6 => EtherTypes::Ipv6,
+            KnownEtherType::Vlan => EtherTypes::Vlan,
+            KnownEtherType::QinQ => EtherTypes::QinQ,
+            KnownEtherType::Pbb => EtherTypes::Pbb,
+            KnownEtherType::Mpls => EtherTypes::Mpls,
+            KnownEtherType::MplsMulticast => EtherTypes::MplsMulticast,
+            KnownEtherType::Nsh => EtherTypes::Nsh,
+            KnownEtherType::Lldp => EtherTypes::Lldp,
+        }
+    }
+}
+
+/// The EtherTypes below this value are looked up in the bitset.
+const ETHER_TYPE_SET_LOW: u16 = 0x1000;
+
+/// A set of `EtherType`s with fast lookup.
+///
+/// Useful as an allowlist in a hot filter, instead of a chain of `||`
+/// comparisons. The values below `0x1000`, which include `Ipv4` and `Arp`,
+/// are kept in a bitset and looked up in constant time. The rarer higher
+/// values are kept in a short list and scanned linearly.
+///
+/// # Example
+///
+/// ```
+/// let allowed = EtherTypeSet::from([EtherTypes::Ipv4, EtherTypes::Ipv6]);
+/// let mut batch = batch.filter(move |ethernet| allowed.contains(ethernet.ether_type()));
+/// ```
+#[derive(Clone)]
+pub struct EtherTypeSet {
+    low: [u64; ETHER_TYPE_SET_LOW as usize / 64],
+    high: Vec<u16>,
+}
+
+impl EtherTypeSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        EtherTypeSet {
+            low: [0; ETHER_TYPE_SET_LOW as usize / 64],
+            high: vec![],
+        }
+    }
+
+    /// Adds an `EtherType` to the set.
+    pub fn insert(&mut self, ether_type: EtherType) {
+        let value = ether_type.0;
+        if value < ETHER_TYPE_SET_LOW {
+            self.low[value as usize / 64] |= 1 << (value % 64);
+        } else if !self.high.contains(&value) {
+            self.high.push(value);
+        }
+    }
+
+    /// Returns whether the set contains the `EtherType`.
+    #[inline]
+    pub fn contains(&self, ether_type: EtherType) -> bool {
+        let value = ether_type.0;
+        if value < ETHER_TYPE_SET_LOW {
+            self.low[value as usize / 64] & (1 << (value % 64)) != 0
+        } else {
+            self.high.contains(&value)
+        }
+    }
+
+    /// Returns the number of `EtherType`s in the set.
+    pub fn len(&self) -> usize {
+        let low = self
+            .low
+            .iter()
+            .map(|w| w.count_ones() as usize)
+            .sum::<usize>();
+        low + self.high.len()
+    }
+
+    /// Returns whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over the `EtherType`s in the set, the values
+    /// below `0x1000` in ascending order first.
+    pub fn iter(&self) -> impl Iterator<Item = EtherType> + '_ {
+        (0..ETHER_TYPE_SET_LOW)
+            .map(EtherType)
+            .filter(move |&ether_type| self.contains(ether_type))
+            .chain(self.high.iter().map(|&value| EtherType(value)))
+    }
+}
+
+impl Default for EtherTypeSet {
+    fn default() -> Self {
+        EtherTypeSet::new()
+    }
+}
+
+impl fmt::Debug for EtherTypeSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.iter().map(|ether_type| format!("{}", ether_type)))
+            .finish()
+    }
+}
+
+impl<const N: usize> From<[EtherType; N]> for EtherTypeSet {
+    fn from(ether_types: [EtherType; N]) -> Self {
+        ether_types.iter().copied().collect()
+    }
+}
+
+impl From<&[EtherType]> for EtherTypeSet {
+    fn from(ether_types: &[EtherType]) -> Self {
+        ether_types.iter().copied().collect()
+    }
+}
+
+impl FromIterator<EtherType> for EtherTypeSet {
+    fn from_iter<I: IntoIterator<Item = EtherType>>(iter: I) -> Self {
+        let mut set = EtherTypeSet::new();
+        iter.into_iter()
+            .for_each(|ether_type| set.insert(ether_type));
+        set
+    }
+}
+
+impl fmt::Display for EtherType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                EtherTypes::Arp => "ARP".to_string(),
+                EtherTypes::Ipv4 => "IPv4".to_string(),
+                EtherTypes::Ipv6 => "IPv6".to_string(),
+                EtherTypes::Pbb => "PBB".to_string(),
+                EtherTypes::Mpls => "MPLS".to_string(),
+                EtherTypes::MplsMulticast => "MPLS multicast".to_string(),
+                EtherTypes::Nsh => "NSH".to_string(),
+                EtherTypes::Vlan => "802.1Q".to_string(),
+                EtherTypes::QinQ => "802.1ad".to_string(),
+                EtherTypes::Lldp => "LLDP".to_string(),
+                _ => {
+                    let t = self.0;
+                    format!("0x{:04x}", t)
+                }
+            }
+        )
+    }
+}
+
+/// VLAN tag.
+///
+/// Tags are applied to a frame with [`Ethernet::apply_template`], which
+/// also sets the tag protocol identifier.
+#[derive(Clone, Copy, Debug, Default, SizeOf)]
+#[repr(C, packed)]
+pub struct VlanTag {
+    tpid: u16be,
+    tci: u16be,
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+impl VlanTag {
+    /// Creates a new tag with the VLAN identifier and the default priority.
+    ///
+    /// Only the lower 12 bits of `vid` are used.
+    pub fn new(vid: u16) -> Self {
+        VlanTag {
+            tpid: u16be::default(),
+            tci: (vid & 0x0fff).into(),
+        }
+    }
+
+    /// Sets the priority code point. Only the lower 3 bits of `priority`
+    /// are used.
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        let tci: u16 = self.tci.into();
+        self.tci = ((tci & 0x1fff) | (u16::from(priority & 0x07) << 13)).into();
+        self
+    }
+
+    /// Returns the tag protocol identifier, either 802.1q (Dot1q) or 802.1ad (QinQ).
+    #[inline]
+    fn tag_id(&self) -> u16 {
+        self.tpid.into()
+    }
+
+    /// Returns whether the tag is a service tag, or S-TAG, with the 802.1ad
+    /// tag protocol identifier `0x88a8`.
+    #[inline]
+    pub fn is_service_tag(&self) -> bool {
+        self.tag_id() == VLAN_802_1AD
+    }
+
+    /// Returns whether the tag is a customer tag, or C-TAG, with the 802.1Q
+    /// tag protocol identifier `0x8100`.
+    #[inline]
+    pub fn is_customer_tag(&self) -> bool {
+        self.tag_id() == VLAN_802_1Q
+    }
+
+    /// Returns the priority code point.
+    #[allow(dead_code)]
+    #[inline]
+    fn priority(&self) -> u8 {
+        let tci: u16 = self.tci.into();
+        (tci >> 13) as u8
+    }
+
+    /// Returns whether the frame is eligible to be dropped in the presence
+    /// of congestion.
+    #[allow(dead_code)]
+    #[inline]
+    fn drop_eligible(&self) -> bool {
+        self.tci & u16be::from(0x1000) > u16be::MIN
+    }
+
+    /// Returns the tag control information.
+    #[inline]
+    fn tci(&self) -> Tci {
+        Tci::from_bits(self.tci.into())
+    }
+
+    /// Returns the VLAN identifier.
+    #[inline]
+    fn identifier(&self) -> u16 {
+        (self.tci & u16be::from(0x0fff)).into()
+    }
+
+    /// Returns whether the tag is a priority tag, with a VLAN identifier of
+    /// `0`. The frame carries a priority but is not a member of any VLAN.
+    #[inline]
+    pub fn is_priority_tagged(&self) -> bool {
+        self.identifier() == 0
+    }
+
+    /// Returns whether the tag has the reserved VLAN identifier 4095.
+    #[inline]
+    pub fn is_reserved(&self) -> bool {
+        self.identifier() == 0x0fff
+    }
+}
+
+/// The tag control information of a VLAN tag.
+///
+/// Build one to remark the priority, drop eligibility and VLAN identifier
+/// of a tag together with [`Ethernet::set_outer_tci`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Tci {
+    /// The priority code point. Only the lower 3 bits are used.
+    pub pcp: u8,
+
+    /// The drop eligible indicator.
+    pub dei: bool,
+
+    /// The VLAN identifier. Only the lower 12 bits are used.
+    pub vid: u16,
+}
+
+impl Tci {
+    /// Returns the 16-bit tag control information.
+    #[inline]
+    pub fn to_bits(self) -> u16 {
+        (u16::from(self.pcp & 0x07) << 13) | (u16::from(self.dei) << 12) | (self.vid & 0x0fff)
+    }
+
+    /// Creates a new `Tci` from the 16-bit tag control information.
+    #[inline]
+    pub fn from_bits(bits: u16) -> Self {
+        Tci {
+            pcp: (bits >> 13) as u8,
+            dei: bits & 0x1000 != 0,
+            vid: bits & 0x0fff,
+        }
+    }
+}
+
+/// Dot1q chunk for a VLAN header.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, packed)]
+struct Dot1q {
+    tag: VlanTag,
+    ether_type: u16be,
+}
+
+/// QinQ chunk for a VLAN header.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, packed)]
+struct Qinq {
+    stag: VlanTag,
+    ctag: VlanTag,
+    ether_type: u16be,
+}
+
+/// The Ethernet header chunk follows the source MAC.
+#[allow(missing_debug_implementations)]
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+union Chunk {
+    ether_type: u16be,
+    dot1q: Dot1q,
+    qinq: Qinq,
+}
+
+impl Default for Chunk {
+    fn default() -> Chunk {
+        Chunk {
+            ether_type: u16be::default(),
+        }
+    }
+}
+
+/// Ethernet header.
+///
+/// Can be built ahead of time as a template for [`Ethernet::apply_template`].
+#[allow(missing_debug_implementations)]
+#[derive(Clone, Copy, Default)]
+#[repr(C, packed)]
+pub struct EthernetHeader {
+    dst: MacAddr,
+    src: MacAddr,
+    chunk: Chunk,
+}
+
+impl EthernetHeader {
+    /// Creates a new untagged header.
+    pub fn new(src: MacAddr, dst: MacAddr, ether_type: EtherType) -> Self {
+        EthernetHeader {
+            dst,
+            src,
+            chunk: Chunk {
+                ether_type: ether_type.0.into(),
+            },
+        }
+    }
+
+    /// Returns the marker that indicates whether the frame is VLAN.
+    #[inline]
+    fn vlan_marker(&self) -> u16 {
+        unsafe { self.chunk.ether_type.into() }
+    }
+
+    /// Returns the protocol identifier of the payload.
+    #[inline]
+    pub(crate) fn ether_type(&self) -> EtherType {
+        let ether_type = unsafe {
+            match self.vlan_marker() {
+                VLAN_802_1Q => self.chunk.dot1q.ether_type,
+                VLAN_802_1AD => self.chunk.qinq.ether_type,
+                _ => self.chunk.ether_type,
+            }
+        };
+
+        EtherType::new(ether_type.into())
+    }
+
+    /// Returns an owned snapshot of the header fields. The VLAN tags must
+    /// be in the buffer.
+    ///
+    /// A tag stripped by the device is reported as the only tag, same as
+    /// a Dot1q tag still in the header.
+    pub(crate) fn info(&self, stripped: Option<u16>) -> EthernetInfo {
+        let (outer_tci, inner_tci) = unsafe {
+            match self.vlan_marker() {
+                _ if stripped.is_some() => (stripped.map(Tci::from_bits), None),
+                VLAN_802_1Q => (Some(self.chunk.dot1q.tag.tci()), None),
+                VLAN_802_1AD => (
+                    Some(self.chunk.qinq.stag.tci()),
+                    Some(self.chunk.qinq.ctag.tci()),
+                ),
+                _ => (None, None),
+            }
+        };
+
+        EthernetInfo {
+            src: self.src,
+            dst: self.dst,
+            ether_type: self.ether_type(),
+            outer_tci,
+            inner_tci,
+            header_len: self.len(),
+        }
+    }
+
+    /// Returns the length of the header including the VLAN tags.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        match self.vlan_marker() {
+            VLAN_802_1Q => EthernetHeader::size_of() + VlanTag::size_of(),
+            VLAN_802_1AD => EthernetHeader::size_of() + VlanTag::size_of() * 2,
+            _ => EthernetHeader::size_of(),
+        }
+    }
+}
+
+impl SizeOf for EthernetHeader {
+    /// Size of the Ethernet header.
+    ///
+    /// Because the Ethernet header is not fixed and modeled with a union, the
+    /// memory layout size is not the correct header size. For a brand new
+    /// Ethernet header, we will always report 14 bytes as the fixed portion,
+    /// which is the minimum size without any tags. `Ethernet::header_len()`
+    /// will report the correct instance size based on the presence or absence
+    /// of VLAN tags.
+    #[inline]
+    fn size_of() -> usize {
+        ETH_HEADER_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::arp::{Arp4, OperationCodes};
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::pbb::Pbb;
+    use crate::testils::byte_arrays::{
+        IPV4_UDP_PACKET, PBB_PACKET, VLAN_DOT1Q_PACKET, VLAN_QINQ_PACKET,
+    };
+    use crate::testils::{build_packet, PacketBuilder};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn size_of_ethernet_header() {
+        assert_eq!(14, EthernetHeader::size_of());
+    }
+
+    #[test]
+    fn ether_type_to_string() {
+        assert_eq!("ARP", EtherTypes::Arp.to_string());
+        assert_eq!("IPv4", EtherTypes::Ipv4.to_string());
+        assert_eq!("IPv6", EtherTypes::Ipv6.to_string());
+        assert_eq!("PBB", EtherTypes::Pbb.to_string());
+        assert_eq!("MPLS", EtherTypes::Mpls.to_string());
+        assert_eq!("MPLS multicast", EtherTypes::MplsMulticast.to_string());
+        assert_eq!("NSH", EtherTypes::Nsh.to_string());
+        assert_eq!("802.1Q", EtherTypes::Vlan.to_string());
+        assert_eq!("802.1ad", EtherTypes::QinQ.to_string());
+        assert_eq!("LLDP", EtherTypes::Lldp.to_string());
+        assert_eq!("0x0000", EtherType::new(0).to_string());
+    }
+
+    #[test]
+    fn known_ether_types() {
+        let all = [
+            KnownEtherType::Arp,
+            KnownEtherType::Ipv4,
+            KnownEtherType::Ipv6,
+            KnownEtherType::Vlan,
+            KnownEtherType::QinQ,
+            KnownEtherType::Pbb,
+            KnownEtherType::Mpls,
+            KnownEtherType::MplsMulticast,
+            KnownEtherType::Nsh,
+            KnownEtherType::Lldp,
+        ];
+
+        for &known in all.iter() {
+            assert_eq!(Some(known), EtherType::from(known).known());
+        }
+
+        assert_eq!(Some(KnownEtherType::Ipv6), EtherType::new(0x86dd).known());
+        assert_eq!(None, EtherType::new(0).known());
+        assert_eq!(None, EtherType::new(0x9000).known());
+    }
+
+    #[test]
+    fn tunnel_ether_types() {
+        assert!(EtherTypes::Mpls.is_tunnel());
+        assert!(EtherTypes::MplsMulticast.is_tunnel());
+        assert!(EtherTypes::Pbb.is_tunnel());
+        assert!(EtherTypes::Nsh.is_tunnel());
+
+        assert!(!EtherTypes::Ipv4.is_tunnel());
+        assert!(!EtherTypes::Arp.is_tunnel());
+        assert!(!EtherType::new(0).is_tunnel());
+    }
+
+    #[test]
+    fn ether_type_bytes_round_trip() {
+        assert_eq!([0x08, 0x00], EtherTypes::Ipv4.to_be_bytes());
+        assert_eq!([0x00, 0x08], EtherTypes::Ipv4.to_le_bytes());
+        assert_eq!(
+            EtherTypes::Ipv4,
+            EtherType::from_be_bytes(EtherTypes::Ipv4.to_be_bytes())
+        );
+        assert_eq!(
+            EtherTypes::Ipv4,
+            EtherType::from_le_bytes(EtherTypes::Ipv4.to_le_bytes())
+        );
+        assert_eq!(EtherTypes::Ipv6, EtherType::from_be_bytes([0x86, 0xdd]));
+    }
+
+    #[capsule::test]
+    fn symmetric_l2_hash() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        let hash = ethernet.symmetric_l2_hash();
+        let seeded = ethernet.symmetric_l2_hash_seeded(42);
+        assert_ne!(hash, seeded);
+
+        ethernet.swap_addresses();
+        assert_eq!(hash, ethernet.symmetric_l2_hash());
+        assert_eq!(seeded, ethernet.symmetric_l2_hash_seeded(42));
+
+        ethernet.set_dst(MacAddr::new(0, 0, 0, 0, 0, 0x10));
+        assert_ne!(hash, ethernet.symmetric_l2_hash());
+    }
+
+    #[capsule::test]
+    fn symmetric_l2_hash_with_vlan() {
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let mut dot1q = packet.parse::<Ethernet>().unwrap();
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let qinq = packet.parse::<Ethernet>().unwrap();
+
+        // same addresses, different tags.
+        assert_eq!(dot1q.src(), qinq.src());
+        assert_eq!(dot1q.dst(), qinq.dst());
+        assert_ne!(dot1q.symmetric_l2_hash(), qinq.symmetric_l2_hash());
+
+        let hash = dot1q.symmetric_l2_hash();
+        dot1q.swap_addresses();
+        assert_eq!(hash, dot1q.symmetric_l2_hash());
+    }
+
+    #[capsule::test]
+    fn canonical_l2_key() {
+        let src = MacAddr::new(0, 0, 0, 0, 0, 1);
+        let dst = MacAddr::new(0, 0, 0, 0, 0, 2);
+        let flow = |builder: PacketBuilder| {
+            builder
+                .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+                .udp(5000, 53)
+                .build()
+        };
+
+        let untagged = flow(build_packet().ethernet(src, dst));
+        let untagged = untagged.parse::<Ethernet>().unwrap();
+        let dot1q = flow(build_packet().ethernet(src, dst).vlan(100));
+        let dot1q = dot1q.parse::<Ethernet>().unwrap();
+        let qinq = flow(build_packet().ethernet(src, dst).qinq(10).vlan(100));
+        let qinq = qinq.parse::<Ethernet>().unwrap();
+
+        let key = untagged.canonical_l2_key(true);
+        assert_eq!(&dst.octets(), &key[..6]);
+        assert_eq!(&src.octets(), &key[6..12]);
+        assert_eq!(&EtherTypes::Ipv4.to_be_bytes(), &key[12..]);
+        assert_eq!(key, dot1q.canonical_l2_key(true));
+        assert_eq!(key, qinq.canonical_l2_key(true));
+
+        // the tags tell the frames apart.
+        assert_eq!(key, untagged.canonical_l2_key(false));
+        assert_ne!(key, dot1q.canonical_l2_key(false));
+        assert_ne!(dot1q.canonical_l2_key(false), qinq.canonical_l2_key(false));
+
+        // a frame of the other direction is another key.
+        let reply = flow(build_packet().ethernet(dst, src));
+        let reply = reply.parse::<Ethernet>().unwrap();
+        assert_ne!(key, reply.canonical_l2_key(true));
+    }
+
+    #[capsule::test]
+    fn parse_ethernet_packet() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert_eq!("00:00:00:00:00:01", ethernet.dst().to_string());
+        assert_eq!("00:00:00:00:00:02", ethernet.src().to_string());
+        assert_eq!(EtherTypes::Ipv4, ethernet.ether_type());
+    }
+
+    #[capsule::test]
+    fn parse_dot1q_packet() {
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert_eq!("00:00:00:00:00:01", ethernet.dst().to_string());
+        assert_eq!("00:00:00:00:00:02", ethernet.src().to_string());
+        assert!(ethernet.is_dot1q());
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
+        assert_eq!(18, ethernet.header_len());
+    }
+
+    #[test]
+    fn tci_round_trip() {
+        let tci = Tci {
+            pcp: 5,
+            dei: true,
+            vid: 0x123,
+        };
+        assert_eq!(0xb123, tci.to_bits());
+        assert_eq!(tci, Tci::from_bits(tci.to_bits()));
+
+        for &bits in [0x0000, 0x007b, 0x1fff, 0xe001, 0xffff].iter() {
+            assert_eq!(bits, Tci::from_bits(bits).to_bits());
+        }
+
+        // the out of range bits are ignored.
+        let tci = Tci {
+            pcp: 0xff,
+            dei: false,
+            vid: 0xffff,
+        };
+        assert_eq!(0xefff, tci.to_bits());
+    }
+
+    #[capsule::test]
+    fn set_outer_tci() {
+        let tci = Tci {
+            pcp: 3,
+            dei: true,
+            vid: 42,
+        };
+
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        assert_eq!(Some(Tci::from_bits(0x007b)), ethernet.outer_tci());
+        ethernet.set_outer_tci(tci).unwrap();
+        assert_eq!(Some(tci), ethernet.outer_tci());
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
+
+        // only the outer tag of a QinQ frame is changed.
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        ethernet.set_outer_tci(tci).unwrap();
+        assert_eq!(Some(tci), ethernet.outer_tci());
+        assert_eq!(0x0065, unsafe {
+            ethernet.header().chunk.qinq.ctag.identifier()
+        });
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        assert_eq!(None, ethernet.outer_tci());
+        assert!(ethernet.set_outer_tci(tci).is_err());
+    }
+
+    #[test]
+    fn max_frame_size_of_layers() {
+        let eth = EthernetHeader::size_of();
+        let tag = VlanTag::size_of();
+
+        assert_eq!(1500, max_frame_size(&[], 1500));
+        assert_eq!(1514, max_frame_size(&[eth], 1500));
+        assert_eq!(1518, max_frame_size(&[eth, tag], 1500));
+        assert_eq!(1522, max_frame_size(&[eth, tag * 2], 1500));
+        assert_eq!(1564, max_frame_size(&[eth, 20, 8, 8, eth], 1500));
+    }
+
+    #[capsule::test]
+    fn max_frame_fits() {
+        let mtu = 1500;
+        let eth = EthernetHeader::size_of();
+        let tag = VlanTag::size_of();
+
+        let frames = [
+            (&IPV4_UDP_PACKET[..], vec![eth]),
+            (&VLAN_DOT1Q_PACKET[..], vec![eth, tag]),
+            (&VLAN_QINQ_PACKET[..], vec![eth, tag * 2]),
+        ];
+
+        for (bytes, layers) in frames.iter() {
+            let packet = Mbuf::from_bytes(bytes).unwrap();
+            let mut ethernet = packet.parse::<Ethernet>().unwrap();
+            let len = ethernet.len();
+            let extra = mtu - ethernet.payload_len();
+            ethernet.mbuf_mut().extend(len, extra).unwrap();
+
+            assert_eq!(max_frame_size(layers, mtu), ethernet.len());
+        }
+    }
+
+    /// Returns a 9000 byte jumbo frame, chained across 5 segments.
+    fn jumbo_frame() -> Mbuf {
+        let mut head = IPV4_UDP_PACKET.to_vec();
+        head.resize(1800, 0);
+        let mut packet = Mbuf::from_bytes(&head).unwrap();
+        for _ in 0..4 {
+            packet.chain(Mbuf::from_bytes(&[0; 1800]).unwrap()).unwrap();
+        }
+        packet
+    }
+
+    #[capsule::test]
+    fn reject_frame_longer_than_max() {
+        assert_eq!(None, ParseOptions::current().max_frame_len);
+        assert!(jumbo_frame().parse::<Ethernet>().is_ok());
+
+        ParseOptions {
+            max_frame_len: Some(1518),
+        }
+        .set();
+
+        let err = jumbo_frame().parse::<Ethernet>().unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FrameError>(),
+            Some(FrameError::TooLong(9000, 1518))
+        ));
+
+        // frames up to the maximum still parse.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(packet.parse::<Ethernet>().is_ok());
+
+        ParseOptions {
+            max_frame_len: Some(9000),
+        }
+        .set();
+        assert!(jumbo_frame().parse::<Ethernet>().is_ok());
+
+        ParseOptions::default().set();
+    }
+
+    /// Returns the Dot1q frame as the device delivers it with the VLAN
+    /// strip offload, with the tag moved from the data to the mbuf.
+    fn stripped_dot1q_packet() -> Mbuf {
+        let mut bytes = VLAN_DOT1Q_PACKET.to_vec();
+        let tag = bytes.drain(12..16).collect::<Vec<_>>();
+        let mut packet = Mbuf::from_bytes(&bytes).unwrap();
+        packet.set_vlan_tci(u16::from_be_bytes([tag[2], tag[3]]));
+        packet
+    }
+
+    #[capsule::test]
+    fn stripped_vlan_tag() {
+        let tagged = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET)
+            .unwrap()
+            .parse::<Ethernet>()
+            .unwrap();
+        let stripped = stripped_dot1q_packet().parse::<Ethernet>().unwrap();
+
+        // the same frame reads the same with or without stripping, but
+        // the tag is no longer in the header.
+        assert!(tagged.has_vlan());
+        assert!(stripped.has_vlan());
+        assert!(!stripped.is_dot1q());
+        assert!(!stripped.is_qinq());
+        assert_eq!(tagged.outer_tci(), stripped.outer_tci());
+        assert_eq!(tagged.switching_vid(), stripped.switching_vid());
+        assert_eq!(tagged.is_priority_tagged(), stripped.is_priority_tagged());
+        assert_eq!(tagged.ether_type(), stripped.ether_type());
+        assert_eq!(tagged.symmetric_l2_hash(), stripped.symmetric_l2_hash());
+        assert!(stripped.validate_vlan_tags().is_ok());
+
+        let view = Ethernet::view(stripped.mbuf()).unwrap();
+        assert!(view.has_vlan());
+        assert!(!view.is_dot1q());
+        assert_eq!(view.info(), stripped.info());
+
+        // except for the bytes, the tag is no longer in the data.
+        let (tagged_info, stripped_info) = (tagged.info(), stripped.info());
+        assert_eq!(tagged_info.outer_tci, stripped_info.outer_tci);
+        assert_eq!(tagged_info.header_len - 4, stripped_info.header_len);
+        assert!(stripped.vlan_tags().is_empty());
+    }
+
+    #[capsule::test]
+    fn set_stripped_outer_tci() {
+        let tci = Tci {
+            pcp: 5,
+            dei: false,
+            vid: 0,
+        };
+
+        let mut ethernet = stripped_dot1q_packet().parse::<Ethernet>().unwrap();
+        ethernet.set_outer_tci(tci).unwrap();
+        assert_eq!(Some(tci), ethernet.outer_tci());
+        assert_eq!(Some(tci.to_bits()), ethernet.mbuf().vlan_tci());
+        assert!(ethernet.is_priority_tagged());
+        assert_eq!(None, ethernet.switching_vid());
+
+        let reserved = Tci { vid: 0x0fff, ..tci };
+        ethernet.set_outer_tci(reserved).unwrap();
+        assert!(ethernet.validate_vlan_tags().is_err());
+    }
+
+    #[capsule::test]
+    fn stripped_tag_only_on_outer_frame() {
+        let mut packet = Mbuf::from_bytes(&PBB_PACKET).unwrap();
+        packet.set_vlan_tci(0x0064);
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(ethernet.has_vlan());
+
+        let customer = ethernet.parse::<Pbb>().unwrap().parse_inner().unwrap();
+        assert_eq!(None, customer.stripped_tci());
+    }
+
+    #[capsule::test]
+    fn decapsulation_chain() {
+        let packet = Mbuf::from_bytes(&PBB_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert_eq!(
+            vec![EtherTypes::Pbb, EtherTypes::Arp],
+            ethernet.decapsulation_chain()
+        );
+
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert_eq!(vec![EtherTypes::Arp], ethernet.decapsulation_chain());
+    }
+
+    #[capsule::test]
+    fn split_at_payload() {
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let (headers, payload) = ethernet.split_at_payload().unwrap();
+
+        assert_eq!(18, headers.pkt_len());
+        assert_eq!(VLAN_DOT1Q_PACKET.len() - 18, payload.pkt_len());
+        assert_eq!(&VLAN_DOT1Q_PACKET[18..], &payload.read_segments(0)[..]);
+
+        // the headers still parse, without a payload.
+        let ethernet = headers.parse::<Ethernet>().unwrap();
+        assert!(ethernet.is_dot1q());
+        assert_eq!(0, ethernet.payload_len());
+        drop(ethernet);
+        drop(payload);
+
+        // the payload of a jumbo frame spans several new buffers.
+        let ethernet = jumbo_frame().parse::<Ethernet>().unwrap();
+        let (headers, payload) = ethernet.split_at_payload().unwrap();
+        assert_eq!(14, headers.pkt_len());
+        assert_eq!(1, headers.segments());
+        assert_eq!(9000 - 14, payload.pkt_len());
+        assert!(payload.segments() > 1);
+        assert_eq!(
+            &IPV4_UDP_PACKET[14..],
+            &payload.read_segments(0)[..IPV4_UDP_PACKET.len() - 14]
+        );
+    }
+
+    #[capsule::test]
+    fn debug_with_chain() {
+        let packet = Mbuf::from_bytes(&PBB_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let debug = ethernet.debug_with_chain();
+        assert!(debug.contains("ether_type: \"PBB→ARP\""));
+        assert!(format!("{:?}", ethernet).contains("ether_type: \"PBB\""));
+
+        // without a tunnel, the same as the Debug output.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert_eq!(format!("{:?}", ethernet), ethernet.debug_with_chain());
+    }
+
+    #[capsule::test]
+    fn encapsulation_depth() {
+        let depth = |bytes: &[u8]| {
+            let packet = Mbuf::from_bytes(bytes).unwrap();
+            packet.parse::<Ethernet>().unwrap().encapsulation_depth()
+        };
+
+        assert_eq!(0, depth(&IPV4_UDP_PACKET));
+        assert_eq!(1, depth(&VLAN_DOT1Q_PACKET));
+        assert_eq!(2, depth(&VLAN_QINQ_PACKET));
+
+        // IPv4 under a stack of three MPLS labels.
+        #[rustfmt::skip]
+        let labels = [
+            0x88, 0x47,
+            0x00, 0x01, 0x40, 0x40,
+            0x00, 0x02, 0x80, 0x40,
+            0x00, 0x03, 0x81, 0x40,
+        ];
+        let mpls = [&IPV4_UDP_PACKET[..12], &labels, &IPV4_UDP_PACKET[14..]].concat();
+        assert_eq!(3, depth(&mpls));
+
+        // the tag stripped by the device still counts.
+        let mut packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        packet.set_vlan_tci(0x0064);
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert_eq!(1, ethernet.encapsulation_depth());
+    }
+
+    #[capsule::test]
+    fn parse_qinq_packet() {
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert_eq!("00:00:00:00:00:01", ethernet.dst().to_string());
+        assert_eq!("00:00:00:00:00:02", ethernet.src().to_string());
+        assert!(ethernet.is_qinq());
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
+        assert_eq!(22, ethernet.header_len());
+    }
+
+    #[capsule::test]
+    fn classify_qinq_tags() {
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+
+        let tags = ethernet.vlan_tags();
+        assert_eq!(2, tags.len());
+        assert!(tags[0].is_service_tag());
+        assert!(!tags[0].is_customer_tag());
+        assert_eq!(30, tags[0].tci().vid);
+        assert!(tags[1].is_customer_tag());
+        assert!(!tags[1].is_service_tag());
+        assert_eq!(101, tags[1].tci().vid);
+
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(ethernet.vlan_tags()[0].is_customer_tag());
+    }
+
+    #[cfg(feature = "journal")]
+    #[capsule::test]
+    fn journal_frame_mutations() {
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        // nothing is journaled before the journal starts.
+        ethernet.swap_addresses();
+        assert!(ethernet.diff_journal().is_empty());
+
+        ethernet.begin_journal();
+        assert!(ethernet.diff_journal().is_empty());
+
+        let src = ethernet.src();
+        let dst = ethernet.dst();
+        ethernet.swap_addresses();
+        ethernet.set_ether_type(EtherTypes::Ipv4);
+        assert!(ethernet.remove_vlan_with_vid(101).unwrap());
+
+        assert_eq!(
+            vec![
+                FieldChange::Src(src, dst),
+                FieldChange::Dst(dst, src),
+                FieldChange::EtherType(EtherTypes::Arp, EtherTypes::Ipv4),
+                FieldChange::Vlan(30 << 12 | 101, 30),
+            ],
+            ethernet.diff_journal()
+        );
+
+        // restarting the journal takes a new snapshot.
+        ethernet.begin_journal();
+        assert!(ethernet.diff_journal().is_empty());
+    }
+
+    #[test]
+    fn ether_type_set_contains() {
+        let set = EtherTypeSet::from([EtherTypes::Ipv4, EtherTypes::Ipv6, EtherTypes::Ipv4]);
+
+        assert!(set.contains(EtherTypes::Ipv4));
+        assert!(set.contains(EtherTypes::Ipv6));
+        assert!(!set.contains(EtherTypes::Arp));
+        assert!(!set.contains(EtherTypes::Mpls));
+        assert!(!set.contains(EtherType::new(0x0000)));
+        assert!(!set.contains(EtherType::new(0xffff)));
+        assert_eq!(2, set.len());
+
+        let set = EtherTypeSet::from(&[EtherType::new(0x0000), EtherType::new(0xffff)][..]);
+        assert!(set.contains(EtherType::new(0x0000)));
+        assert!(set.contains(EtherType::new(0xffff)));
+        assert_eq!(
+            vec![EtherType::new(0x0000), EtherType::new(0xffff)],
+            set.iter().collect::<Vec<_>>()
+        );
+
+        assert!(EtherTypeSet::new().is_empty());
+    }
+
+    #[capsule::test]
+    fn translate_ether_types() {
+        let custom = EtherType::new(0x88b5);
+        let mut table = HashMap::new();
+        table.insert(custom, EtherTypes::Ipv4);
+
+        // untagged.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        ethernet.set_ether_type(custom);
+        assert!(ethernet.translate_ether_type(&table));
+        assert_eq!(EtherTypes::Ipv4, ethernet.ether_type());
+        assert!(ethernet.parse::<Ipv4>().is_ok());
+
+        // dot1q and qinq tagged, the tags are kept.
+        for bytes in [&VLAN_DOT1Q_PACKET[..], &VLAN_QINQ_PACKET[..]].iter() {
+            let packet = Mbuf::from_bytes(bytes).unwrap();
+            let mut ethernet = packet.parse::<Ethernet>().unwrap();
+            let tags = |ethernet: &Ethernet| {
+                ethernet
+                    .vlan_tags()
+                    .iter()
+                    .map(|tag| tag.tci().to_bits())
+                    .collect::<Vec<_>>()
+            };
+            let before = tags(&ethernet);
+            ethernet.set_ether_type(custom);
+
+            assert!(ethernet.translate_ether_type(&table));
+            assert_eq!(EtherTypes::Ipv4, ethernet.ether_type());
+            assert_eq!(before, tags(&ethernet));
+        }
+
+        // the types not in the table are left untouched.
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(!ethernet.translate_ether_type(&table));
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
+    }
+
+    #[capsule::test]
+    fn frame_to_vec() {
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert_eq!(&VLAN_DOT1Q_PACKET[..], &ethernet.to_vec().unwrap()[..]);
+    }
+
+    #[capsule::test]
+    fn patch_header_bytes() {
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert_eq!(18, ethernet.as_header_bytes().len());
+        assert_eq!(&VLAN_DOT1Q_PACKET[..18], ethernet.as_header_bytes());
+
+        let bytes = ethernet.as_header_bytes_mut();
+        assert_eq!(18, bytes.len());
+        bytes[..6].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+        bytes[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x02]);
+
+        assert_eq!(MacAddr::new(0x02, 0, 0, 0, 0, 0x01), ethernet.dst());
+        assert_eq!(MacAddr::new(0x02, 0, 0, 0, 0, 0x02), ethernet.src());
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
+
+        // the payload is untouched.
+        assert_eq!(&VLAN_DOT1Q_PACKET[18..], &ethernet.to_vec().unwrap()[18..]);
+    }
+
+    #[capsule::test]
+    fn apply_header_template() {
+        let src = MacAddr::new(0x02, 0, 0, 0, 0, 0x01);
+        let dst = MacAddr::new(0x02, 0, 0, 0, 0, 0x02);
+        let template = EthernetHeader::new(src, dst, EtherTypes::Ipv4);
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        let payload = ethernet.mbuf().data_len() - ethernet.header_len();
+
+        // untagged to untagged keeps the length.
+        ethernet.apply_template(&template, None).unwrap();
+        assert_eq!(src, ethernet.src());
+        assert_eq!(dst, ethernet.dst());
+        assert_eq!(EtherTypes::Ipv4, ethernet.ether_type());
+        assert_eq!(14, ethernet.header_len());
+
+        // grows into a QinQ frame.
+        let tags = [VlanTag::new(30).with_priority(5), VlanTag::new(101)];
+        ethernet.apply_template(&template, Some(&tags)).unwrap();
+        assert!(ethernet.is_qinq());
+        assert_eq!(EtherTypes::Ipv4, ethernet.ether_type());
+        assert_eq!((30 << 12) | 101, ethernet.vlan_ids());
+        assert_eq!(payload, ethernet.mbuf().data_len() - ethernet.header_len());
+
+        // shrinks back to a Dot1q frame.
+        ethernet
+            .apply_template(&template, Some(&tags[1..]))
+            .unwrap();
+        assert!(ethernet.is_dot1q());
+        assert_eq!(101, ethernet.vlan_ids());
+        assert_eq!(src, ethernet.src());
+
+        // the payload is intact.
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        assert_eq!(payload, ipv4.mbuf().data_len() - ipv4.offset());
+
+        let mut ethernet = ipv4.deparse();
+        let too_many = [VlanTag::new(1), VlanTag::new(2), VlanTag::new(3)];
+        assert!(ethernet.apply_template(&template, Some(&too_many)).is_err());
+    }
+
+    #[capsule::test]
+    fn hop_meta() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        assert_eq!(None, ethernet.hop_meta());
+
+        ethernet.set_hop_meta(0);
+        assert_eq!(Some(0), ethernet.hop_meta());
+        ethernet.set_hop_meta(255);
+        assert_eq!(Some(255), ethernet.hop_meta());
+
+        // not on the wire, and survives reparsing.
+        let packet = ethernet.reset();
+        let data = packet.read_data_slice::<u8>(0, packet.data_len()).unwrap();
+        assert_eq!(&IPV4_UDP_PACKET[..], unsafe { data.as_ref() });
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert_eq!(Some(255), ethernet.hop_meta());
+
+        // other metadata bits are left alone.
+        let mut packet = ethernet.reset();
+        packet.set_metadata(0xff00_0000);
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        ethernet.set_hop_meta(3);
+        assert_eq!(0xff00_0103, ethernet.mbuf().metadata());
+    }
+
+    #[test]
+    fn reserved_and_priority_vids() {
+        assert!(VlanTag::new(0).is_priority_tagged());
+        assert!(!VlanTag::new(0).is_reserved());
+        assert!(VlanTag::new(4095).is_reserved());
+        assert!(!VlanTag::new(4095).is_priority_tagged());
+        assert!(!VlanTag::new(100).with_priority(7).is_priority_tagged());
+        assert!(!VlanTag::new(100).is_reserved());
+    }
+
+    #[capsule::test]
+    fn validate_vlan_tags() {
+        let template = EthernetHeader::new(
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x01),
+            MacAddr::new(0x02, 0, 0, 0, 0, 0x02),
+            EtherTypes::Ipv4,
+        );
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        let mut check = |tags: &[VlanTag]| {
+            ethernet.apply_template(&template, Some(tags)).unwrap();
+            let ids = ethernet
+                .vlan_tags()
+                .iter()
+                .map(|tag| tag.identifier())
+                .collect::<Vec<_>>();
+            let expected = tags.iter().map(|tag| tag.identifier()).collect::<Vec<_>>();
+            assert_eq!(expected, ids);
+            ethernet.validate_vlan_tags()
+        };
+
+        assert!(check(&[]).is_ok());
+        assert!(check(&[VlanTag::new(100)]).is_ok());
+        assert!(check(&[VlanTag::new(4095)]).is_err());
+
+        // priority-tagged frames are fine, but not in the service tag.
+        assert!(check(&[VlanTag::new(0).with_priority(5)]).is_ok());
+        assert!(check(&[VlanTag::new(30), VlanTag::new(0)]).is_ok());
+        assert!(check(&[VlanTag::new(0), VlanTag::new(101)]).is_err());
+        assert!(check(&[VlanTag::new(30), VlanTag::new(4095)]).is_err());
+    }
+
+    #[capsule::test]
+    fn priority_tagged_frame() {
+        let mut bytes = VLAN_DOT1Q_PACKET;
+        // priority 5 and VLAN identifier 0.
+        bytes[14] = 0xa0;
+        bytes[15] = 0x00;
+
+        let packet = Mbuf::from_bytes(&bytes).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert!(ethernet.is_dot1q());
+        assert!(ethernet.is_priority_tagged());
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
+        assert_eq!(None, ethernet.switching_vid());
+        assert_eq!(5, ethernet.outer_tci().unwrap().pcp);
+        assert!(ethernet.parse::<Arp4>().is_ok());
+
+        // a regular VLAN is switched on.
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(!ethernet.is_priority_tagged());
+        assert_eq!(Some(123), ethernet.switching_vid());
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(!ethernet.is_priority_tagged());
+        assert_eq!(None, ethernet.switching_vid());
+    }
+
+    #[capsule::test]
+    fn can_push_vlan_tags() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(ethernet.can_push_vlan());
+        assert!(ethernet.can_push_qinq());
+
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(ethernet.can_push_vlan());
+        assert!(!ethernet.can_push_qinq());
+
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(!ethernet.can_push_vlan());
+        assert!(!ethernet.can_push_qinq());
     }
-}
 
-/// VLAN tag.
-#[derive(Clone, Copy, Debug, Default, SizeOf)]
-#[repr(C, packed)]
-struct VlanTag {
-    tpid: u16be,
-    tci: u16be,
-}
+    #[capsule::test]
+    fn cannot_push_vlan_without_room() {
+        let mut packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
 
-#[allow(clippy::trivially_copy_pass_by_ref)]
-impl VlanTag {
-    /// Returns the tag protocol identifier, either 802.1q (Dot1q) or 802.1ad (QinQ).
-    #[allow(dead_code)]
-    #[inline]
-    fn tag_id(&self) -> u16 {
-        self.tpid.into()
+        // leaves room for one tag but not two.
+        let len = packet.data_len();
+        packet.extend(len, packet.tailroom() - 5).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(ethernet.can_push_vlan());
+        assert!(!ethernet.can_push_qinq());
+
+        let mut packet = ethernet.reset();
+        let len = packet.data_len();
+        packet.extend(len, 1).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(!ethernet.can_push_vlan());
+        assert!(!ethernet.can_push_qinq());
     }
 
-    /// Returns the priority code point.
-    #[allow(dead_code)]
-    #[inline]
-    fn priority(&self) -> u8 {
-        let tci: u16 = self.tci.into();
-        (tci >> 13) as u8
+    #[capsule::test]
+    fn remove_dot1q_tag_by_vid() {
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert!(!ethernet.remove_vlan_with_vid(124).unwrap());
+        assert!(ethernet.is_dot1q());
+        assert_eq!(64, ethernet.len());
+
+        assert!(ethernet.remove_vlan_with_vid(123).unwrap());
+        assert!(!ethernet.is_dot1q());
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
+        assert_eq!(14, ethernet.header_len());
+        assert_eq!(60, ethernet.len());
+        assert_eq!("00:00:00:00:00:02", ethernet.src().to_string());
     }
 
-    /// Returns whether the frame is eligible to be dropped in the presence
-    /// of congestion.
-    #[allow(dead_code)]
-    #[inline]
-    fn drop_eligible(&self) -> bool {
-        self.tci & u16be::from(0x1000) > u16be::MIN
+    #[capsule::test]
+    fn remove_outer_qinq_tag_by_vid() {
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert!(ethernet.remove_vlan_with_vid(30).unwrap());
+        assert!(ethernet.is_dot1q());
+        assert_eq!(101, unsafe {
+            ethernet.header().chunk.dot1q.tag.identifier()
+        });
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
+        assert_eq!(64, ethernet.len());
     }
 
-    /// Returns the VLAN identifier.
-    #[allow(dead_code)]
-    #[inline]
-    fn identifier(&self) -> u16 {
-        (self.tci & u16be::from(0x0fff)).into()
+    #[capsule::test]
+    fn remove_inner_qinq_tag_by_vid() {
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert!(ethernet.remove_vlan_with_vid(101).unwrap());
+        assert!(ethernet.is_dot1q());
+        assert_eq!(30, unsafe {
+            ethernet.header().chunk.dot1q.tag.identifier()
+        });
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
+        assert_eq!(18, ethernet.header_len());
+        assert_eq!(64, ethernet.len());
+
+        // the payload is intact.
+        let arp = ethernet.parse::<Arp4>().unwrap();
+        assert_eq!(OperationCodes::Reply, arp.operation_code());
     }
-}
 
-/// Dot1q chunk for a VLAN header.
-#[derive(Clone, Copy, Debug, Default)]
-#[repr(C, packed)]
-struct Dot1q {
-    tag: VlanTag,
-    ether_type: u16be,
-}
+    #[capsule::test]
+    fn remove_vlan_from_untagged_frame() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
 
-/// QinQ chunk for a VLAN header.
-#[derive(Clone, Copy, Debug, Default)]
-#[repr(C, packed)]
-struct Qinq {
-    stag: VlanTag,
-    ctag: VlanTag,
-    ether_type: u16be,
-}
+        assert!(!ethernet.remove_vlan_with_vid(0).unwrap());
+        assert_eq!(EtherTypes::Ipv4, ethernet.ether_type());
+    }
 
-/// The Ethernet header chunk follows the source MAC.
-#[allow(missing_debug_implementations)]
-#[derive(Clone, Copy)]
-#[repr(C, packed)]
-union Chunk {
-    ether_type: u16be,
-    dot1q: Dot1q,
-    qinq: Qinq,
-}
+    #[capsule::test]
+    fn view_ethernet_packet() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let view = Ethernet::view(&packet).unwrap();
 
-impl Default for Chunk {
-    fn default() -> Chunk {
-        Chunk {
-            ether_type: u16be::default(),
-        }
+        assert_eq!("00:00:00:00:00:01", view.dst().to_string());
+        assert_eq!("00:00:00:00:00:02", view.src().to_string());
+        assert_eq!(EtherTypes::Ipv4, view.ether_type());
+        assert_eq!(14, view.header_len());
+
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let view = Ethernet::view(&packet).unwrap();
+
+        assert!(view.is_qinq());
+        assert!(!view.is_dot1q());
+        assert_eq!(EtherTypes::Arp, view.ether_type());
+        assert_eq!(22, view.header_len());
     }
-}
 
-/// Ethernet header.
-#[allow(missing_debug_implementations)]
-#[derive(Clone, Copy, Default)]
-#[repr(C, packed)]
-struct EthernetHeader {
-    dst: MacAddr,
-    src: MacAddr,
-    chunk: Chunk,
-}
+    #[capsule::test]
+    fn view_truncated_vlan_packet() {
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET[..16]).unwrap();
+        assert!(Ethernet::view(&packet).is_err());
+    }
 
-impl SizeOf for EthernetHeader {
-    /// Size of the Ethernet header.
-    ///
-    /// Because the Ethernet header is not fixed and modeled with a union, the
-    /// memory layout size is not the correct header size. For a brand new
-    /// Ethernet header, we will always report 14 bytes as the fixed portion,
-    /// which is the minimum size without any tags. `Ethernet::header_len()`
-    /// will report the correct instance size based on the presence or absence
-    /// of VLAN tags.
-    #[inline]
-    fn size_of() -> usize {
-        ETH_HEADER_SIZE
+    #[capsule::test]
+    fn parse_partial_complete_packet() {
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
+        let partial = Ethernet::parse_partial(packet).unwrap();
+
+        assert!(partial.is_complete());
+        assert!(partial.is_qinq());
+        assert_eq!(Some(EtherTypes::Arp), partial.ether_type());
+        assert_eq!(Some(22), partial.header_len());
+        assert_eq!(30, partial.outer_tci().unwrap().vid);
+        assert_eq!(101, partial.inner_tci().unwrap().vid);
+
+        let ethernet = partial.into_ethernet().unwrap();
+        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, VLAN_DOT1Q_PACKET, VLAN_QINQ_PACKET};
+    #[capsule::test]
+    fn parse_partial_truncated_qinq_packet() {
+        // cut off before the tag marker.
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET[..12]).unwrap();
+        let partial = Ethernet::parse_partial(packet).unwrap();
+        assert_eq!(12, partial.available());
+        assert!(!partial.is_qinq());
+        assert!(!partial.is_complete());
+        assert_eq!(None, partial.header_len());
+        assert_eq!(None, partial.ether_type());
+        assert_eq!(None, partial.outer_tci());
+
+        // cut off in the middle of the service tag.
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET[..15]).unwrap();
+        let partial = Ethernet::parse_partial(packet).unwrap();
+        assert!(partial.is_qinq());
+        assert_eq!(Some(22), partial.header_len());
+        assert_eq!(None, partial.outer_tci());
+        assert_eq!(None, partial.inner_tci());
+
+        // cut off in the middle of the customer tag.
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET[..18]).unwrap();
+        let partial = Ethernet::parse_partial(packet).unwrap();
+        assert_eq!(30, partial.outer_tci().unwrap().vid);
+        assert_eq!(None, partial.inner_tci());
+        assert_eq!(None, partial.ether_type());
+
+        // cut off before the EtherType.
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET[..20]).unwrap();
+        let partial = Ethernet::parse_partial(packet).unwrap();
+        assert_eq!(101, partial.inner_tci().unwrap().vid);
+        assert_eq!(None, partial.ether_type());
+        assert!(!partial.is_complete());
+        assert!(partial.into_ethernet().is_err());
+    }
+
+    #[capsule::test]
+    fn parse_partial_truncated_dot1q_packet() {
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET[..16]).unwrap();
+        let partial = Ethernet::parse_partial(packet).unwrap();
+
+        assert_eq!("00:00:00:00:00:01", partial.dst().to_string());
+        assert!(partial.is_dot1q());
+        assert_eq!(123, partial.outer_tci().unwrap().vid);
+        assert_eq!(None, partial.inner_tci());
+        assert_eq!(None, partial.ether_type());
+        assert_eq!(Some(18), partial.header_len());
+    }
+
+    #[capsule::test]
+    fn parse_partial_too_short_for_macs() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET[..11]).unwrap();
+        assert!(Ethernet::parse_partial(packet).is_err());
+    }
 
     #[test]
-    fn size_of_ethernet_header() {
-        assert_eq!(14, EthernetHeader::size_of());
+    fn parse_ethernet_slice() {
+        let info = parse_slice(&IPV4_UDP_PACKET).unwrap();
+        assert_eq!("00:00:00:00:00:01", info.dst.to_string());
+        assert_eq!("00:00:00:00:00:02", info.src.to_string());
+        assert_eq!(EtherTypes::Ipv4, info.ether_type);
+        assert_eq!(None, info.outer_tci);
+        assert_eq!(None, info.inner_tci);
+        assert_eq!(14, info.header_len);
+
+        let info = parse_slice(&VLAN_DOT1Q_PACKET).unwrap();
+        assert_eq!(EtherTypes::Arp, info.ether_type);
+        assert_eq!(123, info.outer_tci.unwrap().vid);
+        assert_eq!(None, info.inner_tci);
+        assert_eq!(18, info.header_len);
+
+        let info = parse_slice(&VLAN_QINQ_PACKET).unwrap();
+        assert_eq!(EtherTypes::Arp, info.ether_type);
+        assert_eq!(30, info.outer_tci.unwrap().vid);
+        assert_eq!(
+            Tci {
+                pcp: 1,
+                dei: false,
+                vid: 101
+            },
+            info.inner_tci.unwrap()
+        );
+        assert_eq!(22, info.header_len);
     }
 
     #[test]
-    fn ether_type_to_string() {
-        assert_eq!("ARP", EtherTypes::Arp.to_string());
-        assert_eq!("IPv4", EtherTypes::Ipv4.to_string());
-        assert_eq!("IPv6", EtherTypes::Ipv6.to_string());
-        assert_eq!("0x0000", EtherType::new(0).to_string());
+    fn parse_truncated_ethernet_slice() {
+        assert!(parse_slice(&[]).is_err());
+        assert!(parse_slice(&IPV4_UDP_PACKET[..13]).is_err());
+        assert!(parse_slice(&IPV4_UDP_PACKET[..14]).is_ok());
+        assert!(parse_slice(&VLAN_DOT1Q_PACKET[..17]).is_err());
+        assert!(parse_slice(&VLAN_QINQ_PACKET[..16]).is_err());
+        assert!(parse_slice(&VLAN_QINQ_PACKET[..22]).is_ok());
     }
 
     #[capsule::test]
-    fn parse_ethernet_packet() {
+    fn slice_and_mbuf_parse_the_same() {
+        for bytes in [
+            &IPV4_UDP_PACKET[..],
+            &VLAN_DOT1Q_PACKET[..],
+            &VLAN_QINQ_PACKET[..],
+        ]
+        .iter()
+        {
+            let packet = Mbuf::from_bytes(bytes).unwrap();
+            let view = Ethernet::view(&packet).unwrap().info();
+            let ethernet = packet.parse::<Ethernet>().unwrap();
+
+            assert_eq!(parse_slice(bytes).unwrap(), view);
+            assert_eq!(view, ethernet.info());
+        }
+    }
+
+    #[capsule::test]
+    fn anonymize_macs() {
+        let key = [42; 16];
         let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
-        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        let src = ethernet.src();
+        let dst = ethernet.dst();
 
-        assert_eq!("00:00:00:00:00:01", ethernet.dst().to_string());
-        assert_eq!("00:00:00:00:00:02", ethernet.src().to_string());
+        ethernet.anonymize_macs(&key);
+        assert_eq!(src.pseudonymize(&key), ethernet.src());
+        assert_eq!(dst.pseudonymize(&key), ethernet.dst());
+        assert_ne!(src, ethernet.src());
         assert_eq!(EtherTypes::Ipv4, ethernet.ether_type());
+
+        ethernet.set_dst(MacAddr::BROADCAST);
+        ethernet.anonymize_macs(&key);
+        assert_eq!(MacAddr::BROADCAST, ethernet.dst());
     }
 
     #[capsule::test]
-    fn parse_dot1q_packet() {
-        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
-        let ethernet = packet.parse::<Ethernet>().unwrap();
+    fn swap_addresses() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        ethernet.swap_addresses();
 
-        assert_eq!("00:00:00:00:00:01", ethernet.dst().to_string());
-        assert_eq!("00:00:00:00:00:02", ethernet.src().to_string());
-        assert!(ethernet.is_dot1q());
+        assert_eq!("00:00:00:00:00:02", ethernet.dst().to_string());
+        assert_eq!("00:00:00:00:00:01", ethernet.src().to_string());
+    }
+
+    fn with_fcs(bytes: &[u8]) -> Vec<u8> {
+        let mut frame = bytes.to_vec();
+        frame.extend_from_slice(&checksum::crc32(bytes).to_le_bytes());
+        frame
+    }
+
+    #[capsule::test]
+    fn verify_and_strip_valid_fcs() {
+        let bytes = with_fcs(&VLAN_DOT1Q_PACKET);
+        let packet = Mbuf::from_bytes(&bytes).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert!(ethernet.verify_fcs());
+        assert!(ethernet.has_trailing_fcs(false));
+        assert!(ethernet.has_trailing_fcs(true));
+
+        ethernet.strip_fcs().unwrap();
+        assert_eq!(VLAN_DOT1Q_PACKET.len(), ethernet.mbuf().data_len());
+        assert_eq!(VLAN_DOT1Q_PACKET.len(), ethernet.mbuf().pkt_len());
+        assert!(!ethernet.verify_fcs());
+        assert!(!ethernet.has_trailing_fcs(false));
         assert_eq!(EtherTypes::Arp, ethernet.ether_type());
-        assert_eq!(18, ethernet.header_len());
     }
 
     #[capsule::test]
-    fn parse_qinq_packet() {
-        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET).unwrap();
-        let ethernet = packet.parse::<Ethernet>().unwrap();
+    fn verify_invalid_fcs() {
+        let mut bytes = with_fcs(&IPV4_UDP_PACKET);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let packet = Mbuf::from_bytes(&bytes).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(!ethernet.verify_fcs());
+        assert!(!ethernet.has_trailing_fcs(false));
 
+        // the source is known to keep the FCS, it's stripped regardless.
+        assert!(ethernet.has_trailing_fcs(true));
+        ethernet.strip_fcs().unwrap();
+        assert_eq!(IPV4_UDP_PACKET.len(), ethernet.mbuf().data_len());
+
+        // a corrupted frame with a valid FCS appended before corruption.
+        let mut bytes = with_fcs(&IPV4_UDP_PACKET);
+        bytes[20] ^= 0x01;
+        let packet = Mbuf::from_bytes(&bytes).unwrap();
+        assert!(!packet.parse::<Ethernet>().unwrap().verify_fcs());
+    }
+
+    #[capsule::test]
+    fn strip_fcs_from_short_frame() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET[..16]).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(!ethernet.has_trailing_fcs(true));
+        assert!(ethernet.strip_fcs().is_err());
+        assert_eq!(16, ethernet.mbuf().data_len());
+    }
+
+    #[capsule::test]
+    fn resolve_and_set_dst() {
+        let next_hop: IpAddr = "10.0.0.1".parse().unwrap();
+        let mac = MacAddr::new(0x02, 0, 0, 0, 0, 0x0a);
+        let mut cache = HashMap::new();
+
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        // unresolved, the frame is left as is.
+        assert!(ethernet.resolve_and_set_dst(next_hop, &cache).is_err());
         assert_eq!("00:00:00:00:00:01", ethernet.dst().to_string());
+
+        let _ = cache.insert(next_hop, mac);
+        ethernet.resolve_and_set_dst(next_hop, &cache).unwrap();
+        assert_eq!(mac, ethernet.dst());
         assert_eq!("00:00:00:00:00:02", ethernet.src().to_string());
-        assert!(ethernet.is_qinq());
-        assert_eq!(EtherTypes::Arp, ethernet.ether_type());
-        assert_eq!(22, ethernet.header_len());
+        assert_eq!(123, ethernet.outer_tci().unwrap().vid);
     }
 
     #[capsule::test]
-    fn swap_addresses() {
-        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+    fn swap_addresses_and_remap_vid() {
+        let remap = |vid| match vid {
+            100 => 200,
+            200 => 100,
+            vid => vid,
+        };
+
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET).unwrap();
         let mut ethernet = packet.parse::<Ethernet>().unwrap();
-        ethernet.swap_addresses();
+        ethernet
+            .set_outer_tci(Tci {
+                pcp: 5,
+                dei: false,
+                vid: 100,
+            })
+            .unwrap();
 
+        ethernet.swap_addresses_and_remap_vid(remap).unwrap();
         assert_eq!("00:00:00:00:00:02", ethernet.dst().to_string());
         assert_eq!("00:00:00:00:00:01", ethernet.src().to_string());
+        let tci = ethernet.outer_tci().unwrap();
+        assert_eq!(200, tci.vid);
+        assert_eq!(5, tci.pcp);
+
+        ethernet.swap_addresses_and_remap_vid(remap).unwrap();
+        assert_eq!("00:00:00:00:00:01", ethernet.dst().to_string());
+        assert_eq!(100, ethernet.outer_tci().unwrap().vid);
+
+        // the reserved id is rejected and the frame is left as is.
+        assert!(ethernet.swap_addresses_and_remap_vid(|_| 0x0fff).is_err());
+        assert_eq!("00:00:00:00:00:01", ethernet.dst().to_string());
+        assert_eq!(100, ethernet.outer_tci().unwrap().vid);
+
+        // untagged frames only have their addresses swapped.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        ethernet.swap_addresses_and_remap_vid(remap).unwrap();
+        assert_eq!("00:00:00:00:00:02", ethernet.dst().to_string());
+        assert_eq!(None, ethernet.outer_tci());
+    }
+
+    #[capsule::test]
+    fn append_and_verify_payload_crc32() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        let payload_len = ethernet.payload_len();
+        assert!(!ethernet.verify_payload_crc32());
+
+        ethernet.append_payload_crc32().unwrap();
+        assert_eq!(payload_len + 4, ethernet.payload_len());
+        assert!(ethernet.verify_payload_crc32());
+
+        // the tag survives a reparse on the receiving end.
+        let mbuf = ethernet.reset();
+        let ethernet = mbuf.parse::<Ethernet>().unwrap();
+        assert!(ethernet.verify_payload_crc32());
+    }
+
+    #[capsule::test]
+    fn corrupted_payload_fails_crc32() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+        ethernet.append_payload_crc32().unwrap();
+
+        // flips a single bit in the middle of the payload.
+        let offset = ethernet.payload_offset() + 10;
+        let mut mbuf = ethernet.reset();
+        let byte = unsafe { *mbuf.read_data::<u8>(offset).unwrap().as_ptr() };
+        mbuf.write_data(offset, &(byte ^ 0x01)).unwrap();
+
+        let ethernet = mbuf.parse::<Ethernet>().unwrap();
+        assert!(!ethernet.verify_payload_crc32());
+    }
+
+    #[capsule::test]
+    fn verify_crc32_without_payload() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET[..16]).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(!ethernet.verify_payload_crc32());
     }
 
     #[capsule::test]
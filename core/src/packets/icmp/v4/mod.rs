@@ -18,11 +18,13 @@
 
 //! Internet Control Message Protocol for IPv4.
 
+mod destination_unreachable;
 mod echo_reply;
 mod echo_request;
 mod redirect;
 mod time_exceeded;
 
+pub use self::destination_unreachable::*;
 pub use self::echo_reply::*;
 pub use self::echo_request::*;
 pub use self::redirect::*;
@@ -290,6 +292,11 @@ pub mod Icmpv4Types {
     ///
     /// [Redirect]: crate::packets::icmp::v4::Redirect
     pub const Redirect: Icmpv4Type = Icmpv4Type(5);
+
+    /// Message type for [Destination Unreachable].
+    ///
+    /// [Destination Unreachable]: crate::packets::icmp::v4::DestinationUnreachable
+    pub const DestinationUnreachable: Icmpv4Type = Icmpv4Type(3);
 }
 
 impl fmt::Display for Icmpv4Type {
@@ -302,6 +309,7 @@ impl fmt::Display for Icmpv4Type {
                 Icmpv4Types::EchoReply => "Echo Reply".to_string(),
                 Icmpv4Types::TimeExceeded => "Time Exceeded".to_string(),
                 Icmpv4Types::Redirect => "Redirect".to_string(),
+                Icmpv4Types::DestinationUnreachable => "Destination Unreachable".to_string(),
                 _ => format!("{}", self.0),
             }
         )
@@ -135,6 +135,13 @@ pub trait IpPacket: Packet {
 
     /// Truncates the IP packet to MTU. The data exceeds MTU is lost.
     fn truncate(&mut self, mtu: usize) -> Result<()>;
+
+    /// Returns the buffer offset where the IP packet ends, as given by the
+    /// length field of the IP header.
+    ///
+    /// Unlike the end of the buffer, this leaves out the padding of frames
+    /// shorter than the Ethernet minimum size.
+    fn datagram_end(&self) -> usize;
 }
 
 /// The common attributes (5-tuple) used to identify an IP based network
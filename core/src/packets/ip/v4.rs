@@ -477,14 +477,14 @@ impl Packet for Ipv4 {
     /// the packet.
     ///
     /// * [`total_length`] is set to the total length of the header and the
-    /// payload.
+    /// payload, across all the segments of a coalesced packet.
     /// * [`checksum`] is computed based on the IPv4 header.
     ///
     /// [`total_length`]: Ipv4::total_length
     /// [`checksum`]: Ipv4::checksum
     #[inline]
     fn reconcile(&mut self) {
-        self.set_total_length(self.len() as u16);
+        self.set_total_length(self.full_len() as u16);
         self.compute_checksum();
     }
 }
@@ -570,6 +570,11 @@ impl IpPacket for Ipv4 {
         let to_len = mtu + self.offset();
         self.mbuf_mut().truncate(to_len)
     }
+
+    #[inline]
+    fn datagram_end(&self) -> usize {
+        self.offset() + self.total_length() as usize
+    }
 }
 
 /// IPv4 header.
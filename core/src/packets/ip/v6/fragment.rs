@@ -302,6 +302,11 @@ impl<E: Ipv6Packet> IpPacket for Fragment<E> {
     fn truncate(&mut self, mtu: usize) -> Result<()> {
         self.envelope_mut().truncate(mtu)
     }
+
+    #[inline]
+    fn datagram_end(&self) -> usize {
+        self.envelope().datagram_end()
+    }
 }
 
 impl<E: Ipv6Packet> Ipv6Packet for Fragment<E> {
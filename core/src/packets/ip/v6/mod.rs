@@ -324,12 +324,13 @@ impl Packet for Ipv6 {
     /// the packet.
     ///
     /// * [`payload_length`] is set to the length of the payload which includes
-    /// any extension headers present.
+    /// any extension headers present, across all the segments of a
+    /// coalesced packet.
     ///
     /// [`payload_length`]: Ipv6::payload_length
     #[inline]
     fn reconcile(&mut self) {
-        let len = self.payload_len() as u16;
+        let len = (self.full_len() - self.header_len()) as u16;
         self.set_payload_length(len);
     }
 }
@@ -415,6 +416,11 @@ impl IpPacket for Ipv6 {
         let to_len = mtu + self.offset();
         self.mbuf_mut().truncate(to_len)
     }
+
+    #[inline]
+    fn datagram_end(&self) -> usize {
+        self.payload_offset() + self.payload_length() as usize
+    }
 }
 
 impl Ipv6Packet for Ipv6 {
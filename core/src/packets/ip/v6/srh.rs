@@ -476,6 +476,11 @@ impl<E: Ipv6Packet> IpPacket for SegmentRouting<E> {
     fn truncate(&mut self, mtu: usize) -> Result<()> {
         self.envelope_mut().truncate(mtu)
     }
+
+    #[inline]
+    fn datagram_end(&self) -> usize {
+        self.envelope().datagram_end()
+    }
 }
 
 impl<E: Ipv6Packet> Ipv6Packet for SegmentRouting<E> {
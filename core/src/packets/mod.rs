@@ -18,21 +18,33 @@
 
 //! Packet types for reading and writing various network protocols.
 
+mod action;
 pub mod arp;
 pub mod checksum;
+mod decap;
 mod ethernet;
 pub mod icmp;
 pub mod ip;
+pub mod pbb;
+mod registry;
 mod tcp;
+#[cfg(feature = "parse-timing")]
+mod timing;
 pub mod types;
 mod udp;
 
+pub use self::action::*;
 pub use self::ethernet::*;
+pub use self::registry::*;
 pub use self::tcp::*;
+#[cfg(feature = "parse-timing")]
+pub use self::timing::*;
 pub use self::udp::*;
 
 use crate::Mbuf;
 use anyhow::{Context, Result};
+use std::any::Any;
+use std::cell::Cell;
 use std::fmt;
 use std::marker::PhantomData;
 use std::ops::Deref;
@@ -50,6 +62,57 @@ use std::ops::Deref;
 #[derive(Clone, Debug)]
 pub struct Internal(());
 
+/// Options of the packet parsers.
+///
+/// The options apply to the packets parsed on the current thread. The
+/// runtime sets the options of every core from the config at startup, and
+/// other threads use the defaults until they set their own.
+///
+/// # Example
+///
+/// ```
+/// ParseOptions {
+///     max_frame_len: Some(1518),
+/// }
+/// .set();
+///
+/// // fails on a 9000 byte jumbo frame.
+/// let ethernet = mbuf.parse::<Ethernet>()?;
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ParseOptions {
+    /// The maximum length of a frame, across all the segments of the mbuf.
+    /// [`Ethernet`] fails to parse longer frames before reading the
+    /// header, which keeps oversized frames from a misbehaving source out
+    /// of the pipelines. Defaults to `None`, unlimited.
+    pub max_frame_len: Option<usize>,
+}
+
+impl ParseOptions {
+    /// Returns the options of the current thread.
+    #[inline]
+    pub fn current() -> Self {
+        PARSE_OPTIONS.with(|tls| tls.get())
+    }
+
+    /// Sets the options of the current thread.
+    pub fn set(self) {
+        PARSE_OPTIONS.with(|tls| tls.set(self));
+    }
+}
+
+thread_local! {
+    static PARSE_OPTIONS: Cell<ParseOptions> = Cell::new(ParseOptions::default());
+}
+
+/// Returns the name of the type without the module path and the type
+/// parameters, the default [`Packet::layer_name`].
+pub(crate) fn type_layer_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
 /// A trait all network protocols must implement.
 ///
 /// This is the main trait for interacting with the message buffer as
@@ -127,6 +190,54 @@ pub trait Packet {
         self.len() - self.header_len()
     }
 
+    /// Returns the length of the packet with the payload, across all the
+    /// segments if the packet is coalesced from TCP segments.
+    ///
+    /// The packet types only see the first segment of the mbuf, which is
+    /// what [`len`] reports. For a coalesced packet, this is the length
+    /// the IP and TCP headers are reconciled against instead.
+    ///
+    /// [`len`]: Packet::len
+    #[inline]
+    fn full_len(&self) -> usize {
+        let mbuf = self.mbuf();
+        if mbuf.coalesced_segment_size().is_some() {
+            mbuf.pkt_len() - self.offset()
+        } else {
+            self.len()
+        }
+    }
+
+    /// Returns the name of the packet type, for example `Ipv4` or `Udp`,
+    /// without the module path and the envelope type parameters.
+    fn layer_name(&self) -> &'static str {
+        type_layer_name::<Self>()
+    }
+
+    /// Returns the name, offset and header length of every layer in the
+    /// envelope chain, starting with the current packet and ending with the
+    /// mbuf.
+    ///
+    /// Useful for checking where a parser thinks each header starts and
+    /// ends. The packet is not consumed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// for layer in udp.layer_summary() {
+    ///     println!("{:?}", layer);
+    /// }
+    /// ```
+    fn layer_summary(&self) -> Vec<LayerInfo> {
+        let mut layers = vec![LayerInfo {
+            name: self.layer_name(),
+            offset: self.offset(),
+            header_len: self.header_len(),
+        }];
+        layers.extend(self.envelope().layer_summary());
+        layers
+    }
+
     /// Returns a copy of the packet.
     ///
     /// # Remarks
@@ -300,6 +411,89 @@ pub trait Packet {
     }
 }
 
+/// The position of one layer in a packet's envelope chain.
+///
+/// See [`Packet::layer_summary`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LayerInfo {
+    /// The name of the packet type.
+    pub name: &'static str,
+    /// The buffer offset where the layer begins.
+    pub offset: usize,
+    /// The length of the layer's header.
+    pub header_len: usize,
+}
+
+/// A packet of any type.
+///
+/// [`Packet`] can't be made into a trait object, so packets of different
+/// types are stored together as `Box<dyn AnyPacket>` instead. The concrete
+/// packet is recovered with a downcast, without parsing the buffer again.
+/// It's implemented for all packet types.
+///
+/// # Example
+///
+/// ```
+/// let frames: Vec<Box<dyn AnyPacket>> = vec![Box::new(ethernet), Box::new(udp)];
+///
+/// for frame in frames {
+///     match frame.downcast::<Ethernet>() {
+///         Ok(ethernet) => forward(*ethernet),
+///         Err(other) => drop_packet(other),
+///     }
+/// }
+/// ```
+pub trait AnyPacket: Any {
+    /// Returns the packet as `&dyn Any`.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Returns the packet as `&mut dyn Any`.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Converts the boxed packet into `Box<dyn Any>`.
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Packet + 'static> AnyPacket for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl dyn AnyPacket {
+    /// Returns whether the packet is of type `T`.
+    pub fn is<T: Packet + 'static>(&self) -> bool {
+        self.as_any().is::<T>()
+    }
+
+    /// Returns a reference to the packet if it's of type `T`.
+    pub fn downcast_ref<T: Packet + 'static>(&self) -> Option<&T> {
+        self.as_any().downcast_ref::<T>()
+    }
+
+    /// Returns a mutable reference to the packet if it's of type `T`.
+    pub fn downcast_mut<T: Packet + 'static>(&mut self) -> Option<&mut T> {
+        self.as_any_mut().downcast_mut::<T>()
+    }
+
+    /// Recovers the packet if it's of type `T`, otherwise gives it back.
+    pub fn downcast<T: Packet + 'static>(self: Box<Self>) -> Result<Box<T>, Box<dyn AnyPacket>> {
+        if self.is::<T>() {
+            Ok(self.into_any().downcast::<T>().unwrap())
+        } else {
+            Err(self)
+        }
+    }
+}
+
 /// Immutable smart pointer to a struct.
 ///
 /// A smart pointer that prevents the struct from being modified. The main
@@ -381,6 +575,65 @@ mod tests {
         assert_eq!(v4_4.ttl(), 25);
     }
 
+    #[capsule::test]
+    fn summarize_layers() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let v4 = ethernet.parse::<Ipv4>().unwrap();
+        let udp = v4.parse::<Udp4>().unwrap();
+
+        let layers = udp
+            .layer_summary()
+            .iter()
+            .map(|l| (l.name, l.offset, l.header_len))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                ("Udp", 34, 8),
+                ("Ipv4", 14, 20),
+                ("Ethernet", 0, 14),
+                ("Mbuf", 0, 0)
+            ],
+            layers
+        );
+
+        // the packet is still usable.
+        assert_eq!(39376, udp.src_port());
+    }
+
+    #[capsule::test]
+    fn downcast_any_packet() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let offset = ethernet.payload_offset();
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let udp = packet
+            .parse::<Ethernet>()
+            .unwrap()
+            .parse::<Ipv4>()
+            .unwrap()
+            .parse::<Udp4>()
+            .unwrap();
+
+        let mut frames: Vec<Box<dyn AnyPacket>> = vec![Box::new(ethernet), Box::new(udp)];
+
+        assert!(frames[0].is::<Ethernet>());
+        assert!(frames[0].downcast_ref::<Ipv4>().is_none());
+        frames[1].downcast_mut::<Udp4>().unwrap().set_src_port(53);
+
+        let mut frames = frames.into_iter();
+        let ethernet = frames.next().unwrap().downcast::<Ethernet>().ok().unwrap();
+        assert_eq!(offset, ethernet.payload_offset());
+        assert_eq!(MacAddr::new(0, 0, 0, 0, 0, 2), ethernet.src());
+
+        // the wrong type gives the packet back.
+        let other = frames.next().unwrap().downcast::<Ethernet>().unwrap_err();
+        let udp = other.downcast::<Udp4>().ok().unwrap();
+        assert_eq!(53, udp.src_port());
+    }
+
     #[capsule::test]
     fn remove_header_and_payload() {
         let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
@@ -0,0 +1,459 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Provider Backbone Bridging, or MAC-in-MAC.
+
+use crate::net::MacAddr;
+use crate::packets::types::u32be;
+use crate::packets::{EtherTypes, Ethernet, Internal, Packet};
+use crate::{ensure, SizeOf};
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::ptr::NonNull;
+
+pub(crate) const ITAG_SIZE: usize = 4;
+const ETH_HEADER_SIZE: usize = 14;
+
+const PCP: u32 = 0b1110_0000_0000_0000_0000_0000_0000_0000;
+const DEI: u32 = 0b0001_0000_0000_0000_0000_0000_0000_0000;
+const NCA: u32 = 0b0000_1000_0000_0000_0000_0000_0000_0000;
+const RES: u32 = 0b0000_0111_0000_0000_0000_0000_0000_0000;
+const ISID: u32 = 0b0000_0000_1111_1111_1111_1111_1111_1111;
+
+/// Provider Backbone Bridging frame based on [IEEE 802.1ah].
+///
+/// The backbone Ethernet frame, with EtherType `0x88e7`, carries an I-TAG
+/// followed by the encapsulated customer Ethernet frame.
+///
+/// ```
+///  0                   1                   2                   3
+///  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// | PCP |D|N| RES |                    I-SID                      |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                  Customer Destination MAC                     |
+/// +                               +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                               |                               |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+                               +
+/// |                     Customer Source MAC                       |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |         Customer Type         |           Payload             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// ```
+///
+/// - *PCP*:             3-bit priority code point of the backbone service
+///                      instance.
+///
+/// - *DEI*:             1-bit drop eligible indicator.
+///
+/// - *NCA*:             1-bit no customer address indicator, also known as
+///                      UCA, or use customer addresses.
+///
+/// - *RES*:             3 reserved bits.
+///
+/// - *I-SID*:           24-bit backbone service instance identifier.
+///
+/// - *Customer MACs*:   48-bit destination and source MAC addresses of the
+///                      encapsulated customer frame.
+///
+/// The I-TAG is the header of the packet. The payload is the encapsulated
+/// customer frame, starting with the customer MAC addresses, and can be
+/// parsed as an [`Ethernet`] frame.
+///
+/// # Example
+///
+/// ```
+/// let backbone = packet.parse::<Ethernet>()?;
+/// let pbb = backbone.parse::<Pbb>()?;
+/// let customer = pbb.parse_inner()?;
+/// ```
+///
+/// [IEEE 802.1ah]: https://en.wikipedia.org/wiki/IEEE_802.1ah-2008
+/// [`Ethernet`]: Ethernet
+pub struct Pbb {
+    envelope: Ethernet,
+    header: NonNull<PbbHeader>,
+    offset: usize,
+}
+
+impl Pbb {
+    #[inline]
+    fn header(&self) -> &PbbHeader {
+        unsafe { self.header.as_ref() }
+    }
+
+    #[inline]
+    fn header_mut(&mut self) -> &mut PbbHeader {
+        unsafe { self.header.as_mut() }
+    }
+
+    #[inline]
+    fn tci(&self) -> u32 {
+        self.header().tci.into()
+    }
+
+    #[inline]
+    fn set_tci(&mut self, mask: u32, value: u32) {
+        let tci = (self.tci() & !mask) | (value & mask);
+        self.header_mut().tci = tci.into();
+    }
+
+    /// Returns the priority code point.
+    #[inline]
+    pub fn priority(&self) -> u8 {
+        ((self.tci() & PCP) >> 29) as u8
+    }
+
+    /// Sets the priority code point.
+    #[inline]
+    pub fn set_priority(&mut self, priority: u8) {
+        self.set_tci(PCP, (priority as u32) << 29);
+    }
+
+    /// Returns a flag indicating whether the frame is drop eligible.
+    #[inline]
+    pub fn drop_eligible(&self) -> bool {
+        self.tci() & DEI != 0
+    }
+
+    /// Sets the drop eligible indicator.
+    #[inline]
+    pub fn set_drop_eligible(&mut self) {
+        self.set_tci(DEI, DEI);
+    }
+
+    /// Unsets the drop eligible indicator.
+    #[inline]
+    pub fn unset_drop_eligible(&mut self) {
+        self.set_tci(DEI, 0);
+    }
+
+    /// Returns a flag indicating whether the no customer address indicator
+    /// is set.
+    #[inline]
+    pub fn no_customer_address(&self) -> bool {
+        self.tci() & NCA != 0
+    }
+
+    /// Sets the no customer address indicator.
+    #[inline]
+    pub fn set_no_customer_address(&mut self) {
+        self.set_tci(NCA, NCA);
+    }
+
+    /// Unsets the no customer address indicator.
+    #[inline]
+    pub fn unset_no_customer_address(&mut self) {
+        self.set_tci(NCA, 0);
+    }
+
+    /// Returns the reserved bits.
+    #[inline]
+    pub fn reserved(&self) -> u8 {
+        ((self.tci() & RES) >> 24) as u8
+    }
+
+    /// Returns the backbone service instance identifier.
+    #[inline]
+    pub fn i_sid(&self) -> u32 {
+        self.tci() & ISID
+    }
+
+    /// Sets the backbone service instance identifier.
+    ///
+    /// Only the lower 24 bits of `i_sid` are used.
+    #[inline]
+    pub fn set_i_sid(&mut self, i_sid: u32) {
+        self.set_tci(ISID, i_sid);
+    }
+
+    /// Returns the destination MAC address of the customer frame.
+    #[inline]
+    pub fn customer_dst(&self) -> MacAddr {
+        self.header().customer_dst
+    }
+
+    /// Sets the destination MAC address of the customer frame.
+    #[inline]
+    pub fn set_customer_dst(&mut self, dst: MacAddr) {
+        self.header_mut().customer_dst = dst
+    }
+
+    /// Returns the source MAC address of the customer frame.
+    #[inline]
+    pub fn customer_src(&self) -> MacAddr {
+        self.header().customer_src
+    }
+
+    /// Sets the source MAC address of the customer frame.
+    #[inline]
+    pub fn set_customer_src(&mut self, src: MacAddr) {
+        self.header_mut().customer_src = src
+    }
+
+    /// Parses the encapsulated customer frame.
+    ///
+    /// The customer frame is an [`Ethernet`] over the same mbuf, starting
+    /// after the I-TAG. The backbone frame is left in the buffer as is,
+    /// and deparsing the customer frame gives back the `Mbuf`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the customer Ethernet header is larger than the
+    /// data payload.
+    #[inline]
+    pub fn parse_inner(self) -> Result<Ethernet> {
+        let offset = self.payload_offset();
+        Ethernet::parse_at(self.reset(), offset)
+    }
+}
+
+impl fmt::Debug for Pbb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("pbb")
+            .field("priority", &self.priority())
+            .field("drop_eligible", &self.drop_eligible())
+            .field("no_customer_address", &self.no_customer_address())
+            .field("i_sid", &self.i_sid())
+            .field("customer_dst", &format!("{}", self.customer_dst()))
+            .field("customer_src", &format!("{}", self.customer_src()))
+            .field("$offset", &self.offset())
+            .field("$len", &self.len())
+            .field("$header_len", &self.header_len())
+            .finish()
+    }
+}
+
+impl Packet for Pbb {
+    /// The preceding type for PBB must be `Ethernet`.
+    type Envelope = Ethernet;
+
+    #[inline]
+    fn envelope(&self) -> &Self::Envelope {
+        &self.envelope
+    }
+
+    #[inline]
+    fn envelope_mut(&mut self) -> &mut Self::Envelope {
+        &mut self.envelope
+    }
+
+    #[inline]
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the length of the I-TAG.
+    ///
+    /// The customer MAC addresses are part of the payload.
+    #[inline]
+    fn header_len(&self) -> usize {
+        ITAG_SIZE
+    }
+
+    #[inline]
+    unsafe fn clone(&self, internal: Internal) -> Self {
+        Pbb {
+            envelope: self.envelope.clone(internal),
+            header: self.header,
+            offset: self.offset,
+        }
+    }
+
+    /// Parses the Ethernet payload as a PBB frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`ether_type`] is not set to [`EtherTypes::Pbb`].
+    /// Returns an error if the payload does not have enough data for the
+    /// I-TAG and the customer MAC addresses.
+    ///
+    /// [`ether_type`]: Ethernet::ether_type
+    /// [`EtherTypes::Pbb`]: EtherTypes::Pbb
+    #[inline]
+    fn try_parse(envelope: Self::Envelope, _internal: Internal) -> Result<Self> {
+        ensure!(
+            envelope.ether_type() == EtherTypes::Pbb,
+            anyhow!("not a PBB frame.")
+        );
+
+        let mbuf = envelope.mbuf();
+        let offset = envelope.payload_offset();
+        let header = mbuf.read_data(offset)?;
+
+        Ok(Pbb {
+            envelope,
+            header,
+            offset,
+        })
+    }
+
+    /// Prepends a PBB frame to the beginning of the Ethernet's payload.
+    ///
+    /// [`ether_type`] is set to [`EtherTypes::Pbb`]. The I-TAG is followed
+    /// by a zeroed customer Ethernet header, which can be parsed with
+    /// [`parse_inner`] to fill in the rest of the customer frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the buffer does not have enough free space.
+    ///
+    /// [`ether_type`]: Ethernet::ether_type
+    /// [`EtherTypes::Pbb`]: EtherTypes::Pbb
+    /// [`parse_inner`]: Pbb::parse_inner
+    #[inline]
+    fn try_push(mut envelope: Self::Envelope, _internal: Internal) -> Result<Self> {
+        let offset = envelope.payload_offset();
+        let mbuf = envelope.mbuf_mut();
+
+        mbuf.extend(offset, ITAG_SIZE + ETH_HEADER_SIZE)?;
+        let _ = mbuf.write_data_slice(offset, &[0u8; ITAG_SIZE + ETH_HEADER_SIZE])?;
+        let header = mbuf.read_data(offset)?;
+
+        envelope.set_ether_type(EtherTypes::Pbb);
+
+        Ok(Pbb {
+            envelope,
+            header,
+            offset,
+        })
+    }
+
+    #[inline]
+    fn deparse(self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+/// PBB I-TAG followed by the customer MAC addresses.
+///
+/// Only the I-TAG is the header of the packet, the customer MAC addresses
+/// are the beginning of the payload.
+#[derive(Clone, Copy, Debug, Default, SizeOf)]
+#[repr(C, packed)]
+struct PbbHeader {
+    tci: u32be,
+    customer_dst: MacAddr,
+    customer_src: MacAddr,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::EtherType;
+    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, PBB_PACKET};
+    use crate::Mbuf;
+
+    #[test]
+    fn size_of_pbb_header() {
+        assert_eq!(16, PbbHeader::size_of());
+    }
+
+    #[capsule::test]
+    fn parse_pbb_packet() {
+        let packet = Mbuf::from_bytes(&PBB_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let pbb = ethernet.parse::<Pbb>().unwrap();
+
+        assert_eq!(3, pbb.priority());
+        assert_eq!(false, pbb.drop_eligible());
+        assert_eq!(false, pbb.no_customer_address());
+        assert_eq!(0, pbb.reserved());
+        assert_eq!(100_000, pbb.i_sid());
+        assert_eq!("00:00:00:00:00:03", pbb.customer_dst().to_string());
+        assert_eq!("00:00:00:00:00:04", pbb.customer_src().to_string());
+        assert_eq!(18, pbb.payload_offset());
+        assert_eq!(42, pbb.payload_len());
+    }
+
+    #[capsule::test]
+    fn parse_customer_frame() {
+        let packet = Mbuf::from_bytes(&PBB_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let pbb = ethernet.parse::<Pbb>().unwrap();
+        let customer = pbb.parse_inner().unwrap();
+
+        assert_eq!("00:00:00:00:00:03", customer.dst().to_string());
+        assert_eq!("00:00:00:00:00:04", customer.src().to_string());
+        assert_eq!(EtherTypes::Arp, customer.ether_type());
+        assert_eq!(32, customer.payload_offset());
+    }
+
+    #[capsule::test]
+    fn parse_non_pbb_packet() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert!(ethernet.parse::<Pbb>().is_err());
+    }
+
+    #[capsule::test]
+    fn parse_truncated_pbb_packet() {
+        let packet = Mbuf::from_bytes(&PBB_PACKET[..24]).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+
+        assert!(ethernet.parse::<Pbb>().is_err());
+    }
+
+    #[capsule::test]
+    fn set_i_tag_fields() {
+        let packet = Mbuf::from_bytes(&PBB_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let mut pbb = ethernet.parse::<Pbb>().unwrap();
+
+        pbb.set_i_sid(0xff12_3456);
+        assert_eq!(0x12_3456, pbb.i_sid());
+        assert_eq!(3, pbb.priority());
+
+        pbb.set_priority(5);
+        pbb.set_drop_eligible();
+        pbb.set_no_customer_address();
+        assert_eq!(5, pbb.priority());
+        assert!(pbb.drop_eligible());
+        assert!(pbb.no_customer_address());
+        assert_eq!(0x12_3456, pbb.i_sid());
+
+        pbb.unset_drop_eligible();
+        pbb.unset_no_customer_address();
+        assert!(!pbb.drop_eligible());
+        assert!(!pbb.no_customer_address());
+        assert_eq!(0, pbb.reserved());
+    }
+
+    #[capsule::test]
+    fn push_pbb_packet() {
+        let packet = Mbuf::new().unwrap();
+        let ethernet = packet.push::<Ethernet>().unwrap();
+        let mut pbb = ethernet.push::<Pbb>().unwrap();
+
+        assert_eq!(ITAG_SIZE, pbb.header_len());
+        assert_eq!(EtherTypes::Pbb, pbb.envelope().ether_type());
+
+        pbb.set_i_sid(42);
+        let mut customer = pbb.parse_inner().unwrap();
+        customer.set_dst(MacAddr::new(0, 0, 0, 0, 0, 3));
+        customer.set_ether_type(EtherType::new(0x0800));
+
+        let packet = customer.deparse();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let pbb = ethernet.parse::<Pbb>().unwrap();
+        assert_eq!(42, pbb.i_sid());
+        assert_eq!("00:00:00:00:00:03", pbb.customer_dst().to_string());
+        assert_eq!(32, pbb.mbuf().data_len());
+    }
+}
@@ -0,0 +1,197 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Parsers of the Ethernet payloads the crate doesn't implement.
+
+use super::arp::Arp4;
+use super::ip::v4::Ipv4;
+use super::ip::v6::Ipv6;
+use super::pbb::Pbb;
+use super::{AnyPacket, EtherType, EtherTypes, Ethernet, Packet};
+use crate::ensure;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use thiserror::Error;
+
+/// A parser of the payload of an Ethernet frame, to a packet of a type
+/// implemented outside the crate.
+pub type ParserFn = fn(Ethernet) -> Result<Box<dyn AnyPacket>>;
+
+/// Parser registry errors.
+#[derive(Debug, Error)]
+pub(crate) enum RegistryError {
+    /// The EtherType is parsed by the crate.
+    #[error("EtherType {0} has a built-in parser.")]
+    BuiltIn(EtherType),
+
+    /// Another parser is registered for the EtherType.
+    #[error("EtherType {0} already has a registered parser.")]
+    AlreadyRegistered(EtherType),
+
+    /// No parser is registered for the EtherType.
+    #[error("No parser for EtherType {0}.")]
+    NoParser(EtherType),
+}
+
+static PARSERS: Lazy<RwLock<HashMap<EtherType, ParserFn>>> = Lazy::new(Default::default);
+
+/// Returns whether the crate parses the EtherType.
+fn is_built_in(ether_type: EtherType) -> bool {
+    matches!(
+        ether_type,
+        EtherTypes::Ipv4 | EtherTypes::Ipv6 | EtherTypes::Arp | EtherTypes::Pbb
+    )
+}
+
+/// Registers the parser of the payload of Ethernet frames of the EtherType,
+/// used by [`Ethernet::parse_next_dyn`].
+///
+/// The registry is shared by all the cores. Register the parsers before
+/// the runtime starts executing the pipelines, the registry is meant to be
+/// set up once and only read afterward.
+///
+/// # Errors
+///
+/// Returns an error if the crate already parses the EtherType, or if
+/// another parser is registered for it.
+///
+/// # Example
+///
+/// ```
+/// const MY_PROTO: EtherType = EtherType(0x88b5);
+///
+/// fn parse_my_proto(ethernet: Ethernet) -> Result<Box<dyn AnyPacket>> {
+///     Ok(Box::new(ethernet.parse::<MyProto>()?))
+/// }
+///
+/// packets::register_parser(MY_PROTO, parse_my_proto)?;
+///
+/// let packet = ethernet.parse_next_dyn()?;
+/// if let Some(my_proto) = packet.downcast_ref::<MyProto>() {
+///     ...
+/// }
+/// ```
+pub fn register_parser(ether_type: EtherType, parser: ParserFn) -> Result<()> {
+    ensure!(!is_built_in(ether_type), RegistryError::BuiltIn(ether_type));
+
+    let mut parsers = PARSERS.write().unwrap();
+    ensure!(
+        !parsers.contains_key(&ether_type),
+        RegistryError::AlreadyRegistered(ether_type)
+    );
+    parsers.insert(ether_type, parser);
+    Ok(())
+}
+
+/// Returns the registered parser of the EtherType.
+fn registered_parser(ether_type: EtherType) -> Option<ParserFn> {
+    PARSERS.read().unwrap().get(&ether_type).copied()
+}
+
+impl Ethernet {
+    /// Parses the payload by its EtherType, to the packet type the crate
+    /// or a parser registered with [`register_parser`] implements.
+    ///
+    /// IPv4, IPv6, ARP and PBB payloads are parsed as [`Ipv4`], [`Ipv6`],
+    /// [`Arp4`] and [`Pbb`]. The packet is recovered with
+    /// [`downcast`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no parser is registered for the EtherType, or if
+    /// the parser fails.
+    ///
+    /// [`downcast`]: AnyPacket::downcast
+    pub fn parse_next_dyn(self) -> Result<Box<dyn AnyPacket>> {
+        let ether_type = self.ether_type();
+        match ether_type {
+            EtherTypes::Ipv4 => Ok(Box::new(self.parse::<Ipv4>()?)),
+            EtherTypes::Ipv6 => Ok(Box::new(self.parse::<Ipv6>()?)),
+            EtherTypes::Arp => Ok(Box::new(self.parse::<Arp4>()?)),
+            EtherTypes::Pbb => Ok(Box::new(self.parse::<Pbb>()?)),
+            _ => match registered_parser(ether_type) {
+                Some(parser) => parser(self),
+                None => Err(RegistryError::NoParser(ether_type).into()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, PBB_PACKET};
+    use crate::Mbuf;
+
+    /// An IEEE 802 local experimental EtherType.
+    const EXPERIMENTAL: EtherType = EtherType(0x88b5);
+
+    /// A dummy parser that keeps the frame as is.
+    fn parse_experimental(ethernet: Ethernet) -> Result<Box<dyn AnyPacket>> {
+        Ok(Box::new(ethernet))
+    }
+
+    #[test]
+    fn register_parsers() {
+        // not the one the other tests register.
+        const OTHER: EtherType = EtherType(0x88b7);
+
+        assert!(register_parser(EtherTypes::Ipv4, parse_experimental).is_err());
+
+        register_parser(OTHER, parse_experimental).unwrap();
+        assert!(register_parser(OTHER, parse_experimental).is_err());
+    }
+
+    #[capsule::test]
+    fn parse_next_dyn_built_in() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse_next_dyn().unwrap();
+        assert!(ipv4.is::<Ipv4>());
+
+        let packet = Mbuf::from_bytes(&PBB_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let pbb = ethernet.parse_next_dyn().unwrap();
+        assert!(pbb.is::<Pbb>());
+    }
+
+    #[capsule::test]
+    fn parse_next_dyn_registered() {
+        const UNREGISTERED: EtherType = EtherType(0x88b6);
+
+        let _ = register_parser(EXPERIMENTAL, parse_experimental);
+
+        let mut packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        packet
+            .write_data_slice(12, &EXPERIMENTAL.to_be_bytes())
+            .unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let custom = ethernet.parse_next_dyn().unwrap();
+        let ethernet = custom.downcast::<Ethernet>().ok().unwrap();
+        assert_eq!(EXPERIMENTAL, ethernet.ether_type());
+
+        let mut packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        packet
+            .write_data_slice(12, &UNREGISTERED.to_be_bytes())
+            .unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(ethernet.parse_next_dyn().is_err());
+    }
+}
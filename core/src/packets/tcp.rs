@@ -462,18 +462,25 @@ impl<E: IpPacket> Tcp<E> {
     fn compute_checksum(&mut self) {
         self.set_checksum(0);
 
-        if let Ok(data) = self.mbuf().read_data_slice(self.offset, self.len()) {
-            let data = unsafe { data.as_ref() };
-            let pseudo_header_sum = self
-                .envelope()
-                .pseudo_header(data.len() as u16, ProtocolNumbers::Tcp)
-                .sum();
-            let checksum = checksum::compute(pseudo_header_sum, data);
-            self.set_checksum(checksum);
+        // a coalesced packet has the rest of its payload in the chained
+        // segments, which are copied out to be summed in one pass.
+        let segments;
+        let data = if self.full_len() != self.len() {
+            segments = self.mbuf().read_segments(self.offset);
+            &segments[..]
+        } else if let Ok(data) = self.mbuf().read_data_slice(self.offset, self.len()) {
+            unsafe { data.as_ref() }
         } else {
             // we are reading till the end of buffer, should never run out
             unreachable!()
-        }
+        };
+
+        let pseudo_header_sum = self
+            .envelope()
+            .pseudo_header(data.len() as u16, ProtocolNumbers::Tcp)
+            .sum();
+        let checksum = checksum::compute(pseudo_header_sum, data);
+        self.set_checksum(checksum);
     }
 }
 
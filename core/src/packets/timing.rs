@@ -0,0 +1,282 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::{type_layer_name, Internal, Packet};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Parses the envelope's payload as a packet of type `T`, and returns the
+/// time the parse took alongside the result.
+///
+/// The same as [`parse`], timed. Use [`ParseTimes`] to aggregate the times
+/// by packet type.
+///
+/// # Example
+///
+/// ```
+/// let (ethernet, elapsed) = parse_timed::<Ethernet>(mbuf);
+/// let (ipv4, elapsed) = parse_timed::<Ipv4>(ethernet?);
+/// ```
+///
+/// [`parse`]: Packet::parse
+#[cfg_attr(docsrs, doc(cfg(feature = "parse-timing")))]
+#[inline]
+pub fn parse_timed<T: Packet>(envelope: T::Envelope) -> (Result<T>, Duration) {
+    let start = Instant::now();
+    let result = T::try_parse(envelope, Internal(()));
+    (result, start.elapsed())
+}
+
+/// The number of buckets of a [`ParseHistogram`].
+const BUCKETS: usize = 32;
+
+/// A histogram of parse times.
+///
+/// The buckets are powers of two nanoseconds. Bucket `i` counts the parses
+/// that took less than `2^i` nanoseconds and at least half of that, and the
+/// last bucket counts all the longer ones.
+#[cfg_attr(docsrs, doc(cfg(feature = "parse-timing")))]
+#[derive(Clone, Debug, Default)]
+pub struct ParseHistogram {
+    buckets: [u64; BUCKETS],
+    errors: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl ParseHistogram {
+    /// Records the time of a parse, and whether it failed.
+    pub fn record(&mut self, elapsed: Duration, failed: bool) {
+        let nanos = elapsed.as_nanos();
+        let bucket = (128 - nanos.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+
+        if failed {
+            self.errors += 1;
+        }
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+
+    /// Returns the number of parses recorded.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the number of parses that failed.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Returns the total time of the parses.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Returns the average time of a parse.
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::default(),
+            count => Duration::from_nanos((self.total.as_nanos() / u128::from(count)) as u64),
+        }
+    }
+
+    /// Returns the longest time of a parse.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Returns the counts of the buckets.
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// Returns the time the given fraction of the parses took less than,
+    /// rounded up to the bucket's upper bound and capped by the longest
+    /// time. For example `0.99` for the 99th percentile.
+    pub fn percentile(&self, fraction: f64) -> Duration {
+        let rank = (self.count() as f64 * fraction).ceil() as u64;
+        let mut seen = 0;
+        for (i, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank && count > 0 {
+                // the last bucket doesn't have an upper bound.
+                let bound = if i < BUCKETS - 1 {
+                    Duration::from_nanos(1 << i)
+                } else {
+                    self.max
+                };
+                return bound.min(self.max);
+            }
+        }
+
+        self.max
+    }
+
+    /// Adds the parses of another histogram, for example to combine the
+    /// times recorded on different cores.
+    pub fn merge(&mut self, other: &ParseHistogram) {
+        for (bucket, &count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+        self.errors += other.errors;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// Parse time histograms keyed by the [`layer_name`] of the packet type.
+///
+/// Not shared between cores. Each pipeline keeps its own and the
+/// histograms are merged for reporting.
+///
+/// # Example
+///
+/// ```
+/// let mut times = ParseTimes::default();
+///
+/// let ethernet = times.parse::<Ethernet>(mbuf)?;
+/// let ipv4 = times.parse::<Ipv4>(ethernet)?;
+/// let udp = times.parse::<Udp4>(ipv4)?;
+/// ...
+///
+/// for (layer, histogram) in times.iter() {
+///     println!("{}: {:?} p99 {:?}", layer, histogram.mean(), histogram.percentile(0.99));
+/// }
+/// ```
+///
+/// [`layer_name`]: Packet::layer_name
+#[cfg_attr(docsrs, doc(cfg(feature = "parse-timing")))]
+#[derive(Clone, Debug, Default)]
+pub struct ParseTimes {
+    histograms: HashMap<&'static str, ParseHistogram>,
+}
+
+impl ParseTimes {
+    /// Parses the envelope's payload as a packet of type `T`, and records
+    /// the time of the parse in the type's histogram.
+    #[inline]
+    pub fn parse<T: Packet>(&mut self, envelope: T::Envelope) -> Result<T> {
+        let (result, elapsed) = parse_timed::<T>(envelope);
+        self.record(type_layer_name::<T>(), elapsed, result.is_err());
+        result
+    }
+
+    /// Records the time of a parse in the layer's histogram.
+    pub fn record(&mut self, layer: &'static str, elapsed: Duration, failed: bool) {
+        self.histograms
+            .entry(layer)
+            .or_default()
+            .record(elapsed, failed);
+    }
+
+    /// Returns the histogram of the layer.
+    pub fn get(&self, layer: &str) -> Option<&ParseHistogram> {
+        self.histograms.get(layer)
+    }
+
+    /// Returns an iterator over the layers and their histograms, in no
+    /// particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &ParseHistogram)> {
+        self.histograms
+            .iter()
+            .map(|(&layer, histogram)| (layer, histogram))
+    }
+
+    /// Adds the histograms of another set of parse times.
+    pub fn merge(&mut self, other: &ParseTimes) {
+        for (layer, histogram) in other.iter() {
+            self.histograms.entry(layer).or_default().merge(histogram);
+        }
+    }
+
+    /// Clears all the histograms.
+    pub fn clear(&mut self) {
+        self.histograms.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::{Ethernet, Tcp4, Udp4};
+    use crate::testils::byte_arrays::IPV4_UDP_PACKET;
+    use crate::Mbuf;
+
+    #[test]
+    fn bucket_parse_times() {
+        let mut histogram = ParseHistogram::default();
+        histogram.record(Duration::from_nanos(0), false);
+        histogram.record(Duration::from_nanos(3), false);
+        histogram.record(Duration::from_nanos(100), true);
+        histogram.record(Duration::from_secs(10), false);
+
+        assert_eq!(4, histogram.count());
+        assert_eq!(1, histogram.errors());
+        assert_eq!(1, histogram.buckets()[0]);
+        assert_eq!(1, histogram.buckets()[2]);
+        assert_eq!(1, histogram.buckets()[7]);
+        assert_eq!(1, histogram.buckets()[BUCKETS - 1]);
+        assert_eq!(Duration::from_secs(10), histogram.max());
+        assert_eq!(Duration::from_nanos(128), histogram.percentile(0.75));
+        assert_eq!(Duration::from_secs(10), histogram.percentile(1.0));
+
+        let mut other = ParseHistogram::default();
+        other.record(Duration::from_nanos(3), false);
+        histogram.merge(&other);
+        assert_eq!(5, histogram.count());
+        assert_eq!(2, histogram.buckets()[2]);
+    }
+
+    #[capsule::test]
+    fn time_parses_by_layer() {
+        let mut times = ParseTimes::default();
+
+        for _ in 0..3 {
+            let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+            let ethernet = times.parse::<Ethernet>(packet).unwrap();
+            let ipv4 = times.parse::<Ipv4>(ethernet).unwrap();
+            assert!(times.parse::<Udp4>(ipv4).is_ok());
+        }
+
+        // the packet is not a TCP packet.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = times.parse::<Ethernet>(packet).unwrap();
+        let ipv4 = times.parse::<Ipv4>(ethernet).unwrap();
+        assert!(times.parse::<Tcp4>(ipv4).is_err());
+
+        // the timing alone doesn't record.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let (result, _) = parse_timed::<Ethernet>(packet);
+        assert!(result.is_ok());
+
+        assert_eq!(4, times.get("Ethernet").unwrap().count());
+        assert_eq!(4, times.get("Ipv4").unwrap().count());
+        assert_eq!(3, times.get("Udp").unwrap().count());
+        assert_eq!(0, times.get("Udp").unwrap().errors());
+        assert_eq!(1, times.get("Tcp").unwrap().errors());
+        assert_eq!(4, times.iter().count());
+
+        let mut merged = ParseTimes::default();
+        merged.merge(&times);
+        merged.merge(&times);
+        assert_eq!(8, merged.get("Ethernet").unwrap().count());
+    }
+}
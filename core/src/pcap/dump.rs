@@ -115,8 +115,10 @@ impl Pcap {
 
     unsafe fn dump_packet(&self, ptr: *mut ffi::rte_mbuf) {
         let mut pcap_hdr = ffi::pcap_pkthdr::default();
-        pcap_hdr.len = (*ptr).data_len as u32;
-        pcap_hdr.caplen = pcap_hdr.len;
+        // only the first segment is dumped, the original length is the
+        // length of the whole packet.
+        pcap_hdr.len = (*ptr).pkt_len;
+        pcap_hdr.caplen = (*ptr).data_len as u32;
 
         // If this errors, we'll still want to write packet(s) to the pcap,
         let _ = libc::gettimeofday(
@@ -0,0 +1,282 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use super::read::{
+    read_bytes, read_interface, read_timestamp, read_u16, read_u32, Interface, PcapReadError,
+    Timestamp, BLOCK_MIN_LEN, BYTE_ORDER_MAGIC, ENHANCED_PACKET_BLOCK, INTERFACE_DESCRIPTION_BLOCK,
+    PACKET_BLOCK, SECTION_HEADER_BLOCK,
+};
+use crate::ensure;
+use crate::pcapng::{InterfaceId, PcapngWriter};
+use anyhow::Result;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use thiserror::Error;
+
+/// Errors merging packet captures.
+#[derive(Debug, Error)]
+pub(crate) enum MergeError {
+    /// The input is not a pcapng file.
+    #[error("Not a pcapng file.")]
+    NotPcapng,
+
+    /// The input has a big-endian section, its blocks can't be copied.
+    #[error("Big-endian sections are not merged.")]
+    BigEndian,
+}
+
+/// A packet of an input, waiting to be written in timestamp order.
+struct MergedPacket {
+    timestamp: Timestamp,
+    input: usize,
+    interface: InterfaceId,
+    // the block body from the timestamp on.
+    body: Vec<u8>,
+}
+
+/// Merges pcapng files into a single pcapng file, with the packets of all
+/// the inputs interleaved by timestamp. Returns the number of packets
+/// written.
+///
+/// The interface description blocks of every input are copied to the
+/// merged file, so each packet keeps the interface it was captured on, and
+/// its comment. Packets with the same timestamp are written in the order of
+/// their inputs, and in file order within an input. The capture merges its
+/// per-core files in core id order, which breaks the ties by core id.
+///
+/// The inputs are read whole. Only little-endian pcapng files are merged,
+/// like the ones written by [`PcapngWriter`].
+///
+/// # Errors
+///
+/// Returns an error if an input can't be read or isn't a little-endian
+/// pcapng file, or the output can't be written.
+///
+/// # Example
+///
+/// ```
+/// let count = pcap::merge(&["eth1-lcore1-0.pcapng", "eth1-lcore2-0.pcapng"], "eth1.pcapng")?;
+/// ```
+///
+/// [`PcapngWriter`]: crate::pcapng::PcapngWriter
+pub fn merge<P: AsRef<Path>, Q: AsRef<Path>>(inputs: &[P], output: Q) -> Result<usize> {
+    let mut writer = PcapngWriter::create(output)?;
+    let mut packets = vec![];
+
+    for (input, path) in inputs.iter().enumerate() {
+        let path = path.as_ref();
+        let bytes = fs::read(path)?;
+        read_input(&bytes, input, &mut writer, &mut packets)
+            .map_err(|err| err.context(path.display().to_string()))?;
+    }
+
+    // the sort is stable, so the packets of an input with the same
+    // timestamp keep their order.
+    packets.sort_by_key(|packet| (packet.timestamp, packet.input));
+    for packet in packets.iter() {
+        writer.copy_packet(packet.interface, &packet.body)?;
+    }
+
+    writer.finish()?;
+    Ok(packets.len())
+}
+
+/// Copies the interfaces of an input to the merged file, and collects its
+/// packets.
+fn read_input(
+    bytes: &[u8],
+    input: usize,
+    writer: &mut PcapngWriter<BufWriter<File>>,
+    packets: &mut Vec<MergedPacket>,
+) -> Result<()> {
+    let magic = read_u32(bytes, 0, false)?;
+    ensure!(magic == SECTION_HEADER_BLOCK, MergeError::NotPcapng);
+
+    // the interfaces of the current section, with their ids in the merged
+    // file.
+    let mut interfaces: Vec<(InterfaceId, Interface)> = vec![];
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let block_type = read_u32(bytes, offset, false)?;
+        if block_type == SECTION_HEADER_BLOCK {
+            let magic = read_u32(bytes, offset + 8, false)?;
+            ensure!(magic == BYTE_ORDER_MAGIC, MergeError::BigEndian);
+            interfaces.clear();
+        }
+
+        let len = read_u32(bytes, offset + 4, false)?;
+        if (len as usize) < BLOCK_MIN_LEN || len % 4 != 0 {
+            return Err(PcapReadError::BadBlockLength(len).into());
+        }
+        let body = read_bytes(bytes, offset + 8, len as usize - BLOCK_MIN_LEN)?;
+        if read_u32(bytes, offset + len as usize - 4, false)? != len {
+            return Err(PcapReadError::BadBlockLength(len).into());
+        }
+        offset += len as usize;
+
+        let id = match block_type {
+            INTERFACE_DESCRIPTION_BLOCK => {
+                let interface = read_interface(body, false)?;
+                interfaces.push((writer.copy_interface(body)?, interface));
+                continue;
+            }
+            ENHANCED_PACKET_BLOCK => read_u32(body, 0, false)?,
+            // the legacy packet block only differs in its 16-bit interface
+            // id and drops count, which are not copied.
+            PACKET_BLOCK => read_u16(body, 0, false)?.into(),
+            // the other blocks don't have packets with timestamps.
+            _ => continue,
+        };
+
+        let (interface, timing) = interfaces
+            .get(id as usize)
+            .ok_or(PcapReadError::UnknownInterface(id))?;
+        let timestamp = read_timestamp(body, 4, timing, false)?;
+        let caplen = read_u32(body, 12, false)? as usize;
+        let _ = read_bytes(body, 20, caplen)?;
+
+        packets.push(MergedPacket {
+            timestamp,
+            input,
+            interface: *interface,
+            body: body[4..].to_vec(),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap;
+    use crate::pcapng::Interface as PcapngInterface;
+    use crate::testils::byte_arrays::IPV4_UDP_PACKET;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Writes a per-core capture with the packets at the nanosecond offsets.
+    /// The last byte of each packet is the core id, and the one before it
+    /// the packet's index on the core.
+    fn write_core_file(core: u8, offsets: &[u64], interfaces: usize) -> String {
+        let path = format!("pcap_merge-lcore{}.pcapng", core);
+        let mut writer = PcapngWriter::create(&path).unwrap();
+        let ids = (0..interfaces)
+            .map(|i| {
+                let name = format!("eth{}@lcore{}", i, core);
+                writer.add_interface(&PcapngInterface::new(&name)).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        for (index, &offset) in offsets.iter().enumerate() {
+            let mut data = IPV4_UDP_PACKET.to_vec();
+            let len = data.len();
+            data[len - 2] = index as u8;
+            data[len - 1] = core;
+
+            let timestamp =
+                UNIX_EPOCH + Duration::new(1_600_000_000, 0) + Duration::from_nanos(offset);
+            let comment = format!("core={}", core);
+            writer
+                .write_packet(
+                    ids[index % ids.len()],
+                    timestamp,
+                    &data,
+                    len,
+                    Some(&comment),
+                )
+                .unwrap();
+        }
+
+        writer.finish().unwrap();
+        path
+    }
+
+    /// Returns the number of interface description blocks of a pcapng file.
+    fn count_interfaces(path: &str) -> usize {
+        let bytes = fs::read(path).unwrap();
+        let mut offset = 0;
+        let mut count = 0;
+        while offset < bytes.len() {
+            if read_u32(&bytes, offset, false).unwrap() == INTERFACE_DESCRIPTION_BLOCK {
+                count += 1;
+            }
+            offset += read_u32(&bytes, offset + 4, false).unwrap() as usize;
+        }
+        count
+    }
+
+    #[test]
+    fn merge_per_core_files() {
+        // core 2 has two interfaces. the cores tie at 300ns and 500ns.
+        let inputs = vec![
+            write_core_file(0, &[100, 300, 700, 900], 1),
+            write_core_file(1, &[200, 300, 500], 1),
+            write_core_file(2, &[50, 500, 500, 800, 1_000], 2),
+        ];
+        let output = "pcap_merge.pcapng";
+
+        assert_eq!(12, merge(&inputs, output).unwrap());
+        assert_eq!(4, count_interfaces(output));
+
+        let merged = pcap::read_file(output).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(12, merged.len());
+        assert!(merged.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+        // (core, index on the core) of each packet.
+        let order = merged
+            .iter()
+            .map(|(_, data)| (data[data.len() - 1], data[data.len() - 2]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (2, 0),
+                (0, 0),
+                (1, 0),
+                (0, 1),
+                (1, 1),
+                (1, 2),
+                (2, 1),
+                (2, 2),
+                (0, 2),
+                (2, 3),
+                (0, 3),
+                (2, 4),
+            ],
+            order
+        );
+
+        let _ = fs::remove_file(output);
+        for input in inputs.iter() {
+            let _ = fs::remove_file(input);
+        }
+    }
+
+    #[test]
+    fn reject_classic_pcap_input() {
+        let input = "pcap_merge_classic.pcap";
+        let output = "pcap_merge_classic.pcapng";
+        fs::write(input, &0xa1b2_c3d4u32.to_le_bytes()).unwrap();
+
+        assert!(merge(&[input], output).is_err());
+
+        let _ = fs::remove_file(input);
+        let _ = fs::remove_file(output);
+    }
+}
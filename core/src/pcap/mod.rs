@@ -0,0 +1,35 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Reading packet captures.
+//!
+//! [`read_file`] reads the packets of both classic pcap and pcapng files,
+//! for replaying them or as test input. [`merge`] interleaves the packets of
+//! several pcapng files by timestamp, for example the per-core files of a
+//! capture. With the `pcap-dump` feature, the traffic of every port queue
+//! is also captured to `pcap` files.
+
+#[cfg(feature = "pcap-dump")]
+mod dump;
+mod merge;
+mod read;
+
+#[cfg(feature = "pcap-dump")]
+pub(crate) use self::dump::*;
+pub use self::merge::*;
+pub use self::read::*;
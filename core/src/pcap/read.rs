@@ -0,0 +1,605 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use anyhow::Result;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+// classic pcap magic numbers, as read in little-endian.
+const PCAP_MICROS_LE: u32 = 0xa1b2_c3d4;
+const PCAP_NANOS_LE: u32 = 0xa1b2_3c4d;
+const PCAP_MICROS_BE: u32 = 0xd4c3_b2a1;
+const PCAP_NANOS_BE: u32 = 0x4d3c_b2a1;
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+
+// pcapng block types and options.
+pub(super) const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+pub(super) const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+pub(super) const PACKET_BLOCK: u32 = 0x0000_0002;
+pub(super) const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+pub(super) const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const OPT_ENDOFOPT: u16 = 0;
+const IF_TSRESOL: u16 = 9;
+const IF_TSOFFSET: u16 = 14;
+pub(super) const BLOCK_MIN_LEN: usize = 12;
+
+/// The capture time of a packet, since the Unix epoch.
+pub type Timestamp = Duration;
+
+/// Errors reading a packet capture.
+#[derive(Debug, Error)]
+pub(crate) enum PcapReadError {
+    /// The file doesn't start with a pcap or pcapng magic number.
+    #[error("Not a pcap or pcapng file, unknown magic number 0x{0:08x}.")]
+    BadMagic(u32),
+
+    /// The file ends in the middle of a header, a block or a packet.
+    #[error("Capture file is truncated.")]
+    Truncated,
+
+    /// The length of a pcapng block is invalid.
+    #[error("Block length '{0}' is invalid.")]
+    BadBlockLength(u32),
+
+    /// A pcapng packet refers to an interface not described before it.
+    #[error("Interface {0} is not described in the capture.")]
+    UnknownInterface(u32),
+
+    /// The timestamp of a packet is out of range.
+    #[error("Timestamp is out of range.")]
+    BadTimestamp,
+
+    /// The timestamp resolution of an interface is out of range.
+    #[error("Timestamp resolution 0x{0:02x} is not supported.")]
+    BadResolution(u8),
+}
+
+/// Reads the packets of a classic pcap or pcapng file, with their capture
+/// times.
+///
+/// Both endiannesses of either format are read. In pcap files, both the
+/// microsecond and the nanosecond resolutions are. In pcapng files, the
+/// packets of both the enhanced packet blocks and the legacy packet blocks
+/// are read, from any number of interfaces and sections. Only the captured
+/// bytes of the packets are returned, which may be fewer than the original
+/// length.
+///
+/// The file is read whole when the iterator is first polled. An error
+/// reading or parsing the file is returned as the last item, and ends the
+/// iteration. Parsing never panics on a corrupted file.
+///
+/// # Example
+///
+/// ```
+/// for packet in pcap::read_file("capture.pcapng") {
+///     let (timestamp, data) = packet?;
+///     ...
+/// }
+/// ```
+pub fn read_file<P: AsRef<Path>>(path: P) -> impl Iterator<Item = Result<(Timestamp, Vec<u8>)>> {
+    read_records(path).map(|res| res.map(|record| (record.timestamp, record.data)))
+}
+
+/// A packet read from a capture file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    /// The capture time of the packet.
+    pub timestamp: Timestamp,
+    /// The captured bytes of the packet.
+    pub data: Vec<u8>,
+    /// The original length of the packet, more than the bytes captured if
+    /// the packet is truncated to the snapshot length.
+    pub len: usize,
+}
+
+/// Reads the packets of a classic pcap or pcapng file like [`read_file`],
+/// with the original length of each packet.
+///
+/// # Example
+///
+/// ```
+/// for record in pcap::read_records("capture.pcapng") {
+///     let record = record?;
+///     if record.data.len() < record.len {
+///         println!("truncated from {} bytes.", record.len);
+///     }
+/// }
+/// ```
+pub fn read_records<P: AsRef<Path>>(path: P) -> impl Iterator<Item = Result<Record>> {
+    let path = path.as_ref().to_owned();
+    let mut reader = None;
+
+    std::iter::from_fn(move || {
+        let reader = reader.get_or_insert_with(|| match fs::read(&path) {
+            Ok(bytes) => CaptureReader::new(bytes),
+            Err(err) => CaptureReader::failed(err.into()),
+        });
+        reader.next()
+    })
+}
+
+/// The interface of a pcapng section.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Interface {
+    // the number of timestamp units per second.
+    units: u128,
+    // the seconds added to every timestamp. negative offsets are not
+    // supported.
+    offset: u64,
+}
+
+impl Default for Interface {
+    fn default() -> Self {
+        // microseconds by default.
+        Interface {
+            units: 1_000_000,
+            offset: 0,
+        }
+    }
+}
+
+/// The format of the capture file, detected from its magic number.
+#[derive(Debug)]
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+    },
+    Pcapng {
+        big_endian: bool,
+        interfaces: Vec<Interface>,
+    },
+}
+
+/// Reads the packets of a capture file already in memory.
+struct CaptureReader {
+    bytes: Vec<u8>,
+    offset: usize,
+    format: Option<Format>,
+    error: Option<anyhow::Error>,
+    done: bool,
+}
+
+impl CaptureReader {
+    fn new(bytes: Vec<u8>) -> Self {
+        CaptureReader {
+            bytes,
+            offset: 0,
+            format: None,
+            error: None,
+            done: false,
+        }
+    }
+
+    /// Creates a reader that only returns the error.
+    fn failed(err: anyhow::Error) -> Self {
+        CaptureReader {
+            error: Some(err),
+            ..CaptureReader::new(vec![])
+        }
+    }
+
+    /// Reads the next packet, or returns `None` at the end of the file.
+    fn read_next(&mut self) -> Result<Option<Record>> {
+        if self.format.is_none() {
+            self.format = Some(self.read_format()?);
+        }
+
+        match self.format {
+            Some(Format::Pcap { big_endian, nanos }) => self.read_record(big_endian, nanos),
+            _ => self.read_packet_block(),
+        }
+    }
+
+    /// Detects the format, and skips the pcap file header. The pcapng
+    /// section header is read like any other block.
+    fn read_format(&mut self) -> Result<Format> {
+        let magic = read_u32(&self.bytes, 0, false)?;
+        let (big_endian, nanos) = match magic {
+            PCAP_MICROS_LE => (false, false),
+            PCAP_NANOS_LE => (false, true),
+            PCAP_MICROS_BE => (true, false),
+            PCAP_NANOS_BE => (true, true),
+            SECTION_HEADER_BLOCK => {
+                return Ok(Format::Pcapng {
+                    big_endian: false,
+                    interfaces: vec![],
+                })
+            }
+            _ => return Err(PcapReadError::BadMagic(magic).into()),
+        };
+
+        if self.bytes.len() < PCAP_HEADER_LEN {
+            return Err(PcapReadError::Truncated.into());
+        }
+        self.offset = PCAP_HEADER_LEN;
+        Ok(Format::Pcap { big_endian, nanos })
+    }
+
+    /// Reads the next record of a classic pcap file.
+    fn read_record(&mut self, big_endian: bool, nanos: bool) -> Result<Option<Record>> {
+        if self.offset == self.bytes.len() {
+            return Ok(None);
+        }
+
+        let offset = self.offset;
+        let secs = read_u32(&self.bytes, offset, big_endian)?;
+        let frac = read_u32(&self.bytes, offset + 4, big_endian)?;
+        let caplen = read_u32(&self.bytes, offset + 8, big_endian)? as usize;
+        let len = read_u32(&self.bytes, offset + 12, big_endian)? as usize;
+        let data = read_bytes(&self.bytes, offset + PCAP_RECORD_HEADER_LEN, caplen)?;
+        self.offset = offset + PCAP_RECORD_HEADER_LEN + caplen;
+
+        let frac = if nanos {
+            Duration::from_nanos(frac.into())
+        } else {
+            Duration::from_micros(frac.into())
+        };
+        Ok(Some(Record {
+            timestamp: Duration::from_secs(secs.into()) + frac,
+            data: data.to_vec(),
+            len,
+        }))
+    }
+
+    /// Reads the pcapng blocks up to the next packet.
+    fn read_packet_block(&mut self) -> Result<Option<Record>> {
+        while self.offset < self.bytes.len() {
+            let offset = self.offset;
+
+            // the section header block type reads the same in both byte
+            // orders. its byte order magic sets the order of the section.
+            let block_type = read_u32(&self.bytes, offset, false)?;
+            if block_type == SECTION_HEADER_BLOCK {
+                let magic = read_u32(&self.bytes, offset + 8, false)?;
+                let big_endian = match magic {
+                    BYTE_ORDER_MAGIC => false,
+                    _ if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                    _ => return Err(PcapReadError::BadMagic(magic).into()),
+                };
+                self.format = Some(Format::Pcapng {
+                    big_endian,
+                    interfaces: vec![],
+                });
+            }
+
+            let (big_endian, interfaces) = match &mut self.format {
+                Some(Format::Pcapng {
+                    big_endian,
+                    interfaces,
+                }) => (*big_endian, interfaces),
+                _ => unreachable!(),
+            };
+
+            let block_type = read_u32(&self.bytes, offset, big_endian)?;
+            let len = read_u32(&self.bytes, offset + 4, big_endian)?;
+            if (len as usize) < BLOCK_MIN_LEN || len % 4 != 0 {
+                return Err(PcapReadError::BadBlockLength(len).into());
+            }
+            let body = read_bytes(&self.bytes, offset + 8, len as usize - BLOCK_MIN_LEN)?;
+            if read_u32(&self.bytes, offset + len as usize - 4, big_endian)? != len {
+                return Err(PcapReadError::BadBlockLength(len).into());
+            }
+            self.offset = offset + len as usize;
+
+            match block_type {
+                INTERFACE_DESCRIPTION_BLOCK => {
+                    interfaces.push(read_interface(body, big_endian)?);
+                }
+                ENHANCED_PACKET_BLOCK => {
+                    let id = read_u32(body, 0, big_endian)?;
+                    return read_packet(body, 4, id, interfaces, big_endian).map(Some);
+                }
+                PACKET_BLOCK => {
+                    let id = read_u16(body, 0, big_endian)?;
+                    // skips the interface id and the drops count.
+                    return read_packet(body, 4, id.into(), interfaces, big_endian).map(Some);
+                }
+                // the other blocks don't have packets with timestamps.
+                _ => (),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            self.done = true;
+            return Some(Err(err));
+        }
+        if self.done {
+            return None;
+        }
+
+        match self.read_next() {
+            Ok(Some(packet)) => Some(Ok(packet)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Reads the timestamp resolution and offset of an interface description
+/// block.
+pub(super) fn read_interface(body: &[u8], big_endian: bool) -> Result<Interface> {
+    // skips the link type, the reserved field and the snapshot length.
+    let mut offset = 8;
+    let mut interface = Interface::default();
+    if body.len() < offset {
+        return Err(PcapReadError::Truncated.into());
+    }
+
+    while offset + 4 <= body.len() {
+        let code = read_u16(body, offset, big_endian)?;
+        let len = read_u16(body, offset + 2, big_endian)? as usize;
+        let value = read_bytes(body, offset + 4, len)?;
+        match code {
+            OPT_ENDOFOPT => break,
+            IF_TSRESOL if len == 1 => {
+                let resol = value[0];
+                let units = if resol & 0x80 == 0 {
+                    10u128.checked_pow(resol.into())
+                } else {
+                    2u128.checked_pow((resol & 0x7f).into())
+                };
+                interface.units = units.ok_or(PcapReadError::BadResolution(resol))?;
+            }
+            IF_TSOFFSET if len == 8 => {
+                let word: [u8; 8] = value.try_into().unwrap();
+                interface.offset = if big_endian {
+                    u64::from_be_bytes(word)
+                } else {
+                    u64::from_le_bytes(word)
+                };
+            }
+            _ => (),
+        }
+        // option values are padded to 32 bits.
+        offset += 4 + (len + 3) / 4 * 4;
+    }
+
+    Ok(interface)
+}
+
+/// Reads the timestamp, the captured bytes and the original length of a
+/// packet block, from the offset of the timestamp in the block body.
+fn read_packet(
+    body: &[u8],
+    offset: usize,
+    id: u32,
+    interfaces: &[Interface],
+    big_endian: bool,
+) -> Result<Record> {
+    let interface = interfaces
+        .get(id as usize)
+        .ok_or(PcapReadError::UnknownInterface(id))?;
+
+    let caplen = read_u32(body, offset + 8, big_endian)? as usize;
+    let len = read_u32(body, offset + 12, big_endian)? as usize;
+    let data = read_bytes(body, offset + 16, caplen)?;
+    let timestamp = read_timestamp(body, offset, interface, big_endian)?;
+    Ok(Record {
+        timestamp,
+        data: data.to_vec(),
+        len,
+    })
+}
+
+/// Reads the timestamp of a packet block in the units of its interface,
+/// from the offset of the timestamp in the block body.
+pub(super) fn read_timestamp(
+    body: &[u8],
+    offset: usize,
+    interface: &Interface,
+    big_endian: bool,
+) -> Result<Timestamp> {
+    let high = read_u32(body, offset, big_endian)?;
+    let low = read_u32(body, offset + 4, big_endian)?;
+
+    let units = u128::from(high) << 32 | u128::from(low);
+    let nanos = units * 1_000_000_000 / interface.units;
+    Duration::from_secs(interface.offset)
+        .checked_add(Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        ))
+        .ok_or_else(|| PcapReadError::BadTimestamp.into())
+}
+
+pub(super) fn read_bytes(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| PcapReadError::Truncated.into())
+}
+
+pub(super) fn read_u16(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u16> {
+    let word: [u8; 2] = read_bytes(bytes, offset, 2)?.try_into().unwrap();
+    if big_endian {
+        Ok(u16::from_be_bytes(word))
+    } else {
+        Ok(u16::from_le_bytes(word))
+    }
+}
+
+pub(super) fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> Result<u32> {
+    let word: [u8; 4] = read_bytes(bytes, offset, 4)?.try_into().unwrap();
+    if big_endian {
+        Ok(u32::from_be_bytes(word))
+    } else {
+        Ok(u32::from_le_bytes(word))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, VLAN_DOT1Q_PACKET};
+
+    const LE_MICRO_PCAP: &[u8] = include_bytes!("../../tests/fixtures/le_micro.pcap");
+    const BE_NANO_PCAP: &[u8] = include_bytes!("../../tests/fixtures/be_nano.pcap");
+    const BE_NANO_PCAPNG: &[u8] = include_bytes!("../../tests/fixtures/be_nano.pcapng");
+    const LEGACY_PCAPNG: &[u8] = include_bytes!("../../tests/fixtures/legacy_packet_block.pcapng");
+    const TWO_INTERFACES_PCAPNG: &[u8] =
+        include_bytes!("../../tests/fixtures/two_interfaces.pcapng");
+
+    fn read(bytes: &[u8]) -> Result<Vec<(Timestamp, Vec<u8>)>> {
+        CaptureReader::new(bytes.to_vec())
+            .map(|res| res.map(|record| (record.timestamp, record.data)))
+            .collect()
+    }
+
+    /// The packets every fixture but the one with two interfaces holds.
+    fn expected() -> Vec<(Timestamp, Vec<u8>)> {
+        vec![
+            (
+                Duration::new(1_600_000_000, 500_000_000),
+                IPV4_UDP_PACKET.to_vec(),
+            ),
+            (
+                Duration::new(1_600_000_001, 1_000),
+                VLAN_DOT1Q_PACKET.to_vec(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn read_pcap_files() {
+        assert_eq!(expected(), read(LE_MICRO_PCAP).unwrap());
+        assert_eq!(expected(), read(BE_NANO_PCAP).unwrap());
+    }
+
+    #[test]
+    fn read_pcapng_files() {
+        assert_eq!(expected(), read(BE_NANO_PCAPNG).unwrap());
+        assert_eq!(expected(), read(LEGACY_PCAPNG).unwrap());
+    }
+
+    #[test]
+    fn read_pcapng_interfaces() {
+        let packets = read(TWO_INTERFACES_PCAPNG).unwrap();
+        assert_eq!(2, packets.len());
+
+        let ts = Duration::new(1_600_000_000, 123_456_789);
+        assert_eq!((ts, IPV4_UDP_PACKET.to_vec()), packets[0]);
+        // the second interface truncates its packets.
+        assert_eq!(
+            (
+                ts + Duration::from_micros(1),
+                IPV4_UDP_PACKET[..32].to_vec()
+            ),
+            packets[1]
+        );
+    }
+
+    #[test]
+    fn read_original_lengths() {
+        let lengths = |bytes: &[u8]| {
+            CaptureReader::new(bytes.to_vec())
+                .map(|res| res.map(|record| (record.data.len(), record.len)))
+                .collect::<Result<Vec<_>>>()
+                .unwrap()
+        };
+
+        // the second interface truncates its packets to 32 bytes.
+        let len = IPV4_UDP_PACKET.len();
+        assert_eq!(vec![(len, len), (32, len)], lengths(TWO_INTERFACES_PCAPNG));
+
+        // a classic pcap record cut down to the Ethernet header.
+        let mut bytes = LE_MICRO_PCAP[..PCAP_HEADER_LEN + PCAP_RECORD_HEADER_LEN].to_vec();
+        bytes[PCAP_HEADER_LEN + 8..PCAP_HEADER_LEN + 12].copy_from_slice(&14u32.to_le_bytes());
+        bytes.extend_from_slice(&IPV4_UDP_PACKET[..14]);
+        assert_eq!(vec![(14, len)], lengths(&bytes));
+    }
+
+    #[test]
+    fn read_multiple_sections() {
+        let mut bytes = BE_NANO_PCAPNG.to_vec();
+        bytes.extend_from_slice(LEGACY_PCAPNG);
+
+        let mut twice = expected();
+        twice.extend(expected());
+        assert_eq!(twice, read(&bytes).unwrap());
+    }
+
+    #[test]
+    fn reject_truncated_files() {
+        for fixture in &[
+            LE_MICRO_PCAP,
+            BE_NANO_PCAP,
+            BE_NANO_PCAPNG,
+            LEGACY_PCAPNG,
+            TWO_INTERFACES_PCAPNG,
+        ] {
+            let full = read(fixture).unwrap();
+
+            // a cut anywhere never panics, and loses at least a packet.
+            for len in 0..fixture.len() {
+                if let Ok(packets) = read(&fixture[..len]) {
+                    assert!(packets.len() < full.len());
+                }
+            }
+
+            // a cut in the middle of the last packet is an error.
+            assert!(read(&fixture[..fixture.len() - 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn reject_corrupted_files() {
+        assert!(read(&[0; 24]).is_err());
+
+        // the trailing length of the first packet block doesn't match.
+        let mut bytes = LEGACY_PCAPNG.to_vec();
+        let idb_end = 28 + 20;
+        bytes[idb_end + 4] ^= 0xff;
+        assert!(read(&bytes).is_err());
+
+        // the packet refers to the second interface of the section.
+        let mut bytes = LEGACY_PCAPNG.to_vec();
+        bytes[idb_end + 8] = 1;
+        assert!(read(&bytes).is_err());
+
+        // the byte order magic of the section header is garbage.
+        let mut bytes = BE_NANO_PCAPNG.to_vec();
+        bytes[8] = 0;
+        assert!(read(&bytes).is_err());
+    }
+
+    #[test]
+    fn read_missing_file() {
+        let mut packets = read_file("no_such_capture.pcap");
+        assert!(packets.next().unwrap().is_err());
+        assert!(packets.next().is_none());
+    }
+}
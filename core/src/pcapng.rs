@@ -0,0 +1,617 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Packet captures in the pcapng format.
+//!
+//! Unlike the classic pcap format, a pcapng capture describes each of its
+//! capture sources in an interface description block, so every packet
+//! keeps which port it was seen on. The packets are written in enhanced
+//! packet blocks with nanosecond timestamps, and can carry a comment, for
+//! example the pipeline stage a traced packet was sampled after.
+//!
+//! The writer is not synchronized. On the data path, each core writes to
+//! its own file, named with [`core_path`]. The writes are buffered, and
+//! flushed when the writer is finished or dropped.
+//!
+//! # Example
+//!
+//! ```
+//! let mut writer = PcapngWriter::create(core_path("capture", CoreId::current()))?;
+//! let eth1 = writer.add_interface(&Interface::new("eth1").speed(10_000_000_000))?;
+//! writer.write_mbuf(eth1, &mbuf, Some("after nat"))?;
+//! writer.finish()?;
+//! ```
+//!
+//! The format is specified in the [pcapng draft].
+//!
+//! [pcapng draft]: https://datatracker.ietf.org/doc/draft-ietf-opsawg-pcapng/
+
+use crate::dpdk::CoreId;
+use crate::{ensure, Mbuf};
+use anyhow::Result;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+// block types.
+pub(crate) const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+pub(crate) const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+pub(crate) const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+pub(crate) const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+// option codes.
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_NAME: u16 = 2;
+const IF_SPEED: u16 = 8;
+const IF_TSRESOL: u16 = 9;
+
+// timestamps are in nanoseconds, 10^-9 seconds.
+const TSRESOL_NANOS: u8 = 9;
+
+/// The link type of Ethernet frames.
+pub const LINKTYPE_ETHERNET: u16 = 1;
+
+/// Errors writing a pcapng capture.
+#[derive(Debug, Error)]
+pub(crate) enum PcapngError {
+    /// The interface is not described in the capture.
+    #[error("Interface {0} is not described in the capture.")]
+    UnknownInterface(u32),
+
+    /// The option value doesn't fit in the 16-bit option length.
+    #[error("Option value of {0} bytes is too long.")]
+    OptionTooLong(usize),
+
+    /// The copied block is too short to be an interface description.
+    #[error("Copied interface block is truncated.")]
+    Truncated,
+}
+
+/// A capture source, described once in the capture before its packets.
+#[derive(Clone, Debug)]
+pub struct Interface {
+    name: String,
+    link_type: u16,
+    snaplen: u32,
+    speed: Option<u64>,
+}
+
+impl Interface {
+    /// Creates an Ethernet interface with the name, usually the name of
+    /// the port. The packets are not truncated.
+    pub fn new(name: &str) -> Self {
+        Interface {
+            name: name.to_owned(),
+            link_type: LINKTYPE_ETHERNET,
+            snaplen: 0,
+            speed: None,
+        }
+    }
+
+    /// Sets the link type of the interface. Defaults to Ethernet.
+    pub fn link_type(mut self, link_type: u16) -> Self {
+        self.link_type = link_type;
+        self
+    }
+
+    /// Sets the maximum number of bytes captured per packet. Longer
+    /// packets are truncated. `0` means no limit, the default.
+    pub fn snaplen(mut self, snaplen: u32) -> Self {
+        self.snaplen = snaplen;
+        self
+    }
+
+    /// Sets the speed of the interface in bits per second.
+    pub fn speed(mut self, bits_per_sec: u64) -> Self {
+        self.speed = Some(bits_per_sec);
+        self
+    }
+}
+
+/// The index of an interface in the capture, in the order they are added.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct InterfaceId(u32);
+
+impl InterfaceId {
+    /// Returns the raw index.
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+}
+
+/// Writes a pcapng capture of a single section.
+#[derive(Debug)]
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+    snaplens: Vec<u32>,
+}
+
+impl PcapngWriter<BufWriter<File>> {
+    /// Creates a capture file, replacing the file if it already exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be created.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::create(path)?;
+        PcapngWriter::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Creates a writer of a new capture, and writes its section header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `writer` fails.
+    pub fn new(mut writer: W) -> Result<Self> {
+        let mut body = Vec::with_capacity(16);
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        // version 1.0
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // section length is not specified
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, SECTION_HEADER_BLOCK, body)?;
+
+        Ok(PcapngWriter {
+            writer,
+            snaplens: vec![],
+        })
+    }
+
+    /// Describes a capture source, and returns the id to write its
+    /// packets with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name is too long or writing fails.
+    pub fn add_interface(&mut self, interface: &Interface) -> Result<InterfaceId> {
+        let mut body = Vec::with_capacity(32 + interface.name.len());
+        body.extend_from_slice(&interface.link_type.to_le_bytes());
+        // reserved
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&interface.snaplen.to_le_bytes());
+
+        push_option(&mut body, IF_NAME, interface.name.as_bytes())?;
+        if let Some(speed) = interface.speed {
+            push_option(&mut body, IF_SPEED, &speed.to_le_bytes())?;
+        }
+        push_option(&mut body, IF_TSRESOL, &[TSRESOL_NANOS])?;
+        push_end_of_options(&mut body);
+
+        write_block(&mut self.writer, INTERFACE_DESCRIPTION_BLOCK, body)?;
+
+        let id = InterfaceId(self.snaplens.len() as u32);
+        self.snaplens.push(interface.snaplen);
+        Ok(id)
+    }
+
+    /// Writes a packet seen on the interface. `len` is the original length
+    /// of the packet, and `data` is truncated to the snapshot length of the
+    /// interface.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interface is not added to the capture, the
+    /// comment is too long or writing fails.
+    pub fn write_packet(
+        &mut self,
+        interface: InterfaceId,
+        timestamp: SystemTime,
+        data: &[u8],
+        len: usize,
+        comment: Option<&str>,
+    ) -> Result<()> {
+        let snaplen = *self
+            .snaplens
+            .get(interface.0 as usize)
+            .ok_or(PcapngError::UnknownInterface(interface.0))?;
+        let data = if snaplen > 0 && data.len() > snaplen as usize {
+            &data[..snaplen as usize]
+        } else {
+            data
+        };
+
+        let nanos = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|dur| dur.as_nanos() as u64)
+            .unwrap_or_default();
+
+        let mut body = Vec::with_capacity(32 + data.len() + comment.map_or(0, str::len));
+        body.extend_from_slice(&interface.0.to_le_bytes());
+        body.extend_from_slice(&((nanos >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(nanos as u32).to_le_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(len as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        pad(&mut body);
+
+        if let Some(comment) = comment {
+            push_option(&mut body, OPT_COMMENT, comment.as_bytes())?;
+            push_end_of_options(&mut body);
+        }
+
+        write_block(&mut self.writer, ENHANCED_PACKET_BLOCK, body)
+    }
+
+    /// Writes a packet seen on the interface now, with the data of all its
+    /// segments. Only the bytes within the snapshot length of the interface
+    /// are copied out of the segments, and the original length is the
+    /// length of the whole packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interface is not added to the capture, the
+    /// comment is too long or writing fails.
+    pub fn write_mbuf(
+        &mut self,
+        interface: InterfaceId,
+        mbuf: &Mbuf,
+        comment: Option<&str>,
+    ) -> Result<()> {
+        let snaplen = match *self
+            .snaplens
+            .get(interface.0 as usize)
+            .ok_or(PcapngError::UnknownInterface(interface.0))?
+        {
+            0 => usize::MAX,
+            snaplen => snaplen as usize,
+        };
+
+        let data = mbuf.read_segments_upto(0, snaplen);
+        self.write_packet(interface, SystemTime::now(), &data, mbuf.pkt_len(), comment)
+    }
+
+    /// Copies the body of an interface description block read from another
+    /// little-endian capture, and returns the id to copy its packets with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the body is too short or writing fails.
+    pub(crate) fn copy_interface(&mut self, body: &[u8]) -> Result<InterfaceId> {
+        ensure!(body.len() >= 8, PcapngError::Truncated);
+        let snaplen = u32::from_le_bytes(body[4..8].try_into().unwrap());
+
+        write_block(&mut self.writer, INTERFACE_DESCRIPTION_BLOCK, body.to_vec())?;
+
+        let id = InterfaceId(self.snaplens.len() as u32);
+        self.snaplens.push(snaplen);
+        Ok(id)
+    }
+
+    /// Copies a packet read from another little-endian capture, from the
+    /// timestamp on, as an enhanced packet block of the interface. The
+    /// timestamp is kept in the units of the copied interface.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interface is not added to the capture or
+    /// writing fails.
+    pub(crate) fn copy_packet(&mut self, interface: InterfaceId, body: &[u8]) -> Result<()> {
+        ensure!(
+            (interface.0 as usize) < self.snaplens.len(),
+            PcapngError::UnknownInterface(interface.0)
+        );
+
+        let mut block = Vec::with_capacity(4 + body.len());
+        block.extend_from_slice(&interface.0.to_le_bytes());
+        block.extend_from_slice(body);
+        write_block(&mut self.writer, ENHANCED_PACKET_BLOCK, block)
+    }
+
+    /// Flushes the buffered blocks to the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing fails.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Flushes the capture and returns the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing fails.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Returns the path of the capture file of a core, so each core on the
+/// data path writes to its own file.
+pub fn core_path(prefix: &str, core_id: CoreId) -> String {
+    format!("{}-lcore{}.pcapng", prefix, core_id.raw())
+}
+
+/// Returns the length of the enhanced packet block of a packet with
+/// `captured` bytes of data and no comment.
+pub(crate) fn packet_block_len(captured: usize) -> usize {
+    // block type, lengths, and the 20 bytes of the fixed fields.
+    32 + (captured + 3) / 4 * 4
+}
+
+/// Returns the number of bytes a comment adds to a packet block, with the
+/// option header, the padding and the end of the options.
+pub(crate) fn comment_len(comment: &str) -> usize {
+    4 + (comment.len() + 3) / 4 * 4 + 4
+}
+
+/// Pads the buffer to a 32-bit boundary.
+fn pad(buf: &mut Vec<u8>) {
+    while buf.len() % 4 != 0 {
+        buf.push(0);
+    }
+}
+
+/// Appends an option with the value padded to a 32-bit boundary.
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) -> Result<()> {
+    ensure!(
+        value.len() <= u16::MAX as usize,
+        PcapngError::OptionTooLong(value.len())
+    );
+
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+    Ok(())
+}
+
+/// Appends the option that ends the list of options.
+fn push_end_of_options(buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&OPT_ENDOFOPT.to_le_bytes());
+    buf.extend_from_slice(&0u16.to_le_bytes());
+}
+
+/// Writes a block with the body padded to a 32-bit boundary.
+fn write_block<W: Write>(writer: &mut W, block_type: u32, mut body: Vec<u8>) -> Result<()> {
+    pad(&mut body);
+    let len = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&body)?;
+    writer.write_all(&len.to_le_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{self, ToCString};
+    use crate::testils::byte_arrays::IPV4_UDP_PACKET;
+    use std::fs;
+    use std::os::raw;
+    use std::ptr;
+    use std::time::Duration;
+
+    const PCAP_TSTAMP_PRECISION_NANO: raw::c_uint = 1;
+
+    /// Two interfaces, one with a speed and one truncating the packets,
+    /// and a packet with a comment on each.
+    const GOLDEN: &[u8] = include_bytes!("../tests/fixtures/two_interfaces.pcapng");
+
+    fn timestamp() -> SystemTime {
+        UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789)
+    }
+
+    fn write_two_interfaces<W: Write>(writer: W) -> W {
+        let mut writer = PcapngWriter::new(writer).unwrap();
+        let eth0 = writer
+            .add_interface(&Interface::new("eth0").speed(10_000_000_000))
+            .unwrap();
+        let eth1 = writer
+            .add_interface(&Interface::new("eth1").snaplen(32))
+            .unwrap();
+
+        let len = IPV4_UDP_PACKET.len();
+        writer
+            .write_packet(
+                eth0,
+                timestamp(),
+                &IPV4_UDP_PACKET,
+                len,
+                Some("nat/translate"),
+            )
+            .unwrap();
+        writer
+            .write_packet(
+                eth1,
+                timestamp() + Duration::from_micros(1),
+                &IPV4_UDP_PACKET,
+                len,
+                None,
+            )
+            .unwrap();
+        writer.finish().unwrap()
+    }
+
+    /// Reads the capture with `libpcap`, and returns the timestamp in
+    /// nanoseconds, the captured length and the original length of each
+    /// packet.
+    fn read_with_libpcap(path: &str) -> Vec<(u64, u32, u32)> {
+        let mut errbuf = [0; ffi::RTE_MBUF_DEFAULT_BUF_SIZE as usize];
+        let handle = unsafe {
+            ffi::pcap_open_offline_with_tstamp_precision(
+                path.into_cstring().as_ptr(),
+                PCAP_TSTAMP_PRECISION_NANO,
+                errbuf.as_mut_ptr(),
+            )
+        };
+        assert!(!handle.is_null());
+        assert_eq!(LINKTYPE_ETHERNET as raw::c_int, unsafe {
+            ffi::pcap_datalink(handle)
+        });
+
+        let mut header: *mut ffi::pcap_pkthdr = ptr::null_mut();
+        let mut buf: *const libc::c_uchar = ptr::null();
+        let mut packets = vec![];
+
+        while let 1 = unsafe { ffi::pcap_next_ex(handle, &mut header, &mut buf) } {
+            let header = unsafe { &*header };
+            // with nanosecond precision, `tv_usec` holds the nanoseconds.
+            let nanos = header.ts.tv_sec as u64 * 1_000_000_000 + header.ts.tv_usec as u64;
+            packets.push((nanos, header.caplen, header.len));
+        }
+
+        unsafe {
+            ffi::pcap_close(handle);
+        }
+
+        packets
+    }
+
+    #[test]
+    fn match_golden_capture() {
+        let bytes = write_two_interfaces(vec![]);
+        assert_eq!(GOLDEN, &bytes[..]);
+    }
+
+    #[test]
+    fn read_back_with_libpcap() {
+        let path = std::env::temp_dir().join("capsule_pcapng_read_back.pcapng");
+        let file = fs::File::create(&path).unwrap();
+        let _ = write_two_interfaces(file);
+
+        let nanos = 1_600_000_000_123_456_789;
+        let len = IPV4_UDP_PACKET.len() as u32;
+        assert_eq!(
+            vec![(nanos, len, len), (nanos + 1000, 32, len)],
+            read_with_libpcap(path.to_str().unwrap())
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reject_unknown_interface() {
+        let mut writer = PcapngWriter::new(vec![]).unwrap();
+        let _ = writer.add_interface(&Interface::new("eth0")).unwrap();
+
+        let res = writer.write_packet(
+            InterfaceId(1),
+            timestamp(),
+            &IPV4_UDP_PACKET,
+            IPV4_UDP_PACKET.len(),
+            None,
+        );
+        assert!(res.is_err());
+    }
+
+    #[capsule::test]
+    fn write_mbuf_packet() {
+        let mut writer = PcapngWriter::new(vec![]).unwrap();
+        let eth0 = writer.add_interface(&Interface::new("eth0")).unwrap();
+        let start = writer.writer.len();
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        writer.write_mbuf(eth0, &packet, Some("stage")).unwrap();
+
+        // the packet block holds the whole packet and the comment.
+        let bytes = writer.finish().unwrap();
+        let block = &bytes[start..];
+        assert_eq!(&ENHANCED_PACKET_BLOCK.to_le_bytes(), &block[..4]);
+        assert_eq!(&IPV4_UDP_PACKET[..], &block[28..28 + IPV4_UDP_PACKET.len()]);
+        assert!(block.windows(5).any(|window| window == b"stage"));
+    }
+
+    #[capsule::test]
+    fn write_snapped_mbuf() {
+        let mut writer = PcapngWriter::new(vec![]).unwrap();
+        let eth0 = writer
+            .add_interface(&Interface::new("eth0").snaplen(6))
+            .unwrap();
+        let eth1 = writer
+            .add_interface(&Interface::new("eth1").snaplen(40))
+            .unwrap();
+        let start = writer.get_ref().len();
+
+        // the snapshot of the first interface is shorter than the Ethernet
+        // header. the one of the second spans the two segments.
+        let mut packet = Mbuf::from_bytes(&IPV4_UDP_PACKET[..32]).unwrap();
+        packet
+            .chain(Mbuf::from_bytes(&IPV4_UDP_PACKET[32..]).unwrap())
+            .unwrap();
+        writer.write_mbuf(eth0, &packet, None).unwrap();
+        let middle = writer.get_ref().len();
+        writer.write_mbuf(eth1, &packet, None).unwrap();
+
+        let bytes = writer.finish().unwrap();
+        let lengths = |block: &[u8]| {
+            (
+                u32::from_le_bytes(block[20..24].try_into().unwrap()),
+                u32::from_le_bytes(block[24..28].try_into().unwrap()),
+            )
+        };
+
+        let block = &bytes[start..middle];
+        assert_eq!(packet_block_len(6), block.len());
+        assert_eq!((6, 52), lengths(block));
+        assert_eq!(&IPV4_UDP_PACKET[..6], &block[28..34]);
+
+        let block = &bytes[middle..];
+        assert_eq!(packet_block_len(40), block.len());
+        assert_eq!((40, 52), lengths(block));
+        assert_eq!(&IPV4_UDP_PACKET[..40], &block[28..68]);
+    }
+
+    #[test]
+    fn packet_block_length() {
+        let mut writer = PcapngWriter::new(vec![]).unwrap();
+        let eth0 = writer.add_interface(&Interface::new("eth0")).unwrap();
+
+        for len in 49..53 {
+            let start = writer.get_ref().len();
+            writer
+                .write_packet(eth0, timestamp(), &IPV4_UDP_PACKET[..len], len, None)
+                .unwrap();
+            assert_eq!(packet_block_len(len), writer.get_ref().len() - start);
+        }
+
+        for comment in &["", "a", "abcd", "abcde"] {
+            let start = writer.get_ref().len();
+            writer
+                .write_packet(eth0, timestamp(), &IPV4_UDP_PACKET, 52, Some(comment))
+                .unwrap();
+            assert_eq!(
+                packet_block_len(52) + comment_len(comment),
+                writer.get_ref().len() - start
+            );
+        }
+    }
+
+    #[test]
+    fn core_capture_path() {
+        assert_eq!(
+            "capture-lcore3.pcapng",
+            core_path("capture", CoreId::new(3))
+        );
+    }
+}
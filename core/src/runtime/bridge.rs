@@ -0,0 +1,529 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::debug;
+use crate::net::MacAddr;
+use crate::packets::{Ethernet, Packet};
+use crate::runtime::LruMap;
+use crate::Mbuf;
+use anyhow::Result;
+use futures::{future, StreamExt};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio_executor::current_thread;
+use tokio_timer::Interval;
+
+/// The forwarding decision of the `Bridge` for a frame.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Verdict {
+    /// Forwards the frame to the port.
+    Unicast(usize),
+
+    /// Forwards a copy of the frame to each of the ports.
+    Flood(Vec<usize>),
+
+    /// Drops the frame.
+    Drop,
+}
+
+/// A snapshot of a MAC table entry.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BridgeEntry {
+    /// The MAC address.
+    pub mac: MacAddr,
+
+    /// The port the address is reachable through.
+    pub port: usize,
+
+    /// Whether the entry is configured statically.
+    pub is_static: bool,
+}
+
+/// A transparent MAC learning bridge.
+///
+/// The bridge learns the port each MAC address is reachable through from
+/// the source addresses of the frames it forwards. Frames to a learned
+/// address are forwarded to its port, and frames to an unknown, multicast
+/// or broadcast address are flooded to all the ports except the ingress
+/// port. Frames to an address on the ingress port are dropped.
+///
+/// Learned entries age out after `max_age` without traffic, which is
+/// enforced by [`age`] or [`spawn_aging`]. When the table is full, the
+/// least recently seen learned entry is evicted. Learned entries are kept in
+/// least recently seen order, so both aging and eviction only visit the
+/// entries they remove. Static entries never age out and aren't evicted.
+///
+/// The ports are numbered from `0` to `ports - 1`, typically the indices
+/// of the `PortQueue`s the bridge switches between.
+///
+/// # Example
+///
+/// ```
+/// let mut bridge = Bridge::new(queues.len(), 4096, Duration::from_secs(300));
+///
+/// for in_port in 0..queues.len() {
+///     for packet in queues[in_port].receive() {
+///         for (port, packet) in bridge.switch(packet, in_port)? {
+///             queues[port].transmit(vec![packet]);
+///         }
+///     }
+/// }
+/// ```
+///
+/// [`age`]: Bridge::age
+/// [`spawn_aging`]: Bridge::spawn_aging
+pub struct Bridge {
+    ports: usize,
+    statics: HashMap<MacAddr, usize>,
+    learned: LruMap<MacAddr, usize>,
+    capacity: usize,
+    max_age: Duration,
+    evicted: u64,
+    aged: u64,
+}
+
+impl Bridge {
+    /// Creates a new bridge between `ports` ports, with a MAC table that
+    /// holds up to `capacity` addresses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(ports: usize, capacity: usize, max_age: Duration) -> Self {
+        assert!(capacity > 0, "bridge table capacity must be positive.");
+
+        Bridge {
+            ports,
+            statics: HashMap::new(),
+            learned: LruMap::with_capacity(capacity),
+            capacity,
+            max_age,
+            evicted: 0,
+            aged: 0,
+        }
+    }
+
+    /// Returns the number of ports.
+    #[inline]
+    pub fn ports(&self) -> usize {
+        self.ports
+    }
+
+    /// Returns the number of addresses in the table.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.statics.len() + self.learned.len()
+    }
+
+    /// Returns whether the table is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.statics.is_empty() && self.learned.is_empty()
+    }
+
+    /// Returns the maximum number of addresses in the table.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the total number of entries evicted because the table is
+    /// full.
+    #[inline]
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Returns the total number of entries aged out.
+    #[inline]
+    pub fn aged(&self) -> u64 {
+        self.aged
+    }
+
+    /// Returns the port the address is reachable through.
+    pub fn lookup(&self, mac: MacAddr) -> Option<usize> {
+        self.statics
+            .get(&mac)
+            .or_else(|| self.learned.get(&mac))
+            .copied()
+    }
+
+    /// Returns an iterator over a snapshot of all the entries.
+    pub fn entries(&self) -> impl Iterator<Item = BridgeEntry> + '_ {
+        let statics = self.statics.iter().map(|(&mac, &port)| BridgeEntry {
+            mac,
+            port,
+            is_static: true,
+        });
+        let learned = self.learned.iter().map(|(&mac, &port)| BridgeEntry {
+            mac,
+            port,
+            is_static: false,
+        });
+        statics.chain(learned)
+    }
+
+    /// Adds a static entry that never ages out. Replaces the learned entry
+    /// for the address if there is one.
+    ///
+    /// Returns `false` if the table is full of static entries.
+    pub fn add_static(&mut self, mac: MacAddr, port: usize) -> bool {
+        let replaced = self.learned.remove(&mac).is_some();
+
+        if !replaced && !self.statics.contains_key(&mac) && !self.make_room() {
+            return false;
+        }
+
+        let _ = self.statics.insert(mac, port);
+        true
+    }
+
+    /// Removes the address from the table. Returns whether the address was
+    /// in the table.
+    pub fn remove(&mut self, mac: MacAddr) -> bool {
+        self.statics.remove(&mac).is_some() || self.learned.remove(&mac).is_some()
+    }
+
+    /// Learns the source address of the frame and decides where to forward
+    /// it.
+    pub fn forward(&mut self, ethernet: &Ethernet, in_port: usize) -> Verdict {
+        self.forward_at(ethernet.src(), ethernet.dst(), in_port, Instant::now())
+    }
+
+    fn forward_at(&mut self, src: MacAddr, dst: MacAddr, in_port: usize, now: Instant) -> Verdict {
+        self.learn(src, in_port, now);
+
+        if dst.is_multicast() {
+            return self.flood(in_port);
+        }
+
+        match self.lookup(dst) {
+            Some(port) if port == in_port => Verdict::Drop,
+            Some(port) => Verdict::Unicast(port),
+            None => self.flood(in_port),
+        }
+    }
+
+    /// Forwards the frame, and returns the frame paired with each of its
+    /// egress ports. Flooded frames are copied for every port but the last.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the packet is not an Ethernet frame, or if the
+    /// copies can't be allocated.
+    pub fn switch(&mut self, packet: Mbuf, in_port: usize) -> Result<Vec<(usize, Mbuf)>> {
+        let ethernet = packet.parse::<Ethernet>()?;
+        let verdict = self.forward(&ethernet, in_port);
+        let packet = ethernet.deparse();
+
+        match verdict {
+            Verdict::Unicast(port) => Ok(vec![(port, packet)]),
+            Verdict::Flood(ports) => {
+                let mut out = Vec::with_capacity(ports.len());
+
+                if let Some((&last, others)) = ports.split_last() {
+                    let data = packet.read_data_slice::<u8>(0, packet.data_len())?;
+                    let data = unsafe { data.as_ref() };
+                    for &port in others {
+                        out.push((port, Mbuf::from_bytes(data)?));
+                    }
+                    out.push((last, packet));
+                }
+
+                Ok(out)
+            }
+            Verdict::Drop => Ok(vec![]),
+        }
+    }
+
+    /// Removes the learned entries not seen for `max_age`, and returns the
+    /// removed addresses, least recently seen first.
+    pub fn age(&mut self) -> Vec<MacAddr> {
+        self.age_at(Instant::now())
+    }
+
+    fn age_at(&mut self, now: Instant) -> Vec<MacAddr> {
+        let mut aged = Vec::new();
+
+        while let Some(seen) = self.learned.oldest_seen() {
+            if now.saturating_duration_since(seen) < self.max_age {
+                break;
+            }
+
+            aged.extend(self.learned.pop_oldest().map(|(mac, _)| mac));
+        }
+
+        self.aged += aged.len() as u64;
+        aged
+    }
+
+    /// Spawns a task on the current core's executor that ages out the
+    /// entries every `period`. The task ends when the bridge is dropped.
+    pub fn spawn_aging(bridge: &Rc<RefCell<Self>>, period: Duration) {
+        let weak = Rc::downgrade(bridge);
+
+        let fut = Interval::new_interval(period)
+            .take_while(move |_| future::ready(weak.strong_count() > 0))
+            .for_each({
+                let weak = Rc::downgrade(bridge);
+                move |_| {
+                    if let Some(bridge) = weak.upgrade() {
+                        let aged = bridge.borrow_mut().age();
+                        if !aged.is_empty() {
+                            debug!(message = "aged out MAC addresses.", count = aged.len());
+                        }
+                    }
+                    future::ready(())
+                }
+            });
+
+        let _ = current_thread::spawn(fut);
+    }
+
+    /// Learns that the source address is reachable through the port.
+    fn learn(&mut self, src: MacAddr, port: usize, now: Instant) {
+        // group addresses are never a valid source.
+        if src.is_multicast() {
+            return;
+        }
+
+        if self.statics.contains_key(&src) {
+            return;
+        }
+
+        if let Some(learned) = self.learned.touch(&src, now) {
+            if *learned != port {
+                debug!(message = "MAC address moved.", mac = %src, from = *learned, to = port);
+                *learned = port;
+            }
+            return;
+        }
+
+        if self.make_room() {
+            let _ = self.learned.insert(src, port, now);
+        }
+    }
+
+    /// Evicts the least recently seen learned entry if the table is full.
+    /// Returns whether there is room for a new entry.
+    fn make_room(&mut self) -> bool {
+        if self.len() < self.capacity {
+            return true;
+        }
+
+        match self.learned.pop_oldest() {
+            Some(_) => {
+                self.evicted += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn flood(&self, in_port: usize) -> Verdict {
+        Verdict::Flood((0..self.ports).filter(|&port| port != in_port).collect())
+    }
+}
+
+impl fmt::Debug for Bridge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bridge")
+            .field("ports", &self.ports)
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .field("max_age", &self.max_age)
+            .field("evicted", &self.evicted)
+            .field("aged", &self.aged)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::IPV4_UDP_PACKET;
+
+    fn mac(last: u8) -> MacAddr {
+        MacAddr::new(0x02, 0, 0, 0, 0, last)
+    }
+
+    fn new_bridge() -> Bridge {
+        Bridge::new(3, 16, Duration::from_secs(300))
+    }
+
+    #[test]
+    fn learn_and_forward() {
+        let mut bridge = new_bridge();
+        let now = Instant::now();
+
+        // unknown destination is flooded.
+        assert_eq!(
+            Verdict::Flood(vec![1, 2]),
+            bridge.forward_at(mac(1), mac(2), 0, now)
+        );
+        assert_eq!(Some(0), bridge.lookup(mac(1)));
+
+        // the reply is unicast back to the learned port.
+        assert_eq!(
+            Verdict::Unicast(0),
+            bridge.forward_at(mac(2), mac(1), 2, now)
+        );
+        assert_eq!(
+            Verdict::Unicast(2),
+            bridge.forward_at(mac(1), mac(2), 0, now)
+        );
+    }
+
+    #[test]
+    fn flood_broadcast_and_multicast() {
+        let mut bridge = new_bridge();
+        let now = Instant::now();
+        let _ = bridge.forward_at(mac(1), mac(2), 0, now);
+
+        assert_eq!(
+            Verdict::Flood(vec![0, 2]),
+            bridge.forward_at(mac(2), MacAddr::BROADCAST, 1, now)
+        );
+        let multicast = MacAddr::new(0x01, 0x00, 0x5e, 0, 0, 1);
+        assert_eq!(
+            Verdict::Flood(vec![0, 1]),
+            bridge.forward_at(mac(3), multicast, 2, now)
+        );
+
+        // multicast source is not learned.
+        let _ = bridge.forward_at(multicast, mac(1), 2, now);
+        assert_eq!(None, bridge.lookup(multicast));
+    }
+
+    #[test]
+    fn move_mac_between_ports() {
+        let mut bridge = new_bridge();
+        let now = Instant::now();
+
+        let _ = bridge.forward_at(mac(1), mac(2), 0, now);
+        assert_eq!(Some(0), bridge.lookup(mac(1)));
+
+        let _ = bridge.forward_at(mac(1), mac(2), 1, now);
+        assert_eq!(Some(1), bridge.lookup(mac(1)));
+        assert_eq!(
+            Verdict::Unicast(1),
+            bridge.forward_at(mac(2), mac(1), 2, now)
+        );
+    }
+
+    #[test]
+    fn drop_hairpin() {
+        let mut bridge = new_bridge();
+        let now = Instant::now();
+
+        let _ = bridge.forward_at(mac(1), mac(2), 0, now);
+        let _ = bridge.forward_at(mac(2), mac(1), 0, now);
+
+        // both are on port 0.
+        assert_eq!(Verdict::Drop, bridge.forward_at(mac(1), mac(2), 0, now));
+    }
+
+    #[test]
+    fn age_out_entries() {
+        let mut bridge = new_bridge();
+        let now = Instant::now();
+
+        let _ = bridge.forward_at(mac(1), mac(9), 0, now);
+        let _ = bridge.forward_at(mac(2), mac(9), 1, now + Duration::from_secs(100));
+        assert!(bridge.add_static(mac(3), 2));
+
+        assert!(bridge.age_at(now + Duration::from_secs(299)).is_empty());
+        assert_eq!(vec![mac(1)], bridge.age_at(now + Duration::from_secs(300)));
+        assert_eq!(None, bridge.lookup(mac(1)));
+        assert_eq!(Some(1), bridge.lookup(mac(2)));
+
+        // static entries never age out.
+        let _ = bridge.age_at(now + Duration::from_secs(3600));
+        assert_eq!(1, bridge.len());
+        assert_eq!(Some(2), bridge.lookup(mac(3)));
+        assert_eq!(2, bridge.aged());
+    }
+
+    #[test]
+    fn static_entries() {
+        let mut bridge = new_bridge();
+        let now = Instant::now();
+
+        assert!(bridge.add_static(mac(1), 2));
+
+        // static entries don't move.
+        let _ = bridge.forward_at(mac(1), mac(2), 0, now);
+        assert_eq!(Some(2), bridge.lookup(mac(1)));
+
+        let entries = bridge.entries().collect::<Vec<_>>();
+        assert_eq!(
+            vec![BridgeEntry {
+                mac: mac(1),
+                port: 2,
+                is_static: true
+            }],
+            entries
+        );
+
+        assert!(bridge.remove(mac(1)));
+        assert!(!bridge.remove(mac(1)));
+    }
+
+    #[test]
+    fn evict_least_recently_seen() {
+        let mut bridge = Bridge::new(3, 2, Duration::from_secs(300));
+        let now = Instant::now();
+
+        let _ = bridge.forward_at(mac(1), mac(9), 0, now);
+        let _ = bridge.forward_at(mac(2), mac(9), 1, now + Duration::from_secs(1));
+        let _ = bridge.forward_at(mac(3), mac(9), 2, now + Duration::from_secs(2));
+
+        assert_eq!(2, bridge.len());
+        assert_eq!(None, bridge.lookup(mac(1)));
+        assert_eq!(1, bridge.evicted());
+
+        // a table full of static entries stops learning.
+        let mut bridge = Bridge::new(3, 1, Duration::from_secs(300));
+        assert!(bridge.add_static(mac(1), 0));
+        assert!(!bridge.add_static(mac(2), 1));
+        let _ = bridge.forward_at(mac(3), mac(9), 2, now);
+        assert_eq!(None, bridge.lookup(mac(3)));
+    }
+
+    #[capsule::test]
+    fn switch_flooded_frame() {
+        let mut bridge = new_bridge();
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+
+        let out = bridge.switch(packet, 1).unwrap();
+        assert_eq!(
+            vec![0, 2],
+            out.iter().map(|(port, _)| *port).collect::<Vec<_>>()
+        );
+        for (_, packet) in out.iter() {
+            assert_eq!(IPV4_UDP_PACKET.len(), packet.data_len());
+        }
+
+        // the fixture's source is now known on port 1.
+        let src = "00:00:00:00:00:02".parse().unwrap();
+        assert_eq!(Some(1), bridge.lookup(src));
+    }
+}
@@ -0,0 +1,194 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use anyhow::Result;
+use futures::channel::mpsc::{self, Receiver, Sender};
+use futures::SinkExt;
+use std::fmt;
+use thiserror::Error;
+
+/// Command channel errors.
+#[derive(Debug, Error)]
+pub(crate) enum CommandError {
+    /// The channel is full.
+    #[error("Command channel is full.")]
+    Full,
+
+    /// The receiving end is dropped.
+    #[error("Command channel is disconnected.")]
+    Disconnected,
+}
+
+/// Creates a bounded channel for sending commands from control tasks to
+/// the pipelines running on the data plane cores.
+///
+/// The control end is asynchronous, and [`send`] waits for room when the
+/// channel is full. The data plane end never blocks, and is meant to be
+/// checked once per pipeline run with [`try_recv`]. Commands are delivered
+/// in the order they are sent.
+///
+/// # Example
+///
+/// ```
+/// let (mut tx, mut rx) = command_channel(16);
+///
+/// runtime.spawn_control(async move {
+///     let _ = tx.send(Command::Disable).await;
+/// });
+///
+/// runtime.add_pipeline_to_core(1, move |qs| {
+///     let mut enabled = true;
+///     Poll::new(qs["eth1"].clone())
+///         .filter(move |_| {
+///             while let Some(cmd) = rx.try_recv() {
+///                 enabled = cmd == Command::Enable;
+///             }
+///             enabled
+///         })
+///         .send(qs["eth1"].clone())
+/// })?;
+/// ```
+///
+/// [`send`]: CommandTx::send
+/// [`try_recv`]: CommandRx::try_recv
+pub fn command_channel<T>(capacity: usize) -> (CommandTx<T>, CommandRx<T>) {
+    // the futures channel has one extra slot per sender.
+    let (sender, receiver) = mpsc::channel(capacity.saturating_sub(1));
+    (CommandTx { sender }, CommandRx { receiver })
+}
+
+/// The control end of a command channel.
+///
+/// Can be cloned to send commands from more than one control task.
+pub struct CommandTx<T> {
+    sender: Sender<T>,
+}
+
+impl<T> CommandTx<T> {
+    /// Sends a command, waiting for room if the channel is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CommandError::Disconnected` if the receiving end is dropped.
+    pub async fn send(&mut self, cmd: T) -> Result<()> {
+        self.sender
+            .send(cmd)
+            .await
+            .map_err(|_| CommandError::Disconnected.into())
+    }
+
+    /// Sends a command without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CommandError::Full` if the channel is full, or
+    /// `CommandError::Disconnected` if the receiving end is dropped.
+    pub fn try_send(&mut self, cmd: T) -> Result<()> {
+        self.sender.try_send(cmd).map_err(|err| {
+            if err.is_full() {
+                CommandError::Full.into()
+            } else {
+                CommandError::Disconnected.into()
+            }
+        })
+    }
+}
+
+impl<T> Clone for CommandTx<T> {
+    fn clone(&self) -> Self {
+        CommandTx {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for CommandTx<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandTx").finish()
+    }
+}
+
+/// The data plane end of a command channel.
+pub struct CommandRx<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> CommandRx<T> {
+    /// Receives the next command if there is one, without blocking.
+    ///
+    /// Returns `None` if the channel is empty, or all the control ends
+    /// are dropped.
+    #[inline]
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.receiver.try_next().ok().flatten()
+    }
+}
+
+impl<T> fmt::Debug for CommandRx<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandRx").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor;
+
+    #[test]
+    fn deliver_commands_in_order() {
+        let (mut tx, mut rx) = command_channel(4);
+        let mut tx2 = tx.clone();
+
+        executor::block_on(async {
+            for i in 0..3 {
+                tx.send(i).await.unwrap();
+            }
+            tx2.send(3).await.unwrap();
+        });
+
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            std::iter::from_fn(|| rx.try_recv()).collect::<Vec<_>>()
+        );
+        assert_eq!(None, rx.try_recv());
+    }
+
+    #[test]
+    fn try_send_to_full_channel() {
+        let (mut tx, mut rx) = command_channel(2);
+
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert!(tx.try_send(3).is_err());
+
+        assert_eq!(Some(1), rx.try_recv());
+        tx.try_send(3).unwrap();
+        assert_eq!(Some(2), rx.try_recv());
+        assert_eq!(Some(3), rx.try_recv());
+    }
+
+    #[test]
+    fn send_to_dropped_receiver() {
+        let (mut tx, rx) = command_channel(2);
+        drop(rx);
+
+        assert!(tx.try_send(1).is_err());
+        assert!(executor::block_on(tx.send(2)).is_err());
+    }
+}
@@ -17,6 +17,7 @@
 */
 
 use crate::dpdk::{CoreId, Mempool, MempoolMap, MEMPOOL};
+use crate::packets::ParseOptions;
 use crate::{debug, error, ffi, info};
 use anyhow::Result;
 use futures::Future;
@@ -180,6 +181,7 @@ pub(crate) struct CoreMapBuilder<'a> {
     cores: HashSet<CoreId>,
     master_core: CoreId,
     mempools: MempoolMap<'a>,
+    parse_options: ParseOptions,
 }
 
 impl<'a> CoreMapBuilder<'a> {
@@ -189,6 +191,7 @@ impl<'a> CoreMapBuilder<'a> {
             cores: Default::default(),
             master_core: CoreId::new(0),
             mempools: Default::default(),
+            parse_options: Default::default(),
         }
     }
 
@@ -207,6 +210,11 @@ impl<'a> CoreMapBuilder<'a> {
         self
     }
 
+    pub(crate) fn parse_options(&mut self, parse_options: ParseOptions) -> &mut Self {
+        self.parse_options = parse_options;
+        self
+    }
+
     pub(crate) fn mempools(&'a mut self, mempools: &'a mut [Mempool]) -> &'a mut Self {
         self.mempools = MempoolMap::new(mempools);
         self
@@ -221,7 +229,8 @@ impl<'a> CoreMapBuilder<'a> {
         let socket_id = self.master_core.socket_id();
         let mempool = self.mempools.get_raw(socket_id)?;
 
-        let (master_thread, core_executor) = init_master_core(self.master_core, mempool)?;
+        let (master_thread, core_executor) =
+            init_master_core(self.master_core, mempool, self.parse_options)?;
 
         // adds the master core to the map. tasks can be spawned onto the
         // master core like any other cores.
@@ -241,6 +250,7 @@ impl<'a> CoreMapBuilder<'a> {
             let socket_id = core_id.socket_id();
             let mempool = self.mempools.get_raw(socket_id)?;
             let ptr = SendablePtr(mempool);
+            let parse_options = self.parse_options;
 
             // creates a synchronous channel so we can retrieve the executor for
             // the background core.
@@ -253,7 +263,7 @@ impl<'a> CoreMapBuilder<'a> {
                 .spawn(move || {
                     debug!("spawned background thread {:?}.", thread::current().id());
 
-                    match init_background_core(core_id, ptr.0) {
+                    match init_background_core(core_id, ptr.0, parse_options) {
                         Ok((mut thread, park, shutdown, executor)) => {
                             info!("initialized thread on {:?}.", core_id);
 
@@ -302,6 +312,7 @@ impl<'a> CoreMapBuilder<'a> {
 fn init_master_core(
     id: CoreId,
     mempool: *mut ffi::rte_mempool,
+    parse_options: ParseOptions,
 ) -> Result<(MasterExecutor, CoreExecutor)> {
     // affinitize the running thread to this core.
     id.set_thread_affinity()?;
@@ -309,6 +320,9 @@ fn init_master_core(
     // sets the mempool
     MEMPOOL.with(|tls| tls.set(mempool));
 
+    // sets the options of the packets parsed on this core.
+    parse_options.set();
+
     // starts a reactor so we can receive signals on the master core.
     let reactor = Reactor::new()?;
     let reactor_handle = reactor.handle();
@@ -342,6 +356,7 @@ fn init_master_core(
 fn init_background_core(
     id: CoreId,
     mempool: *mut ffi::rte_mempool,
+    parse_options: ParseOptions,
 ) -> Result<(
     CurrentThread<Timer<ParkThread>>,
     Park,
@@ -354,6 +369,9 @@ fn init_background_core(
     // sets the mempool
     MEMPOOL.with(|tls| tls.set(mempool));
 
+    // sets the options of the packets parsed on this core.
+    parse_options.set();
+
     // starts a per-core timer so we can schedule timed tasks.
     let park = ParkThread::new();
     let timer = Timer::new(park);
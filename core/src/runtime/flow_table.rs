@@ -0,0 +1,503 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::debug;
+use crate::dpdk::CoreId;
+use crate::packets::ip::{IpPacket, ProtocolNumber, ProtocolNumbers};
+use crate::packets::{Packet, Tcp, Udp};
+use crate::runtime::LruMap;
+use futures::{future, StreamExt};
+use std::cell::RefCell;
+use std::fmt;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio_executor::current_thread;
+use tokio_timer::Interval;
+
+/// The 5-tuple that identifies a transport layer flow.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FlowKey {
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    src_port: u16,
+    dst_port: u16,
+    protocol: u8,
+}
+
+impl FlowKey {
+    /// Creates a new flow key.
+    pub fn new(
+        src_ip: IpAddr,
+        src_port: u16,
+        dst_ip: IpAddr,
+        dst_port: u16,
+        protocol: ProtocolNumber,
+    ) -> Self {
+        FlowKey {
+            src_ip,
+            dst_ip,
+            src_port,
+            dst_port,
+            protocol: protocol.0,
+        }
+    }
+
+    /// Returns the source IP address.
+    #[inline]
+    pub fn src_ip(&self) -> IpAddr {
+        self.src_ip
+    }
+
+    /// Returns the destination IP address.
+    #[inline]
+    pub fn dst_ip(&self) -> IpAddr {
+        self.dst_ip
+    }
+
+    /// Returns the source port.
+    #[inline]
+    pub fn src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    /// Returns the destination port.
+    #[inline]
+    pub fn dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    /// Returns the transport protocol.
+    #[inline]
+    pub fn protocol(&self) -> ProtocolNumber {
+        ProtocolNumber::new(self.protocol)
+    }
+
+    /// Returns the key of the reverse direction of the flow.
+    #[inline]
+    pub fn reversed(&self) -> Self {
+        FlowKey {
+            src_ip: self.dst_ip,
+            dst_ip: self.src_ip,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+            protocol: self.protocol,
+        }
+    }
+
+    /// Returns the canonical key of the flow, which is the same for both
+    /// directions of the flow.
+    ///
+    /// Use the canonical key to track the two directions of a connection
+    /// as one entry.
+    #[inline]
+    pub fn canonical(&self) -> Self {
+        if (self.src_ip, self.src_port) <= (self.dst_ip, self.dst_port) {
+            *self
+        } else {
+            self.reversed()
+        }
+    }
+}
+
+impl<E: IpPacket> From<&Tcp<E>> for FlowKey {
+    fn from(tcp: &Tcp<E>) -> Self {
+        let ip = tcp.envelope();
+        FlowKey::new(
+            ip.src(),
+            tcp.src_port(),
+            ip.dst(),
+            tcp.dst_port(),
+            ProtocolNumbers::Tcp,
+        )
+    }
+}
+
+impl<E: IpPacket> From<&Udp<E>> for FlowKey {
+    fn from(udp: &Udp<E>) -> Self {
+        let ip = udp.envelope();
+        FlowKey::new(
+            ip.src(),
+            udp.src_port(),
+            ip.dst(),
+            udp.dst_port(),
+            ProtocolNumbers::Udp,
+        )
+    }
+}
+
+/// Returns the core that owns the flow with the RSS hash.
+///
+/// Use to steer packets to the core that holds the flow's state when
+/// the flow tables are sharded across `cores`. Returns `None` if `cores`
+/// is empty.
+#[inline]
+pub fn owner_core(rss_hash: u32, cores: &[CoreId]) -> Option<CoreId> {
+    if cores.is_empty() {
+        None
+    } else {
+        Some(cores[rss_hash as usize % cores.len()])
+    }
+}
+
+/// A bounded flow table keyed by the 5-tuple, with idle timeout expiry.
+///
+/// The table is meant to be owned by a single core, so there is no
+/// locking. To shard the flows across cores, create one table per core
+/// and steer the packets with [`owner_core`].
+///
+/// Entries are kept in least recently used order. When the table is full,
+/// inserting a new flow evicts the least recently used one. Entries not
+/// accessed within the idle timeout are removed by [`expire`].
+///
+/// # Example
+///
+/// ```
+/// let flows = Rc::new(RefCell::new(FlowTable::new(65536, Duration::from_secs(30))));
+/// FlowTable::spawn_expiry(&flows, Duration::from_secs(1));
+///
+/// Poll::new(q.clone())
+///     .map(|packet| {
+///         let tcp = packet.parse::<Ethernet>()?.parse::<Ipv4>()?.parse::<Tcp4>()?;
+///         let key = FlowKey::from(&tcp).canonical();
+///         *flows.borrow_mut().get_or_insert_with(key, || 0) += 1;
+///         Ok(tcp)
+///     })
+///     .send(q)
+/// ```
+///
+/// [`owner_core`]: owner_core
+/// [`expire`]: FlowTable::expire
+pub struct FlowTable<V> {
+    lru: LruMap<FlowKey, V>,
+    capacity: usize,
+    idle_timeout: Duration,
+    evicted: u64,
+    expired: u64,
+}
+
+impl<V> FlowTable<V> {
+    /// Creates a new flow table that holds up to `capacity` flows.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize, idle_timeout: Duration) -> Self {
+        assert!(capacity > 0, "flow table capacity must be positive.");
+
+        FlowTable {
+            lru: LruMap::with_capacity(capacity),
+            capacity,
+            idle_timeout,
+            evicted: 0,
+            expired: 0,
+        }
+    }
+
+    /// Returns the number of flows in the table.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.lru.len()
+    }
+
+    /// Returns whether the table is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.lru.is_empty()
+    }
+
+    /// Returns the maximum number of flows the table holds.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the idle timeout.
+    #[inline]
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    /// Returns the total number of flows evicted to make room for new ones.
+    #[inline]
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Returns the total number of flows removed for being idle.
+    #[inline]
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+
+    fn insert_at(&mut self, key: FlowKey, value: V, now: Instant) -> Option<V> {
+        if !self.lru.contains_key(&key) && self.lru.len() >= self.capacity {
+            let _ = self.lru.pop_oldest();
+            self.evicted += 1;
+        }
+
+        self.lru.insert(key, value, now)
+    }
+
+    /// Inserts the flow into the table, and marks it as the most recently
+    /// used.
+    ///
+    /// If the table is full, the least recently used flow is evicted. If the
+    /// table already has the flow, the value is replaced and the old value
+    /// is returned.
+    pub fn insert(&mut self, key: FlowKey, value: V) -> Option<V> {
+        self.insert_at(key, value, Instant::now())
+    }
+
+    /// Returns a reference to the value of the flow without marking it as
+    /// used.
+    #[inline]
+    pub fn get(&self, key: &FlowKey) -> Option<&V> {
+        self.lru.get(key)
+    }
+
+    /// Returns a mutable reference to the value of the flow, and marks it
+    /// as the most recently used.
+    pub fn get_mut(&mut self, key: &FlowKey) -> Option<&mut V> {
+        self.lru.touch(key, Instant::now())
+    }
+
+    /// Returns a mutable reference to the value of the flow, inserting the
+    /// value returned by `f` if the table doesn't have the flow. The flow
+    /// is marked as the most recently used.
+    pub fn get_or_insert_with<F>(&mut self, key: FlowKey, f: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        let now = Instant::now();
+        if !self.lru.contains_key(&key) {
+            let _ = self.insert_at(key, f(), now);
+        }
+
+        self.lru.touch(&key, now).unwrap()
+    }
+
+    /// Returns whether the table has the flow.
+    #[inline]
+    pub fn contains_key(&self, key: &FlowKey) -> bool {
+        self.lru.contains_key(key)
+    }
+
+    /// Removes the flow from the table, and returns its value.
+    pub fn remove(&mut self, key: &FlowKey) -> Option<V> {
+        self.lru.remove(key)
+    }
+
+    fn expire_at(&mut self, now: Instant) -> Vec<(FlowKey, V)> {
+        let mut expired = Vec::new();
+
+        while let Some(last_seen) = self.lru.oldest_seen() {
+            if now.saturating_duration_since(last_seen) < self.idle_timeout {
+                break;
+            }
+
+            expired.extend(self.lru.pop_oldest());
+        }
+
+        self.expired += expired.len() as u64;
+        expired
+    }
+
+    /// Removes the flows that are idle for longer than the idle timeout,
+    /// and returns them, least recently used first.
+    pub fn expire(&mut self) -> Vec<(FlowKey, V)> {
+        self.expire_at(Instant::now())
+    }
+
+    /// Returns an iterator over the flows in the table, in no particular
+    /// order.
+    ///
+    /// Use to export the flows to the control plane.
+    pub fn iter(&self) -> impl Iterator<Item = (&FlowKey, &V)> {
+        self.lru.iter()
+    }
+}
+
+impl<V: 'static> FlowTable<V> {
+    /// Spawns a task that expires the idle flows of the table every
+    /// `period`. The task ends when the table is dropped.
+    ///
+    /// Must be called from the core that owns the table, for example in
+    /// the pipeline installer, so the task runs on the same core.
+    pub fn spawn_expiry(table: &Rc<RefCell<Self>>, period: Duration) {
+        let weak = Rc::downgrade(table);
+
+        let fut = Interval::new_interval(period)
+            .take_while(move |_| future::ready(weak.strong_count() > 0))
+            .for_each({
+                let weak = Rc::downgrade(table);
+                move |_| {
+                    if let Some(table) = weak.upgrade() {
+                        let expired = table.borrow_mut().expire();
+                        if !expired.is_empty() {
+                            debug!(message = "expired idle flows.", count = expired.len());
+                        }
+                    }
+                    future::ready(())
+                }
+            });
+
+        current_thread::spawn(fut);
+    }
+}
+
+impl<V> fmt::Debug for FlowTable<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlowTable")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("evicted", &self.evicted)
+            .field("expired", &self.expired)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::Ethernet;
+    use crate::testils::byte_arrays::{IPV4_TCP_PACKET, IPV4_UDP_PACKET};
+    use crate::Mbuf;
+    use std::net::Ipv4Addr;
+
+    fn key(src_port: u16, dst_port: u16) -> FlowKey {
+        FlowKey::new(
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+            src_port,
+            Ipv4Addr::new(10, 0, 0, 2).into(),
+            dst_port,
+            ProtocolNumbers::Tcp,
+        )
+    }
+
+    #[capsule::test]
+    fn flow_key_from_packets() {
+        let packet = Mbuf::from_bytes(&IPV4_TCP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let tcp = ipv4.parse::<Tcp<Ipv4>>().unwrap();
+        let key = FlowKey::from(&tcp);
+
+        assert_eq!(tcp.envelope().src(), key.src_ip());
+        assert_eq!(tcp.envelope().dst(), key.dst_ip());
+        assert_eq!(tcp.src_port(), key.src_port());
+        assert_eq!(tcp.dst_port(), key.dst_port());
+        assert_eq!(ProtocolNumbers::Tcp, key.protocol());
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let udp = ipv4.parse::<Udp<Ipv4>>().unwrap();
+        let key = FlowKey::from(&udp);
+
+        assert_eq!(udp.src_port(), key.src_port());
+        assert_eq!(udp.dst_port(), key.dst_port());
+        assert_eq!(ProtocolNumbers::Udp, key.protocol());
+    }
+
+    #[test]
+    fn canonical_key_symmetry() {
+        let forward = key(1234, 80);
+        let reverse = forward.reversed();
+
+        assert_ne!(forward, reverse);
+        assert_eq!(forward, reverse.reversed());
+        assert_eq!(forward.canonical(), reverse.canonical());
+        assert_ne!(key(1234, 80).canonical(), key(1235, 80).canonical());
+    }
+
+    #[test]
+    fn insert_and_lookup() {
+        let mut table = FlowTable::new(4, Duration::from_secs(10));
+
+        assert_eq!(None, table.insert(key(1, 80), 1));
+        assert_eq!(Some(1), table.insert(key(1, 80), 2));
+        assert_eq!(Some(&2), table.get(&key(1, 80)));
+        assert_eq!(None, table.get(&key(2, 80)));
+
+        *table.get_or_insert_with(key(2, 80), || 0) += 5;
+        *table.get_or_insert_with(key(2, 80), || 0) += 5;
+        assert_eq!(Some(&10), table.get(&key(2, 80)));
+        assert_eq!(2, table.len());
+
+        assert_eq!(Some(2), table.remove(&key(1, 80)));
+        assert!(!table.contains_key(&key(1, 80)));
+        assert_eq!(1, table.iter().count());
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let mut table = FlowTable::new(2, Duration::from_secs(10));
+        let _ = table.insert(key(1, 80), 1);
+        let _ = table.insert(key(2, 80), 2);
+
+        // touches the first flow, so the second one is the oldest.
+        *table.get_mut(&key(1, 80)).unwrap() += 10;
+
+        let _ = table.insert(key(3, 80), 3);
+        assert_eq!(2, table.len());
+        assert_eq!(1, table.evicted());
+        assert!(table.contains_key(&key(1, 80)));
+        assert!(!table.contains_key(&key(2, 80)));
+        assert!(table.contains_key(&key(3, 80)));
+
+        // keeps evicting under pressure.
+        for port in 4..100 {
+            let _ = table.insert(key(port, 80), port);
+        }
+        assert_eq!(2, table.len());
+        assert_eq!(97, table.evicted());
+        assert!(table.contains_key(&key(98, 80)));
+        assert!(table.contains_key(&key(99, 80)));
+    }
+
+    #[test]
+    fn expire_idle_flows() {
+        let timeout = Duration::from_secs(10);
+        let mut table = FlowTable::new(8, timeout);
+        let _ = table.insert(key(1, 80), 1);
+        let _ = table.insert(key(2, 80), 2);
+
+        assert!(table.expire_at(Instant::now()).is_empty());
+
+        let expired = table.expire_at(Instant::now() + timeout);
+        assert_eq!(vec![(key(1, 80), 1), (key(2, 80), 2)], expired);
+        assert!(table.is_empty());
+        assert_eq!(2, table.expired());
+
+        // the freed slots are reused.
+        let _ = table.insert(key(3, 80), 3);
+        assert_eq!(Some(&3), table.get(&key(3, 80)));
+    }
+
+    #[test]
+    fn owner_core_routing() {
+        let cores = [CoreId::new(1), CoreId::new(2), CoreId::new(3)];
+
+        assert_eq!(None, owner_core(7, &[]));
+        assert_eq!(Some(CoreId::new(2)), owner_core(7, &cores));
+        assert_eq!(owner_core(42, &cores), owner_core(42, &cores));
+    }
+}
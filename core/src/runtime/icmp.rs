@@ -0,0 +1,730 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::batch::{Either, Stage};
+use crate::debug;
+use crate::packets::icmp::v4::{self, Icmpv4, Icmpv4Packet, Icmpv4Types};
+use crate::packets::icmp::v6::{self, Icmpv6, Icmpv6Packet, Icmpv6Types};
+use crate::packets::ip::v4::Ipv4;
+use crate::packets::ip::v6::{Ipv6, Ipv6Packet, IPV6_MIN_MTU};
+use crate::packets::ip::{ProtocolNumbers, DEFAULT_IP_TTL};
+use crate::packets::{EtherTypes, Ethernet, Packet};
+use crate::Mbuf;
+use anyhow::Result;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Instant;
+
+/// The maximum length of an ICMPv4 error, from the start of the IPv4
+/// header. See [IETF RFC 1812 §4.3.2.3].
+///
+/// [IETF RFC 1812 §4.3.2.3]: https://tools.ietf.org/html/rfc1812#section-4.3.2.3
+const ICMPV4_ERROR_MAX_LEN: usize = 576;
+
+/// The length of the IPv4 and ICMPv4 headers of an ICMPv4 error.
+const ICMPV4_ERROR_HEADERS_LEN: usize = 28;
+
+/// The length of the IPv6 and ICMPv6 headers of an ICMPv6 error.
+const ICMPV6_ERROR_HEADERS_LEN: usize = 48;
+
+/// The rate limits and the header values of an `IcmpResponder`.
+#[derive(Clone, Copy, Debug)]
+pub struct IcmpConfig {
+    /// The number of echo replies sent per second on average. The default
+    /// is 100.
+    pub echo_rate: u32,
+
+    /// The number of error messages sent per second on average. The default
+    /// is 100.
+    pub error_rate: u32,
+
+    /// The number of messages of each kind that can be sent back to back
+    /// above the average rate. The default is 10.
+    pub burst: u32,
+
+    /// The TTL, or the hop limit, of the generated messages. The default
+    /// is 64.
+    pub hop_limit: u8,
+}
+
+impl Default for IcmpConfig {
+    fn default() -> Self {
+        IcmpConfig {
+            echo_rate: 100,
+            error_rate: 100,
+            burst: 10,
+            hop_limit: DEFAULT_IP_TTL,
+        }
+    }
+}
+
+/// What the `IcmpResponder` does with a packet not addressed to it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IcmpVerdict {
+    /// Passes the packet through untouched.
+    Forward,
+
+    /// Replaces the packet with a time exceeded message.
+    TimeExceeded,
+
+    /// Replaces the packet with a host unreachable message.
+    HostUnreachable,
+
+    /// Replaces the packet with a port unreachable message.
+    PortUnreachable,
+}
+
+/// A token bucket that refills at a fixed rate.
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, burst: u32) -> Self {
+        TokenBucket {
+            rate: rate as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            updated: None,
+        }
+    }
+
+    /// Takes a token if there's one available at `now`.
+    fn take_at(&mut self, now: Instant) -> bool {
+        if let Some(updated) = self.updated {
+            let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        }
+        self.updated = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A chain stage that answers pings and generates ICMP errors on behalf of
+/// the application.
+///
+/// Echo requests addressed to one of the responder's IPv4 or IPv6 addresses
+/// are replaced with echo replies. Every other packet is handed to the
+/// verdict closure, which decides whether the packet is forwarded, or
+/// replaced with an ICMP error sent back to its source. Without a closure,
+/// all other packets are forwarded.
+///
+/// The replies and the errors go out the pipeline's TX with the Ethernet
+/// addresses swapped. Both are rate limited separately, as recommended by
+/// [IETF RFC 4443]. No message is ever sent to a multicast, broadcast or
+/// unspecified source, and no error is sent in response to an ICMP error,
+/// to a non-initial fragment, or to a multicast or broadcast destination.
+/// Packets that are not answered for any of those reasons are dropped.
+///
+/// # Example
+///
+/// ```
+/// let responder = IcmpResponder::new(IcmpConfig::default())
+///     .with_ipv4(local_v4)
+///     .with_verdict(move |packet| {
+///         if is_expired(packet) {
+///             IcmpVerdict::TimeExceeded
+///         } else {
+///             IcmpVerdict::Forward
+///         }
+///     });
+///
+/// let chain = Chain::new(&switches)
+///     .stage("icmp", responder)
+///     .stage("route", route);
+/// ```
+///
+/// [IETF RFC 4443]: https://tools.ietf.org/html/rfc4443#section-2.4
+pub struct IcmpResponder {
+    config: IcmpConfig,
+    ipv4: Vec<Ipv4Addr>,
+    ipv6: Vec<Ipv6Addr>,
+    verdict: Option<Box<dyn FnMut(&Mbuf) -> IcmpVerdict>>,
+    echo_bucket: TokenBucket,
+    error_bucket: TokenBucket,
+    replied: u64,
+    errors: u64,
+    suppressed: u64,
+    rate_limited: u64,
+}
+
+impl IcmpResponder {
+    /// Creates a new responder without any addresses.
+    pub fn new(config: IcmpConfig) -> Self {
+        IcmpResponder {
+            config,
+            ipv4: vec![],
+            ipv6: vec![],
+            verdict: None,
+            echo_bucket: TokenBucket::new(config.echo_rate, config.burst),
+            error_bucket: TokenBucket::new(config.error_rate, config.burst),
+            replied: 0,
+            errors: 0,
+            suppressed: 0,
+            rate_limited: 0,
+        }
+    }
+
+    /// Adds an IPv4 address to answer echo requests for. The first address
+    /// is also the source of the ICMPv4 errors.
+    pub fn with_ipv4(mut self, addr: Ipv4Addr) -> Self {
+        self.ipv4.push(addr);
+        self
+    }
+
+    /// Adds an IPv6 address to answer echo requests for. The first address
+    /// is also the source of the ICMPv6 errors.
+    pub fn with_ipv6(mut self, addr: Ipv6Addr) -> Self {
+        self.ipv6.push(addr);
+        self
+    }
+
+    /// Sets the closure that decides what to do with the packets not
+    /// addressed to the responder.
+    pub fn with_verdict<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&Mbuf) -> IcmpVerdict + 'static,
+    {
+        self.verdict = Some(Box::new(f));
+        self
+    }
+
+    /// Returns the configuration.
+    pub fn config(&self) -> &IcmpConfig {
+        &self.config
+    }
+
+    /// Returns the number of echo replies sent.
+    pub fn replied(&self) -> u64 {
+        self.replied
+    }
+
+    /// Returns the number of error messages sent.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Returns the number of packets not answered because of the
+    /// suppression rules.
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed
+    }
+
+    /// Returns the number of packets not answered because of the rate
+    /// limits.
+    pub fn rate_limited(&self) -> u64 {
+        self.rate_limited
+    }
+
+    fn process_at(&mut self, packet: Mbuf, now: Instant) -> Result<Either<Mbuf>> {
+        let ether_type = packet.peek::<Ethernet>().map(|e| e.ether_type());
+        let ether_type = match ether_type {
+            Ok(ether_type) => ether_type,
+            Err(_) => return Ok(Either::Keep(packet)),
+        };
+
+        let is_echo = match ether_type {
+            EtherTypes::Ipv4 => self.is_echo_v4(&packet),
+            EtherTypes::Ipv6 => self.is_echo_v6(&packet),
+            _ => false,
+        };
+        if is_echo {
+            return self.reply_at(packet, ether_type == EtherTypes::Ipv4, now);
+        }
+
+        let verdict = match self.verdict {
+            Some(ref mut f) => f(&packet),
+            None => IcmpVerdict::Forward,
+        };
+        match verdict {
+            IcmpVerdict::Forward => Ok(Either::Keep(packet)),
+            _ => self.error_at(packet, verdict, now),
+        }
+    }
+
+    /// Returns whether the packet is an ICMPv4 echo request for us.
+    fn is_echo_v4(&self, packet: &Mbuf) -> bool {
+        let ethernet = match packet.peek::<Ethernet>() {
+            Ok(ethernet) => ethernet,
+            Err(_) => return false,
+        };
+        let ipv4 = match ethernet.peek::<Ipv4>() {
+            Ok(ipv4) => ipv4,
+            Err(_) => return false,
+        };
+
+        self.ipv4.contains(&ipv4.dst())
+            && ipv4.protocol() == ProtocolNumbers::Icmpv4
+            && ipv4
+                .peek::<Icmpv4>()
+                .map(|icmp| icmp.msg_type() == Icmpv4Types::EchoRequest)
+                .unwrap_or(false)
+    }
+
+    /// Returns whether the packet is an ICMPv6 echo request for us.
+    fn is_echo_v6(&self, packet: &Mbuf) -> bool {
+        let ethernet = match packet.peek::<Ethernet>() {
+            Ok(ethernet) => ethernet,
+            Err(_) => return false,
+        };
+        let ipv6 = match ethernet.peek::<Ipv6>() {
+            Ok(ipv6) => ipv6,
+            Err(_) => return false,
+        };
+
+        self.ipv6.contains(&ipv6.dst())
+            && ipv6.next_header() == ProtocolNumbers::Icmpv6
+            && ipv6
+                .peek::<Icmpv6<Ipv6>>()
+                .map(|icmp| icmp.msg_type() == Icmpv6Types::EchoRequest)
+                .unwrap_or(false)
+    }
+
+    fn reply_at(&mut self, packet: Mbuf, is_v4: bool, now: Instant) -> Result<Either<Mbuf>> {
+        let unicast_src = {
+            let ethernet = packet.peek::<Ethernet>()?;
+            if is_v4 {
+                let src = ethernet.peek::<Ipv4>()?.src();
+                !(src.is_broadcast() || src.is_multicast() || src.is_unspecified())
+            } else {
+                let src = ethernet.peek::<Ipv6>()?.src();
+                !(src.is_multicast() || src.is_unspecified())
+            }
+        };
+
+        if !unicast_src {
+            self.suppressed += 1;
+            return Ok(Either::Drop(packet));
+        }
+        if !self.echo_bucket.take_at(now) {
+            self.rate_limited += 1;
+            return Ok(Either::Drop(packet));
+        }
+
+        let reply = if is_v4 {
+            self.echo_reply_v4(&packet)?
+        } else {
+            self.echo_reply_v6(&packet)?
+        };
+        self.replied += 1;
+        Ok(Either::Keep(reply))
+    }
+
+    fn echo_reply_v4(&self, packet: &Mbuf) -> Result<Mbuf> {
+        let ethernet = packet.peek::<Ethernet>()?;
+        let mut reply = Mbuf::new()?.push::<Ethernet>()?;
+        reply.set_src(ethernet.dst());
+        reply.set_dst(ethernet.src());
+
+        let ipv4 = ethernet.peek::<Ipv4>()?;
+        let mut reply = reply.push::<Ipv4>()?;
+        reply.set_src(ipv4.dst());
+        reply.set_dst(ipv4.src());
+        reply.set_ttl(self.config.hop_limit);
+
+        let request = ipv4.peek::<v4::EchoRequest>()?;
+        let mut reply = reply.push::<v4::EchoReply>()?;
+        reply.set_identifier(request.identifier());
+        reply.set_seq_no(request.seq_no());
+        reply.set_data(request.data())?;
+        reply.reconcile_all();
+
+        debug!(?reply);
+        Ok(reply.reset())
+    }
+
+    fn echo_reply_v6(&self, packet: &Mbuf) -> Result<Mbuf> {
+        let ethernet = packet.peek::<Ethernet>()?;
+        let mut reply = Mbuf::new()?.push::<Ethernet>()?;
+        reply.set_src(ethernet.dst());
+        reply.set_dst(ethernet.src());
+
+        let ipv6 = ethernet.peek::<Ipv6>()?;
+        let mut reply = reply.push::<Ipv6>()?;
+        reply.set_src(ipv6.dst());
+        reply.set_dst(ipv6.src());
+        reply.set_hop_limit(self.config.hop_limit);
+
+        let request = ipv6.peek::<v6::EchoRequest<Ipv6>>()?;
+        let mut reply = reply.push::<v6::EchoReply<Ipv6>>()?;
+        reply.set_identifier(request.identifier());
+        reply.set_seq_no(request.seq_no());
+        reply.set_data(request.data())?;
+        reply.reconcile_all();
+
+        debug!(?reply);
+        Ok(reply.reset())
+    }
+
+    fn error_at(
+        &mut self,
+        packet: Mbuf,
+        verdict: IcmpVerdict,
+        now: Instant,
+    ) -> Result<Either<Mbuf>> {
+        let (is_v4, allowed) = {
+            let ethernet = packet.peek::<Ethernet>()?;
+            let l2_group = ethernet.dst().is_broadcast() || ethernet.dst().is_multicast();
+            let allowed = !l2_group
+                && match ethernet.ether_type() {
+                    EtherTypes::Ipv4 => self.can_send_error_v4(&*ethernet.peek::<Ipv4>()?),
+                    EtherTypes::Ipv6 => self.can_send_error_v6(&*ethernet.peek::<Ipv6>()?),
+                    _ => false,
+                };
+            (ethernet.ether_type() == EtherTypes::Ipv4, allowed)
+        };
+
+        if !allowed {
+            self.suppressed += 1;
+            return Ok(Either::Drop(packet));
+        }
+        if !self.error_bucket.take_at(now) {
+            self.rate_limited += 1;
+            return Ok(Either::Drop(packet));
+        }
+
+        let error = match (is_v4, verdict) {
+            (true, IcmpVerdict::TimeExceeded) => self.error_v4::<v4::TimeExceeded>(packet, 0)?,
+            (true, IcmpVerdict::HostUnreachable) => {
+                self.error_v4::<v4::DestinationUnreachable>(packet, 1)?
+            }
+            (true, _) => self.error_v4::<v4::DestinationUnreachable>(packet, 3)?,
+            (false, IcmpVerdict::TimeExceeded) => {
+                self.error_v6::<v6::TimeExceeded<Ipv6>>(packet, 0)?
+            }
+            (false, IcmpVerdict::HostUnreachable) => {
+                self.error_v6::<v6::DestinationUnreachable<Ipv6>>(packet, 3)?
+            }
+            (false, _) => self.error_v6::<v6::DestinationUnreachable<Ipv6>>(packet, 4)?,
+        };
+        self.errors += 1;
+        Ok(Either::Keep(error))
+    }
+
+    /// Returns whether an ICMPv4 error can be sent in response to the
+    /// packet.
+    fn can_send_error_v4(&self, ipv4: &Ipv4) -> bool {
+        let (src, dst) = (ipv4.src(), ipv4.dst());
+        let is_error = ipv4.protocol() == ProtocolNumbers::Icmpv4
+            && ipv4
+                .peek::<Icmpv4>()
+                .map(|icmp| matches!(icmp.msg_type().0, 3 | 4 | 5 | 11 | 12))
+                .unwrap_or(true);
+
+        !self.ipv4.is_empty()
+            && !(src.is_broadcast() || src.is_multicast() || src.is_unspecified())
+            && !(dst.is_broadcast() || dst.is_multicast())
+            && ipv4.fragment_offset() == 0
+            && !is_error
+    }
+
+    /// Returns whether an ICMPv6 error can be sent in response to the
+    /// packet.
+    fn can_send_error_v6(&self, ipv6: &Ipv6) -> bool {
+        let (src, dst) = (ipv6.src(), ipv6.dst());
+        let is_error = ipv6.next_header() == ProtocolNumbers::Icmpv6
+            && ipv6
+                .peek::<Icmpv6<Ipv6>>()
+                .map(|icmp| icmp.msg_type().0 < 128)
+                .unwrap_or(true);
+
+        !self.ipv6.is_empty()
+            && !(src.is_multicast() || src.is_unspecified())
+            && !dst.is_multicast()
+            && !is_error
+    }
+
+    /// Turns the packet into an ICMPv4 error carrying as much of the
+    /// original IPv4 packet as fits in 576 bytes.
+    fn error_v4<T>(&self, packet: Mbuf, code: u8) -> Result<Mbuf>
+    where
+        T: Packet<Envelope = Ipv4> + Icmpv4Packet + fmt::Debug,
+    {
+        let mut ethernet = packet.parse::<Ethernet>()?;
+        let src = ethernet.peek::<Ipv4>()?.src();
+        ethernet.swap_addresses();
+        truncate_invoking(
+            &mut ethernet,
+            ICMPV4_ERROR_MAX_LEN - ICMPV4_ERROR_HEADERS_LEN,
+        )?;
+
+        let mut ipv4 = ethernet.push::<Ipv4>()?;
+        ipv4.set_src(self.ipv4[0]);
+        ipv4.set_dst(src);
+        ipv4.set_ttl(self.config.hop_limit);
+
+        let mut error = ipv4.push::<T>()?;
+        error.set_code(code);
+        error.reconcile_all();
+
+        debug!(?error);
+        Ok(error.reset())
+    }
+
+    /// Turns the packet into an ICMPv6 error carrying as much of the
+    /// original IPv6 packet as fits in the minimum IPv6 MTU.
+    fn error_v6<T>(&self, packet: Mbuf, code: u8) -> Result<Mbuf>
+    where
+        T: Packet<Envelope = Ipv6> + Icmpv6Packet + fmt::Debug,
+    {
+        let mut ethernet = packet.parse::<Ethernet>()?;
+        let src = ethernet.peek::<Ipv6>()?.src();
+        ethernet.swap_addresses();
+        // see IETF RFC 4443 §2.4(c).
+        truncate_invoking(&mut ethernet, IPV6_MIN_MTU - ICMPV6_ERROR_HEADERS_LEN)?;
+
+        let mut ipv6 = ethernet.push::<Ipv6>()?;
+        ipv6.set_src(self.ipv6[0]);
+        ipv6.set_dst(src);
+        ipv6.set_hop_limit(self.config.hop_limit);
+
+        let mut error = ipv6.push::<T>()?;
+        error.set_code(code);
+        error.reconcile_all();
+
+        debug!(?error);
+        Ok(error.reset())
+    }
+}
+
+/// Truncates the invoking packet in the frame to at most `max_len` bytes,
+/// before the headers of the error are pushed in front of it.
+fn truncate_invoking(ethernet: &mut Ethernet, max_len: usize) -> Result<()> {
+    let to_len = ethernet.payload_offset() + max_len;
+    if to_len < ethernet.mbuf().data_len() {
+        ethernet.mbuf_mut().truncate(to_len)?;
+    }
+    Ok(())
+}
+
+impl Stage for IcmpResponder {
+    fn process(&mut self, packet: Mbuf) -> Result<Either<Mbuf>> {
+        self.process_at(packet, Instant::now())
+    }
+}
+
+impl fmt::Debug for IcmpResponder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IcmpResponder")
+            .field("config", &self.config)
+            .field("ipv4", &self.ipv4)
+            .field("ipv6", &self.ipv6)
+            .field("replied", &self.replied)
+            .field("errors", &self.errors)
+            .field("suppressed", &self.suppressed)
+            .field("rate_limited", &self.rate_limited)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{Batch, Chain, PacketTx, Pipeline, Poll, StageSwitches};
+    use crate::net::MacAddr;
+    use crate::testils::build_packet;
+    use crate::testils::byte_arrays::{ICMPV4_PACKET, IPV4_UDP_PACKET};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    // the echo request in `ICMPV4_PACKET` answered with a TTL of 64.
+    #[rustfmt::skip]
+    const ICMPV4_REPLY: [u8; 74] = [
+    // Ethernet header
+        0x00, 0x0c, 0x29, 0x34, 0x0B, 0xde,
+        0x00, 0x50, 0x56, 0xe0, 0x14, 0x49,
+        0x08, 0x00,
+    // IPv4 header
+        0x45, 0x00, 0x00, 0x3c,
+        0x00, 0x00, 0x00, 0x00,
+        0x40, 0x01, 0x42, 0xb7,
+        0xae, 0x89, 0x2a, 0x4d,
+        0xc0, 0xa8, 0x9e, 0x8b,
+    // ICMPv4 header
+        0x00, 0x00, 0x32, 0x5c,
+    // data
+        0x02, 0x00, 0x21, 0x00, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c,
+        0x6d, 0x6e, 0x6f, 0x70, 0x71, 0x72, 0x73, 0x74, 0x75, 0x76, 0x77, 0x61, 0x62, 0x63, 0x64, 0x65,
+        0x66, 0x67, 0x68, 0x69,
+    ];
+
+    fn local_v4() -> Ipv4Addr {
+        Ipv4Addr::new(174, 137, 42, 77)
+    }
+
+    #[capsule::test]
+    fn reply_to_echo_request() {
+        let (mut tx, rx) = mpsc::channel();
+        let (out, out_rx) = mpsc::channel();
+
+        let responder = IcmpResponder::new(IcmpConfig::default()).with_ipv4(local_v4());
+        let chain = Chain::new(&StageSwitches::default()).stage("icmp_echo", responder);
+        let mut pipeline = Poll::new(rx).chain(chain).send(out);
+
+        tx.transmit(vec![Mbuf::from_bytes(&ICMPV4_PACKET).unwrap()]);
+        pipeline.run_once();
+
+        let sent = out_rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(1, sent.len());
+        let bytes = sent[0]
+            .read_data_slice::<u8>(0, sent[0].data_len())
+            .unwrap();
+        assert_eq!(&ICMPV4_REPLY[..], unsafe { bytes.as_ref() });
+    }
+
+    #[capsule::test]
+    fn ignore_echo_request_for_others() {
+        let mut responder = IcmpResponder::new(IcmpConfig::default())
+            .with_ipv4(Ipv4Addr::new(10, 0, 0, 1))
+            .with_verdict(|_| IcmpVerdict::Forward);
+
+        let packet = Mbuf::from_bytes(&ICMPV4_PACKET).unwrap();
+        match responder.process(packet).unwrap() {
+            Either::Keep(packet) => assert_eq!(ICMPV4_PACKET.len(), packet.data_len()),
+            Either::Drop(_) => panic!("packet should be forwarded."),
+        }
+        assert_eq!(0, responder.replied());
+    }
+
+    #[capsule::test]
+    fn rate_limit_echo_replies() {
+        let config = IcmpConfig {
+            echo_rate: 10,
+            burst: 2,
+            ..IcmpConfig::default()
+        };
+        let mut responder = IcmpResponder::new(config).with_ipv4(local_v4());
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            let packet = Mbuf::from_bytes(&ICMPV4_PACKET).unwrap();
+            let _ = responder.process_at(packet, now).unwrap();
+        }
+        assert_eq!(2, responder.replied());
+        assert_eq!(1, responder.rate_limited());
+
+        // a token is refilled every 100ms.
+        let packet = Mbuf::from_bytes(&ICMPV4_PACKET).unwrap();
+        let later = now + Duration::from_millis(100);
+        assert!(matches!(
+            responder.process_at(packet, later).unwrap(),
+            Either::Keep(_)
+        ));
+        assert_eq!(3, responder.replied());
+    }
+
+    #[capsule::test]
+    fn port_unreachable_carries_invoking_packet() {
+        let mut responder = IcmpResponder::new(IcmpConfig::default())
+            .with_ipv4(Ipv4Addr::new(10, 0, 0, 1))
+            .with_verdict(|_| IcmpVerdict::PortUnreachable);
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let error = match responder.process(packet).unwrap() {
+            Either::Keep(error) => error,
+            Either::Drop(_) => panic!("packet should be answered."),
+        };
+
+        let ipv4 = error.parse::<Ethernet>().unwrap().parse::<Ipv4>().unwrap();
+        assert_eq!(Ipv4Addr::new(10, 0, 0, 1), ipv4.src());
+        assert_eq!(Ipv4Addr::new(139, 133, 217, 110), ipv4.dst());
+
+        let unreachable = ipv4.parse::<v4::DestinationUnreachable>().unwrap();
+        assert_eq!(3, unreachable.code());
+
+        // the data is the original IPv4 packet.
+        assert_eq!(&IPV4_UDP_PACKET[14..], unreachable.data());
+        assert_eq!(1, responder.errors());
+    }
+
+    #[capsule::test]
+    fn truncate_invoking_packet() {
+        let mut responder = IcmpResponder::new(IcmpConfig::default())
+            .with_ipv4(Ipv4Addr::new(10, 0, 0, 1))
+            .with_ipv6("2001:db8::1".parse().unwrap())
+            .with_verdict(|_| IcmpVerdict::PortUnreachable);
+        let src = MacAddr::new(0x02, 0, 0, 0, 0, 0x01);
+        let dst = MacAddr::new(0x02, 0, 0, 0, 0, 0x02);
+
+        // a full sized 1500 bytes frame.
+        let packet = build_packet()
+            .ethernet(src, dst)
+            .ipv4(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 1))
+            .udp(5000, 53)
+            .payload(&[0xaa; 1458])
+            .build();
+        assert_eq!(1500, packet.data_len());
+
+        let error = match responder.process(packet).unwrap() {
+            Either::Keep(error) => error,
+            Either::Drop(_) => panic!("packet should be answered."),
+        };
+        let ipv4 = error.parse::<Ethernet>().unwrap().parse::<Ipv4>().unwrap();
+        assert!(ipv4.len() <= 576);
+        let unreachable = ipv4.parse::<v4::DestinationUnreachable>().unwrap();
+        assert_eq!(Ipv4Addr::new(10, 0, 0, 2), unreachable.envelope().dst());
+
+        let packet = build_packet()
+            .ethernet(src, dst)
+            .ipv6(
+                "2001:db8::2".parse().unwrap(),
+                "2001:db8::1".parse().unwrap(),
+            )
+            .udp(5000, 53)
+            .payload(&[0xaa; 1438])
+            .build();
+        assert_eq!(1500, packet.data_len());
+
+        let error = match responder.process(packet).unwrap() {
+            Either::Keep(error) => error,
+            Either::Drop(_) => panic!("packet should be answered."),
+        };
+        let ipv6 = error.parse::<Ethernet>().unwrap().parse::<Ipv6>().unwrap();
+        assert_eq!(1280, ipv6.len());
+        let unreachable = ipv6.parse::<v6::DestinationUnreachable<Ipv6>>().unwrap();
+        assert_eq!(4, unreachable.code());
+        assert_eq!(2, responder.errors());
+    }
+
+    #[capsule::test]
+    fn never_answer_an_icmp_error() {
+        let mut responder = IcmpResponder::new(IcmpConfig::default())
+            .with_ipv4(Ipv4Addr::new(10, 0, 0, 1))
+            .with_verdict(|_| IcmpVerdict::TimeExceeded);
+
+        // the first error is sent, but not an error about the error.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let error = match responder.process(packet).unwrap() {
+            Either::Keep(error) => error,
+            Either::Drop(_) => panic!("packet should be answered."),
+        };
+        assert!(matches!(responder.process(error).unwrap(), Either::Drop(_)));
+
+        assert_eq!(1, responder.errors());
+        assert_eq!(1, responder.suppressed());
+    }
+}
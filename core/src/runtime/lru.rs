@@ -0,0 +1,256 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Instant;
+
+const NIL: usize = std::usize::MAX;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    last_seen: Instant,
+    prev: usize,
+    next: usize,
+}
+
+/// A hash map with its entries linked in least recently used order, for
+/// the tables owned by a single core.
+///
+/// Marking an entry as used and removing the least recently used entry
+/// are both O(1). The map is unbounded, the tables decide when to evict.
+pub(crate) struct LruMap<K, V> {
+    map: HashMap<K, usize>,
+    nodes: Vec<Option<Node<K, V>>>,
+    free: Vec<usize>,
+    // most recently used.
+    head: usize,
+    // least recently used.
+    tail: usize,
+}
+
+impl<K: Copy + Eq + Hash, V> LruMap<K, V> {
+    /// Creates a new map with room for `capacity` entries.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        LruMap {
+            map: HashMap::with_capacity(capacity),
+            nodes: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+        }
+    }
+
+    /// Returns the number of entries in the map.
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns whether the map is empty.
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    #[inline]
+    fn node(&self, idx: usize) -> &Node<K, V> {
+        self.nodes[idx].as_ref().unwrap()
+    }
+
+    #[inline]
+    fn node_mut(&mut self, idx: usize) -> &mut Node<K, V> {
+        self.nodes[idx].as_mut().unwrap()
+    }
+
+    /// Removes the node from the recently used list.
+    fn unlink(&mut self, idx: usize) {
+        let (prev, next) = {
+            let node = self.node(idx);
+            (node.prev, node.next)
+        };
+
+        if prev == NIL {
+            self.head = next;
+        } else {
+            self.node_mut(prev).next = next;
+        }
+
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            self.node_mut(next).prev = prev;
+        }
+    }
+
+    /// Adds the node to the front of the recently used list.
+    fn push_front(&mut self, idx: usize) {
+        let head = self.head;
+        {
+            let node = self.node_mut(idx);
+            node.prev = NIL;
+            node.next = head;
+        }
+
+        if head == NIL {
+            self.tail = idx;
+        } else {
+            self.node_mut(head).prev = idx;
+        }
+        self.head = idx;
+    }
+
+    /// Removes the node from the map.
+    fn remove_node(&mut self, idx: usize) -> Node<K, V> {
+        self.unlink(idx);
+        let node = self.nodes[idx].take().unwrap();
+        self.map.remove(&node.key);
+        self.free.push(idx);
+        node
+    }
+
+    /// Inserts the entry as the most recently used. If the map already has
+    /// the key, the value is replaced and the old value is returned.
+    pub(crate) fn insert(&mut self, key: K, value: V, now: Instant) -> Option<V> {
+        if let Some(value_mut) = self.touch(&key, now) {
+            return Some(std::mem::replace(value_mut, value));
+        }
+
+        let node = Node {
+            key,
+            value,
+            last_seen: now,
+            prev: NIL,
+            next: NIL,
+        };
+
+        let idx = match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx] = Some(node);
+                idx
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+
+        self.push_front(idx);
+        let _ = self.map.insert(key, idx);
+        None
+    }
+
+    /// Returns a reference to the value without marking it as used.
+    #[inline]
+    pub(crate) fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|&idx| &self.node(idx).value)
+    }
+
+    /// Marks the entry as the most recently used, and returns a mutable
+    /// reference to the value.
+    pub(crate) fn touch(&mut self, key: &K, now: Instant) -> Option<&mut V> {
+        let idx = *self.map.get(key)?;
+        self.node_mut(idx).last_seen = now;
+        if self.head != idx {
+            self.unlink(idx);
+            self.push_front(idx);
+        }
+        Some(&mut self.node_mut(idx).value)
+    }
+
+    /// Returns whether the map has the key.
+    #[inline]
+    pub(crate) fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Removes the entry, and returns its value.
+    pub(crate) fn remove(&mut self, key: &K) -> Option<V> {
+        let idx = *self.map.get(key)?;
+        Some(self.remove_node(idx).value)
+    }
+
+    /// Returns when the least recently used entry was last used.
+    #[inline]
+    pub(crate) fn oldest_seen(&self) -> Option<Instant> {
+        if self.tail == NIL {
+            None
+        } else {
+            Some(self.node(self.tail).last_seen)
+        }
+    }
+
+    /// Removes the least recently used entry, and returns it.
+    pub(crate) fn pop_oldest(&mut self) -> Option<(K, V)> {
+        if self.tail == NIL {
+            None
+        } else {
+            let node = self.remove_node(self.tail);
+            Some((node.key, node.value))
+        }
+    }
+
+    /// Returns an iterator over the entries, in no particular order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.nodes
+            .iter()
+            .filter_map(|node| node.as_ref().map(|node| (&node.key, &node.value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn keep_recently_used_order() {
+        let mut map = LruMap::with_capacity(4);
+        let now = Instant::now();
+        let _ = map.insert(1, "a", now);
+        let _ = map.insert(2, "b", now + Duration::from_secs(1));
+        let _ = map.insert(3, "c", now + Duration::from_secs(2));
+
+        // touches the oldest, so the second one becomes the oldest.
+        assert_eq!(Some(&mut "a"), map.touch(&1, now + Duration::from_secs(3)));
+        assert_eq!(Some(now + Duration::from_secs(1)), map.oldest_seen());
+        assert_eq!(Some((2, "b")), map.pop_oldest());
+        assert_eq!(Some((3, "c")), map.pop_oldest());
+        assert_eq!(Some((1, "a")), map.pop_oldest());
+        assert_eq!(None, map.pop_oldest());
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn replace_and_remove() {
+        let mut map = LruMap::with_capacity(4);
+        let now = Instant::now();
+        assert_eq!(None, map.insert(1, "a", now));
+        assert_eq!(Some("a"), map.insert(1, "b", now));
+        assert_eq!(1, map.len());
+
+        assert_eq!(Some("b"), map.remove(&1));
+        assert!(!map.contains_key(&1));
+
+        // the freed slot is reused.
+        let _ = map.insert(2, "c", now);
+        assert_eq!(Some(&"c"), map.get(&2));
+        assert_eq!(1, map.iter().count());
+    }
+}
@@ -16,22 +16,56 @@
 * SPDX-License-Identifier: Apache-2.0
 */
 
+mod bridge;
+mod control;
 mod core_map;
-
+mod flow_table;
+mod icmp;
+mod lru;
+mod neighbor;
+mod port_registry;
+mod reorder;
+mod vlan_limiter;
+
+#[allow(unreachable_pub)]
+pub use self::bridge::*;
+#[allow(unreachable_pub)]
+pub use self::control::*;
 pub(crate) use self::core_map::*;
+#[allow(unreachable_pub)]
+pub use self::flow_table::*;
+#[allow(unreachable_pub)]
+pub use self::icmp::*;
+pub(crate) use self::lru::*;
+#[allow(unreachable_pub)]
+pub use self::neighbor::*;
+#[allow(unreachable_pub)]
+pub use self::port_registry::*;
+#[allow(unreachable_pub)]
+pub use self::reorder::*;
+#[allow(unreachable_pub)]
+pub use self::vlan_limiter::*;
 
 use crate::batch::Pipeline;
-use crate::config::RuntimeConfig;
+use crate::capture::CaptureController;
+use crate::config::{PortConfig, ReloadableConfig, RuntimeConfig};
 use crate::dpdk::{
-    self, CoreId, KniError, KniRx, Mempool, Port, PortBuilder, PortError, PortQueue,
+    self, BondBuilder, CoreId, KniError, KniRx, LinkCallback, LinkStatus, LinkWatcher, Mempool,
+    Port, PortBuilder, PortError, PortId, PortQueue, SocketId,
 };
-use crate::{debug, ensure, info};
+use crate::packets::EtherType;
+use crate::{debug, ensure, info, warn};
 use anyhow::Result;
-use futures::{future, stream, StreamExt};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::future::{AbortHandle, Abortable};
+use futures::{future, stream, Future, FutureExt, Stream, StreamExt};
 use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fmt;
-use std::mem::ManuallyDrop;
-use std::sync::Arc;
+use std::mem::{self, ManuallyDrop};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{self, Arc};
 use std::time::{Duration, Instant};
 use tokio_executor::current_thread;
 use tokio_net::driver;
@@ -63,6 +97,50 @@ pub enum UnixSignal {
     SIGTERM = libc::SIGTERM as isize,
 }
 
+/// Why the runtime woke up while waiting.
+enum Wake {
+    /// The runtime should stop, for the reason.
+    Stop(&'static str),
+
+    /// The settings should be reloaded from the file.
+    Reload,
+}
+
+/// The file and the overrides the settings are reloaded from on `SIGHUP`.
+#[derive(Clone, Debug)]
+struct ReloadSource {
+    file: PathBuf,
+    overrides: Vec<(String, String)>,
+}
+
+/// How often the `wait_until` condition is checked.
+const UNTIL_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How often the links of the ports without link status change interrupts
+/// are polled.
+const LINK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A handle to terminate the runtime programmatically.
+///
+/// The handle can be cloned and sent to other threads. Shutting down the
+/// runtime has the same effect as a stop signal, the blocked [`execute`]
+/// or [`wait_until`] returns after stopping the cores and the ports.
+///
+/// [`execute`]: Runtime::execute
+/// [`wait_until`]: Runtime::wait_until
+#[derive(Clone, Debug)]
+pub struct RuntimeHandle {
+    shutdown: UnboundedSender<()>,
+}
+
+impl RuntimeHandle {
+    /// Requests the runtime to shut down. Does nothing if the runtime is
+    /// already terminated.
+    pub fn shutdown(&self) {
+        let _ = self.shutdown.unbounded_send(());
+    }
+}
+
 /// The Capsule runtime.
 ///
 /// The runtime initializes the underlying DPDK environment, and it also manages
@@ -71,14 +149,80 @@ pub struct Runtime {
     ports: ManuallyDrop<Vec<Port>>,
     mempools: ManuallyDrop<Vec<Mempool>>,
     core_map: CoreMap,
+    registry: PortRegistry,
+    pipelines: HashMap<String, Vec<AbortHandle>>,
+    controls: Vec<AbortHandle>,
+    running: bool,
+    signal_handling: bool,
+    shutdown_tx: UnboundedSender<()>,
+    shutdown_rx: UnboundedReceiver<()>,
     on_signal: Arc<dyn Fn(UnixSignal) -> bool>,
+    link_callbacks: Vec<LinkCallback>,
+    reload_source: Option<ReloadSource>,
     config: RuntimeConfig,
 }
 
+/// Builds and configures a port from the config settings.
+fn build_port(conf: &PortConfig, mempools: &mut [Mempool]) -> Result<Port> {
+    if let Some(bond) = &conf.bond {
+        let socket_id = conf
+            .cores
+            .first()
+            .map(CoreId::socket_id)
+            .unwrap_or(SocketId::ANY);
+        BondBuilder::new(&conf.device_name(), bond.mode)
+            .slaves(&bond.slave_devices())
+            .primary(bond.primary.as_deref())
+            .xmit_policy(bond.xmit_policy)
+            .lacp(bond.lacp)
+            .socket_id(socket_id)
+            .finish()?;
+    }
+
+    let mut builder = PortBuilder::new(conf.name.clone(), conf.device_name())?;
+    builder
+        .cores(&conf.cores)?
+        .rx_tx_queue_capacity(conf.rxd, conf.txd)?
+        .cross_socket(conf.cross_socket);
+    for queue in conf.queues.iter() {
+        builder.queue_capacity(queue.core, queue.rxd, queue.txd)?;
+    }
+
+    let ether_types = conf
+        .ether_types
+        .iter()
+        .map(|&ether_type| EtherType(ether_type))
+        .collect::<Vec<_>>();
+
+    let port = builder
+        .mempools(mempools)
+        .tx_policy(conf.tx_policy, conf.tx_backlog)
+        .rx_burst(conf.rx_burst, conf.rx_burst_mode)?
+        .offloads(&conf.rx_offloads, &conf.tx_offloads)?
+        .rx_filter(&ether_types, &conf.vlan_ids)?
+        .mtu(conf.mtu)
+        .finish(conf.promiscuous, conf.multicast, conf.kni)?;
+    Ok(port)
+}
+
+/// Waits for the link of a started port to come up if the config sets a
+/// link timeout.
+fn wait_for_link(port: &Port, conf: &PortConfig) -> Result<()> {
+    if let Some(timeout) = conf.link_timeout {
+        info!("waiting for port {} link to come up...", port.name());
+        let link = port.id().wait_for_link_up(timeout)?;
+        info!("port {} link is {}.", port.name(), link);
+    }
+
+    Ok(())
+}
+
 impl Runtime {
     /// Builds a runtime from config settings.
     #[allow(clippy::cognitive_complexity)]
     pub fn build(config: RuntimeConfig) -> Result<Self> {
+        config.validate()?;
+
         info!("initializing EAL...");
         dpdk::eal_init(config.to_eal_args())?;
 
@@ -104,6 +248,7 @@ impl Runtime {
             .app_name(&config.app_name)
             .cores(&cores)
             .master_core(config.master_core)
+            .parse_options(config.parse_options())
             .mempools(&mut mempools)
             .finish()?;
 
@@ -114,15 +259,12 @@ impl Runtime {
         }
 
         info!("initializing ports...");
+        let registry = PortRegistry::default();
         let mut ports = vec![];
         for conf in config.ports.iter() {
-            let port = PortBuilder::new(conf.name.clone(), conf.device.clone())?
-                .cores(&conf.cores)?
-                .mempools(&mut mempools)
-                .rx_tx_queue_capacity(conf.rxd, conf.txd)?
-                .finish(conf.promiscuous, conf.multicast, conf.kni)?;
-
+            let port = build_port(conf, &mut mempools)?;
             debug!(?port);
+            registry.insert(port.name(), port.queues().clone());
             ports.push(port);
         }
 
@@ -130,15 +272,28 @@ impl Runtime {
         {
             crate::metrics::register_port_stats(&ports);
             crate::metrics::register_mempool_stats(&mempools);
+            crate::metrics::register_pipeline_stats();
+            crate::metrics::register_stage_stats();
         }
 
         info!("runtime ready.");
 
+        let (shutdown_tx, shutdown_rx) = mpsc::unbounded();
+
         Ok(Runtime {
             ports: ManuallyDrop::new(ports),
             mempools: ManuallyDrop::new(mempools),
             core_map,
+            registry,
+            pipelines: HashMap::new(),
+            controls: vec![],
+            running: false,
+            signal_handling: true,
+            shutdown_tx,
+            shutdown_rx,
             on_signal: Arc::new(|_| true),
+            link_callbacks: vec![],
+            reload_source: None,
             config,
         })
     }
@@ -213,6 +368,402 @@ impl Runtime {
         self
     }
 
+    /// Sets whether the runtime handles the Unix signals. Enabled by default.
+    ///
+    /// When disabled, the runtime doesn't install any signal handlers and
+    /// the `on_signal` handler is not used. Applications that own the
+    /// signal handling should stop the runtime with [`RuntimeHandle`]
+    /// instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut runtime = Runtime::build(config)?;
+    /// runtime.with_signal_handling(false);
+    /// let handle = runtime.handle();
+    /// ```
+    pub fn with_signal_handling(&mut self, enabled: bool) -> &mut Self {
+        self.signal_handling = enabled;
+        self
+    }
+
+    /// Registers a callback invoked when the link of a port goes up or
+    /// down, or changes speed or duplex.
+    ///
+    /// The callback runs as a control task on the master core, with the
+    /// name of the port and its new link status. Ports with devices that
+    /// support the link status change interrupt are checked when the
+    /// interrupt fires, the others are polled every 500 milliseconds.
+    /// Ports added at runtime are watched too. The slaves of bonded ports
+    /// are polled and named after the port and the slave device, for
+    /// example `bond0/net_ring0`.
+    ///
+    /// # Remarks
+    ///
+    /// The callbacks must be registered before the runtime starts, and
+    /// like the other control tasks, they only run while the runtime is
+    /// blocked in [`execute`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// runtime.on_link_change(|port, link| {
+    ///     if !link.up {
+    ///         warn!("port {} is down.", port);
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`execute`]: Runtime::execute
+    pub fn on_link_change<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&str, LinkStatus) + 'static,
+    {
+        self.link_callbacks.push(Box::new(f));
+        self
+    }
+
+    /// Reloads the settings from the file when the process receives
+    /// `SIGHUP`, with the environment and the key/value pairs overriding
+    /// the file like [`RuntimeConfig::from_sources`].
+    ///
+    /// The signal is not passed to the `on_signal` handler. Settings that
+    /// fail to load or change the ones that need a restart are logged, and
+    /// the runtime keeps running with the current settings.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// runtime
+    ///     .reload_on_hangup("config.toml", &[("ports.0.device", "0000:01:00.0")])
+    ///     .execute()?;
+    /// ```
+    pub fn reload_on_hangup<P: AsRef<Path>>(
+        &mut self,
+        file: P,
+        overrides: &[(&str, &str)],
+    ) -> &mut Self {
+        self.reload_source = Some(ReloadSource {
+            file: file.as_ref().to_path_buf(),
+            overrides: overrides
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+        });
+        self
+    }
+
+    /// Returns the current settings.
+    pub fn config(&self) -> &RuntimeConfig {
+        &self.config
+    }
+
+    /// Applies the reloadable settings without restarting the data plane.
+    ///
+    /// Only the settings that differ from the current ones are applied.
+    /// The port modes change on the devices before `apply` returns, and
+    /// the per-core settings are sent to the cores, which pick them up
+    /// between the runs of their tasks. See [`ReloadableConfig`] for when
+    /// each setting takes effect. No packets are dropped by the change.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a port is not found, or its device fails to
+    /// change mode. The changes applied before the failure are kept.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut reloadable = runtime.config().reloadable();
+    /// reloadable.max_frame_len = Some(9018);
+    /// runtime.apply(reloadable)?;
+    /// ```
+    pub fn apply(&mut self, config: ReloadableConfig) -> Result<&mut Self> {
+        let plan = self.config.reloadable().diff(&config)?;
+        if plan.is_empty() {
+            return Ok(self);
+        }
+
+        for port in plan.ports.iter() {
+            let port_id = self.get_port(&port.name)?.id();
+            port_id.set_promiscuous(port.promiscuous)?;
+            port_id.set_allmulticast(port.multicast)?;
+
+            if let Some(conf) = self.config.ports.iter_mut().find(|c| c.name == port.name) {
+                conf.promiscuous = port.promiscuous;
+                conf.multicast = port.multicast;
+            }
+            info!(
+                "reloaded port {}, promiscuous {}, multicast {}.",
+                port.name, port.promiscuous, port.multicast
+            );
+        }
+
+        if let Some(parse_options) = plan.parse_options {
+            // the master core's tasks run on this thread.
+            for (&core_id, core) in self.core_map.cores.iter() {
+                if core_id != self.config.master_core {
+                    let plan = plan.clone();
+                    core.thread
+                        .spawn(future::lazy(move |_| plan.apply_to_core()))?;
+                }
+            }
+            plan.apply_to_core();
+
+            self.config.max_frame_len = parse_options.max_frame_len;
+            info!(
+                "reloaded max frame length {:?}.",
+                parse_options.max_frame_len
+            );
+        }
+
+        Ok(self)
+    }
+
+    /// Reloads the settings, and applies the reloadable ones that changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the settings are invalid, or change settings
+    /// that need a restart, in which case nothing is applied. Otherwise
+    /// fails like [`apply`].
+    ///
+    /// [`apply`]: Runtime::apply
+    pub fn reload(&mut self, config: RuntimeConfig) -> Result<&mut Self> {
+        config.validate()?;
+        self.config.check_reload(&config)?;
+        self.apply(config.reloadable())
+    }
+
+    /// Reloads the settings from the file set with `reload_on_hangup`,
+    /// and logs the failure if they can't be reloaded.
+    fn reload_from_source(&mut self) {
+        let source = match &self.reload_source {
+            Some(source) => source.clone(),
+            None => return,
+        };
+
+        info!("reloading settings from {}...", source.file.display());
+        let overrides = source
+            .overrides
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        let result = RuntimeConfig::from_sources(&source.file, env::vars(), &overrides)
+            .and_then(|config| self.reload(config).map(|_| ()));
+
+        match result {
+            Ok(()) => info!("reloaded settings."),
+            Err(err) => warn!("failed to reload settings: {}", err),
+        }
+    }
+
+    /// Returns a handle that can shut down the runtime from any thread.
+    pub fn handle(&self) -> RuntimeHandle {
+        RuntimeHandle {
+            shutdown: self.shutdown_tx.clone(),
+        }
+    }
+
+    /// Returns a handle to the registry of the currently active ports.
+    ///
+    /// Pipelines that should pick up ports added or removed at runtime
+    /// look them up through the registry on every poll.
+    pub fn port_registry(&self) -> PortRegistry {
+        self.registry.clone()
+    }
+
+    /// Returns a handle to start and stop packet captures of the ports and
+    /// the pipelines at runtime.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let captures = runtime.capture_controller();
+    /// runtime.spawn_control(async move {
+    ///     let target = CaptureTarget::Pipeline("nat".to_owned());
+    ///     let opts = CaptureOpts::new("/tmp/nat").max_packets(10_000);
+    ///     let _ = captures.start(target, opts);
+    /// });
+    /// ```
+    pub fn capture_controller(&self) -> CaptureController {
+        CaptureController
+    }
+
+    /// Adds a new port at runtime.
+    ///
+    /// The device is attached and probed, then the port is configured with
+    /// a queue pair for each of its assigned cores. If the runtime is already
+    /// started, the port is started right away. Once added, the port is
+    /// available through the [`PortRegistry`], and pipelines can be
+    /// installed to it with [`add_pipeline_to_port`].
+    ///
+    /// # Remarks
+    ///
+    /// The cores assigned to the port must already be part of the runtime.
+    /// KNI enabled ports can only be configured at startup.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// runtime.start()?;
+    /// runtime.add_port(conf)?.add_pipeline_to_port("vm1", install)?;
+    /// ...
+    /// runtime.remove_port("vm1")?;
+    /// ```
+    ///
+    /// [`PortRegistry`]: crate::PortRegistry
+    /// [`add_pipeline_to_port`]: Runtime::add_pipeline_to_port
+    pub fn add_port(&mut self, conf: PortConfig) -> Result<&mut Self> {
+        conf.validate()?;
+        ensure!(!conf.kni, PortError::KniNotHotpluggable(conf.name.clone()));
+        ensure!(
+            conf.bond.is_none(),
+            PortError::BondNotHotpluggable(conf.name.clone())
+        );
+        ensure!(
+            self.get_port(&conf.name).is_err(),
+            PortError::AlreadyExists(conf.name.clone())
+        );
+        for &core_id in conf.cores.iter() {
+            let _ = self.get_core(core_id)?;
+        }
+
+        let device = conf.device_name();
+        info!("attaching device {}...", device);
+        let args = conf.device_args().unwrap_or_default();
+        dpdk::dev_hotplug_add(conf.bus_name(), &device, &args)?;
+
+        // detaches the device again if the port can't be configured or
+        // started, so it can be retried with different settings.
+        let mut port = match build_port(&conf, &mut self.mempools) {
+            Ok(port) => port,
+            Err(err) => {
+                let _ = dpdk::dev_hotplug_remove(conf.bus_name(), &device);
+                return Err(err);
+            }
+        };
+        debug!(?port);
+
+        if self.running {
+            if let Err(err) = port.start() {
+                drop(port);
+                let _ = dpdk::dev_hotplug_remove(conf.bus_name(), &device);
+                return Err(err);
+            }
+
+            if let Err(err) = wait_for_link(&port, &conf) {
+                port.stop();
+                drop(port);
+                let _ = dpdk::dev_hotplug_remove(conf.bus_name(), &device);
+                return Err(err);
+            }
+        }
+
+        self.registry.insert(port.name(), port.queues().clone());
+        info!("added port {}.", port.name());
+
+        self.ports.push(port);
+        self.config.ports.push(conf);
+
+        Ok(self)
+    }
+
+    /// Removes a port at runtime.
+    ///
+    /// The pipelines installed to the port are aborted and the port is
+    /// removed from the [`PortRegistry`]. Once the cores assigned to the
+    /// port finished the pipeline runs in progress, the port is stopped and
+    /// closed, its queues are freed and the device is detached.
+    ///
+    /// # Remarks
+    ///
+    /// Pipelines installed to a core with [`add_pipeline_to_core`] capture
+    /// the queues at installation time and are not aware of the removal.
+    /// They must look up the port through the [`PortRegistry`] instead.
+    ///
+    /// [`PortRegistry`]: crate::PortRegistry
+    /// [`add_pipeline_to_core`]: Runtime::add_pipeline_to_core
+    pub fn remove_port(&mut self, name: &str) -> Result<&mut Self> {
+        let idx = self
+            .ports
+            .iter()
+            .position(|p| p.name() == name)
+            .ok_or_else(|| PortError::NotFound(name.to_owned()))?;
+        ensure!(
+            !self.ports[idx].has_kni(),
+            PortError::KniNotHotpluggable(name.to_owned())
+        );
+        ensure!(
+            self.ports[idx].id().bond_slaves().is_err(),
+            PortError::BondNotHotpluggable(name.to_owned())
+        );
+
+        // stops the pipelines bound to the port, and hides the port from
+        // the pipelines looking it up through the registry.
+        if let Some(handles) = self.pipelines.remove(name) {
+            handles.iter().for_each(AbortHandle::abort);
+        }
+        self.registry.remove(name);
+
+        if self.running {
+            let cores = self.ports[idx].queues().keys().copied().collect::<Vec<_>>();
+            self.quiesce_cores(&cores)?;
+        }
+
+        let mut port = self.ports.remove(idx);
+        if self.running {
+            port.stop();
+        }
+        let device = port.device().to_owned();
+
+        // closes the port and frees the queues.
+        drop(port);
+
+        let bus = match self.config.ports.iter().position(|c| c.name == name) {
+            Some(i) => self.config.ports.remove(i).bus_name(),
+            None => "vdev",
+        };
+
+        info!("detaching device {}...", device);
+        dpdk::dev_hotplug_remove(bus, &device)?;
+        info!("removed port {}.", name);
+
+        Ok(self)
+    }
+
+    /// Waits for the cores to finish the task runs in progress.
+    ///
+    /// Each core runs its tasks one at a time, so once a barrier task runs
+    /// on a core, no other task is in the middle of a run there. An aborted
+    /// pipeline is never polled again after that. The master core is
+    /// skipped because its tasks only run while the runtime is blocked
+    /// waiting in `execute`.
+    fn quiesce_cores(&self, cores: &[CoreId]) -> Result<()> {
+        let (sender, receiver) = sync::mpsc::channel();
+        let mut count = 0;
+
+        for &core_id in cores.iter() {
+            if core_id == self.config.master_core {
+                continue;
+            }
+
+            let sender = sender.clone();
+            let thread = &self.get_core(core_id)?.thread;
+            thread.spawn(future::lazy(move |_| {
+                let _ = sender.send(core_id);
+            }))?;
+            count += 1;
+        }
+
+        for _ in 0..count {
+            let core_id = receiver.recv()?;
+            debug!("quiesced {:?}.", core_id);
+        }
+
+        Ok(())
+    }
+
     /// Installs a pipeline to a port. The pipeline will run on all the
     /// cores assigned to the port.
     ///
@@ -240,25 +791,32 @@ impl Runtime {
     {
         let port = self.get_port(port)?;
         let f = Arc::new(installer);
+        let mut handles = vec![];
 
         for (core_id, port_q) in port.queues() {
             let f = f.clone();
             let port_q = port_q.clone();
             let thread = &self.get_core(*core_id)?.thread;
 
+            // the pipeline is aborted if the port is removed at runtime.
+            let (handle, registration) = AbortHandle::new_pair();
+            handles.push(handle);
+
             // spawns the bootstrap. we want the bootstrapping to execute on the
             // target core instead of the master core. that way the actual task
             // is spawned locally and the type bounds are less restricting.
             thread.spawn(future::lazy(move |_| {
                 let fut = f(port_q);
                 debug!("spawned pipeline {}.", fut.name());
-                current_thread::spawn(fut);
+                current_thread::spawn(Abortable::new(fut, registration).map(|_| ()));
             }))?;
 
             debug!("installed pipeline on port_q for {:?}.", core_id);
         }
 
-        info!("installed pipeline for port {}.", port.name());
+        let name = port.name().to_owned();
+        info!("installed pipeline for port {}.", name);
+        self.pipelines.entry(name).or_default().extend(handles);
 
         Ok(self)
     }
@@ -460,57 +1018,134 @@ impl Runtime {
         Ok(self)
     }
 
-    /// Blocks the main thread until a timeout expires.
+    /// Spawns a control plane task onto the master core.
     ///
-    /// This mode is useful for running integration tests. The timeout
-    /// duration can be set in `RuntimeSettings`.
-    fn wait_for_timeout(&mut self, timeout: Duration) {
-        let MasterExecutor {
-            ref timer,
-            ref mut thread,
-            ..
-        } = self.core_map.master_core;
-
-        let when = Instant::now() + timeout;
-        let delay = timer.delay(when);
+    /// Control tasks are regular futures, for example a gRPC or HTTP
+    /// server, config fetches or periodic jobs, that run on the master
+    /// core's executor alongside the signal handling. The executor has a
+    /// reactor and a timer, and the tasks don't need to be `Send`. Use a
+    /// [`command_channel`] to pass commands from the control tasks to the
+    /// pipelines on the data plane cores.
+    ///
+    /// # Remarks
+    ///
+    /// Control tasks run while the runtime is blocked in [`execute`]. They
+    /// are aborted when the runtime stops.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// runtime.spawn_control(async move {
+    ///     while let Some(cmd) = requests.next().await {
+    ///         let _ = tx.send(cmd).await;
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// [`command_channel`]: crate::command_channel
+    /// [`execute`]: Runtime::execute
+    pub fn spawn_control<F>(&mut self, fut: F) -> &mut Self
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let (handle, registration) = AbortHandle::new_pair();
+        self.core_map
+            .master_core
+            .thread
+            .spawn(Abortable::new(fut, registration).map(|_| ()));
+        self.controls.push(handle);
 
-        debug!("waiting for {:?}...", timeout);
-        let _timer = timer::set_default(&timer);
-        thread.block_on(delay);
-        info!("timed out after {:?}.", timeout);
+        debug!("spawned control task.");
+        self
     }
 
-    /// Blocks the main thread until receives a signal to terminate.
-    fn wait_for_signal(&mut self) -> Result<()> {
-        let sighup = unix::signal(SignalKind::hangup())?.map(|_| UnixSignal::SIGHUP);
-        let sigint = unix::signal(SignalKind::interrupt())?.map(|_| UnixSignal::SIGINT);
-        let sigterm = unix::signal(SignalKind::terminate())?.map(|_| UnixSignal::SIGTERM);
-
-        // combines the streams together
-        let stream = stream::select(stream::select(sighup, sigint), sigterm);
-
-        // passes each signal through the `on_signal` closure, and discard
-        // any that shouldn't stop the execution.
-        let f = self.on_signal.clone();
-        let mut stream = stream.filter(|&signal| future::ready(f(signal)));
+    /// Blocks the main thread until the runtime should stop.
+    ///
+    /// The runtime stops on the first of, a Unix signal that the `on_signal`
+    /// handler doesn't discard if signal handling is enabled, a shutdown
+    /// through a [`RuntimeHandle`], the timeout expiring if there's one set
+    /// in `RuntimeConfig`, or the `until` condition turning `true`. A
+    /// `SIGHUP` reloads the settings instead if there's a file to reload
+    /// from, and the runtime keeps waiting.
+    fn wait(&mut self, mut until: Option<Box<dyn FnMut() -> bool + '_>>) -> Result<()> {
+        let deadline = self.config.duration.map(|timeout| {
+            debug!("waiting for {:?}...", timeout);
+            Instant::now() + timeout
+        });
+
+        loop {
+            match self.wait_once(deadline, until.as_mut())? {
+                Wake::Stop(reason) => {
+                    info!("{}.", reason);
+                    return Ok(());
+                }
+                Wake::Reload => self.reload_from_source(),
+            }
+        }
+    }
 
+    /// Runs the control tasks on the master core until the first wake.
+    fn wait_once(
+        &mut self,
+        deadline: Option<Instant>,
+        until: Option<&mut Box<dyn FnMut() -> bool + '_>>,
+    ) -> Result<Wake> {
         let MasterExecutor {
             ref reactor,
             ref timer,
             ref mut thread,
-            ..
         } = self.core_map.master_core;
 
-        // sets the reactor so we receive the signals and runs the future
-        // on the master core. the execution stops on the first signal that
-        // wasn't filtered out.
-        debug!("waiting for a Unix signal...");
+        // sets the reactor so we receive the signals, and the timer for the
+        // timeout. the control tasks running on the master core while it's
+        // waiting use them too.
         let _guard = driver::set_default(&reactor);
         let _timer = timer::set_default(&timer);
-        let _ = thread.block_on(stream.next());
-        info!("signaled to stop.");
 
-        Ok(())
+        // each wake source yields the reason to wake.
+        let mut wakes: Vec<Pin<Box<dyn Stream<Item = Wake> + '_>>> = vec![];
+
+        let shutdown = self.shutdown_rx.by_ref();
+        wakes.push(Box::pin(shutdown.map(|_| Wake::Stop("shutdown requested"))));
+
+        if self.signal_handling {
+            let reload = self.reload_source.is_some();
+            let sighup = unix::signal(SignalKind::hangup())?.map(|_| UnixSignal::SIGHUP);
+            let sigint = unix::signal(SignalKind::interrupt())?.map(|_| UnixSignal::SIGINT);
+            let sigterm = unix::signal(SignalKind::terminate())?.map(|_| UnixSignal::SIGTERM);
+
+            // passes each signal through the `on_signal` closure, and discard
+            // any that shouldn't stop the execution. `SIGHUP` reloads the
+            // settings instead if there's a file to reload from.
+            let f = self.on_signal.clone();
+            let signals =
+                stream::select(stream::select(sighup, sigint), sigterm).filter_map(move |signal| {
+                    let wake = match signal {
+                        UnixSignal::SIGHUP if reload => Some(Wake::Reload),
+                        _ if f(signal) => Some(Wake::Stop("signaled to stop")),
+                        _ => None,
+                    };
+                    future::ready(wake)
+                });
+            wakes.push(Box::pin(signals));
+        }
+
+        if let Some(deadline) = deadline {
+            let delay = timer.delay(deadline);
+            wakes.push(Box::pin(
+                delay.into_stream().map(|_| Wake::Stop("timed out")),
+            ));
+        }
+
+        if let Some(until) = until {
+            let checks = Interval::new_interval(UNTIL_CHECK_INTERVAL)
+                .filter(move |_| future::ready(until()))
+                .map(|_| Wake::Stop("wait condition met"));
+            wakes.push(Box::pin(checks));
+        }
+
+        let wake = thread.block_on(stream::select_all(wakes).next());
+        Ok(wake.unwrap_or(Wake::Stop("no more wake sources")))
     }
 
     /// Installs the KNI TX pipelines.
@@ -541,15 +1176,93 @@ impl Runtime {
         Ok(())
     }
 
-    /// Starts all the ports to receive packets.
+    /// Starts all the ports to receive packets, and waits for the links
+    /// to come up.
     fn start_ports(&mut self) -> Result<()> {
         for port in self.ports.iter_mut() {
             port.start()?;
         }
 
+        for port in self.ports.iter() {
+            if let Some(conf) = self.config.ports.iter().find(|c| c.name == port.name()) {
+                wait_for_link(port, conf)?;
+            }
+        }
+
+        // the kernel interfaces start with the carrier of the links.
+        for port in self.ports.iter_mut() {
+            let up = port.id().link().map(|link| link.up).unwrap_or(false);
+            if let Some(kni) = port.kni() {
+                kni.link().update(up);
+            }
+        }
+
         Ok(())
     }
 
+    /// Spawns the control task that watches the links of the active ports
+    /// if there are link change callbacks or KNI enabled ports.
+    fn spawn_link_watcher(&mut self) {
+        // keeps the carrier of the kernel interfaces in sync with the links.
+        let knis = self
+            .ports
+            .iter_mut()
+            .filter_map(|port| {
+                let name = port.name().to_owned();
+                port.kni().map(|kni| (name, kni.link()))
+            })
+            .collect::<HashMap<_, _>>();
+        if !knis.is_empty() {
+            self.link_callbacks.push(Box::new(move |name, status| {
+                if let Some(kni) = knis.get(name) {
+                    kni.update(status.up);
+                }
+            }));
+        }
+
+        if self.link_callbacks.is_empty() {
+            return;
+        }
+
+        let mut watcher = LinkWatcher::new(mem::take(&mut self.link_callbacks));
+        let registry = self.registry.clone();
+        let events = dpdk::lsc_subscribe();
+
+        self.spawn_control(async move {
+            // an interrupt checks the port it's for. each tick checks the
+            // ports without interrupts.
+            let events = events.map(Some);
+            let ticks = Interval::new_interval(LINK_POLL_INTERVAL).map(|_| None);
+            let mut wakes = stream::select(events, ticks);
+
+            while let Some(event) = wakes.next().await {
+                let mut ports = registry
+                    .names()
+                    .into_iter()
+                    .filter_map(|name| registry.port_id(&name).map(|id| (name, id)))
+                    .collect::<Vec<_>>();
+
+                // the slaves of bonded ports are polled on ticks too.
+                let slaves = ports
+                    .iter()
+                    .filter_map(|(name, id)| id.bond_slaves().ok().map(|slaves| (name, slaves)))
+                    .flat_map(|(name, slaves)| {
+                        slaves.into_iter().map(move |slave| {
+                            let device = slave.device_name().unwrap_or_default();
+                            (format!("{}/{}", name, device), slave)
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                ports.extend(slaves);
+
+                match event {
+                    Some(port_id) => watcher.poll(&ports, |id| id == port_id, PortId::link),
+                    None => watcher.poll(&ports, |id| !dpdk::lsc_enabled(id), PortId::link),
+                }
+            }
+        });
+    }
+
     /// Unparks all the cores to start task execution.
     fn unpark_cores(&mut self) {
         for core in self.core_map.cores.values() {
@@ -581,23 +1294,73 @@ impl Runtime {
         }
     }
 
-    /// Executes the pipeline(s) until a stop signal is received.
-    pub fn execute(&mut self) -> Result<()> {
+    /// Starts the ports and the cores to execute the pipeline(s), and
+    /// returns without blocking.
+    ///
+    /// Use this instead of [`execute`] when the main thread acts as the
+    /// control thread, for example to add and remove ports at runtime.
+    /// Tasks installed to the master core don't run until [`execute`] is
+    /// called.
+    ///
+    /// [`execute`]: Runtime::execute
+    pub fn start(&mut self) -> Result<()> {
+        if self.running {
+            return Ok(());
+        }
+
         self.add_kni_tx_pipelines()?;
         self.start_ports()?;
+        self.spawn_link_watcher();
         self.unpark_cores();
+        self.running = true;
+        info!("runtime started.");
 
-        // runs the app until main loop finishes.
-        match self.config.duration {
-            None => self.wait_for_signal()?,
-            Some(d) => self.wait_for_timeout(d),
-        };
+        Ok(())
+    }
+
+    /// Shuts down the cores and stops the ports.
+    pub fn stop(&mut self) {
+        if !self.running {
+            return;
+        }
 
+        self.controls.drain(..).for_each(|handle| handle.abort());
         self.shutdown_cores();
         self.stop_ports();
+        self.running = false;
         info!("runtime terminated.");
+    }
 
-        Ok(())
+    /// Executes the pipeline(s) until a stop signal is received, or the
+    /// runtime is shut down through a [`RuntimeHandle`].
+    pub fn execute(&mut self) -> Result<()> {
+        self.start()?;
+        let result = self.wait(None);
+        self.stop();
+        result
+    }
+
+    /// Executes the pipeline(s) until the condition turns `true`.
+    ///
+    /// The condition is checked on the master core every 10 milliseconds.
+    /// The runtime still stops early on a stop signal or a shutdown through
+    /// a [`RuntimeHandle`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let done = Arc::new(AtomicBool::new(false));
+    /// let flag = done.clone();
+    /// runtime.wait_until(move || flag.load(Ordering::Relaxed))?;
+    /// ```
+    pub fn wait_until<F>(&mut self, condition: F) -> Result<()>
+    where
+        F: FnMut() -> bool,
+    {
+        self.start()?;
+        let result = self.wait(Some(Box::new(condition)));
+        self.stop();
+        result
     }
 }
 
@@ -611,6 +1374,10 @@ impl<'a> fmt::Debug for Runtime {
 
 impl Drop for Runtime {
     fn drop(&mut self) {
+        // stops the ports before closing them, so devices writing to files,
+        // like pcap ports, flush and close them.
+        self.stop();
+
         // the default rust drop order is self before fields, which is the wrong
         // order for what EAL needs. To control the order, we manually drop the
         // fields first.
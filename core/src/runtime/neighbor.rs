@@ -0,0 +1,1010 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::batch::PacketTx;
+use crate::debug;
+use crate::net::MacAddr;
+use crate::packets::arp::{Arp4, OperationCodes};
+use crate::packets::icmp::v6::ndp::{
+    LinkLayerAddress, NdpOptionTypes, NdpPacket, NeighborAdvertisement, NeighborSolicitation,
+};
+use crate::packets::ip::v6::Ipv6;
+use crate::packets::{EtherTypes, Ethernet, NeighborLookup, Packet};
+use crate::Mbuf;
+use anyhow::{anyhow, Result};
+use futures::{future, StreamExt};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio_executor::current_thread;
+use tokio_timer::Interval;
+
+/// The timers and limits of a `NeighborCache`.
+#[derive(Clone, Copy, Debug)]
+pub struct NeighborConfig {
+    /// How long a neighbor is considered reachable after its address is
+    /// confirmed. The default is 30 seconds.
+    pub reachable_time: Duration,
+
+    /// How long an unconfirmed stale neighbor is kept before it's removed.
+    /// The default is 60 seconds.
+    pub stale_time: Duration,
+
+    /// The interval between probes for the same neighbor. The default is
+    /// 1 second.
+    pub retrans_time: Duration,
+
+    /// The number of probes sent before giving up on a neighbor. The
+    /// default is 3.
+    pub max_probes: u32,
+
+    /// The number of outgoing packets queued per neighbor while its
+    /// address is being resolved. The default is 8.
+    pub queue_len: usize,
+}
+
+impl Default for NeighborConfig {
+    fn default() -> Self {
+        NeighborConfig {
+            reachable_time: Duration::from_secs(30),
+            stale_time: Duration::from_secs(60),
+            retrans_time: Duration::from_secs(1),
+            max_probes: 3,
+            queue_len: 8,
+        }
+    }
+}
+
+/// The reachability state of a neighbor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NeighborState {
+    /// The address resolution is in progress.
+    Incomplete,
+
+    /// The address is recently confirmed.
+    Reachable,
+
+    /// The address is no longer confirmed but still used. The next lookup
+    /// probes the neighbor.
+    Stale,
+
+    /// The address is being reconfirmed with unicast probes.
+    Probe,
+
+    /// The address is configured statically and never expires.
+    Static,
+}
+
+/// A snapshot of a neighbor cache entry.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NeighborEntry {
+    /// The IP address of the neighbor.
+    pub ip: IpAddr,
+
+    /// The MAC address of the neighbor, or `None` if not yet resolved.
+    pub mac: Option<MacAddr>,
+
+    /// The reachability state of the neighbor.
+    pub state: NeighborState,
+}
+
+struct Neighbor {
+    mac: Option<MacAddr>,
+    state: NeighborState,
+    // when the state last changed.
+    updated: Instant,
+    // when the last probe was sent.
+    probed: Instant,
+    probes: u32,
+    queue: VecDeque<Mbuf>,
+}
+
+impl Neighbor {
+    fn new(mac: Option<MacAddr>, state: NeighborState, now: Instant) -> Self {
+        Neighbor {
+            mac,
+            state,
+            updated: now,
+            probed: now,
+            probes: 0,
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+/// An ARP and NDP neighbor cache that maps next-hop IP addresses to MAC
+/// addresses.
+///
+/// Outgoing packets are handed to [`resolve`] with their next-hop address.
+/// If the neighbor's MAC address is known, the packet is returned with
+/// its Ethernet destination rewritten. Otherwise, the packet is queued and
+/// an ARP request or a neighbor solicitation is generated. The queued
+/// packets are released once the matching ARP reply or neighbor
+/// advertisement is fed to [`learn`].
+///
+/// The generated probes and the released packets are collected in an
+/// outbound queue, which the application drains with [`take_outbound`]
+/// and transmits.
+///
+/// The cache is not thread-safe. Each core keeps its own cache, and the
+/// timers are driven by [`tick`] or [`spawn_timers`].
+///
+/// # Example
+///
+/// ```
+/// let cache = Rc::new(RefCell::new(
+///     NeighborCache::new(q.mac_addr(), NeighborConfig::default())
+///         .with_ipv4(Ipv4Addr::new(10, 0, 0, 1)),
+/// ));
+/// NeighborCache::spawn_timers(&cache, Duration::from_millis(100));
+///
+/// Poll::new(q.clone())
+///     .filter({
+///         let cache = cache.clone();
+///         move |packet| !cache.borrow_mut().learn(packet)
+///     })
+///     .map(route)
+///     .send(NeighborTx::new(cache, q, next_hop))
+/// ```
+///
+/// [`resolve`]: NeighborCache::resolve
+/// [`learn`]: NeighborCache::learn
+/// [`take_outbound`]: NeighborCache::take_outbound
+/// [`tick`]: NeighborCache::tick
+/// [`spawn_timers`]: NeighborCache::spawn_timers
+pub struct NeighborCache {
+    mac: MacAddr,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    config: NeighborConfig,
+    neighbors: HashMap<IpAddr, Neighbor>,
+    outbound: Vec<Mbuf>,
+    dropped: u64,
+}
+
+impl NeighborCache {
+    /// Creates a new neighbor cache for the interface with MAC address
+    /// `mac`.
+    pub fn new(mac: MacAddr, config: NeighborConfig) -> Self {
+        NeighborCache {
+            mac,
+            ipv4: None,
+            ipv6: None,
+            config,
+            neighbors: HashMap::new(),
+            outbound: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Sets the interface's IPv4 address used as the sender address of
+    /// ARP requests.
+    pub fn with_ipv4(mut self, addr: Ipv4Addr) -> Self {
+        self.ipv4 = Some(addr);
+        self
+    }
+
+    /// Sets the interface's IPv6 address used as the source address of
+    /// neighbor solicitations.
+    pub fn with_ipv6(mut self, addr: Ipv6Addr) -> Self {
+        self.ipv6 = Some(addr);
+        self
+    }
+
+    /// Returns the configured timers and limits.
+    #[inline]
+    pub fn config(&self) -> &NeighborConfig {
+        &self.config
+    }
+
+    /// Returns the number of neighbors in the cache.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.neighbors.len()
+    }
+
+    /// Returns whether the cache is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.neighbors.is_empty()
+    }
+
+    /// Returns the total number of queued packets dropped, either because
+    /// the neighbor's queue is full or because its resolution failed.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Returns the MAC address of the neighbor if it's resolved.
+    pub fn lookup(&self, ip: IpAddr) -> Option<MacAddr> {
+        self.neighbors.get(&ip).and_then(|neighbor| neighbor.mac)
+    }
+
+    /// Returns an iterator over a snapshot of all the entries.
+    pub fn entries(&self) -> impl Iterator<Item = NeighborEntry> + '_ {
+        self.neighbors.iter().map(|(&ip, neighbor)| NeighborEntry {
+            ip,
+            mac: neighbor.mac,
+            state: neighbor.state,
+        })
+    }
+
+    /// Adds a static entry that is never probed or expired. Packets queued
+    /// for the neighbor are released.
+    pub fn add_static(&mut self, ip: IpAddr, mac: MacAddr) {
+        let now = Instant::now();
+        let neighbor = self
+            .neighbors
+            .entry(ip)
+            .or_insert_with(|| Neighbor::new(None, NeighborState::Static, now));
+        neighbor.mac = Some(mac);
+        neighbor.state = NeighborState::Static;
+        neighbor.updated = now;
+
+        let queue = mem::take(&mut neighbor.queue);
+        self.release(queue, mac);
+    }
+
+    /// Removes the neighbor from the cache. Packets queued for the neighbor
+    /// are dropped. Returns whether the neighbor was in the cache.
+    pub fn remove(&mut self, ip: IpAddr) -> bool {
+        match self.neighbors.remove(&ip) {
+            Some(neighbor) => {
+                self.dropped += neighbor.queue.len() as u64;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Resolves the MAC address of the next hop for the packet.
+    ///
+    /// If the address is known, the packet's Ethernet destination is set
+    /// and the packet is returned. Otherwise the packet is queued and an
+    /// ARP request or a neighbor solicitation is added to the outbound
+    /// queue, and `None` is returned. When the neighbor's queue is full,
+    /// the oldest queued packet is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the packet is not an Ethernet frame, or if the
+    /// interface has no address of the next hop's IP version.
+    pub fn resolve(&mut self, next_hop: IpAddr, packet: Mbuf) -> Result<Option<Mbuf>> {
+        self.resolve_at(next_hop, packet, Instant::now())
+    }
+
+    fn resolve_at(&mut self, next_hop: IpAddr, packet: Mbuf, now: Instant) -> Result<Option<Mbuf>> {
+        let queue_len = self.config.queue_len;
+
+        let (packet, probe) = match self.neighbors.get_mut(&next_hop) {
+            Some(neighbor) => match neighbor.mac {
+                Some(mac) => {
+                    // reconfirms a stale address while still using it.
+                    let probe = if neighbor.state == NeighborState::Stale {
+                        neighbor.state = NeighborState::Probe;
+                        neighbor.updated = now;
+                        neighbor.probed = now;
+                        neighbor.probes = 1;
+                        Some(mac)
+                    } else {
+                        None
+                    };
+
+                    (Some(set_dst(packet, mac)?), probe)
+                }
+                None => {
+                    neighbor.queue.push_back(packet);
+                    if neighbor.queue.len() > queue_len {
+                        let _ = neighbor.queue.pop_front();
+                        self.dropped += 1;
+                    }
+                    return Ok(None);
+                }
+            },
+            None => {
+                self.ensure_local_addr(next_hop)?;
+
+                let mut neighbor = Neighbor::new(None, NeighborState::Incomplete, now);
+                neighbor.probes = 1;
+                neighbor.queue.push_back(packet);
+                let _ = self.neighbors.insert(next_hop, neighbor);
+                debug!(message = "resolving neighbor.", ip = ?next_hop);
+
+                let probe = self.probe(next_hop, None)?;
+                self.outbound.push(probe);
+                return Ok(None);
+            }
+        };
+
+        if let Some(mac) = probe {
+            let probe = self.probe(next_hop, Some(mac))?;
+            self.outbound.push(probe);
+        }
+
+        Ok(packet)
+    }
+
+    /// Updates the cache from an ARP reply or a neighbor advertisement.
+    ///
+    /// Only neighbors already in the cache are updated; static entries are
+    /// left untouched. Queued packets of a newly resolved neighbor are
+    /// released to the outbound queue.
+    ///
+    /// Returns whether the packet is an ARP reply or a neighbor
+    /// advertisement. Such packets are meant for the cache, and can be
+    /// dropped by the caller.
+    pub fn learn<P: Packet>(&mut self, packet: &P) -> bool {
+        self.learn_at(packet.mbuf(), Instant::now())
+    }
+
+    fn learn_at(&mut self, mbuf: &Mbuf, now: Instant) -> bool {
+        let ethernet = match mbuf.peek::<Ethernet>() {
+            Ok(ethernet) => ethernet,
+            Err(_) => return false,
+        };
+
+        match ethernet.ether_type() {
+            EtherTypes::Arp => match ethernet.peek::<Arp4>() {
+                Ok(ref arp) if arp.operation_code() == OperationCodes::Reply => {
+                    let ip = IpAddr::V4(arp.sender_protocol_addr());
+                    self.confirm(ip, Some(arp.sender_hardware_addr()), true, now);
+                    true
+                }
+                _ => false,
+            },
+            EtherTypes::Ipv6 => {
+                let ipv6 = match ethernet.peek::<Ipv6>() {
+                    Ok(ipv6) => ipv6,
+                    Err(_) => return false,
+                };
+
+                match ipv6.peek::<NeighborAdvertisement<Ipv6>>() {
+                    Ok(advert) => {
+                        let mut mac = None;
+                        let mut iter = advert.options_iter();
+                        while let Ok(Some(mut option)) = iter.next() {
+                            if option.option_type() == NdpOptionTypes::TargetLinkLayerAddress {
+                                if let Ok(addr) = option.downcast::<LinkLayerAddress<'_>>() {
+                                    mac = Some(addr.addr());
+                                }
+                            }
+                        }
+
+                        let ip = IpAddr::V6(advert.target());
+                        self.confirm(ip, mac, advert.r#override(), now);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Marks the neighbor reachable and releases its queued packets.
+    fn confirm(&mut self, ip: IpAddr, mac: Option<MacAddr>, r#override: bool, now: Instant) {
+        let neighbor = match self.neighbors.get_mut(&ip) {
+            Some(neighbor) if neighbor.state != NeighborState::Static => neighbor,
+            _ => return,
+        };
+
+        let mac = match (neighbor.mac, mac) {
+            (None, Some(new)) => new,
+            (Some(_), Some(new)) if r#override => new,
+            // a different address without the override flag doesn't
+            // replace the known one.
+            (Some(old), Some(new)) if old != new => return,
+            (Some(old), _) => old,
+            (None, None) => return,
+        };
+
+        neighbor.mac = Some(mac);
+        neighbor.state = NeighborState::Reachable;
+        neighbor.updated = now;
+        neighbor.probes = 0;
+        debug!(message = "neighbor reachable.", ip = ?ip, mac = ?mac);
+
+        let queue = mem::take(&mut neighbor.queue);
+        self.release(queue, mac);
+    }
+
+    /// Sets the destination of the queued packets and moves them to the
+    /// outbound queue.
+    fn release(&mut self, queue: VecDeque<Mbuf>, mac: MacAddr) {
+        for packet in queue {
+            match set_dst(packet, mac) {
+                Ok(packet) => self.outbound.push(packet),
+                Err(_) => self.dropped += 1,
+            }
+        }
+    }
+
+    /// Advances the timers of the cache.
+    ///
+    /// Unresolved neighbors are probed again every `retrans_time`, and
+    /// removed after `max_probes` unanswered probes along with their queued
+    /// packets. Reachable neighbors become stale after `reachable_time`,
+    /// and stale neighbors are removed after `stale_time`.
+    pub fn tick(&mut self) {
+        self.tick_at(Instant::now())
+    }
+
+    fn tick_at(&mut self, now: Instant) {
+        let config = self.config;
+        let mut probes = vec![];
+        let mut removed = vec![];
+
+        for (&ip, neighbor) in self.neighbors.iter_mut() {
+            let elapsed = now.saturating_duration_since(neighbor.updated);
+
+            match neighbor.state {
+                NeighborState::Incomplete | NeighborState::Probe => {
+                    if now.saturating_duration_since(neighbor.probed) >= config.retrans_time {
+                        if neighbor.probes >= config.max_probes {
+                            removed.push(ip);
+                        } else {
+                            neighbor.probes += 1;
+                            neighbor.probed = now;
+                            probes.push((ip, neighbor.mac));
+                        }
+                    }
+                }
+                NeighborState::Reachable => {
+                    if elapsed >= config.reachable_time {
+                        neighbor.state = NeighborState::Stale;
+                        neighbor.updated = now;
+                    }
+                }
+                NeighborState::Stale => {
+                    if elapsed >= config.stale_time {
+                        removed.push(ip);
+                    }
+                }
+                NeighborState::Static => (),
+            }
+        }
+
+        for ip in removed {
+            debug!(message = "removing neighbor.", ip = ?ip);
+            let _ = self.remove(ip);
+        }
+
+        for (ip, mac) in probes {
+            if let Ok(probe) = self.probe(ip, mac) {
+                self.outbound.push(probe);
+            }
+        }
+    }
+
+    /// Takes the probes and the released packets that are ready to be
+    /// transmitted.
+    pub fn take_outbound(&mut self) -> Vec<Mbuf> {
+        mem::take(&mut self.outbound)
+    }
+
+    /// Spawns a task on the current core's executor that ticks the cache
+    /// every `period`. The task ends when the cache is dropped.
+    pub fn spawn_timers(cache: &Rc<RefCell<Self>>, period: Duration) {
+        let weak = Rc::downgrade(cache);
+
+        let fut = Interval::new_interval(period)
+            .take_while(move |_| future::ready(weak.strong_count() > 0))
+            .for_each({
+                let weak = Rc::downgrade(cache);
+                move |_| {
+                    if let Some(cache) = weak.upgrade() {
+                        cache.borrow_mut().tick();
+                    }
+                    future::ready(())
+                }
+            });
+
+        let _ = current_thread::spawn(fut);
+    }
+
+    fn ensure_local_addr(&self, ip: IpAddr) -> Result<()> {
+        match ip {
+            IpAddr::V4(_) if self.ipv4.is_none() => {
+                Err(anyhow!("no local IPv4 address to resolve {}.", ip))
+            }
+            IpAddr::V6(_) if self.ipv6.is_none() => {
+                Err(anyhow!("no local IPv6 address to resolve {}.", ip))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds an address resolution probe. The probe is broadcast or sent
+    /// to the solicited-node multicast address if `dst` is `None`.
+    fn probe(&self, ip: IpAddr, dst: Option<MacAddr>) -> Result<Mbuf> {
+        match ip {
+            IpAddr::V4(target) => self.arp_request(target, dst.unwrap_or(MacAddr::BROADCAST)),
+            IpAddr::V6(target) => self.neighbor_solicitation(target, dst),
+        }
+    }
+
+    fn arp_request(&self, target: Ipv4Addr, dst: MacAddr) -> Result<Mbuf> {
+        let sender = self.ipv4.ok_or_else(|| anyhow!("no local IPv4 address."))?;
+
+        let mut ethernet = Mbuf::new()?.push::<Ethernet>()?;
+        ethernet.set_src(self.mac);
+        ethernet.set_dst(dst);
+
+        let mut arp = ethernet.push::<Arp4>()?;
+        arp.set_operation_code(OperationCodes::Request);
+        arp.set_sender_hardware_addr(self.mac);
+        arp.set_sender_protocol_addr(sender);
+        arp.set_target_hardware_addr(MacAddr::UNSPECIFIED);
+        arp.set_target_protocol_addr(target);
+        arp.reconcile_all();
+
+        Ok(arp.reset())
+    }
+
+    fn neighbor_solicitation(&self, target: Ipv6Addr, dst: Option<MacAddr>) -> Result<Mbuf> {
+        let src = self.ipv6.ok_or_else(|| anyhow!("no local IPv6 address."))?;
+
+        let (dst_ip, dst_mac) = match dst {
+            Some(mac) => (target, mac),
+            None => solicited_node(target),
+        };
+
+        let mut ethernet = Mbuf::new()?.push::<Ethernet>()?;
+        ethernet.set_src(self.mac);
+        ethernet.set_dst(dst_mac);
+
+        let mut ipv6 = ethernet.push::<Ipv6>()?;
+        ipv6.set_src(src);
+        ipv6.set_dst(dst_ip);
+        ipv6.set_hop_limit(255);
+
+        let mut solicit = ipv6.push::<NeighborSolicitation<Ipv6>>()?;
+        solicit.set_target(target);
+
+        {
+            let mut options = solicit.options_mut();
+            let mut source = options.append::<LinkLayerAddress<'_>>()?;
+            source.set_option_type_source();
+            source.set_addr(self.mac);
+        }
+
+        solicit.reconcile_all();
+        Ok(solicit.reset())
+    }
+}
+
+impl NeighborLookup for NeighborCache {
+    fn lookup_mac(&self, ip: IpAddr) -> Option<MacAddr> {
+        self.lookup(ip)
+    }
+}
+
+impl fmt::Debug for NeighborCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NeighborCache")
+            .field("mac", &self.mac)
+            .field("ipv4", &self.ipv4)
+            .field("ipv6", &self.ipv6)
+            .field("len", &self.len())
+            .field("outbound", &self.outbound.len())
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
+
+/// A `PacketTx` adapter that resolves the next hop of the packets through
+/// a `NeighborCache` before transmitting them.
+///
+/// The next hop of each packet is returned by the `next_hop` closure.
+/// Packets without a next hop are transmitted as is. Along with the
+/// resolved packets, the cache's probes and released packets are also
+/// transmitted.
+#[allow(missing_debug_implementations)]
+pub struct NeighborTx<Tx, F>
+where
+    Tx: PacketTx,
+    F: FnMut(&Mbuf) -> Option<IpAddr>,
+{
+    cache: Rc<RefCell<NeighborCache>>,
+    tx: Tx,
+    next_hop: F,
+}
+
+impl<Tx, F> NeighborTx<Tx, F>
+where
+    Tx: PacketTx,
+    F: FnMut(&Mbuf) -> Option<IpAddr>,
+{
+    /// Creates a new adapter that transmits to `tx`.
+    pub fn new(cache: Rc<RefCell<NeighborCache>>, tx: Tx, next_hop: F) -> Self {
+        NeighborTx {
+            cache,
+            tx,
+            next_hop,
+        }
+    }
+
+    /// Resolves the next hop of the packets and transmits the ones with
+    /// a known destination.
+    pub fn transmit(&mut self, packets: Vec<Mbuf>) {
+        let mut cache = self.cache.borrow_mut();
+        let mut resolved = Vec::with_capacity(packets.len());
+
+        for packet in packets {
+            match (self.next_hop)(&packet) {
+                Some(ip) => match cache.resolve(ip, packet) {
+                    Ok(Some(packet)) => resolved.push(packet),
+                    Ok(None) => (),
+                    Err(err) => debug!(message = "failed to resolve neighbor.", ?err),
+                },
+                None => resolved.push(packet),
+            }
+        }
+
+        resolved.extend(cache.take_outbound());
+        drop(cache);
+
+        if !resolved.is_empty() {
+            self.tx.transmit(resolved);
+        }
+    }
+}
+
+/// Sets the Ethernet destination of the packet.
+fn set_dst(packet: Mbuf, mac: MacAddr) -> Result<Mbuf> {
+    let mut ethernet = packet.parse::<Ethernet>()?;
+    ethernet.set_dst(mac);
+    Ok(ethernet.deparse())
+}
+
+/// Returns the solicited-node multicast IP and MAC addresses of the target.
+fn solicited_node(target: Ipv6Addr) -> (Ipv6Addr, MacAddr) {
+    let o = target.octets();
+    let ip = Ipv6Addr::new(
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | u16::from(o[13]),
+        u16::from(o[14]) << 8 | u16::from(o[15]),
+    );
+    let mac = MacAddr::new(0x33, 0x33, 0xff, o[13], o[14], o[15]);
+    (ip, mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, IPV6_TCP_PACKET};
+    use std::sync::mpsc;
+
+    fn local_mac() -> MacAddr {
+        MacAddr::new(0x02, 0, 0, 0, 0, 0x01)
+    }
+
+    fn peer_mac() -> MacAddr {
+        MacAddr::new(0x02, 0, 0, 0, 0, 0x02)
+    }
+
+    fn local_v4() -> Ipv4Addr {
+        Ipv4Addr::new(10, 0, 0, 1)
+    }
+
+    fn peer_v4() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))
+    }
+
+    fn peer_v6() -> Ipv6Addr {
+        "fe80::2".parse().unwrap()
+    }
+
+    fn new_cache() -> NeighborCache {
+        NeighborCache::new(local_mac(), NeighborConfig::default())
+            .with_ipv4(local_v4())
+            .with_ipv6("fe80::1".parse().unwrap())
+    }
+
+    fn arp_reply(sender: IpAddr, mac: MacAddr) -> Mbuf {
+        let sender = match sender {
+            IpAddr::V4(addr) => addr,
+            IpAddr::V6(_) => unreachable!(),
+        };
+
+        let mut ethernet = Mbuf::new().unwrap().push::<Ethernet>().unwrap();
+        ethernet.set_src(mac);
+        ethernet.set_dst(local_mac());
+        let mut arp = ethernet.push::<Arp4>().unwrap();
+        arp.set_operation_code(OperationCodes::Reply);
+        arp.set_sender_hardware_addr(mac);
+        arp.set_sender_protocol_addr(sender);
+        arp.set_target_hardware_addr(local_mac());
+        arp.set_target_protocol_addr(local_v4());
+        arp.reconcile_all();
+        arp.reset()
+    }
+
+    fn neighbor_advert(target: Ipv6Addr, mac: MacAddr) -> Mbuf {
+        let mut ethernet = Mbuf::new().unwrap().push::<Ethernet>().unwrap();
+        ethernet.set_src(mac);
+        ethernet.set_dst(local_mac());
+        let mut ipv6 = ethernet.push::<Ipv6>().unwrap();
+        ipv6.set_src(target);
+        ipv6.set_dst("fe80::1".parse().unwrap());
+        ipv6.set_hop_limit(255);
+        let mut advert = ipv6.push::<NeighborAdvertisement<Ipv6>>().unwrap();
+        advert.set_target(target);
+        advert.set_solicited();
+        advert.set_override();
+        {
+            let mut options = advert.options_mut();
+            let mut lla = options.append::<LinkLayerAddress<'_>>().unwrap();
+            lla.set_option_type_target();
+            lla.set_addr(mac);
+        }
+        advert.reconcile_all();
+        advert.reset()
+    }
+
+    fn dst_of(packet: &Mbuf) -> MacAddr {
+        packet.peek::<Ethernet>().unwrap().dst()
+    }
+
+    #[capsule::test]
+    fn resolve_queue_and_release() {
+        let mut cache = new_cache();
+
+        // first packet triggers a broadcast ARP request.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(cache.resolve(peer_v4(), packet).unwrap().is_none());
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(cache.resolve(peer_v4(), packet).unwrap().is_none());
+
+        let outbound = cache.take_outbound();
+        assert_eq!(1, outbound.len());
+        let ethernet = outbound[0].peek::<Ethernet>().unwrap();
+        assert_eq!(MacAddr::BROADCAST, ethernet.dst());
+        let request = ethernet.peek::<Arp4>().unwrap();
+        assert_eq!(OperationCodes::Request, request.operation_code());
+        assert_eq!(local_mac(), request.sender_hardware_addr());
+        assert_eq!(local_v4(), request.sender_protocol_addr());
+        assert_eq!(peer_v4(), IpAddr::V4(request.target_protocol_addr()));
+
+        let entry = cache.entries().next().unwrap();
+        assert_eq!(NeighborState::Incomplete, entry.state);
+        assert_eq!(None, entry.mac);
+
+        // the reply resolves the neighbor and releases both packets.
+        let reply = arp_reply(peer_v4(), peer_mac());
+        assert!(cache.learn(&reply));
+
+        let released = cache.take_outbound();
+        assert_eq!(2, released.len());
+        assert!(released.iter().all(|packet| dst_of(packet) == peer_mac()));
+        assert_eq!(Some(peer_mac()), cache.lookup(peer_v4()));
+
+        // subsequent packets are sent right away.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let packet = cache.resolve(peer_v4(), packet).unwrap().unwrap();
+        assert_eq!(peer_mac(), dst_of(&packet));
+        assert!(cache.take_outbound().is_empty());
+    }
+
+    #[capsule::test]
+    fn set_dst_from_cache_lookup() {
+        let mut cache = new_cache();
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let mut ethernet = packet.parse::<Ethernet>().unwrap();
+
+        // the lookup doesn't start the resolution.
+        assert!(ethernet.resolve_and_set_dst(peer_v4(), &cache).is_err());
+        assert!(cache.is_empty());
+        assert!(cache.take_outbound().is_empty());
+
+        cache.add_static(peer_v4(), peer_mac());
+        ethernet.resolve_and_set_dst(peer_v4(), &cache).unwrap();
+        assert_eq!(peer_mac(), ethernet.dst());
+    }
+
+    #[capsule::test]
+    fn drop_oldest_when_queue_full() {
+        let config = NeighborConfig {
+            queue_len: 2,
+            ..NeighborConfig::default()
+        };
+        let mut cache = NeighborCache::new(local_mac(), config).with_ipv4(local_v4());
+
+        for _ in 0..4 {
+            let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+            assert!(cache.resolve(peer_v4(), packet).unwrap().is_none());
+        }
+        assert_eq!(2, cache.dropped());
+
+        let _ = cache.take_outbound();
+        assert!(cache.learn(&arp_reply(peer_v4(), peer_mac())));
+        assert_eq!(2, cache.take_outbound().len());
+    }
+
+    #[capsule::test]
+    fn reprobe_and_give_up() {
+        let mut cache = new_cache();
+        let now = Instant::now();
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(cache.resolve_at(peer_v4(), packet, now).unwrap().is_none());
+        assert_eq!(1, cache.take_outbound().len());
+
+        // too soon to probe again.
+        cache.tick_at(now + Duration::from_millis(500));
+        assert!(cache.take_outbound().is_empty());
+
+        cache.tick_at(now + Duration::from_secs(1));
+        assert_eq!(1, cache.take_outbound().len());
+        cache.tick_at(now + Duration::from_secs(2));
+        assert_eq!(1, cache.take_outbound().len());
+
+        // gives up after 3 probes and drops the queued packet.
+        cache.tick_at(now + Duration::from_secs(3));
+        assert!(cache.take_outbound().is_empty());
+        assert!(cache.is_empty());
+        assert_eq!(1, cache.dropped());
+    }
+
+    #[capsule::test]
+    fn reachable_to_stale_to_probe() {
+        let mut cache = new_cache();
+        let now = Instant::now();
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let _ = cache.resolve_at(peer_v4(), packet, now).unwrap();
+        let _ = cache.take_outbound();
+        cache.confirm(peer_v4(), Some(peer_mac()), true, now);
+        let _ = cache.take_outbound();
+
+        cache.tick_at(now + Duration::from_secs(30));
+        let entry = cache.entries().next().unwrap();
+        assert_eq!(NeighborState::Stale, entry.state);
+
+        // still usable, but triggers a unicast probe.
+        let later = now + Duration::from_secs(31);
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        let packet = cache.resolve_at(peer_v4(), packet, later).unwrap();
+        assert_eq!(peer_mac(), dst_of(&packet.unwrap()));
+
+        let outbound = cache.take_outbound();
+        assert_eq!(1, outbound.len());
+        assert_eq!(peer_mac(), dst_of(&outbound[0]));
+        assert_eq!(NeighborState::Probe, cache.entries().next().unwrap().state);
+    }
+
+    #[capsule::test]
+    fn remove_stale_neighbor() {
+        let mut cache = new_cache();
+        let now = Instant::now();
+        let _ = cache.neighbors.insert(
+            peer_v4(),
+            Neighbor::new(Some(peer_mac()), NeighborState::Stale, now),
+        );
+
+        cache.tick_at(now + Duration::from_secs(59));
+        assert_eq!(1, cache.len());
+        cache.tick_at(now + Duration::from_secs(60));
+        assert!(cache.is_empty());
+    }
+
+    #[capsule::test]
+    fn resolve_with_ndp() {
+        let mut cache = new_cache();
+
+        let packet = Mbuf::from_bytes(&IPV6_TCP_PACKET).unwrap();
+        assert!(cache
+            .resolve(IpAddr::V6(peer_v6()), packet)
+            .unwrap()
+            .is_none());
+
+        let outbound = cache.take_outbound();
+        assert_eq!(1, outbound.len());
+        let ethernet = outbound[0].peek::<Ethernet>().unwrap();
+        assert_eq!(MacAddr::new(0x33, 0x33, 0xff, 0, 0, 0x02), ethernet.dst());
+        let ipv6 = ethernet.peek::<Ipv6>().unwrap();
+        assert_eq!("ff02::1:ff00:2".parse::<Ipv6Addr>().unwrap(), ipv6.dst());
+        assert_eq!(255, ipv6.hop_limit());
+        let solicit = ipv6.peek::<NeighborSolicitation<Ipv6>>().unwrap();
+        assert_eq!(peer_v6(), solicit.target());
+
+        let advert = neighbor_advert(peer_v6(), peer_mac());
+        assert!(cache.learn(&advert));
+
+        let released = cache.take_outbound();
+        assert_eq!(1, released.len());
+        assert_eq!(peer_mac(), dst_of(&released[0]));
+    }
+
+    #[capsule::test]
+    fn static_entries() {
+        let mut cache = new_cache();
+
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(cache.resolve(peer_v4(), packet).unwrap().is_none());
+        let _ = cache.take_outbound();
+
+        // adding the static entry releases the queued packet.
+        cache.add_static(peer_v4(), peer_mac());
+        assert_eq!(1, cache.take_outbound().len());
+
+        // replies don't override static entries.
+        let other = MacAddr::new(0x02, 0, 0, 0, 0, 0x03);
+        assert!(cache.learn(&arp_reply(peer_v4(), other)));
+        assert_eq!(Some(peer_mac()), cache.lookup(peer_v4()));
+
+        cache.tick_at(Instant::now() + Duration::from_secs(3600));
+        let entries = cache.entries().collect::<Vec<_>>();
+        assert_eq!(
+            vec![NeighborEntry {
+                ip: peer_v4(),
+                mac: Some(peer_mac()),
+                state: NeighborState::Static,
+            }],
+            entries
+        );
+
+        assert!(cache.remove(peer_v4()));
+        assert!(cache.is_empty());
+    }
+
+    #[capsule::test]
+    fn transmit_through_neighbor_tx() {
+        let cache = Rc::new(RefCell::new(new_cache()));
+        let (sender, receiver) = mpsc::channel();
+        let mut tx = NeighborTx::new(cache.clone(), sender, |_: &Mbuf| Some(peer_v4()));
+
+        // only the ARP request goes out.
+        tx.transmit(vec![Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap()]);
+        let sent = receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(1, sent.len());
+        assert_eq!(MacAddr::BROADCAST, dst_of(&sent[0]));
+
+        // the queued packet goes out with the next transmit.
+        assert!(cache.borrow_mut().learn(&arp_reply(peer_v4(), peer_mac())));
+        tx.transmit(vec![Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap()]);
+        let sent = receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(2, sent.len());
+        assert!(sent.iter().all(|packet| dst_of(packet) == peer_mac()));
+    }
+
+    #[capsule::test]
+    fn ignore_unrelated_packets() {
+        let mut cache = new_cache();
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(!cache.learn(&packet));
+    }
+
+    #[capsule::test]
+    fn no_local_address() {
+        let mut cache = NeighborCache::new(local_mac(), NeighborConfig::default());
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(cache.resolve(peer_v4(), packet).is_err());
+        assert!(cache.is_empty());
+    }
+}
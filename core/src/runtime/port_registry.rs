@@ -0,0 +1,186 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::dpdk::{CoreId, PortId, PortQueue};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// A shared lookup of the currently active ports by name.
+///
+/// Ports added to the runtime with [`Runtime::add_port`] are registered
+/// here, and ports removed with [`Runtime::remove_port`] are unregistered
+/// before their devices are closed. Pipelines that need to work with ports
+/// coming and going should look them up here on every poll instead of
+/// capturing a fixed set of `PortQueue`s when they are installed.
+///
+/// Lookups return the queues owned by the current core. The lock is only
+/// taken for writing when a port is added or removed, so the readers on
+/// the polling cores don't contend with each other.
+///
+/// # Example
+///
+/// ```
+/// let registry = runtime.port_registry();
+///
+/// runtime.add_periodic_task_to_core(1, move || {
+///     for (name, mut q) in registry.active_ports() {
+///         let packets = q.receive();
+///         ...
+///     }
+/// }, Duration::from_millis(1))?;
+/// ```
+///
+/// [`Runtime::add_port`]: crate::Runtime::add_port
+/// [`Runtime::remove_port`]: crate::Runtime::remove_port
+#[derive(Clone, Default)]
+pub struct PortRegistry {
+    ports: Arc<RwLock<HashMap<String, HashMap<CoreId, PortQueue>>>>,
+}
+
+impl PortRegistry {
+    /// Registers the queues of a port.
+    pub(crate) fn insert(&self, name: &str, queues: HashMap<CoreId, PortQueue>) {
+        self.ports.write().unwrap().insert(name.to_owned(), queues);
+    }
+
+    /// Unregisters a port. Returns whether the port was registered.
+    pub(crate) fn remove(&self, name: &str) -> bool {
+        self.ports.write().unwrap().remove(name).is_some()
+    }
+
+    /// Returns whether a port with the name is registered.
+    pub fn contains(&self, name: &str) -> bool {
+        self.ports.read().unwrap().contains_key(name)
+    }
+
+    /// Returns the queue of the port owned by the current core.
+    ///
+    /// Returns `None` if the port is not active or the current core is not
+    /// assigned to the port.
+    pub fn get(&self, name: &str) -> Option<PortQueue> {
+        self.get_for_core(name, CoreId::current())
+    }
+
+    /// Returns the queue of the port owned by the core.
+    pub(crate) fn get_for_core(&self, name: &str, core_id: CoreId) -> Option<PortQueue> {
+        self.ports
+            .read()
+            .unwrap()
+            .get(name)
+            .and_then(|queues| queues.get(&core_id))
+            .cloned()
+    }
+
+    /// Returns the ID of the port, to read its device counters from any
+    /// thread.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let stats = registry.port_id("eth1").unwrap().stats()?;
+    /// println!("missed by the NIC: {}", stats.imissed);
+    /// ```
+    pub fn port_id(&self, name: &str) -> Option<PortId> {
+        self.ports
+            .read()
+            .unwrap()
+            .get(name)
+            .and_then(|queues| queues.values().next())
+            .map(PortQueue::port_id)
+    }
+
+    /// Returns the names of all the active ports.
+    pub fn names(&self) -> Vec<String> {
+        self.ports.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns the queues of the currently active ports owned by the
+    /// current core, keyed by the port name.
+    pub fn active_ports(&self) -> Vec<(String, PortQueue)> {
+        self.active_ports_for_core(CoreId::current())
+    }
+
+    /// Returns the queues of the currently active ports owned by the core.
+    pub(crate) fn active_ports_for_core(&self, core_id: CoreId) -> Vec<(String, PortQueue)> {
+        self.ports
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, queues)| queues.get(&core_id).map(|q| (name.clone(), q.clone())))
+            .collect()
+    }
+
+    /// Returns the number of active ports.
+    pub fn len(&self) -> usize {
+        self.ports.read().unwrap().len()
+    }
+
+    /// Returns whether there are no active ports.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for PortRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PortRegistry")
+            .field("ports", &self.names())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dpdk::{self, Mempool};
+    use crate::testils::TestVdev;
+
+    #[capsule::test]
+    fn attach_and_detach_null_vdev() {
+        let core_id = CoreId::new(0);
+        let vdev = TestVdev::new("net_null");
+        let mut mempools = vec![Mempool::new(63, 0, core_id.socket_id()).unwrap()];
+        let registry = PortRegistry::default();
+
+        let mut port = vdev.port("vm1", &[core_id], &mut mempools);
+        port.start().unwrap();
+
+        registry.insert(port.name(), port.queues().clone());
+        assert!(registry.contains("vm1"));
+        assert_eq!(1, registry.active_ports_for_core(core_id).len());
+        assert!(registry.get_for_core("vm1", core_id).is_some());
+        assert!(registry.get_for_core("vm1", CoreId::new(1)).is_none());
+        assert_eq!(Some(port.id()), registry.port_id("vm1"));
+        assert_eq!(None, registry.port_id("vm2"));
+
+        assert!(registry.remove("vm1"));
+        assert!(!registry.remove("vm1"));
+        assert!(registry.is_empty());
+        assert!(registry.active_ports_for_core(core_id).is_empty());
+
+        port.stop();
+        drop(port);
+        let device = vdev.device().to_owned();
+        drop(vdev);
+
+        // the device can be attached again once detached.
+        dpdk::dev_hotplug_add("vdev", &device, "").unwrap();
+        dpdk::dev_hotplug_remove("vdev", &device).unwrap();
+    }
+}
@@ -0,0 +1,496 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::packets::ip::IpPacket;
+use crate::packets::{Packet, Tcp};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// An event emitted by the `ReorderBuffer`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReorderEvent {
+    /// Contiguous in-order bytes of the stream.
+    Data {
+        /// The sequence number of the first byte.
+        seq: u32,
+        /// The bytes.
+        payload: Vec<u8>,
+    },
+
+    /// Bytes of the stream that are never going to arrive, because the
+    /// buffer filled up before the gap was filled.
+    Loss {
+        /// The sequence number of the first missing byte.
+        seq: u32,
+        /// The number of missing bytes.
+        len: u32,
+    },
+}
+
+/// A buffer that puts the TCP segments of one direction of a flow back in
+/// order.
+///
+/// Segments are pushed in the order they arrive, and the buffer emits the
+/// stream's payload in order as it becomes contiguous. Retransmitted bytes
+/// that were already emitted or buffered are trimmed, so every byte is
+/// emitted at most once.
+///
+/// Out-of-order segments are buffered up to a maximum number of bytes and
+/// segments. When either limit is exceeded, the buffer stops waiting for
+/// the missing bytes, reports them with a `Loss` event, and moves on to
+/// the buffered data.
+///
+/// The buffer copies the payload, so packets can be freed or forwarded
+/// right after they are pushed without holding on to the mempool.
+///
+/// Application layer parsers that need the in-order stream, such as a TLS
+/// or an HTTP parser, should consume the `Data` events instead of the raw
+/// packets, and resynchronize on `Loss`.
+///
+/// # Example
+///
+/// ```
+/// let mut flows = FlowTable::new(4096, Duration::from_secs(60));
+///
+/// Poll::new(q.clone())
+///     .for_each(move |packet| {
+///         let tcp = packet.peek::<Ethernet>()?.peek::<Ipv4>()?.peek::<Tcp4>()?;
+///         let buffer = flows
+///             .get_or_insert_with(FlowKey::from(&*tcp), || ReorderBuffer::new(65536, 64));
+///         for event in buffer.push(&tcp) {
+///             parser.feed(event);
+///         }
+///         Ok(())
+///     })
+///     .send(q)
+/// ```
+pub struct ReorderBuffer {
+    // sequence number of the next expected byte.
+    next_seq: Option<u32>,
+    // stream position of the next expected byte, which unlike the
+    // sequence number doesn't wrap around.
+    next_pos: u64,
+    // out-of-order segments keyed by their stream position. the segments
+    // never overlap.
+    segments: BTreeMap<u64, Vec<u8>>,
+    buffered: usize,
+    max_bytes: usize,
+    max_segments: usize,
+    lost: u64,
+}
+
+impl ReorderBuffer {
+    /// Creates a new reorder buffer that holds up to `max_bytes` bytes in
+    /// up to `max_segments` out-of-order segments.
+    pub fn new(max_bytes: usize, max_segments: usize) -> Self {
+        ReorderBuffer {
+            next_seq: None,
+            next_pos: 0,
+            segments: BTreeMap::new(),
+            buffered: 0,
+            max_bytes,
+            max_segments,
+            lost: 0,
+        }
+    }
+
+    /// Returns the sequence number of the next expected byte, or `None` if
+    /// no segment has been pushed yet.
+    #[inline]
+    pub fn next_seq(&self) -> Option<u32> {
+        self.next_seq
+    }
+
+    /// Returns the number of out-of-order bytes buffered.
+    #[inline]
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered
+    }
+
+    /// Returns the number of out-of-order segments buffered.
+    #[inline]
+    pub fn buffered_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Returns the total number of bytes reported lost.
+    #[inline]
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// Pushes a TCP segment and returns the events it completes.
+    ///
+    /// The stream starts after the `SYN` if it's seen. Otherwise, the
+    /// stream starts at the first segment pushed.
+    ///
+    /// The payload length is derived from the IP length, so the padding of
+    /// short frames is left out, and the payload of a coalesced packet is
+    /// read across all its segments.
+    pub fn push<E: IpPacket>(&mut self, tcp: &Tcp<E>) -> Vec<ReorderEvent> {
+        let mut seq = tcp.seq_no();
+
+        if tcp.syn() {
+            seq = seq.wrapping_add(1);
+            if self.next_seq.is_none() {
+                self.next_seq = Some(seq);
+            }
+        }
+
+        // the IP length of a coalesced packet may be off, but the packet
+        // is never padded.
+        let end = if tcp.mbuf().coalesced_segment_size().is_some() {
+            tcp.offset() + tcp.full_len()
+        } else {
+            cmp::min(tcp.envelope().datagram_end(), tcp.offset() + tcp.len())
+        };
+
+        let offset = tcp.payload_offset();
+        if end <= offset {
+            return vec![];
+        }
+
+        let payload = tcp.mbuf().read_segments_upto(offset, end - offset);
+        self.push_segment(seq, &payload)
+    }
+
+    /// Pushes the payload of a segment starting at sequence number `seq`,
+    /// and returns the events it completes.
+    pub fn push_segment(&mut self, seq: u32, payload: &[u8]) -> Vec<ReorderEvent> {
+        if payload.is_empty() {
+            return vec![];
+        }
+
+        let next_seq = *self.next_seq.get_or_insert(seq);
+
+        // the distance from the next expected byte, negative for
+        // retransmitted bytes already emitted.
+        let offset = seq.wrapping_sub(next_seq) as i32 as i64;
+        let end = offset + payload.len() as i64;
+        if end <= 0 {
+            return vec![];
+        }
+
+        let (offset, payload) = if offset < 0 {
+            (0, &payload[(-offset) as usize..])
+        } else {
+            (offset as u64, payload)
+        };
+
+        self.insert(self.next_pos + offset, payload);
+
+        let mut events = vec![];
+        self.drain(&mut events);
+
+        // gives up on the gap in front of the buffered data until the
+        // buffer is back within its limits.
+        while self.buffered > self.max_bytes || self.segments.len() > self.max_segments {
+            self.skip_gap(&mut events);
+            self.drain(&mut events);
+        }
+
+        events
+    }
+
+    /// Reports the remaining gaps as lost and emits all the buffered data.
+    /// Should be called when the flow ends.
+    pub fn flush(&mut self) -> Vec<ReorderEvent> {
+        let mut events = vec![];
+
+        while !self.segments.is_empty() {
+            self.skip_gap(&mut events);
+            self.drain(&mut events);
+        }
+
+        events
+    }
+
+    /// Buffers the bytes not already buffered, so segments never overlap.
+    fn insert(&mut self, pos: u64, payload: &[u8]) {
+        let end = pos + payload.len() as u64;
+        let mut pieces = vec![];
+        let mut cursor = pos;
+
+        for (&start, segment) in self.segments.range(..end) {
+            let segment_end = start + segment.len() as u64;
+            if segment_end <= cursor {
+                continue;
+            }
+            if start > cursor {
+                pieces.push((cursor, start));
+            }
+            cursor = cursor.max(segment_end);
+        }
+
+        if cursor < end {
+            pieces.push((cursor, end));
+        }
+
+        for (start, stop) in pieces {
+            let bytes = payload[(start - pos) as usize..(stop - pos) as usize].to_vec();
+            self.buffered += bytes.len();
+            let _ = self.segments.insert(start, bytes);
+        }
+    }
+
+    /// Emits the buffered segments that are contiguous with the stream.
+    fn drain(&mut self, events: &mut Vec<ReorderEvent>) {
+        let seq = match self.next_seq {
+            Some(seq) => seq,
+            None => return,
+        };
+
+        let mut payload = vec![];
+        while let Some(segment) = self.segments.remove(&self.next_pos) {
+            self.buffered -= segment.len();
+            self.next_pos += segment.len() as u64;
+            payload.extend_from_slice(&segment);
+        }
+
+        if !payload.is_empty() {
+            self.next_seq = Some(seq.wrapping_add(payload.len() as u32));
+            events.push(ReorderEvent::Data { seq, payload });
+        }
+    }
+
+    /// Reports the gap before the first buffered segment as lost.
+    fn skip_gap(&mut self, events: &mut Vec<ReorderEvent>) {
+        let (seq, first) = match (self.next_seq, self.segments.keys().next()) {
+            (Some(seq), Some(&first)) => (seq, first),
+            _ => return,
+        };
+
+        let len = (first - self.next_pos) as u32;
+        if len > 0 {
+            self.lost += len as u64;
+            self.next_pos = first;
+            self.next_seq = Some(seq.wrapping_add(len));
+            events.push(ReorderEvent::Loss { seq, len });
+        }
+    }
+}
+
+impl fmt::Debug for ReorderBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReorderBuffer")
+            .field("next_seq", &self.next_seq)
+            .field("buffered_bytes", &self.buffered)
+            .field("buffered_segments", &self.segments.len())
+            .field("max_bytes", &self.max_bytes)
+            .field("max_segments", &self.max_segments)
+            .field("lost", &self.lost)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::{Ethernet, Tcp4};
+    use crate::testils::byte_arrays::IPV4_TCP_PACKET;
+    use crate::Mbuf;
+
+    const STREAM: &[u8] = b"the quick brown fox jumps over the lazy dog";
+
+    fn data(events: &[ReorderEvent]) -> Vec<u8> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                ReorderEvent::Data { payload, .. } => Some(payload.clone()),
+                ReorderEvent::Loss { .. } => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Splits the stream into segments of 5 bytes starting at `isn`.
+    fn segments(isn: u32) -> Vec<(u32, &'static [u8])> {
+        STREAM
+            .chunks(5)
+            .enumerate()
+            .map(|(i, chunk)| (isn.wrapping_add(i as u32 * 5), chunk))
+            .collect()
+    }
+
+    #[test]
+    fn in_order_segments() {
+        let mut buffer = ReorderBuffer::new(1024, 16);
+        let mut events = vec![];
+
+        for (seq, payload) in segments(1000) {
+            let emitted = buffer.push_segment(seq, payload);
+            assert_eq!(1, emitted.len());
+            events.extend(emitted);
+        }
+
+        assert_eq!(STREAM, &data(&events)[..]);
+        assert_eq!(0, buffer.buffered_bytes());
+    }
+
+    #[test]
+    fn shuffled_segments() {
+        // the first segment fixes the start of the stream.
+        let order = [0, 3, 1, 8, 5, 2, 7, 4, 6];
+        let segments = segments(u32::max_value() - 12);
+        let mut buffer = ReorderBuffer::new(1024, 16);
+        let mut events = vec![];
+
+        for &i in order.iter() {
+            let (seq, payload) = segments[i];
+            events.extend(buffer.push_segment(seq, payload));
+        }
+
+        assert_eq!(STREAM, &data(&events)[..]);
+        assert_eq!(0, buffer.buffered_segments());
+        assert_eq!(
+            Some(segments[0].0.wrapping_add(STREAM.len() as u32)),
+            buffer.next_seq()
+        );
+    }
+
+    #[test]
+    fn trim_overlapping_retransmissions() {
+        let mut buffer = ReorderBuffer::new(1024, 16);
+        let mut events = vec![];
+
+        events.extend(buffer.push_segment(100, &STREAM[..10]));
+        // out of order, then partially overlapped by a retransmission.
+        events.extend(buffer.push_segment(120, &STREAM[20..30]));
+        events.extend(buffer.push_segment(115, &STREAM[15..25]));
+        // fully retransmitted bytes are ignored.
+        events.extend(buffer.push_segment(100, &STREAM[..10]));
+        assert_eq!(15, buffer.buffered_bytes());
+
+        // overlaps both the emitted and the buffered bytes.
+        events.extend(buffer.push_segment(105, &STREAM[5..35]));
+        events.extend(buffer.push_segment(135, &STREAM[35..]));
+
+        assert_eq!(STREAM, &data(&events)[..]);
+        assert_eq!(0, buffer.buffered_bytes());
+    }
+
+    #[test]
+    fn report_gap_as_loss() {
+        let mut buffer = ReorderBuffer::new(12, 16);
+        let mut events = vec![];
+
+        events.extend(buffer.push_segment(0, &STREAM[..5]));
+        // bytes 5..10 never arrive.
+        events.extend(buffer.push_segment(10, &STREAM[10..15]));
+        events.extend(buffer.push_segment(15, &STREAM[15..20]));
+        assert_eq!(10, buffer.buffered_bytes());
+
+        // exceeds the byte limit.
+        let emitted = buffer.push_segment(20, &STREAM[20..25]);
+        assert_eq!(
+            vec![
+                ReorderEvent::Loss { seq: 5, len: 5 },
+                ReorderEvent::Data {
+                    seq: 10,
+                    payload: STREAM[10..25].to_vec()
+                },
+            ],
+            emitted
+        );
+        assert_eq!(5, buffer.lost());
+
+        // the late bytes are dropped as retransmissions.
+        assert!(buffer.push_segment(5, &STREAM[5..10]).is_empty());
+        events.extend(emitted);
+        assert_eq!([&STREAM[..5], &STREAM[10..25]].concat(), data(&events));
+    }
+
+    #[test]
+    fn limit_buffered_segments() {
+        let mut buffer = ReorderBuffer::new(1024, 2);
+
+        assert_eq!(1, buffer.push_segment(0, &STREAM[..5]).len());
+        assert!(buffer.push_segment(10, &STREAM[10..15]).is_empty());
+        assert!(buffer.push_segment(20, &STREAM[20..25]).is_empty());
+
+        let emitted = buffer.push_segment(30, &STREAM[30..35]);
+        assert_eq!(ReorderEvent::Loss { seq: 5, len: 5 }, emitted[0]);
+        assert_eq!(2, buffer.buffered_segments());
+    }
+
+    #[test]
+    fn flush_remaining_segments() {
+        let mut buffer = ReorderBuffer::new(1024, 16);
+
+        let _ = buffer.push_segment(0, &STREAM[..5]);
+        let _ = buffer.push_segment(10, &STREAM[10..15]);
+        let _ = buffer.push_segment(20, &STREAM[20..25]);
+
+        assert_eq!(
+            vec![
+                ReorderEvent::Loss { seq: 5, len: 5 },
+                ReorderEvent::Data {
+                    seq: 10,
+                    payload: STREAM[10..15].to_vec()
+                },
+                ReorderEvent::Loss { seq: 15, len: 5 },
+                ReorderEvent::Data {
+                    seq: 20,
+                    payload: STREAM[20..25].to_vec()
+                },
+            ],
+            buffer.flush()
+        );
+        assert_eq!(0, buffer.buffered_bytes());
+    }
+
+    #[capsule::test]
+    fn push_tcp_packet() {
+        let packet = Mbuf::from_bytes(&IPV4_TCP_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let tcp = ipv4.parse::<Tcp4>().unwrap();
+
+        let mut buffer = ReorderBuffer::new(1024, 16);
+        let events = buffer.push(&tcp);
+
+        let seq = if tcp.syn() {
+            tcp.seq_no().wrapping_add(1)
+        } else {
+            tcp.seq_no()
+        };
+        let expected = tcp.payload_len() as u32;
+        assert_eq!(Some(seq.wrapping_add(expected)), buffer.next_seq());
+        assert_eq!(expected as usize, data(&events).len());
+    }
+
+    #[capsule::test]
+    fn push_padded_tcp_packet() {
+        let mut bytes = IPV4_TCP_PACKET.to_vec();
+        bytes.extend_from_slice(&[0; 16]);
+
+        let packet = Mbuf::from_bytes(&bytes).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let tcp = ipv4.parse::<Tcp4>().unwrap();
+
+        let mut buffer = ReorderBuffer::new(1024, 16);
+        let events = buffer.push(&tcp);
+
+        // the padding isn't part of the stream.
+        let expected = tcp.payload_len() - 16;
+        assert_eq!(expected, data(&events).len());
+    }
+}
@@ -0,0 +1,208 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::packets::Ethernet;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// A token bucket of frames that holds up to one second worth of tokens.
+#[derive(Debug)]
+struct VlanBucket {
+    pps: u32,
+    tokens: f64,
+    updated: Option<Instant>,
+    denied: u64,
+}
+
+impl VlanBucket {
+    fn new(pps: u32) -> Self {
+        VlanBucket {
+            pps,
+            tokens: pps as f64,
+            updated: None,
+            denied: 0,
+        }
+    }
+
+    /// Takes a token if there's one available at `now`.
+    fn take_at(&mut self, now: Instant) -> bool {
+        let burst = self.pps as f64;
+        if let Some(updated) = self.updated {
+            let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * burst).min(burst);
+        }
+        self.updated = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            self.denied += 1;
+            false
+        }
+    }
+}
+
+/// A per-VLAN frame rate limiter.
+///
+/// Each VLAN with a limit has its own token bucket, keyed on the identifier
+/// of the outer tag. Untagged and priority-tagged frames share the default
+/// bucket. The frames of VLANs without a limit are always allowed, and so
+/// are the untagged frames until the default limit is set. A bucket holds
+/// up to one second worth of frames, so an idle VLAN can burst up to its
+/// limit.
+///
+/// The buckets refill as the frames come in, so the limiter needs no timer.
+/// It's not shared, keep one per core with its share of the rates to
+/// avoid contention.
+///
+/// # Example
+///
+/// ```
+/// let mut limiter = VlanRateLimiter::new();
+/// limiter.set_limit(100, 10_000);
+/// limiter.set_default_limit(1_000);
+///
+/// let mut batch = batch.filter(move |ethernet| limiter.allow(ethernet));
+/// ```
+#[derive(Debug, Default)]
+pub struct VlanRateLimiter {
+    default: Option<VlanBucket>,
+    buckets: HashMap<u16, VlanBucket>,
+}
+
+impl VlanRateLimiter {
+    /// Creates a new limiter without any limits.
+    pub fn new() -> Self {
+        VlanRateLimiter::default()
+    }
+
+    /// Limits the frames of the VLAN `vid` to `pps` frames per second.
+    /// Only the lower 12 bits of `vid` are used. Replaces the previous
+    /// limit, with a full bucket.
+    pub fn set_limit(&mut self, vid: u16, pps: u32) {
+        let _ = self.buckets.insert(vid & 0x0fff, VlanBucket::new(pps));
+    }
+
+    /// Removes the limit of the VLAN `vid`. Returns whether the VLAN had a
+    /// limit.
+    pub fn remove_limit(&mut self, vid: u16) -> bool {
+        self.buckets.remove(&(vid & 0x0fff)).is_some()
+    }
+
+    /// Limits the untagged and priority-tagged frames to `pps` frames per
+    /// second.
+    pub fn set_default_limit(&mut self, pps: u32) {
+        self.default = Some(VlanBucket::new(pps));
+    }
+
+    /// Returns the number of frames denied for the VLAN `vid`, or for the
+    /// untagged and priority-tagged frames if `None`.
+    pub fn denied(&self, vid: Option<u16>) -> u64 {
+        let bucket = match vid {
+            Some(vid) => self.buckets.get(&(vid & 0x0fff)),
+            None => self.default.as_ref(),
+        };
+        bucket.map(|bucket| bucket.denied).unwrap_or(0)
+    }
+
+    /// Returns whether the frame is within the budget of its VLAN, and
+    /// takes a token from the bucket if it is. The caller decides whether
+    /// to drop or forward the frames over budget.
+    #[inline]
+    pub fn allow(&mut self, frame: &Ethernet) -> bool {
+        self.allow_at(frame, Instant::now())
+    }
+
+    fn allow_at(&mut self, frame: &Ethernet, now: Instant) -> bool {
+        let bucket = match frame.switching_vid() {
+            Some(vid) => self.buckets.get_mut(&vid),
+            None => self.default.as_mut(),
+        };
+
+        match bucket {
+            Some(bucket) => bucket.take_at(now),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::Packet;
+    use crate::testils::byte_arrays::{IPV4_UDP_PACKET, VLAN_DOT1Q_PACKET};
+    use crate::Mbuf;
+    use std::time::Duration;
+
+    #[capsule::test]
+    fn limit_vlan_rates() {
+        let mut limiter = VlanRateLimiter::new();
+        limiter.set_limit(123, 2);
+
+        let tagged = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET)
+            .unwrap()
+            .parse::<Ethernet>()
+            .unwrap();
+        let untagged = Mbuf::from_bytes(&IPV4_UDP_PACKET)
+            .unwrap()
+            .parse::<Ethernet>()
+            .unwrap();
+
+        let now = Instant::now();
+        assert!(limiter.allow_at(&tagged, now));
+        assert!(limiter.allow_at(&tagged, now));
+        assert!(!limiter.allow_at(&tagged, now));
+        assert_eq!(1, limiter.denied(Some(123)));
+
+        // half a second refills one token.
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.allow_at(&tagged, later));
+        assert!(!limiter.allow_at(&tagged, later));
+
+        // untagged frames are not limited until the default is set.
+        assert!(limiter.allow_at(&untagged, now));
+        assert!(limiter.allow_at(&untagged, now));
+        limiter.set_default_limit(1);
+        assert!(limiter.allow_at(&untagged, now));
+        assert!(!limiter.allow_at(&untagged, now));
+        assert_eq!(1, limiter.denied(None));
+
+        assert!(limiter.remove_limit(123));
+        assert!(limiter.allow_at(&tagged, later));
+        assert_eq!(0, limiter.denied(Some(123)));
+    }
+
+    #[capsule::test]
+    fn limit_priority_tagged_as_untagged() {
+        let mut bytes = VLAN_DOT1Q_PACKET;
+        bytes[14] = 0xa0;
+        bytes[15] = 0x00;
+        let frame = Mbuf::from_bytes(&bytes)
+            .unwrap()
+            .parse::<Ethernet>()
+            .unwrap();
+
+        let mut limiter = VlanRateLimiter::new();
+        limiter.set_limit(0, 0);
+        assert!(limiter.allow_at(&frame, Instant::now()));
+
+        limiter.set_default_limit(0);
+        assert!(!limiter.allow_at(&frame, Instant::now()));
+    }
+}
@@ -0,0 +1,1149 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Lightweight per-core runtime statistics.
+//!
+//! Every pipeline and every port queue keeps a set of counters for the
+//! core it runs on. The counters are only ever written to by the owning
+//! core, and can be read by any other thread at any time without stopping
+//! the data path. Use [`RuntimeStats::snapshot`] to collect a point-in-time
+//! copy of all the counters.
+//!
+//! # Pipeline Counters
+//!
+//! * `runs`, total number of times the pipeline executes.
+//! * `received`, total number of packets polled from the pipeline's source.
+//! * `transmitted`, total number of packets handed to the pipeline's TX.
+//! * `emitted`, total number of packets sent through [`emit`].
+//! * `dropped`, total number of packets intentionally dropped. `filtered`
+//! and `replaced` further break down the drops by cause.
+//! * `errored`, total number of packets aborted due to processing errors.
+//! * `busy`, total time spent processing batches.
+//!
+//! # Port Queue Counters
+//!
+//! * `received`, total number of packets received from the RX queue.
+//! * `transmitted`, total number of packets sent through the TX queue.
+//! * `dropped`, total number of packets dropped because the TX queue is full.
+//! * `overflowed`, total number of packets dropped because the TX backlog
+//! is full. See [`TxPolicy`] for when packets are held in the backlog.
+//! * `filtered`, total number of received packets dropped by the port's
+//! software RX filter, before reaching the pipeline. These are not counted
+//! as pipeline drops.
+//! * `rx_burst`, the number of packets the RX queue is currently polled for
+//! at a time. See [`RxBurstMode`] for when it changes.
+//!
+//! # Periodic Task Counters
+//!
+//! Every task added with [`Pipeline::every`] keeps its own counters, under
+//! the name of the pipeline it's attached to.
+//!
+//! * `calls`, total number of times the task is invoked.
+//! * `missed`, total number of invocations skipped because the poll loop
+//! fell behind by more than one period.
+//! * `overran`, total number of invocations that took longer than the
+//! period.
+//! * `busy`, total time spent in the task. `max_busy` is the longest single
+//! invocation.
+//! * `max_late`, the longest delay between when an invocation is due and
+//! when it's made.
+//!
+//! # Stage Counters
+//!
+//! Every stage of a [`Chain`] keeps its own counters, under the stage name.
+//!
+//! * `processed`, total number of packets the stage processes.
+//! * `bypassed`, total number of packets passed through untouched while
+//! the stage is disabled.
+//! * `dropped`, total number of packets the stage drops.
+//! * `errored`, total number of packets aborted due to stage errors.
+//!
+//! # Capture Counters
+//!
+//! Every port or pipeline captured with the [`CaptureController`] keeps
+//! its own counters, under the name of the [`CaptureTarget`].
+//!
+//! * `active`, whether the capture is currently on.
+//! * `packets`, total number of packets written to the capture files.
+//! * `filtered`, total number of packets the capture filter skips.
+//! * `bytes`, total number of bytes written to the capture files.
+//! * `files`, total number of capture files opened, including the rotated
+//! ones that are since deleted.
+//!
+//! # Parse Error Counters
+//!
+//! With the `parse-errors` feature, every core counts the frames that fail
+//! to parse as Ethernet, by reason. Use [`parse_error_stats`] for the
+//! current core and [`aggregate_parse_error_stats`] for all the cores.
+//!
+//! * `out_of_buffer`, total number of frames too short for the Ethernet
+//! header.
+//! * `bad_vlan`, total number of frames too short for their VLAN tags.
+//! * `oversized`, total number of frames longer than the maximum frame
+//! length of the parse options.
+//!
+//! # Custom Counters
+//!
+//! Applications can create their own counters with [`RuntimeStats::counter`].
+//! Counters with the same name are aggregated together in the snapshot.
+//!
+//! # Example
+//!
+//! ```
+//! Runtime::build(config)?
+//!     .add_pipeline_to_port("eth1", install)?
+//!     .add_periodic_task_to_core(0, || {
+//!         let snapshot = RuntimeStats::snapshot();
+//!         println!("{:?}", snapshot.pipeline("default"));
+//!     }, Duration::from_secs(1))?
+//!     .execute()
+//! ```
+//!
+//! [`emit`]: crate::batch::Batch::emit
+//! [`TxPolicy`]: crate::TxPolicy
+//! [`RxBurstMode`]: crate::RxBurstMode
+//! [`Pipeline::every`]: crate::batch::Pipeline::every
+//! [`Chain`]: crate::batch::Chain
+//! [`CaptureController`]: crate::capture::CaptureController
+//! [`CaptureTarget`]: crate::capture::CaptureTarget
+
+use crate::dpdk::CoreId;
+use once_cell::sync::Lazy;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The cause of a packet dropped by a pipeline.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DropReason {
+    /// Dropped by a `filter`, a `filter_map`, a `demux` or a chain stage.
+    Filtered,
+    /// The original packet dropped by a `replace` combinator.
+    Replaced,
+    /// Aborted due to a processing error, for example a packet that fails
+    /// to parse.
+    Errored,
+}
+
+impl fmt::Display for DropReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DropReason::Filtered => write!(f, "filtered"),
+            DropReason::Replaced => write!(f, "replaced"),
+            DropReason::Errored => write!(f, "errored"),
+        }
+    }
+}
+
+/// Increments an atomic counter.
+#[inline]
+fn incr(counter: &AtomicU64, value: u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+/// Reads an atomic counter.
+#[inline]
+fn read(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+/// The counters for a pipeline running on one core.
+#[derive(Debug, Default)]
+pub(crate) struct PipelineCounters {
+    runs: AtomicU64,
+    received: AtomicU64,
+    transmitted: AtomicU64,
+    emitted: AtomicU64,
+    dropped: AtomicU64,
+    filtered: AtomicU64,
+    replaced: AtomicU64,
+    errored: AtomicU64,
+    busy_nanos: AtomicU64,
+}
+
+impl PipelineCounters {
+    /// Makes the counters the target of the combinators executed on the
+    /// current thread until the returned guard goes out of scope.
+    #[inline]
+    pub(crate) fn enter(self: &Arc<Self>) -> PipelineScope {
+        let prev = CURRENT.with(|c| c.replace(Some(self.clone())));
+        PipelineScope { prev }
+    }
+
+    /// Records the outcome of one pipeline run.
+    #[inline]
+    pub(crate) fn record_run(
+        &self,
+        transmitted: u64,
+        emitted: u64,
+        dropped: u64,
+        errored: u64,
+        busy: Duration,
+    ) {
+        incr(&self.runs, 1);
+        incr(&self.transmitted, transmitted);
+        incr(&self.emitted, emitted);
+        incr(&self.dropped, dropped);
+        incr(&self.errored, errored);
+        incr(&self.busy_nanos, busy.as_nanos() as u64);
+    }
+
+    fn snapshot(&self) -> PipelineStats {
+        PipelineStats {
+            runs: read(&self.runs),
+            received: read(&self.received),
+            transmitted: read(&self.transmitted),
+            emitted: read(&self.emitted),
+            dropped: read(&self.dropped),
+            filtered: read(&self.filtered),
+            replaced: read(&self.replaced),
+            errored: read(&self.errored),
+            busy: Duration::from_nanos(read(&self.busy_nanos)),
+        }
+    }
+}
+
+/// Guard that restores the previously entered pipeline counters on drop.
+pub(crate) struct PipelineScope {
+    prev: Option<Arc<PipelineCounters>>,
+}
+
+impl Drop for PipelineScope {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT.with(|c| c.replace(prev));
+    }
+}
+
+thread_local! {
+    /// The counters of the pipeline currently executing on this thread.
+    static CURRENT: RefCell<Option<Arc<PipelineCounters>>> = RefCell::new(None);
+}
+
+/// Records packets received by the pipeline currently executing.
+#[inline]
+pub(crate) fn record_received(count: usize) {
+    CURRENT.with(|c| {
+        if let Some(counters) = c.borrow().as_ref() {
+            incr(&counters.received, count as u64);
+        }
+    });
+}
+
+/// Records the cause of a packet dropped by the pipeline currently
+/// executing.
+#[inline]
+pub(crate) fn record_dropped(reason: DropReason) {
+    CURRENT.with(|c| {
+        if let Some(counters) = c.borrow().as_ref() {
+            match reason {
+                DropReason::Filtered => incr(&counters.filtered, 1),
+                DropReason::Replaced => incr(&counters.replaced, 1),
+                // counted as `errored` at the end of the pipeline run.
+                DropReason::Errored => (),
+            }
+        }
+    });
+}
+
+/// The counters for a port queue owned by one core.
+#[derive(Debug, Default)]
+pub(crate) struct QueueCounters {
+    received: AtomicU64,
+    transmitted: AtomicU64,
+    dropped: AtomicU64,
+    overflowed: AtomicU64,
+    filtered: AtomicU64,
+    rx_burst: AtomicU64,
+}
+
+impl QueueCounters {
+    /// Records packets received from the RX queue.
+    #[inline]
+    pub(crate) fn record_received(&self, count: u64) {
+        incr(&self.received, count);
+    }
+
+    /// Records packets sent through the TX queue.
+    #[inline]
+    pub(crate) fn record_transmitted(&self, count: u64) {
+        incr(&self.transmitted, count);
+    }
+
+    /// Records packets dropped because the TX queue is full.
+    #[inline]
+    pub(crate) fn record_dropped(&self, count: u64) {
+        incr(&self.dropped, count);
+    }
+
+    /// Records packets dropped because the TX backlog is full.
+    #[inline]
+    pub(crate) fn record_overflowed(&self, count: u64) {
+        incr(&self.overflowed, count);
+    }
+
+    /// Records received packets dropped by the RX filter.
+    #[inline]
+    pub(crate) fn record_filtered(&self, count: u64) {
+        incr(&self.filtered, count);
+    }
+
+    /// Records the effective RX burst size.
+    #[inline]
+    pub(crate) fn record_rx_burst(&self, size: usize) {
+        self.rx_burst.store(size as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> PortQueueStats {
+        PortQueueStats {
+            received: read(&self.received),
+            transmitted: read(&self.transmitted),
+            dropped: read(&self.dropped),
+            overflowed: read(&self.overflowed),
+            filtered: read(&self.filtered),
+            rx_burst: read(&self.rx_burst),
+        }
+    }
+}
+
+/// The counters for a periodic task running on one core.
+#[derive(Debug, Default)]
+pub(crate) struct TaskCounters {
+    period: Duration,
+    calls: AtomicU64,
+    missed: AtomicU64,
+    overran: AtomicU64,
+    busy_nanos: AtomicU64,
+    max_busy_nanos: AtomicU64,
+    max_late_nanos: AtomicU64,
+}
+
+impl TaskCounters {
+    /// Records one invocation of the task.
+    #[inline]
+    pub(crate) fn record_call(&self, missed: u64, overran: bool, busy: Duration, late: Duration) {
+        let busy = busy.as_nanos() as u64;
+        incr(&self.calls, 1);
+        incr(&self.missed, missed);
+        incr(&self.overran, overran as u64);
+        incr(&self.busy_nanos, busy);
+        self.max_busy_nanos.fetch_max(busy, Ordering::Relaxed);
+        self.max_late_nanos
+            .fetch_max(late.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> TaskStats {
+        TaskStats {
+            period: self.period,
+            calls: read(&self.calls),
+            missed: read(&self.missed),
+            overran: read(&self.overran),
+            busy: Duration::from_nanos(read(&self.busy_nanos)),
+            max_busy: Duration::from_nanos(read(&self.max_busy_nanos)),
+            max_late: Duration::from_nanos(read(&self.max_late_nanos)),
+        }
+    }
+}
+
+/// The counters for a chain stage running on one core.
+#[derive(Debug, Default)]
+pub(crate) struct StageCounters {
+    processed: AtomicU64,
+    bypassed: AtomicU64,
+    dropped: AtomicU64,
+    errored: AtomicU64,
+}
+
+impl StageCounters {
+    /// Records a packet processed by the stage.
+    #[inline]
+    pub(crate) fn record_processed(&self) {
+        incr(&self.processed, 1);
+    }
+
+    /// Records a packet passed through the disabled stage.
+    #[inline]
+    pub(crate) fn record_bypassed(&self) {
+        incr(&self.bypassed, 1);
+    }
+
+    /// Records a packet dropped by the stage.
+    #[inline]
+    pub(crate) fn record_dropped(&self) {
+        incr(&self.dropped, 1);
+    }
+
+    /// Records a packet aborted by the stage.
+    #[inline]
+    pub(crate) fn record_errored(&self) {
+        incr(&self.errored, 1);
+    }
+
+    fn snapshot(&self) -> StageStats {
+        StageStats {
+            processed: read(&self.processed),
+            bypassed: read(&self.bypassed),
+            dropped: read(&self.dropped),
+            errored: read(&self.errored),
+        }
+    }
+}
+
+/// The counters for a packet capture, shared by all the cores of the
+/// captured port or pipeline.
+#[derive(Debug, Default)]
+pub(crate) struct CaptureCounters {
+    active: AtomicBool,
+    packets: AtomicU64,
+    filtered: AtomicU64,
+    bytes: AtomicU64,
+    files: AtomicU64,
+}
+
+impl CaptureCounters {
+    /// Records whether the capture is on.
+    #[inline]
+    pub(crate) fn record_active(&self, active: bool) {
+        self.active.store(active, Ordering::Relaxed);
+    }
+
+    /// Records a packet written to the capture file.
+    #[inline]
+    pub(crate) fn record_packet(&self) {
+        incr(&self.packets, 1);
+    }
+
+    /// Records a packet the capture filter skips.
+    #[inline]
+    pub(crate) fn record_filtered(&self) {
+        incr(&self.filtered, 1);
+    }
+
+    /// Records bytes written to the capture file.
+    #[inline]
+    pub(crate) fn record_bytes(&self, count: u64) {
+        incr(&self.bytes, count);
+    }
+
+    /// Records a new capture file opened.
+    #[inline]
+    pub(crate) fn record_file(&self) {
+        incr(&self.files, 1);
+    }
+
+    fn snapshot(&self) -> CaptureStats {
+        CaptureStats {
+            active: self.active.load(Ordering::Relaxed),
+            packets: read(&self.packets),
+            filtered: read(&self.filtered),
+            bytes: read(&self.bytes),
+            files: read(&self.files),
+        }
+    }
+}
+
+/// Why a frame fails to parse as Ethernet.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ParseFailure {
+    /// The buffer is too short for the Ethernet header.
+    OutOfBuffer,
+    /// The buffer is too short for the VLAN tags the header announces.
+    BadVlan,
+    /// The frame is longer than the maximum of the parse options.
+    Oversized,
+}
+
+/// The number of frames that failed to parse as Ethernet, by reason.
+#[cfg_attr(docsrs, doc(cfg(feature = "parse-errors")))]
+#[cfg(feature = "parse-errors")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ParseErrorStats {
+    /// Number of frames too short for the Ethernet header.
+    pub out_of_buffer: u64,
+    /// Number of frames too short for their VLAN tags.
+    pub bad_vlan: u64,
+    /// Number of frames longer than the maximum frame length.
+    pub oversized: u64,
+}
+
+#[cfg(feature = "parse-errors")]
+impl ParseErrorStats {
+    /// Returns the number of frames that failed to parse.
+    pub fn total(&self) -> u64 {
+        self.out_of_buffer + self.bad_vlan + self.oversized
+    }
+}
+
+/// The parse failure counters of one core.
+#[cfg(feature = "parse-errors")]
+#[derive(Debug, Default)]
+pub(crate) struct ParseErrorCounters {
+    out_of_buffer: AtomicU64,
+    bad_vlan: AtomicU64,
+    oversized: AtomicU64,
+}
+
+#[cfg(feature = "parse-errors")]
+impl ParseErrorCounters {
+    fn snapshot(&self) -> ParseErrorStats {
+        ParseErrorStats {
+            out_of_buffer: read(&self.out_of_buffer),
+            bad_vlan: read(&self.bad_vlan),
+            oversized: read(&self.oversized),
+        }
+    }
+}
+
+#[cfg(feature = "parse-errors")]
+thread_local! {
+    /// The parse failure counters of the current core, registered on
+    /// first use.
+    static PARSE_ERRORS: Arc<ParseErrorCounters> = register_parse_errors();
+}
+
+/// Records a frame that failed to parse on the current core.
+#[cfg(feature = "parse-errors")]
+#[inline]
+pub(crate) fn record_parse_failure(failure: ParseFailure) {
+    PARSE_ERRORS.with(|counters| match failure {
+        ParseFailure::OutOfBuffer => incr(&counters.out_of_buffer, 1),
+        ParseFailure::BadVlan => incr(&counters.bad_vlan, 1),
+        ParseFailure::Oversized => incr(&counters.oversized, 1),
+    });
+}
+
+#[cfg(not(feature = "parse-errors"))]
+#[inline(always)]
+pub(crate) fn record_parse_failure(_failure: ParseFailure) {}
+
+/// Returns the number of frames that failed to parse as Ethernet on the
+/// current core, by reason.
+///
+/// The counters are per core, so counting doesn't contend with the other
+/// cores. Use [`aggregate_parse_error_stats`] for the numbers of all the
+/// cores.
+#[cfg_attr(docsrs, doc(cfg(feature = "parse-errors")))]
+#[cfg(feature = "parse-errors")]
+pub fn parse_error_stats() -> ParseErrorStats {
+    PARSE_ERRORS.with(|counters| counters.snapshot())
+}
+
+/// Returns the number of frames that failed to parse as Ethernet on all
+/// the cores, by reason.
+///
+/// # Example
+///
+/// ```
+/// runtime.add_periodic_task_to_core(0, || {
+///     let errors = stats::aggregate_parse_error_stats();
+///     if errors.total() > 0 {
+///         warn!(?errors);
+///     }
+/// }, Duration::from_secs(10))?;
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "parse-errors")))]
+#[cfg(feature = "parse-errors")]
+pub fn aggregate_parse_error_stats() -> ParseErrorStats {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .parse_errors
+        .iter()
+        .map(|counters| counters.snapshot())
+        .fold(ParseErrorStats::default(), |total, core| ParseErrorStats {
+            out_of_buffer: total.out_of_buffer + core.out_of_buffer,
+            bad_vlan: total.bad_vlan + core.bad_vlan,
+            oversized: total.oversized + core.oversized,
+        })
+}
+
+/// All the registered counters.
+#[derive(Default)]
+struct Registry {
+    pipelines: Vec<(String, CoreId, Arc<PipelineCounters>)>,
+    queues: Vec<(String, CoreId, Arc<QueueCounters>)>,
+    tasks: Vec<(String, CoreId, Arc<TaskCounters>)>,
+    stages: Vec<(String, CoreId, Arc<StageCounters>)>,
+    counters: Vec<(String, CoreId, Arc<AtomicU64>)>,
+    captures: Vec<(String, Arc<CaptureCounters>)>,
+    #[cfg(feature = "parse-errors")]
+    parse_errors: Vec<Arc<ParseErrorCounters>>,
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(Default::default);
+
+/// Registers a new set of counters for a pipeline running on the current
+/// core.
+pub(crate) fn register_pipeline(name: &str) -> Arc<PipelineCounters> {
+    let counters = Arc::new(PipelineCounters::default());
+    REGISTRY
+        .lock()
+        .unwrap()
+        .pipelines
+        .push((name.to_owned(), CoreId::current(), counters.clone()));
+    counters
+}
+
+/// Registers a new set of counters for a port queue owned by a core.
+pub(crate) fn register_queue(port: &str, core_id: CoreId) -> Arc<QueueCounters> {
+    let counters = Arc::new(QueueCounters::default());
+    REGISTRY
+        .lock()
+        .unwrap()
+        .queues
+        .push((port.to_owned(), core_id, counters.clone()));
+    counters
+}
+
+/// Registers a new set of counters for a periodic task running on the
+/// current core.
+pub(crate) fn register_task(name: &str, period: Duration) -> Arc<TaskCounters> {
+    let counters = Arc::new(TaskCounters {
+        period,
+        ..Default::default()
+    });
+    REGISTRY
+        .lock()
+        .unwrap()
+        .tasks
+        .push((name.to_owned(), CoreId::current(), counters.clone()));
+    counters
+}
+
+/// Registers a new set of counters for a chain stage running on the
+/// current core.
+pub(crate) fn register_stage(name: &str) -> Arc<StageCounters> {
+    let counters = Arc::new(StageCounters::default());
+    REGISTRY
+        .lock()
+        .unwrap()
+        .stages
+        .push((name.to_owned(), CoreId::current(), counters.clone()));
+    counters
+}
+
+/// Registers a new set of counters for a capture target.
+pub(crate) fn register_capture(name: &str) -> Arc<CaptureCounters> {
+    let counters = Arc::new(CaptureCounters::default());
+    REGISTRY
+        .lock()
+        .unwrap()
+        .captures
+        .push((name.to_owned(), counters.clone()));
+    counters
+}
+
+/// Registers a new set of parse failure counters for the current core.
+#[cfg(feature = "parse-errors")]
+fn register_parse_errors() -> Arc<ParseErrorCounters> {
+    let counters = Arc::new(ParseErrorCounters::default());
+    REGISTRY.lock().unwrap().parse_errors.push(counters.clone());
+    counters
+}
+
+/// A user-incrementable counter bound to the core it's created on.
+#[derive(Clone, Debug)]
+pub struct Counter {
+    value: Arc<AtomicU64>,
+}
+
+impl Counter {
+    /// Increments the counter by one.
+    #[inline]
+    pub fn incr(&self) {
+        incr(&self.value, 1);
+    }
+
+    /// Increments the counter by `value`.
+    #[inline]
+    pub fn add(&self, value: u64) {
+        incr(&self.value, value);
+    }
+
+    /// Returns the current value of this instance of the counter.
+    #[inline]
+    pub fn get(&self) -> u64 {
+        read(&self.value)
+    }
+}
+
+/// Point-in-time copy of a pipeline's counters.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PipelineStats {
+    /// Number of times the pipeline executes.
+    pub runs: u64,
+    /// Number of packets polled from the pipeline's source.
+    pub received: u64,
+    /// Number of packets handed to the pipeline's TX.
+    pub transmitted: u64,
+    /// Number of packets sent out through `emit`.
+    pub emitted: u64,
+    /// Number of packets intentionally dropped for all causes.
+    pub dropped: u64,
+    /// Number of packets dropped by `filter` or `filter_map`.
+    pub filtered: u64,
+    /// Number of original packets dropped by `replace`.
+    pub replaced: u64,
+    /// Number of packets aborted due to processing errors.
+    pub errored: u64,
+    /// Time spent processing batches.
+    pub busy: Duration,
+}
+
+impl PipelineStats {
+    fn merge(&mut self, other: &PipelineStats) {
+        self.runs += other.runs;
+        self.received += other.received;
+        self.transmitted += other.transmitted;
+        self.emitted += other.emitted;
+        self.dropped += other.dropped;
+        self.filtered += other.filtered;
+        self.replaced += other.replaced;
+        self.errored += other.errored;
+        self.busy += other.busy;
+    }
+}
+
+/// Point-in-time copy of a port queue's counters.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PortQueueStats {
+    /// Number of packets received from the RX queue.
+    pub received: u64,
+    /// Number of packets sent through the TX queue.
+    pub transmitted: u64,
+    /// Number of packets dropped because the TX queue is full.
+    pub dropped: u64,
+    /// Number of packets dropped because the TX backlog is full.
+    pub overflowed: u64,
+    /// Number of received packets dropped by the RX filter.
+    pub filtered: u64,
+    /// The effective RX burst size. When aggregated, the largest of the
+    /// queues.
+    pub rx_burst: u64,
+}
+
+impl PortQueueStats {
+    fn merge(&mut self, other: &PortQueueStats) {
+        self.received += other.received;
+        self.transmitted += other.transmitted;
+        self.dropped += other.dropped;
+        self.overflowed += other.overflowed;
+        self.filtered += other.filtered;
+        self.rx_burst = self.rx_burst.max(other.rx_burst);
+    }
+}
+
+/// Point-in-time copy of a periodic task's counters.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TaskStats {
+    /// The requested period between invocations.
+    pub period: Duration,
+    /// Number of times the task is invoked.
+    pub calls: u64,
+    /// Number of invocations skipped because the poll loop fell behind.
+    pub missed: u64,
+    /// Number of invocations that took longer than the period.
+    pub overran: u64,
+    /// Time spent in the task.
+    pub busy: Duration,
+    /// The longest single invocation.
+    pub max_busy: Duration,
+    /// The longest delay between when an invocation is due and when it's
+    /// made.
+    pub max_late: Duration,
+}
+
+impl TaskStats {
+    fn merge(&mut self, other: &TaskStats) {
+        if self.period == Duration::default() {
+            self.period = other.period;
+        }
+        self.calls += other.calls;
+        self.missed += other.missed;
+        self.overran += other.overran;
+        self.busy += other.busy;
+        self.max_busy = self.max_busy.max(other.max_busy);
+        self.max_late = self.max_late.max(other.max_late);
+    }
+}
+
+/// Point-in-time copy of a chain stage's counters.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StageStats {
+    /// Number of packets the stage processes.
+    pub processed: u64,
+    /// Number of packets passed through while the stage is disabled.
+    pub bypassed: u64,
+    /// Number of packets the stage drops.
+    pub dropped: u64,
+    /// Number of packets aborted due to stage errors.
+    pub errored: u64,
+}
+
+impl StageStats {
+    fn merge(&mut self, other: &StageStats) {
+        self.processed += other.processed;
+        self.bypassed += other.bypassed;
+        self.dropped += other.dropped;
+        self.errored += other.errored;
+    }
+}
+
+/// Point-in-time copy of a packet capture's counters.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CaptureStats {
+    /// Whether the capture is currently on.
+    pub active: bool,
+    /// Number of packets written to the capture files.
+    pub packets: u64,
+    /// Number of packets the capture filter skips.
+    pub filtered: u64,
+    /// Number of bytes written to the capture files.
+    pub bytes: u64,
+    /// Number of capture files opened.
+    pub files: u64,
+}
+
+/// Point-in-time copy of all the runtime counters.
+///
+/// Each entry is labeled with the name it's registered under and the raw
+/// id of the core it belongs to.
+#[derive(Clone, Debug, Default)]
+pub struct StatsSnapshot {
+    pipelines: Vec<(String, usize, PipelineStats)>,
+    queues: Vec<(String, usize, PortQueueStats)>,
+    tasks: Vec<(String, usize, TaskStats)>,
+    stages: Vec<(String, usize, StageStats)>,
+    counters: Vec<(String, usize, u64)>,
+    captures: Vec<(String, CaptureStats)>,
+}
+
+impl StatsSnapshot {
+    /// Returns the per core pipeline stats.
+    pub fn pipelines(&self) -> impl Iterator<Item = (&str, usize, &PipelineStats)> {
+        self.pipelines
+            .iter()
+            .map(|(name, core, stats)| (name.as_str(), *core, stats))
+    }
+
+    /// Returns the stats of a pipeline aggregated across all cores and
+    /// instances.
+    pub fn pipeline(&self, name: &str) -> Option<PipelineStats> {
+        self.pipelines()
+            .filter(|(n, _, _)| *n == name)
+            .fold(None, |acc, (_, _, stats)| {
+                let mut acc: PipelineStats = acc.unwrap_or_default();
+                acc.merge(stats);
+                Some(acc)
+            })
+    }
+
+    /// Returns the per core port queue stats.
+    pub fn port_queues(&self) -> impl Iterator<Item = (&str, usize, &PortQueueStats)> {
+        self.queues
+            .iter()
+            .map(|(name, core, stats)| (name.as_str(), *core, stats))
+    }
+
+    /// Returns the stats of a port aggregated across all queues.
+    pub fn port(&self, name: &str) -> Option<PortQueueStats> {
+        self.port_queues()
+            .filter(|(n, _, _)| *n == name)
+            .fold(None, |acc, (_, _, stats)| {
+                let mut acc: PortQueueStats = acc.unwrap_or_default();
+                acc.merge(stats);
+                Some(acc)
+            })
+    }
+
+    /// Returns the per core periodic task stats.
+    pub fn tasks(&self) -> impl Iterator<Item = (&str, usize, &TaskStats)> {
+        self.tasks
+            .iter()
+            .map(|(name, core, stats)| (name.as_str(), *core, stats))
+    }
+
+    /// Returns the stats of the periodic tasks attached to a pipeline
+    /// aggregated across all cores and instances.
+    ///
+    /// The `period` is the one of the first task found. Use [`tasks`] to
+    /// tell apart multiple tasks with different periods.
+    ///
+    /// [`tasks`]: StatsSnapshot::tasks
+    pub fn task(&self, name: &str) -> Option<TaskStats> {
+        self.tasks()
+            .filter(|(n, _, _)| *n == name)
+            .fold(None, |acc, (_, _, stats)| {
+                let mut acc: TaskStats = acc.unwrap_or_default();
+                acc.merge(stats);
+                Some(acc)
+            })
+    }
+
+    /// Returns the per core chain stage stats.
+    pub fn stages(&self) -> impl Iterator<Item = (&str, usize, &StageStats)> {
+        self.stages
+            .iter()
+            .map(|(name, core, stats)| (name.as_str(), *core, stats))
+    }
+
+    /// Returns the stats of a chain stage aggregated across all cores and
+    /// instances.
+    pub fn stage(&self, name: &str) -> Option<StageStats> {
+        self.stages()
+            .filter(|(n, _, _)| *n == name)
+            .fold(None, |acc, (_, _, stats)| {
+                let mut acc: StageStats = acc.unwrap_or_default();
+                acc.merge(stats);
+                Some(acc)
+            })
+    }
+
+    /// Returns the per core custom counter values.
+    pub fn counters(&self) -> impl Iterator<Item = (&str, usize, u64)> {
+        self.counters
+            .iter()
+            .map(|(name, core, value)| (name.as_str(), *core, *value))
+    }
+
+    /// Returns the value of a custom counter aggregated across all cores.
+    pub fn counter(&self, name: &str) -> u64 {
+        self.counters()
+            .filter(|(n, _, _)| *n == name)
+            .map(|(_, _, value)| value)
+            .sum()
+    }
+
+    /// Returns the stats of all the capture targets.
+    pub fn captures(&self) -> impl Iterator<Item = (&str, &CaptureStats)> {
+        self.captures
+            .iter()
+            .map(|(name, stats)| (name.as_str(), stats))
+    }
+
+    /// Returns the stats of a capture target, named as the target is
+    /// displayed, for example `port:eth1`.
+    pub fn capture(&self, name: &str) -> Option<CaptureStats> {
+        self.captures()
+            .find(|(n, _)| *n == name)
+            .map(|(_, stats)| stats.clone())
+    }
+}
+
+/// Entry point to the runtime statistics.
+#[derive(Debug)]
+pub struct RuntimeStats;
+
+impl RuntimeStats {
+    /// Takes a snapshot of all the counters.
+    ///
+    /// The snapshot is taken without pausing the pipelines. Each counter
+    /// is read atomically, but counters are not read all at once. A busy
+    /// pipeline may make progress while the snapshot is being taken.
+    pub fn snapshot() -> StatsSnapshot {
+        let registry = REGISTRY.lock().unwrap();
+
+        StatsSnapshot {
+            pipelines: registry
+                .pipelines
+                .iter()
+                .map(|(name, core, c)| (name.clone(), core.raw(), c.snapshot()))
+                .collect(),
+            queues: registry
+                .queues
+                .iter()
+                .map(|(name, core, c)| (name.clone(), core.raw(), c.snapshot()))
+                .collect(),
+            tasks: registry
+                .tasks
+                .iter()
+                .map(|(name, core, c)| (name.clone(), core.raw(), c.snapshot()))
+                .collect(),
+            stages: registry
+                .stages
+                .iter()
+                .map(|(name, core, c)| (name.clone(), core.raw(), c.snapshot()))
+                .collect(),
+            counters: registry
+                .counters
+                .iter()
+                .map(|(name, core, c)| (name.clone(), core.raw(), read(c)))
+                .collect(),
+            captures: registry
+                .captures
+                .iter()
+                .map(|(name, c)| (name.clone(), c.snapshot()))
+                .collect(),
+        }
+    }
+
+    /// Creates a new custom counter for the current core.
+    ///
+    /// Create the counter on the core that increments it, for example,
+    /// inside the pipeline installer. All counters sharing the same name
+    /// are summed together by [`StatsSnapshot::counter`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let syns = RuntimeStats::counter("syn");
+    /// let batch = batch.for_each(move |tcp| {
+    ///     if tcp.syn() {
+    ///         syns.incr();
+    ///     }
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn counter(name: &str) -> Counter {
+        let value = Arc::new(AtomicU64::new(0));
+        REGISTRY
+            .lock()
+            .unwrap()
+            .counters
+            .push((name.to_owned(), CoreId::current(), value.clone()));
+        Counter { value }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{Batch, Either, PacketTx, Pipeline, Poll};
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::ip::ProtocolNumbers;
+    #[cfg(feature = "parse-errors")]
+    use crate::packets::ParseOptions;
+    use crate::packets::{Ethernet, Packet};
+    use crate::testils::byte_arrays::{ICMPV4_PACKET, IPV4_TCP_PACKET, IPV4_UDP_PACKET};
+    #[cfg(feature = "parse-errors")]
+    use crate::testils::byte_arrays::{VLAN_DOT1Q_PACKET, VLAN_QINQ_PACKET};
+    use crate::Mbuf;
+    use std::sync::mpsc;
+
+    #[capsule::test]
+    fn pipeline_counters() {
+        let (mut tx, rx) = mpsc::channel();
+        let (out, _out_rx) = mpsc::channel();
+
+        let mut pipeline = Poll::new(rx)
+            .map(|p| p.parse::<Ethernet>()?.parse::<Ipv4>())
+            .filter(|v4| v4.protocol() != ProtocolNumbers::Icmpv4)
+            .filter_map(|v4| {
+                if v4.protocol() == ProtocolNumbers::Tcp {
+                    Ok(Either::Keep(v4))
+                } else {
+                    Ok(Either::Drop(v4.reset()))
+                }
+            })
+            .send_named("stats_pipeline_counters", out);
+
+        tx.transmit(vec![
+            Mbuf::from_bytes(&IPV4_TCP_PACKET).unwrap(),
+            Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap(),
+            Mbuf::from_bytes(&ICMPV4_PACKET).unwrap(),
+            Mbuf::from_bytes(&[0; 8]).unwrap(),
+        ]);
+        pipeline.run_once();
+        pipeline.run_once();
+
+        let stats = RuntimeStats::snapshot()
+            .pipeline("stats_pipeline_counters")
+            .unwrap();
+        assert_eq!(2, stats.runs);
+        assert_eq!(4, stats.received);
+        assert_eq!(1, stats.transmitted);
+        assert_eq!(0, stats.emitted);
+        assert_eq!(2, stats.dropped);
+        assert_eq!(2, stats.filtered);
+        assert_eq!(0, stats.replaced);
+        assert_eq!(1, stats.errored);
+    }
+
+    #[capsule::test]
+    fn replace_counters() {
+        let (mut tx, rx) = mpsc::channel();
+        let (out, _out_rx) = mpsc::channel();
+
+        let mut pipeline = Poll::new(rx)
+            .replace(|_| Mbuf::from_bytes(&IPV4_TCP_PACKET))
+            .send_named("stats_replace_counters", out);
+
+        tx.transmit(vec![Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap()]);
+        pipeline.run_once();
+
+        let stats = RuntimeStats::snapshot()
+            .pipeline("stats_replace_counters")
+            .unwrap();
+        assert_eq!(1, stats.received);
+        assert_eq!(1, stats.transmitted);
+        assert_eq!(1, stats.dropped);
+        assert_eq!(1, stats.replaced);
+    }
+
+    #[test]
+    fn custom_counters() {
+        let a = RuntimeStats::counter("stats_custom_counters");
+        let b = RuntimeStats::counter("stats_custom_counters");
+        a.incr();
+        b.add(5);
+
+        assert_eq!(1, a.get());
+        assert_eq!(6, RuntimeStats::snapshot().counter("stats_custom_counters"));
+        assert_eq!(0, RuntimeStats::snapshot().counter("stats_no_such_counter"));
+    }
+
+    #[cfg(feature = "parse-errors")]
+    #[capsule::test]
+    fn count_parse_errors() {
+        let before = parse_error_stats();
+
+        let packet = Mbuf::from_bytes(&[0; 10]).unwrap();
+        assert!(packet.parse::<Ethernet>().is_err());
+
+        // the Dot1q frame cut in the middle of its tag.
+        let packet = Mbuf::from_bytes(&VLAN_DOT1Q_PACKET[..16]).unwrap();
+        assert!(packet.parse::<Ethernet>().is_err());
+        let packet = Mbuf::from_bytes(&VLAN_QINQ_PACKET[..20]).unwrap();
+        assert!(packet.parse::<Ethernet>().is_err());
+
+        ParseOptions {
+            max_frame_len: Some(1518),
+        }
+        .set();
+        let mut long = IPV4_UDP_PACKET.to_vec();
+        long.resize(1600, 0);
+        let packet = Mbuf::from_bytes(&long).unwrap();
+        assert!(packet.parse::<Ethernet>().is_err());
+        ParseOptions::default().set();
+
+        // frames that parse are not counted.
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert!(packet.parse::<Ethernet>().is_ok());
+
+        let after = parse_error_stats();
+        assert_eq!(1, after.out_of_buffer - before.out_of_buffer);
+        assert_eq!(2, after.bad_vlan - before.bad_vlan);
+        assert_eq!(1, after.oversized - before.oversized);
+        assert_eq!(4, after.total() - before.total());
+
+        // the current core is part of the aggregate.
+        assert!(aggregate_parse_error_stats().total() >= after.total());
+    }
+}
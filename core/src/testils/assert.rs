@@ -0,0 +1,538 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Assertions on the bytes of packets, with readable failures.
+
+use crate::packets::checksum::{self, PseudoHeader};
+use crate::packets::ip::ProtocolNumber;
+use crate::packets::Packet;
+use anyhow::Result;
+use std::fmt::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use thiserror::Error;
+
+/// The bytes of a packet to assert on, of an mbuf, a packet parsed from
+/// one or a byte array. The byte arrays don't need the EAL.
+pub trait PacketBytes {
+    /// Returns the bytes of the packet.
+    fn packet_bytes(&self) -> Vec<u8>;
+}
+
+/// The bytes of the whole mbuf, across the segments, regardless of the
+/// layer the packet is parsed to.
+impl<T: Packet> PacketBytes for T {
+    fn packet_bytes(&self) -> Vec<u8> {
+        self.mbuf().read_segments(0)
+    }
+}
+
+impl PacketBytes for [u8] {
+    fn packet_bytes(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl<const N: usize> PacketBytes for [u8; N] {
+    fn packet_bytes(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl PacketBytes for Vec<u8> {
+    fn packet_bytes(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+/// Returns the bytes in the classic hexdump format, the offset, 16 bytes
+/// in hex and the same bytes in ASCII on each line, followed by the length.
+///
+/// # Example
+///
+/// ```
+/// 00000000  00 00 00 00 00 01 00 00  00 00 00 02 08 00 45 00  |..............E.|
+/// 00000010  00 26 ab 49 40 00 ff 11  f7 00 8b 85 d9 6e 8b 85  |.&.I@........n..|
+/// ...
+/// 00000034
+/// ```
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x}  ", line * 16);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(dump, "{:02x} ", byte);
+                }
+                None => dump.push_str("   "),
+            }
+            if i == 7 {
+                dump.push(' ');
+            }
+        }
+
+        dump.push_str(" |");
+        dump.extend(chunk.iter().map(|&byte| printable(byte)));
+        dump.push_str("|\n");
+    }
+
+    let _ = write!(dump, "{:08x}", bytes.len());
+    dump
+}
+
+/// Returns the byte as an ASCII character, or `.` if it's not printable.
+fn printable(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        byte as char
+    } else {
+        '.'
+    }
+}
+
+/// Returns the two byte arrays side by side, 8 bytes per line, with the
+/// lines that differ annotated with the offsets of the differing bytes.
+///
+/// # Example
+///
+/// ```
+/// actual is 52 bytes, expected is 52 bytes, first difference at 0x16.
+/// offset    actual                    expected
+/// 00000000  00 00 00 00 00 01 00 00 | 00 00 00 00 00 01 00 00
+/// 00000008  00 00 00 02 08 00 45 00 | 00 00 00 02 08 00 45 00
+/// 00000010  00 26 ab 49 40 00 40 11 | 00 26 ab 49 40 00 ff 11  <- 0x16
+/// ...
+/// ```
+pub fn hexdump_diff(actual: &[u8], expected: &[u8]) -> String {
+    let len = actual.len().max(expected.len());
+    let first = (0..len).find(|&i| actual.get(i) != expected.get(i));
+
+    let mut dump = format!(
+        "actual is {} bytes, expected is {} bytes",
+        actual.len(),
+        expected.len()
+    );
+    match first {
+        Some(offset) => {
+            let _ = writeln!(dump, ", first difference at {:#x}.", offset);
+        }
+        None => dump.push_str(", no difference.\n"),
+    }
+    dump.push_str("offset    actual                    expected");
+
+    for start in (0..len).step_by(8) {
+        let row = |bytes: &[u8]| {
+            (start..start + 8)
+                .map(|i| match bytes.get(i) {
+                    Some(byte) => format!("{:02x}", byte),
+                    None => "  ".to_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let line = format!("{:08x}  {} | {}", start, row(actual), row(expected));
+        dump.push('\n');
+        dump.push_str(line.trim_end());
+
+        let differing = (start..(start + 8).min(len))
+            .filter(|&i| actual.get(i) != expected.get(i))
+            .map(|i| format!("{:#x}", i))
+            .collect::<Vec<_>>();
+        if !differing.is_empty() {
+            let _ = write!(dump, "  <- {}", differing.join(", "));
+        }
+    }
+
+    dump
+}
+
+/// A checksum that is not the one computed over the bytes.
+#[derive(Debug)]
+pub(crate) struct Mismatch {
+    layer: &'static str,
+    offset: usize,
+    actual: u16,
+    expected: u16,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} checksum at {:#x} is {:#06x}, expected {:#06x}.",
+            self.layer, self.offset, self.actual, self.expected
+        )
+    }
+}
+
+/// Checksum verification errors.
+#[derive(Debug, Error)]
+pub(crate) enum ChecksumError {
+    /// The checksums don't match the ones computed.
+    #[error("Invalid checksums:\n{}", join(.0))]
+    Invalid(Vec<Mismatch>),
+}
+
+fn join(mismatches: &[Mismatch]) -> String {
+    mismatches
+        .iter()
+        .map(|mismatch| mismatch.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reads the 16-bit value in network byte order at the offset.
+fn be16(bytes: &[u8], offset: usize) -> Option<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+/// Verifies every checksum of the packet, the ones of the IPv4 headers,
+/// and of the TCP, UDP, ICMPv4 and ICMPv6 messages over IPv4 or IPv6 and
+/// Ethernet with VLAN tags.
+///
+/// The walk stops at the first layer that's not one of them or that is
+/// truncated, and skips the messages in IP fragments. A UDP message
+/// without a checksum is valid.
+///
+/// # Errors
+///
+/// Returns an error listing the checksums that don't match the computed
+/// ones, with their offsets.
+pub fn verify_checksums(bytes: &[u8]) -> Result<()> {
+    let mut mismatches = vec![];
+
+    // skips the VLAN tags.
+    let mut offset = 12;
+    while let Some(0x8100) | Some(0x88a8) = be16(bytes, offset) {
+        offset += 4;
+    }
+
+    match be16(bytes, offset) {
+        Some(0x0800) => verify_ipv4(bytes, offset + 2, &mut mismatches),
+        Some(0x86dd) => verify_ipv6(bytes, offset + 2, &mut mismatches),
+        _ => (),
+    }
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(ChecksumError::Invalid(mismatches).into())
+    }
+}
+
+/// Verifies the checksum at `at` in `bytes[start..]`, with the sum of
+/// the pseudo header.
+fn verify(
+    layer: &'static str,
+    bytes: &[u8],
+    start: usize,
+    at: usize,
+    pseudo_header_sum: u16,
+    mismatches: &mut Vec<Mismatch>,
+) {
+    let mut data = bytes[start..].to_vec();
+    let actual = u16::from_be_bytes([data[at], data[at + 1]]);
+    data[at] = 0;
+    data[at + 1] = 0;
+
+    let expected = match (layer, checksum::compute(pseudo_header_sum, &data)) {
+        // a zero UDP checksum means no checksum.
+        ("UDP", 0) => 0xffff,
+        (_, expected) => expected,
+    };
+
+    if actual != expected {
+        mismatches.push(Mismatch {
+            layer,
+            offset: start + at,
+            actual,
+            expected,
+        });
+    }
+}
+
+fn verify_ipv4(bytes: &[u8], offset: usize, mismatches: &mut Vec<Mismatch>) {
+    let header_len = match bytes.get(offset) {
+        Some(version_ihl) if version_ihl & 0x0f >= 5 => (version_ihl & 0x0f) as usize * 4,
+        _ => return,
+    };
+    let total_len = match be16(bytes, offset + 2) {
+        Some(total_len) if total_len as usize >= header_len => total_len as usize,
+        _ => return,
+    };
+    if bytes.len() < offset + total_len {
+        return;
+    }
+
+    let bytes = &bytes[..offset + total_len];
+    verify(
+        "IPv4",
+        &bytes[..offset + header_len],
+        offset,
+        10,
+        0,
+        mismatches,
+    );
+
+    // the message of a fragment is not whole.
+    let fragment = be16(bytes, offset + 6).unwrap_or_default();
+    if fragment & 0x3fff != 0 {
+        return;
+    }
+
+    let octets = |at: usize| {
+        let mut octets = [0; 4];
+        octets.copy_from_slice(&bytes[offset + at..offset + at + 4]);
+        Ipv4Addr::from(octets)
+    };
+    let protocol = ProtocolNumber::new(bytes[offset + 9]);
+    let pseudo_header = PseudoHeader::V4 {
+        src: octets(12),
+        dst: octets(16),
+        packet_len: (total_len - header_len) as u16,
+        protocol,
+    };
+
+    verify_transport(
+        bytes,
+        offset + header_len,
+        protocol,
+        pseudo_header.sum(),
+        mismatches,
+    );
+}
+
+fn verify_ipv6(bytes: &[u8], offset: usize, mismatches: &mut Vec<Mismatch>) {
+    let payload_len = match be16(bytes, offset + 4) {
+        Some(payload_len) => payload_len as usize,
+        None => return,
+    };
+    if bytes.len() < offset + 40 + payload_len {
+        return;
+    }
+
+    let bytes = &bytes[..offset + 40 + payload_len];
+    let octets = |at: usize| {
+        let mut octets = [0; 16];
+        octets.copy_from_slice(&bytes[at..at + 16]);
+        Ipv6Addr::from(octets)
+    };
+
+    let mut next_header = bytes[offset + 6];
+    let mut dst = octets(offset + 24);
+    let mut start = offset + 40;
+
+    // walks the extension headers, to the upper layer.
+    loop {
+        let (ext_type, ext_len) = match (bytes.get(start + 2), bytes.get(start + 1)) {
+            (Some(&ext_type), Some(&ext_len)) => (ext_type, (ext_len as usize + 1) * 8),
+            _ => break,
+        };
+
+        match next_header {
+            // hop-by-hop and destination options.
+            0x00 | 0x3c => (),
+            0x2b => {
+                // with a routing header, the destination is the final one,
+                // the first segment of a segment routing header.
+                if ext_type == 0x04 && ext_len > 8 && bytes.len() >= start + 24 {
+                    dst = octets(start + 8);
+                }
+            }
+            // the message of a fragment is not whole.
+            0x2c => return,
+            _ => break,
+        }
+
+        if bytes.len() < start + ext_len {
+            return;
+        }
+        next_header = bytes[start];
+        start += ext_len;
+    }
+
+    let protocol = ProtocolNumber::new(next_header);
+    let pseudo_header = PseudoHeader::V6 {
+        src: octets(offset + 8),
+        dst,
+        packet_len: (bytes.len() - start) as u16,
+        protocol,
+    };
+
+    verify_transport(bytes, start, protocol, pseudo_header.sum(), mismatches);
+}
+
+fn verify_transport(
+    bytes: &[u8],
+    start: usize,
+    protocol: ProtocolNumber,
+    pseudo_header_sum: u16,
+    mismatches: &mut Vec<Mismatch>,
+) {
+    let len = bytes.len().saturating_sub(start);
+
+    match protocol.0 {
+        0x06 if len >= 20 => verify("TCP", bytes, start, 16, pseudo_header_sum, mismatches),
+        0x11 if len >= 8 => {
+            if be16(bytes, start + 6) != Some(0) {
+                verify("UDP", bytes, start, 6, pseudo_header_sum, mismatches)
+            }
+        }
+        0x01 if len >= 4 => verify("ICMPv4", bytes, start, 2, 0, mismatches),
+        0x3a if len >= 4 => verify("ICMPv6", bytes, start, 2, pseudo_header_sum, mismatches),
+        _ => (),
+    }
+}
+
+/// Asserts that the bytes of a packet are the expected ones.
+///
+/// Both sides are anything with [`PacketBytes`], an mbuf, a packet parsed
+/// from one or a byte array. On failure, the bytes are printed side by
+/// side with the differences annotated.
+///
+/// [`PacketBytes`]: crate::testils::PacketBytes
+///
+/// # Example
+///
+/// ```
+/// let packet = build_packet()
+///     .ethernet(src, dst)
+///     .ipv4(src_ip, dst_ip)
+///     .udp(39376, 1087)
+///     .build();
+///
+/// assert_packet_eq!(packet, IPV4_UDP_PACKET);
+/// ```
+#[macro_export]
+macro_rules! assert_packet_eq {
+    ($actual:expr, $expected:expr $(,)?) => {{
+        use $crate::testils::PacketBytes as _;
+        let actual = ($actual).packet_bytes();
+        let expected = ($expected).packet_bytes();
+        if actual != expected {
+            panic!(
+                "assertion failed: packets are not equal\n{}",
+                $crate::testils::hexdump_diff(&actual, &expected)
+            );
+        }
+    }};
+}
+
+/// Asserts that every checksum of a packet is valid.
+///
+/// The packet is anything with [`PacketBytes`], an mbuf, a packet parsed
+/// from one or a byte array. See [`verify_checksums`] for the checksums
+/// verified. On failure, the invalid checksums are printed with the
+/// hexdump of the packet.
+///
+/// [`PacketBytes`]: crate::testils::PacketBytes
+/// [`verify_checksums`]: crate::testils::verify_checksums
+///
+/// # Example
+///
+/// ```
+/// tcp.set_dst_ip(dst_ip)?;
+/// assert_checksums_valid!(tcp);
+/// ```
+#[macro_export]
+macro_rules! assert_checksums_valid {
+    ($packet:expr $(,)?) => {{
+        use $crate::testils::PacketBytes as _;
+        let bytes = ($packet).packet_bytes();
+        if let Err(err) = $crate::testils::verify_checksums(&bytes) {
+            panic!("{}\n{}", err, $crate::testils::hexdump(&bytes));
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testils::byte_arrays::*;
+    use crate::Mbuf;
+
+    #[test]
+    fn hexdump_bytes() {
+        let dump = hexdump(&IPV4_UDP_PACKET);
+        let lines = dump.lines().collect::<Vec<_>>();
+
+        assert_eq!(5, lines.len());
+        assert_eq!(
+            "00000000  00 00 00 00 00 01 00 00  00 00 00 02 08 00 45 00  |..............E.|",
+            lines[0]
+        );
+        assert!(lines[3].starts_with("00000030  65 6c 6c 6f    "));
+        assert!(lines[3].ends_with("  |ello|"));
+        assert_eq!(lines[0].len() - 12, lines[3].len());
+        assert_eq!("00000034", lines[4]);
+        assert_eq!("00000000", hexdump(&[]));
+    }
+
+    #[test]
+    fn hexdump_diff_bytes() {
+        let mut actual = IPV4_UDP_PACKET.to_vec();
+        actual[22] = 0x40;
+        actual.pop();
+
+        let diff = hexdump_diff(&actual, &IPV4_UDP_PACKET);
+        let lines = diff.lines().collect::<Vec<_>>();
+        assert_eq!(
+            "actual is 51 bytes, expected is 52 bytes, first difference at 0x16.",
+            lines[0]
+        );
+        assert_eq!(
+            "00000010  00 26 ab 49 40 00 40 11 | 00 26 ab 49 40 00 ff 11  <- 0x16",
+            lines[4]
+        );
+        assert!(lines[8].ends_with("<- 0x33"));
+    }
+
+    #[test]
+    fn verify_fixture_checksums() {
+        assert_checksums_valid!(IPV4_TCP_PACKET);
+        assert_checksums_valid!(IPV4_UDP_PACKET);
+        assert_checksums_valid!(ICMPV4_PACKET);
+        assert_checksums_valid!(ROUTER_ADVERT_PACKET);
+        assert_checksums_valid!(IPV6_FRAGMENT_PACKET);
+        assert_checksums_valid!(ARP4_PACKET);
+
+        // the checksum is the one of the IPv4 packet.
+        let err = verify_checksums(&IPV6_TCP_PACKET).unwrap_err();
+        assert_eq!(
+            "Invalid checksums:\nTCP checksum at 0x46 is 0xa92c, expected 0x1b1c.",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "first difference at 0x16")]
+    fn assert_packet_eq_fails() {
+        let mut actual = IPV4_UDP_PACKET;
+        actual[22] = 0x40;
+        assert_packet_eq!(actual, IPV4_UDP_PACKET);
+    }
+
+    #[capsule::test]
+    fn assert_mbuf() {
+        let packet = Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap();
+        assert_packet_eq!(packet, &IPV4_UDP_PACKET[..]);
+        assert_checksums_valid!(&packet);
+    }
+}
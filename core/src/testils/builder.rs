@@ -0,0 +1,1029 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::net::MacAddr;
+use crate::packets::arp::OperationCode;
+use crate::packets::checksum::{self, PseudoHeader};
+use crate::packets::ip::{ProtocolNumber, ProtocolNumbers};
+use crate::packets::{EtherType, EtherTypes};
+use crate::Mbuf;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Returns a builder for a test packet, assembled layer by layer from the
+/// outermost in.
+///
+/// # Example
+///
+/// ```
+/// let packet = build_packet()
+///     .ethernet(src, dst)
+///     .vlan(123)
+///     .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+///     .ttl(1)
+///     .udp(5000, 53)
+///     .payload(b"hello")
+///     .build();
+/// ```
+pub fn build_packet() -> PacketBuilder {
+    PacketBuilder::default()
+}
+
+/// A layer of the packet, with the settings that are not computed.
+#[derive(Clone, Debug)]
+enum Layer {
+    Ethernet {
+        src: MacAddr,
+        dst: MacAddr,
+        // the TPID and the TCI of each VLAN tag, outermost first.
+        tags: Vec<(EtherType, u16)>,
+        ether_type: Option<EtherType>,
+    },
+    Pbb {
+        pcp: u8,
+        isid: u32,
+    },
+    Arp {
+        operation: OperationCode,
+        sender_mac: MacAddr,
+        sender_ip: Ipv4Addr,
+        target_mac: MacAddr,
+        target_ip: Ipv4Addr,
+    },
+    Ipv4 {
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        // the header length is always 20 bytes, the IHL can be set to
+        // another one to malform the packet.
+        ihl: u8,
+        tos: u8,
+        ident: u16,
+        dont_fragment: bool,
+        ttl: u8,
+        protocol: Option<ProtocolNumber>,
+        checksum: Option<u16>,
+    },
+    Ipv6 {
+        src: Ipv6Addr,
+        dst: Ipv6Addr,
+        traffic_class: u8,
+        flow_label: u32,
+        hop_limit: u8,
+        next_header: Option<ProtocolNumber>,
+    },
+    SegmentRouting {
+        segments: Vec<Ipv6Addr>,
+        segments_left: u8,
+        next_header: Option<ProtocolNumber>,
+    },
+    Fragment {
+        offset: u16,
+        more_fragments: bool,
+        ident: u32,
+        next_header: Option<ProtocolNumber>,
+    },
+    Tcp {
+        src_port: u16,
+        dst_port: u16,
+        seq_no: u32,
+        ack_no: u32,
+        flags: u8,
+        window: u16,
+        options: Vec<u8>,
+        checksum: Option<u16>,
+    },
+    Udp {
+        src_port: u16,
+        dst_port: u16,
+        checksum: Option<u16>,
+    },
+    Icmpv4 {
+        msg_type: u8,
+        code: u8,
+        checksum: Option<u16>,
+    },
+    Icmpv6 {
+        msg_type: u8,
+        code: u8,
+        checksum: Option<u16>,
+    },
+}
+
+impl Layer {
+    /// Returns the length of the header.
+    fn len(&self) -> usize {
+        match self {
+            Layer::Ethernet { tags, .. } => 14 + tags.len() * 4,
+            Layer::Pbb { .. } => 4,
+            Layer::Arp { .. } => 28,
+            Layer::Ipv4 { .. } => 20,
+            Layer::Ipv6 { .. } => 40,
+            Layer::SegmentRouting { segments, .. } => 8 + segments.len() * 16,
+            Layer::Fragment { .. } => 8,
+            Layer::Tcp { options, .. } => 20 + (options.len() + 3) / 4 * 4,
+            Layer::Udp { .. } => 8,
+            Layer::Icmpv4 { .. } | Layer::Icmpv6 { .. } => 4,
+        }
+    }
+
+    /// Returns the EtherType that identifies the layer.
+    fn ether_type(&self) -> Option<EtherType> {
+        match self {
+            Layer::Pbb { .. } => Some(EtherTypes::Pbb),
+            Layer::Arp { .. } => Some(EtherTypes::Arp),
+            Layer::Ipv4 { .. } => Some(EtherTypes::Ipv4),
+            Layer::Ipv6 { .. } => Some(EtherTypes::Ipv6),
+            _ => None,
+        }
+    }
+
+    /// Returns the protocol number that identifies the layer.
+    fn protocol(&self) -> Option<ProtocolNumber> {
+        match self {
+            Layer::Ipv4 { .. } => Some(ProtocolNumber::new(0x04)),
+            Layer::Ipv6 { .. } => Some(ProtocolNumber::new(0x29)),
+            Layer::SegmentRouting { .. } => Some(ProtocolNumbers::Ipv6Route),
+            Layer::Fragment { .. } => Some(ProtocolNumbers::Ipv6Frag),
+            Layer::Tcp { .. } => Some(ProtocolNumbers::Tcp),
+            Layer::Udp { .. } => Some(ProtocolNumbers::Udp),
+            Layer::Icmpv4 { .. } => Some(ProtocolNumbers::Icmpv4),
+            Layer::Icmpv6 { .. } => Some(ProtocolNumbers::Icmpv6),
+            _ => None,
+        }
+    }
+
+    /// Writes the header, with the checksum left as zero. `rest` is the
+    /// number of bytes after the header.
+    fn write(&self, next: Option<&Layer>, rest: usize, bytes: &mut Vec<u8>) {
+        let next_ether_type = next.and_then(Layer::ether_type).unwrap_or_default();
+        let next_protocol = next.and_then(Layer::protocol).unwrap_or_default();
+
+        match *self {
+            Layer::Ethernet {
+                src,
+                dst,
+                ref tags,
+                ether_type,
+            } => {
+                bytes.extend_from_slice(&dst.octets());
+                bytes.extend_from_slice(&src.octets());
+                for &(tpid, tci) in tags.iter() {
+                    bytes.extend_from_slice(&tpid.to_be_bytes());
+                    bytes.extend_from_slice(&tci.to_be_bytes());
+                }
+                let ether_type = ether_type.unwrap_or(next_ether_type);
+                bytes.extend_from_slice(&ether_type.to_be_bytes());
+            }
+            Layer::Pbb { pcp, isid } => {
+                bytes.push(pcp << 5);
+                bytes.extend_from_slice(&isid.to_be_bytes()[1..]);
+            }
+            Layer::Arp {
+                operation,
+                sender_mac,
+                sender_ip,
+                target_mac,
+                target_ip,
+            } => {
+                bytes.extend_from_slice(&[0x00, 0x01, 0x08, 0x00, 0x06, 0x04]);
+                bytes.extend_from_slice(&operation.value().to_be_bytes());
+                bytes.extend_from_slice(&sender_mac.octets());
+                bytes.extend_from_slice(&sender_ip.octets());
+                bytes.extend_from_slice(&target_mac.octets());
+                bytes.extend_from_slice(&target_ip.octets());
+            }
+            Layer::Ipv4 {
+                src,
+                dst,
+                ihl,
+                tos,
+                ident,
+                dont_fragment,
+                ttl,
+                protocol,
+                ..
+            } => {
+                let total_len = (self.len() + rest) as u16;
+                let flags: u16 = if dont_fragment { 0x4000 } else { 0 };
+                bytes.extend_from_slice(&[0x40 | (ihl & 0x0f), tos]);
+                bytes.extend_from_slice(&total_len.to_be_bytes());
+                bytes.extend_from_slice(&ident.to_be_bytes());
+                bytes.extend_from_slice(&flags.to_be_bytes());
+                bytes.extend_from_slice(&[ttl, protocol.unwrap_or(next_protocol).0, 0, 0]);
+                bytes.extend_from_slice(&src.octets());
+                bytes.extend_from_slice(&dst.octets());
+            }
+            Layer::Ipv6 {
+                src,
+                dst,
+                traffic_class,
+                flow_label,
+                hop_limit,
+                next_header,
+            } => {
+                let first = 6 << 28 | u32::from(traffic_class) << 20 | flow_label & 0xf_ffff;
+                bytes.extend_from_slice(&first.to_be_bytes());
+                bytes.extend_from_slice(&(rest as u16).to_be_bytes());
+                bytes.extend_from_slice(&[next_header.unwrap_or(next_protocol).0, hop_limit]);
+                bytes.extend_from_slice(&src.octets());
+                bytes.extend_from_slice(&dst.octets());
+            }
+            Layer::SegmentRouting {
+                ref segments,
+                segments_left,
+                next_header,
+            } => {
+                let last_entry = segments.len().saturating_sub(1) as u8;
+                bytes.extend_from_slice(&[
+                    next_header.unwrap_or(next_protocol).0,
+                    (segments.len() * 2) as u8,
+                    0x04,
+                    segments_left,
+                    last_entry,
+                    0,
+                    0,
+                    0,
+                ]);
+                for segment in segments.iter() {
+                    bytes.extend_from_slice(&segment.octets());
+                }
+            }
+            Layer::Fragment {
+                offset,
+                more_fragments,
+                ident,
+                next_header,
+            } => {
+                let offset = offset << 3 | if more_fragments { 1 } else { 0 };
+                bytes.extend_from_slice(&[next_header.unwrap_or(next_protocol).0, 0]);
+                bytes.extend_from_slice(&offset.to_be_bytes());
+                bytes.extend_from_slice(&ident.to_be_bytes());
+            }
+            Layer::Tcp {
+                src_port,
+                dst_port,
+                seq_no,
+                ack_no,
+                flags,
+                window,
+                ref options,
+                ..
+            } => {
+                let data_offset = (self.len() / 4) as u8;
+                bytes.extend_from_slice(&src_port.to_be_bytes());
+                bytes.extend_from_slice(&dst_port.to_be_bytes());
+                bytes.extend_from_slice(&seq_no.to_be_bytes());
+                bytes.extend_from_slice(&ack_no.to_be_bytes());
+                bytes.extend_from_slice(&[data_offset << 4, flags]);
+                bytes.extend_from_slice(&window.to_be_bytes());
+                bytes.extend_from_slice(&[0, 0, 0, 0]);
+                bytes.extend_from_slice(options);
+                // pads the options to the 4-byte boundary.
+                bytes.resize(bytes.len() + (4 - options.len() % 4) % 4, 0);
+            }
+            Layer::Udp {
+                src_port, dst_port, ..
+            } => {
+                let len = (self.len() + rest) as u16;
+                bytes.extend_from_slice(&src_port.to_be_bytes());
+                bytes.extend_from_slice(&dst_port.to_be_bytes());
+                bytes.extend_from_slice(&len.to_be_bytes());
+                bytes.extend_from_slice(&[0, 0]);
+            }
+            Layer::Icmpv4 { msg_type, code, .. } | Layer::Icmpv6 { msg_type, code, .. } => {
+                bytes.extend_from_slice(&[msg_type, code, 0, 0]);
+            }
+        }
+    }
+
+    /// Returns the offset of the checksum in the header, and the value it
+    /// is pinned to if it's not computed.
+    fn checksum(&self) -> Option<(usize, Option<u16>)> {
+        match *self {
+            Layer::Ipv4 { checksum, .. } => Some((10, checksum)),
+            Layer::Tcp { checksum, .. } => Some((16, checksum)),
+            Layer::Udp { checksum, .. } => Some((6, checksum)),
+            Layer::Icmpv4 { checksum, .. } | Layer::Icmpv6 { checksum, .. } => Some((2, checksum)),
+            _ => None,
+        }
+    }
+
+    /// Returns the pinned checksum of the layer to set.
+    fn checksum_mut(&mut self) -> Option<&mut Option<u16>> {
+        match self {
+            Layer::Ipv4 { checksum, .. }
+            | Layer::Tcp { checksum, .. }
+            | Layer::Udp { checksum, .. }
+            | Layer::Icmpv4 { checksum, .. }
+            | Layer::Icmpv6 { checksum, .. } => Some(checksum),
+            _ => None,
+        }
+    }
+}
+
+/// A builder for test packets, the readable alternative to the byte arrays
+/// of [`byte_arrays`].
+///
+/// Each method adds a layer after the previous ones, or sets a field of the
+/// last layer it applies to. The lengths, the EtherTypes, the protocol
+/// numbers and the checksums are computed, the same as [`reconcile_all`]
+/// does, unless set explicitly. A checksum can be pinned to a value, for
+/// example to an invalid one.
+///
+/// The methods that set a field panic if there's no layer the field is of.
+///
+/// [`byte_arrays`]: crate::testils::byte_arrays
+/// [`reconcile_all`]: crate::packets::Packet::reconcile_all
+#[derive(Clone, Debug, Default)]
+pub struct PacketBuilder {
+    layers: Vec<Layer>,
+    payload: Vec<u8>,
+}
+
+impl PacketBuilder {
+    /// Adds an Ethernet header.
+    pub fn ethernet(mut self, src: MacAddr, dst: MacAddr) -> Self {
+        self.layers.push(Layer::Ethernet {
+            src,
+            dst,
+            tags: vec![],
+            ether_type: None,
+        });
+        self
+    }
+
+    /// Sets the EtherType of the last Ethernet header, instead of the one
+    /// of the next layer.
+    pub fn ether_type(mut self, ether_type: EtherType) -> Self {
+        match self.last_mut("Ethernet", |l| matches!(l, Layer::Ethernet { .. })) {
+            Layer::Ethernet { ether_type: e, .. } => *e = Some(ether_type),
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Adds a 802.1Q VLAN tag to the last Ethernet header.
+    pub fn vlan(self, vid: u16) -> Self {
+        self.tag(EtherTypes::Vlan, vid)
+    }
+
+    /// Adds a 802.1ad service tag to the last Ethernet header.
+    pub fn qinq(self, vid: u16) -> Self {
+        self.tag(EtherTypes::QinQ, vid)
+    }
+
+    fn tag(mut self, tpid: EtherType, vid: u16) -> Self {
+        match self.last_mut("Ethernet", |l| matches!(l, Layer::Ethernet { .. })) {
+            Layer::Ethernet { tags, .. } => tags.push((tpid, vid & 0x0fff)),
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Sets the priority code point of the last VLAN tag or I-TAG.
+    pub fn pcp(mut self, pcp: u8) -> Self {
+        let layer = self.last_mut("VLAN tag or I-TAG", |l| match l {
+            Layer::Ethernet { tags, .. } => !tags.is_empty(),
+            Layer::Pbb { .. } => true,
+            _ => false,
+        });
+        match layer {
+            Layer::Ethernet { tags, .. } => {
+                let tci = &mut tags.last_mut().unwrap().1;
+                *tci = *tci & 0x1fff | u16::from(pcp & 0x07) << 13;
+            }
+            Layer::Pbb { pcp: p, .. } => *p = pcp & 0x07,
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Adds a provider backbone bridging I-TAG. Add the customer frame
+    /// with [`ethernet`] after it.
+    ///
+    /// [`ethernet`]: PacketBuilder::ethernet
+    pub fn pbb(mut self, isid: u32) -> Self {
+        self.layers.push(Layer::Pbb {
+            pcp: 0,
+            isid: isid & 0xff_ffff,
+        });
+        self
+    }
+
+    /// Adds an ARP message for IPv4 over Ethernet.
+    pub fn arp(
+        mut self,
+        operation: OperationCode,
+        sender_mac: MacAddr,
+        sender_ip: Ipv4Addr,
+        target_mac: MacAddr,
+        target_ip: Ipv4Addr,
+    ) -> Self {
+        self.layers.push(Layer::Arp {
+            operation,
+            sender_mac,
+            sender_ip,
+            target_mac,
+            target_ip,
+        });
+        self
+    }
+
+    /// Adds an IPv4 header, with the TTL of 64.
+    pub fn ipv4(mut self, src: Ipv4Addr, dst: Ipv4Addr) -> Self {
+        self.layers.push(Layer::Ipv4 {
+            src,
+            dst,
+            ihl: 5,
+            tos: 0,
+            ident: 0,
+            dont_fragment: false,
+            ttl: 64,
+            protocol: None,
+            checksum: None,
+        });
+        self
+    }
+
+    /// Sets the IHL of the last IPv4 header, without changing the length
+    /// of the header. Only the lower 4 bits are used.
+    pub fn ihl(mut self, ihl: u8) -> Self {
+        match self.last_mut("IPv4", |l| matches!(l, Layer::Ipv4 { .. })) {
+            Layer::Ipv4 { ihl: i, .. } => *i = ihl,
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Sets the type of service of the last IPv4 header.
+    pub fn tos(mut self, tos: u8) -> Self {
+        match self.last_mut("IPv4", |l| matches!(l, Layer::Ipv4 { .. })) {
+            Layer::Ipv4 { tos: t, .. } => *t = tos,
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Sets the identification of the last IPv4 header.
+    pub fn ident(mut self, ident: u16) -> Self {
+        match self.last_mut("IPv4", |l| matches!(l, Layer::Ipv4 { .. })) {
+            Layer::Ipv4 { ident: i, .. } => *i = ident,
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Sets the don't fragment flag of the last IPv4 header.
+    pub fn dont_fragment(mut self) -> Self {
+        match self.last_mut("IPv4", |l| matches!(l, Layer::Ipv4 { .. })) {
+            Layer::Ipv4 { dont_fragment, .. } => *dont_fragment = true,
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Sets the TTL of the last IPv4 header, or the hop limit of the last
+    /// IPv6 header.
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        let ip = |l: &Layer| matches!(l, Layer::Ipv4 { .. } | Layer::Ipv6 { .. });
+        match self.last_mut("IP", ip) {
+            Layer::Ipv4 { ttl: t, .. } => *t = ttl,
+            Layer::Ipv6 { hop_limit, .. } => *hop_limit = ttl,
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Sets the protocol of the last IP header or IPv6 extension header,
+    /// instead of the one of the next layer.
+    pub fn protocol(mut self, protocol: ProtocolNumber) -> Self {
+        let ip = |l: &Layer| {
+            matches!(
+                l,
+                Layer::Ipv4 { .. }
+                    | Layer::Ipv6 { .. }
+                    | Layer::SegmentRouting { .. }
+                    | Layer::Fragment { .. }
+            )
+        };
+        match self.last_mut("IP", ip) {
+            Layer::Ipv4 { protocol: p, .. }
+            | Layer::Ipv6 { next_header: p, .. }
+            | Layer::SegmentRouting { next_header: p, .. }
+            | Layer::Fragment { next_header: p, .. } => *p = Some(protocol),
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Adds an IPv6 header, with the hop limit of 64.
+    pub fn ipv6(mut self, src: Ipv6Addr, dst: Ipv6Addr) -> Self {
+        self.layers.push(Layer::Ipv6 {
+            src,
+            dst,
+            traffic_class: 0,
+            flow_label: 0,
+            hop_limit: 64,
+            next_header: None,
+        });
+        self
+    }
+
+    /// Sets the traffic class and the flow label of the last IPv6 header.
+    pub fn flow(mut self, traffic_class: u8, flow_label: u32) -> Self {
+        match self.last_mut("IPv6", |l| matches!(l, Layer::Ipv6 { .. })) {
+            Layer::Ipv6 {
+                traffic_class: t,
+                flow_label: f,
+                ..
+            } => {
+                *t = traffic_class;
+                *f = flow_label;
+            }
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Adds a segment routing header, with all the segments left.
+    pub fn srh(mut self, segments: &[Ipv6Addr]) -> Self {
+        self.layers.push(Layer::SegmentRouting {
+            segments: segments.to_vec(),
+            segments_left: segments.len().saturating_sub(1) as u8,
+            next_header: None,
+        });
+        self
+    }
+
+    /// Sets the segments left of the last segment routing header.
+    pub fn segments_left(mut self, segments_left: u8) -> Self {
+        let srh = |l: &Layer| matches!(l, Layer::SegmentRouting { .. });
+        match self.last_mut("segment routing", srh) {
+            Layer::SegmentRouting {
+                segments_left: s, ..
+            } => *s = segments_left,
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Adds an IPv6 fragment header. The offset is in 8-byte units.
+    pub fn fragment(mut self, offset: u16, more_fragments: bool, ident: u32) -> Self {
+        self.layers.push(Layer::Fragment {
+            offset,
+            more_fragments,
+            ident,
+            next_header: None,
+        });
+        self
+    }
+
+    /// Adds a TCP header, with the window of 65535 and no flags.
+    pub fn tcp(mut self, src_port: u16, dst_port: u16) -> Self {
+        self.layers.push(Layer::Tcp {
+            src_port,
+            dst_port,
+            seq_no: 0,
+            ack_no: 0,
+            flags: 0,
+            window: 0xffff,
+            options: vec![],
+            checksum: None,
+        });
+        self
+    }
+
+    /// Sets the sequence and acknowledgment numbers of the last TCP
+    /// header.
+    pub fn seq(mut self, seq_no: u32, ack_no: u32) -> Self {
+        match self.last_mut("TCP", |l| matches!(l, Layer::Tcp { .. })) {
+            Layer::Tcp {
+                seq_no: s,
+                ack_no: a,
+                ..
+            } => {
+                *s = seq_no;
+                *a = ack_no;
+            }
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Sets the flags of the last TCP header, for example `0x02` for SYN.
+    pub fn tcp_flags(mut self, flags: u8) -> Self {
+        match self.last_mut("TCP", |l| matches!(l, Layer::Tcp { .. })) {
+            Layer::Tcp { flags: f, .. } => *f = flags,
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Sets the window of the last TCP header.
+    pub fn window(mut self, window: u16) -> Self {
+        match self.last_mut("TCP", |l| matches!(l, Layer::Tcp { .. })) {
+            Layer::Tcp { window: w, .. } => *w = window,
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Sets the options of the last TCP header, padded with zeros to the
+    /// 4-byte boundary.
+    pub fn tcp_options(mut self, options: &[u8]) -> Self {
+        match self.last_mut("TCP", |l| matches!(l, Layer::Tcp { .. })) {
+            Layer::Tcp { options: o, .. } => *o = options.to_vec(),
+            _ => unreachable!(),
+        }
+        self
+    }
+
+    /// Adds a UDP header.
+    pub fn udp(mut self, src_port: u16, dst_port: u16) -> Self {
+        self.layers.push(Layer::Udp {
+            src_port,
+            dst_port,
+            checksum: None,
+        });
+        self
+    }
+
+    /// Adds an ICMPv4 header. The rest of the message is the payload.
+    pub fn icmpv4(mut self, msg_type: u8, code: u8) -> Self {
+        self.layers.push(Layer::Icmpv4 {
+            msg_type,
+            code,
+            checksum: None,
+        });
+        self
+    }
+
+    /// Adds an ICMPv6 header. The rest of the message is the payload.
+    pub fn icmpv6(mut self, msg_type: u8, code: u8) -> Self {
+        self.layers.push(Layer::Icmpv6 {
+            msg_type,
+            code,
+            checksum: None,
+        });
+        self
+    }
+
+    /// Pins the checksum of the last layer that has one, instead of
+    /// computing it.
+    pub fn checksum(mut self, checksum: u16) -> Self {
+        let layer = self.last_mut("checksummed", |l| l.checksum().is_some());
+        *layer.checksum_mut().unwrap() = Some(checksum);
+        self
+    }
+
+    /// Appends the bytes after the last layer.
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload.extend_from_slice(payload);
+        self
+    }
+
+    /// Returns the bytes of the packet. Doesn't need the EAL.
+    pub fn build_bytes(&self) -> Vec<u8> {
+        let len = self.layers.iter().map(Layer::len).sum::<usize>() + self.payload.len();
+        let mut bytes = Vec::with_capacity(len);
+        let mut offsets = Vec::with_capacity(self.layers.len());
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            offsets.push(bytes.len());
+            let rest = len - bytes.len() - layer.len();
+            layer.write(self.layers.get(i + 1), rest, &mut bytes);
+        }
+        bytes.extend_from_slice(&self.payload);
+
+        // the inner checksums first, the outer ones cover them.
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            let (at, pinned) = match layer.checksum() {
+                Some(checksum) => checksum,
+                None => continue,
+            };
+            let start = offsets[i];
+            let checksum = pinned.unwrap_or_else(|| match layer {
+                Layer::Ipv4 { .. } => checksum::compute(0, &bytes[start..start + layer.len()]),
+                _ => {
+                    let sum = self.pseudo_header(i, bytes.len() - start);
+                    let computed = checksum::compute(sum, &bytes[start..]);
+                    // a zero UDP checksum means no checksum.
+                    match (layer, computed) {
+                        (Layer::Udp { .. }, 0) => 0xffff,
+                        _ => computed,
+                    }
+                }
+            });
+            bytes[start + at..start + at + 2].copy_from_slice(&checksum.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    /// Returns the packet in a new buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer can't be allocated.
+    pub fn build(&self) -> Mbuf {
+        Mbuf::from_bytes(&self.build_bytes()).unwrap()
+    }
+
+    /// Returns the sum of the pseudo header for the upper layer at the
+    /// index, of the closest IP header before it. ICMPv4 has none.
+    fn pseudo_header(&self, index: usize, packet_len: usize) -> u16 {
+        let protocol = match self.layers[index].protocol() {
+            Some(ProtocolNumbers::Icmpv4) | None => return 0,
+            Some(protocol) => protocol,
+        };
+
+        // with a routing header, the destination is the final one, the
+        // first segment.
+        let mut final_dst = None;
+        let header = self.layers[..index]
+            .iter()
+            .rev()
+            .find_map(|layer| match *layer {
+                Layer::SegmentRouting { ref segments, .. } => {
+                    final_dst = final_dst.or_else(|| segments.first().copied());
+                    None
+                }
+                Layer::Ipv4 { src, dst, .. } => Some(PseudoHeader::V4 {
+                    src,
+                    dst,
+                    packet_len: packet_len as u16,
+                    protocol,
+                }),
+                Layer::Ipv6 { src, dst, .. } => Some(PseudoHeader::V6 {
+                    src,
+                    dst: final_dst.unwrap_or(dst),
+                    packet_len: packet_len as u16,
+                    protocol,
+                }),
+                _ => None,
+            });
+
+        header.map(|header| header.sum()).unwrap_or_default()
+    }
+
+    /// Returns the last layer that matches.
+    fn last_mut<F: Fn(&Layer) -> bool>(&mut self, name: &str, matches: F) -> &mut Layer {
+        self.layers
+            .iter_mut()
+            .rev()
+            .find(|layer| matches(layer))
+            .unwrap_or_else(|| panic!("no {} layer to set", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::arp::OperationCodes;
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::{Ethernet, Packet, Udp4};
+    use crate::testils::byte_arrays::*;
+
+    fn mac(last: u8) -> MacAddr {
+        MacAddr::new(0, 0, 0, 0, 0, last)
+    }
+
+    /// The ARP reply in the VLAN fixtures.
+    fn arp_reply(builder: PacketBuilder) -> PacketBuilder {
+        builder
+            .arp(
+                OperationCodes::Reply,
+                MacAddr::new(0x00, 0x19, 0x06, 0xea, 0xb8, 0xc1),
+                Ipv4Addr::new(192, 168, 123, 1),
+                MacAddr::BROADCAST,
+                Ipv4Addr::new(192, 168, 123, 1),
+            )
+            // pads the frame to the minimum length.
+            .payload(&[0; 18])
+    }
+
+    fn v6(addr: &str) -> Ipv6Addr {
+        addr.parse().unwrap()
+    }
+
+    const V4_SRC: Ipv4Addr = Ipv4Addr::new(139, 133, 217, 110);
+    const V4_DST: Ipv4Addr = Ipv4Addr::new(139, 133, 233, 2);
+    const V6_SRC: &str = "2001:db8:85a3::1";
+    const V6_DST: &str = "2001:db8:85a3::8a2e:370:7334";
+    const LINK_LOCAL: &str = "fe80::d4f0:45ff:fe0c:664b";
+    const ALL_NODES: &str = "ff02::1";
+
+    #[test]
+    fn build_vlan_packets() {
+        let dot1q = arp_reply(build_packet().ethernet(mac(2), mac(1)).vlan(123));
+        assert_eq!(&VLAN_DOT1Q_PACKET[..], &dot1q.build_bytes()[..]);
+
+        let qinq = build_packet()
+            .ethernet(mac(2), mac(1))
+            .qinq(30)
+            .vlan(101)
+            .pcp(1);
+        assert_eq!(&VLAN_QINQ_PACKET[..], &arp_reply(qinq).build_bytes()[..]);
+    }
+
+    #[test]
+    fn build_pbb_packet() {
+        let packet = build_packet()
+            .ethernet(mac(2), mac(1))
+            .pbb(100_000)
+            .pcp(3)
+            .ethernet(mac(4), mac(3))
+            .arp(
+                OperationCodes::Reply,
+                MacAddr::new(0x00, 0x19, 0x06, 0xea, 0xb8, 0xc1),
+                Ipv4Addr::new(192, 168, 123, 1),
+                MacAddr::BROADCAST,
+                Ipv4Addr::new(192, 168, 123, 1),
+            );
+        assert_eq!(&PBB_PACKET[..], &packet.build_bytes()[..]);
+    }
+
+    #[test]
+    fn build_arp_packet() {
+        let packet = build_packet().ethernet(mac(1), MacAddr::BROADCAST).arp(
+            OperationCodes::Request,
+            mac(1),
+            V4_SRC,
+            MacAddr::UNSPECIFIED,
+            V4_DST,
+        );
+        assert_eq!(&ARP4_PACKET[..], &packet.build_bytes()[..]);
+    }
+
+    #[test]
+    fn build_ipv4_packets() {
+        let tcp = build_packet()
+            .ethernet(mac(2), mac(1))
+            .ipv4(V4_SRC, V4_DST)
+            .ident(2232)
+            .dont_fragment()
+            .ttl(255)
+            .tcp(36869, 23)
+            .seq(1_913_975_060, 0)
+            .tcp_flags(0x02)
+            .window(8760)
+            .tcp_options(&[0x02, 0x04, 0x05, 0xb4]);
+        assert_eq!(&IPV4_TCP_PACKET[..], &tcp.build_bytes()[..]);
+
+        let udp = build_packet()
+            .ethernet(mac(2), mac(1))
+            .ipv4(V4_SRC, V4_DST)
+            .ident(43849)
+            .dont_fragment()
+            .ttl(255)
+            .udp(39376, 1087)
+            .payload(b"hellohello");
+        assert_eq!(&IPV4_UDP_PACKET[..], &udp.build_bytes()[..]);
+
+        let icmp = build_packet()
+            .ethernet(
+                MacAddr::new(0x00, 0x0c, 0x29, 0x34, 0x0b, 0xde),
+                MacAddr::new(0x00, 0x50, 0x56, 0xe0, 0x14, 0x49),
+            )
+            .ipv4(
+                Ipv4Addr::new(192, 168, 158, 139),
+                Ipv4Addr::new(174, 137, 42, 77),
+            )
+            .ident(0xd743)
+            .ttl(128)
+            .icmpv4(0x08, 0)
+            .payload(&[0x02, 0x00, 0x21, 0x00])
+            .payload(b"abcdefghijklmnopqrstuvwabcdefghi");
+        assert_eq!(&ICMPV4_PACKET[..], &icmp.build_bytes()[..]);
+    }
+
+    #[test]
+    fn build_ipv6_packets() {
+        // the fixture's checksum is of the IPv4 TCP packet.
+        let tcp = build_packet()
+            .ethernet(mac(2), mac(1))
+            .ipv6(v6(V6_SRC), v6(V6_DST))
+            .ttl(2)
+            .tcp(36869, 23)
+            .seq(1_913_975_060, 0)
+            .tcp_flags(0x02)
+            .window(8760)
+            .tcp_options(&[0x02, 0x04, 0x05, 0xb4])
+            .checksum(0xa92c);
+        assert_eq!(&IPV6_TCP_PACKET[..], &tcp.build_bytes()[..]);
+
+        // the fixture doesn't have a checksum.
+        let mut payload = [0; 40];
+        payload[39] = 0x07;
+        let sr = build_packet()
+            .ethernet(mac(2), mac(1))
+            .ipv6(v6(V6_SRC), v6(V6_DST))
+            .ttl(2)
+            .srh(&[
+                v6("2001:db8:85a3::8a2e:370:7333"),
+                v6("2001:db8:85a3::8a2e:370:7334"),
+                v6("2001:db8:85a3::8a2e:370:7335"),
+            ])
+            .segments_left(0)
+            .tcp(3464, 1024)
+            .tcp_flags(0x02)
+            .window(10)
+            .checksum(0)
+            .payload(&payload);
+        assert_eq!(&SR_TCP_PACKET[..], &sr.build_bytes()[..]);
+
+        let fragment = build_packet()
+            .ethernet(mac(2), mac(1))
+            .ipv6(v6(V6_SRC), v6(V6_DST))
+            .flow(0, 0x2_1289)
+            .ttl(64)
+            .fragment(543, false, 0xf88e_b466)
+            .protocol(ProtocolNumbers::Udp)
+            .payload(b"hellohello");
+        assert_eq!(&IPV6_FRAGMENT_PACKET[..], &fragment.build_bytes()[..]);
+    }
+
+    #[test]
+    fn build_icmpv6_packets() {
+        // the fixture's checksum is not valid.
+        let unknown = build_packet()
+            .ethernet(mac(2), mac(1))
+            .ipv6(v6(LINK_LOCAL), v6(ALL_NODES))
+            .ttl(255)
+            .icmpv6(0xff, 0)
+            .checksum(0x01f0)
+            .payload(&[0; 4]);
+        assert_eq!(&ICMPV6_PACKET[..], &unknown.build_bytes()[..]);
+
+        let advert = build_packet()
+            .ethernet(mac(2), mac(1))
+            .ipv6(v6(LINK_LOCAL), v6(ALL_NODES))
+            .ttl(255)
+            .icmpv6(0x86, 0)
+            // hop limit, flags, router lifetime, reachable time, retrans timer
+            .payload(&[0x40, 0x40, 0x0e, 0x10, 0, 0, 0, 0, 0, 0, 0, 0])
+            // prefix information
+            .payload(&[
+                0x03, 0x04, 0x40, 0xc0, 0x00, 0x00, 0x09, 0x3e, 0x00, 0x00, 0x09, 0x3e, 0, 0, 0, 0,
+            ])
+            .payload(&v6("2607:fcc8:f142:b0f0::").octets())
+            // MTU
+            .payload(&[0x05, 0x01, 0, 0, 0x00, 0x00, 0x05, 0xdc])
+            // source link-layer address
+            .payload(&[0x01, 0x01, 0x70, 0x3a, 0xcb, 0x1b, 0xf9, 0x7a])
+            // recursive DNS server
+            .payload(&[0x19, 0x03, 0, 0, 0xff, 0xff, 0xff, 0xff])
+            .payload(&v6("2607:fcc8:f142:b0f0:d4f0:45ff:fe0c:664b").octets());
+        assert_eq!(&ROUTER_ADVERT_PACKET[..], &advert.build_bytes()[..]);
+
+        // the fixture's checksum is of the router advertisement.
+        let solicit = build_packet()
+            .ethernet(mac(2), mac(1))
+            .ipv6(v6(LINK_LOCAL), v6(ALL_NODES))
+            .ttl(255)
+            .icmpv6(0x85, 0)
+            .checksum(0xf50c)
+            .payload(&[0; 4])
+            .payload(&[0x01, 0x01, 0x70, 0x3a, 0xcb, 0x1b, 0xf9, 0x7a]);
+        assert_eq!(&ROUTER_SOLICIT_PACKET[..], &solicit.build_bytes()[..]);
+    }
+
+    #[capsule::test]
+    fn build_parsable_packet() {
+        let packet = build_packet()
+            .ethernet(mac(2), mac(1))
+            .vlan(123)
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+            .ttl(1)
+            .udp(5000, 53)
+            .payload(b"hello")
+            .build();
+
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(ethernet.is_dot1q());
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        assert_eq!(1, ipv4.ttl());
+        let mut udp = ipv4.parse::<Udp4>().unwrap();
+        assert_eq!(53, udp.dst_port());
+
+        // the computed fields are the ones reconcile computes.
+        let checksum = udp.checksum();
+        udp.reconcile_all();
+        assert_eq!(checksum, udp.checksum());
+        assert_eq!(13, udp.length());
+    }
+}
@@ -62,6 +62,29 @@ pub const VLAN_QINQ_PACKET: [u8; 68] = [
     0x00, 0x00, 0x00, 0x00, 0x00, 0x00
 ];
 
+/// A provider backbone bridging (802.1ah) packet encapsulating an ARP
+/// packet.
+#[rustfmt::skip]
+pub const PBB_PACKET: [u8; 60] = [
+// Backbone Ethernet header
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+    0x88, 0xe7,
+// I-TAG
+    // pcp, dei, nca, res
+    0x60,
+    // i-sid
+    0x01, 0x86, 0xa0,
+// Customer Ethernet header
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x04,
+    0x08, 0x06,
+// ARP payload
+    0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x02, 0x00, 0x19,
+    0x06, 0xea, 0xb8, 0xc1, 0xc0, 0xa8, 0x7b, 0x01, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xc0, 0xa8, 0x7b, 0x01
+];
+
 /// An ARP packet.
 #[rustfmt::skip]
 pub const ARP4_PACKET: [u8; 42] = [
@@ -0,0 +1,952 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Curated packets of common protocols, with their field values documented
+//! for the tests to assert against.
+//!
+//! The related fixtures are of a same flow, between `10.0.0.1`, or
+//! `2001:db8::1`, and `10.0.0.2`, or `2001:db8::2`. The lengths and the
+//! checksums are valid, except in the `MALFORMED_` fixtures, which are
+//! broken on purpose.
+
+/// A TCP SYN packet, the first of the three-way handshake of
+/// [`TCP_SYN_ACK_PACKET`] and [`TCP_ACK_PACKET`].
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv4: `10.0.0.1` to `10.0.0.2`, ident 1, DF, TTL 64, checksum
+///   `0x26b9`.
+/// * TCP: port 49152 to 80, seq_no 1000, window 64240, checksum `0x7470`.
+/// * TCP options: MSS 1460, SACK permitted, timestamps 100 and 0, and
+///   window scale 7.
+#[rustfmt::skip]
+pub const TCP_SYN_PACKET: [u8; 74] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 60
+    0x00, 0x3c,
+    // ident = 1, flags = DF, frag_offset = 0
+    0x00, 0x01, 0x40, 0x00,
+    // ttl = 64, protocol = TCP, checksum = 0x26b9
+    0x40, 0x06, 0x26, 0xb9,
+    // src = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+    // dst = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+// TCP header
+    // src_port = 49152, dst_port = 80
+    0xc0, 0x00, 0x00, 0x50,
+    // seq_no = 1000
+    0x00, 0x00, 0x03, 0xe8,
+    // ack_no = 0
+    0x00, 0x00, 0x00, 0x00,
+    // data_offset = 10, flags = SYN
+    0xa0, 0x02,
+    // window = 64240, checksum = 0x7470, urgent = 0
+    0xfa, 0xf0, 0x74, 0x70, 0x00, 0x00,
+// TCP options
+    // MSS = 1460
+    0x02, 0x04, 0x05, 0xb4,
+    // SACK permitted
+    0x04, 0x02,
+    // timestamps, tsval = 100, tsecr = 0
+    0x08, 0x0a, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x00,
+    // NOP, window scale = 7
+    0x01, 0x03, 0x03, 0x07
+];
+
+/// A TCP SYN-ACK packet, the answer to [`TCP_SYN_PACKET`].
+///
+/// * Ethernet: `02:00:00:00:00:02` to `02:00:00:00:00:01`.
+/// * IPv4: `10.0.0.2` to `10.0.0.1`, ident 0, DF, TTL 64, checksum
+///   `0x26ba`.
+/// * TCP: port 80 to 49152, seq_no 5000, ack_no 1001, window 65160,
+///   checksum `0x5c77`.
+/// * TCP options: MSS 1460, SACK permitted, timestamps 200 and 100, and
+///   window scale 7.
+#[rustfmt::skip]
+pub const TCP_SYN_ACK_PACKET: [u8; 74] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // src = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 60
+    0x00, 0x3c,
+    // ident = 0, flags = DF, frag_offset = 0
+    0x00, 0x00, 0x40, 0x00,
+    // ttl = 64, protocol = TCP, checksum = 0x26ba
+    0x40, 0x06, 0x26, 0xba,
+    // src = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+    // dst = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+// TCP header
+    // src_port = 80, dst_port = 49152
+    0x00, 0x50, 0xc0, 0x00,
+    // seq_no = 5000
+    0x00, 0x00, 0x13, 0x88,
+    // ack_no = 1001
+    0x00, 0x00, 0x03, 0xe9,
+    // data_offset = 10, flags = SYN|ACK
+    0xa0, 0x12,
+    // window = 65160, checksum = 0x5c77, urgent = 0
+    0xfe, 0x88, 0x5c, 0x77, 0x00, 0x00,
+// TCP options
+    // MSS = 1460
+    0x02, 0x04, 0x05, 0xb4,
+    // SACK permitted
+    0x04, 0x02,
+    // timestamps, tsval = 200, tsecr = 100
+    0x08, 0x0a, 0x00, 0x00, 0x00, 0xc8, 0x00, 0x00, 0x00, 0x64,
+    // NOP, window scale = 7
+    0x01, 0x03, 0x03, 0x07
+];
+
+/// A TCP ACK packet, completing the handshake of [`TCP_SYN_PACKET`] and
+/// [`TCP_SYN_ACK_PACKET`].
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv4: `10.0.0.1` to `10.0.0.2`, ident 2, DF, TTL 64, checksum
+///   `0x26c0`.
+/// * TCP: port 49152 to 80, seq_no 1001, ack_no 5001, window 502, checksum
+///   `0x87d5`.
+/// * TCP options: timestamps 101 and 200.
+#[rustfmt::skip]
+pub const TCP_ACK_PACKET: [u8; 66] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 52
+    0x00, 0x34,
+    // ident = 2, flags = DF, frag_offset = 0
+    0x00, 0x02, 0x40, 0x00,
+    // ttl = 64, protocol = TCP, checksum = 0x26c0
+    0x40, 0x06, 0x26, 0xc0,
+    // src = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+    // dst = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+// TCP header
+    // src_port = 49152, dst_port = 80
+    0xc0, 0x00, 0x00, 0x50,
+    // seq_no = 1001
+    0x00, 0x00, 0x03, 0xe9,
+    // ack_no = 5001
+    0x00, 0x00, 0x13, 0x89,
+    // data_offset = 8, flags = ACK
+    0x80, 0x10,
+    // window = 502, checksum = 0x87d5, urgent = 0
+    0x01, 0xf6, 0x87, 0xd5, 0x00, 0x00,
+// TCP options
+    // NOP, NOP
+    0x01, 0x01,
+    // timestamps, tsval = 101, tsecr = 200
+    0x08, 0x0a, 0x00, 0x00, 0x00, 0x65, 0x00, 0x00, 0x00, 0xc8
+];
+
+/// An ICMPv4 echo request packet.
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv4: `10.0.0.1` to `10.0.0.2`, ident 3, DF, TTL 64, checksum
+///   `0x26cc`.
+/// * ICMPv4: echo request, identifier `0x1234`, seq_no 1, 16 bytes of
+///   data `abcdefghijklmnop`, checksum `0xa27f`.
+#[rustfmt::skip]
+pub const ICMPV4_ECHO_REQUEST_PACKET: [u8; 58] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 44
+    0x00, 0x2c,
+    // ident = 3, flags = DF, frag_offset = 0
+    0x00, 0x03, 0x40, 0x00,
+    // ttl = 64, protocol = ICMPv4, checksum = 0x26cc
+    0x40, 0x01, 0x26, 0xcc,
+    // src = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+    // dst = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+// ICMPv4 header
+    // type = 8, code = 0, checksum = 0xa27f
+    0x08, 0x00, 0xa2, 0x7f,
+    // identifier = 0x1234, seq_no = 1
+    0x12, 0x34, 0x00, 0x01,
+    // data = "abcdefghijklmnop"
+    0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70
+];
+
+/// An ICMPv4 echo reply packet, the answer to
+/// [`ICMPV4_ECHO_REQUEST_PACKET`].
+///
+/// * Ethernet: `02:00:00:00:00:02` to `02:00:00:00:00:01`.
+/// * IPv4: `10.0.0.2` to `10.0.0.1`, ident 1000, no flags, TTL 64, checksum
+///   `0x62e7`.
+/// * ICMPv4: echo reply, identifier `0x1234`, seq_no 1, the same data as
+///   the request, checksum `0xaa7f`.
+#[rustfmt::skip]
+pub const ICMPV4_ECHO_REPLY_PACKET: [u8; 58] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // src = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 44
+    0x00, 0x2c,
+    // ident = 1000, flags = none, frag_offset = 0
+    0x03, 0xe8, 0x00, 0x00,
+    // ttl = 64, protocol = ICMPv4, checksum = 0x62e7
+    0x40, 0x01, 0x62, 0xe7,
+    // src = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+    // dst = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+// ICMPv4 header
+    // type = 0, code = 0, checksum = 0xaa7f
+    0x00, 0x00, 0xaa, 0x7f,
+    // identifier = 0x1234, seq_no = 1
+    0x12, 0x34, 0x00, 0x01,
+    // data = "abcdefghijklmnop"
+    0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70
+];
+
+/// An ICMPv6 echo request packet.
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv6: `2001:db8::1` to `2001:db8::2`, hop limit 64.
+/// * ICMPv6: echo request, identifier `0x1234`, seq_no 1, 16 bytes of
+///   data `abcdefghijklmnop`, checksum `0xceb7`.
+#[rustfmt::skip]
+pub const ICMPV6_ECHO_REQUEST_PACKET: [u8; 78] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv6
+    0x86, 0xdd,
+// IPv6 header
+    // version = 6, traffic_class = 0, flow_label = 0
+    0x60, 0x00, 0x00, 0x00,
+    // payload_len = 24, next_header = ICMPv6, hop_limit = 64
+    0x00, 0x18, 0x3a, 0x40,
+    // src = 2001:db8::1
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // dst = 2001:db8::2
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+// ICMPv6 header
+    // type = 128, code = 0, checksum = 0xceb7
+    0x80, 0x00, 0xce, 0xb7,
+    // identifier = 0x1234, seq_no = 1
+    0x12, 0x34, 0x00, 0x01,
+    // data = "abcdefghijklmnop"
+    0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70
+];
+
+/// An ICMPv6 echo reply packet, the answer to
+/// [`ICMPV6_ECHO_REQUEST_PACKET`].
+///
+/// * Ethernet: `02:00:00:00:00:02` to `02:00:00:00:00:01`.
+/// * IPv6: `2001:db8::2` to `2001:db8::1`, hop limit 64.
+/// * ICMPv6: echo reply, identifier `0x1234`, seq_no 1, the same data as
+///   the request, checksum `0xcdb7`.
+#[rustfmt::skip]
+pub const ICMPV6_ECHO_REPLY_PACKET: [u8; 78] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // src = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // ether_type = IPv6
+    0x86, 0xdd,
+// IPv6 header
+    // version = 6, traffic_class = 0, flow_label = 0
+    0x60, 0x00, 0x00, 0x00,
+    // payload_len = 24, next_header = ICMPv6, hop_limit = 64
+    0x00, 0x18, 0x3a, 0x40,
+    // src = 2001:db8::2
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // dst = 2001:db8::1
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+// ICMPv6 header
+    // type = 129, code = 0, checksum = 0xcdb7
+    0x81, 0x00, 0xcd, 0xb7,
+    // identifier = 0x1234, seq_no = 1
+    0x12, 0x34, 0x00, 0x01,
+    // data = "abcdefghijklmnop"
+    0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70
+];
+
+/// An IPv6 TCP packet with a hop-by-hop options header.
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv6: `2001:db8::1` to `2001:db8::2`, hop limit 64.
+/// * Hop-by-hop options: a router alert of value 0, padded with a PadN.
+/// * TCP: port 49152 to 80, seq_no 1001, ack_no 5001, ACK, window 502,
+///   checksum `0x7aa7`.
+#[rustfmt::skip]
+pub const IPV6_HOP_BY_HOP_TCP_PACKET: [u8; 82] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv6
+    0x86, 0xdd,
+// IPv6 header
+    // version = 6, traffic_class = 0, flow_label = 0
+    0x60, 0x00, 0x00, 0x00,
+    // payload_len = 28, next_header = Hop-by-Hop Options, hop_limit = 64
+    0x00, 0x1c, 0x00, 0x40,
+    // src = 2001:db8::1
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // dst = 2001:db8::2
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+// Hop-by-hop options header
+    // next_header = TCP, hdr_ext_len = 0
+    0x06, 0x00,
+    // router alert, value = 0
+    0x05, 0x02, 0x00, 0x00,
+    // PadN, 0 bytes of padding
+    0x01, 0x00,
+// TCP header
+    // src_port = 49152, dst_port = 80
+    0xc0, 0x00, 0x00, 0x50,
+    // seq_no = 1001
+    0x00, 0x00, 0x03, 0xe9,
+    // ack_no = 5001
+    0x00, 0x00, 0x13, 0x89,
+    // data_offset = 5, flags = ACK
+    0x50, 0x10,
+    // window = 502, checksum = 0x7aa7, urgent = 0
+    0x01, 0xf6, 0x7a, 0xa7, 0x00, 0x00
+];
+
+/// A DNS query packet, for the A record of `example.com`.
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv4: `10.0.0.1` to `10.0.0.2`, ident 4, DF, TTL 64, checksum
+///   `0x26ae`.
+/// * UDP: port 53000 to 53, length 37, checksum `0x32cb`.
+/// * DNS: id `0x1a2b`, recursion desired, one question.
+#[rustfmt::skip]
+pub const DNS_QUERY_PACKET: [u8; 71] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 57
+    0x00, 0x39,
+    // ident = 4, flags = DF, frag_offset = 0
+    0x00, 0x04, 0x40, 0x00,
+    // ttl = 64, protocol = UDP, checksum = 0x26ae
+    0x40, 0x11, 0x26, 0xae,
+    // src = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+    // dst = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+// UDP header
+    // src_port = 53000, dst_port = 53
+    0xcf, 0x08, 0x00, 0x35,
+    // length = 37, checksum = 0x32cb
+    0x00, 0x25, 0x32, 0xcb,
+// DNS header
+    // id = 0x1a2b, flags = 0x0100 (RD)
+    0x1a, 0x2b, 0x01, 0x00,
+    // questions = 1, answers = 0, authorities = 0, additionals = 0
+    0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+// DNS question
+    // name = example.com
+    0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+    // type = A, class = IN
+    0x00, 0x01, 0x00, 0x01
+];
+
+/// A DNS response packet, the answer to [`DNS_QUERY_PACKET`].
+///
+/// * Ethernet: `02:00:00:00:00:02` to `02:00:00:00:00:01`.
+/// * IPv4: `10.0.0.2` to `10.0.0.1`, ident 5, DF, TTL 64, checksum
+///   `0x269d`.
+/// * UDP: port 53 to 53000, length 53, checksum `0x9832`.
+/// * DNS: id `0x1a2b`, a recursive answer without error, the question and
+///   one A record of `93.184.216.34` with a TTL of 300 seconds.
+#[rustfmt::skip]
+pub const DNS_RESPONSE_PACKET: [u8; 87] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // src = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 73
+    0x00, 0x49,
+    // ident = 5, flags = DF, frag_offset = 0
+    0x00, 0x05, 0x40, 0x00,
+    // ttl = 64, protocol = UDP, checksum = 0x269d
+    0x40, 0x11, 0x26, 0x9d,
+    // src = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+    // dst = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+// UDP header
+    // src_port = 53, dst_port = 53000
+    0x00, 0x35, 0xcf, 0x08,
+    // length = 53, checksum = 0x9832
+    0x00, 0x35, 0x98, 0x32,
+// DNS header
+    // id = 0x1a2b, flags = 0x8180 (QR|RD|RA)
+    0x1a, 0x2b, 0x81, 0x80,
+    // questions = 1, answers = 1, authorities = 0, additionals = 0
+    0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+// DNS question
+    // name = example.com
+    0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+    // type = A, class = IN
+    0x00, 0x01, 0x00, 0x01,
+// DNS answer
+    // name = pointer to offset 12, type = A, class = IN
+    0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01,
+    // ttl = 300, rdlength = 4
+    0x00, 0x00, 0x01, 0x2c, 0x00, 0x04,
+    // address = 93.184.216.34
+    0x5d, 0xb8, 0xd8, 0x22
+];
+
+/// A VXLAN packet encapsulating [`ICMPV4_ECHO_REQUEST_PACKET`].
+///
+/// * Ethernet: `02:00:00:00:00:0a` to `02:00:00:00:00:0b`.
+/// * IPv4: `192.0.2.1` to `192.0.2.2`, ident 6, DF, TTL 64, checksum
+///   `0xb685`.
+/// * UDP: port 54321 to 4789, length 74, no checksum.
+/// * VXLAN: VNI 100.
+/// * The inner frame starts at offset 50.
+#[rustfmt::skip]
+pub const VXLAN_PACKET: [u8; 108] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:0b
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x0b,
+    // src = 02:00:00:00:00:0a
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x0a,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 94
+    0x00, 0x5e,
+    // ident = 6, flags = DF, frag_offset = 0
+    0x00, 0x06, 0x40, 0x00,
+    // ttl = 64, protocol = UDP, checksum = 0xb685
+    0x40, 0x11, 0xb6, 0x85,
+    // src = 192.0.2.1
+    0xc0, 0x00, 0x02, 0x01,
+    // dst = 192.0.2.2
+    0xc0, 0x00, 0x02, 0x02,
+// UDP header
+    // src_port = 54321, dst_port = 4789
+    0xd4, 0x31, 0x12, 0xb5,
+    // length = 74, checksum = 0x0000
+    0x00, 0x4a, 0x00, 0x00,
+// VXLAN header
+    // flags = I, reserved
+    0x08, 0x00, 0x00, 0x00,
+    // vni = 100, reserved
+    0x00, 0x00, 0x64, 0x00,
+// Inner Ethernet frame
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00, 0x45, 0x00,
+    0x00, 0x2c, 0x00, 0x03, 0x40, 0x00, 0x40, 0x01, 0x26, 0xcc, 0x0a, 0x00, 0x00, 0x01, 0x0a, 0x00,
+    0x00, 0x02, 0x08, 0x00, 0xa2, 0x7f, 0x12, 0x34, 0x00, 0x01, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66,
+    0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f, 0x70
+];
+
+/// The first fragment of an IPv4 UDP datagram, completed by
+/// [`IPV4_FRAGMENT_LAST_PACKET`].
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv4: `10.0.0.1` to `10.0.0.2`, ident 7238, MF, fragment offset 0,
+///   TTL 64, checksum `0x2a81`.
+/// * UDP: port 5000 to 5000, length 32, checksum `0x73db` of the whole
+///   datagram.
+/// * The first 8 of the 24 bytes of data `fragmented udp datagram!`.
+#[rustfmt::skip]
+pub const IPV4_FRAGMENT_FIRST_PACKET: [u8; 50] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 36
+    0x00, 0x24,
+    // ident = 7238, flags = MF, frag_offset = 0
+    0x1c, 0x46, 0x20, 0x00,
+    // ttl = 64, protocol = UDP, checksum = 0x2a81
+    0x40, 0x11, 0x2a, 0x81,
+    // src = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+    // dst = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+// UDP header
+    // src_port = 5000, dst_port = 5000
+    0x13, 0x88, 0x13, 0x88,
+    // length = 32, checksum = 0x73db
+    0x00, 0x20, 0x73, 0xdb,
+    // UDP payload
+    0x66, 0x72, 0x61, 0x67, 0x6d, 0x65, 0x6e, 0x74
+];
+
+/// The last fragment of the IPv4 UDP datagram of
+/// [`IPV4_FRAGMENT_FIRST_PACKET`].
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv4: `10.0.0.1` to `10.0.0.2`, ident 7238, fragment offset 2 (16
+///   bytes), TTL 64, checksum `0x4a7f`.
+/// * The remaining 16 bytes of the UDP data.
+#[rustfmt::skip]
+pub const IPV4_FRAGMENT_LAST_PACKET: [u8; 50] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 36
+    0x00, 0x24,
+    // ident = 7238, flags = none, frag_offset = 2
+    0x1c, 0x46, 0x00, 0x02,
+    // ttl = 64, protocol = UDP, checksum = 0x4a7f
+    0x40, 0x11, 0x4a, 0x7f,
+    // src = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+    // dst = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+    // UDP payload, continued
+    0x65, 0x64, 0x20, 0x75, 0x64, 0x70, 0x20, 0x64, 0x61, 0x74, 0x61, 0x67, 0x72, 0x61, 0x6d, 0x21
+];
+
+/// An IPv4 packet truncated in the middle of its header.
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv4: 12 of the 20 bytes of the header, ending right before the
+///   addresses. The total length is 28.
+#[rustfmt::skip]
+pub const MALFORMED_TRUNCATED_IPV4_PACKET: [u8; 26] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 28
+    0x00, 0x1c,
+    // ident = 8, flags = DF, frag_offset = 0
+    0x00, 0x08, 0x40, 0x00,
+    // ttl = 64, protocol = UDP, checksum = 0x26c7
+    0x40, 0x11, 0x26, 0xc7
+];
+
+/// An IPv4 UDP packet with a wrong UDP checksum.
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv4: `10.0.0.1` to `10.0.0.2`, ident 9, DF, TTL 64, checksum
+///   `0x26c1`.
+/// * UDP: port 5000 to 5000, length 13, checksum `0xdead` instead of
+///   `0x80ef`.
+/// * 5 bytes of data `hello`.
+#[rustfmt::skip]
+pub const MALFORMED_BAD_CHECKSUM_PACKET: [u8; 47] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 5, dscp = 0, ecn = 0
+    0x45, 0x00,
+    // total_length = 33
+    0x00, 0x21,
+    // ident = 9, flags = DF, frag_offset = 0
+    0x00, 0x09, 0x40, 0x00,
+    // ttl = 64, protocol = UDP, checksum = 0x26c1
+    0x40, 0x11, 0x26, 0xc1,
+    // src = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+    // dst = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+// UDP header
+    // src_port = 5000, dst_port = 5000
+    0x13, 0x88, 0x13, 0x88,
+    // length = 13, checksum = 0xdead
+    0x00, 0x0d, 0xde, 0xad,
+    // UDP payload
+    0x68, 0x65, 0x6c, 0x6c, 0x6f
+];
+
+/// An IPv4 UDP packet with an IHL longer than the packet.
+///
+/// * Ethernet: `02:00:00:00:00:01` to `02:00:00:00:00:02`.
+/// * IPv4: `10.0.0.1` to `10.0.0.2`, ident 10, DF, TTL 64, checksum
+///   `0x1cc5` of the first 20 bytes. The IHL is 15, a 60 bytes header,
+///   but the packet ends 28 bytes after the start of the header.
+/// * UDP: port 5000 to 5000, length 8, checksum `0xc4cb`, no data.
+#[rustfmt::skip]
+pub const MALFORMED_IHL_TOO_LONG_PACKET: [u8; 42] = [
+// Ethernet header
+    // dst = 02:00:00:00:00:02
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+    // src = 02:00:00:00:00:01
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+    // ether_type = IPv4
+    0x08, 0x00,
+// IPv4 header
+    // version = 4, ihl = 15, dscp = 0, ecn = 0
+    0x4f, 0x00,
+    // total_length = 28
+    0x00, 0x1c,
+    // ident = 10, flags = DF, frag_offset = 0
+    0x00, 0x0a, 0x40, 0x00,
+    // ttl = 64, protocol = UDP, checksum = 0x1cc5
+    0x40, 0x11, 0x1c, 0xc5,
+    // src = 10.0.0.1
+    0x0a, 0x00, 0x00, 0x01,
+    // dst = 10.0.0.2
+    0x0a, 0x00, 0x00, 0x02,
+// UDP header
+    // src_port = 5000, dst_port = 5000
+    0x13, 0x88, 0x13, 0x88,
+    // length = 8, checksum = 0xc4cb
+    0x00, 0x08, 0xc4, 0xcb
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::MacAddr;
+    use crate::packets::icmp::v4::{self, Icmpv4};
+    use crate::packets::icmp::v6::{self, Icmpv6};
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::ip::v6::Ipv6;
+    use crate::packets::{Ethernet, Packet, Tcp4, Udp4};
+    use crate::testils::{build_packet, verify_checksums, PacketBuilder};
+    use crate::Mbuf;
+    use crate::{assert_checksums_valid, assert_packet_eq};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    const CLIENT_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    const SERVER_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+
+    /// The identifier, the sequence number and the data of the echoes.
+    const ECHO: &[u8] = b"\x12\x34\x00\x01abcdefghijklmnop";
+
+    fn mac(last: u8) -> MacAddr {
+        MacAddr::new(0x02, 0, 0, 0, 0, last)
+    }
+
+    fn to_server() -> PacketBuilder {
+        build_packet()
+            .ethernet(mac(0x01), mac(0x02))
+            .ipv4(CLIENT, SERVER)
+    }
+
+    fn to_client() -> PacketBuilder {
+        build_packet()
+            .ethernet(mac(0x02), mac(0x01))
+            .ipv4(SERVER, CLIENT)
+    }
+
+    #[test]
+    fn build_tcp_handshake() {
+        let syn = to_server()
+            .ident(1)
+            .dont_fragment()
+            .tcp(49152, 80)
+            .seq(1000, 0)
+            .tcp_flags(0x02)
+            .window(64240)
+            .tcp_options(&TCP_SYN_PACKET[54..]);
+        assert_packet_eq!(syn.build_bytes(), TCP_SYN_PACKET);
+
+        let syn_ack = to_client()
+            .dont_fragment()
+            .tcp(80, 49152)
+            .seq(5000, 1001)
+            .tcp_flags(0x12)
+            .window(65160)
+            .tcp_options(&TCP_SYN_ACK_PACKET[54..]);
+        assert_packet_eq!(syn_ack.build_bytes(), TCP_SYN_ACK_PACKET);
+
+        let ack = to_server()
+            .ident(2)
+            .dont_fragment()
+            .tcp(49152, 80)
+            .seq(1001, 5001)
+            .tcp_flags(0x10)
+            .window(502)
+            .tcp_options(&TCP_ACK_PACKET[54..]);
+        assert_packet_eq!(ack.build_bytes(), TCP_ACK_PACKET);
+    }
+
+    #[test]
+    fn build_echoes() {
+        let request = to_server()
+            .ident(3)
+            .dont_fragment()
+            .icmpv4(8, 0)
+            .payload(ECHO);
+        assert_packet_eq!(request.build_bytes(), ICMPV4_ECHO_REQUEST_PACKET);
+
+        let reply = to_client().ident(1000).icmpv4(0, 0).payload(ECHO);
+        assert_packet_eq!(reply.build_bytes(), ICMPV4_ECHO_REPLY_PACKET);
+
+        let request = build_packet()
+            .ethernet(mac(0x01), mac(0x02))
+            .ipv6(CLIENT_V6, SERVER_V6)
+            .icmpv6(128, 0)
+            .payload(ECHO);
+        assert_packet_eq!(request.build_bytes(), ICMPV6_ECHO_REQUEST_PACKET);
+
+        let reply = build_packet()
+            .ethernet(mac(0x02), mac(0x01))
+            .ipv6(SERVER_V6, CLIENT_V6)
+            .icmpv6(129, 0)
+            .payload(ECHO);
+        assert_packet_eq!(reply.build_bytes(), ICMPV6_ECHO_REPLY_PACKET);
+    }
+
+    #[test]
+    fn build_udp_packets() {
+        let query = to_server()
+            .ident(4)
+            .dont_fragment()
+            .udp(53000, 53)
+            .payload(&DNS_QUERY_PACKET[42..]);
+        assert_packet_eq!(query.build_bytes(), DNS_QUERY_PACKET);
+
+        let response = to_client()
+            .ident(5)
+            .dont_fragment()
+            .udp(53, 53000)
+            .payload(&DNS_RESPONSE_PACKET[42..]);
+        assert_packet_eq!(response.build_bytes(), DNS_RESPONSE_PACKET);
+
+        let mut encapsulated = vec![0x08, 0, 0, 0, 0, 0, 100, 0];
+        encapsulated.extend_from_slice(&ICMPV4_ECHO_REQUEST_PACKET);
+        let vxlan = build_packet()
+            .ethernet(mac(0x0a), mac(0x0b))
+            .ipv4(Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2))
+            .ident(6)
+            .dont_fragment()
+            .udp(54321, 4789)
+            .checksum(0)
+            .payload(&encapsulated);
+        assert_packet_eq!(vxlan.build_bytes(), VXLAN_PACKET);
+    }
+
+    #[test]
+    fn build_malformed_packets() {
+        let bad_checksum = to_server()
+            .ident(9)
+            .dont_fragment()
+            .udp(5000, 5000)
+            .checksum(0xdead)
+            .payload(b"hello");
+        assert_packet_eq!(bad_checksum.build_bytes(), MALFORMED_BAD_CHECKSUM_PACKET);
+
+        let ihl_too_long = to_server()
+            .ident(10)
+            .dont_fragment()
+            .ihl(15)
+            .udp(5000, 5000);
+        assert_packet_eq!(ihl_too_long.build_bytes(), MALFORMED_IHL_TOO_LONG_PACKET);
+
+        let truncated = to_server().ident(8).dont_fragment().udp(5000, 5000);
+        assert_packet_eq!(
+            &truncated.build_bytes()[..26],
+            MALFORMED_TRUNCATED_IPV4_PACKET
+        );
+    }
+
+    #[test]
+    fn valid_checksums() {
+        assert_checksums_valid!(TCP_SYN_PACKET);
+        assert_checksums_valid!(TCP_SYN_ACK_PACKET);
+        assert_checksums_valid!(TCP_ACK_PACKET);
+        assert_checksums_valid!(ICMPV4_ECHO_REQUEST_PACKET);
+        assert_checksums_valid!(ICMPV4_ECHO_REPLY_PACKET);
+        assert_checksums_valid!(ICMPV6_ECHO_REQUEST_PACKET);
+        assert_checksums_valid!(ICMPV6_ECHO_REPLY_PACKET);
+        assert_checksums_valid!(IPV6_HOP_BY_HOP_TCP_PACKET);
+        assert_checksums_valid!(DNS_QUERY_PACKET);
+        assert_checksums_valid!(DNS_RESPONSE_PACKET);
+        assert_checksums_valid!(VXLAN_PACKET);
+        assert_checksums_valid!(IPV4_FRAGMENT_FIRST_PACKET);
+        assert_checksums_valid!(IPV4_FRAGMENT_LAST_PACKET);
+
+        assert!(verify_checksums(&MALFORMED_BAD_CHECKSUM_PACKET).is_err());
+    }
+
+    #[capsule::test]
+    fn parse_tcp_handshake() {
+        let packet = Mbuf::from_bytes(&TCP_SYN_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let syn = ipv4.parse::<Tcp4>().unwrap();
+        assert!(syn.syn() && !syn.ack());
+        assert_eq!(1000, syn.seq_no());
+        assert_eq!(10, syn.data_offset());
+
+        let packet = Mbuf::from_bytes(&TCP_SYN_ACK_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let syn_ack = ipv4.parse::<Tcp4>().unwrap();
+        assert!(syn_ack.syn() && syn_ack.ack());
+        assert_eq!(syn.seq_no() + 1, syn_ack.ack_no());
+
+        let packet = Mbuf::from_bytes(&TCP_ACK_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let ack = ipv4.parse::<Tcp4>().unwrap();
+        assert!(!ack.syn() && ack.ack());
+        assert_eq!(syn_ack.ack_no(), ack.seq_no());
+        assert_eq!(syn_ack.seq_no() + 1, ack.ack_no());
+    }
+
+    #[capsule::test]
+    fn parse_echoes() {
+        let packet = Mbuf::from_bytes(&ICMPV4_ECHO_REQUEST_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let icmpv4 = ipv4.parse::<Icmpv4>().unwrap();
+        let request = icmpv4.downcast::<v4::EchoRequest>().unwrap();
+        assert_eq!(0x1234, request.identifier());
+        assert_eq!(1, request.seq_no());
+        assert_eq!(b"abcdefghijklmnop", request.data());
+
+        let packet = Mbuf::from_bytes(&ICMPV6_ECHO_REPLY_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv6 = ethernet.parse::<Ipv6>().unwrap();
+        let icmpv6 = ipv6.parse::<Icmpv6<Ipv6>>().unwrap();
+        let reply = icmpv6.downcast::<v6::EchoReply<Ipv6>>().unwrap();
+        assert_eq!(0x1234, reply.identifier());
+        assert_eq!(1, reply.seq_no());
+        assert_eq!(b"abcdefghijklmnop", reply.data());
+    }
+
+    #[capsule::test]
+    fn parse_vxlan_packet() {
+        let packet = Mbuf::from_bytes(&VXLAN_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let udp = ipv4.parse::<Udp4>().unwrap();
+        assert_eq!(4789, udp.dst_port());
+        assert_eq!(0, udp.checksum());
+        assert_eq!(50, udp.payload_offset() + 8);
+    }
+
+    #[capsule::test]
+    fn parse_fragments() {
+        let packet = Mbuf::from_bytes(&IPV4_FRAGMENT_FIRST_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let first = ethernet.parse::<Ipv4>().unwrap();
+        assert!(first.more_fragments());
+        assert_eq!(0, first.fragment_offset());
+
+        let packet = Mbuf::from_bytes(&IPV4_FRAGMENT_LAST_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let last = ethernet.parse::<Ipv4>().unwrap();
+        assert!(!last.more_fragments());
+        assert_eq!(2, last.fragment_offset());
+        assert_eq!(first.identification(), last.identification());
+    }
+
+    #[capsule::test]
+    fn parse_malformed_packets() {
+        let packet = Mbuf::from_bytes(&MALFORMED_TRUNCATED_IPV4_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        assert!(ethernet.parse::<Ipv4>().is_err());
+
+        let packet = Mbuf::from_bytes(&MALFORMED_BAD_CHECKSUM_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        let udp = ipv4.parse::<Udp4>().unwrap();
+        assert_eq!(0xdead, udp.checksum());
+
+        // the UDP header is read past the end of the packet.
+        let packet = Mbuf::from_bytes(&MALFORMED_IHL_TOO_LONG_PACKET).unwrap();
+        let ethernet = packet.parse::<Ethernet>().unwrap();
+        let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+        assert!(ipv4.parse::<Udp4>().is_err());
+    }
+}
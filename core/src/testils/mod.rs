@@ -18,14 +18,23 @@
 
 //! Utilities for unit tests and benchmarks.
 
+mod assert;
+mod builder;
 pub mod byte_arrays;
 pub mod criterion;
+pub mod fixtures;
 mod packet;
 pub mod proptest;
 mod rvg;
+#[cfg(test)]
+mod vdev;
 
+pub use self::assert::*;
+pub use self::builder::*;
 pub use self::packet::*;
 pub use self::rvg::*;
+#[cfg(test)]
+pub(crate) use self::vdev::*;
 
 use crate::dpdk::{self, Mempool, SocketId, MEMPOOL};
 use crate::metrics;
@@ -21,8 +21,11 @@
 
 use crate::dpdk::Mbuf;
 use crate::net::MacAddr;
+use crate::packets::{EtherType, EtherTypes, VlanTag};
 use proptest::arbitrary::{any, Arbitrary, StrategyFor};
-use proptest::strategy::{MapInto, Strategy};
+use proptest::prop_oneof;
+use proptest::sample::select;
+use proptest::strategy::{BoxedStrategy, MapInto, Strategy};
 
 impl Arbitrary for MacAddr {
     type Parameters = ();
@@ -33,6 +36,42 @@ impl Arbitrary for MacAddr {
     }
 }
 
+/// Mostly the EtherTypes the crate knows, and sometimes any other.
+impl Arbitrary for EtherType {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        let known = vec![
+            EtherTypes::Ipv4,
+            EtherTypes::Ipv6,
+            EtherTypes::Arp,
+            EtherTypes::Vlan,
+            EtherTypes::QinQ,
+            EtherTypes::Pbb,
+            EtherTypes::Mpls,
+            EtherTypes::Lldp,
+        ];
+        prop_oneof![
+            3 => select(known),
+            1 => any::<u16>().prop_map(EtherType::new),
+        ]
+        .boxed()
+    }
+}
+
+/// Tags with any priority and VLAN identifier, except the reserved 4095.
+impl Arbitrary for VlanTag {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (0..0x0fffu16, 0..8u8)
+            .prop_map(|(vid, pcp)| VlanTag::new(vid).with_priority(pcp))
+            .boxed()
+    }
+}
+
 impl Arbitrary for Mbuf {
     type Parameters = ();
     type Strategy = fn() -> Self;
@@ -24,6 +24,8 @@
 //! [`Strategy`]: https://docs.rs/proptest/latest/proptest/strategy/trait.Strategy.html
 
 mod arbitrary;
+mod stack;
 mod strategy;
 
+pub use self::stack::*;
 pub use self::strategy::*;
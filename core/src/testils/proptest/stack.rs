@@ -0,0 +1,331 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Strategies of headers and packet stacks, assembled with the
+//! [`PacketBuilder`].
+//!
+//! Unlike the strategies set through `fieldmap!`, the fields have the
+//! distributions of real traffic, and a packet can be malformed on purpose.
+//! The packets shrink to smaller payloads, fewer VLAN tags and UDP over
+//! IPv4.
+
+use crate::net::MacAddr;
+use crate::testils::{build_packet, PacketBuilder};
+use crate::Mbuf;
+use proptest::arbitrary::any;
+use proptest::bool::weighted;
+use proptest::collection::vec;
+use proptest::prop_oneof;
+use proptest::sample::{select, Index};
+use proptest::strategy::{Just, Strategy};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// The malformations [`arbitrary_packet_with`] may apply to the packets.
+///
+/// Each enabled malformation is applied to about half of the packets, and
+/// is removed when shrinking.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Malformations {
+    /// Truncates the packet anywhere after the first byte.
+    pub truncation: bool,
+
+    /// Sets a wrong checksum of the TCP or UDP header.
+    pub bad_checksums: bool,
+
+    /// Sets the IHL of the IPv4 header to below the minimum of 5.
+    pub illegal_ihl: bool,
+}
+
+impl Malformations {
+    /// Returns all the malformations.
+    pub fn all() -> Self {
+        Malformations {
+            truncation: true,
+            bad_checksums: true,
+            illegal_ihl: true,
+        }
+    }
+}
+
+/// Returns a strategy of whether to apply a malformation.
+fn malformed(enabled: bool) -> impl Strategy<Value = bool> {
+    weighted(if enabled { 0.5 } else { 0.0 })
+}
+
+/// Returns a strategy of the TTL or hop limit, mostly the common initial
+/// values.
+fn ttl() -> impl Strategy<Value = u8> {
+    prop_oneof![select(vec![64u8, 128, 255]), 1..=255u8]
+}
+
+/// Returns a strategy of the TCP or UDP port, well-known, registered or
+/// ephemeral.
+fn port() -> impl Strategy<Value = u16> {
+    prop_oneof![
+        select(vec![53u16, 80, 443, 22, 123]),
+        1024..49152u16,
+        49152..=65535u16,
+    ]
+}
+
+/// Returns a strategy of the payload of up to 256 bytes.
+fn payload() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..=256)
+}
+
+/// Returns a strategy to generate Ethernet headers with up to two VLAN
+/// tags. With two tags, the outer one is a service tag.
+pub fn ethernet_header() -> impl Strategy<Value = PacketBuilder> {
+    (
+        any::<MacAddr>(),
+        any::<MacAddr>(),
+        vec((0..0x0fffu16, 0..8u8), 0..=2),
+    )
+        .prop_map(|(src, dst, tags)| {
+            let mut builder = build_packet().ethernet(src, dst);
+            for (i, &(vid, pcp)) in tags.iter().enumerate() {
+                builder = if i + 1 < tags.len() {
+                    builder.qinq(vid)
+                } else {
+                    builder.vlan(vid)
+                };
+                builder = builder.pcp(pcp);
+            }
+            builder
+        })
+}
+
+/// Returns a strategy to append IPv4 headers to the builders of `outer`.
+///
+/// The total length, the protocol and the checksum are computed by the
+/// builder from the layers after the header.
+pub fn ipv4_header(
+    outer: impl Strategy<Value = PacketBuilder>,
+) -> impl Strategy<Value = PacketBuilder> {
+    (
+        outer,
+        any::<Ipv4Addr>(),
+        any::<Ipv4Addr>(),
+        any::<u8>(),
+        any::<u16>(),
+        any::<bool>(),
+        ttl(),
+    )
+        .prop_map(|(builder, src, dst, tos, ident, dont_fragment, ttl)| {
+            let builder = builder.ipv4(src, dst).tos(tos).ident(ident).ttl(ttl);
+            if dont_fragment {
+                builder.dont_fragment()
+            } else {
+                builder
+            }
+        })
+}
+
+/// Returns a strategy to append IPv6 headers to the builders of `outer`.
+///
+/// The payload length and the next header are computed by the builder
+/// from the layers after the header.
+pub fn ipv6_header(
+    outer: impl Strategy<Value = PacketBuilder>,
+) -> impl Strategy<Value = PacketBuilder> {
+    (
+        outer,
+        any::<Ipv6Addr>(),
+        any::<Ipv6Addr>(),
+        any::<u8>(),
+        0..=0xf_ffffu32,
+        ttl(),
+    )
+        .prop_map(
+            |(builder, src, dst, traffic_class, flow_label, hop_limit)| {
+                builder
+                    .ipv6(src, dst)
+                    .flow(traffic_class, flow_label)
+                    .ttl(hop_limit)
+            },
+        )
+}
+
+/// Returns a strategy to append TCP headers to the builders of `outer`.
+///
+/// The flags are mostly the combinations of a connection's lifetime, and
+/// the options are either none or the MSS. The checksum is computed by
+/// the builder.
+pub fn tcp_header(
+    outer: impl Strategy<Value = PacketBuilder>,
+) -> impl Strategy<Value = PacketBuilder> {
+    let flags = prop_oneof![
+        // SYN, SYN-ACK, ACK, PSH-ACK, FIN-ACK, RST and RST-ACK.
+        3 => select(vec![0x02u8, 0x12, 0x10, 0x18, 0x11, 0x04, 0x14]),
+        1 => any::<u8>(),
+    ];
+    let options = prop_oneof![Just(vec![]), Just(vec![0x02, 0x04, 0x05, 0xb4])];
+
+    (
+        outer,
+        port(),
+        port(),
+        any::<u32>(),
+        any::<u32>(),
+        flags,
+        any::<u16>(),
+        options,
+    )
+        .prop_map(
+            |(builder, src_port, dst_port, seq_no, ack_no, flags, window, options)| {
+                builder
+                    .tcp(src_port, dst_port)
+                    .seq(seq_no, ack_no)
+                    .tcp_flags(flags)
+                    .window(window)
+                    .tcp_options(&options)
+            },
+        )
+}
+
+/// Returns a strategy to append UDP headers to the builders of `outer`.
+///
+/// The length and the checksum are computed by the builder.
+pub fn udp_header(
+    outer: impl Strategy<Value = PacketBuilder>,
+) -> impl Strategy<Value = PacketBuilder> {
+    (outer, port(), port())
+        .prop_map(|(builder, src_port, dst_port)| builder.udp(src_port, dst_port))
+}
+
+/// Returns a strategy of the IPv4 or IPv6 headers over Ethernet.
+fn ip_header(malformations: Malformations) -> impl Strategy<Value = PacketBuilder> {
+    let v4 = (
+        ipv4_header(ethernet_header()),
+        malformed(malformations.illegal_ihl),
+        0..5u8,
+    )
+        .prop_map(
+            |(builder, malformed, ihl)| {
+                if malformed {
+                    builder.ihl(ihl)
+                } else {
+                    builder
+                }
+            },
+        );
+
+    prop_oneof![v4, ipv6_header(ethernet_header())]
+}
+
+/// Returns a strategy to generate TCP or UDP packets, over IPv4 or IPv6
+/// and Ethernet with up to two VLAN tags.
+///
+/// All the packets are valid. See [`arbitrary_packet_with`] to malform
+/// them.
+pub fn arbitrary_packet() -> impl Strategy<Value = Mbuf> {
+    arbitrary_packet_with(Malformations::default())
+}
+
+/// Returns a strategy to generate TCP or UDP packets, with the
+/// malformations.
+///
+/// # Example
+///
+/// ```
+/// #[capsule::test]
+/// fn parse_malformed_packet() {
+///     proptest!(|(packet in arbitrary_packet_with(Malformations::all()))| {
+///         if let Ok(ethernet) = packet.parse::<Ethernet>() {
+///             let _ = ethernet.parse_next_dyn();
+///         }
+///     });
+/// }
+/// ```
+pub fn arbitrary_packet_with(malformations: Malformations) -> impl Strategy<Value = Mbuf> {
+    let stack = prop_oneof![
+        udp_header(ip_header(malformations)),
+        tcp_header(ip_header(malformations)),
+    ];
+
+    (
+        stack,
+        payload(),
+        (malformed(malformations.bad_checksums), 1..=0xffffu16),
+        (malformed(malformations.truncation), any::<Index>()),
+    )
+        .prop_map(
+            |(builder, payload, (bad_checksum, checksum), (truncate, len))| {
+                let builder = builder.payload(&payload);
+                let mut bytes = builder.build_bytes();
+
+                if bad_checksum {
+                    let pinned = builder.clone().checksum(checksum).build_bytes();
+                    // the checksum is only wrong if it's not the computed one.
+                    bytes = if pinned != bytes {
+                        pinned
+                    } else {
+                        builder
+                            .checksum(checksum.wrapping_add(1).max(1))
+                            .build_bytes()
+                    };
+                }
+
+                if truncate {
+                    bytes.truncate(1 + len.index(bytes.len() - 1));
+                }
+
+                Mbuf::from_bytes(&bytes).unwrap()
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::ip::v4::Ipv4;
+    use crate::packets::{Ethernet, Packet, Tcp4};
+    use proptest::{prop_assert, prop_assert_eq, proptest};
+
+    #[capsule::test]
+    fn incremental_checksum_equals_recompute() {
+        let packets = tcp_header(ipv4_header(ethernet_header()));
+
+        proptest!(|(builder in packets, dst in any::<Ipv4Addr>())| {
+            let packet = builder.build();
+            let ethernet = packet.parse::<Ethernet>().unwrap();
+            let ipv4 = ethernet.parse::<Ipv4>().unwrap();
+            let mut tcp = ipv4.parse::<Tcp4>().unwrap();
+
+            tcp.set_dst_ip(dst.into()).unwrap();
+            let incremental = tcp.checksum();
+            tcp.reconcile_all();
+            prop_assert_eq!(incremental, tcp.checksum());
+        });
+    }
+
+    #[capsule::test]
+    fn parse_arbitrary_packets() {
+        proptest!(|(packet in arbitrary_packet())| {
+            let ethernet = packet.parse::<Ethernet>().unwrap();
+            prop_assert!(ethernet.parse_next_dyn().is_ok());
+        });
+
+        // malformed packets may not parse, but never panic.
+        proptest!(|(packet in arbitrary_packet_with(Malformations::all()))| {
+            if let Ok(ethernet) = packet.parse::<Ethernet>() {
+                let _ = ethernet.parse_next_dyn();
+            }
+        });
+    }
+}
@@ -0,0 +1,89 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use crate::dpdk::{self, CoreId, Mempool, Port, PortBuilder};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of the next test virtual device.
+static NEXT_VDEV: AtomicUsize = AtomicUsize::new(0);
+
+/// A virtual device hotplugged for the duration of a test.
+///
+/// Each device gets a name unique to the process, so the tests that use
+/// the same driver can run in parallel. The device is removed on drop,
+/// which must come after its port is dropped. Declare the device before
+/// the port so the locals are dropped in that order.
+///
+/// # Example
+///
+/// ```
+/// let vdev = TestVdev::new("net_null");
+/// let mut mempools = vec![Mempool::new(63, 0, core_id.socket_id()).unwrap()];
+/// let mut port = vdev.port("vm1", &[core_id], &mut mempools);
+/// port.start().unwrap();
+/// ```
+pub(crate) struct TestVdev {
+    device: String,
+}
+
+impl TestVdev {
+    /// Hotplugs a new virtual device of the driver, for example `net_null`
+    /// or `net_ring`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the device can't be added.
+    pub(crate) fn new(driver: &str) -> Self {
+        let device = format!(
+            "{}_test{}",
+            driver,
+            NEXT_VDEV.fetch_add(1, Ordering::Relaxed)
+        );
+        dpdk::dev_hotplug_add("vdev", &device, "").unwrap();
+        TestVdev { device }
+    }
+
+    /// Returns the device name.
+    pub(crate) fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// Returns a builder for a port named `name` on the device.
+    pub(crate) fn port_builder<'a>(&self, name: &str) -> PortBuilder<'a> {
+        PortBuilder::new(name.to_owned(), self.device.clone()).unwrap()
+    }
+
+    /// Builds a port named `name` on the device, with a queue pair of 32
+    /// descriptors for each of the cores. The port is not started.
+    pub(crate) fn port(&self, name: &str, cores: &[CoreId], mempools: &mut [Mempool]) -> Port {
+        self.port_builder(name)
+            .cores(cores)
+            .unwrap()
+            .mempools(mempools)
+            .rx_tx_queue_capacity(32, 32)
+            .unwrap()
+            .finish(false, false, false)
+            .unwrap()
+    }
+}
+
+impl Drop for TestVdev {
+    fn drop(&mut self) {
+        dpdk::dev_hotplug_remove("vdev", &self.device).unwrap();
+    }
+}
@@ -0,0 +1,463 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Sampled packet tracing through the stages of a pipeline.
+//!
+//! Tracing is switched on and off at runtime by pipeline name. While it's
+//! on, every `n`th packet entering a [`Chain`] of that pipeline is sampled,
+//! and a snapshot of the packet is taken after each stage it goes through.
+//! The snapshots are kept in a bounded ring per core, holding the most
+//! recent 1024 samples of up to 256 bytes each. Older samples are
+//! overwritten.
+//!
+//! When tracing is off, the only cost is a single branch per packet.
+//!
+//! # Example
+//!
+//! ```
+//! PacketTrace::enable("eth1_in", 1000);
+//! ...
+//! let mut file = File::create("trace.pcapng")?;
+//! PacketTrace::export(&mut file)?;
+//! PacketTrace::disable("eth1_in");
+//! ```
+//!
+//! [`Chain`]: crate::batch::Chain
+
+use crate::dpdk::CoreId;
+use crate::pcapng::{Interface, PcapngWriter};
+use crate::Mbuf;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// The number of samples kept per core.
+const RING_CAPACITY: usize = 1024;
+
+/// The maximum number of bytes captured per sample.
+const SNAPLEN: usize = 256;
+
+/// A snapshot of a packet taken after a stage.
+#[derive(Clone, Debug)]
+pub struct TraceSample {
+    /// The name of the pipeline.
+    pub pipeline: String,
+    /// The name of the stage.
+    pub stage: String,
+    /// The core the pipeline runs on.
+    pub core: usize,
+    /// When the snapshot is taken.
+    pub timestamp: SystemTime,
+    /// The length of the packet.
+    pub len: usize,
+    /// The first bytes of the packet, up to 256 bytes.
+    pub data: Vec<u8>,
+}
+
+/// The most recent samples taken on a core.
+#[derive(Default)]
+struct TraceRing {
+    samples: VecDeque<TraceSample>,
+}
+
+impl TraceRing {
+    fn push(&mut self, sample: TraceSample) {
+        if self.samples.len() == RING_CAPACITY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+}
+
+/// The tracing switches and the sample rings of all the cores.
+#[derive(Default)]
+struct Registry {
+    switches: HashMap<String, Arc<AtomicU64>>,
+    rings: Vec<Arc<Mutex<TraceRing>>>,
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(Default::default);
+
+thread_local! {
+    static RING: RefCell<Option<Arc<Mutex<TraceRing>>>> = RefCell::new(None);
+    static CURRENT: RefCell<Option<Sampler>> = RefCell::new(None);
+}
+
+/// Returns the switch of a pipeline, adding a disabled one if the pipeline
+/// is new. The switch holds the sampling interval, `0` means disabled.
+fn switch(pipeline: &str) -> Arc<AtomicU64> {
+    REGISTRY
+        .lock()
+        .unwrap()
+        .switches
+        .entry(pipeline.to_owned())
+        .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+        .clone()
+}
+
+/// Returns the sample ring of the current core, registering it on first
+/// use.
+fn local_ring() -> Arc<Mutex<TraceRing>> {
+    RING.with(|ring| {
+        ring.borrow_mut()
+            .get_or_insert_with(|| {
+                let ring = Arc::new(Mutex::new(TraceRing::default()));
+                REGISTRY.lock().unwrap().rings.push(ring.clone());
+                ring
+            })
+            .clone()
+    })
+}
+
+/// The tracing state of a pipeline instance.
+pub(crate) struct PipelineTrace {
+    pipeline: Arc<str>,
+    every: Arc<AtomicU64>,
+    seen: Arc<AtomicU64>,
+}
+
+impl PipelineTrace {
+    /// Creates the tracing state for a pipeline.
+    pub(crate) fn new(pipeline: &str) -> Self {
+        PipelineTrace {
+            pipeline: pipeline.into(),
+            every: switch(pipeline),
+            seen: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Makes the sampler of the pipeline available to the chains executed
+    /// on the current thread until the returned scope drops. Returns `None`
+    /// if tracing is disabled.
+    #[inline]
+    pub(crate) fn enter(&self) -> Option<TraceScope> {
+        let every = self.every.load(Ordering::Relaxed);
+        if every == 0 {
+            return None;
+        }
+
+        let sampler = Sampler {
+            pipeline: self.pipeline.clone(),
+            core: CoreId::current().raw(),
+            every,
+            seen: self.seen.clone(),
+            ring: local_ring(),
+        };
+        let prev = CURRENT.with(|current| current.replace(Some(sampler)));
+        Some(TraceScope { prev })
+    }
+}
+
+/// Restores the previous sampler of the thread when dropped.
+pub(crate) struct TraceScope {
+    prev: Option<Sampler>,
+}
+
+impl Drop for TraceScope {
+    fn drop(&mut self) {
+        let prev = self.prev.take();
+        CURRENT.with(|current| *current.borrow_mut() = prev);
+    }
+}
+
+/// Returns the sampler of the pipeline executing on the current thread,
+/// or `None` if it's not traced.
+#[inline]
+pub(crate) fn current() -> Option<Sampler> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Picks the packets to sample and records their snapshots.
+#[derive(Clone)]
+pub(crate) struct Sampler {
+    pipeline: Arc<str>,
+    core: usize,
+    every: u64,
+    seen: Arc<AtomicU64>,
+    ring: Arc<Mutex<TraceRing>>,
+}
+
+impl Sampler {
+    /// Counts a new packet, and returns whether it's sampled.
+    #[inline]
+    pub(crate) fn sample(&self) -> bool {
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) + 1;
+        seen % self.every == 0
+    }
+
+    /// Records a snapshot of the packet after a stage.
+    pub(crate) fn record(&self, stage: &str, mbuf: &Mbuf) {
+        let len = mbuf.data_len();
+        let data = mbuf
+            .read_data_slice::<u8>(0, cmp::min(len, SNAPLEN))
+            .map(|slice| unsafe { slice.as_ref().to_vec() })
+            .unwrap_or_default();
+
+        self.ring.lock().unwrap().push(TraceSample {
+            pipeline: self.pipeline.to_string(),
+            stage: stage.to_owned(),
+            core: self.core,
+            timestamp: SystemTime::now(),
+            len,
+            data,
+        });
+    }
+}
+
+/// Entry point to the packet tracing.
+#[derive(Debug)]
+pub struct PacketTrace;
+
+impl PacketTrace {
+    /// Enables tracing of a pipeline on all cores, sampling every `every`th
+    /// packet. Takes effect from the next batch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `every` is `0`.
+    pub fn enable(pipeline: &str, every: u64) {
+        assert!(every > 0, "sampling interval must be at least 1.");
+        switch(pipeline).store(every, Ordering::Relaxed);
+    }
+
+    /// Disables tracing of a pipeline on all cores. The samples already
+    /// taken are kept.
+    pub fn disable(pipeline: &str) {
+        switch(pipeline).store(0, Ordering::Relaxed);
+    }
+
+    /// Returns whether a pipeline is traced.
+    pub fn is_enabled(pipeline: &str) -> bool {
+        REGISTRY
+            .lock()
+            .unwrap()
+            .switches
+            .get(pipeline)
+            .map(|switch| switch.load(Ordering::Relaxed) > 0)
+            .unwrap_or(false)
+    }
+
+    /// Returns a copy of the samples of all the cores, oldest first.
+    pub fn samples() -> Vec<TraceSample> {
+        let registry = REGISTRY.lock().unwrap();
+        let mut samples = registry
+            .rings
+            .iter()
+            .flat_map(|ring| ring.lock().unwrap().samples.clone())
+            .collect::<Vec<_>>();
+        samples.sort_by_key(|sample| sample.timestamp);
+        samples
+    }
+
+    /// Discards the samples of all the cores.
+    pub fn clear() {
+        for ring in REGISTRY.lock().unwrap().rings.iter() {
+            ring.lock().unwrap().samples.clear();
+        }
+    }
+
+    /// Writes the samples of all the cores as a pcapng capture, oldest
+    /// first. Each pipeline is described as an interface, and each packet
+    /// has a comment naming the pipeline, the stage and the core of the
+    /// sample. Returns the number of packets written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to `writer` fails.
+    pub fn export<W: Write>(writer: W) -> Result<usize> {
+        let samples = PacketTrace::samples();
+        let mut writer = PcapngWriter::new(writer)?;
+
+        let mut interfaces = HashMap::new();
+        for sample in samples.iter() {
+            if !interfaces.contains_key(&sample.pipeline) {
+                let interface = Interface::new(&sample.pipeline).snaplen(SNAPLEN as u32);
+                let id = writer.add_interface(&interface)?;
+                interfaces.insert(sample.pipeline.clone(), id);
+            }
+        }
+
+        for sample in samples.iter() {
+            let comment = format!(
+                "{}/{} on core {}",
+                sample.pipeline, sample.stage, sample.core
+            );
+            writer.write_packet(
+                interfaces[&sample.pipeline],
+                sample.timestamp,
+                &sample.data,
+                sample.len,
+                Some(&comment),
+            )?;
+        }
+        writer.finish()?;
+
+        Ok(samples.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::{Batch, Chain, Either, PacketTx, Pipeline, Poll, StageSwitches};
+    use crate::pcapng::{
+        BYTE_ORDER_MAGIC, ENHANCED_PACKET_BLOCK, INTERFACE_DESCRIPTION_BLOCK, SECTION_HEADER_BLOCK,
+    };
+    use crate::testils::byte_arrays::IPV4_UDP_PACKET;
+    use std::sync::mpsc;
+
+    fn pass(packet: Mbuf) -> Result<Either<Mbuf>> {
+        Ok(Either::Keep(packet))
+    }
+
+    fn traced(pipeline: &str) -> Vec<TraceSample> {
+        PacketTrace::samples()
+            .into_iter()
+            .filter(|sample| sample.pipeline == pipeline)
+            .collect()
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(buf)
+    }
+
+    #[test]
+    fn ring_is_bounded() {
+        let mut ring = TraceRing::default();
+        for i in 0..RING_CAPACITY + 10 {
+            ring.push(TraceSample {
+                pipeline: "trace_ring".to_owned(),
+                stage: "stage".to_owned(),
+                core: 0,
+                timestamp: SystemTime::now(),
+                len: i,
+                data: vec![],
+            });
+        }
+
+        assert_eq!(RING_CAPACITY, ring.samples.len());
+        assert_eq!(10, ring.samples.front().unwrap().len);
+    }
+
+    #[capsule::test]
+    fn sample_every_nth_packet() {
+        let switches = StageSwitches::default();
+        let (mut tx, rx) = mpsc::channel();
+        let (out, _out_rx) = mpsc::channel();
+
+        let chain = Chain::new(&switches)
+            .stage("trace_first", pass)
+            .stage("trace_second", pass);
+        let mut pipeline = Poll::new(rx)
+            .chain(chain)
+            .send_named("trace_sample_every_nth", out);
+
+        let packets = || {
+            (0..9)
+                .map(|_| Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // disabled, nothing is sampled.
+        tx.transmit(packets());
+        pipeline.run_once();
+        assert!(traced("trace_sample_every_nth").is_empty());
+
+        // every third packet is sampled after each stage.
+        PacketTrace::enable("trace_sample_every_nth", 3);
+        assert!(PacketTrace::is_enabled("trace_sample_every_nth"));
+        tx.transmit(packets());
+        pipeline.run_once();
+
+        let samples = traced("trace_sample_every_nth");
+        assert_eq!(6, samples.len());
+        for (i, sample) in samples.iter().enumerate() {
+            let stage = if i % 2 == 0 {
+                "trace_first"
+            } else {
+                "trace_second"
+            };
+            assert_eq!(stage, sample.stage);
+            assert_eq!(IPV4_UDP_PACKET.len(), sample.len);
+            assert_eq!(&IPV4_UDP_PACKET[..], &sample.data[..]);
+        }
+
+        // disabled again, the samples are kept but no new ones are taken.
+        PacketTrace::disable("trace_sample_every_nth");
+        assert!(!PacketTrace::is_enabled("trace_sample_every_nth"));
+        tx.transmit(packets());
+        pipeline.run_once();
+        assert_eq!(6, traced("trace_sample_every_nth").len());
+    }
+
+    #[capsule::test]
+    fn export_pcapng() {
+        let switches = StageSwitches::default();
+        let (mut tx, rx) = mpsc::channel();
+        let (out, _out_rx) = mpsc::channel();
+
+        let chain = Chain::new(&switches).stage("trace_export_stage", pass);
+        let mut pipeline = Poll::new(rx).chain(chain).send_named("trace_export", out);
+
+        PacketTrace::enable("trace_export", 1);
+        tx.transmit(vec![Mbuf::from_bytes(&IPV4_UDP_PACKET).unwrap()]);
+        pipeline.run_once();
+        PacketTrace::disable("trace_export");
+
+        let mut bytes = vec![];
+        let written = PacketTrace::export(&mut bytes).unwrap();
+        assert!(written >= 1);
+
+        // section header, then an interface description per pipeline.
+        assert_eq!(SECTION_HEADER_BLOCK, u32_at(&bytes, 0));
+        assert_eq!(BYTE_ORDER_MAGIC, u32_at(&bytes, 8));
+        let mut offset = u32_at(&bytes, 4) as usize;
+        let mut interfaces = vec![];
+        while u32_at(&bytes, offset) == INTERFACE_DESCRIPTION_BLOCK {
+            let len = u32_at(&bytes, offset + 4) as usize;
+            interfaces.push(&bytes[offset..offset + len]);
+            offset += len;
+        }
+        assert!(interfaces
+            .iter()
+            .any(|block| block.windows(12).any(|window| window == b"trace_export")));
+
+        // walks the packet blocks looking for the traced stage.
+        let comment = b"trace_export/trace_export_stage on core";
+        let mut found = false;
+        for _ in 0..written {
+            assert_eq!(ENHANCED_PACKET_BLOCK, u32_at(&bytes, offset));
+            let len = u32_at(&bytes, offset + 4) as usize;
+            assert_eq!(len, u32_at(&bytes, offset + len - 4) as usize);
+            found |= bytes[offset..offset + len]
+                .windows(comment.len())
+                .any(|window| window == &comment[..]);
+            offset += len;
+        }
+        assert_eq!(bytes.len(), offset);
+        assert!(found);
+    }
+}
@@ -0,0 +1,65 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use capsule::config::RuntimeConfig;
+use capsule::Runtime;
+
+const CONFIG: &str = r#"
+    app_name = "capsule_bond_test"
+    master_core = 0
+    dpdk_args = "--no-huge --iova-mode=va"
+
+    [mempool]
+        capacity = 1023
+        cache_size = 32
+
+    [[ports]]
+        name = "bond0"
+        device = "net_bonding0"
+        cores = [0]
+
+        [ports.bond]
+            mode = "active_backup"
+            slaves = ["net_ring0", "net_ring1"]
+            primary = "net_ring0"
+"#;
+
+// EAL can only be initialized once per process, so the whole bonded port
+// lifecycle is tested in one test.
+#[test]
+fn active_backup_failover() {
+    let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+    let mut runtime = Runtime::build(config).unwrap();
+    let bond = runtime.port_registry().port_id("bond0").unwrap();
+
+    let slaves = bond.bond_slaves().unwrap();
+    assert_eq!(2, slaves.len());
+    assert_eq!(slaves[0], bond.bond_primary().unwrap());
+
+    runtime.start().unwrap();
+    assert_eq!(2, bond.bond_active_slaves().unwrap().len());
+
+    // forces a failover to the backup.
+    bond.set_bond_primary(slaves[1]).unwrap();
+    assert_eq!(slaves[1], bond.bond_primary().unwrap());
+
+    // only slaves can be the primary.
+    assert!(bond.set_bond_primary(bond).is_err());
+
+    runtime.stop();
+}
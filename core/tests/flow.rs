@@ -0,0 +1,86 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Needs a NIC with `rte_flow` support and at least 2 receive queues. Set
+//! `CAPSULE_FLOW_DEVICE` to its PCI address, and run with
+//! `cargo test --features hw-flow-tests --test flow`.
+
+#![cfg(feature = "hw-flow-tests")]
+
+use capsule::config::RuntimeConfig;
+use capsule::{EthMatch, FlowRule, Ipv4Match, PortMatch, Runtime, VlanMatch};
+use std::env;
+
+// EAL can only be initialized once per process, so the whole flow rule
+// lifecycle is tested in one test.
+#[test]
+fn flow_rule_lifecycle() {
+    let device = env::var("CAPSULE_FLOW_DEVICE").expect("CAPSULE_FLOW_DEVICE is not set.");
+    let config = format!(
+        r#"
+        app_name = "capsule_flow_test"
+        master_core = 0
+        cores = [1]
+
+        [mempool]
+            capacity = 1023
+            cache_size = 32
+
+        [[ports]]
+            name = "nic0"
+            device = "{}"
+            cores = [0, 1]
+    "#,
+        device
+    );
+
+    let config: RuntimeConfig = toml::from_str(&config).unwrap();
+    let runtime = Runtime::build(config).unwrap();
+    let port_id = runtime.port_registry().port_id("nic0").unwrap();
+
+    let to_queue = FlowRule::new()
+        .eth(EthMatch::default())
+        .vlan(VlanMatch { vid: Some(100) })
+        .ipv4(Ipv4Match::default())
+        .tcp(PortMatch {
+            dst_port: Some(443),
+            ..Default::default()
+        })
+        .mark(7)
+        .queue(1);
+    port_id.validate_flow(&to_queue).unwrap();
+    let flow = port_id.create_flow(&to_queue).unwrap();
+    flow.destroy().unwrap();
+
+    let discard = FlowRule::new()
+        .eth(EthMatch::default())
+        .ipv4(Ipv4Match {
+            src: Some("10.1.2.0/24".parse().unwrap()),
+            ..Default::default()
+        })
+        .discard();
+    let flow = port_id.create_flow(&discard).unwrap();
+
+    // a queue the port doesn't have.
+    let bad_queue = FlowRule::new().eth(EthMatch::default()).queue(1000);
+    assert!(port_id.validate_flow(&bad_queue).is_err());
+
+    // destroying after a flush is a no-op.
+    port_id.flush_flows().unwrap();
+    flow.destroy().unwrap();
+}
@@ -0,0 +1,119 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Needs the `rte_kni` kernel module loaded, the `CAP_NET_ADMIN`
+//! capability, and the `ip` command. Run with
+//! `sudo -E cargo test --features kni-tests --test kni`.
+
+#![cfg(feature = "kni-tests")]
+
+use capsule::batch::{self, Pipeline};
+use capsule::config::RuntimeConfig;
+use capsule::net::MacAddr;
+use capsule::{PortQueue, Runtime};
+use std::fs;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const CONFIG: &str = r#"
+    app_name = "capsule_kni_test"
+    master_core = 0
+    dpdk_args = "--no-huge --iova-mode=va"
+
+    [mempool]
+        capacity = 1023
+        cache_size = 32
+
+    [[ports]]
+        name = "capsule_kni0"
+        cores = [0]
+        kni = true
+
+        [ports.vdev]
+            type = "tap"
+            iface = "capsule_test1"
+            mac = "02:00:00:ff:ff:01"
+"#;
+
+fn install(q: PortQueue) -> impl Pipeline {
+    batch::splice(q.clone(), q.kni().unwrap().clone())
+}
+
+fn ip(args: &str) {
+    let status = Command::new("ip")
+        .args(args.split_whitespace())
+        .status()
+        .unwrap();
+    assert!(status.success(), "ip {} failed.", args);
+}
+
+fn carrier() -> String {
+    fs::read_to_string("/sys/class/net/capsule_kni0/carrier")
+        .unwrap()
+        .trim()
+        .to_owned()
+}
+
+// EAL can only be initialized once per process, so all the requests are
+// made in one test. the kernel waits for the requests to be handled by
+// the KNI rx pipeline, so they are made from another thread.
+#[test]
+fn apply_kernel_requests_to_port() {
+    let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+    let mut runtime = Runtime::build(config).unwrap();
+    runtime
+        .with_signal_handling(false)
+        .add_pipeline_to_port("capsule_kni0", install)
+        .unwrap()
+        .add_kni_rx_pipeline_to_port("capsule_kni0", batch::splice)
+        .unwrap();
+
+    let registry = runtime.port_registry();
+    let port_id = registry.port_id("capsule_kni0").unwrap();
+
+    let done = Arc::new(AtomicBool::new(false));
+    let flag = done.clone();
+    let requests = thread::spawn(move || {
+        // gives the runtime time to start polling.
+        thread::sleep(Duration::from_millis(500));
+
+        ip("link set dev capsule_kni0 mtu 1400");
+        ip("link set dev capsule_kni0 promisc on");
+        ip("link set dev capsule_kni0 address 02:00:00:ff:ff:02");
+        ip("link set up dev capsule_kni0");
+        let carrier = carrier();
+
+        flag.store(true, Ordering::Relaxed);
+        carrier
+    });
+
+    runtime
+        .wait_until(move || done.load(Ordering::Relaxed))
+        .unwrap();
+
+    assert_eq!("1", requests.join().unwrap());
+    assert_eq!(1400, port_id.mtu().unwrap());
+    assert!(port_id.is_promiscuous());
+    assert_eq!(
+        MacAddr::new(0x02, 0, 0, 0xff, 0xff, 0x02),
+        port_id.mac_addr()
+    );
+}
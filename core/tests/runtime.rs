@@ -0,0 +1,61 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use capsule::config::RuntimeConfig;
+use capsule::Runtime;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONFIG: &str = r#"
+    app_name = "capsule_runtime_test"
+    master_core = 0
+    dpdk_args = "--no-huge --iova-mode=va"
+
+    [mempool]
+        capacity = 255
+        cache_size = 16
+
+    [[ports]]
+        name = "null0"
+        device = "net_null0"
+        cores = [0]
+"#;
+
+// EAL can only be initialized once per process, so the whole runtime
+// lifecycle is tested in one test.
+#[test]
+fn shutdown_from_another_thread() {
+    let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+    let mut runtime = Runtime::build(config).unwrap();
+    runtime.with_signal_handling(false);
+
+    let handle = runtime.handle();
+    let shutdown = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+    });
+
+    // the condition never turns true, only the shutdown stops the runtime.
+    let start = Instant::now();
+    runtime.wait_until(|| false).unwrap();
+    let elapsed = start.elapsed();
+
+    shutdown.join().unwrap();
+    assert!(elapsed >= Duration::from_millis(100));
+    assert!(elapsed < Duration::from_secs(5));
+}
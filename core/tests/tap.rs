@@ -0,0 +1,135 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Needs the `CAP_NET_ADMIN` capability to create the TAP interface, and
+//! the `ip` and `ping` commands. Run with
+//! `sudo -E cargo test --features tap-tests --test tap`.
+
+#![cfg(feature = "tap-tests")]
+
+use anyhow::Result;
+use capsule::batch::{Batch, Pipeline, Poll};
+use capsule::config::RuntimeConfig;
+use capsule::packets::icmp::v4::{EchoReply, EchoRequest};
+use capsule::packets::ip::v4::Ipv4;
+use capsule::packets::{Ethernet, Packet};
+use capsule::{Mbuf, PortQueue, Runtime};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const CONFIG: &str = r#"
+    app_name = "capsule_tap_test"
+    master_core = 0
+    dpdk_args = "--no-huge --iova-mode=va"
+
+    [mempool]
+        capacity = 1023
+        cache_size = 32
+
+    [[ports]]
+        name = "tap0"
+        cores = [0]
+
+        [ports.vdev]
+            type = "tap"
+            iface = "capsule_test0"
+            mac = "02:00:00:ff:ff:01"
+"#;
+
+fn is_echo(packet: &Mbuf) -> bool {
+    packet
+        .peek::<Ethernet>()
+        .and_then(|ethernet| ethernet.peek::<Ipv4>()?.peek::<EchoRequest>().map(|_| ()))
+        .is_ok()
+}
+
+fn reply_echo(packet: &Mbuf) -> Result<EchoReply> {
+    let ethernet = packet.peek::<Ethernet>()?;
+    let ipv4 = ethernet.peek::<Ipv4>()?;
+    let request = ipv4.peek::<EchoRequest>()?;
+
+    let mut reply = Mbuf::new()?.push::<Ethernet>()?;
+    reply.set_src(ethernet.dst());
+    reply.set_dst(ethernet.src());
+
+    let mut reply = reply.push::<Ipv4>()?;
+    reply.set_src(ipv4.dst());
+    reply.set_dst(ipv4.src());
+    reply.set_ttl(255);
+
+    let mut reply = reply.push::<EchoReply>()?;
+    reply.set_identifier(request.identifier());
+    reply.set_seq_no(request.seq_no());
+    reply.set_data(request.data())?;
+    reply.reconcile_all();
+
+    Ok(reply)
+}
+
+fn install(q: PortQueue) -> impl Pipeline {
+    Poll::new(q.clone())
+        .filter(is_echo)
+        .replace(reply_echo)
+        .send(q)
+}
+
+fn ip(args: &str) {
+    let status = Command::new("ip")
+        .args(args.split_whitespace())
+        .status()
+        .unwrap();
+    assert!(status.success(), "ip {} failed.", args);
+}
+
+// EAL can only be initialized once per process, so the kernel pings the
+// application in one test.
+#[test]
+fn ping_through_tap() {
+    let config: RuntimeConfig = toml::from_str(CONFIG).unwrap();
+    let mut runtime = Runtime::build(config).unwrap();
+    runtime
+        .with_signal_handling(false)
+        .add_pipeline_to_port("tap0", install)
+        .unwrap();
+
+    // the TAP interface exists once the device is probed. the static
+    // neighbor entry spares the pipeline from answering ARP.
+    ip("addr add 10.100.0.1/24 dev capsule_test0");
+    ip("link set up dev capsule_test0");
+    ip("neigh replace 10.100.0.2 lladdr 02:00:00:ff:ff:01 dev capsule_test0");
+
+    let done = Arc::new(AtomicBool::new(false));
+    let flag = done.clone();
+    let ping = thread::spawn(move || {
+        // gives the runtime time to start polling.
+        thread::sleep(Duration::from_millis(500));
+        let status = Command::new("ping")
+            .args(&["-c", "3", "-W", "1", "10.100.0.2"])
+            .status();
+        flag.store(true, Ordering::Relaxed);
+        status.unwrap()
+    });
+
+    runtime
+        .wait_until(move || done.load(Ordering::Relaxed))
+        .unwrap();
+    assert!(ping.join().unwrap().success());
+}
@@ -0,0 +1,108 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use anyhow::Result;
+use capsule::batch::{Batch, Pipeline, Poll};
+use capsule::config::{load_config, PortConfig, RuntimeConfig};
+use capsule::packets::{EtherTypes, Ethernet, Packet};
+use capsule::{command_channel, CommandRx, Mbuf, PortQueue, Runtime};
+use futures::StreamExt;
+use std::env;
+use std::time::Duration;
+use tokio_timer::Interval;
+use tracing::{debug, info, Level};
+use tracing_subscriber::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Filter {
+    PassAll,
+    DropIpv4,
+}
+
+fn install(q: PortQueue, mut commands: CommandRx<Filter>) -> impl Pipeline {
+    let mut filter = Filter::PassAll;
+
+    Poll::new(q.clone())
+        .map(|packet: Mbuf| packet.parse::<Ethernet>())
+        .filter(move |ethernet| {
+            // applies the commands from the control task before the packet.
+            while let Some(cmd) = commands.try_recv() {
+                filter = cmd;
+            }
+
+            filter == Filter::PassAll || ethernet.ether_type() != EtherTypes::Ipv4
+        })
+        .for_each(|ethernet| {
+            info!(?ethernet);
+            Ok(())
+        })
+        .send(q)
+}
+
+/// The same settings as `control.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("control")
+        .master_core(0)
+        .duration(Duration::from_secs(5))
+        .port(
+            PortConfig::builder()
+                .name("eth1")
+                .device("net_pcap0")
+                .args("rx_pcap=../pktdump/tcp4.pcap,tx_iface=lo")
+                .core(0),
+        )
+        .build()
+}
+
+fn main() -> Result<()> {
+    let subscriber = fmt::Subscriber::builder()
+        .with_max_level(Level::INFO)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
+    debug!(?config);
+
+    let (mut tx, rx) = command_channel(8);
+
+    Runtime::build(config)?
+        .spawn_control(async move {
+            // toggles the filter every second.
+            let mut filter = Filter::PassAll;
+            let mut interval = Interval::new_interval(Duration::from_secs(1));
+            while interval.next().await.is_some() {
+                filter = match filter {
+                    Filter::PassAll => Filter::DropIpv4,
+                    Filter::DropIpv4 => Filter::PassAll,
+                };
+                info!(?filter, "toggled filter.");
+
+                if tx.send(filter).await.is_err() {
+                    break;
+                }
+            }
+        })
+        .add_pipeline_to_core(0, move |qs| install(qs["eth1"].clone(), rx))?
+        .execute()
+}
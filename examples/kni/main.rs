@@ -17,11 +17,12 @@
 */
 
 use anyhow::Result;
-use capsule::config::load_config;
+use capsule::config::{load_config, PortConfig, RuntimeConfig};
 use capsule::metrics;
 use capsule::{batch, Runtime};
 use metrics_core::{Builder, Drain, Observe};
 use metrics_observer_yaml::YamlBuilder;
+use std::env;
 use std::time::Duration;
 use tracing::{debug, Level};
 use tracing_subscriber::fmt;
@@ -32,13 +33,34 @@ fn print_stats() {
     println!("{}", observer.drain());
 }
 
+/// The same settings as `kni.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("kni")
+        .master_core(0)
+        .mempool(65535, 256)
+        .port(
+            PortConfig::builder()
+                .name("kni0")
+                .device("0000:00:08.0")
+                .core(1)
+                .kni(true),
+        )
+        .build()
+}
+
 fn main() -> Result<()> {
     let subscriber = fmt::Subscriber::builder()
         .with_max_level(Level::INFO)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let config = load_config()?;
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
     debug!(?config);
 
     Runtime::build(config)?
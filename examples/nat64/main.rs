@@ -18,7 +18,7 @@
 
 use anyhow::Result;
 use capsule::batch::{Batch, Either, Pipeline, Poll};
-use capsule::config::load_config;
+use capsule::config::{load_config, PortConfig, RuntimeConfig};
 use capsule::packets::ip::v4::Ipv4;
 use capsule::packets::ip::v6::{Ipv6, Ipv6Packet};
 use capsule::packets::ip::ProtocolNumbers;
@@ -27,6 +27,7 @@ use capsule::{Mbuf, PortQueue, Runtime};
 use chashmap::CHashMap;
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
+use std::env;
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::sync::atomic::{AtomicU16, Ordering};
 use tracing::{debug, Level};
@@ -161,13 +162,39 @@ fn install_4to6(qs: HashMap<String, PortQueue>) -> impl Pipeline {
         .send(qs["eth1"].clone())
 }
 
+/// The same settings as `nat64.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("nat64")
+        .master_core(0)
+        .mempool(65535, 256)
+        .port(
+            PortConfig::builder()
+                .name("eth1")
+                .device("0000:00:08.0")
+                .core(1),
+        )
+        .port(
+            PortConfig::builder()
+                .name("eth2")
+                .device("0000:00:09.0")
+                .core(1),
+        )
+        .build()
+}
+
 fn main() -> Result<()> {
     let subscriber = fmt::Subscriber::builder()
         .with_max_level(Level::INFO)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let config = load_config()?;
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
     debug!(?config);
 
     Runtime::build(config)?
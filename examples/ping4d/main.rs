@@ -18,11 +18,13 @@
 
 use anyhow::Result;
 use capsule::batch::{Batch, Pipeline, Poll};
-use capsule::config::load_config;
+use capsule::config::{load_config, PortConfig, RuntimeConfig};
 use capsule::packets::icmp::v4::{EchoReply, EchoRequest};
 use capsule::packets::ip::v4::Ipv4;
 use capsule::packets::{Ethernet, Packet};
 use capsule::{Mbuf, PortQueue, Runtime};
+use std::env;
+use std::time::Duration;
 use tracing::{debug, Level};
 use tracing_subscriber::fmt;
 
@@ -57,13 +59,35 @@ fn install(q: PortQueue) -> impl Pipeline {
     Poll::new(q.clone()).replace(reply_echo).send(q)
 }
 
+/// The same settings as `ping4d.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("ping4d")
+        .master_core(0)
+        .duration(Duration::from_secs(5))
+        .mempool(65535, 256)
+        .port(
+            PortConfig::builder()
+                .name("eth1")
+                .device("net_pcap0")
+                .args("rx_pcap=echo.pcap,tx_iface=lo")
+                .core(0),
+        )
+        .build()
+}
+
 fn main() -> Result<()> {
     let subscriber = fmt::Subscriber::builder()
         .with_max_level(Level::DEBUG)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let config = load_config()?;
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
     debug!(?config);
 
     Runtime::build(config)?
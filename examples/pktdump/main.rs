@@ -18,13 +18,15 @@
 
 use anyhow::Result;
 use capsule::batch::{Batch, Pipeline, Poll};
-use capsule::config::load_config;
+use capsule::config::{load_config, PortConfig, RuntimeConfig};
 use capsule::packets::ip::v4::Ipv4;
 use capsule::packets::ip::v6::Ipv6;
 use capsule::packets::ip::IpPacket;
 use capsule::packets::{EtherTypes, Ethernet, Packet, Tcp, Tcp4, Tcp6};
 use capsule::{compose, Mbuf, PortQueue, Runtime};
 use colored::*;
+use std::env;
+use std::time::Duration;
 use tracing::{debug, Level};
 use tracing_subscriber::fmt;
 
@@ -90,13 +92,42 @@ fn install(q: PortQueue) -> impl Pipeline {
         .send(q)
 }
 
+/// The same settings as `pktdump.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("pktdump")
+        .master_core(0)
+        .duration(Duration::from_secs(5))
+        .mempool(65535, 256)
+        .port(
+            PortConfig::builder()
+                .name("eth1")
+                .device("net_pcap0")
+                .args("rx_pcap=tcp4.pcap,tx_iface=lo")
+                .core(0),
+        )
+        .port(
+            PortConfig::builder()
+                .name("eth2")
+                .device("net_pcap1")
+                .args("rx_pcap=tcp6.pcap,tx_iface=lo")
+                .core(0),
+        )
+        .build()
+}
+
 fn main() -> Result<()> {
     let subscriber = fmt::Subscriber::builder()
         .with_max_level(Level::DEBUG)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let config = load_config()?;
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
     debug!(?config);
 
     Runtime::build(config)?
@@ -0,0 +1,92 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use anyhow::{anyhow, Result};
+use capsule::batch::{Batch, Pipeline, Poll};
+use capsule::config::{load_config, PcapEof, PortConfig, RuntimeConfig, VdevConfig};
+use capsule::packets::ip::v4::Ipv4;
+use capsule::packets::{Ethernet, Packet};
+use capsule::{PortQueue, Runtime};
+use std::env;
+use std::time::Duration;
+use tracing::{debug, Level};
+use tracing_subscriber::fmt;
+
+/// Routes an IPv4 packet back where it came from, and drops the packets
+/// that are not IPv4 or that expired.
+fn route(mut ethernet: Ethernet) -> Result<Ipv4> {
+    ethernet.swap_addresses();
+
+    let mut ipv4 = ethernet.parse::<Ipv4>()?;
+    let ttl = ipv4.ttl();
+    if ttl <= 1 {
+        return Err(anyhow!("TTL expired."));
+    }
+    ipv4.set_ttl(ttl - 1);
+    ipv4.reconcile_all();
+
+    debug!(?ipv4);
+    Ok(ipv4)
+}
+
+fn install(q: PortQueue) -> impl Pipeline {
+    Poll::new(q.clone())
+        .map(|packet| packet.parse::<Ethernet>())
+        .map(route)
+        .send(q)
+}
+
+/// The same settings as `replay.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("replay")
+        .master_core(0)
+        .duration(Duration::from_secs(2))
+        .dpdk_args("--no-huge --iova-mode=va")
+        .mempool(1023, 32)
+        .port(
+            PortConfig::builder()
+                .name("eth1")
+                .core(0)
+                .vdev(VdevConfig::Pcap {
+                    rx_file: Some("input.pcap".to_owned()),
+                    tx_file: Some("output.pcap".to_owned()),
+                    eof: PcapEof::Stop,
+                }),
+        )
+        .build()
+}
+
+fn main() -> Result<()> {
+    let subscriber = fmt::Subscriber::builder()
+        .with_max_level(Level::DEBUG)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
+    debug!(?config);
+
+    Runtime::build(config)?
+        .add_pipeline_to_port("eth1", install)?
+        .execute()
+}
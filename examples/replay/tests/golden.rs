@@ -0,0 +1,66 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+//! Runs the example on `input.pcap` and compares the packets it writes to
+//! `output.pcap` with `expected.pcap`.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Returns the packets of a pcap file. The timestamps are left out since
+/// the port stamps the packets with the time they are transmitted.
+fn read_packets(path: &Path) -> Vec<Vec<u8>> {
+    const GLOBAL_HEADER_LEN: usize = 24;
+    const RECORD_HEADER_LEN: usize = 16;
+
+    let bytes = fs::read(path).unwrap();
+    assert_eq!(&[0xd4, 0xc3, 0xb2, 0xa1], &bytes[..4], "not a pcap file.");
+
+    let mut packets = vec![];
+    let mut offset = GLOBAL_HEADER_LEN;
+    while offset < bytes.len() {
+        let header = &bytes[offset..offset + RECORD_HEADER_LEN];
+        let mut len = [0; 4];
+        len.copy_from_slice(&header[8..12]);
+        let len = u32::from_le_bytes(len) as usize;
+
+        offset += RECORD_HEADER_LEN;
+        packets.push(bytes[offset..offset + len].to_vec());
+        offset += len;
+    }
+
+    packets
+}
+
+#[test]
+fn replay_matches_golden_file() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = dir.join("output.pcap");
+    let _ = fs::remove_file(&output);
+
+    let status = Command::new(env!("CARGO_BIN_EXE_replay"))
+        .args(&["-f", "replay.toml"])
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let expected = read_packets(&dir.join("expected.pcap"));
+    assert_eq!(expected, read_packets(&output));
+}
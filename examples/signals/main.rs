@@ -17,9 +17,10 @@
 */
 
 use anyhow::Result;
-use capsule::config::load_config;
+use capsule::config::{load_config, RuntimeConfig};
 use capsule::Runtime;
 use capsule::UnixSignal::{self, *};
+use std::env;
 use tracing::{info, Level};
 use tracing_subscriber::fmt;
 
@@ -31,13 +32,26 @@ fn on_signal(signal: UnixSignal) -> bool {
     }
 }
 
+/// The same settings as `signals.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("signals")
+        .master_core(0)
+        .build()
+}
+
 fn main() -> Result<()> {
     let subscriber = fmt::Subscriber::builder()
         .with_max_level(Level::INFO)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let config = load_config()?;
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
     let mut runtime = Runtime::build(config)?;
     runtime.set_on_signal(on_signal);
 
@@ -17,18 +17,41 @@
 */
 
 use anyhow::Result;
-use capsule::config::load_config;
+use capsule::config::{load_config, PortConfig, RuntimeConfig};
 use capsule::Runtime;
+use std::env;
+use std::time::Duration;
 use tracing::{debug, Level};
 use tracing_subscriber::fmt;
 
+/// The same settings as `skeleton.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("skeleton")
+        .master_core(0)
+        .dpdk_args("-v --log-level eal:8")
+        .duration(Duration::from_secs(5))
+        .port(
+            PortConfig::builder()
+                .name("eth1")
+                .device("net_ring0")
+                .core(0),
+        )
+        .build()
+}
+
 fn main() -> Result<()> {
     let subscriber = fmt::Subscriber::builder()
         .with_max_level(Level::TRACE)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let config = load_config()?;
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
     debug!(?config);
 
     Runtime::build(config)?.execute()
@@ -18,7 +18,7 @@
 
 use anyhow::Result;
 use capsule::batch::{Batch, Pipeline};
-use capsule::config::load_config;
+use capsule::config::{load_config, PortConfig, RuntimeConfig};
 use capsule::metrics;
 use capsule::net::MacAddr;
 use capsule::packets::ip::v4::Ipv4;
@@ -27,6 +27,7 @@ use capsule::{batch, Mbuf, PortQueue, Runtime};
 use metrics_core::{Builder, Drain, Observe};
 use metrics_observer_yaml::YamlBuilder;
 use std::collections::HashMap;
+use std::env;
 use std::net::Ipv4Addr;
 use std::time::Duration;
 use tracing::{debug, error, Level};
@@ -76,13 +77,35 @@ fn print_stats() {
     println!("{}", observer.drain());
 }
 
+/// The same settings as `syn-flood.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("syn-flood")
+        .master_core(0)
+        .mempool(65535, 256)
+        .port(
+            PortConfig::builder()
+                .name("eth1")
+                .device("0000:00:08.0")
+                .core(1)
+                .rxd(512)
+                .txd(512),
+        )
+        .build()
+}
+
 fn main() -> Result<()> {
     let subscriber = fmt::Subscriber::builder()
         .with_max_level(Level::INFO)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let config = load_config()?;
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
     debug!(?config);
 
     Runtime::build(config)?
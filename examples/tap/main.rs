@@ -0,0 +1,143 @@
+/*
+* Copyright 2019 Comcast Cable Communications Management, LLC
+*
+* Licensed under the Apache License, Version 2.0 (the "License");
+* you may not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+* http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific language governing permissions and
+* limitations under the License.
+*
+* SPDX-License-Identifier: Apache-2.0
+*/
+
+use anyhow::Result;
+use capsule::batch::{Batch, Either, Pipeline, Poll};
+use capsule::config::{load_config, PortConfig, RuntimeConfig, VdevConfig};
+use capsule::net::MacAddr;
+use capsule::packets::arp::{Arp4, OperationCodes};
+use capsule::packets::icmp::v4::{EchoReply, EchoRequest};
+use capsule::packets::ip::v4::Ipv4;
+use capsule::packets::{EtherTypes, Ethernet, Packet};
+use capsule::{Mbuf, PortQueue, Runtime};
+use std::env;
+use std::net::Ipv4Addr;
+use tracing::{debug, Level};
+use tracing_subscriber::fmt;
+
+/// The address the application answers for on the TAP interface.
+const ADDR: Ipv4Addr = Ipv4Addr::new(10, 100, 0, 2);
+
+/// Turns an ARP request for `ADDR` into the reply in place.
+fn reply_arp(ethernet: Ethernet, mac: MacAddr) -> Result<Either<Mbuf>> {
+    let mut arp = ethernet.parse::<Arp4>()?;
+    if arp.operation_code() != OperationCodes::Request || arp.target_protocol_addr() != ADDR {
+        return Ok(Either::Drop(arp.reset()));
+    }
+
+    arp.set_operation_code(OperationCodes::Reply);
+    arp.set_target_hardware_addr(arp.sender_hardware_addr());
+    arp.set_target_protocol_addr(arp.sender_protocol_addr());
+    arp.set_sender_hardware_addr(mac);
+    arp.set_sender_protocol_addr(ADDR);
+    debug!(?arp);
+
+    let mut ethernet = arp.deparse();
+    ethernet.set_dst(ethernet.src());
+    ethernet.set_src(mac);
+    Ok(Either::Keep(ethernet.reset()))
+}
+
+/// Replaces an echo request for `ADDR` with the reply.
+fn reply_echo(ethernet: Ethernet) -> Result<Either<Mbuf>> {
+    let is_echo = ethernet
+        .peek::<Ipv4>()
+        .map(|ipv4| ipv4.dst() == ADDR && ipv4.peek::<EchoRequest>().is_ok())
+        .unwrap_or(false);
+    if !is_echo {
+        return Ok(Either::Drop(ethernet.reset()));
+    }
+
+    let ipv4 = ethernet.peek::<Ipv4>()?;
+    let request = ipv4.peek::<EchoRequest>()?;
+
+    let reply = Mbuf::new()?;
+    let mut reply = reply.push::<Ethernet>()?;
+    reply.set_src(ethernet.dst());
+    reply.set_dst(ethernet.src());
+
+    let mut reply = reply.push::<Ipv4>()?;
+    reply.set_src(ipv4.dst());
+    reply.set_dst(ipv4.src());
+    reply.set_ttl(255);
+
+    let mut reply = reply.push::<EchoReply>()?;
+    reply.set_identifier(request.identifier());
+    reply.set_seq_no(request.seq_no());
+    reply.set_data(request.data())?;
+    reply.reconcile_all();
+
+    debug!(?request);
+    debug!(?reply);
+
+    Ok(Either::Keep(reply.reset()))
+}
+
+fn install(q: PortQueue) -> impl Pipeline {
+    let mac = q.mac_addr();
+
+    // the kernel also sends IPv6 neighbor discovery and router
+    // solicitations through the interface, which are dropped.
+    Poll::new(q.clone())
+        .filter_map(move |packet| {
+            let ethernet = packet.parse::<Ethernet>()?;
+            match ethernet.ether_type() {
+                EtherTypes::Arp => reply_arp(ethernet, mac),
+                EtherTypes::Ipv4 => reply_echo(ethernet),
+                _ => Ok(Either::Drop(ethernet.reset())),
+            }
+        })
+        .send(q)
+}
+
+/// The same settings as `tap.toml`, built in code.
+fn build_config() -> Result<RuntimeConfig> {
+    RuntimeConfig::builder()
+        .app_name("tap")
+        .master_core(0)
+        .mempool(65535, 256)
+        .port(
+            PortConfig::builder()
+                .name("tap0")
+                .core(0)
+                .vdev(VdevConfig::Tap {
+                    iface: Some("capsule0".to_owned()),
+                    mac: Some(MacAddr::new(0x02, 0x00, 0x00, 0xff, 0xff, 0x01)),
+                }),
+        )
+        .build()
+}
+
+fn main() -> Result<()> {
+    let subscriber = fmt::Subscriber::builder()
+        .with_max_level(Level::DEBUG)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    // uses the settings built in code when run without a config file.
+    let config = if env::args().len() > 1 {
+        load_config()?
+    } else {
+        build_config()?
+    };
+    debug!(?config);
+
+    Runtime::build(config)?
+        .add_pipeline_to_port("tap0", install)?
+        .execute()
+}
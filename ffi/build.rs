@@ -183,7 +183,7 @@ fn bind(path: &Path) {
         .opaque_type(r"rte_arp_ipv4|rte_arp_hdr")
         .whitelist_type(r"(rte|eth|pcap)_.*")
         .whitelist_function(r"(_rte|rte|eth|numa|pcap)_.*")
-        .whitelist_var(r"(RTE|DEV|ETH|MEMPOOL|PKT|rte)_.*")
+        .whitelist_var(r"(RTE|DEV|ETH|MEMPOOL|PKT|RING|BONDING|BALANCE|rte)_.*")
         .derive_copy(true)
         .derive_debug(true)
         .derive_default(true)
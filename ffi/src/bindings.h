@@ -21,9 +21,13 @@
 
 // all the necessary DPDK functions, types and constants are defined
 // in the following header files.
+#include <rte_cycles.h>
 #include <rte_eal.h>
 #include <rte_errno.h>
+#include <rte_eth_bond.h>
+#include <rte_eth_bond_8023ad.h>
 #include <rte_ethdev.h>
+#include <rte_ring.h>
 //#include <rte_kni.h>
 
 // libnuma functions and types
@@ -51,6 +55,24 @@ struct rte_mbuf *_rte_pktmbuf_alloc(struct rte_mempool *mp);
  */
 void _rte_pktmbuf_free(struct rte_mbuf *m);
 
+/**
+ * Create a "clone" of the given packet mbuf. The clone is an indirect
+ * mbuf attached to the data buffer of the original.
+ */
+struct rte_mbuf *_rte_pktmbuf_clone(struct rte_mbuf *md, struct rte_mempool *mp);
+
+/**
+ * Decrease reference counter and unlink a mbuf segment. Returns the mbuf
+ * if it can be put back into its mempool, or NULL otherwise.
+ */
+struct rte_mbuf *_rte_pktmbuf_prefree_seg(struct rte_mbuf *m);
+
+/**
+ * Chain an mbuf to another, thereby creating a segmented packet. Returns
+ * -EOVERFLOW if the chain segment limit is exceeded.
+ */
+int _rte_pktmbuf_chain(struct rte_mbuf *head, struct rte_mbuf *tail);
+
 /**
  * Allocate a bulk of mbufs, initialize refcnt and reset the fields to
  * default values.
@@ -87,3 +109,44 @@ uint16_t _rte_eth_tx_burst(
     uint16_t queue_id,
     struct rte_mbuf **tx_pkts,
     uint16_t nb_pkts);
+
+/**
+ * Enqueue several objects on a ring. The ring must be single-producer.
+ */
+unsigned int _rte_ring_sp_enqueue_burst(
+    struct rte_ring *r,
+    void *const *obj_table,
+    unsigned int n,
+    unsigned int *free_space);
+
+/**
+ * Dequeue several objects from a ring. The ring must be single-consumer.
+ */
+unsigned int _rte_ring_sc_dequeue_burst(
+    struct rte_ring *r,
+    void **obj_table,
+    unsigned int n,
+    unsigned int *available);
+
+/**
+ * Enqueue several objects on a ring. Safe for multiple producers.
+ */
+unsigned int _rte_ring_mp_enqueue_burst(
+    struct rte_ring *r,
+    void *const *obj_table,
+    unsigned int n,
+    unsigned int *free_space);
+
+/**
+ * Dequeue several objects from a ring. Safe for multiple consumers.
+ */
+unsigned int _rte_ring_mc_dequeue_burst(
+    struct rte_ring *r,
+    void **obj_table,
+    unsigned int n,
+    unsigned int *available);
+
+/**
+ * Read the time-stamp counter.
+ */
+uint64_t _rte_rdtsc(void);
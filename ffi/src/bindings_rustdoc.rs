@@ -420,6 +420,9 @@ pub const RTE_MEMPOOL_MZ_FORMAT: &'static [u8; 6usize] = b"MP_%s\0";
 pub const MEMPOOL_PG_NUM_DEFAULT: u32 = 1;
 pub const RTE_MEMPOOL_ALIGN: u32 = 64;
 pub const RTE_MEMPOOL_ALIGN_MASK: u32 = 63;
+pub const RING_F_SP_ENQ: u32 = 1;
+pub const RING_F_SC_DEQ: u32 = 2;
+pub const RING_F_EXACT_SZ: u32 = 4;
 pub const MEMPOOL_F_NO_SPREAD: u32 = 1;
 pub const MEMPOOL_F_NO_CACHE_ALIGN: u32 = 2;
 pub const MEMPOOL_F_SP_PUT: u32 = 4;
@@ -871,6 +874,16 @@ pub const RTE_ETH_TX_DESC_DONE: u32 = 1;
 pub const RTE_ETH_TX_DESC_UNAVAIL: u32 = 2;
 pub const RTE_CLASS_ANY_ID: u32 = 16777215;
 pub const RTE_KNI_NAMESIZE: u32 = 16;
+pub const BONDING_MODE_ROUND_ROBIN: u32 = 0;
+pub const BONDING_MODE_ACTIVE_BACKUP: u32 = 1;
+pub const BONDING_MODE_BALANCE: u32 = 2;
+pub const BONDING_MODE_BROADCAST: u32 = 3;
+pub const BONDING_MODE_8023AD: u32 = 4;
+pub const BONDING_MODE_TLB: u32 = 5;
+pub const BONDING_MODE_ALB: u32 = 6;
+pub const BALANCE_XMIT_POLICY_LAYER2: u32 = 0;
+pub const BALANCE_XMIT_POLICY_LAYER23: u32 = 1;
+pub const BALANCE_XMIT_POLICY_LAYER34: u32 = 2;
 pub type __u_char = ::std::os::raw::c_uchar;
 pub type __u_short = ::std::os::raw::c_ushort;
 pub type __u_int = ::std::os::raw::c_uint;
@@ -24929,6 +24942,119 @@ extern "C" {
 extern "C" {
     pub fn rte_kni_close();
 }
+extern "C" {
+    pub fn rte_eth_bond_create(
+        name: *const ::std::os::raw::c_char,
+        mode: u8,
+        socket_id: u8,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_free(name: *const ::std::os::raw::c_char) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_slave_add(bonded_port_id: u16, slave_port_id: u16)
+        -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_slave_remove(
+        bonded_port_id: u16,
+        slave_port_id: u16,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_mode_get(bonded_port_id: u16) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_primary_set(
+        bonded_port_id: u16,
+        slave_port_id: u16,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_primary_get(bonded_port_id: u16) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_slaves_get(
+        bonded_port_id: u16,
+        slaves: *mut u16,
+        len: u16,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_active_slaves_get(
+        bonded_port_id: u16,
+        slaves: *mut u16,
+        len: u16,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_xmit_policy_set(bonded_port_id: u16, policy: u8) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_link_monitoring_set(
+        bonded_port_id: u16,
+        internal_ms: u32,
+    ) -> ::std::os::raw::c_int;
+}
+pub mod rte_bond_8023ad_selection {
+    pub type Type = ::std::os::raw::c_uint;
+    pub const UNSELECTED: Type = 0;
+    pub const STANDBY: Type = 1;
+    pub const SELECTED: Type = 2;
+}
+pub mod rte_bond_8023ad_agg_selection {
+    pub type Type = ::std::os::raw::c_uint;
+    pub const AGG_BANDWIDTH: Type = 0;
+    pub const AGG_COUNT: Type = 1;
+    pub const AGG_STABLE: Type = 2;
+}
+pub type rte_eth_bond_8023ad_ext_slowrx_fn =
+    ::std::option::Option<unsafe extern "C" fn(slave_id: u16, lacp_pkt: *mut rte_mbuf)>;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct rte_eth_bond_8023ad_conf {
+    pub fast_periodic_ms: u32,
+    pub slow_periodic_ms: u32,
+    pub short_timeout_ms: u32,
+    pub long_timeout_ms: u32,
+    pub aggregate_wait_timeout_ms: u32,
+    pub tx_period_ms: u32,
+    pub rx_marker_period_ms: u32,
+    pub update_timeout_ms: u32,
+    pub slowrx_cb: rte_eth_bond_8023ad_ext_slowrx_fn,
+    pub agg_selection: rte_bond_8023ad_agg_selection::Type,
+}
+#[test]
+fn bindgen_test_layout_rte_eth_bond_8023ad_conf() {
+    assert_eq!(
+        ::std::mem::size_of::<rte_eth_bond_8023ad_conf>(),
+        48usize,
+        concat!("Size of: ", stringify!(rte_eth_bond_8023ad_conf))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<rte_eth_bond_8023ad_conf>(),
+        8usize,
+        concat!("Alignment of ", stringify!(rte_eth_bond_8023ad_conf))
+    );
+}
+impl Default for rte_eth_bond_8023ad_conf {
+    fn default() -> Self {
+        unsafe { ::std::mem::zeroed() }
+    }
+}
+extern "C" {
+    pub fn rte_eth_bond_8023ad_conf_get(
+        port_id: u16,
+        conf: *mut rte_eth_bond_8023ad_conf,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn rte_eth_bond_8023ad_setup(
+        port_id: u16,
+        conf: *mut rte_eth_bond_8023ad_conf,
+    ) -> ::std::os::raw::c_int;
+}
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, PartialEq)]
 pub struct nodemask_t {
@@ -26085,6 +26211,21 @@ extern "C" {
     #[doc = " Free a packet mbuf back into its original mempool."]
     pub fn _rte_pktmbuf_free(m: *mut rte_mbuf);
 }
+extern "C" {
+    #[doc = " Create a \"clone\" of the given packet mbuf. The clone is an indirect"]
+    #[doc = " mbuf attached to the data buffer of the original."]
+    pub fn _rte_pktmbuf_clone(md: *mut rte_mbuf, mp: *mut rte_mempool) -> *mut rte_mbuf;
+}
+extern "C" {
+    #[doc = " Decrease reference counter and unlink a mbuf segment. Returns the mbuf"]
+    #[doc = " if it can be put back into its mempool, or NULL otherwise."]
+    pub fn _rte_pktmbuf_prefree_seg(m: *mut rte_mbuf) -> *mut rte_mbuf;
+}
+extern "C" {
+    #[doc = " Chain an mbuf to another, thereby creating a segmented packet. Returns"]
+    #[doc = " -EOVERFLOW if the chain segment limit is exceeded."]
+    pub fn _rte_pktmbuf_chain(head: *mut rte_mbuf, tail: *mut rte_mbuf) -> ::std::os::raw::c_int;
+}
 extern "C" {
     #[doc = " Allocate a bulk of mbufs, initialize refcnt and reset the fields to"]
     #[doc = " default values."]
@@ -26122,6 +26263,46 @@ extern "C" {
         nb_pkts: u16,
     ) -> u16;
 }
+extern "C" {
+    #[doc = " Enqueue several objects on a ring. The ring must be single-producer."]
+    pub fn _rte_ring_sp_enqueue_burst(
+        r: *mut rte_ring,
+        obj_table: *const *mut ::std::os::raw::c_void,
+        n: ::std::os::raw::c_uint,
+        free_space: *mut ::std::os::raw::c_uint,
+    ) -> ::std::os::raw::c_uint;
+}
+extern "C" {
+    #[doc = " Dequeue several objects from a ring. The ring must be single-consumer."]
+    pub fn _rte_ring_sc_dequeue_burst(
+        r: *mut rte_ring,
+        obj_table: *mut *mut ::std::os::raw::c_void,
+        n: ::std::os::raw::c_uint,
+        available: *mut ::std::os::raw::c_uint,
+    ) -> ::std::os::raw::c_uint;
+}
+extern "C" {
+    #[doc = " Enqueue several objects on a ring. Safe for multiple producers."]
+    pub fn _rte_ring_mp_enqueue_burst(
+        r: *mut rte_ring,
+        obj_table: *const *mut ::std::os::raw::c_void,
+        n: ::std::os::raw::c_uint,
+        free_space: *mut ::std::os::raw::c_uint,
+    ) -> ::std::os::raw::c_uint;
+}
+extern "C" {
+    #[doc = " Dequeue several objects from a ring. Safe for multiple consumers."]
+    pub fn _rte_ring_mc_dequeue_burst(
+        r: *mut rte_ring,
+        obj_table: *mut *mut ::std::os::raw::c_void,
+        n: ::std::os::raw::c_uint,
+        available: *mut ::std::os::raw::c_uint,
+    ) -> ::std::os::raw::c_uint;
+}
+extern "C" {
+    #[doc = " Read the time-stamp counter."]
+    pub fn _rte_rdtsc() -> u64;
+}
 pub type __builtin_va_list = [__va_list_tag; 1usize];
 #[repr(C)]
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -16,10 +16,12 @@
 * SPDX-License-Identifier: Apache-2.0
 */
 
+#include <rte_cycles.h>
 #include <rte_errno.h>
 #include <rte_ethdev.h>
 #include <rte_mbuf.h>
 #include <rte_mempool.h>
+#include <rte_ring.h>
 
 int _rte_errno(void) {
     return rte_errno;
@@ -33,6 +35,18 @@ void _rte_pktmbuf_free(struct rte_mbuf *m) {
     rte_pktmbuf_free(m);
 }
 
+struct rte_mbuf *_rte_pktmbuf_clone(struct rte_mbuf *md, struct rte_mempool *mp) {
+    return rte_pktmbuf_clone(md, mp);
+}
+
+struct rte_mbuf *_rte_pktmbuf_prefree_seg(struct rte_mbuf *m) {
+    return rte_pktmbuf_prefree_seg(m);
+}
+
+int _rte_pktmbuf_chain(struct rte_mbuf *head, struct rte_mbuf *tail) {
+    return rte_pktmbuf_chain(head, tail);
+}
+
 int _rte_pktmbuf_alloc_bulk(
     struct rte_mempool *pool,
     struct rte_mbuf **mbufs,
@@ -62,3 +76,39 @@ uint16_t _rte_eth_tx_burst(
     uint16_t nb_pkts) {
     return rte_eth_tx_burst(port_id, queue_id, tx_pkts, nb_pkts);
 }
+
+unsigned int _rte_ring_sp_enqueue_burst(
+    struct rte_ring *r,
+    void *const *obj_table,
+    unsigned int n,
+    unsigned int *free_space) {
+    return rte_ring_sp_enqueue_burst(r, obj_table, n, free_space);
+}
+
+unsigned int _rte_ring_sc_dequeue_burst(
+    struct rte_ring *r,
+    void **obj_table,
+    unsigned int n,
+    unsigned int *available) {
+    return rte_ring_sc_dequeue_burst(r, obj_table, n, available);
+}
+
+unsigned int _rte_ring_mp_enqueue_burst(
+    struct rte_ring *r,
+    void *const *obj_table,
+    unsigned int n,
+    unsigned int *free_space) {
+    return rte_ring_mp_enqueue_burst(r, obj_table, n, free_space);
+}
+
+unsigned int _rte_ring_mc_dequeue_burst(
+    struct rte_ring *r,
+    void **obj_table,
+    unsigned int n,
+    unsigned int *available) {
+    return rte_ring_mc_dequeue_burst(r, obj_table, n, available);
+}
+
+uint64_t _rte_rdtsc(void) {
+    return rte_rdtsc();
+}